@@ -0,0 +1,262 @@
+//! Conversions between Scratch values, matching `Cast` in scratch-vm.
+//!
+//! Scratch never fails to convert a value: strings that do not look like
+//! numbers become 0, and anything can be compared with anything.
+
+use std::cmp::Ordering;
+
+use crate::model::{PrimitiveValue, RichValue};
+
+const JS_WHITESPACE: [char; 14] = [
+    '\t', '\n', '\u{000B}', '\u{000C}', '\r', ' ', '\u{00A0}', '\u{1680}', '\u{2028}', '\u{2029}',
+    '\u{202F}', '\u{205F}', '\u{3000}', '\u{FEFF}',
+];
+
+/// Whitespace as understood by JavaScript's `String.prototype.trim`.
+pub fn is_js_whitespace(c: char) -> bool {
+    JS_WHITESPACE.contains(&c) || ('\u{2000}'..='\u{200A}').contains(&c)
+}
+
+/// Trims `s` the way JavaScript's `String.prototype.trim` does.
+pub fn js_trim(s: &str) -> &str {
+    s.trim_matches(is_js_whitespace)
+}
+
+fn is_decimal_literal(s: &str) -> bool {
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let mut parts = mantissa.splitn(2, '.');
+    let integer = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+    if integer.is_empty() && fraction.is_empty() {
+        return false;
+    }
+    if !integer.chars().all(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit())
+    {
+        return false;
+    }
+    match exponent {
+        None => true,
+        Some(e) => {
+            let digits = e.strip_prefix(['+', '-']).unwrap_or(e);
+            !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+        }
+    }
+}
+
+/// Parses `s` like JavaScript's `Number(s)`, returning NaN when `s` is not a number.
+/// Surrounding whitespace is ignored, an empty string is 0, and hexadecimal (`0x`),
+/// binary (`0b`) and octal (`0o`) literals as well as `Infinity` are accepted.
+pub fn str_to_number(s: &str) -> f64 {
+    let t = js_trim(s);
+    if t.is_empty() {
+        return 0.0;
+    }
+    let bytes = t.as_bytes();
+    if bytes.len() > 2 && bytes[0] == b'0' {
+        let radix = match bytes[1] {
+            b'x' | b'X' => 16,
+            b'b' | b'B' => 2,
+            b'o' | b'O' => 8,
+            _ => 0,
+        };
+        if radix != 0 {
+            return t[2..]
+                .chars()
+                .try_fold(0.0, |acc, c| {
+                    c.to_digit(radix).map(|d| acc * radix as f64 + d as f64)
+                })
+                .unwrap_or(f64::NAN);
+        }
+    }
+    let (sign, body) = match bytes[0] {
+        b'+' => (1.0, &t[1..]),
+        b'-' => (-1.0, &t[1..]),
+        _ => (1.0, t),
+    };
+    if body == "Infinity" {
+        return sign * f64::INFINITY;
+    }
+    if !is_decimal_literal(body) {
+        return f64::NAN;
+    }
+    body.parse::<f64>().map(|n| sign * n).unwrap_or(f64::NAN)
+}
+
+/// Converts a number to a string exactly like JavaScript's `Number.prototype.toString`:
+/// the shortest digits that round-trip, in plain notation for magnitudes between
+/// 1e-7 and 1e21 and in exponential notation (`1e+21`, `1.5e-7`) otherwise.
+pub fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        return String::from("NaN");
+    }
+    if n == 0.0 {
+        // Negative zero too.
+        return String::from("0");
+    }
+    if n.is_infinite() {
+        if n.is_sign_negative() {
+            return String::from("-Infinity");
+        }
+        return String::from("Infinity");
+    }
+    if n < 0.0 {
+        return format!("-{}", number_to_string(-n));
+    }
+
+    // Rust already finds the shortest round-tripping digits, only the layout differs.
+    let scientific = format!("{n:e}");
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("scientific notation always has an exponent");
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    let n = exponent
+        .parse::<i32>()
+        .expect("exponent is always an integer")
+        + 1;
+
+    if k <= n && n <= 21 {
+        format!("{digits}{}", "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{digits}", "0".repeat(-n as usize))
+    } else {
+        let sign = if n - 1 < 0 { '-' } else { '+' };
+        match digits.len() {
+            1 => format!("{digits}e{sign}{}", (n - 1).abs()),
+            _ => format!("{}.{}e{sign}{}", &digits[..1], &digits[1..], (n - 1).abs()),
+        }
+    }
+}
+
+/// The length of `s` in UTF-16 code units, like JavaScript's `String.prototype.length`.
+pub fn js_length(s: &str) -> usize {
+    s.encode_utf16().count()
+}
+
+/// The UTF-16 code unit at `index` of `s`, like JavaScript's `String.prototype.charAt`.
+/// Half of a surrogate pair cannot be represented on its own and becomes U+FFFD.
+pub fn js_char_at(s: &str, index: usize) -> String {
+    match s.encode_utf16().nth(index) {
+        Some(unit) => String::from_utf16_lossy(&[unit]),
+        None => String::new(),
+    }
+}
+
+/// The value as JavaScript's `Number()` would see it, NaN included.
+fn js_number(value: &RichValue) -> f64 {
+    match value {
+        RichValue::Boolean(b) => *b as u8 as f64,
+        RichValue::Number(n) | RichValue::PositiveNumber(n) | RichValue::Angle(n) => *n,
+        RichValue::Integer(i) => *i as f64,
+        RichValue::PositiveInteger(i) => *i as f64,
+        RichValue::String(s) | RichValue::Color(s) | RichValue::Broadcast(s) => str_to_number(s),
+    }
+}
+
+/// Scratch's `Cast.toNumber`: anything that is not a number becomes 0.
+pub fn to_number(value: &RichValue) -> f64 {
+    let n = js_number(value);
+    if n.is_nan() {
+        return 0.0;
+    }
+    n
+}
+
+/// Scratch's `Cast.toBoolean`: `""`, `"0"` and `"false"` (in any case) are false,
+/// as are 0 and NaN. Everything else is true.
+pub fn to_boolean(value: &RichValue) -> bool {
+    match value {
+        RichValue::Boolean(b) => *b,
+        RichValue::Number(n) | RichValue::PositiveNumber(n) | RichValue::Angle(n) => {
+            *n != 0.0 && !n.is_nan()
+        }
+        RichValue::Integer(i) => *i != 0,
+        RichValue::PositiveInteger(i) => *i != 0,
+        RichValue::String(s) | RichValue::Color(s) | RichValue::Broadcast(s) => {
+            !(s.is_empty() || s == "0" || s.eq_ignore_ascii_case("false"))
+        }
+    }
+}
+
+/// Scratch's `Cast.toString`.
+pub fn to_string(value: &RichValue) -> String {
+    match value {
+        RichValue::Boolean(b) => b.to_string(),
+        RichValue::Number(n) | RichValue::PositiveNumber(n) | RichValue::Angle(n) => {
+            number_to_string(*n)
+        }
+        RichValue::Integer(i) => i.to_string(),
+        RichValue::PositiveInteger(i) => i.to_string(),
+        RichValue::String(s) | RichValue::Color(s) | RichValue::Broadcast(s) => s.clone(),
+    }
+}
+
+/// Scratch's `Cast.isWhiteSpace`: whether the value is a string made only of whitespace.
+pub fn is_whitespace(value: &RichValue) -> bool {
+    match value {
+        RichValue::String(s) | RichValue::Color(s) | RichValue::Broadcast(s) => {
+            js_trim(s).is_empty()
+        }
+        _ => false,
+    }
+}
+
+/// Scratch's `Cast.isInt`: whether the value should be treated as an integer,
+/// e.g. by `pick random`. Strings count as integers unless they contain a dot.
+pub fn is_int(value: &RichValue) -> bool {
+    match value {
+        RichValue::Boolean(_) | RichValue::Integer(_) | RichValue::PositiveInteger(_) => true,
+        RichValue::Number(n) | RichValue::PositiveNumber(n) | RichValue::Angle(n) => {
+            n.is_nan() || *n == n.floor()
+        }
+        RichValue::String(s) | RichValue::Color(s) | RichValue::Broadcast(s) => !s.contains('.'),
+    }
+}
+
+/// Scratch's `Cast.compare`. Values are compared as numbers when both look like
+/// numbers, and as case-insensitive strings otherwise.
+pub fn compare(v1: &RichValue, v2: &RichValue) -> Ordering {
+    let mut n1 = js_number(v1);
+    let mut n2 = js_number(v2);
+    if n1 == 0.0 && is_whitespace(v1) {
+        n1 = f64::NAN;
+    } else if n2 == 0.0 && is_whitespace(v2) {
+        n2 = f64::NAN;
+    }
+    if n1.is_nan() || n2.is_nan() {
+        let s1 = to_string(v1).to_lowercase();
+        let s2 = to_string(v2).to_lowercase();
+        // JavaScript compares strings by UTF-16 code units.
+        return s1.encode_utf16().cmp(s2.encode_utf16());
+    }
+    if n1.is_infinite() && n1 == n2 {
+        return Ordering::Equal;
+    }
+    n1.partial_cmp(&n2).unwrap_or(Ordering::Equal)
+}
+
+/// Scratch's `Math.round`, which rounds halves towards positive infinity.
+pub fn round(n: f64) -> f64 {
+    let floor = n.floor();
+    if n - floor >= 0.5 {
+        return floor + 1.0;
+    }
+    floor
+}
+
+impl PrimitiveValue {
+    /// Shorthand for [`to_number`].
+    pub fn to_number(&self) -> f64 {
+        to_number(&self.into())
+    }
+
+    /// Shorthand for [`to_boolean`].
+    pub fn to_boolean(&self) -> bool {
+        to_boolean(&self.into())
+    }
+}
@@ -24,12 +24,12 @@ impl std::error::Error for ScratchError {}
 
 impl Display for ScratchError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Runtime error occured. Traceback:\n")?;
+        writeln!(f, "Runtime error occured. Traceback:")?;
         for t in &self.trace {
-            write!(f, "at {}\n", t.location)?;
-            write!(f, "    {:?}: {}\n", t.error_type, t.description)?;
+            writeln!(f, "at {}", t.location)?;
+            writeln!(f, "    {:?}: {}", t.error_type, t.description)?;
         }
-        write!(f, "See the above traceback for details.\n")
+        writeln!(f, "See the above traceback for details.")
     }
 }
 
@@ -1,4 +1,5 @@
+pub mod cast;
+pub mod errors;
 pub mod model;
 pub mod parser;
 pub mod prelude;
-pub mod errors;
\ No newline at end of file
@@ -15,7 +15,7 @@ pub struct Costume {
     pub data_format: String,
     pub asset_id: String,
     pub md5ext: String,
-    
+
     pub rotation_center_x: f64,
     pub rotation_center_y: f64,
     pub bitmap_resolution: Option<f64>,
@@ -1,4 +1,4 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BlockType {
     #[cfg_attr(feature = "serde", serde(rename = "motion_movesteps"))]
@@ -103,6 +103,14 @@ pub enum BlockType {
     ControlWait,
     #[cfg_attr(feature = "serde", serde(rename = "control_repeat"))]
     ControlRepeat,
+    #[cfg_attr(feature = "serde", serde(rename = "control_forever"))]
+    ControlForever,
+    #[cfg_attr(feature = "serde", serde(rename = "control_repeat_until"))]
+    ControlRepeatUntil,
+    #[cfg_attr(feature = "serde", serde(rename = "control_while"))]
+    ControlWhile,
+    #[cfg_attr(feature = "serde", serde(rename = "control_wait_until"))]
+    ControlWaitUntil,
     #[cfg_attr(feature = "serde", serde(rename = "control_if"))]
     ControlIf,
     #[cfg_attr(feature = "serde", serde(rename = "control_if_else"))]
@@ -136,10 +144,16 @@ pub enum BlockType {
     SensingSetDragMode,
     #[cfg_attr(feature = "serde", serde(rename = "sensing_resettimer"))]
     SensingResetTimer,
+    #[cfg_attr(feature = "serde", serde(rename = "sensing_timer"))]
+    SensingTimer,
     #[cfg_attr(feature = "serde", serde(rename = "sensing_dayssince2000"))]
     SensingDaysSince2000,
     #[cfg_attr(feature = "serde", serde(rename = "sensing_username"))]
     SensingUsername,
+    #[cfg_attr(feature = "serde", serde(rename = "sensing_askandwait"))]
+    SensingAskAndWait,
+    #[cfg_attr(feature = "serde", serde(rename = "sensing_answer"))]
+    SensingAnswer,
 
     #[cfg_attr(feature = "serde", serde(rename = "operator_add"))]
     OperatorAdd,
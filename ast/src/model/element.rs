@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use crate::cast;
 use crate::errors::ScratchError;
 
 use super::BlockType;
@@ -126,7 +127,7 @@ pub struct ProcedurePrototype {
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
-/// The prototype of a procedure, specifying its name and argument list.
+/// The mutation of the stop block, telling whether blocks can follow it.
 pub struct ControlStopMutation {
     /// Often an empty list.
     pub children: Vec<String>,
@@ -134,7 +135,10 @@ pub struct ControlStopMutation {
     /// Always 'mutation'
     pub tag_name: String,
 
-    #[cfg_attr(feature = "serde", serde(rename = "hasnext"))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "hasnext", with = "serde_nested_json")
+    )]
     pub has_next: bool,
 }
 
@@ -224,19 +228,7 @@ impl From<PrimitiveValue> for String {
         match value {
             PrimitiveValue::String(s) => s,
             PrimitiveValue::Integer(i) => i.to_string(),
-            PrimitiveValue::Number(i) => {
-                if i.is_nan() {
-                    return String::from("NaN");
-                }
-                if i.is_infinite() {
-                    if i.is_sign_negative() {
-                        return String::from("-Infinity");
-                    }
-                    return String::from("Infinity");
-                }
-
-                i.to_string()
-            }
+            PrimitiveValue::Number(i) => cast::number_to_string(i),
         }
     }
 }
@@ -245,16 +237,7 @@ impl TryFrom<PrimitiveValue> for f64 {
     type Error = ScratchError;
 
     fn try_from(value: PrimitiveValue) -> Result<Self, Self::Error> {
-        match value {
-            PrimitiveValue::String(s) => s.parse().map_err(|e| {
-                ScratchError::type_error(
-                    format!("Cannot convert {:#?} to an f64: {e}", s),
-                    format!("converting {:#?} to an f64: {e}", s),
-                )
-            }),
-            PrimitiveValue::Integer(i) => Ok(i as f64),
-            PrimitiveValue::Number(i) => Ok(i),
-        }
+        Ok(value.to_number())
     }
 }
 
@@ -319,19 +302,7 @@ impl TryInto<bool> for PrimitiveValue {
     type Error = String;
 
     fn try_into(self) -> Result<bool, Self::Error> {
-        match self {
-            PrimitiveValue::String(s) => {
-                if s == "true" {
-                    Ok(true)
-                } else if s == "false" {
-                    Ok(false)
-                } else {
-                    Err(format!("Cannot convert {s:#?} to a boolean"))
-                }
-            }
-            PrimitiveValue::Integer(i) => Err(format!("Cannot convert {i:#?} to a boolean")),
-            PrimitiveValue::Number(i) => Err(format!("Cannot convert {i:#?} to a boolean")),
-        }
+        Ok(self.to_boolean())
     }
 }
 
@@ -494,8 +465,8 @@ pub struct Comment {
     /// The ID of this comment
     #[serde(skip)]
     pub obj_id: String,
-    /// The block this comment is attached to.
-    pub block_id: String,
+    /// The block this comment is attached to, if it is not on the workspace.
+    pub block_id: Option<String>,
     pub x: f64,
     pub y: f64,
     pub width: f64,
@@ -512,6 +483,9 @@ pub struct Comment {
 pub struct Variable {
     pub name: String,
     pub value: PrimitiveValue,
+    /// Whether the variable is stored on the cloud server.
+    /// Only variables of the stage can be cloud variables.
+    pub is_cloud: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
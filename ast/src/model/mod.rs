@@ -1,11 +1,11 @@
-pub mod project;
-pub mod target;
 pub mod assets;
-pub mod element;
 pub mod blocktype;
+pub mod element;
+pub mod project;
+pub mod target;
 
-pub use project::*;
-pub use target::*;
 pub use assets::*;
-pub use element::*;
 pub use blocktype::*;
+pub use element::*;
+pub use project::*;
+pub use target::*;
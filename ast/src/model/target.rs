@@ -1,8 +1,8 @@
-use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
+use crate::model::Variable;
 use crate::model::assets::{Costume, Sound};
 use crate::model::element::{Block, Comment, List};
-use crate::model::Variable;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase", untagged)]
@@ -14,12 +14,8 @@ pub enum Target {
 impl Target {
     pub fn blocks(&self) -> HashMap<String, Block> {
         match self {
-            Self::Sprite(s) => {
-                s.blocks.clone()
-            },
-            Self::Stage(s) => {
-                s.blocks.clone()
-            }
+            Self::Sprite(s) => s.blocks.clone(),
+            Self::Stage(s) => s.blocks.clone(),
         }
     }
 }
@@ -37,7 +33,7 @@ impl RotationStyle {
             Self::AllAround => "all around",
             Self::LeftRight => "left-right",
             Self::DontRotate => "don't rotate",
-        }   
+        }
     }
 }
 
@@ -64,7 +60,7 @@ pub struct Sprite {
     pub broadcasts: HashMap<String, String>,
     pub variables: HashMap<String, Variable>,
     pub lists: HashMap<String, List>,
-    pub comments: CommentList, 
+    pub comments: CommentList,
 
     pub visible: bool,
     pub x: f64,
@@ -75,7 +71,6 @@ pub struct Sprite {
     pub rotation_style: RotationStyle,
 }
 
-
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Stage {
@@ -90,7 +85,7 @@ pub struct Stage {
     pub broadcasts: HashMap<String, String>,
     pub variables: HashMap<String, Variable>,
     pub lists: HashMap<String, List>,
-    pub comments: CommentList, 
+    pub comments: CommentList,
 
     pub video_transparency: i32,
     pub video_state: String,
@@ -100,5 +95,9 @@ pub struct Stage {
     pub text_to_speech_language: Option<String>,
 }
 
-fn default_tempo() -> i32 {60}
-fn default_none() -> Option<String> {Option::None}
\ No newline at end of file
+fn default_tempo() -> i32 {
+    60
+}
+fn default_none() -> Option<String> {
+    Option::None
+}
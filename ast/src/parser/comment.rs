@@ -1,16 +1,17 @@
 use crate::model::element::Comment;
 use crate::model::target::CommentList;
-use std::ops::{Deref, DerefMut};
 use serde::de::Visitor;
-use serde::{Deserialize, Serialize};
 use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
 
 struct CommentVisitor;
 
 impl Serialize for CommentList {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-        where
-            S: serde::Serializer {
+    where
+        S: serde::Serializer,
+    {
         let mut map = serializer.serialize_map(Some(self.len()))?;
         for b in self.0.clone().into_iter() {
             map.serialize_entry(&b.obj_id, &b)?;
@@ -35,9 +36,9 @@ impl DerefMut for CommentList {
 
 impl<'de> Deserialize<'de> for CommentList {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-        where
-            D: serde::Deserializer<'de> {
-        
+    where
+        D: serde::Deserializer<'de>,
+    {
         deserializer.deserialize_map(CommentVisitor::new())
     }
 }
@@ -56,16 +57,17 @@ impl<'de> Visitor<'de> for CommentVisitor {
     }
 
     fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
-        where
-            A: serde::de::MapAccess<'de>, {
-        
+    where
+        A: serde::de::MapAccess<'de>,
+    {
         let mut output: CommentList = CommentList(Vec::new());
         while let Some((id, content)) = map.next_entry::<String, serde_json::Value>()? {
-            let mut b: Comment = serde_json::from_value(content).unwrap();
+            let mut b: Comment =
+                serde_json::from_value(content).map_err(serde::de::Error::custom)?;
             b.obj_id = id;
             output.push(b);
         }
-        
+
         Ok(output)
     }
-}
\ No newline at end of file
+}
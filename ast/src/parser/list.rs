@@ -1,7 +1,7 @@
 use crate::model::PrimitiveValue;
 use crate::model::element::List;
 use serde::de::Visitor;
-use serde::ser::{SerializeSeq};
+use serde::ser::SerializeSeq;
 use serde::{Deserialize, Serialize};
 
 struct ListVisitor;
@@ -1,17 +1,18 @@
 use std::collections::HashMap;
 
-use serde::{de::value::MapDeserializer, Serialize};
-use serde::de::Visitor;
 use serde::Deserialize;
+use serde::de::Visitor;
+use serde::{Serialize, de::value::MapDeserializer};
 use serde_json::Value;
 
-use crate::model::target::{Sprite, Stage, Target};
 use crate::model::RotationStyle;
+use crate::model::target::{Sprite, Stage, Target};
 
 impl<'de> Deserialize<'de> for Target {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-        where
-            D: serde::Deserializer<'de> {
+    where
+        D: serde::Deserializer<'de>,
+    {
         deserializer.deserialize_map(TargetVisitor {})
     }
 }
@@ -25,10 +26,10 @@ impl<'de> Visitor<'de> for TargetVisitor {
         formatter.write_str("a JSON object property representing a target")
     }
 
-    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>         
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
     where
-        A: serde::de::MapAccess<'de>, 
-        {
+        A: serde::de::MapAccess<'de>,
+    {
         let mut h: HashMap<String, Value> = HashMap::new();
         while let Some((prop, val)) = map.next_entry::<String, serde_json::Value>()? {
             h.insert(prop, val);
@@ -36,9 +37,15 @@ impl<'de> Visitor<'de> for TargetVisitor {
 
         if let Some(v) = h.get("isStage") {
             if v.as_bool().unwrap() {
-                return Ok(Target::Stage(Stage::deserialize(MapDeserializer::new(h.into_iter())).map_err(|e| -> A::Error {serde::de::Error::custom(e.to_string())})?));
+                return Ok(Target::Stage(
+                    Stage::deserialize(MapDeserializer::new(h.into_iter()))
+                        .map_err(|e| -> A::Error { serde::de::Error::custom(e.to_string()) })?,
+                ));
             } else {
-                return Ok(Target::Sprite(Sprite::deserialize(MapDeserializer::new(h.into_iter())).map_err(|e| -> A::Error {serde::de::Error::custom(e.to_string())})?));
+                return Ok(Target::Sprite(
+                    Sprite::deserialize(MapDeserializer::new(h.into_iter()))
+                        .map_err(|e| -> A::Error { serde::de::Error::custom(e.to_string()) })?,
+                ));
             }
         }
 
@@ -50,8 +57,9 @@ struct RotationStyleVisitor;
 
 impl Serialize for RotationStyle {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-        where
-            S: serde::Serializer {
+    where
+        S: serde::Serializer,
+    {
         match self {
             RotationStyle::AllAround => serializer.serialize_str("all around"),
             RotationStyle::LeftRight => serializer.serialize_str("left-right"),
@@ -62,8 +70,9 @@ impl Serialize for RotationStyle {
 
 impl<'de> Deserialize<'de> for RotationStyle {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-        where
-            D: serde::Deserializer<'de> {
+    where
+        D: serde::Deserializer<'de>,
+    {
         deserializer.deserialize_str(RotationStyleVisitor {})
     }
 }
@@ -76,17 +85,14 @@ impl<'de> Visitor<'de> for RotationStyleVisitor {
     }
 
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
-        where
-            E: serde::de::Error, {
+    where
+        E: serde::de::Error,
+    {
         match v {
             "all around" => Ok(RotationStyle::AllAround),
             "left-right" => Ok(RotationStyle::LeftRight),
             "don't rotate" => Ok(RotationStyle::DontRotate),
-            _ => Err(serde::de::Error::custom("invalid rotation style"))
+            _ => Err(serde::de::Error::custom("invalid rotation style")),
         }
     }
 }
-
-
-
-
@@ -6,6 +6,29 @@ use crate::model::{BlockRef, Evaluable, RichValue, ShadowType, ShadowValue, Valu
 
 struct BlockRefVisitor;
 
+/// Reads the content of a number slot. Number slots can still hold any text,
+/// such as `last` in the index slot of list blocks, which is kept as a string.
+fn numeric_literal<T: std::str::FromStr>(
+    raw: serde_json::Value,
+    variant: fn(T) -> RichValue,
+) -> RichValue {
+    let text = match raw {
+        serde_json::Value::String(s) => s,
+        v => v.to_string(),
+    };
+    // Rust would also accept words like `inf` and `NaN`, which Scratch does not.
+    if text
+        .chars()
+        .any(|c| c.is_ascii_alphabetic() && !matches!(c, 'e' | 'E'))
+    {
+        return RichValue::String(text);
+    }
+    match text.parse() {
+        Ok(n) => variant(n),
+        Err(_) => RichValue::String(text),
+    }
+}
+
 impl Serialize for BlockRef {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -160,40 +183,25 @@ impl<'de> Visitor<'de> for ValueVisitor {
     {
         let it: i32 = seq.next_element().unwrap().unwrap();
         match it {
-            4 => Ok(Evaluable::Bare(RichValue::Number(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0.0),
+            4 => Ok(Evaluable::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::Number,
             ))),
-            5 => Ok(Evaluable::Bare(RichValue::PositiveNumber(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0.0),
+            5 => Ok(Evaluable::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::PositiveNumber,
             ))),
-            6 => Ok(Evaluable::Bare(RichValue::PositiveInteger(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0),
+            6 => Ok(Evaluable::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::PositiveInteger,
             ))),
-            7 => Ok(Evaluable::Bare(RichValue::Integer(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0),
+            7 => Ok(Evaluable::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::Integer,
             ))),
-            8 => Ok(Evaluable::Bare(RichValue::Angle(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0.0),
+            8 => Ok(Evaluable::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::Angle,
             ))),
             9 => Ok(Evaluable::Bare(RichValue::Color(
                 seq.next_element().unwrap().expect("Malformed project file"),
@@ -201,9 +209,12 @@ impl<'de> Visitor<'de> for ValueVisitor {
             10 => Ok(Evaluable::Bare(RichValue::String(
                 seq.next_element().unwrap().expect("Malformed project file"),
             ))),
-            11 => Ok(Evaluable::Bare(RichValue::Broadcast(
-                seq.next_element().unwrap().expect("Malformed project file"),
-            ))),
+            11 => {
+                let name = seq.next_element()?.expect("Malformed project file");
+                // Broadcasts are found by name, the ID that follows is not needed.
+                seq.next_element::<serde_json::Value>()?;
+                Ok(Evaluable::Bare(RichValue::Broadcast(name)))
+            }
             12 => Ok(Evaluable::Pointer(ValuePointer::Variable {
                 name: seq.next_element().unwrap().unwrap(),
                 id: seq.next_element().unwrap().unwrap(),
@@ -289,40 +300,25 @@ impl<'de> Visitor<'de> for ShadowValueVisitor {
     {
         let it: i32 = seq.next_element().unwrap().unwrap();
         match it {
-            4 => Ok(ShadowValue::Bare(RichValue::Number(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0.0),
+            4 => Ok(ShadowValue::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::Number,
             ))),
-            5 => Ok(ShadowValue::Bare(RichValue::PositiveNumber(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0.0),
+            5 => Ok(ShadowValue::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::PositiveNumber,
             ))),
-            6 => Ok(ShadowValue::Bare(RichValue::PositiveInteger(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0),
+            6 => Ok(ShadowValue::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::PositiveInteger,
             ))),
-            7 => Ok(ShadowValue::Bare(RichValue::Integer(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0),
+            7 => Ok(ShadowValue::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::Integer,
             ))),
-            8 => Ok(ShadowValue::Bare(RichValue::Angle(
-                seq.next_element::<String>()
-                    .unwrap()
-                    .unwrap()
-                    .parse()
-                    .unwrap_or(0.0),
+            8 => Ok(ShadowValue::Bare(numeric_literal(
+                seq.next_element()?.expect("Malformed project file"),
+                RichValue::Angle,
             ))),
             9 => Ok(ShadowValue::Bare(RichValue::Color(
                 seq.next_element().unwrap().expect("Malformed project file"),
@@ -330,9 +326,12 @@ impl<'de> Visitor<'de> for ShadowValueVisitor {
             10 => Ok(ShadowValue::Bare(RichValue::String(
                 seq.next_element().unwrap().expect("Malformed project file"),
             ))),
-            11 => Ok(ShadowValue::Bare(RichValue::Broadcast(
-                seq.next_element().unwrap().expect("Malformed project file"),
-            ))),
+            11 => {
+                let name = seq.next_element()?.expect("Malformed project file");
+                // Broadcasts are found by name, the ID that follows is not needed.
+                seq.next_element::<serde_json::Value>()?;
+                Ok(ShadowValue::Bare(RichValue::Broadcast(name)))
+            }
             12 => Ok(ShadowValue::Pointer(ValuePointer::Variable {
                 name: seq.next_element().unwrap().unwrap(),
                 id: seq.next_element().unwrap().unwrap(),
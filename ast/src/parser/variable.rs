@@ -11,9 +11,12 @@ impl Serialize for Variable {
     where
         S: serde::Serializer,
     {
-        let mut seq = serializer.serialize_seq(Some(2))?;
+        let mut seq = serializer.serialize_seq(Some(if self.is_cloud { 3 } else { 2 }))?;
         seq.serialize_element(&self.name)?;
         seq.serialize_element(&self.value)?;
+        if self.is_cloud {
+            seq.serialize_element(&true)?;
+        }
         seq.end()
     }
 }
@@ -53,6 +56,7 @@ impl<'de> Visitor<'de> for VariableVisitor {
                 .next_element::<PrimitiveValue>()
                 .expect("enum variable array length is shorter than 2")
                 .expect("cannot parse variable value"),
+            is_cloud: seq.next_element::<bool>()?.unwrap_or(false),
         })
     }
 }
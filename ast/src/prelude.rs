@@ -1,4 +1,4 @@
+pub use crate::errors::*;
 #[allow(ambiguous_glob_reexports)]
 pub use crate::model::*;
 pub use crate::parser::*;
-pub use crate::errors::*;
\ No newline at end of file
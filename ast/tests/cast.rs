@@ -0,0 +1,152 @@
+//! Checks the conversions in `cast` against what scratch-vm does.
+
+use std::cmp::Ordering;
+
+use scratch_ast::{cast, model::RichValue};
+
+fn text(s: &str) -> RichValue {
+    RichValue::String(s.to_string())
+}
+
+#[test]
+fn to_number() {
+    assert_eq!(cast::to_number(&text("")), 0.0);
+    assert_eq!(cast::to_number(&text(" \t\n")), 0.0);
+    assert_eq!(cast::to_number(&text(" 12 ")), 12.0);
+    assert_eq!(cast::to_number(&text("\u{00A0}7\u{3000}")), 7.0);
+    assert_eq!(cast::to_number(&text("0x1f")), 31.0);
+    assert_eq!(cast::to_number(&text("0b101")), 5.0);
+    assert_eq!(cast::to_number(&text("0o17")), 15.0);
+    assert_eq!(cast::to_number(&text("0x")), 0.0);
+    assert_eq!(cast::to_number(&text("0xg")), 0.0);
+    assert_eq!(cast::to_number(&text("Infinity")), f64::INFINITY);
+    assert_eq!(cast::to_number(&text("-Infinity")), f64::NEG_INFINITY);
+    assert_eq!(cast::to_number(&text("infinity")), 0.0);
+    assert_eq!(cast::to_number(&text("1e3")), 1000.0);
+    assert_eq!(cast::to_number(&text(".5")), 0.5);
+    assert_eq!(cast::to_number(&text("5.")), 5.0);
+    assert_eq!(cast::to_number(&text(".")), 0.0);
+    assert_eq!(cast::to_number(&text("1e")), 0.0);
+    assert_eq!(cast::to_number(&text("12abc")), 0.0);
+    assert_eq!(cast::to_number(&RichValue::Number(f64::NAN)), 0.0);
+    assert_eq!(cast::to_number(&RichValue::Boolean(true)), 1.0);
+}
+
+#[test]
+fn to_boolean() {
+    assert!(!cast::to_boolean(&text("")));
+    assert!(!cast::to_boolean(&text("0")));
+    assert!(!cast::to_boolean(&text("false")));
+    assert!(!cast::to_boolean(&text("FaLsE")));
+    assert!(cast::to_boolean(&text(" ")));
+    assert!(cast::to_boolean(&text("0.0")));
+    assert!(cast::to_boolean(&text("false ")));
+    assert!(cast::to_boolean(&text("true")));
+    assert!(!cast::to_boolean(&RichValue::Number(0.0)));
+    assert!(!cast::to_boolean(&RichValue::Number(-0.0)));
+    assert!(!cast::to_boolean(&RichValue::Number(f64::NAN)));
+    assert!(cast::to_boolean(&RichValue::Number(0.5)));
+    assert!(!cast::to_boolean(&RichValue::Integer(0)));
+}
+
+#[test]
+fn is_int() {
+    assert!(cast::is_int(&text("1")));
+    assert!(cast::is_int(&text("abc")));
+    assert!(!cast::is_int(&text("1.0")));
+    assert!(!cast::is_int(&text(".5")));
+    assert!(cast::is_int(&RichValue::Number(1.0)));
+    assert!(!cast::is_int(&RichValue::Number(1.5)));
+    assert!(cast::is_int(&RichValue::Number(f64::NAN)));
+    assert!(cast::is_int(&RichValue::Boolean(false)));
+    assert!(cast::is_int(&RichValue::Integer(-3)));
+}
+
+#[test]
+fn compare_numbers() {
+    assert_eq!(cast::compare(&text("10"), &text("9")), Ordering::Greater);
+    assert_eq!(
+        cast::compare(&text(" 2 "), &RichValue::Number(2.0)),
+        Ordering::Equal
+    );
+    assert_eq!(
+        cast::compare(&text("0x1f"), &RichValue::Integer(31)),
+        Ordering::Equal
+    );
+    assert_eq!(
+        cast::compare(&RichValue::Boolean(true), &text("1")),
+        Ordering::Equal
+    );
+    assert_eq!(
+        cast::compare(&text("Infinity"), &RichValue::Number(f64::INFINITY)),
+        Ordering::Equal
+    );
+    assert_eq!(
+        cast::compare(&text("-Infinity"), &RichValue::Number(f64::INFINITY)),
+        Ordering::Less
+    );
+}
+
+#[test]
+fn compare_strings() {
+    assert_eq!(cast::compare(&text("abc"), &text("ABC")), Ordering::Equal);
+    assert_eq!(cast::compare(&text("a"), &text("B")), Ordering::Less);
+    assert_eq!(
+        cast::compare(&text("Zebra"), &text("apple")),
+        Ordering::Greater
+    );
+    // Only one side looks like a number, so both are compared as strings.
+    assert_eq!(cast::compare(&text("10"), &text("9a")), Ordering::Less);
+    // Whitespace is not 0 when comparing.
+    assert_eq!(
+        cast::compare(&text(" "), &RichValue::Number(0.0)),
+        Ordering::Less
+    );
+    assert_eq!(
+        cast::compare(&text(""), &RichValue::Number(0.0)),
+        Ordering::Less
+    );
+}
+
+#[test]
+fn compare_nan() {
+    let nan = RichValue::Number(f64::NAN);
+    assert_eq!(cast::compare(&nan, &nan), Ordering::Equal);
+    assert_eq!(cast::compare(&nan, &text("NaN")), Ordering::Equal);
+    assert_eq!(cast::compare(&nan, &text("nan")), Ordering::Equal);
+    assert_eq!(
+        cast::compare(&nan, &RichValue::Number(1.0)),
+        Ordering::Greater
+    );
+    assert_eq!(cast::compare(&nan, &text("z")), Ordering::Less);
+}
+
+#[test]
+fn number_to_string() {
+    assert_eq!(cast::number_to_string(1e21), "1e+21");
+    assert_eq!(cast::number_to_string(1e20), "100000000000000000000");
+    assert_eq!(cast::number_to_string(1.5e21), "1.5e+21");
+    assert_eq!(cast::number_to_string(1e-7), "1e-7");
+    assert_eq!(cast::number_to_string(1.5e-7), "1.5e-7");
+    assert_eq!(cast::number_to_string(1e-6), "0.000001");
+    assert_eq!(cast::number_to_string(-0.0), "0");
+    assert_eq!(cast::number_to_string(0.1 + 0.2), "0.30000000000000004");
+    assert_eq!(cast::number_to_string(-2.5), "-2.5");
+    assert_eq!(cast::number_to_string(123.0), "123");
+    assert_eq!(cast::number_to_string(f64::NAN), "NaN");
+    assert_eq!(cast::number_to_string(f64::NEG_INFINITY), "-Infinity");
+}
+
+#[test]
+fn strings_count_utf16_units() {
+    assert_eq!(cast::js_length("abc"), 3);
+    assert_eq!(cast::js_length("é"), 1);
+    assert_eq!(cast::js_length("😀"), 2);
+    assert_eq!(cast::js_length("a😀b"), 4);
+    // Each half of a surrogate pair is a letter of its own.
+    assert_eq!(cast::js_char_at("😀", 0), "\u{FFFD}");
+    assert_eq!(cast::js_char_at("😀", 1), "\u{FFFD}");
+    assert_eq!(cast::js_char_at("😀", 2), "");
+    assert_eq!(cast::js_char_at("a😀b", 3), "b");
+    assert_eq!(cast::js_char_at("é", 0), "é");
+}
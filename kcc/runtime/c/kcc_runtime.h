@@ -0,0 +1,1291 @@
+/*
+ * Scratch semantics for projects compiled to C by kcc.
+ *
+ * Every script is a state machine, and scripts take turns on a single thread
+ * like in Scratch, so the runtime needs nothing but standard C99.
+ * Values own their text: every function taking a kcc_value frees it,
+ * and every function returning one gives it to the caller.
+ *
+ * Build with: cc -std=c99 project.c -lm
+ */
+#ifndef KCC_RUNTIME_H
+#define KCC_RUNTIME_H
+
+#include <ctype.h>
+#include <locale.h>
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <wctype.h>
+
+/* Lists cannot grow past this many items. */
+#define KCC_LIST_ITEM_LIMIT 200000
+#define KCC_START_OF_2000_TIMESTAMP 946684800.0
+#define KCC_MILISECS_IN_A_DAY (1000.0 * 60 * 60 * 24)
+#define KCC_PI 3.14159265358979323846
+/* The script of no thread. */
+#define KCC_NO_SCRIPT ((size_t)-1)
+
+typedef enum { KCC_NUMBER, KCC_BOOLEAN, KCC_STRING } kcc_kind;
+
+/* A Scratch value. Booleans keep 0 or 1 in `number`. */
+typedef struct {
+    kcc_kind kind;
+    double number;
+    char *string;
+} kcc_value;
+
+/* Why a frame stopped running. */
+typedef enum {
+    /* The end of a loop iteration, which ends the turn of its thread. */
+    KCC_YIELD,
+    /* Waiting until the frame's `wake`. */
+    KCC_SLEEP,
+    /* The frame's `callee` runs first, then the frame goes on. */
+    KCC_CALL,
+    KCC_RETURN,
+    KCC_STOP_ALL
+} kcc_turn;
+
+/* A script or custom block being run: a state machine that `resume` runs
+ * until its thread's turn ends or another frame has to run. */
+typedef struct kcc_frame kcc_frame;
+struct kcc_frame {
+    kcc_turn (*resume)(kcc_frame *frame);
+    /* Frees the frame and the values it keeps. */
+    void (*drop)(kcc_frame *frame);
+    int state;
+    /* When a sleeping frame wakes up, in seconds since the project started. */
+    double wake;
+    kcc_frame *callee;
+};
+
+/* A script receiving a broadcast, by lowercase name. */
+typedef struct {
+    const char *name;
+    size_t script;
+} kcc_receiver;
+
+/* A running script and the custom blocks it is in. */
+typedef struct {
+    size_t script;
+    kcc_frame **frames;
+    size_t depth;
+    size_t capacity;
+    int sleeping;
+    double wake;
+} kcc_thread;
+
+typedef struct {
+    kcc_value *items;
+    size_t length;
+    size_t capacity;
+} kcc_list;
+
+static kcc_value *kcc_variables;
+static kcc_list *kcc_lists;
+static double kcc_started;
+static double kcc_timer_start;
+static char *kcc_answer_text;
+static uint64_t kcc_seed;
+static const kcc_receiver *kcc_receiver_table;
+static size_t kcc_receiver_count;
+static kcc_frame *(*kcc_start_frame)(size_t script);
+static kcc_thread *kcc_threads;
+static size_t kcc_thread_count;
+static size_t kcc_thread_capacity;
+/* The script taking its turn, which restarts after it if it starts itself. */
+static size_t kcc_current = KCC_NO_SCRIPT;
+static int kcc_restart;
+
+static inline void *kcc_alloc(size_t size) {
+    void *p = malloc(size ? size : 1);
+    if (!p) {
+        fputs("kcc: out of memory\n", stderr);
+        exit(1);
+    }
+    return p;
+}
+
+static inline void *kcc_grow(void *p, size_t size) {
+    p = realloc(p, size ? size : 1);
+    if (!p) {
+        fputs("kcc: out of memory\n", stderr);
+        exit(1);
+    }
+    return p;
+}
+
+static inline char *kcc_strndup(const char *s, size_t length) {
+    char *copy = (char *)kcc_alloc(length + 1);
+    memcpy(copy, s, length);
+    copy[length] = '\0';
+    return copy;
+}
+
+static inline char *kcc_strdup(const char *s) { return kcc_strndup(s, strlen(s)); }
+
+static inline kcc_value kcc_num(double n) {
+    kcc_value v = {KCC_NUMBER, n, NULL};
+    return v;
+}
+
+static inline kcc_value kcc_bool(int b) {
+    kcc_value v = {KCC_BOOLEAN, b ? 1.0 : 0.0, NULL};
+    return v;
+}
+
+/* A string value holding a copy of `s`. */
+static inline kcc_value kcc_str(const char *s) {
+    kcc_value v = {KCC_STRING, 0.0, kcc_strdup(s)};
+    return v;
+}
+
+/* A string value taking ownership of `s`. */
+static inline kcc_value kcc_str_owned(char *s) {
+    kcc_value v = {KCC_STRING, 0.0, s};
+    return v;
+}
+
+static inline void kcc_free(kcc_value v) { free(v.string); }
+
+static inline kcc_value kcc_copy(kcc_value v) {
+    if (v.string) {
+        v.string = kcc_strdup(v.string);
+    }
+    return v;
+}
+
+/* Replaces the value kept at `slot`, freeing the old one. */
+static inline void kcc_put(kcc_value *slot, kcc_value v) {
+    kcc_free(*slot);
+    *slot = v;
+}
+
+/* ---- Text ---- */
+
+/* Decodes the code point at `s`, storing its length in bytes in `length`.
+ * Malformed bytes decode to U+FFFD, one byte at a time. */
+static inline uint32_t kcc_utf8_decode(const char *s, size_t *length) {
+    const unsigned char *u = (const unsigned char *)s;
+    uint32_t cp;
+    size_t n, i;
+    if (u[0] < 0x80) {
+        *length = 1;
+        return u[0];
+    } else if ((u[0] & 0xE0) == 0xC0) {
+        cp = u[0] & 0x1F;
+        n = 2;
+    } else if ((u[0] & 0xF0) == 0xE0) {
+        cp = u[0] & 0x0F;
+        n = 3;
+    } else if ((u[0] & 0xF8) == 0xF0) {
+        cp = u[0] & 0x07;
+        n = 4;
+    } else {
+        *length = 1;
+        return 0xFFFD;
+    }
+    for (i = 1; i < n; i++) {
+        if ((u[i] & 0xC0) != 0x80) {
+            *length = 1;
+            return 0xFFFD;
+        }
+        cp = (cp << 6) | (u[i] & 0x3F);
+    }
+    *length = n;
+    return cp;
+}
+
+/* Writes the UTF-8 encoding of `cp` to `out`, returning its length. */
+static inline size_t kcc_utf8_encode(uint32_t cp, char *out) {
+    if (cp < 0x80) {
+        out[0] = (char)cp;
+        return 1;
+    } else if (cp < 0x800) {
+        out[0] = (char)(0xC0 | (cp >> 6));
+        out[1] = (char)(0x80 | (cp & 0x3F));
+        return 2;
+    } else if (cp < 0x10000) {
+        out[0] = (char)(0xE0 | (cp >> 12));
+        out[1] = (char)(0x80 | ((cp >> 6) & 0x3F));
+        out[2] = (char)(0x80 | (cp & 0x3F));
+        return 3;
+    }
+    out[0] = (char)(0xF0 | (cp >> 18));
+    out[1] = (char)(0x80 | ((cp >> 12) & 0x3F));
+    out[2] = (char)(0x80 | ((cp >> 6) & 0x3F));
+    out[3] = (char)(0x80 | (cp & 0x3F));
+    return 4;
+}
+
+/* Whitespace as understood by JavaScript's `String.prototype.trim`. */
+static inline int kcc_is_js_whitespace(uint32_t cp) {
+    switch (cp) {
+    case '\t': case '\n': case 0x0B: case 0x0C: case '\r': case ' ': case 0xA0:
+    case 0x1680: case 0x2028: case 0x2029: case 0x202F: case 0x205F: case 0x3000:
+    case 0xFEFF:
+        return 1;
+    default:
+        return cp >= 0x2000 && cp <= 0x200A;
+    }
+}
+
+/* Trims `s` the way JavaScript's `String.prototype.trim` does. */
+static inline void kcc_js_trim(const char *s, const char **start, const char **end) {
+    size_t length;
+    const char *last = s;
+    while (*s && kcc_is_js_whitespace(kcc_utf8_decode(s, &length))) {
+        s += length;
+    }
+    *start = s;
+    last = s;
+    while (*s) {
+        uint32_t cp = kcc_utf8_decode(s, &length);
+        s += length;
+        if (!kcc_is_js_whitespace(cp)) {
+            last = s;
+        }
+    }
+    *end = last;
+}
+
+/* The UTF-16 code units of `s`, like JavaScript strings. */
+static inline uint16_t *kcc_utf16(const char *s, size_t *count) {
+    uint16_t *units = (uint16_t *)kcc_alloc(sizeof(uint16_t) * (strlen(s) + 1));
+    size_t n = 0, length;
+    while (*s) {
+        uint32_t cp = kcc_utf8_decode(s, &length);
+        s += length;
+        if (cp >= 0x10000) {
+            cp -= 0x10000;
+            units[n++] = (uint16_t)(0xD800 | (cp >> 10));
+            units[n++] = (uint16_t)(0xDC00 | (cp & 0x3FF));
+        } else {
+            units[n++] = (uint16_t)cp;
+        }
+    }
+    *count = n;
+    return units;
+}
+
+/* The length of `s` in UTF-16 code units, like JavaScript's `String.prototype.length`. */
+static inline size_t kcc_js_length(const char *s) {
+    size_t n = 0, length;
+    while (*s) {
+        n += kcc_utf8_decode(s, &length) >= 0x10000 ? 2 : 1;
+        s += length;
+    }
+    return n;
+}
+
+/* The number of code points of `s`. */
+static inline size_t kcc_char_count(const char *s) {
+    size_t n = 0, length;
+    while (*s) {
+        kcc_utf8_decode(s, &length);
+        s += length;
+        n++;
+    }
+    return n;
+}
+
+/* A lowercase copy of `s`. */
+static inline char *kcc_lowercase(const char *s) {
+    char *out = (char *)kcc_alloc(strlen(s) * 2 + 1);
+    size_t n = 0, length;
+    while (*s) {
+        uint32_t cp = kcc_utf8_decode(s, &length);
+        s += length;
+        if (cp < 0x80) {
+            cp = (uint32_t)tolower((int)cp);
+        } else {
+            cp = (uint32_t)towlower((wint_t)cp);
+        }
+        n += kcc_utf8_encode(cp, out + n);
+    }
+    out[n] = '\0';
+    return out;
+}
+
+static inline int kcc_eq_ignore_ascii_case(const char *a, const char *b) {
+    while (*a && *b) {
+        if (tolower((unsigned char)*a) != tolower((unsigned char)*b)) {
+            return 0;
+        }
+        a++;
+        b++;
+    }
+    return *a == *b;
+}
+
+/* ---- Casting, matching `Cast` in scratch-vm ---- */
+
+static inline int kcc_is_decimal_literal(const char *s, size_t length) {
+    size_t i = 0, integer = 0, fraction = 0, exponent = 0;
+    while (i < length && s[i] >= '0' && s[i] <= '9') {
+        i++;
+        integer++;
+    }
+    if (i < length && s[i] == '.') {
+        i++;
+        while (i < length && s[i] >= '0' && s[i] <= '9') {
+            i++;
+            fraction++;
+        }
+    }
+    if (integer == 0 && fraction == 0) {
+        return 0;
+    }
+    if (i == length) {
+        return 1;
+    }
+    if (s[i] != 'e' && s[i] != 'E') {
+        return 0;
+    }
+    i++;
+    if (i < length && (s[i] == '+' || s[i] == '-')) {
+        i++;
+    }
+    while (i < length && s[i] >= '0' && s[i] <= '9') {
+        i++;
+        exponent++;
+    }
+    return exponent > 0 && i == length;
+}
+
+/* Parses `s` like JavaScript's `Number(s)`, returning NaN when `s` is not a number. */
+static inline double kcc_str_to_number(const char *s) {
+    const char *start, *end;
+    size_t length;
+    double sign = 1.0, n;
+    char *body;
+    kcc_js_trim(s, &start, &end);
+    length = (size_t)(end - start);
+    if (length == 0) {
+        return 0.0;
+    }
+    if (length > 2 && start[0] == '0') {
+        int radix = 0;
+        switch (start[1]) {
+        case 'x': case 'X': radix = 16; break;
+        case 'b': case 'B': radix = 2; break;
+        case 'o': case 'O': radix = 8; break;
+        }
+        if (radix) {
+            double acc = 0.0;
+            const char *c;
+            for (c = start + 2; c < end; c++) {
+                int digit;
+                if (*c >= '0' && *c <= '9') {
+                    digit = *c - '0';
+                } else if (*c >= 'a' && *c <= 'z') {
+                    digit = *c - 'a' + 10;
+                } else if (*c >= 'A' && *c <= 'Z') {
+                    digit = *c - 'A' + 10;
+                } else {
+                    return NAN;
+                }
+                if (digit >= radix) {
+                    return NAN;
+                }
+                acc = acc * radix + digit;
+            }
+            return acc;
+        }
+    }
+    if (start[0] == '+' || start[0] == '-') {
+        sign = start[0] == '-' ? -1.0 : 1.0;
+        start++;
+        length--;
+    }
+    if (length == 8 && memcmp(start, "Infinity", 8) == 0) {
+        return sign * INFINITY;
+    }
+    if (!kcc_is_decimal_literal(start, length)) {
+        return NAN;
+    }
+    body = kcc_strndup(start, length);
+    n = strtod(body, NULL);
+    free(body);
+    return sign * n;
+}
+
+/* Formats a number exactly like JavaScript's `Number.prototype.toString`:
+ * the shortest digits that round-trip, in plain notation for magnitudes between
+ * 1e-7 and 1e21 and in exponential notation (`1e+21`, `1.5e-7`) otherwise. */
+static inline char *kcc_number_to_string(double n) {
+    char scientific[40], digits[24], out[64];
+    int precision, k, e, i;
+    char *mark;
+    if (isnan(n)) {
+        return kcc_strdup("NaN");
+    }
+    if (n == 0.0) {
+        /* Negative zero too. */
+        return kcc_strdup("0");
+    }
+    if (isinf(n)) {
+        return kcc_strdup(n < 0 ? "-Infinity" : "Infinity");
+    }
+    if (n < 0) {
+        char *positive = kcc_number_to_string(-n);
+        char *negative = (char *)kcc_alloc(strlen(positive) + 2);
+        negative[0] = '-';
+        strcpy(negative + 1, positive);
+        free(positive);
+        return negative;
+    }
+    for (precision = 1; precision < 17; precision++) {
+        snprintf(scientific, sizeof scientific, "%.*e", precision - 1, n);
+        if (strtod(scientific, NULL) == n) {
+            break;
+        }
+    }
+    snprintf(scientific, sizeof scientific, "%.*e", precision - 1, n);
+    mark = strchr(scientific, 'e');
+    k = 0;
+    for (i = 0; scientific + i < mark; i++) {
+        if (scientific[i] != '.') {
+            digits[k++] = scientific[i];
+        }
+    }
+    digits[k] = '\0';
+    e = atoi(mark + 1) + 1;
+    if (k <= e && e <= 21) {
+        snprintf(out, sizeof out, "%s%.*s", digits, e - k, "000000000000000000000");
+    } else if (0 < e && e <= 21) {
+        snprintf(out, sizeof out, "%.*s.%s", e, digits, digits + e);
+    } else if (-6 < e && e <= 0) {
+        snprintf(out, sizeof out, "0.%.*s%s", -e, "000000", digits);
+    } else if (k == 1) {
+        snprintf(out, sizeof out, "%se%c%d", digits, e - 1 < 0 ? '-' : '+', abs(e - 1));
+    } else {
+        snprintf(out, sizeof out, "%c.%se%c%d", digits[0], digits + 1, e - 1 < 0 ? '-' : '+',
+                 abs(e - 1));
+    }
+    return kcc_strdup(out);
+}
+
+/* The value as JavaScript's `Number()` would see it, NaN included. */
+static inline double kcc_js_number(const kcc_value *v) {
+    if (v->kind == KCC_STRING) {
+        return kcc_str_to_number(v->string);
+    }
+    return v->number;
+}
+
+/* Scratch's `Cast.toNumber`: anything that is not a number becomes 0. */
+static inline double kcc_to_number(const kcc_value *v) {
+    double n = kcc_js_number(v);
+    return isnan(n) ? 0.0 : n;
+}
+
+/* Scratch's `Cast.toBoolean`: `""`, `"0"` and `"false"` (in any case) are false,
+ * as are 0 and NaN. Everything else is true. */
+static inline int kcc_to_boolean(const kcc_value *v) {
+    switch (v->kind) {
+    case KCC_BOOLEAN:
+        return v->number != 0.0;
+    case KCC_NUMBER:
+        return v->number != 0.0 && !isnan(v->number);
+    default:
+        return !(v->string[0] == '\0' || strcmp(v->string, "0") == 0 ||
+                 kcc_eq_ignore_ascii_case(v->string, "false"));
+    }
+}
+
+/* Scratch's `Cast.toString`. */
+static inline char *kcc_to_string(const kcc_value *v) {
+    switch (v->kind) {
+    case KCC_BOOLEAN:
+        return kcc_strdup(v->number != 0.0 ? "true" : "false");
+    case KCC_NUMBER:
+        return kcc_number_to_string(v->number);
+    default:
+        return kcc_strdup(v->string);
+    }
+}
+
+/* Scratch's `Cast.isWhiteSpace`. */
+static inline int kcc_is_whitespace(const kcc_value *v) {
+    const char *start, *end;
+    if (v->kind != KCC_STRING) {
+        return 0;
+    }
+    kcc_js_trim(v->string, &start, &end);
+    return start == end;
+}
+
+/* Scratch's `Cast.isInt`. Strings count as integers unless they contain a dot. */
+static inline int kcc_is_int(const kcc_value *v) {
+    switch (v->kind) {
+    case KCC_BOOLEAN:
+        return 1;
+    case KCC_NUMBER:
+        return isnan(v->number) || v->number == floor(v->number);
+    default:
+        return strchr(v->string, '.') == NULL;
+    }
+}
+
+/* Scratch's `Cast.compare`. Values are compared as numbers when both look like
+ * numbers, and as case-insensitive strings otherwise. */
+static inline int kcc_compare(const kcc_value *v1, const kcc_value *v2) {
+    double n1 = kcc_js_number(v1), n2 = kcc_js_number(v2);
+    if (n1 == 0.0 && kcc_is_whitespace(v1)) {
+        n1 = NAN;
+    } else if (n2 == 0.0 && kcc_is_whitespace(v2)) {
+        n2 = NAN;
+    }
+    if (isnan(n1) || isnan(n2)) {
+        char *s1 = kcc_to_string(v1), *s2 = kcc_to_string(v2);
+        char *l1 = kcc_lowercase(s1), *l2 = kcc_lowercase(s2);
+        size_t c1, c2, i;
+        /* JavaScript compares strings by UTF-16 code units. */
+        uint16_t *u1 = kcc_utf16(l1, &c1), *u2 = kcc_utf16(l2, &c2);
+        int result = 0;
+        for (i = 0; i < c1 && i < c2 && result == 0; i++) {
+            result = (u1[i] > u2[i]) - (u1[i] < u2[i]);
+        }
+        if (result == 0) {
+            result = (c1 > c2) - (c1 < c2);
+        }
+        free(s1);
+        free(s2);
+        free(l1);
+        free(l2);
+        free(u1);
+        free(u2);
+        return result;
+    }
+    if (isinf(n1) && n1 == n2) {
+        return 0;
+    }
+    return (n1 > n2) - (n1 < n2);
+}
+
+/* Scratch's `Math.round`, which rounds halves towards positive infinity. */
+static inline double kcc_round(double n) {
+    double f = floor(n);
+    return n - f >= 0.5 ? f + 1.0 : f;
+}
+
+/* What variables and lists hold: booleans become text. */
+static inline kcc_value kcc_primitive(kcc_value v) {
+    if (v.kind == KCC_BOOLEAN) {
+        return kcc_str(v.number != 0.0 ? "true" : "false");
+    }
+    return v;
+}
+
+/* Converts and frees `v`. */
+static inline double kcc_number(kcc_value v) {
+    double n = kcc_to_number(&v);
+    kcc_free(v);
+    return n;
+}
+
+/* Converts and frees `v`. */
+static inline int kcc_boolean(kcc_value v) {
+    int b = kcc_to_boolean(&v);
+    kcc_free(v);
+    return b;
+}
+
+/* Converts and frees `v`. */
+static inline char *kcc_string(kcc_value v) {
+    char *s = kcc_to_string(&v);
+    kcc_free(v);
+    return s;
+}
+
+/* ---- Operators ---- */
+
+static inline kcc_value kcc_add(kcc_value a, kcc_value b) {
+    double n1 = kcc_number(a);
+    return kcc_num(n1 + kcc_number(b));
+}
+
+static inline kcc_value kcc_subtract(kcc_value a, kcc_value b) {
+    double n1 = kcc_number(a);
+    return kcc_num(n1 - kcc_number(b));
+}
+
+static inline kcc_value kcc_multiply(kcc_value a, kcc_value b) {
+    double n1 = kcc_number(a);
+    return kcc_num(n1 * kcc_number(b));
+}
+
+static inline kcc_value kcc_divide(kcc_value a, kcc_value b) {
+    double n1 = kcc_number(a);
+    return kcc_num(n1 / kcc_number(b));
+}
+
+/* A random number in [0, 1). */
+static inline double kcc_random_unit(void) {
+    uint64_t x;
+    /* xorshift64* */
+    kcc_seed ^= kcc_seed >> 12;
+    kcc_seed ^= kcc_seed << 25;
+    kcc_seed ^= kcc_seed >> 27;
+    x = kcc_seed * 0x2545F4914F6CDD1DULL;
+    return (double)(x >> 11) / (double)(1ULL << 53);
+}
+
+static inline kcc_value kcc_random(kcc_value from, kcc_value to) {
+    double n1 = kcc_to_number(&from), n2 = kcc_to_number(&to);
+    double low = n1 <= n2 ? n1 : n2, high = n1 <= n2 ? n2 : n1;
+    int ints = kcc_is_int(&from) && kcc_is_int(&to);
+    kcc_free(from);
+    kcc_free(to);
+    if (low == high) {
+        return kcc_num(low);
+    }
+    if (ints) {
+        return kcc_num(low + floor(kcc_random_unit() * (high - low + 1.0)));
+    }
+    return kcc_num(low + kcc_random_unit() * (high - low));
+}
+
+static inline kcc_value kcc_gt(kcc_value a, kcc_value b) {
+    int result = kcc_compare(&a, &b) > 0;
+    kcc_free(a);
+    kcc_free(b);
+    return kcc_bool(result);
+}
+
+static inline kcc_value kcc_lt(kcc_value a, kcc_value b) {
+    int result = kcc_compare(&a, &b) < 0;
+    kcc_free(a);
+    kcc_free(b);
+    return kcc_bool(result);
+}
+
+static inline kcc_value kcc_equals(kcc_value a, kcc_value b) {
+    int result = kcc_compare(&a, &b) == 0;
+    kcc_free(a);
+    kcc_free(b);
+    return kcc_bool(result);
+}
+
+static inline kcc_value kcc_and(kcc_value a, kcc_value b) {
+    int b1 = kcc_boolean(a);
+    int b2 = kcc_boolean(b);
+    return kcc_bool(b1 && b2);
+}
+
+static inline kcc_value kcc_or(kcc_value a, kcc_value b) {
+    int b1 = kcc_boolean(a);
+    int b2 = kcc_boolean(b);
+    return kcc_bool(b1 || b2);
+}
+
+static inline kcc_value kcc_not(kcc_value a) { return kcc_bool(!kcc_boolean(a)); }
+
+static inline kcc_value kcc_join(kcc_value a, kcc_value b) {
+    char *s1 = kcc_string(a), *s2 = kcc_string(b);
+    size_t l1 = strlen(s1), l2 = strlen(s2);
+    char *joined = (char *)kcc_alloc(l1 + l2 + 1);
+    memcpy(joined, s1, l1);
+    memcpy(joined + l1, s2, l2 + 1);
+    free(s1);
+    free(s2);
+    return kcc_str_owned(joined);
+}
+
+static inline kcc_value kcc_letter_of(kcc_value letter, kcc_value string) {
+    double index = kcc_number(letter) - 1.0;
+    char *s = kcc_string(string);
+    size_t count;
+    uint16_t *units = kcc_utf16(s, &count);
+    char out[4];
+    size_t length = 0;
+    free(s);
+    if (index >= 0.0 && index < (double)count) {
+        uint16_t unit = units[(size_t)index];
+        /* Half of a surrogate pair cannot be represented on its own. */
+        length = kcc_utf8_encode(unit >= 0xD800 && unit <= 0xDFFF ? 0xFFFD : unit, out);
+    }
+    free(units);
+    return kcc_str_owned(kcc_strndup(out, length));
+}
+
+static inline kcc_value kcc_length(kcc_value string) {
+    char *s = kcc_string(string);
+    double length = (double)kcc_js_length(s);
+    free(s);
+    return kcc_num(length);
+}
+
+static inline kcc_value kcc_contains(kcc_value a, kcc_value b) {
+    char *s1 = kcc_string(a), *s2 = kcc_string(b);
+    char *l1 = kcc_lowercase(s1), *l2 = kcc_lowercase(s2);
+    int result = strstr(l1, l2) != NULL;
+    free(s1);
+    free(s2);
+    free(l1);
+    free(l2);
+    return kcc_bool(result);
+}
+
+static inline kcc_value kcc_modulo(kcc_value a, kcc_value b) {
+    double n = kcc_number(a), modulus = kcc_number(b);
+    double result = fmod(n, modulus);
+    /* Scratch's mod takes the sign of the divisor. */
+    if (result / modulus < 0.0) {
+        result += modulus;
+    }
+    return kcc_num(result);
+}
+
+static inline kcc_value kcc_round_value(kcc_value a) { return kcc_num(kcc_round(kcc_number(a))); }
+
+/* Scratch rounds trigonometric results to 10 decimal places, so that e.g. sin(180) is exactly 0. */
+static inline double kcc_round10(double x) { return round(x * 1e10) / 1e10; }
+
+static inline double kcc_radians(double x) { return KCC_PI * x / 180.0; }
+
+static inline double kcc_degrees(double x) { return x * (180.0 / KCC_PI); }
+
+static inline kcc_value kcc_mathop(kcc_value operator_, kcc_value number) {
+    char *op = kcc_string(operator_), *lower = kcc_lowercase(op);
+    double n = kcc_number(number), result = 0.0;
+    free(op);
+    if (strcmp(lower, "abs") == 0) {
+        result = fabs(n);
+    } else if (strcmp(lower, "floor") == 0) {
+        result = floor(n);
+    } else if (strcmp(lower, "ceiling") == 0) {
+        result = ceil(n);
+    } else if (strcmp(lower, "sqrt") == 0) {
+        result = sqrt(n);
+    } else if (strcmp(lower, "sin") == 0) {
+        result = kcc_round10(sin(kcc_radians(n)));
+    } else if (strcmp(lower, "cos") == 0) {
+        result = kcc_round10(cos(kcc_radians(n)));
+    } else if (strcmp(lower, "tan") == 0) {
+        double angle = fmod(n, 360.0);
+        if (angle == -270.0 || angle == 90.0) {
+            result = INFINITY;
+        } else if (angle == -90.0 || angle == 270.0) {
+            result = -INFINITY;
+        } else {
+            result = kcc_round10(tan(kcc_radians(angle)));
+        }
+    } else if (strcmp(lower, "asin") == 0) {
+        result = kcc_degrees(asin(n));
+    } else if (strcmp(lower, "acos") == 0) {
+        result = kcc_degrees(acos(n));
+    } else if (strcmp(lower, "atan") == 0) {
+        result = kcc_degrees(atan(n));
+    } else if (strcmp(lower, "ln") == 0) {
+        result = log(n);
+    } else if (strcmp(lower, "log") == 0) {
+        result = log10(n);
+    } else if (strcmp(lower, "e ^") == 0) {
+        result = exp(n);
+    } else if (strcmp(lower, "10 ^") == 0) {
+        result = pow(10.0, n);
+    }
+    free(lower);
+    return kcc_num(result);
+}
+
+/* ---- Time ---- */
+
+/* Seconds since an arbitrary point. Before C11 there is no clock finer than
+ * `time`, so this counts processor time, which waiting spins away. */
+static inline double kcc_clock(void) {
+#if defined(__STDC_VERSION__) && __STDC_VERSION__ >= 201112L && defined(TIME_UTC)
+    struct timespec now;
+    timespec_get(&now, TIME_UTC);
+    return (double)now.tv_sec + (double)now.tv_nsec / 1e9;
+#else
+    return (double)clock() / CLOCKS_PER_SEC;
+#endif
+}
+
+/* Seconds since the project started. */
+static inline double kcc_now(void) { return kcc_clock() - kcc_started; }
+
+/* C has no portable way to sleep, so waiting spins. */
+static inline void kcc_wait_until(double wake) {
+    while (kcc_now() < wake) {
+    }
+}
+
+/* When a wait of `secs` seconds ends. */
+static inline kcc_value kcc_deadline(kcc_value secs) {
+    double n = kcc_number(secs);
+    return kcc_num(kcc_now() + (n > 0.0 ? n : 0.0));
+}
+
+/* Like kcc_deadline, but drops what is shorter than a millisecond. */
+static inline kcc_value kcc_deadline_millis(kcc_value secs) {
+    double millis = trunc(kcc_number(secs) * 1000.0);
+    return kcc_num(kcc_now() + (millis > 0.0 ? millis : 0.0) / 1000.0);
+}
+
+static inline void kcc_reset_timer(void) { kcc_timer_start = kcc_clock(); }
+
+static inline kcc_value kcc_timer(void) { return kcc_num(kcc_clock() - kcc_timer_start); }
+
+static inline kcc_value kcc_days_since_2000(void) {
+    double millis = floor((difftime(time(NULL), (time_t)0) - KCC_START_OF_2000_TIMESTAMP) * 1000.0);
+    return kcc_num(millis / KCC_MILISECS_IN_A_DAY);
+}
+
+/* ---- Variables ---- */
+
+static inline kcc_value kcc_var(size_t slot) { return kcc_copy(kcc_variables[slot]); }
+
+static inline void kcc_set_var(size_t slot, kcc_value value) {
+    kcc_free(kcc_variables[slot]);
+    kcc_variables[slot] = kcc_primitive(value);
+}
+
+static inline void kcc_change_var(size_t slot, kcc_value delta) {
+    double n = kcc_to_number(&kcc_variables[slot]) + kcc_number(delta);
+    kcc_free(kcc_variables[slot]);
+    kcc_variables[slot] = kcc_num(n);
+}
+
+/* ---- Lists ---- */
+
+typedef enum { KCC_INDEX_INVALID, KCC_INDEX_ALL, KCC_INDEX_POSITION } kcc_index_kind;
+
+/* Scratch's `Cast.toListIndex`. Resolves a 1-based index, `last`, `random`/`any`
+ * and, if `accept_all` is set, `all`, storing a 0-based position in `position`. */
+static inline kcc_index_kind kcc_to_list_index(const kcc_value *index, size_t length,
+                                               int accept_all, size_t *position) {
+    double n;
+    if (index->kind == KCC_STRING) {
+        const char *s = index->string;
+        if (strcmp(s, "all") == 0) {
+            return accept_all ? KCC_INDEX_ALL : KCC_INDEX_INVALID;
+        }
+        if (strcmp(s, "last") == 0) {
+            if (length == 0) {
+                return KCC_INDEX_INVALID;
+            }
+            *position = length - 1;
+            return KCC_INDEX_POSITION;
+        }
+        if (strcmp(s, "random") == 0 || strcmp(s, "any") == 0) {
+            if (length == 0) {
+                return KCC_INDEX_INVALID;
+            }
+            *position = (size_t)(kcc_random_unit() * (double)length);
+            return KCC_INDEX_POSITION;
+        }
+    }
+    n = floor(kcc_to_number(index));
+    if (n < 1.0 || n > (double)length) {
+        return KCC_INDEX_INVALID;
+    }
+    *position = (size_t)n - 1;
+    return KCC_INDEX_POSITION;
+}
+
+static inline void kcc_list_reserve(kcc_list *list, size_t length) {
+    if (length > list->capacity) {
+        list->capacity = list->capacity ? list->capacity * 2 : 8;
+        if (list->capacity < length) {
+            list->capacity = length;
+        }
+        list->items = (kcc_value *)kcc_grow(list->items, sizeof(kcc_value) * list->capacity);
+    }
+}
+
+/* The contents of a list as shown by its reporter: items are joined by spaces,
+ * unless every item is a single character. */
+static inline kcc_value kcc_list_contents(size_t slot) {
+    kcc_list *list = &kcc_lists[slot];
+    char **items;
+    size_t i, total = 0, n;
+    int single = 1;
+    char *out;
+    n = list->length;
+    items = (char **)kcc_alloc(sizeof(char *) * n);
+    for (i = 0; i < n; i++) {
+        items[i] = kcc_to_string(&list->items[i]);
+        total += strlen(items[i]) + 1;
+        single = single && kcc_char_count(items[i]) == 1;
+    }
+    out = (char *)kcc_alloc(total + 1);
+    total = 0;
+    for (i = 0; i < n; i++) {
+        size_t length = strlen(items[i]);
+        if (i > 0 && !single) {
+            out[total++] = ' ';
+        }
+        memcpy(out + total, items[i], length);
+        total += length;
+        free(items[i]);
+    }
+    out[total] = '\0';
+    free(items);
+    return kcc_str_owned(out);
+}
+
+static inline void kcc_list_add(size_t slot, kcc_value item) {
+    kcc_list *list = &kcc_lists[slot];
+    item = kcc_primitive(item);
+    if (list->length < KCC_LIST_ITEM_LIMIT) {
+        kcc_list_reserve(list, list->length + 1);
+        list->items[list->length++] = item;
+        item.string = NULL;
+    }
+    kcc_free(item);
+}
+
+static inline void kcc_list_clear_items(kcc_list *list) {
+    size_t i;
+    for (i = 0; i < list->length; i++) {
+        kcc_free(list->items[i]);
+    }
+    list->length = 0;
+}
+
+static inline void kcc_list_delete(size_t slot, kcc_value index) {
+    kcc_list *list = &kcc_lists[slot];
+    size_t i;
+    switch (kcc_to_list_index(&index, list->length, 1, &i)) {
+    case KCC_INDEX_ALL:
+        kcc_list_clear_items(list);
+        break;
+    case KCC_INDEX_POSITION:
+        kcc_free(list->items[i]);
+        memmove(list->items + i, list->items + i + 1, sizeof(kcc_value) * (list->length - i - 1));
+        list->length--;
+        break;
+    default:
+        break;
+    }
+    kcc_free(index);
+}
+
+static inline void kcc_list_clear(size_t slot) { kcc_list_clear_items(&kcc_lists[slot]); }
+
+static inline void kcc_list_insert(size_t slot, kcc_value item, kcc_value index) {
+    kcc_list *list = &kcc_lists[slot];
+    size_t i;
+    item = kcc_primitive(item);
+    /* Inserting one past the end appends. */
+    if (kcc_to_list_index(&index, list->length + 1, 0, &i) == KCC_INDEX_POSITION &&
+        i < KCC_LIST_ITEM_LIMIT) {
+        kcc_list_reserve(list, list->length + 1);
+        memmove(list->items + i + 1, list->items + i, sizeof(kcc_value) * (list->length - i));
+        list->items[i] = item;
+        item.string = NULL;
+        list->length++;
+        if (list->length > KCC_LIST_ITEM_LIMIT) {
+            kcc_free(list->items[--list->length]);
+        }
+    }
+    kcc_free(item);
+    kcc_free(index);
+}
+
+static inline void kcc_list_replace(size_t slot, kcc_value item, kcc_value index) {
+    kcc_list *list = &kcc_lists[slot];
+    size_t i;
+    kcc_value old = kcc_num(0.0);
+    item = kcc_primitive(item);
+    if (kcc_to_list_index(&index, list->length, 0, &i) == KCC_INDEX_POSITION) {
+        old = list->items[i];
+        list->items[i] = item;
+        item.string = NULL;
+    }
+    kcc_free(old);
+    kcc_free(item);
+    kcc_free(index);
+}
+
+static inline kcc_value kcc_list_item(size_t slot, kcc_value index) {
+    kcc_list *list = &kcc_lists[slot];
+    kcc_value result;
+    size_t i;
+    if (kcc_to_list_index(&index, list->length, 0, &i) == KCC_INDEX_POSITION) {
+        result = kcc_copy(list->items[i]);
+    } else {
+        result = kcc_str("");
+    }
+    kcc_free(index);
+    return result;
+}
+
+/* The 1-based position of the first item equal to `item`, or 0. */
+static inline kcc_value kcc_list_index_of(size_t slot, kcc_value item) {
+    kcc_list *list = &kcc_lists[slot];
+    size_t i, position = 0;
+    for (i = 0; i < list->length; i++) {
+        if (kcc_compare(&list->items[i], &item) == 0) {
+            position = i + 1;
+            break;
+        }
+    }
+    kcc_free(item);
+    return kcc_num((double)position);
+}
+
+static inline kcc_value kcc_list_length(size_t slot) {
+    return kcc_num((double)kcc_lists[slot].length);
+}
+
+static inline kcc_value kcc_list_contains(size_t slot, kcc_value item) {
+    return kcc_bool(kcc_number(kcc_list_index_of(slot, item)) != 0.0);
+}
+
+/* ---- Looks and sensing ---- */
+
+static inline void kcc_say(kcc_value message) {
+    char *s = kcc_string(message);
+    puts(s);
+    fflush(stdout);
+    free(s);
+}
+
+/* Asks on the standard output and reads the answer from the standard input.
+ * Nothing else runs until it is answered. */
+static inline void kcc_ask(kcc_value question) {
+    char *q = kcc_string(question);
+    size_t length = 0, capacity = 64;
+    char *line = (char *)kcc_alloc(capacity);
+    int c;
+    if (q[0] != '\0') {
+        puts(q);
+    }
+    fflush(stdout);
+    while ((c = getchar()) != EOF && c != '\n') {
+        if (length + 1 == capacity) {
+            capacity *= 2;
+            line = (char *)kcc_grow(line, capacity);
+        }
+        line[length++] = (char)c;
+    }
+    while (length > 0 && line[length - 1] == '\r') {
+        length--;
+    }
+    line[length] = '\0';
+    free(kcc_answer_text);
+    kcc_answer_text = line;
+    free(q);
+}
+
+static inline kcc_value kcc_answer(void) { return kcc_str(kcc_answer_text); }
+
+/* ---- Scripts ---- */
+
+/* Sets up a frame that nothing has run yet. */
+static inline void kcc_frame_init(kcc_frame *frame, kcc_turn (*resume)(kcc_frame *),
+                                  void (*drop)(kcc_frame *)) {
+    frame->resume = resume;
+    frame->drop = drop;
+    frame->state = 0;
+    frame->wake = 0.0;
+    frame->callee = NULL;
+}
+
+static inline void kcc_push_frame(kcc_thread *thread, kcc_frame *frame) {
+    if (thread->depth == thread->capacity) {
+        thread->capacity = thread->capacity ? thread->capacity * 2 : 4;
+        thread->frames =
+            (kcc_frame **)kcc_grow(thread->frames, sizeof(kcc_frame *) * thread->capacity);
+    }
+    thread->frames[thread->depth++] = frame;
+}
+
+static inline void kcc_drop_thread(kcc_thread *thread) {
+    while (thread->depth > 0) {
+        kcc_frame *frame = thread->frames[--thread->depth];
+        frame->drop(frame);
+    }
+    free(thread->frames);
+    thread->frames = NULL;
+    thread->capacity = 0;
+}
+
+static inline kcc_thread kcc_thread_of(size_t script) {
+    kcc_thread thread;
+    thread.script = script;
+    thread.frames = NULL;
+    thread.depth = 0;
+    thread.capacity = 0;
+    thread.sleeping = 0;
+    thread.wake = 0.0;
+    if (script != KCC_NO_SCRIPT) {
+        kcc_push_frame(&thread, kcc_start_frame(script));
+    }
+    return thread;
+}
+
+/* Starts a script, restarting it if it is already running. */
+static inline void kcc_start(size_t script) {
+    size_t i;
+    if (kcc_current == script) {
+        kcc_restart = 1;
+        return;
+    }
+    for (i = 0; i < kcc_thread_count; i++) {
+        if (kcc_threads[i].script == script) {
+            kcc_drop_thread(&kcc_threads[i]);
+            kcc_threads[i] = kcc_thread_of(script);
+            return;
+        }
+    }
+    if (kcc_thread_count == kcc_thread_capacity) {
+        kcc_thread_capacity = kcc_thread_capacity ? kcc_thread_capacity * 2 : 16;
+        kcc_threads = (kcc_thread *)kcc_grow(kcc_threads, sizeof(kcc_thread) * kcc_thread_capacity);
+    }
+    kcc_threads[kcc_thread_count++] = kcc_thread_of(script);
+}
+
+/* Starts the scripts receiving the broadcast named `name`. */
+static inline void kcc_broadcast(kcc_value name) {
+    char *s = kcc_string(name), *lower = kcc_lowercase(s);
+    size_t i;
+    for (i = 0; i < kcc_receiver_count; i++) {
+        if (strcmp(kcc_receiver_table[i].name, lower) == 0) {
+            kcc_start(kcc_receiver_table[i].script);
+        }
+    }
+    free(lower);
+    free(s);
+}
+
+/* Whether a script receiving the broadcast named `name` is still running. */
+static inline int kcc_receiving(kcc_value name) {
+    char *s = kcc_string(name), *lower = kcc_lowercase(s);
+    size_t i, j;
+    int receiving = 0;
+    for (i = 0; i < kcc_receiver_count && !receiving; i++) {
+        if (strcmp(kcc_receiver_table[i].name, lower) == 0) {
+            for (j = 0; j < kcc_thread_count; j++) {
+                receiving = receiving || kcc_threads[j].script == kcc_receiver_table[i].script;
+            }
+        }
+    }
+    free(lower);
+    free(s);
+    return receiving;
+}
+
+/* Runs a thread until it yields, waits or finishes, which it reports as KCC_RETURN. */
+static inline kcc_turn kcc_take_turn(kcc_thread *thread) {
+    for (;;) {
+        kcc_frame *frame;
+        kcc_turn turn;
+        if (thread->depth == 0) {
+            return KCC_RETURN;
+        }
+        frame = thread->frames[thread->depth - 1];
+        turn = frame->resume(frame);
+        switch (turn) {
+        case KCC_CALL:
+            kcc_push_frame(thread, frame->callee);
+            frame->callee = NULL;
+            break;
+        case KCC_RETURN:
+            thread->depth--;
+            frame->drop(frame);
+            break;
+        case KCC_SLEEP:
+            thread->wake = frame->wake;
+            return turn;
+        default:
+            return turn;
+        }
+    }
+}
+
+/* Sets up `variables` variables and `lists` lists, holding 0 and nothing. */
+static inline void kcc_init(size_t variables, size_t lists) {
+    size_t i;
+    if (!setlocale(LC_CTYPE, "C.UTF-8")) {
+        setlocale(LC_CTYPE, "");
+    }
+    kcc_variables = (kcc_value *)kcc_alloc(sizeof(kcc_value) * variables);
+    for (i = 0; i < variables; i++) {
+        kcc_variables[i] = kcc_num(0.0);
+    }
+    kcc_lists = (kcc_list *)kcc_alloc(sizeof(kcc_list) * lists);
+    memset(kcc_lists, 0, sizeof(kcc_list) * lists);
+    kcc_started = kcc_clock();
+    kcc_timer_start = kcc_started;
+    kcc_answer_text = kcc_strdup("");
+    kcc_seed = ((uint64_t)time(NULL) * 1000000007ULL + (uint64_t)clock()) | 1;
+}
+
+/* Clicks the green flag, and runs until every script finishes. `start` makes
+ * the frame of a script, numbered like `green_flag` and `receivers` do. */
+static inline int kcc_run(const size_t *green_flag, size_t green_flag_count,
+                          const kcc_receiver *receivers, size_t receiver_count,
+                          kcc_frame *(*start)(size_t script)) {
+    size_t i;
+    kcc_receiver_table = receivers;
+    kcc_receiver_count = receiver_count;
+    kcc_start_frame = start;
+    for (i = 0; i < green_flag_count; i++) {
+        kcc_start(green_flag[i]);
+    }
+    while (kcc_thread_count > 0) {
+        int ready = 0;
+        double earliest = HUGE_VAL;
+        i = 0;
+        while (i < kcc_thread_count) {
+            kcc_thread thread;
+            kcc_turn turn;
+            if (kcc_threads[i].sleeping && kcc_now() < kcc_threads[i].wake) {
+                earliest = kcc_threads[i].wake < earliest ? kcc_threads[i].wake : earliest;
+                i++;
+                continue;
+            }
+            /* The thread is taken out while it runs, so that it cannot be found,
+             * e.g. by `broadcast and wait`. */
+            thread = kcc_threads[i];
+            kcc_threads[i] = kcc_thread_of(KCC_NO_SCRIPT);
+            thread.sleeping = 0;
+            kcc_current = thread.script;
+            turn = kcc_take_turn(&thread);
+            kcc_current = KCC_NO_SCRIPT;
+            if (kcc_restart) {
+                kcc_restart = 0;
+                kcc_drop_thread(&thread);
+                kcc_threads[i] = kcc_thread_of(thread.script);
+                ready = 1;
+                i++;
+                continue;
+            }
+            switch (turn) {
+            case KCC_YIELD:
+                ready = 1;
+                break;
+            case KCC_SLEEP:
+                thread.sleeping = 1;
+                earliest = thread.wake < earliest ? thread.wake : earliest;
+                break;
+            case KCC_RETURN:
+                kcc_drop_thread(&thread);
+                kcc_thread_count--;
+                memmove(kcc_threads + i, kcc_threads + i + 1,
+                        sizeof(kcc_thread) * (kcc_thread_count - i));
+                continue;
+            default:
+                return 0;
+            }
+            kcc_threads[i] = thread;
+            i++;
+        }
+        if (!ready && earliest < HUGE_VAL) {
+            kcc_wait_until(earliest);
+        }
+    }
+    return 0;
+}
+
+#endif
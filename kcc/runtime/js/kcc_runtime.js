@@ -0,0 +1,385 @@
+// Scratch semantics for projects compiled to JavaScript by kcc.
+//
+// Scripts are generators taking turns on one thread, like in the kcc bytecode machine:
+// they yield nothing at the end of a loop iteration, a deadline to wait until,
+// STOP for `stop all`, or a promise whose value they get back without giving up their turn.
+
+const STOP = Symbol("stop all");
+/** Lists cannot grow past this many items. */
+const LIST_ITEM_LIMIT = 200000;
+const START_OF_2000 = Date.UTC(2000, 0, 1);
+const MILLISECS_IN_A_DAY = 1000 * 60 * 60 * 24;
+
+// ---- Casting, matching `Cast` in scratch-vm ----
+
+function toNumber(value) {
+    const n = Number(value);
+    return Number.isNaN(n) ? 0 : n;
+}
+
+function toBoolean(value) {
+    if (typeof value === "string") {
+        return !(value === "" || value === "0" || value.toLowerCase() === "false");
+    }
+    return Boolean(value);
+}
+
+function toString(value) {
+    return String(value);
+}
+
+function isWhiteSpace(value) {
+    return typeof value === "string" && value.trim().length === 0;
+}
+
+/** Strings count as integers unless they contain a dot. */
+function isInt(value) {
+    if (typeof value === "number") {
+        return Number.isNaN(value) || Number.isInteger(value);
+    }
+    if (typeof value === "boolean") {
+        return true;
+    }
+    return value.indexOf(".") < 0;
+}
+
+/** Compares as numbers when both values look like numbers, and as case-insensitive strings otherwise. */
+function compare(v1, v2) {
+    let n1 = Number(v1);
+    let n2 = Number(v2);
+    if (n1 === 0 && isWhiteSpace(v1)) {
+        n1 = NaN;
+    } else if (n2 === 0 && isWhiteSpace(v2)) {
+        n2 = NaN;
+    }
+    if (Number.isNaN(n1) || Number.isNaN(n2)) {
+        const s1 = String(v1).toLowerCase();
+        const s2 = String(v2).toLowerCase();
+        return s1 < s2 ? -1 : s1 > s2 ? 1 : 0;
+    }
+    if (n1 === n2) {
+        return 0;
+    }
+    return n1 > n2 ? 1 : -1;
+}
+
+/** What variables, lists and arguments hold: booleans become text. */
+function primitive(value) {
+    return typeof value === "boolean" ? String(value) : value;
+}
+
+// ---- Operators ----
+
+const add = (a, b) => toNumber(a) + toNumber(b);
+const subtract = (a, b) => toNumber(a) - toNumber(b);
+const multiply = (a, b) => toNumber(a) * toNumber(b);
+const divide = (a, b) => toNumber(a) / toNumber(b);
+const gt = (a, b) => compare(a, b) > 0;
+const lt = (a, b) => compare(a, b) < 0;
+const equals = (a, b) => compare(a, b) === 0;
+const and = (a, b) => toBoolean(a) && toBoolean(b);
+const or = (a, b) => toBoolean(a) || toBoolean(b);
+const not = (a) => !toBoolean(a);
+const join = (a, b) => toString(a) + toString(b);
+const length = (s) => toString(s).length;
+const contains = (a, b) => toString(a).toLowerCase().includes(toString(b).toLowerCase());
+const round = (n) => Math.round(toNumber(n));
+
+function random(from, to) {
+    const n1 = toNumber(from);
+    const n2 = toNumber(to);
+    const low = Math.min(n1, n2);
+    const high = Math.max(n1, n2);
+    if (low === high) {
+        return low;
+    }
+    if (isInt(from) && isInt(to)) {
+        return low + Math.floor(Math.random() * (high - low + 1));
+    }
+    return low + Math.random() * (high - low);
+}
+
+function letterOf(letter, string) {
+    const index = toNumber(letter) - 1;
+    const s = toString(string);
+    return index < 0 || index >= s.length ? "" : s.charAt(index);
+}
+
+/** Scratch's mod takes the sign of the divisor. */
+function mod(a, b) {
+    const n = toNumber(a);
+    const modulus = toNumber(b);
+    let result = n % modulus;
+    if (result / modulus < 0) {
+        result += modulus;
+    }
+    return result;
+}
+
+/** Scratch rounds trigonometric results to 10 decimal places, so that e.g. sin(180) is exactly 0. */
+const round10 = (x) => Math.round(x * 1e10) / 1e10;
+const radians = (x) => (Math.PI * x) / 180;
+const degrees = (x) => (x * 180) / Math.PI;
+
+function mathop(operator, number) {
+    const n = toNumber(number);
+    switch (operator) {
+        case "abs": return Math.abs(n);
+        case "floor": return Math.floor(n);
+        case "ceiling": return Math.ceil(n);
+        case "sqrt": return Math.sqrt(n);
+        case "sin": return round10(Math.sin(radians(n)));
+        case "cos": return round10(Math.cos(radians(n)));
+        case "tan": {
+            const angle = n % 360;
+            if (angle === -270 || angle === 90) return Infinity;
+            if (angle === -90 || angle === 270) return -Infinity;
+            return round10(Math.tan(radians(angle)));
+        }
+        case "asin": return degrees(Math.asin(n));
+        case "acos": return degrees(Math.acos(n));
+        case "atan": return degrees(Math.atan(n));
+        case "ln": return Math.log(n);
+        case "log": return Math.log10(n);
+        case "e ^": return Math.exp(n);
+        case "10 ^": return Math.pow(10, n);
+        default: throw new Error(`unknown math operator ${operator}`);
+    }
+}
+
+// ---- Lists ----
+
+/** Scratch's `Cast.toListIndex`, as a 0-based position, "all" or -1 if there is no such item. */
+function toListIndex(index, length, acceptAll) {
+    if (typeof index === "string") {
+        switch (index) {
+            case "all": return acceptAll ? "all" : -1;
+            case "last": return length > 0 ? length - 1 : -1;
+            case "random":
+            case "any": return length > 0 ? Math.floor(Math.random() * length) : -1;
+        }
+    }
+    const n = Math.floor(toNumber(index));
+    return n < 1 || n > length ? -1 : n - 1;
+}
+
+/** Items are joined by spaces, unless every item is a single character. */
+function listContents(list) {
+    const items = list.map(toString);
+    const single = items.every((item) => [...item].length === 1);
+    return items.join(single ? "" : " ");
+}
+
+function addToList(list, item) {
+    if (list.length < LIST_ITEM_LIMIT) {
+        list.push(primitive(item));
+    }
+}
+
+function deleteOfList(list, index) {
+    const i = toListIndex(index, list.length, true);
+    if (i === "all") {
+        list.length = 0;
+    } else if (i >= 0) {
+        list.splice(i, 1);
+    }
+}
+
+/** Inserting one past the end appends. */
+function insertAtList(list, index, item) {
+    const i = toListIndex(index, list.length + 1, false);
+    if (i < 0 || i >= LIST_ITEM_LIMIT) {
+        return;
+    }
+    list.splice(i, 0, primitive(item));
+    if (list.length > LIST_ITEM_LIMIT) {
+        list.pop();
+    }
+}
+
+function replaceItemOfList(list, index, item) {
+    const i = toListIndex(index, list.length, false);
+    if (i >= 0) {
+        list[i] = primitive(item);
+    }
+}
+
+function itemOfList(list, index) {
+    const i = toListIndex(index, list.length, false);
+    return i >= 0 ? list[i] : "";
+}
+
+/** The 1-based position of the first item equal to `item`, or 0. */
+function itemNumOfList(list, item) {
+    return list.findIndex((e) => compare(e, item) === 0) + 1;
+}
+
+// ---- Scripts ----
+
+/** Reads a line from the standard input, where there is one. */
+async function readLine() {
+    if (!globalThis.process) {
+        return "";
+    }
+    const fs = await import("node:fs");
+    const bytes = [];
+    const byte = new Uint8Array(1);
+    try {
+        while (fs.readSync(0, byte, 0, 1, null) === 1 && byte[0] !== 10) {
+            bytes.push(byte[0]);
+        }
+    } catch {
+        // Nothing more to read.
+    }
+    return new TextDecoder().decode(new Uint8Array(bytes)).replace(/\r$/, "");
+}
+
+class Runtime {
+    constructor(program, host) {
+        this.program = program;
+        this.say = host.say ?? ((line) => console.log(line));
+        this.think = host.think ?? this.say;
+        this.readLine = host.ask ?? readLine;
+        /** Milliseconds since some point in the past. */
+        this.clock = host.now ?? (() => globalThis.performance?.now() ?? Date.now());
+        this.variables = program.variables.slice();
+        this.lists = program.lists.map((list) => list.slice());
+        this.threads = [];
+        /** The thread taking its turn, which restarts once its turn ends if `restart` is set. */
+        this.current = undefined;
+        this.restart = false;
+        this.start = this.clock();
+        this.timerStart = this.start;
+        this.answerText = "";
+    }
+
+    /** Seconds since the project started. */
+    now() {
+        return (this.clock() - this.start) / 1000;
+    }
+
+    thread(script) {
+        return { script, generator: this.program.scripts[script](this), wake: undefined };
+    }
+
+    /** Starts a script, restarting it if it is already running. */
+    startScript(script) {
+        if (this.current?.script === script) {
+            this.restart = true;
+            return;
+        }
+        const i = this.threads.findIndex((t) => t.script === script);
+        if (i >= 0) {
+            this.threads[i] = this.thread(script);
+        } else {
+            this.threads.push(this.thread(script));
+        }
+    }
+
+    broadcast(name) {
+        for (const script of this.program.receivers[toString(name).toLowerCase()] ?? []) {
+            this.startScript(script);
+        }
+    }
+
+    /** Whether another script receiving `name` is still running. */
+    receiving(name) {
+        const scripts = this.program.receivers[toString(name).toLowerCase()] ?? [];
+        return this.threads.some((t) => t !== this.current && scripts.includes(t.script));
+    }
+
+    deadline(secs) {
+        return this.now() + Math.max(toNumber(secs), 0);
+    }
+
+    deadlineMillis(secs) {
+        return this.now() + Math.max(Math.trunc(toNumber(secs) * 1000), 0) / 1000;
+    }
+
+    timer() {
+        return (this.clock() - this.timerStart) / 1000;
+    }
+
+    resetTimer() {
+        this.timerStart = this.clock();
+    }
+
+    daysSince2000() {
+        return (Date.now() - START_OF_2000) / MILLISECS_IN_A_DAY;
+    }
+
+    async ask(question) {
+        const q = toString(question);
+        if (q !== "") {
+            this.say(q);
+        }
+        this.answerText = (await this.readLine(q)).replace(/\r?\n$/, "");
+    }
+
+    /** Runs a thread until it yields, waits or finishes. */
+    async turn(thread) {
+        let sent;
+        for (;;) {
+            const { value, done } = thread.generator.next(sent);
+            sent = undefined;
+            if (done) {
+                return "done";
+            }
+            if (value === undefined || value === STOP || typeof value === "number") {
+                return value;
+            }
+            sent = await value;
+        }
+    }
+
+    /** Runs every green flag script until all scripts have finished. */
+    async run() {
+        this.program.greenFlag.forEach((script) => this.startScript(script));
+        while (this.threads.length > 0) {
+            let ready = false;
+            let earliest = Infinity;
+            let i = 0;
+            while (i < this.threads.length) {
+                const thread = this.threads[i];
+                if (thread.wake !== undefined && this.now() < thread.wake) {
+                    earliest = Math.min(earliest, thread.wake);
+                    i += 1;
+                    continue;
+                }
+                thread.wake = undefined;
+                this.current = thread;
+                let turn;
+                try {
+                    turn = await this.turn(thread);
+                } finally {
+                    this.current = undefined;
+                }
+                if (this.restart) {
+                    this.restart = false;
+                    this.threads[i] = this.thread(thread.script);
+                    ready = true;
+                    i += 1;
+                    continue;
+                }
+                if (turn === STOP) {
+                    return;
+                } else if (turn === "done") {
+                    this.threads.splice(i, 1);
+                    continue;
+                } else if (turn === undefined) {
+                    ready = true;
+                } else {
+                    thread.wake = turn;
+                    earliest = Math.min(earliest, turn);
+                }
+                i += 1;
+            }
+            if (!ready && Number.isFinite(earliest)) {
+                const delay = earliest - this.now();
+                if (delay > 0) {
+                    await new Promise((resolve) => setTimeout(resolve, delay * 1000));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! The values of compiled projects, a subset of `scratch_ast::model`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrimitiveValue {
+    Number(f64),
+    Integer(i64),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RichValue {
+    Boolean(bool),
+    Number(f64),
+    PositiveNumber(f64),
+    Integer(i64),
+    PositiveInteger(u32),
+    Angle(f64),
+    Color(String),
+    Broadcast(String),
+    String(String),
+}
+
+impl From<&PrimitiveValue> for RichValue {
+    fn from(value: &PrimitiveValue) -> RichValue {
+        match value {
+            PrimitiveValue::Number(n) => RichValue::Number(*n),
+            PrimitiveValue::Integer(n) => RichValue::Integer(*n),
+            PrimitiveValue::String(s) => {
+                if s.len() == 7 && s.starts_with('#') {
+                    return RichValue::Color(s.to_string());
+                }
+                RichValue::String(s.to_string())
+            }
+        }
+    }
+}
+
+impl From<RichValue> for PrimitiveValue {
+    fn from(value: RichValue) -> Self {
+        match value {
+            RichValue::Boolean(b) => PrimitiveValue::String(b.to_string()),
+            RichValue::Number(n) | RichValue::PositiveNumber(n) | RichValue::Angle(n) => {
+                PrimitiveValue::Number(n)
+            }
+            RichValue::Integer(n) => PrimitiveValue::Integer(n),
+            RichValue::PositiveInteger(n) => PrimitiveValue::Integer(n as i64),
+            RichValue::String(s) | RichValue::Color(s) | RichValue::Broadcast(s) => {
+                PrimitiveValue::String(s)
+            }
+        }
+    }
+}
@@ -0,0 +1,612 @@
+//! Scratch semantics for projects compiled to Rust by kcc.
+//!
+//! Like in the kcc bytecode machine, scripts take turns on a single OS thread.
+//! Every script is a [`Frame`], a state machine that runs until it yields at the
+//! end of a loop iteration, waits, or calls a custom block, whose frame runs
+//! on top of it until it returns.
+
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    io::{BufRead, Write},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{cast, model::RichValue};
+
+pub use crate::model::PrimitiveValue;
+pub type Value = RichValue;
+
+/// Lists cannot grow past this many items.
+pub const LIST_ITEM_LIMIT: usize = 200_000;
+const START_OF_2000_TIMESTAMP: u64 = 946684800;
+const MILISECS_IN_A_DAY: u64 = 1000 * 60 * 60 * 24;
+
+/// A script or custom block being run, with its values and where it goes on.
+pub trait Frame<S> {
+    /// Runs until the turn of the thread ends or another frame has to run.
+    fn resume(&mut self, rt: &mut Runtime<S>) -> Turn<S>;
+}
+
+/// What a frame stopped for.
+pub enum Turn<S> {
+    /// The end of a loop iteration, which ends the turn.
+    Yield,
+    /// Waits until the given time, in seconds since the start of the project.
+    Sleep(f64),
+    /// Runs a custom block, then goes on with the frame.
+    Call(Box<dyn Frame<S>>),
+    Return,
+    StopAll,
+}
+
+/// Everything the runtime needs to know about a compiled project, whose
+/// variables and lists are `S`.
+pub struct Project<S> {
+    pub slots: S,
+    pub green_flag: &'static [usize],
+    /// The scripts receiving a broadcast, by lowercase name.
+    pub receivers: fn(&str) -> &'static [usize],
+    /// The first frame of a script started by the green flag or a broadcast.
+    pub start: fn(usize) -> Box<dyn Frame<S>>,
+}
+
+struct Thread<S> {
+    /// The script the thread started with.
+    script: usize,
+    frames: Vec<Box<dyn Frame<S>>>,
+    /// When a waiting thread may run again, in seconds since the start of the project.
+    wake: Option<f64>,
+}
+
+pub struct Runtime<S> {
+    pub slots: S,
+    pub answer: String,
+    receivers: fn(&str) -> &'static [usize],
+    start: fn(usize) -> Box<dyn Frame<S>>,
+    threads: Vec<Thread<S>>,
+    /// The script of the thread taking its turn, which restarts once its turn ends
+    /// if `restart` is set.
+    current: Option<usize>,
+    restart: bool,
+    started: Instant,
+    timer: Instant,
+}
+
+/// Clicks the green flag, and runs until every script finishes.
+pub fn run<S>(project: Project<S>) {
+    let now = Instant::now();
+    let mut rt = Runtime {
+        slots: project.slots,
+        answer: String::new(),
+        receivers: project.receivers,
+        start: project.start,
+        threads: Vec::new(),
+        current: None,
+        restart: false,
+        started: now,
+        timer: now,
+    };
+    for script in project.green_flag {
+        rt.start(*script);
+    }
+    rt.run();
+}
+
+/// Ends the project the way the interpreter does when a script fails.
+pub fn fail(message: &str) -> ! {
+    eprintln!("error: {message}");
+    std::process::exit(1);
+}
+
+impl<S> Runtime<S> {
+    fn thread(&self, script: usize) -> Thread<S> {
+        Thread {
+            script,
+            frames: vec![(self.start)(script)],
+            wake: None,
+        }
+    }
+
+    /// Starts a script, restarting it if it is already running.
+    fn start(&mut self, script: usize) {
+        if self.current == Some(script) {
+            self.restart = true;
+        } else if let Some(i) = self.threads.iter().position(|t| t.script == script) {
+            self.threads[i] = self.thread(script);
+        } else {
+            let thread = self.thread(script);
+            self.threads.push(thread);
+        }
+    }
+
+    fn run(&mut self) {
+        while !self.threads.is_empty() {
+            let mut ready = false;
+            let mut earliest = f64::INFINITY;
+            let mut i = 0;
+            while i < self.threads.len() {
+                if let Some(wake) = self.threads[i].wake {
+                    if self.now() < wake {
+                        earliest = earliest.min(wake);
+                        i += 1;
+                        continue;
+                    }
+                }
+                // The thread is taken out while it runs, so that it cannot be found,
+                // e.g. by `broadcast and wait`.
+                let mut thread = std::mem::replace(
+                    &mut self.threads[i],
+                    Thread {
+                        script: usize::MAX,
+                        frames: Vec::new(),
+                        wake: None,
+                    },
+                );
+                thread.wake = None;
+                self.current = Some(thread.script);
+                let turn = self.turn(&mut thread);
+                self.current = None;
+                if std::mem::take(&mut self.restart) {
+                    self.threads[i] = self.thread(thread.script);
+                    ready = true;
+                    i += 1;
+                    continue;
+                }
+                match turn {
+                    Turn::Yield => ready = true,
+                    Turn::Sleep(wake) => {
+                        thread.wake = Some(wake);
+                        earliest = earliest.min(wake);
+                    }
+                    Turn::Return => {
+                        self.threads.remove(i);
+                        continue;
+                    }
+                    Turn::StopAll => return,
+                    Turn::Call(_) => unreachable!("calls are run within the turn"),
+                }
+                self.threads[i] = thread;
+                i += 1;
+            }
+            if !ready && earliest.is_finite() {
+                let delay = earliest - self.now();
+                if delay > 0.0 {
+                    thread::sleep(Duration::from_secs_f64(delay));
+                }
+            }
+        }
+    }
+
+    /// Runs a thread until it yields, waits or finishes, which it reports as
+    /// [`Turn::Return`].
+    fn turn(&mut self, thread: &mut Thread<S>) -> Turn<S> {
+        loop {
+            let Some(frame) = thread.frames.last_mut() else {
+                return Turn::Return;
+            };
+            match frame.resume(self) {
+                Turn::Call(callee) => thread.frames.push(callee),
+                Turn::Return => {
+                    thread.frames.pop();
+                }
+                turn => return turn,
+            }
+        }
+    }
+
+    /// Whether a script receiving `name` is still running.
+    pub fn receiving(&self, name: &(impl Cast + ?Sized)) -> bool {
+        let scripts = (self.receivers)(&name.text().to_lowercase());
+        self.threads.iter().any(|t| scripts.contains(&t.script))
+    }
+
+    /// Starts the scripts receiving the broadcast named `name`.
+    pub fn broadcast(&mut self, name: &(impl Cast + ?Sized)) {
+        for script in (self.receivers)(&name.text().to_lowercase()) {
+            self.start(*script);
+        }
+    }
+
+    /// Seconds since the project started.
+    pub fn now(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    /// When a wait of `secs` seconds ends.
+    pub fn deadline(&self, secs: f64) -> f64 {
+        self.now() + secs.max(0.0)
+    }
+
+    /// Like [`Runtime::deadline`], but drops what is shorter than a millisecond.
+    pub fn deadline_millis(&self, secs: f64) -> f64 {
+        self.now() + (secs * 1000.0).trunc().max(0.0) / 1000.0
+    }
+
+    pub fn timer(&self) -> f64 {
+        self.timer.elapsed().as_secs_f64()
+    }
+
+    pub fn reset_timer(&mut self) {
+        self.timer = Instant::now();
+    }
+
+    /// Asks on the standard output and reads the answer from the standard input.
+    /// Nothing else runs until it is answered.
+    pub fn ask(&mut self, question: &(impl Cast + ?Sized)) {
+        let question = question.text();
+        if !question.is_empty() {
+            println!("{question}");
+        }
+        std::io::stdout().flush().ok();
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer).ok();
+        self.answer = answer.trim_end_matches(['\r', '\n']).to_string();
+    }
+}
+
+/// What every value converts to, whatever the type it is kept as.
+pub trait Cast {
+    fn value(&self) -> Value;
+
+    fn number(&self) -> f64 {
+        cast::to_number(&self.value())
+    }
+
+    fn boolean(&self) -> bool {
+        cast::to_boolean(&self.value())
+    }
+
+    fn text(&self) -> String {
+        cast::to_string(&self.value())
+    }
+
+    /// What variables, lists and arguments hold: booleans become text.
+    fn primitive(&self) -> PrimitiveValue {
+        self.value().into()
+    }
+
+    fn is_int(&self) -> bool {
+        cast::is_int(&self.value())
+    }
+}
+
+impl Cast for f64 {
+    fn value(&self) -> Value {
+        Value::Number(*self)
+    }
+
+    fn number(&self) -> f64 {
+        if self.is_nan() {
+            return 0.0;
+        }
+        *self
+    }
+
+    fn boolean(&self) -> bool {
+        *self != 0.0 && !self.is_nan()
+    }
+
+    fn text(&self) -> String {
+        cast::number_to_string(*self)
+    }
+
+    fn primitive(&self) -> PrimitiveValue {
+        PrimitiveValue::Number(*self)
+    }
+}
+
+impl Cast for bool {
+    fn value(&self) -> Value {
+        Value::Boolean(*self)
+    }
+
+    fn number(&self) -> f64 {
+        *self as u8 as f64
+    }
+
+    fn boolean(&self) -> bool {
+        *self
+    }
+
+    fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Cast for str {
+    fn value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+
+    fn number(&self) -> f64 {
+        let n = cast::str_to_number(self);
+        if n.is_nan() {
+            return 0.0;
+        }
+        n
+    }
+
+    fn boolean(&self) -> bool {
+        !(self.is_empty() || self == "0" || self.eq_ignore_ascii_case("false"))
+    }
+
+    fn text(&self) -> String {
+        self.to_string()
+    }
+
+    fn primitive(&self) -> PrimitiveValue {
+        PrimitiveValue::String(self.to_string())
+    }
+
+    fn is_int(&self) -> bool {
+        !self.contains('.')
+    }
+}
+
+impl Cast for String {
+    fn value(&self) -> Value {
+        self.as_str().value()
+    }
+
+    fn number(&self) -> f64 {
+        self.as_str().number()
+    }
+
+    fn boolean(&self) -> bool {
+        self.as_str().boolean()
+    }
+
+    fn text(&self) -> String {
+        self.clone()
+    }
+
+    fn primitive(&self) -> PrimitiveValue {
+        self.as_str().primitive()
+    }
+
+    fn is_int(&self) -> bool {
+        self.as_str().is_int()
+    }
+}
+
+impl Cast for PrimitiveValue {
+    fn value(&self) -> Value {
+        self.into()
+    }
+
+    fn primitive(&self) -> PrimitiveValue {
+        self.clone()
+    }
+}
+
+impl Cast for Value {
+    fn value(&self) -> Value {
+        self.clone()
+    }
+
+    fn number(&self) -> f64 {
+        cast::to_number(self)
+    }
+
+    fn boolean(&self) -> bool {
+        cast::to_boolean(self)
+    }
+
+    fn text(&self) -> String {
+        cast::to_string(self)
+    }
+
+    fn is_int(&self) -> bool {
+        cast::is_int(self)
+    }
+}
+
+pub fn say(message: &(impl Cast + ?Sized)) {
+    println!("{}", message.text());
+}
+
+pub fn days_since_2000() -> f64 {
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH + Duration::from_secs(START_OF_2000_TIMESTAMP))
+        .unwrap_or_default();
+    since.as_millis() as f64 / MILISECS_IN_A_DAY as f64
+}
+
+thread_local! {
+    static SEED: Cell<u64> = Cell::new(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0x2545_f491_4f6c_dd1d, |d| d.as_nanos() as u64)
+            | 1,
+    );
+}
+
+/// A random number in `[0, 1)`.
+fn random_unit() -> f64 {
+    // xorshift64*
+    SEED.with(|seed| {
+        let mut s = seed.get();
+        s ^= s >> 12;
+        s ^= s << 25;
+        s ^= s >> 27;
+        seed.set(s);
+        (s.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+pub fn random(from: &(impl Cast + ?Sized), to: &(impl Cast + ?Sized)) -> f64 {
+    let n1 = from.number();
+    let n2 = to.number();
+    let (low, high) = if n1 <= n2 { (n1, n2) } else { (n2, n1) };
+    if low == high {
+        return low;
+    }
+    if from.is_int() && to.is_int() {
+        return low + (random_unit() * (high - low + 1.0)).floor();
+    }
+    low + random_unit() * (high - low)
+}
+
+/// Scratch's `Cast.compare`.
+pub fn compare(a: &(impl Cast + ?Sized), b: &(impl Cast + ?Sized)) -> Ordering {
+    cast::compare(&a.value(), &b.value())
+}
+
+/// [`compare`] for values known to be numbers.
+pub fn compare_numbers(a: f64, b: f64) -> Ordering {
+    match a.partial_cmp(&b) {
+        Some(ordering) => ordering,
+        // NaN is compared as text.
+        None => compare(&a, &b),
+    }
+}
+
+pub fn join(a: &(impl Cast + ?Sized), b: &(impl Cast + ?Sized)) -> String {
+    a.text() + &b.text()
+}
+
+pub fn letter_of(letter: f64, s: &(impl Cast + ?Sized)) -> String {
+    let index = letter - 1.0;
+    let s = s.text();
+    if index < 0.0 || index >= cast::js_length(&s) as f64 {
+        return String::new();
+    }
+    cast::js_char_at(&s, index as usize)
+}
+
+pub fn length(s: &(impl Cast + ?Sized)) -> f64 {
+    cast::js_length(&s.text()) as f64
+}
+
+pub fn contains(a: &(impl Cast + ?Sized), b: &(impl Cast + ?Sized)) -> bool {
+    a.text().to_lowercase().contains(&b.text().to_lowercase())
+}
+
+/// Scratch's mod takes the sign of the divisor.
+pub fn modulo(n: f64, modulus: f64) -> f64 {
+    let mut result = n % modulus;
+    if result / modulus < 0.0 {
+        result += modulus;
+    }
+    result
+}
+
+pub fn mathop(operator: &str, n: f64) -> f64 {
+    // Scratch rounds trigonometric results to 10 decimal places,
+    // so that e.g. sin(180) is exactly 0.
+    let round10 = |x: f64| (x * 1e10).round() / 1e10;
+    let radians = |x: f64| std::f64::consts::PI * x / 180.0;
+    match operator {
+        "abs" => n.abs(),
+        "floor" => n.floor(),
+        "ceiling" => n.ceil(),
+        "sqrt" => n.sqrt(),
+        "sin" => round10(radians(n).sin()),
+        "cos" => round10(radians(n).cos()),
+        "tan" => match n % 360.0 {
+            -270.0 | 90.0 => f64::INFINITY,
+            -90.0 | 270.0 => f64::NEG_INFINITY,
+            _ => round10(radians(n % 360.0).tan()),
+        },
+        "asin" => n.asin().to_degrees(),
+        "acos" => n.acos().to_degrees(),
+        "atan" => n.atan().to_degrees(),
+        "ln" => n.ln(),
+        "log" => n.log10(),
+        "e ^" => n.exp(),
+        "10 ^" => 10f64.powf(n),
+        _ => fail("unknown math operator"),
+    }
+}
+
+/// A list index as typed by the user, resolved against a list.
+enum ListIndex {
+    Invalid,
+    All,
+    Position(usize),
+}
+
+/// Scratch's `Cast.toListIndex`.
+fn to_list_index(index: &(impl Cast + ?Sized), length: usize, accept_all: bool) -> ListIndex {
+    if let Value::String(s) = index.value() {
+        match s.as_str() {
+            "all" if accept_all => return ListIndex::All,
+            "all" => return ListIndex::Invalid,
+            "last" if length > 0 => return ListIndex::Position(length - 1),
+            "random" | "any" if length > 0 => {
+                return ListIndex::Position((random_unit() * length as f64) as usize)
+            }
+            "last" | "random" | "any" => return ListIndex::Invalid,
+            _ => (),
+        }
+    }
+    let index = index.number().floor();
+    if index < 1.0 || index > length as f64 {
+        return ListIndex::Invalid;
+    }
+    ListIndex::Position(index as usize - 1)
+}
+
+/// The contents of a list as shown by its reporter: items are joined by spaces,
+/// unless every item is a single character.
+pub fn list_contents<T: Cast>(list: &[T]) -> String {
+    let items = list.iter().map(Cast::text).collect::<Vec<_>>();
+    if items.iter().all(|i| i.chars().count() == 1) {
+        return items.join("");
+    }
+    items.join(" ")
+}
+
+pub fn list_add<T>(list: &mut Vec<T>, item: T) {
+    if list.len() < LIST_ITEM_LIMIT {
+        list.push(item);
+    }
+}
+
+pub fn list_delete<T>(list: &mut Vec<T>, index: &(impl Cast + ?Sized)) {
+    match to_list_index(index, list.len(), true) {
+        ListIndex::Invalid => (),
+        ListIndex::All => list.clear(),
+        ListIndex::Position(i) => {
+            list.remove(i);
+        }
+    }
+}
+
+/// Inserting one past the end appends.
+pub fn list_insert<T>(list: &mut Vec<T>, index: &(impl Cast + ?Sized), item: T) {
+    let ListIndex::Position(i) = to_list_index(index, list.len() + 1, false) else {
+        return;
+    };
+    if i >= LIST_ITEM_LIMIT {
+        return;
+    }
+    list.insert(i, item);
+    if list.len() > LIST_ITEM_LIMIT {
+        list.pop();
+    }
+}
+
+pub fn list_replace<T>(list: &mut [T], index: &(impl Cast + ?Sized), item: T) {
+    if let ListIndex::Position(i) = to_list_index(index, list.len(), false) {
+        list[i] = item;
+    }
+}
+
+/// An item of a list. Items out of range are empty strings.
+pub fn list_item<T: Cast>(list: &[T], index: &(impl Cast + ?Sized)) -> Value {
+    match to_list_index(index, list.len(), false) {
+        ListIndex::Position(i) => list[i].value(),
+        _ => Value::String(String::new()),
+    }
+}
+
+/// The 1-based position of the first item equal to `item`, or 0.
+pub fn list_index_of<T: Cast>(list: &[T], item: &(impl Cast + ?Sized)) -> f64 {
+    let item = item.value();
+    list.iter()
+        .position(|e| cast::compare(&e.value(), &item).is_eq())
+        .map_or(0.0, |i| (i + 1) as f64)
+}
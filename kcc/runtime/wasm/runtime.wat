@@ -0,0 +1,2923 @@
+;; Runtime of projects compiled to WebAssembly by kcc, giving compiled code the
+;; semantics of the interpreter. These are module fields: the compiler wraps them in
+;; a module together with the compiled scripts, which provide the memory,
+;; $kcc_heap_start, $kcc_variable_count, $kcc_list_count, $kcc_script_count,
+;; the $kcc_frame_sizes, $kcc_green_flag and $kcc_receivers tables with their counts,
+;; the $kcc_scripts function table and $kcc_setup, which sets variables and lists up.
+;;
+;; Values are 64 bits wide. Numbers are doubles, with a single NaN, and the
+;; other values hide in the NaN space behind a 16-bit tag:
+;;   0xFFF9 booleans, 0xFFFA strings, 0xFFFB arrays,
+;; with the address of the object in linear memory in the low 32 bits. Objects are
+;; 8-byte aligned and start with two 32-bit words: their kind and a count.
+;; Strings hold `count` UTF-16 code units, arrays hold `count` values.
+;;
+;; Scripts are state machines run by a scheduler, so that they take turns like
+;; in Scratch. The memory below 16 KiB is scratch space and text of the runtime;
+;; text of the project comes next, then the heap, which is garbage collected
+;; between turns.
+
+(import "kcc" "say" (func $host_say (param i32 i32)))
+(import "kcc" "think" (func $host_think (param i32 i32)))
+(import "kcc" "ask" (func $host_ask (param i32 i32 i32 i32) (result i32)))
+(import "kcc" "now" (func $host_now (result f64)))
+
+(type $kcc_script (func (param i32) (result i32)))
+
+;; ---- Text of the runtime ----
+
+(data (i32.const 8192) "\00\00\00\00\00\00\00\00")
+(data (i32.const 8200) "\00\00\00\00\04\00\00\00t\00r\00u\00e\00")
+(data (i32.const 8216) "\00\00\00\00\05\00\00\00f\00a\00l\00s\00e\00")
+(data (i32.const 8240) "\00\00\00\00\03\00\00\00N\00a\00N\00")
+(data (i32.const 8256) "\00\00\00\00\08\00\00\00I\00n\00f\00i\00n\00i\00t\00y\00")
+(data (i32.const 8280) "\00\00\00\00\09\00\00\00-\00I\00n\00f\00i\00n\00i\00t\00y\00")
+(data (i32.const 8312) "\00\00\00\00\01\00\00\000\00")
+(global $kcc_empty i32 (i32.const 8192))
+(global $kcc_true_text i32 (i32.const 8200))
+(global $kcc_false_text i32 (i32.const 8216))
+(global $kcc_nan_text i32 (i32.const 8240))
+(global $kcc_infinity_text i32 (i32.const 8256))
+(global $kcc_negative_infinity_text i32 (i32.const 8280))
+(global $kcc_zero_text i32 (i32.const 8312))
+
+;; ASCII keywords, compared against strings.
+(data (i32.const 8400) "alllastrandomanyfalseInfinity")
+(global $kcc_all i32 (i32.const 8400))
+(global $kcc_last i32 (i32.const 8403))
+(global $kcc_random_keyword i32 (i32.const 8407))
+(global $kcc_any i32 (i32.const 8413))
+(global $kcc_false_keyword i32 (i32.const 8416))
+(global $kcc_infinity_keyword i32 (i32.const 8421))
+
+;; Scratch space: big numbers of 48 32-bit limbs, and digit buffers.
+(global $bn_r i32 (i32.const 1024))
+(global $bn_s i32 (i32.const 1216))
+(global $bn_plus i32 (i32.const 1408))
+(global $bn_minus i32 (i32.const 1600))
+(global $bn_t i32 (i32.const 1792))
+(global $bn_x i32 (i32.const 1984))
+(global $bn_y i32 (i32.const 2176))
+(global $bn_d i32 (i32.const 2368))
+(global $kcc_digits i32 (i32.const 2600))
+(global $kcc_decimal_digits i32 (i32.const 2640))
+(global $kcc_text_buffer i32 (i32.const 4096))
+
+;; 2^(k/128) ~= H[k] * (1 + T[k]), as pairs of T[k] and H[k] - (k << 45).
+(data (i32.const 10240)
+  "\00\00\00\00\00\00\00\00\00\00\00\00\00\00\f0\3f\6e\bf\88\1a\4f\3b\9b\3c\35\33\fb\a9\3d\f6\ef\3f"
+  "\5d\dc\d8\9c\13\60\71\bc\61\80\77\3e\9a\ec\ef\3f\d1\66\87\10\7a\5e\90\bc\85\7f\6e\e8\15\e3\ef\3f"
+  "\13\f6\67\35\52\d2\8c\3c\74\85\15\d3\b0\d9\ef\3f\fa\8e\f9\23\80\ce\8b\bc\de\f6\dd\29\6b\d0\ef\3f"
+  "\61\c8\e6\61\4e\f7\60\3c\c8\9b\75\18\45\c7\ef\3f\99\d3\33\5b\e4\a3\90\3c\83\f3\c6\ca\3e\be\ef\3f"
+  "\6d\7b\83\5d\a6\9a\97\3c\0f\89\f9\6c\58\b5\ef\3f\fc\ef\fd\92\1a\b5\8e\3c\f7\47\72\2b\92\ac\ef\3f"
+  "\d1\9c\2f\70\3d\be\3e\3c\a2\d1\d3\32\ec\a3\ef\3f\0b\6e\90\89\34\03\6a\bc\1b\d3\fe\af\66\9b\ef\3f"
+  "\0e\bd\2f\2a\52\56\95\bc\51\5b\12\d0\01\93\ef\3f\55\ea\4e\8c\ef\80\50\bc\cc\31\6c\c0\bd\8a\ef\3f"
+  "\16\f4\d5\b9\23\c9\91\bc\e0\2d\a9\ae\9a\82\ef\3f\af\55\5c\e9\e3\d3\80\3c\51\8e\a5\c8\98\7a\ef\3f"
+  "\48\93\a5\ea\15\1b\80\bc\7b\51\7d\3c\b8\72\ef\3f\3d\32\de\55\f0\1f\8f\bc\ea\8d\8c\38\f9\6a\ef\3f"
+  "\bf\53\13\3f\8c\89\8b\3c\75\cb\6f\eb\5b\63\ef\3f\26\eb\11\76\9c\d9\96\bc\d4\5c\04\84\e0\5b\ef\3f"
+  "\60\2f\3a\3e\f7\ec\9a\3c\aa\b9\68\31\87\54\ef\3f\9d\38\86\cb\82\e7\8f\bc\1d\d9\fc\22\50\4d\ef\3f"
+  "\8d\c3\a6\44\41\6f\8a\3c\d6\8c\62\88\3b\46\ef\3f\7d\04\e4\b0\05\7a\80\3c\96\dc\7d\91\49\3f\ef\3f"
+  "\94\a8\a8\e3\fd\8e\96\3c\38\62\75\6e\7a\38\ef\3f\7d\48\74\f2\18\5e\87\3c\3f\a6\b2\4f\ce\31\ef\3f"
+  "\f2\e7\1f\98\2b\47\80\3c\dd\7c\e2\65\45\2b\ef\3f\5e\08\71\3f\7b\b8\96\bc\81\63\f5\e1\df\24\ef\3f"
+  "\31\ab\09\6d\e1\f7\82\3c\e1\de\1f\f5\9d\1e\ef\3f\fa\bf\6f\1a\9b\21\3d\bc\90\d9\da\d0\7f\18\ef\3f"
+  "\b4\0a\0c\72\82\37\8b\3c\0b\03\e4\a6\85\12\ef\3f\8f\cb\ce\89\92\14\6e\3c\56\2f\3e\a9\af\0c\ef\3f"
+  "\b6\ab\b0\4d\75\4d\83\3c\15\b7\31\0a\fe\06\ef\3f\4c\74\ac\e2\01\42\86\3c\31\d8\4c\fc\70\01\ef\3f"
+  "\4a\f8\d3\5d\39\dd\8f\3c\ff\16\64\b2\08\fc\ee\3f\04\5b\8e\3b\80\a3\86\bc\f1\9f\92\5f\c5\f6\ee\3f"
+  "\68\50\4b\cc\ed\4a\92\bc\cb\a9\3a\37\a7\f1\ee\3f\8e\2d\51\1b\f8\07\99\bc\66\d8\05\6d\ae\ec\ee\3f"
+  "\d2\36\94\3e\e8\d1\71\bc\f7\9f\e5\34\db\e7\ee\3f\15\1b\ce\b3\19\19\99\bc\e5\a8\13\c3\2d\e3\ee\3f"
+  "\6d\4c\2a\a7\48\9f\85\3c\22\34\12\4c\a6\de\ee\3f\8a\69\28\7a\60\12\93\bc\1c\80\ac\04\45\da\ee\3f"
+  "\5b\89\17\48\8f\a7\58\bc\2a\2e\f7\21\0a\d6\ee\3f\1b\9a\49\67\9b\2c\7c\bc\97\a8\50\d9\f5\d1\ee\3f"
+  "\11\ac\c2\60\ed\63\43\3c\2d\89\61\60\08\ce\ee\3f\ef\64\06\3b\09\66\96\3c\57\00\1d\ed\41\ca\ee\3f"
+  "\79\03\a1\da\e1\cc\6e\3c\d0\3c\c1\b5\a2\c6\ee\3f\30\12\0f\3f\8e\ff\93\3c\de\d3\d7\f0\2a\c3\ee\3f"
+  "\b0\af\7a\bb\ce\90\76\3c\27\2a\36\d5\da\bf\ee\3f\77\e0\54\eb\bd\1d\93\3c\0d\dd\fd\99\b2\bc\ee\3f"
+  "\8e\a3\71\00\34\94\8f\bc\a7\2c\9d\76\b2\b9\ee\3f\49\a3\93\dc\cc\de\87\bc\42\66\cf\a2\da\b6\ee\3f"
+  "\5f\38\0f\bd\c6\de\78\bc\82\4f\9d\56\2b\b4\ee\3f\f6\5c\7b\ec\46\12\86\bc\0f\92\5d\ca\a4\b1\ee\3f"
+  "\8e\d7\fd\18\05\35\93\3c\da\27\b5\36\47\af\ee\3f\05\9b\8a\2f\b7\98\7b\3c\fd\c7\97\d4\12\ad\ee\3f"
+  "\09\54\1c\e2\e1\63\90\3c\29\54\48\dd\07\ab\ee\3f\ea\c6\19\50\85\c7\34\3c\b7\46\59\8a\26\a9\ee\3f"
+  "\35\c0\64\2b\e6\32\94\3c\48\21\ad\15\6f\a7\ee\3f\9f\76\99\61\4a\e4\8c\bc\09\dc\76\b9\e1\a5\ee\3f"
+  "\a8\4d\ef\3b\c5\33\8c\bc\85\55\3a\b0\7e\a4\ee\3f\ae\e9\2b\89\78\53\84\bc\20\c3\cc\34\46\a3\ee\3f"
+  "\58\58\56\78\dd\ce\93\bc\25\22\55\82\38\a2\ee\3f\64\19\7e\80\aa\10\57\3c\73\a9\4c\d4\55\a1\ee\3f"
+  "\28\22\5e\bf\ef\b3\93\bc\cd\3b\7f\66\9e\a0\ee\3f\82\b9\34\87\ad\12\6a\bc\bf\da\0b\75\12\a0\ee\3f"
+  "\ee\a9\6d\b8\ef\67\63\bc\2f\1a\65\3c\b2\9f\ee\3f\51\88\e0\54\3d\dc\80\bc\84\94\51\f9\7d\9f\ee\3f"
+  "\cf\3e\5a\7e\64\1f\78\bc\74\5f\ec\e8\75\9f\ee\3f\b0\7d\8b\c0\4a\ee\86\bc\74\81\a5\48\9a\9f\ee\3f"
+  "\8a\e6\55\1e\32\19\86\bc\c9\67\42\56\eb\9f\ee\3f\d3\d4\09\5e\cb\9c\90\3c\3f\5d\de\4f\69\a0\ee\3f"
+  "\1d\a5\4d\b9\dc\32\7b\bc\87\01\eb\73\14\a1\ee\3f\6b\c0\67\54\fd\ec\94\3c\32\c1\30\01\ed\a1\ee\3f"
+  "\55\6c\d6\ab\e1\eb\65\3c\62\4e\cf\36\f3\a2\ee\3f\42\cf\b3\2f\c5\a1\88\bc\12\1a\3e\54\27\a4\ee\3f"
+  "\34\37\3b\f1\b6\69\93\bc\13\ce\4c\99\89\a5\ee\3f\1e\ff\19\3a\84\5e\80\bc\ad\c7\23\46\1a\a7\ee\3f"
+  "\6e\57\72\d8\50\d4\94\bc\ed\92\44\9b\d9\a8\ee\3f\00\8a\0e\5b\67\ad\90\3c\99\66\8a\d9\c7\aa\ee\3f"
+  "\b4\ea\f0\c1\2f\b7\8d\3c\db\a0\2a\42\e5\ac\ee\3f\ff\e7\c5\9c\60\b6\65\bc\8c\44\b5\16\32\af\ee\3f"
+  "\44\5f\f3\59\83\f6\7b\3c\36\77\15\99\ae\b1\ee\3f\83\3d\1e\a7\1f\09\93\bc\c6\ff\91\0b\5b\b4\ee\3f"
+  "\29\1e\6c\8b\b8\a9\5d\bc\e5\c5\cd\b0\37\b7\ee\3f\59\b9\90\7c\f9\23\6c\bc\0f\52\c8\cb\44\ba\ee\3f"
+  "\aa\f9\f4\22\43\43\92\bc\50\4e\de\9f\82\bd\ee\3f\4b\8e\66\d7\6c\ca\85\bc\ba\07\ca\70\f1\c0\ee\3f"
+  "\27\ce\91\2b\fc\af\71\3c\90\f0\a3\82\91\c4\ee\3f\bb\73\0a\e1\35\d2\6d\3c\23\23\e3\19\63\c8\ee\3f"
+  "\63\22\62\22\04\c5\87\bc\65\e5\5d\7b\66\cc\ee\3f\d5\31\e2\e3\86\1c\8b\3c\33\2d\4a\ec\9b\d0\ee\3f"
+  "\15\bb\bc\d3\d1\bb\91\bc\5d\25\3e\b2\03\d5\ee\3f\d2\31\ee\9c\31\cc\90\3c\58\b3\30\13\9e\d9\ee\3f"
+  "\b3\5a\73\6e\84\69\84\3c\bf\fd\79\55\6b\de\ee\3f\b4\9d\8e\97\cd\df\82\bc\7a\f3\d3\bf\6b\e3\ee\3f"
+  "\87\33\cb\92\77\1a\8c\3c\ad\d3\5a\99\9f\e8\ee\3f\fa\d9\d1\4a\8f\7b\90\bc\66\b6\8d\29\07\ee\ee\3f"
+  "\ba\ae\dc\56\d9\c3\55\bc\fb\15\4f\b8\a2\f3\ee\3f\40\f6\a6\3d\0e\a4\90\bc\3a\59\e5\8d\72\f9\ee\3f"
+  "\34\93\ad\38\f4\d6\68\bc\47\5e\fb\f2\76\ff\ee\3f\35\8a\58\6b\e2\ee\91\bc\4a\06\a1\30\b0\05\ef\3f"
+  "\cd\dd\5f\0a\d7\ff\74\3c\d2\c1\4b\90\1e\0c\ef\3f\ac\98\92\fa\fb\bd\91\bc\09\1e\d7\5b\c2\12\ef\3f"
+  "\b3\0c\af\30\ae\6e\73\3c\9c\52\85\dd\9b\19\ef\3f\94\fd\9f\5c\32\e3\8e\3c\7a\d0\ff\5f\ab\20\ef\3f"
+  "\ac\59\09\d1\8f\e0\84\3c\4b\d1\57\2e\f1\27\ef\3f\67\1a\4e\38\af\cd\63\3c\b5\e7\06\94\6d\2f\ef\3f"
+  "\68\19\92\6c\2c\6b\67\3c\69\90\ef\dc\20\37\ef\3f\d2\b5\cc\83\18\8a\80\bc\fa\c3\5d\55\0b\3f\ef\3f"
+  "\6f\fa\ff\3f\5d\ad\8f\bc\7c\89\07\4a\2d\47\ef\3f\49\a9\75\38\ae\0d\90\bc\f2\89\0d\08\87\4f\ef\3f"
+  "\a7\07\3d\a6\85\a3\74\3c\87\a4\fb\dc\18\58\ef\3f\0f\22\40\20\9e\91\82\bc\98\83\c9\16\e3\60\ef\3f"
+  "\ac\92\c1\d5\50\5a\8e\3c\85\32\db\03\e6\69\ef\3f\4b\6b\01\ac\59\3a\84\3c\60\b4\01\f3\21\73\ef\3f"
+  "\1f\3e\b4\07\21\d5\82\bc\5f\9b\7b\33\97\7c\ef\3f\c9\0d\47\3b\b9\2a\89\bc\29\a1\f5\14\46\86\ef\3f"
+  "\d3\88\3a\60\04\b6\74\3c\f6\3f\8b\e7\2e\90\ef\3f\71\72\9d\51\ec\c5\83\3c\83\4c\c7\fb\51\9a\ef\3f"
+  "\f0\91\d3\8f\12\f7\8f\bc\da\90\a4\a2\af\a4\ef\3f\7d\74\23\e2\98\ae\8d\bc\f1\67\8e\2d\48\af\ef\3f"
+  "\08\20\aa\41\bc\c3\8e\3c\27\5a\61\ee\1b\ba\ef\3f\32\eb\a9\c3\94\2b\84\3c\97\ba\6b\37\2b\c5\ef\3f"
+  "\ee\85\d1\31\a9\64\8a\3c\40\45\6e\5b\76\d0\ef\3f\ed\e3\3b\e4\ba\37\8e\bc\14\be\9c\ad\fd\db\ef\3f"
+  "\9d\cd\91\4d\3b\89\77\3c\d8\90\9e\81\c1\e7\ef\3f\89\cc\60\41\c1\05\53\3c\f1\71\8f\2b\c2\f3\ef\3f")
+(global $kcc_exp_table i32 (i32.const 10240))
+
+;; ---- State ----
+
+(global $kcc_top (mut i32) (i32.const 0))
+(global $kcc_collect_at (mut i32) (i32.const 0))
+(global $kcc_copy_top (mut i32) (i32.const 0))
+;; Arrays of the values of variables; of the length and items of lists; of threads.
+(global $kcc_variables (mut i32) (i32.const 0))
+(global $kcc_lists (mut i32) (i32.const 0))
+(global $kcc_threads (mut i32) (i32.const 0))
+(global $kcc_answer (mut i64) (i64.const 0))
+(global $kcc_timer_start (mut f64) (f64.const 0))
+(global $kcc_seed (mut i64) (i64.const 1))
+(global $kcc_stopped (mut i32) (i32.const 0))
+;; The running thread, the array of its frames, when it wants to run again,
+;; and whether a broadcast restarted it.
+(global $kcc_thread (mut i32) (i32.const -1))
+(global $kcc_stack (mut i32) (i32.const 0))
+(global $kcc_wake (mut f64) (f64.const 0))
+(global $kcc_restart (mut i32) (i32.const 0))
+;; Results of $kcc_shortest and $kcc_utf8 beside their return values.
+(global $kcc_exponent (mut i32) (i32.const 0))
+(global $kcc_utf8_length (mut i32) (i32.const 0))
+
+;; ---- Memory ----
+
+;; Grows memory to hold `end` bytes, doubling it when it can.
+(func $kcc_reserve (param $end i32)
+  (local $pages i32)
+  (local.set $pages
+    (i32.sub
+      (i32.wrap_i64
+        (i64.shr_u
+          (i64.add (i64.extend_i32_u (local.get $end)) (i64.const 65535))
+          (i64.const 16)))
+      (memory.size)))
+  (if (i32.gt_s (local.get $pages) (i32.const 0))
+    (then
+      (if (i32.lt_s
+            (memory.grow
+              (select (local.get $pages) (memory.size)
+                (i32.gt_u (local.get $pages) (memory.size))))
+            (i32.const 0))
+        (then
+          (if (i32.lt_s (memory.grow (local.get $pages)) (i32.const 0))
+            (then unreachable)))))))
+
+(func $kcc_alloc (param $size i32) (result i32)
+  (local $p i32)
+  (local $end i32)
+  (local.set $p (global.get $kcc_top))
+  (local.set $end
+    (i32.add (local.get $p)
+      (i32.and (i32.add (local.get $size) (i32.const 7)) (i32.const -8))))
+  (call $kcc_reserve (local.get $end))
+  (global.set $kcc_top (local.get $end))
+  (local.get $p))
+
+;; The size of an object in bytes, padding included.
+(func $kcc_size (param $p i32) (result i32)
+  (if (result i32) (i32.eqz (i32.load (local.get $p)))
+    (then
+      (i32.and
+        (i32.add (i32.shl (i32.load offset=4 (local.get $p)) (i32.const 1)) (i32.const 15))
+        (i32.const -8)))
+    (else
+      (i32.add (i32.shl (i32.load offset=4 (local.get $p)) (i32.const 3)) (i32.const 8)))))
+
+;; A string of `length` code units, to be filled in.
+(func $kcc_new_string (param $length i32) (result i32)
+  (local $p i32)
+  (local.set $p
+    (call $kcc_alloc (i32.add (i32.shl (local.get $length) (i32.const 1)) (i32.const 8))))
+  (i32.store (local.get $p) (i32.const 0))
+  (i32.store offset=4 (local.get $p) (local.get $length))
+  (local.get $p))
+
+;; An array of `count` zeros.
+(func $kcc_new_array (param $count i32) (result i32)
+  (local $p i32)
+  (local.set $p
+    (call $kcc_alloc (i32.add (i32.shl (local.get $count) (i32.const 3)) (i32.const 8))))
+  (i32.store (local.get $p) (i32.const 1))
+  (i32.store offset=4 (local.get $p) (local.get $count))
+  (memory.fill (i32.add (local.get $p) (i32.const 8)) (i32.const 0)
+    (i32.shl (local.get $count) (i32.const 3)))
+  (local.get $p))
+
+;; The address of value `i` of an array.
+(func $kcc_slot (param $array i32) (param $i i32) (result i32)
+  (i32.add (local.get $array) (i32.add (i32.shl (local.get $i) (i32.const 3)) (i32.const 8))))
+
+(func $kcc_array_ref (param $array i32) (result i64)
+  (i64.or (i64.const 0xFFFB000000000000) (i64.extend_i32_u (local.get $array))))
+
+;; ---- Garbage collection ----
+
+;; Whether a value points to an object on the heap.
+(func $kcc_is_heap (param $v i64) (result i32)
+  (i32.and
+    (i64.eq (i64.shr_u (local.get $v) (i64.const 49)) (i64.const 0x7FFD))
+    (i32.ge_u (i32.wrap_i64 (local.get $v)) (global.get $kcc_heap_start))))
+
+;; Copies the object a value points to past the top of the heap, once,
+;; and returns the value pointing to the copy.
+(func $kcc_forward (param $v i64) (result i64)
+  (local $p i32)
+  (local $size i32)
+  (if (i32.eqz (call $kcc_is_heap (local.get $v)))
+    (then (return (local.get $v))))
+  (local.set $p (i32.wrap_i64 (local.get $v)))
+  (if (i32.ne (i32.load (local.get $p)) (i32.const 2))
+    (then
+      (local.set $size (call $kcc_size (local.get $p)))
+      (memory.copy (global.get $kcc_copy_top) (local.get $p) (local.get $size))
+      (i32.store (local.get $p) (i32.const 2))
+      (i32.store offset=4 (local.get $p) (global.get $kcc_copy_top))
+      (global.set $kcc_copy_top (i32.add (global.get $kcc_copy_top) (local.get $size)))))
+  (i64.or
+    (i64.and (local.get $v) (i64.const 0xFFFFFFFF00000000))
+    (i64.extend_i32_u (i32.load offset=4 (local.get $p)))))
+
+(func $kcc_forward_array (param $array i32) (result i32)
+  (i32.wrap_i64 (call $kcc_forward (call $kcc_array_ref (local.get $array)))))
+
+;; Moves a value pointing to a copy by `delta` bytes.
+(func $kcc_relocate (param $v i64) (param $delta i32) (result i64)
+  (if (result i64) (call $kcc_is_heap (local.get $v))
+    (then (i64.sub (local.get $v) (i64.extend_i32_u (local.get $delta))))
+    (else (local.get $v))))
+
+;; Applies $kcc_forward, or $kcc_relocate when `delta` is set, to every value
+;; of the arrays from `p` to the top of the copies.
+(func $kcc_scan (param $p i32) (param $delta i32)
+  (local $i i32)
+  (local $n i32)
+  (local $slot i32)
+  (block $done
+    (loop $objects
+      (br_if $done (i32.ge_u (local.get $p) (global.get $kcc_copy_top)))
+      (if (i32.eq (i32.load (local.get $p)) (i32.const 1))
+        (then
+          (local.set $n (i32.load offset=4 (local.get $p)))
+          (local.set $i (i32.const 0))
+          (block $end
+            (loop $values
+              (br_if $end (i32.ge_u (local.get $i) (local.get $n)))
+              (local.set $slot (call $kcc_slot (local.get $p) (local.get $i)))
+              (i64.store (local.get $slot)
+                (if (result i64) (local.get $delta)
+                  (then (call $kcc_relocate (i64.load (local.get $slot)) (local.get $delta)))
+                  (else (call $kcc_forward (i64.load (local.get $slot))))))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br $values)))))
+      (local.set $p (i32.add (local.get $p) (call $kcc_size (local.get $p))))
+      (br $objects))))
+
+;; Copies everything reachable from variables, lists, threads and the answer
+;; past the top of the heap, then slides the copies down to the start of the heap.
+(func $kcc_collect
+  (local $to i32)
+  (local $delta i32)
+  (local $live i32)
+  (local.set $to (global.get $kcc_top))
+  (call $kcc_reserve
+    (i32.add (local.get $to) (i32.sub (global.get $kcc_top) (global.get $kcc_heap_start))))
+  (global.set $kcc_copy_top (local.get $to))
+  (global.set $kcc_variables (call $kcc_forward_array (global.get $kcc_variables)))
+  (global.set $kcc_lists (call $kcc_forward_array (global.get $kcc_lists)))
+  (global.set $kcc_threads (call $kcc_forward_array (global.get $kcc_threads)))
+  (global.set $kcc_answer (call $kcc_forward (global.get $kcc_answer)))
+  (call $kcc_scan (local.get $to) (i32.const 0))
+
+  (local.set $delta (i32.sub (local.get $to) (global.get $kcc_heap_start)))
+  (call $kcc_scan (local.get $to) (local.get $delta))
+  (global.set $kcc_variables (i32.sub (global.get $kcc_variables) (local.get $delta)))
+  (global.set $kcc_lists (i32.sub (global.get $kcc_lists) (local.get $delta)))
+  (global.set $kcc_threads (i32.sub (global.get $kcc_threads) (local.get $delta)))
+  (global.set $kcc_answer (call $kcc_relocate (global.get $kcc_answer) (local.get $delta)))
+  (local.set $live (i32.sub (global.get $kcc_copy_top) (local.get $to)))
+  (memory.copy (global.get $kcc_heap_start) (local.get $to) (local.get $live))
+  (global.set $kcc_top (i32.add (global.get $kcc_heap_start) (local.get $live)))
+  ;; Collects again once the heap has grown by as much as is live, or 4 MiB.
+  (global.set $kcc_collect_at
+    (i32.add (global.get $kcc_top)
+      (select (local.get $live) (i32.const 0x400000)
+        (i32.gt_u (local.get $live) (i32.const 0x400000))))))
+
+;; ---- Values ----
+
+(func $kcc_num (param $n f64) (result i64)
+  (if (result i64) (f64.ne (local.get $n) (local.get $n))
+    (then (i64.const 0x7FF8000000000000))
+    (else (i64.reinterpret_f64 (local.get $n)))))
+
+(func $kcc_bool (param $b i32) (result i64)
+  (i64.or (i64.const 0xFFF9000000000000)
+    (i64.extend_i32_u (i32.ne (local.get $b) (i32.const 0)))))
+
+(func $kcc_str (param $s i32) (result i64)
+  (i64.or (i64.const 0xFFFA000000000000) (i64.extend_i32_u (local.get $s))))
+
+(func $kcc_is_number (param $v i64) (result i32)
+  (i64.lt_u (local.get $v) (i64.const 0xFFF9000000000000)))
+
+(func $kcc_is_boolean (param $v i64) (result i32)
+  (i64.eq (i64.shr_u (local.get $v) (i64.const 48)) (i64.const 0xFFF9)))
+
+(func $kcc_is_string (param $v i64) (result i32)
+  (i64.eq (i64.shr_u (local.get $v) (i64.const 48)) (i64.const 0xFFFA)))
+
+;; ---- Text ----
+
+(func $kcc_length_of (param $s i32) (result i32)
+  (i32.load offset=4 (local.get $s)))
+
+(func $kcc_unit (param $s i32) (param $i i32) (result i32)
+  (i32.load16_u offset=8 (i32.add (local.get $s) (i32.shl (local.get $i) (i32.const 1)))))
+
+(func $kcc_set_unit (param $s i32) (param $i i32) (param $u i32)
+  (i32.store16 offset=8
+    (i32.add (local.get $s) (i32.shl (local.get $i) (i32.const 1)))
+    (local.get $u)))
+
+;; A string of the `length` ASCII characters at `p`.
+(func $kcc_ascii (param $p i32) (param $length i32) (result i32)
+  (local $s i32)
+  (local $i i32)
+  (local.set $s (call $kcc_new_string (local.get $length)))
+  (block $done
+    (loop $units
+      (br_if $done (i32.ge_u (local.get $i) (local.get $length)))
+      (call $kcc_set_unit (local.get $s) (local.get $i)
+        (i32.load8_u (i32.add (local.get $p) (local.get $i))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $units)))
+  (local.get $s))
+
+;; Whether a string is the `length` ASCII characters at `p`.
+(func $kcc_equals_ascii (param $s i32) (param $p i32) (param $length i32) (result i32)
+  (local $i i32)
+  (if (i32.ne (call $kcc_length_of (local.get $s)) (local.get $length))
+    (then (return (i32.const 0))))
+  (block $done
+    (loop $units
+      (br_if $done (i32.ge_u (local.get $i) (local.get $length)))
+      (if (i32.ne (call $kcc_unit (local.get $s) (local.get $i))
+            (i32.load8_u (i32.add (local.get $p) (local.get $i))))
+        (then (return (i32.const 0))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $units)))
+  (i32.const 1))
+
+(func $kcc_text_equals (param $a i32) (param $b i32) (result i32)
+  (local $i i32)
+  (local $length i32)
+  (local.set $length (call $kcc_length_of (local.get $a)))
+  (if (i32.ne (call $kcc_length_of (local.get $b)) (local.get $length))
+    (then (return (i32.const 0))))
+  (block $done
+    (loop $units
+      (br_if $done (i32.ge_u (local.get $i) (local.get $length)))
+      (if (i32.ne (call $kcc_unit (local.get $a) (local.get $i))
+            (call $kcc_unit (local.get $b) (local.get $i)))
+        (then (return (i32.const 0))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $units)))
+  (i32.const 1))
+
+(func $kcc_concat (param $a i32) (param $b i32) (result i32)
+  (local $s i32)
+  (local $length i32)
+  (local.set $length (call $kcc_length_of (local.get $a)))
+  (local.set $s
+    (call $kcc_new_string (i32.add (local.get $length) (call $kcc_length_of (local.get $b)))))
+  (memory.copy (i32.add (local.get $s) (i32.const 8)) (i32.add (local.get $a) (i32.const 8))
+    (i32.shl (local.get $length) (i32.const 1)))
+  (memory.copy
+    (i32.add (i32.add (local.get $s) (i32.const 8)) (i32.shl (local.get $length) (i32.const 1)))
+    (i32.add (local.get $b) (i32.const 8))
+    (i32.shl (call $kcc_length_of (local.get $b)) (i32.const 1)))
+  (local.get $s))
+
+;; Whitespace, as trimmed by JavaScript.
+(func $kcc_is_space (param $u i32) (result i32)
+  (if (i32.le_u (i32.sub (local.get $u) (i32.const 9)) (i32.const 4))
+    (then (return (i32.const 1))))
+  (if (i32.le_u (i32.sub (local.get $u) (i32.const 0x2000)) (i32.const 10))
+    (then (return (i32.const 1))))
+  (i32.or
+    (i32.or
+      (i32.or (i32.eq (local.get $u) (i32.const 0x20)) (i32.eq (local.get $u) (i32.const 0xA0)))
+      (i32.or (i32.eq (local.get $u) (i32.const 0x1680)) (i32.eq (local.get $u) (i32.const 0x2028))))
+    (i32.or
+      (i32.or (i32.eq (local.get $u) (i32.const 0x2029)) (i32.eq (local.get $u) (i32.const 0x202F)))
+      (i32.or
+        (i32.or (i32.eq (local.get $u) (i32.const 0x205F)) (i32.eq (local.get $u) (i32.const 0x3000)))
+        (i32.eq (local.get $u) (i32.const 0xFEFF))))))
+
+;; The lowercase form of a code unit. Covers the letters of Latin, Greek,
+;; Cyrillic and Armenian, which is all Scratch projects commonly compare.
+(func $kcc_lower (param $u i32) (result i32)
+  (local $odd i32)
+  (local.set $odd (i32.and (local.get $u) (i32.const 1)))
+  (if (i32.lt_u (local.get $u) (i32.const 0x80))
+    (then
+      (return
+        (select (i32.add (local.get $u) (i32.const 32)) (local.get $u)
+          (i32.le_u (i32.sub (local.get $u) (i32.const 0x41)) (i32.const 25))))))
+  (if (i32.lt_u (local.get $u) (i32.const 0x100))
+    (then
+      (return
+        (select (i32.add (local.get $u) (i32.const 32)) (local.get $u)
+          (i32.and
+            (i32.le_u (i32.sub (local.get $u) (i32.const 0xC0)) (i32.const 0x1E))
+            (i32.ne (local.get $u) (i32.const 0xD7)))))))
+  (if (i32.eq (local.get $u) (i32.const 0x130)) (then (return (i32.const 0x69))))
+  (if (i32.eq (local.get $u) (i32.const 0x178)) (then (return (i32.const 0xFF))))
+  ;; Pairs of an uppercase letter and its lowercase form.
+  (if (i32.or
+        (i32.or
+          (i32.and (i32.le_u (i32.sub (local.get $u) (i32.const 0x100)) (i32.const 0x37))
+            (i32.eqz (local.get $odd)))
+          (i32.and (i32.le_u (i32.sub (local.get $u) (i32.const 0x139)) (i32.const 0xF))
+            (local.get $odd)))
+        (i32.or
+          (i32.or
+            (i32.and (i32.le_u (i32.sub (local.get $u) (i32.const 0x14A)) (i32.const 0x2D))
+              (i32.eqz (local.get $odd)))
+            (i32.and (i32.le_u (i32.sub (local.get $u) (i32.const 0x179)) (i32.const 5))
+              (local.get $odd)))
+          (i32.or
+            (i32.and
+              (i32.or
+                (i32.or
+                  (i32.le_u (i32.sub (local.get $u) (i32.const 0x460)) (i32.const 0x21))
+                  (i32.le_u (i32.sub (local.get $u) (i32.const 0x48A)) (i32.const 0x35)))
+                (i32.or
+                  (i32.le_u (i32.sub (local.get $u) (i32.const 0x4D0)) (i32.const 0x5F))
+                  (i32.or
+                    (i32.le_u (i32.sub (local.get $u) (i32.const 0x1E00)) (i32.const 0x95))
+                    (i32.le_u (i32.sub (local.get $u) (i32.const 0x1EA0)) (i32.const 0x5F)))))
+              (i32.eqz (local.get $odd)))
+            (i32.and (i32.le_u (i32.sub (local.get $u) (i32.const 0x4C1)) (i32.const 0xD))
+              (local.get $odd)))))
+    (then (return (i32.add (local.get $u) (i32.const 1)))))
+  ;; Greek.
+  (if (i32.eq (local.get $u) (i32.const 0x386)) (then (return (i32.const 0x3AC))))
+  (if (i32.le_u (i32.sub (local.get $u) (i32.const 0x388)) (i32.const 2))
+    (then (return (i32.add (local.get $u) (i32.const 37)))))
+  (if (i32.eq (local.get $u) (i32.const 0x38C)) (then (return (i32.const 0x3CC))))
+  (if (i32.le_u (i32.sub (local.get $u) (i32.const 0x38E)) (i32.const 1))
+    (then (return (i32.add (local.get $u) (i32.const 63)))))
+  (if (i32.and (i32.le_u (i32.sub (local.get $u) (i32.const 0x391)) (i32.const 0x18))
+        (i32.ne (local.get $u) (i32.const 0x3A2)))
+    (then (return (i32.add (local.get $u) (i32.const 32)))))
+  ;; Cyrillic, Armenian and fullwidth Latin.
+  (if (i32.le_u (i32.sub (local.get $u) (i32.const 0x400)) (i32.const 0xF))
+    (then (return (i32.add (local.get $u) (i32.const 80)))))
+  (if (i32.le_u (i32.sub (local.get $u) (i32.const 0x410)) (i32.const 0x1F))
+    (then (return (i32.add (local.get $u) (i32.const 32)))))
+  (if (i32.le_u (i32.sub (local.get $u) (i32.const 0x531)) (i32.const 0x25))
+    (then (return (i32.add (local.get $u) (i32.const 48)))))
+  (if (i32.le_u (i32.sub (local.get $u) (i32.const 0xFF21)) (i32.const 25))
+    (then (return (i32.add (local.get $u) (i32.const 32)))))
+  (local.get $u))
+
+(func $kcc_lowercase (param $s i32) (result i32)
+  (local $t i32)
+  (local $i i32)
+  (local $length i32)
+  (local.set $length (call $kcc_length_of (local.get $s)))
+  (local.set $t (call $kcc_new_string (local.get $length)))
+  (block $done
+    (loop $units
+      (br_if $done (i32.ge_u (local.get $i) (local.get $length)))
+      (call $kcc_set_unit (local.get $t) (local.get $i)
+        (call $kcc_lower (call $kcc_unit (local.get $s) (local.get $i))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $units)))
+  (local.get $t))
+
+;; Compares strings ignoring case, by UTF-16 code units.
+(func $kcc_compare_text (param $a i32) (param $b i32) (result i32)
+  (local $i i32)
+  (local $x i32)
+  (local $y i32)
+  (local $la i32)
+  (local $lb i32)
+  (local.set $la (call $kcc_length_of (local.get $a)))
+  (local.set $lb (call $kcc_length_of (local.get $b)))
+  (block $done
+    (loop $units
+      (br_if $done
+        (i32.or (i32.ge_u (local.get $i) (local.get $la)) (i32.ge_u (local.get $i) (local.get $lb))))
+      (local.set $x (call $kcc_lower (call $kcc_unit (local.get $a) (local.get $i))))
+      (local.set $y (call $kcc_lower (call $kcc_unit (local.get $b) (local.get $i))))
+      (if (i32.ne (local.get $x) (local.get $y))
+        (then (return (select (i32.const -1) (i32.const 1) (i32.lt_u (local.get $x) (local.get $y))))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $units)))
+  (i32.sub (i32.gt_u (local.get $la) (local.get $lb)) (i32.lt_u (local.get $la) (local.get $lb))))
+
+;; UTF-8 text of a string, in a fresh buffer whose length goes to $kcc_utf8_length.
+;; Unpaired surrogates become U+FFFD.
+(func $kcc_utf8 (param $s i32) (result i32)
+  (local $buffer i32)
+  (local $out i32)
+  (local $i i32)
+  (local $length i32)
+  (local $c i32)
+  (local $next i32)
+  (local.set $length (call $kcc_length_of (local.get $s)))
+  (local.set $buffer (call $kcc_alloc (i32.mul (local.get $length) (i32.const 3))))
+  (local.set $out (local.get $buffer))
+  (block $done
+    (loop $units
+      (br_if $done (i32.ge_u (local.get $i) (local.get $length)))
+      (local.set $c (call $kcc_unit (local.get $s) (local.get $i)))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (if (i32.eq (i32.and (local.get $c) (i32.const 0xF800)) (i32.const 0xD800))
+        (then
+          (local.set $next
+            (if (result i32) (i32.lt_u (local.get $i) (local.get $length))
+              (then (call $kcc_unit (local.get $s) (local.get $i)))
+              (else (i32.const 0))))
+          (if (i32.and
+                (i32.lt_u (local.get $c) (i32.const 0xDC00))
+                (i32.eq (i32.and (local.get $next) (i32.const 0xFC00)) (i32.const 0xDC00)))
+            (then
+              (local.set $c
+                (i32.add (i32.const 0x10000)
+                  (i32.or
+                    (i32.shl (i32.and (local.get $c) (i32.const 0x3FF)) (i32.const 10))
+                    (i32.and (local.get $next) (i32.const 0x3FF)))))
+              (local.set $i (i32.add (local.get $i) (i32.const 1))))
+            (else (local.set $c (i32.const 0xFFFD))))))
+      (if (i32.lt_u (local.get $c) (i32.const 0x80))
+        (then
+          (i32.store8 (local.get $out) (local.get $c))
+          (local.set $out (i32.add (local.get $out) (i32.const 1))))
+        (else
+          (if (i32.lt_u (local.get $c) (i32.const 0x800))
+            (then
+              (i32.store8 (local.get $out)
+                (i32.or (i32.const 0xC0) (i32.shr_u (local.get $c) (i32.const 6))))
+              (local.set $out (i32.add (local.get $out) (i32.const 1))))
+            (else
+              (if (i32.lt_u (local.get $c) (i32.const 0x10000))
+                (then
+                  (i32.store8 (local.get $out)
+                    (i32.or (i32.const 0xE0) (i32.shr_u (local.get $c) (i32.const 12)))))
+                (else
+                  (i32.store8 (local.get $out)
+                    (i32.or (i32.const 0xF0) (i32.shr_u (local.get $c) (i32.const 18))))
+                  (local.set $out (i32.add (local.get $out) (i32.const 1)))
+                  (i32.store8 (local.get $out)
+                    (i32.or (i32.const 0x80)
+                      (i32.and (i32.shr_u (local.get $c) (i32.const 12)) (i32.const 0x3F))))))
+              (local.set $out (i32.add (local.get $out) (i32.const 1)))
+              (i32.store8 (local.get $out)
+                (i32.or (i32.const 0x80)
+                  (i32.and (i32.shr_u (local.get $c) (i32.const 6)) (i32.const 0x3F))))
+              (local.set $out (i32.add (local.get $out) (i32.const 1)))))
+          (i32.store8 (local.get $out)
+            (i32.or (i32.const 0x80) (i32.and (local.get $c) (i32.const 0x3F))))
+          (local.set $out (i32.add (local.get $out) (i32.const 1)))))
+      (br $units)))
+  (global.set $kcc_utf8_length (i32.sub (local.get $out) (local.get $buffer)))
+  (local.get $buffer))
+
+;; A string of the `length` bytes of UTF-8 text at `p`. Invalid bytes become U+FFFD.
+(func $kcc_from_utf8 (param $p i32) (param $length i32) (result i32)
+  (local $s i32)
+  (local $end i32)
+  (local $units i32)
+  (local $b i32)
+  (local $c i32)
+  (local $extra i32)
+  (local $min i32)
+  (local $i i32)
+  (local $next i32)
+  (local.set $s (call $kcc_new_string (local.get $length)))
+  (local.set $end (i32.add (local.get $p) (local.get $length)))
+  (block $done
+    (loop $chars
+      (br_if $done (i32.ge_u (local.get $p) (local.get $end)))
+      (local.set $b (i32.load8_u (local.get $p)))
+      (local.set $p (i32.add (local.get $p) (i32.const 1)))
+      (local.set $extra (i32.const 0))
+      (if (i32.lt_u (local.get $b) (i32.const 0x80))
+        (then (local.set $c (local.get $b)))
+        (else
+          (if (i32.eq (i32.and (local.get $b) (i32.const 0xE0)) (i32.const 0xC0))
+            (then
+              (local.set $c (i32.and (local.get $b) (i32.const 0x1F)))
+              (local.set $extra (i32.const 1))
+              (local.set $min (i32.const 0x80)))
+            (else
+              (if (i32.eq (i32.and (local.get $b) (i32.const 0xF0)) (i32.const 0xE0))
+                (then
+                  (local.set $c (i32.and (local.get $b) (i32.const 0x0F)))
+                  (local.set $extra (i32.const 2))
+                  (local.set $min (i32.const 0x800)))
+                (else
+                  (if (i32.eq (i32.and (local.get $b) (i32.const 0xF8)) (i32.const 0xF0))
+                    (then
+                      (local.set $c (i32.and (local.get $b) (i32.const 0x07)))
+                      (local.set $extra (i32.const 3))
+                      (local.set $min (i32.const 0x10000)))
+                    (else (local.set $c (i32.const -1))))))))
+          (local.set $i (i32.const 0))
+          (block $bad
+            (loop $continuation
+              (br_if $bad (i32.ge_u (local.get $i) (local.get $extra)))
+              (br_if $bad (i32.ge_u (local.get $p) (local.get $end)))
+              (local.set $next (i32.load8_u (local.get $p)))
+              (if (i32.ne (i32.and (local.get $next) (i32.const 0xC0)) (i32.const 0x80))
+                (then (local.set $c (i32.const -1)) (br $bad)))
+              (local.set $c
+                (i32.or (i32.shl (local.get $c) (i32.const 6))
+                  (i32.and (local.get $next) (i32.const 0x3F))))
+              (local.set $p (i32.add (local.get $p) (i32.const 1)))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br $continuation)))
+          (if (i32.or
+                (i32.or (i32.lt_u (local.get $i) (local.get $extra)) (i32.lt_s (local.get $c) (local.get $min)))
+                (i32.or (i32.gt_s (local.get $c) (i32.const 0x10FFFF))
+                  (i32.eq (i32.and (local.get $c) (i32.const -2048)) (i32.const 0xD800))))
+            (then (local.set $c (i32.const 0xFFFD))))))
+      (if (i32.ge_u (local.get $c) (i32.const 0x10000))
+        (then
+          (local.set $c (i32.sub (local.get $c) (i32.const 0x10000)))
+          (call $kcc_set_unit (local.get $s) (local.get $units)
+            (i32.or (i32.const 0xD800) (i32.shr_u (local.get $c) (i32.const 10))))
+          (local.set $units (i32.add (local.get $units) (i32.const 1)))
+          (local.set $c (i32.or (i32.const 0xDC00) (i32.and (local.get $c) (i32.const 0x3FF))))))
+      (call $kcc_set_unit (local.get $s) (local.get $units) (local.get $c))
+      (local.set $units (i32.add (local.get $units) (i32.const 1)))
+      (br $chars)))
+  (i32.store offset=4 (local.get $s) (local.get $units))
+  (local.get $s))
+
+;; ---- Big numbers ----
+;; Formatting and parsing doubles exactly needs integers of up to about 1300 bits.
+
+(func $bn_set (param $a i32) (param $v i64)
+  (memory.fill (local.get $a) (i32.const 0) (i32.const 192))
+  (i64.store (local.get $a) (local.get $v)))
+
+(func $bn_copy (param $to i32) (param $from i32)
+  (memory.copy (local.get $to) (local.get $from) (i32.const 192)))
+
+(func $bn_mul_small (param $a i32) (param $m i64)
+  (local $end i32)
+  (local $t i64)
+  (local $carry i64)
+  (local.set $end (i32.add (local.get $a) (i32.const 192)))
+  (loop $limbs
+    (local.set $t
+      (i64.add (i64.mul (i64.load32_u (local.get $a)) (local.get $m)) (local.get $carry)))
+    (i64.store32 (local.get $a) (local.get $t))
+    (local.set $carry (i64.shr_u (local.get $t) (i64.const 32)))
+    (local.set $a (i32.add (local.get $a) (i32.const 4)))
+    (br_if $limbs (i32.lt_u (local.get $a) (local.get $end)))))
+
+(func $bn_add_small (param $a i32) (param $v i64)
+  (local $end i32)
+  (local $t i64)
+  (local.set $end (i32.add (local.get $a) (i32.const 192)))
+  (block $done
+    (loop $limbs
+      (br_if $done (i64.eqz (local.get $v)))
+      (br_if $done (i32.ge_u (local.get $a) (local.get $end)))
+      (local.set $t (i64.add (i64.load32_u (local.get $a)) (local.get $v)))
+      (i64.store32 (local.get $a) (local.get $t))
+      (local.set $v (i64.shr_u (local.get $t) (i64.const 32)))
+      (local.set $a (i32.add (local.get $a) (i32.const 4)))
+      (br $limbs))))
+
+(func $bn_add (param $to i32) (param $a i32) (param $b i32)
+  (local $i i32)
+  (local $t i64)
+  (loop $limbs
+    (local.set $t
+      (i64.add
+        (i64.add
+          (i64.load32_u (i32.add (local.get $a) (local.get $i)))
+          (i64.load32_u (i32.add (local.get $b) (local.get $i))))
+        (i64.shr_u (local.get $t) (i64.const 32))))
+    (i64.store32 (i32.add (local.get $to) (local.get $i)) (local.get $t))
+    (local.set $i (i32.add (local.get $i) (i32.const 4)))
+    (br_if $limbs (i32.lt_u (local.get $i) (i32.const 192)))))
+
+;; a -= b, where a >= b.
+(func $bn_sub (param $a i32) (param $b i32)
+  (local $i i32)
+  (local $t i64)
+  (local $borrow i64)
+  (loop $limbs
+    (local.set $t
+      (i64.sub
+        (i64.sub
+          (i64.load32_u (i32.add (local.get $a) (local.get $i)))
+          (i64.load32_u (i32.add (local.get $b) (local.get $i))))
+        (local.get $borrow)))
+    (local.set $borrow (i64.extend_i32_u (i64.lt_s (local.get $t) (i64.const 0))))
+    (i64.store32 (i32.add (local.get $a) (local.get $i)) (local.get $t))
+    (local.set $i (i32.add (local.get $i) (i32.const 4)))
+    (br_if $limbs (i32.lt_u (local.get $i) (i32.const 192)))))
+
+(func $bn_shl (param $a i32) (param $bits i32)
+  (local $words i32)
+  (local $shift i32)
+  (local $i i32)
+  (local $from i32)
+  (local $high i32)
+  (local $low i32)
+  (local.set $words (i32.shr_u (local.get $bits) (i32.const 5)))
+  (local.set $shift (i32.and (local.get $bits) (i32.const 31)))
+  (local.set $i (i32.const 47))
+  (loop $limbs
+    (local.set $from (i32.sub (local.get $i) (local.get $words)))
+    (local.set $high
+      (if (result i32) (i32.ge_s (local.get $from) (i32.const 0))
+        (then (i32.load (i32.add (local.get $a) (i32.shl (local.get $from) (i32.const 2)))))
+        (else (i32.const 0))))
+    (local.set $low
+      (if (result i32) (i32.ge_s (local.get $from) (i32.const 1))
+        (then (i32.load (i32.add (local.get $a) (i32.shl (i32.sub (local.get $from) (i32.const 1)) (i32.const 2)))))
+        (else (i32.const 0))))
+    (i32.store (i32.add (local.get $a) (i32.shl (local.get $i) (i32.const 2)))
+      (if (result i32) (local.get $shift)
+        (then
+          (i32.or (i32.shl (local.get $high) (local.get $shift))
+            (i32.shr_u (local.get $low) (i32.sub (i32.const 32) (local.get $shift)))))
+        (else (local.get $high))))
+    (local.set $i (i32.sub (local.get $i) (i32.const 1)))
+    (br_if $limbs (i32.ge_s (local.get $i) (i32.const 0)))))
+
+(func $bn_cmp (param $a i32) (param $b i32) (result i32)
+  (local $i i32)
+  (local $x i32)
+  (local $y i32)
+  (local.set $i (i32.const 188))
+  (loop $limbs
+    (local.set $x (i32.load (i32.add (local.get $a) (local.get $i))))
+    (local.set $y (i32.load (i32.add (local.get $b) (local.get $i))))
+    (if (i32.ne (local.get $x) (local.get $y))
+      (then (return (select (i32.const 1) (i32.const -1) (i32.gt_u (local.get $x) (local.get $y))))))
+    (local.set $i (i32.sub (local.get $i) (i32.const 4)))
+    (br_if $limbs (i32.ge_s (local.get $i) (i32.const 0))))
+  (i32.const 0))
+
+(func $bn_mul_pow10 (param $a i32) (param $k i32)
+  (local $m i64)
+  (block $done
+    (loop $chunks
+      (br_if $done (i32.lt_s (local.get $k) (i32.const 9)))
+      (call $bn_mul_small (local.get $a) (i64.const 1000000000))
+      (local.set $k (i32.sub (local.get $k) (i32.const 9)))
+      (br $chunks)))
+  (local.set $m (i64.const 1))
+  (block $done
+    (loop $digits
+      (br_if $done (i32.le_s (local.get $k) (i32.const 0)))
+      (local.set $m (i64.mul (local.get $m) (i64.const 10)))
+      (local.set $k (i32.sub (local.get $k) (i32.const 1)))
+      (br $digits)))
+  (call $bn_mul_small (local.get $a) (local.get $m)))
+
+;; ---- Numbers to text ----
+
+;; The shortest digits telling a positive finite double apart from every other,
+;; by the free-format algorithm of Burger and Dybvig, into $kcc_digits.
+;; Returns how many there are; the number is 0.DIGITS * 10^$kcc_exponent.
+(func $kcc_shortest (param $v f64) (result i32)
+  (local $bits i64)
+  (local $be i32)
+  (local $f i64)
+  (local $e i32)
+  (local $even i32)
+  (local $estimate i32)
+  (local $c i32)
+  (local $d i32)
+  (local $count i32)
+  (local $low i32)
+  (local $high i32)
+  (local.set $bits (i64.reinterpret_f64 (local.get $v)))
+  (local.set $be (i32.wrap_i64 (i64.shr_u (local.get $bits) (i64.const 52))))
+  (local.set $f (i64.and (local.get $bits) (i64.const 0xFFFFFFFFFFFFF)))
+  (if (local.get $be)
+    (then
+      (local.set $f (i64.or (local.get $f) (i64.const 0x10000000000000)))
+      (local.set $e (i32.sub (local.get $be) (i32.const 1075))))
+    (else (local.set $e (i32.const -1074))))
+  (local.set $even (i64.eqz (i64.and (local.get $f) (i64.const 1))))
+  ;; v = r / s, and the gaps to the neighbouring doubles are m+ / s and m- / s.
+  (if (i32.ge_s (local.get $e) (i32.const 0))
+    (then
+      (if (i64.ne (local.get $f) (i64.const 0x10000000000000))
+        (then
+          (call $bn_set (global.get $bn_r) (local.get $f))
+          (call $bn_shl (global.get $bn_r) (i32.add (local.get $e) (i32.const 1)))
+          (call $bn_set (global.get $bn_s) (i64.const 2))
+          (call $bn_set (global.get $bn_plus) (i64.const 1))
+          (call $bn_shl (global.get $bn_plus) (local.get $e))
+          (call $bn_copy (global.get $bn_minus) (global.get $bn_plus)))
+        (else
+          (call $bn_set (global.get $bn_r) (local.get $f))
+          (call $bn_shl (global.get $bn_r) (i32.add (local.get $e) (i32.const 2)))
+          (call $bn_set (global.get $bn_s) (i64.const 4))
+          (call $bn_set (global.get $bn_plus) (i64.const 1))
+          (call $bn_shl (global.get $bn_plus) (i32.add (local.get $e) (i32.const 1)))
+          (call $bn_set (global.get $bn_minus) (i64.const 1))
+          (call $bn_shl (global.get $bn_minus) (local.get $e)))))
+    (else
+      (if (i32.or (i32.le_u (local.get $be) (i32.const 1))
+            (i64.ne (local.get $f) (i64.const 0x10000000000000)))
+        (then
+          (call $bn_set (global.get $bn_r) (i64.shl (local.get $f) (i64.const 1)))
+          (call $bn_set (global.get $bn_s) (i64.const 1))
+          (call $bn_shl (global.get $bn_s) (i32.sub (i32.const 1) (local.get $e)))
+          (call $bn_set (global.get $bn_plus) (i64.const 1))
+          (call $bn_set (global.get $bn_minus) (i64.const 1)))
+        (else
+          (call $bn_set (global.get $bn_r) (i64.shl (local.get $f) (i64.const 2)))
+          (call $bn_set (global.get $bn_s) (i64.const 1))
+          (call $bn_shl (global.get $bn_s) (i32.sub (i32.const 2) (local.get $e)))
+          (call $bn_set (global.get $bn_plus) (i64.const 2))
+          (call $bn_set (global.get $bn_minus) (i64.const 1))))))
+  ;; Estimates the exponent, then fixes the estimate up.
+  (local.set $estimate
+    (i32.trunc_f64_s
+      (f64.ceil
+        (f64.sub
+          (f64.mul
+            (f64.convert_i32_s
+              (i32.add (local.get $e) (i32.sub (i32.const 63) (i32.wrap_i64 (i64.clz (local.get $f))))))
+            (f64.const 0.30102999566398114))
+          (f64.const 1e-10)))))
+  (if (i32.ge_s (local.get $estimate) (i32.const 0))
+    (then (call $bn_mul_pow10 (global.get $bn_s) (local.get $estimate)))
+    (else
+      (call $bn_mul_pow10 (global.get $bn_r) (i32.sub (i32.const 0) (local.get $estimate)))
+      (call $bn_mul_pow10 (global.get $bn_plus) (i32.sub (i32.const 0) (local.get $estimate)))
+      (call $bn_mul_pow10 (global.get $bn_minus) (i32.sub (i32.const 0) (local.get $estimate)))))
+  (call $bn_add (global.get $bn_t) (global.get $bn_r) (global.get $bn_plus))
+  (local.set $c (call $bn_cmp (global.get $bn_t) (global.get $bn_s)))
+  (if (select (i32.ge_s (local.get $c) (i32.const 0)) (i32.gt_s (local.get $c) (i32.const 0))
+        (local.get $even))
+    (then (global.set $kcc_exponent (i32.add (local.get $estimate) (i32.const 1))))
+    (else
+      (global.set $kcc_exponent (local.get $estimate))
+      (call $bn_mul_small (global.get $bn_r) (i64.const 10))
+      (call $bn_mul_small (global.get $bn_plus) (i64.const 10))
+      (call $bn_mul_small (global.get $bn_minus) (i64.const 10))))
+  ;; Generates digits until the rest is within the gaps.
+  (loop $digits
+    (local.set $d (i32.const 0))
+    (block $divided
+      (loop $subtract
+        (br_if $divided (i32.lt_s (call $bn_cmp (global.get $bn_r) (global.get $bn_s)) (i32.const 0)))
+        (call $bn_sub (global.get $bn_r) (global.get $bn_s))
+        (local.set $d (i32.add (local.get $d) (i32.const 1)))
+        (br $subtract)))
+    (local.set $c (call $bn_cmp (global.get $bn_r) (global.get $bn_minus)))
+    (local.set $low
+      (select (i32.le_s (local.get $c) (i32.const 0)) (i32.lt_s (local.get $c) (i32.const 0))
+        (local.get $even)))
+    (call $bn_add (global.get $bn_t) (global.get $bn_r) (global.get $bn_plus))
+    (local.set $c (call $bn_cmp (global.get $bn_t) (global.get $bn_s)))
+    (local.set $high
+      (select (i32.ge_s (local.get $c) (i32.const 0)) (i32.gt_s (local.get $c) (i32.const 0))
+        (local.get $even)))
+    (if (i32.eqz (i32.or (local.get $low) (local.get $high)))
+      (then
+        (i32.store8 (i32.add (global.get $kcc_digits) (local.get $count))
+          (i32.add (local.get $d) (i32.const 48)))
+        (local.set $count (i32.add (local.get $count) (i32.const 1)))
+        (call $bn_mul_small (global.get $bn_r) (i64.const 10))
+        (call $bn_mul_small (global.get $bn_plus) (i64.const 10))
+        (call $bn_mul_small (global.get $bn_minus) (i64.const 10))
+        (br $digits))))
+  ;; The last digit rounds up when only that is close enough, or when the rest is
+  ;; at least half of a digit.
+  (if (i32.and (local.get $low) (local.get $high))
+    (then
+      (call $bn_add (global.get $bn_t) (global.get $bn_r) (global.get $bn_r))
+      (if (i32.ge_s (call $bn_cmp (global.get $bn_t) (global.get $bn_s)) (i32.const 0))
+        (then (local.set $d (i32.add (local.get $d) (i32.const 1))))))
+    (else
+      (if (local.get $high)
+        (then (local.set $d (i32.add (local.get $d) (i32.const 1)))))))
+  (i32.store8 (i32.add (global.get $kcc_digits) (local.get $count))
+    (i32.add (local.get $d) (i32.const 48)))
+  (i32.add (local.get $count) (i32.const 1)))
+
+(func $kcc_put (param $out i32) (param $byte i32) (result i32)
+  (i32.store8 (local.get $out) (local.get $byte))
+  (i32.add (local.get $out) (i32.const 1)))
+
+(func $kcc_put_digits (param $out i32) (param $from i32) (param $to i32) (result i32)
+  (memory.copy (local.get $out) (i32.add (global.get $kcc_digits) (local.get $from))
+    (i32.sub (local.get $to) (local.get $from)))
+  (i32.add (local.get $out) (i32.sub (local.get $to) (local.get $from))))
+
+(func $kcc_put_zeros (param $out i32) (param $count i32) (result i32)
+  (memory.fill (local.get $out) (i32.const 48) (local.get $count))
+  (i32.add (local.get $out) (local.get $count)))
+
+;; Writes the digits of an integer into $kcc_digits and returns how many there are.
+(func $kcc_integer_digits (param $n i64) (result i32)
+  (local $count i32)
+  (local $t i64)
+  (local $i i32)
+  (local.set $t (local.get $n))
+  (loop $count
+    (local.set $count (i32.add (local.get $count) (i32.const 1)))
+    (local.set $t (i64.div_u (local.get $t) (i64.const 10)))
+    (br_if $count (i64.ne (local.get $t) (i64.const 0))))
+  (local.set $i (local.get $count))
+  (loop $digits
+    (local.set $i (i32.sub (local.get $i) (i32.const 1)))
+    (i32.store8 (i32.add (global.get $kcc_digits) (local.get $i))
+      (i32.add (i32.wrap_i64 (i64.rem_u (local.get $n) (i64.const 10))) (i32.const 48)))
+    (local.set $n (i64.div_u (local.get $n) (i64.const 10)))
+    (br_if $digits (local.get $i)))
+  (local.get $count))
+
+;; Formats a number like JavaScript.
+(func $kcc_number_to_string (param $n f64) (result i32)
+  (local $out i32)
+  (local $count i32)
+  (local $k i32)
+  (if (f64.ne (local.get $n) (local.get $n)) (then (return (global.get $kcc_nan_text))))
+  (if (f64.eq (local.get $n) (f64.const 0)) (then (return (global.get $kcc_zero_text))))
+  (if (f64.eq (local.get $n) (f64.const inf)) (then (return (global.get $kcc_infinity_text))))
+  (if (f64.eq (local.get $n) (f64.const -inf))
+    (then (return (global.get $kcc_negative_infinity_text))))
+  (local.set $out (global.get $kcc_text_buffer))
+  (if (f64.lt (local.get $n) (f64.const 0))
+    (then
+      (local.set $out (call $kcc_put (local.get $out) (i32.const 45)))
+      (local.set $n (f64.neg (local.get $n)))))
+  (if (i32.and (f64.lt (local.get $n) (f64.const 0x1p53))
+        (f64.eq (local.get $n) (f64.floor (local.get $n))))
+    (then
+      (local.set $count (call $kcc_integer_digits (i64.trunc_f64_u (local.get $n))))
+      (local.set $k (local.get $count)))
+    (else
+      (local.set $count (call $kcc_shortest (local.get $n)))
+      (local.set $k (global.get $kcc_exponent))))
+  (if (i32.and (i32.le_s (local.get $count) (local.get $k)) (i32.le_s (local.get $k) (i32.const 21)))
+    (then
+      (local.set $out (call $kcc_put_digits (local.get $out) (i32.const 0) (local.get $count)))
+      (local.set $out
+        (call $kcc_put_zeros (local.get $out) (i32.sub (local.get $k) (local.get $count)))))
+    (else
+      (if (i32.and (i32.gt_s (local.get $k) (i32.const 0)) (i32.le_s (local.get $k) (i32.const 21)))
+        (then
+          (local.set $out (call $kcc_put_digits (local.get $out) (i32.const 0) (local.get $k)))
+          (local.set $out (call $kcc_put (local.get $out) (i32.const 46)))
+          (local.set $out (call $kcc_put_digits (local.get $out) (local.get $k) (local.get $count))))
+        (else
+          (if (i32.and (i32.gt_s (local.get $k) (i32.const -6)) (i32.le_s (local.get $k) (i32.const 0)))
+            (then
+              (local.set $out (call $kcc_put (local.get $out) (i32.const 48)))
+              (local.set $out (call $kcc_put (local.get $out) (i32.const 46)))
+              (local.set $out (call $kcc_put_zeros (local.get $out) (i32.sub (i32.const 0) (local.get $k))))
+              (local.set $out (call $kcc_put_digits (local.get $out) (i32.const 0) (local.get $count))))
+            (else
+              (local.set $out (call $kcc_put_digits (local.get $out) (i32.const 0) (i32.const 1)))
+              (if (i32.gt_s (local.get $count) (i32.const 1))
+                (then
+                  (local.set $out (call $kcc_put (local.get $out) (i32.const 46)))
+                  (local.set $out (call $kcc_put_digits (local.get $out) (i32.const 1) (local.get $count)))))
+              (local.set $out (call $kcc_put (local.get $out) (i32.const 101)))
+              (local.set $k (i32.sub (local.get $k) (i32.const 1)))
+              (local.set $out
+                (call $kcc_put (local.get $out)
+                  (select (i32.const 45) (i32.const 43) (i32.lt_s (local.get $k) (i32.const 0)))))
+              (if (i32.lt_s (local.get $k) (i32.const 0))
+                (then (local.set $k (i32.sub (i32.const 0) (local.get $k)))))
+              ;; The exponent goes through the digit buffer, which is no longer needed.
+              (local.set $count (call $kcc_integer_digits (i64.extend_i32_u (local.get $k))))
+              (local.set $out (call $kcc_put_digits (local.get $out) (i32.const 0) (local.get $count)))))))))
+  (call $kcc_ascii (global.get $kcc_text_buffer)
+    (i32.sub (local.get $out) (global.get $kcc_text_buffer))))
+
+;; ---- Text to numbers ----
+
+;; Splits a positive double into m * 2^k.
+(global $kcc_mantissa (mut i64) (i64.const 0))
+(global $kcc_power (mut i32) (i32.const 0))
+
+(func $kcc_split (param $z f64)
+  (local $bits i64)
+  (local $be i32)
+  (local.set $bits (i64.reinterpret_f64 (local.get $z)))
+  (local.set $be (i32.wrap_i64 (i64.shr_u (local.get $bits) (i64.const 52))))
+  (global.set $kcc_mantissa (i64.and (local.get $bits) (i64.const 0xFFFFFFFFFFFFF)))
+  (if (local.get $be)
+    (then
+      (global.set $kcc_mantissa (i64.or (global.get $kcc_mantissa) (i64.const 0x10000000000000)))
+      (global.set $kcc_power (i32.sub (local.get $be) (i32.const 1075))))
+    (else (global.set $kcc_power (i32.const -1074)))))
+
+;; Compares D * 10^e, where D is in $bn_d and `sticky` stands for dropped nonzero
+;; digits, with (2m + 1) * 2^(k - 1), the point halfway between m * 2^k and the
+;; next double up.
+(func $kcc_versus_halfway (param $m i64) (param $k i32) (param $e i32) (param $sticky i32) (result i32)
+  (local $c i32)
+  (call $bn_copy (global.get $bn_x) (global.get $bn_d))
+  (call $bn_set (global.get $bn_y) (i64.add (i64.shl (local.get $m) (i64.const 1)) (i64.const 1)))
+  (if (i32.ge_s (local.get $e) (i32.const 0))
+    (then (call $bn_mul_pow10 (global.get $bn_x) (local.get $e)))
+    (else (call $bn_mul_pow10 (global.get $bn_y) (i32.sub (i32.const 0) (local.get $e)))))
+  (if (i32.ge_s (local.get $k) (i32.const 1))
+    (then (call $bn_shl (global.get $bn_y) (i32.sub (local.get $k) (i32.const 1))))
+    (else (call $bn_shl (global.get $bn_x) (i32.sub (i32.const 1) (local.get $k)))))
+  (local.set $c (call $bn_cmp (global.get $bn_x) (global.get $bn_y)))
+  (if (result i32) (i32.and (i32.eqz (local.get $c)) (local.get $sticky))
+    (then (i32.const 1))
+    (else (local.get $c))))
+
+;; 10^k for 0 <= k <= 22, exactly.
+(func $kcc_exact_pow10 (param $k i32) (result f64)
+  (local $p f64)
+  (local.set $p (f64.const 1))
+  (block $done
+    (loop $digits
+      (br_if $done (i32.le_s (local.get $k) (i32.const 0)))
+      (local.set $p (f64.mul (local.get $p) (f64.const 10)))
+      (local.set $k (i32.sub (local.get $k) (i32.const 1)))
+      (br $digits)))
+  (local.get $p))
+
+;; x * 10^e, with a few roundings.
+(func $kcc_scale10 (param $x f64) (param $e i32) (result f64)
+  (block $done
+    (loop $up
+      (br_if $done (i32.lt_s (local.get $e) (i32.const 22)))
+      (local.set $x (f64.mul (local.get $x) (f64.const 1e22)))
+      (local.set $e (i32.sub (local.get $e) (i32.const 22)))
+      (br $up)))
+  (block $done
+    (loop $down
+      (br_if $done (i32.gt_s (local.get $e) (i32.const -22)))
+      (local.set $x (f64.div (local.get $x) (f64.const 1e22)))
+      (local.set $e (i32.add (local.get $e) (i32.const 22)))
+      (br $down)))
+  (if (result f64) (i32.ge_s (local.get $e) (i32.const 0))
+    (then (f64.mul (local.get $x) (call $kcc_exact_pow10 (local.get $e))))
+    (else (f64.div (local.get $x) (call $kcc_exact_pow10 (i32.sub (i32.const 0) (local.get $e)))))))
+
+;; The double nearest to D * 10^e, ties to even, where D is the `count` digits in
+;; $kcc_decimal_digits, without leading zeros, followed by nonzero digits if `sticky` is set.
+(func $kcc_decimal (param $count i32) (param $e i32) (param $sticky i32) (result f64)
+  (local $i i32)
+  (local $d i64)
+  (local $head i32)
+  (local $z f64)
+  (local $down f64)
+  (local $m i64)
+  (if (i32.gt_s (i32.add (local.get $count) (local.get $e)) (i32.const 310))
+    (then (return (f64.const inf))))
+  (if (i32.lt_s (i32.add (local.get $count) (local.get $e)) (i32.const -330))
+    (then (return (f64.const 0))))
+  (local.set $head (select (local.get $count) (i32.const 19) (i32.lt_s (local.get $count) (i32.const 19))))
+  (block $done
+    (loop $digits
+      (br_if $done (i32.ge_s (local.get $i) (local.get $head)))
+      (local.set $d
+        (i64.add (i64.mul (local.get $d) (i64.const 10))
+          (i64.load8_u (i32.add (global.get $kcc_decimal_digits) (local.get $i)))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $digits)))
+  ;; Both the digits and the power of ten are exact doubles.
+  (if (i32.and
+        (i32.and (i32.le_s (local.get $count) (i32.const 15)) (i32.eqz (local.get $sticky)))
+        (i32.le_u (i32.add (local.get $e) (i32.const 22)) (i32.const 44)))
+    (then
+      (return
+        (if (result f64) (i32.ge_s (local.get $e) (i32.const 0))
+          (then (f64.mul (f64.convert_i64_u (local.get $d)) (call $kcc_exact_pow10 (local.get $e))))
+          (else
+            (f64.div (f64.convert_i64_u (local.get $d))
+              (call $kcc_exact_pow10 (i32.sub (i32.const 0) (local.get $e)))))))))
+  ;; Otherwise approximates, then walks to the nearest double comparing exactly.
+  (call $bn_set (global.get $bn_d) (i64.const 0))
+  (local.set $i (i32.const 0))
+  (block $done
+    (loop $digits
+      (br_if $done (i32.ge_s (local.get $i) (local.get $count)))
+      (call $bn_mul_small (global.get $bn_d) (i64.const 10))
+      (call $bn_add_small (global.get $bn_d)
+        (i64.load8_u (i32.add (global.get $kcc_decimal_digits) (local.get $i))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $digits)))
+  (local.set $z
+    (call $kcc_scale10 (f64.convert_i64_u (local.get $d))
+      (i32.add (local.get $e) (i32.sub (local.get $count) (local.get $head)))))
+  (if (f64.eq (local.get $z) (f64.const inf))
+    (then (local.set $z (f64.const 0x1.fffffffffffffp1023))))
+  (loop $walk
+    (call $kcc_split (local.get $z))
+    (local.set $m (global.get $kcc_mantissa))
+    (local.set $i
+      (call $kcc_versus_halfway (local.get $m) (global.get $kcc_power) (local.get $e) (local.get $sticky)))
+    (if (i32.or (i32.gt_s (local.get $i) (i32.const 0))
+          (i32.and (i32.eqz (local.get $i)) (i32.wrap_i64 (i64.and (local.get $m) (i64.const 1)))))
+      (then
+        (local.set $z (f64.reinterpret_i64 (i64.add (i64.reinterpret_f64 (local.get $z)) (i64.const 1))))
+        (br_if $walk (f64.ne (local.get $z) (f64.const inf)))
+        (return (local.get $z))))
+    (if (f64.gt (local.get $z) (f64.const 0))
+      (then
+        (local.set $down (f64.reinterpret_i64 (i64.sub (i64.reinterpret_f64 (local.get $z)) (i64.const 1))))
+        (call $kcc_split (local.get $down))
+        (local.set $i
+          (call $kcc_versus_halfway (global.get $kcc_mantissa) (global.get $kcc_power) (local.get $e)
+            (local.get $sticky)))
+        (if (i32.or (i32.lt_s (local.get $i) (i32.const 0))
+              (i32.and (i32.eqz (local.get $i)) (i32.wrap_i64 (i64.and (local.get $m) (i64.const 1)))))
+          (then
+            (local.set $z (local.get $down))
+            (br $walk))))))
+  (local.get $z))
+
+(func $kcc_is_digit (param $u i32) (result i32)
+  (i32.le_u (i32.sub (local.get $u) (i32.const 48)) (i32.const 9)))
+
+;; Parses the units of `s` from `i` to `end` as a decimal literal, or returns NaN.
+(func $kcc_parse_decimal (param $s i32) (param $i i32) (param $end i32) (result f64)
+  (local $u i32)
+  (local $d i32)
+  (local $count i32)
+  (local $sticky i32)
+  (local $e i32)
+  (local $seen i32)
+  (local $fraction i32)
+  (local $exponent i32)
+  (local $negative i32)
+  (block $digits_done
+    (loop $digits
+      (br_if $digits_done (i32.ge_u (local.get $i) (local.get $end)))
+      (local.set $u (call $kcc_unit (local.get $s) (local.get $i)))
+      (if (i32.and (i32.eq (local.get $u) (i32.const 46)) (i32.eqz (local.get $fraction)))
+        (then
+          (local.set $fraction (i32.const 1))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br $digits)))
+      (br_if $digits_done (i32.eqz (call $kcc_is_digit (local.get $u))))
+      (local.set $seen (i32.const 1))
+      (local.set $d (i32.sub (local.get $u) (i32.const 48)))
+      (if (i32.and (i32.eqz (local.get $count)) (i32.eqz (local.get $d)))
+        (then
+          ;; Leading zeros only shift the point.
+          (if (local.get $fraction) (then (local.set $e (i32.sub (local.get $e) (i32.const 1))))))
+        (else
+          (if (i32.lt_s (local.get $count) (i32.const 40))
+            (then
+              (i32.store8 (i32.add (global.get $kcc_decimal_digits) (local.get $count)) (local.get $d))
+              (local.set $count (i32.add (local.get $count) (i32.const 1)))
+              (if (local.get $fraction) (then (local.set $e (i32.sub (local.get $e) (i32.const 1))))))
+            (else
+              ;; Digits past the 40th only matter as being nonzero.
+              (if (local.get $d) (then (local.set $sticky (i32.const 1))))
+              (if (i32.eqz (local.get $fraction)) (then (local.set $e (i32.add (local.get $e) (i32.const 1)))))))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $digits)))
+  (if (i32.eqz (local.get $seen)) (then (return (f64.const nan))))
+  (if (i32.lt_u (local.get $i) (local.get $end))
+    (then
+      (if (i32.ne (i32.or (call $kcc_unit (local.get $s) (local.get $i)) (i32.const 0x20)) (i32.const 101))
+        (then (return (f64.const nan))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (if (i32.lt_u (local.get $i) (local.get $end))
+        (then
+          (local.set $u (call $kcc_unit (local.get $s) (local.get $i)))
+          (if (i32.or (i32.eq (local.get $u) (i32.const 43)) (i32.eq (local.get $u) (i32.const 45)))
+            (then
+              (local.set $negative (i32.eq (local.get $u) (i32.const 45)))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))))))
+      (local.set $seen (i32.const 0))
+      (block $exponent_done
+        (loop $exponent_digits
+          (br_if $exponent_done (i32.ge_u (local.get $i) (local.get $end)))
+          (local.set $u (call $kcc_unit (local.get $s) (local.get $i)))
+          (br_if $exponent_done (i32.eqz (call $kcc_is_digit (local.get $u))))
+          (local.set $seen (i32.const 1))
+          (if (i32.lt_s (local.get $exponent) (i32.const 100000))
+            (then
+              (local.set $exponent
+                (i32.add (i32.mul (local.get $exponent) (i32.const 10))
+                  (i32.sub (local.get $u) (i32.const 48))))))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br $exponent_digits)))
+      (if (i32.or (i32.eqz (local.get $seen)) (i32.lt_u (local.get $i) (local.get $end)))
+        (then (return (f64.const nan))))
+      (local.set $e
+        (if (result i32) (local.get $negative)
+          (then (i32.sub (local.get $e) (local.get $exponent)))
+          (else (i32.add (local.get $e) (local.get $exponent)))))))
+  (if (i32.eqz (local.get $count)) (then (return (f64.const 0))))
+  (call $kcc_decimal (local.get $count) (local.get $e) (local.get $sticky)))
+
+;; Parses text like JavaScript's Number(), but with NaN for text that is not a number.
+(func $kcc_str_to_number (param $s i32) (result f64)
+  (local $start i32)
+  (local $end i32)
+  (local $u i32)
+  (local $radix i32)
+  (local $d i32)
+  (local $acc f64)
+  (local $sign f64)
+  (local.set $end (call $kcc_length_of (local.get $s)))
+  (block $done
+    (loop $trim
+      (br_if $done (i32.ge_u (local.get $start) (local.get $end)))
+      (br_if $done (i32.eqz (call $kcc_is_space (call $kcc_unit (local.get $s) (local.get $start)))))
+      (local.set $start (i32.add (local.get $start) (i32.const 1)))
+      (br $trim)))
+  (block $done
+    (loop $trim
+      (br_if $done (i32.ge_u (local.get $start) (local.get $end)))
+      (br_if $done
+        (i32.eqz (call $kcc_is_space (call $kcc_unit (local.get $s) (i32.sub (local.get $end) (i32.const 1))))))
+      (local.set $end (i32.sub (local.get $end) (i32.const 1)))
+      (br $trim)))
+  (if (i32.eq (local.get $start) (local.get $end)) (then (return (f64.const 0))))
+  (if (i32.and (i32.gt_u (i32.sub (local.get $end) (local.get $start)) (i32.const 2))
+        (i32.eq (call $kcc_unit (local.get $s) (local.get $start)) (i32.const 48)))
+    (then
+      (local.set $u (i32.or (call $kcc_unit (local.get $s) (i32.add (local.get $start) (i32.const 1))) (i32.const 0x20)))
+      (local.set $radix
+        (select (i32.const 16)
+          (select (i32.const 2) (select (i32.const 8) (i32.const 0) (i32.eq (local.get $u) (i32.const 111)))
+            (i32.eq (local.get $u) (i32.const 98)))
+          (i32.eq (local.get $u) (i32.const 120))))
+      (if (local.get $radix)
+        (then
+          (local.set $start (i32.add (local.get $start) (i32.const 2)))
+          (loop $digits
+            (local.set $u (call $kcc_unit (local.get $s) (local.get $start)))
+            (local.set $d
+              (if (result i32) (call $kcc_is_digit (local.get $u))
+                (then (i32.sub (local.get $u) (i32.const 48)))
+                (else
+                  (if (result i32) (i32.le_u (i32.sub (i32.or (local.get $u) (i32.const 0x20)) (i32.const 97)) (i32.const 25))
+                    (then (i32.sub (i32.or (local.get $u) (i32.const 0x20)) (i32.const 87)))
+                    (else (i32.const 99))))))
+            (if (i32.ge_u (local.get $d) (local.get $radix)) (then (return (f64.const nan))))
+            (local.set $acc
+              (f64.add (f64.mul (local.get $acc) (f64.convert_i32_u (local.get $radix)))
+                (f64.convert_i32_u (local.get $d))))
+            (local.set $start (i32.add (local.get $start) (i32.const 1)))
+            (br_if $digits (i32.lt_u (local.get $start) (local.get $end))))
+          (return (local.get $acc))))))
+  (local.set $sign (f64.const 1))
+  (local.set $u (call $kcc_unit (local.get $s) (local.get $start)))
+  (if (i32.or (i32.eq (local.get $u) (i32.const 43)) (i32.eq (local.get $u) (i32.const 45)))
+    (then
+      (if (i32.eq (local.get $u) (i32.const 45)) (then (local.set $sign (f64.const -1))))
+      (local.set $start (i32.add (local.get $start) (i32.const 1)))))
+  (if (i32.eq (i32.sub (local.get $end) (local.get $start)) (i32.const 8))
+    (then
+      (local.set $u (i32.const 0))
+      (block $different
+        (loop $units
+          (br_if $different
+            (i32.ne (call $kcc_unit (local.get $s) (i32.add (local.get $start) (local.get $u)))
+              (i32.load8_u (i32.add (global.get $kcc_infinity_keyword) (local.get $u)))))
+          (local.set $u (i32.add (local.get $u) (i32.const 1)))
+          (br_if $units (i32.lt_u (local.get $u) (i32.const 8)))
+          (return (f64.mul (local.get $sign) (f64.const inf)))))))
+  (f64.mul (local.get $sign) (call $kcc_parse_decimal (local.get $s) (local.get $start) (local.get $end))))
+
+;; ---- Math ----
+;; WebAssembly has no math library: these follow musl, itself derived from fdlibm.
+
+(func $kcc_high_word (param $x f64) (result i32)
+  (i32.wrap_i64 (i64.shr_u (i64.reinterpret_f64 (local.get $x)) (i64.const 32))))
+
+;; x with its low 32 bits cleared.
+(func $kcc_clear_low (param $x f64) (result f64)
+  (f64.reinterpret_i64 (i64.and (i64.reinterpret_f64 (local.get $x)) (i64.const 0xFFFFFFFF00000000))))
+
+(func $kcc_fmod (param $x f64) (param $y f64) (result f64)
+  (local $ux i64)
+  (local $uy i64)
+  (local $ex i32)
+  (local $ey i32)
+  (local $sx i64)
+  (local $i i64)
+  (local.set $ux (i64.reinterpret_f64 (local.get $x)))
+  (local.set $uy (i64.reinterpret_f64 (local.get $y)))
+  (local.set $ex (i32.and (i32.wrap_i64 (i64.shr_u (local.get $ux) (i64.const 52))) (i32.const 0x7FF)))
+  (local.set $ey (i32.and (i32.wrap_i64 (i64.shr_u (local.get $uy) (i64.const 52))) (i32.const 0x7FF)))
+  (local.set $sx (i64.and (local.get $ux) (i64.const 0x8000000000000000)))
+  (if (i32.or
+        (i32.or (i64.eqz (i64.shl (local.get $uy) (i64.const 1))) (f64.ne (local.get $y) (local.get $y)))
+        (i32.eq (local.get $ex) (i32.const 0x7FF)))
+    (then (return (f64.const nan))))
+  (if (i64.le_u (i64.shl (local.get $ux) (i64.const 1)) (i64.shl (local.get $uy) (i64.const 1)))
+    (then
+      (if (i64.eq (i64.shl (local.get $ux) (i64.const 1)) (i64.shl (local.get $uy) (i64.const 1)))
+        (then (return (f64.mul (f64.const 0) (local.get $x)))))
+      (return (local.get $x))))
+  ;; Normalizes both mantissas.
+  (if (local.get $ex)
+    (then
+      (local.set $ux
+        (i64.or (i64.and (local.get $ux) (i64.const 0xFFFFFFFFFFFFF)) (i64.const 0x10000000000000))))
+    (else
+      (local.set $i (i64.shl (local.get $ux) (i64.const 12)))
+      (block $done
+        (loop $shift
+          (br_if $done (i64.lt_s (local.get $i) (i64.const 0)))
+          (local.set $ex (i32.sub (local.get $ex) (i32.const 1)))
+          (local.set $i (i64.shl (local.get $i) (i64.const 1)))
+          (br $shift)))
+      (local.set $ux (i64.shl (local.get $ux) (i64.extend_i32_u (i32.sub (i32.const 1) (local.get $ex)))))))
+  (if (local.get $ey)
+    (then
+      (local.set $uy
+        (i64.or (i64.and (local.get $uy) (i64.const 0xFFFFFFFFFFFFF)) (i64.const 0x10000000000000))))
+    (else
+      (local.set $i (i64.shl (local.get $uy) (i64.const 12)))
+      (block $done
+        (loop $shift
+          (br_if $done (i64.lt_s (local.get $i) (i64.const 0)))
+          (local.set $ey (i32.sub (local.get $ey) (i32.const 1)))
+          (local.set $i (i64.shl (local.get $i) (i64.const 1)))
+          (br $shift)))
+      (local.set $uy (i64.shl (local.get $uy) (i64.extend_i32_u (i32.sub (i32.const 1) (local.get $ey)))))))
+  ;; Long division, keeping the remainder.
+  (block $done
+    (loop $divide
+      (br_if $done (i32.le_s (local.get $ex) (local.get $ey)))
+      (local.set $i (i64.sub (local.get $ux) (local.get $uy)))
+      (if (i64.ge_s (local.get $i) (i64.const 0))
+        (then
+          (if (i64.eqz (local.get $i)) (then (return (f64.mul (f64.const 0) (local.get $x)))))
+          (local.set $ux (local.get $i))))
+      (local.set $ux (i64.shl (local.get $ux) (i64.const 1)))
+      (local.set $ex (i32.sub (local.get $ex) (i32.const 1)))
+      (br $divide)))
+  (local.set $i (i64.sub (local.get $ux) (local.get $uy)))
+  (if (i64.ge_s (local.get $i) (i64.const 0))
+    (then
+      (if (i64.eqz (local.get $i)) (then (return (f64.mul (f64.const 0) (local.get $x)))))
+      (local.set $ux (local.get $i))))
+  (block $done
+    (loop $normalize
+      (br_if $done (i64.ne (i64.shr_u (local.get $ux) (i64.const 52)) (i64.const 0)))
+      (local.set $ux (i64.shl (local.get $ux) (i64.const 1)))
+      (local.set $ex (i32.sub (local.get $ex) (i32.const 1)))
+      (br $normalize)))
+  (if (i32.gt_s (local.get $ex) (i32.const 0))
+    (then
+      (local.set $ux
+        (i64.or (i64.sub (local.get $ux) (i64.const 0x10000000000000))
+          (i64.shl (i64.extend_i32_u (local.get $ex)) (i64.const 52)))))
+    (else
+      (local.set $ux (i64.shr_u (local.get $ux) (i64.extend_i32_u (i32.sub (i32.const 1) (local.get $ex)))))))
+  (f64.reinterpret_i64 (i64.or (local.get $ux) (local.get $sx))))
+
+;; Rounds half away from zero, like C's round().
+(func $kcc_round_away (param $x f64) (result f64)
+  (local $t f64)
+  (local.set $t (f64.trunc (local.get $x)))
+  (if (result f64) (f64.ge (f64.abs (f64.sub (local.get $x) (local.get $t))) (f64.const 0.5))
+    (then (f64.add (local.get $t) (f64.copysign (f64.const 1) (local.get $x))))
+    (else (local.get $t))))
+
+;; x * 2^n.
+(func $kcc_scalbn (param $x f64) (param $n i32) (result f64)
+  (if (i32.gt_s (local.get $n) (i32.const 1023))
+    (then
+      (local.set $x (f64.mul (local.get $x) (f64.const 0x1p1023)))
+      (local.set $n (i32.sub (local.get $n) (i32.const 1023)))
+      (if (i32.gt_s (local.get $n) (i32.const 1023))
+        (then
+          (local.set $x (f64.mul (local.get $x) (f64.const 0x1p1023)))
+          (local.set $n (i32.sub (local.get $n) (i32.const 1023)))
+          (if (i32.gt_s (local.get $n) (i32.const 1023)) (then (local.set $n (i32.const 1023))))))))
+  (if (i32.lt_s (local.get $n) (i32.const -1022))
+    (then
+      (local.set $x (f64.mul (local.get $x) (f64.const 0x1p-969)))
+      (local.set $n (i32.add (local.get $n) (i32.const 969)))
+      (if (i32.lt_s (local.get $n) (i32.const -1022))
+        (then
+          (local.set $x (f64.mul (local.get $x) (f64.const 0x1p-969)))
+          (local.set $n (i32.add (local.get $n) (i32.const 969)))
+          (if (i32.lt_s (local.get $n) (i32.const -1022)) (then (local.set $n (i32.const -1022))))))))
+  (f64.mul (local.get $x)
+    (f64.reinterpret_i64 (i64.shl (i64.extend_i32_u (i32.add (i32.const 0x3FF) (local.get $n))) (i64.const 52)))))
+
+;; Reduces x by multiples of pi/2 into $kcc_y0 + $kcc_y1, returning the multiple.
+;; Exact for |x| below about 2^20 * pi/2.
+(global $kcc_y0 (mut f64) (f64.const 0))
+(global $kcc_y1 (mut f64) (f64.const 0))
+
+(func $kcc_rem_pio2 (param $x f64) (result i32)
+  (local $fn f64)
+  (local $r f64)
+  (local $w f64)
+  (local $t f64)
+  (local $ex i32)
+  (local $ey i32)
+  (local.set $fn (f64.nearest (f64.mul (local.get $x) (f64.const 6.36619772367581382433e-01))))
+  (local.set $r (f64.sub (local.get $x) (f64.mul (local.get $fn) (f64.const 1.57079632673412561417e+00))))
+  (local.set $w (f64.mul (local.get $fn) (f64.const 6.07710050650619224932e-11)))
+  (if (f64.lt (f64.sub (local.get $r) (local.get $w)) (f64.const -0x1.921fb54442d18p-1))
+    (then
+      (local.set $fn (f64.sub (local.get $fn) (f64.const 1)))
+      (local.set $r (f64.sub (local.get $x) (f64.mul (local.get $fn) (f64.const 1.57079632673412561417e+00))))
+      (local.set $w (f64.mul (local.get $fn) (f64.const 6.07710050650619224932e-11))))
+    (else
+      (if (f64.gt (f64.sub (local.get $r) (local.get $w)) (f64.const 0x1.921fb54442d18p-1))
+        (then
+          (local.set $fn (f64.add (local.get $fn) (f64.const 1)))
+          (local.set $r (f64.sub (local.get $x) (f64.mul (local.get $fn) (f64.const 1.57079632673412561417e+00))))
+          (local.set $w (f64.mul (local.get $fn) (f64.const 6.07710050650619224932e-11)))))))
+  (global.set $kcc_y0 (f64.sub (local.get $r) (local.get $w)))
+  (local.set $ex (i32.shr_u (i32.and (call $kcc_high_word (local.get $x)) (i32.const 0x7FFFFFFF)) (i32.const 20)))
+  (local.set $ey (i32.and (i32.shr_u (call $kcc_high_word (global.get $kcc_y0)) (i32.const 20)) (i32.const 0x7FF)))
+  (if (i32.gt_s (i32.sub (local.get $ex) (local.get $ey)) (i32.const 16))
+    (then
+      (local.set $t (local.get $r))
+      (local.set $w (f64.mul (local.get $fn) (f64.const 6.07710050630396597660e-11)))
+      (local.set $r (f64.sub (local.get $t) (local.get $w)))
+      (local.set $w
+        (f64.sub (f64.mul (local.get $fn) (f64.const 2.02226624879595063154e-21))
+          (f64.sub (f64.sub (local.get $t) (local.get $r)) (local.get $w))))
+      (global.set $kcc_y0 (f64.sub (local.get $r) (local.get $w)))
+      (local.set $ey (i32.and (i32.shr_u (call $kcc_high_word (global.get $kcc_y0)) (i32.const 20)) (i32.const 0x7FF)))
+      (if (i32.gt_s (i32.sub (local.get $ex) (local.get $ey)) (i32.const 49))
+        (then
+          (local.set $t (local.get $r))
+          (local.set $w (f64.mul (local.get $fn) (f64.const 2.02226624871116645580e-21)))
+          (local.set $r (f64.sub (local.get $t) (local.get $w)))
+          (local.set $w
+            (f64.sub (f64.mul (local.get $fn) (f64.const 8.47842766036889956997e-32))
+              (f64.sub (f64.sub (local.get $t) (local.get $r)) (local.get $w))))
+          (global.set $kcc_y0 (f64.sub (local.get $r) (local.get $w)))))))
+  (global.set $kcc_y1 (f64.sub (f64.sub (local.get $r) (global.get $kcc_y0)) (local.get $w)))
+  (i32.trunc_f64_s (local.get $fn)))
+
+(func $kcc_sin_kernel (param $x f64) (param $y f64) (param $iy i32) (result f64)
+  (local $z f64)
+  (local $w f64)
+  (local $r f64)
+  (local $v f64)
+  (local.set $z (f64.mul (local.get $x) (local.get $x)))
+  (local.set $w (f64.mul (local.get $z) (local.get $z)))
+  (local.set $r
+    (f64.add
+      (f64.add (f64.const 8.33333333332248946124e-03)
+        (f64.mul (local.get $z)
+          (f64.add (f64.const -1.98412698298579493134e-04) (f64.mul (local.get $z) (f64.const 2.75573137070700676789e-06)))))
+      (f64.mul (f64.mul (local.get $z) (local.get $w))
+        (f64.add (f64.const -2.50507602534068634195e-08) (f64.mul (local.get $z) (f64.const 1.58969099521155010221e-10))))))
+  (local.set $v (f64.mul (local.get $z) (local.get $x)))
+  (if (result f64) (i32.eqz (local.get $iy))
+    (then
+      (f64.add (local.get $x)
+        (f64.mul (local.get $v)
+          (f64.add (f64.const -1.66666666666666324348e-01) (f64.mul (local.get $z) (local.get $r))))))
+    (else
+      (f64.sub (local.get $x)
+        (f64.sub
+          (f64.sub
+            (f64.mul (local.get $z)
+              (f64.sub (f64.mul (f64.const 0.5) (local.get $y)) (f64.mul (local.get $v) (local.get $r))))
+            (local.get $y))
+          (f64.mul (local.get $v) (f64.const -1.66666666666666324348e-01)))))))
+
+(func $kcc_cos_kernel (param $x f64) (param $y f64) (result f64)
+  (local $z f64)
+  (local $w f64)
+  (local $r f64)
+  (local $hz f64)
+  (local.set $z (f64.mul (local.get $x) (local.get $x)))
+  (local.set $w (f64.mul (local.get $z) (local.get $z)))
+  (local.set $r
+    (f64.add
+      (f64.mul (local.get $z)
+        (f64.add (f64.const 4.16666666666666019037e-02)
+          (f64.mul (local.get $z)
+            (f64.add (f64.const -1.38888888888741095749e-03)
+              (f64.mul (local.get $z) (f64.const 2.48015872894767294178e-05))))))
+      (f64.mul (f64.mul (local.get $w) (local.get $w))
+        (f64.add (f64.const -2.75573143513906633035e-07)
+          (f64.mul (local.get $z)
+            (f64.add (f64.const 2.08757232129817482790e-09)
+              (f64.mul (local.get $z) (f64.const -1.13596475577881948265e-11))))))))
+  (local.set $hz (f64.mul (f64.const 0.5) (local.get $z)))
+  (local.set $w (f64.sub (f64.const 1) (local.get $hz)))
+  (f64.add (local.get $w)
+    (f64.add
+      (f64.sub (f64.sub (f64.const 1) (local.get $w)) (local.get $hz))
+      (f64.sub (f64.mul (local.get $z) (local.get $r)) (f64.mul (local.get $x) (local.get $y))))))
+
+(func $kcc_tan_kernel (param $x f64) (param $y f64) (param $odd i32) (result f64)
+  (local $hx i32)
+  (local $big i32)
+  (local $negative i32)
+  (local $z f64)
+  (local $w f64)
+  (local $r f64)
+  (local $v f64)
+  (local $s f64)
+  (local $a f64)
+  (local $w0 f64)
+  (local $a0 f64)
+  (local.set $hx (call $kcc_high_word (local.get $x)))
+  (local.set $big (i32.ge_u (i32.and (local.get $hx) (i32.const 0x7FFFFFFF)) (i32.const 0x3FE59428)))
+  (if (local.get $big)
+    (then
+      (local.set $negative (i32.shr_u (local.get $hx) (i32.const 31)))
+      (if (local.get $negative)
+        (then
+          (local.set $x (f64.neg (local.get $x)))
+          (local.set $y (f64.neg (local.get $y)))))
+      (local.set $x
+        (f64.add (f64.sub (f64.const 7.85398163397448278999e-01) (local.get $x))
+          (f64.sub (f64.const 3.06161699786838301793e-17) (local.get $y))))
+      (local.set $y (f64.const 0))))
+  (local.set $z (f64.mul (local.get $x) (local.get $x)))
+  (local.set $w (f64.mul (local.get $z) (local.get $z)))
+  (local.set $r
+    (f64.add (f64.const 1.33333333333201242699e-01)
+      (f64.mul (local.get $w)
+        (f64.add (f64.const 2.18694882948595424599e-02)
+          (f64.mul (local.get $w)
+            (f64.add (f64.const 3.59207910759131235356e-03)
+              (f64.mul (local.get $w)
+                (f64.add (f64.const 5.88041240820264096874e-04)
+                  (f64.mul (local.get $w)
+                    (f64.add (f64.const 7.81794442939557092300e-05)
+                      (f64.mul (local.get $w) (f64.const -1.85586374855275456654e-05))))))))))))
+  (local.set $v
+    (f64.mul (local.get $z)
+      (f64.add (f64.const 5.39682539762260521377e-02)
+        (f64.mul (local.get $w)
+          (f64.add (f64.const 8.86323982359930005737e-03)
+            (f64.mul (local.get $w)
+              (f64.add (f64.const 1.45620945432529025516e-03)
+                (f64.mul (local.get $w)
+                  (f64.add (f64.const 2.46463134818469906812e-04)
+                    (f64.mul (local.get $w)
+                      (f64.add (f64.const 7.14072491382608190305e-05)
+                        (f64.mul (local.get $w) (f64.const 2.59073051863633712884e-05)))))))))))))
+  (local.set $s (f64.mul (local.get $z) (local.get $x)))
+  (local.set $r
+    (f64.add
+      (f64.add (local.get $y)
+        (f64.mul (local.get $z)
+          (f64.add (f64.mul (local.get $s) (f64.add (local.get $r) (local.get $v))) (local.get $y))))
+      (f64.mul (local.get $s) (f64.const 3.33333333333334091986e-01))))
+  (local.set $w (f64.add (local.get $x) (local.get $r)))
+  (if (local.get $big)
+    (then
+      (local.set $s (f64.convert_i32_s (i32.sub (i32.const 1) (i32.shl (local.get $odd) (i32.const 1)))))
+      (local.set $v
+        (f64.sub (local.get $s)
+          (f64.mul (f64.const 2)
+            (f64.add (local.get $x)
+              (f64.sub (local.get $r)
+                (f64.div (f64.mul (local.get $w) (local.get $w)) (f64.add (local.get $w) (local.get $s))))))))
+      (return (select (f64.neg (local.get $v)) (local.get $v) (local.get $negative)))))
+  (if (i32.eqz (local.get $odd)) (then (return (local.get $w))))
+  ;; -1 / (x + r), accurately.
+  (local.set $w0 (call $kcc_clear_low (local.get $w)))
+  (local.set $v (f64.sub (local.get $r) (f64.sub (local.get $w0) (local.get $x))))
+  (local.set $a (f64.div (f64.const -1) (local.get $w)))
+  (local.set $a0 (call $kcc_clear_low (local.get $a)))
+  (f64.add (local.get $a0)
+    (f64.mul (local.get $a)
+      (f64.add (f64.add (f64.const 1) (f64.mul (local.get $a0) (local.get $w0)))
+        (f64.mul (local.get $a0) (local.get $v))))))
+
+(func $kcc_sin (param $x f64) (result f64)
+  (local $ix i32)
+  (local $n i32)
+  (local.set $ix (i32.and (call $kcc_high_word (local.get $x)) (i32.const 0x7FFFFFFF)))
+  (if (i32.le_u (local.get $ix) (i32.const 0x3FE921FB))
+    (then
+      (if (i32.lt_u (local.get $ix) (i32.const 0x3E500000)) (then (return (local.get $x))))
+      (return (call $kcc_sin_kernel (local.get $x) (f64.const 0) (i32.const 0)))))
+  (if (i32.ge_u (local.get $ix) (i32.const 0x7FF00000)) (then (return (f64.const nan))))
+  (local.set $n (i32.and (call $kcc_rem_pio2 (local.get $x)) (i32.const 3)))
+  (if (i32.eqz (local.get $n))
+    (then (return (call $kcc_sin_kernel (global.get $kcc_y0) (global.get $kcc_y1) (i32.const 1)))))
+  (if (i32.eq (local.get $n) (i32.const 1))
+    (then (return (call $kcc_cos_kernel (global.get $kcc_y0) (global.get $kcc_y1)))))
+  (if (i32.eq (local.get $n) (i32.const 2))
+    (then (return (f64.neg (call $kcc_sin_kernel (global.get $kcc_y0) (global.get $kcc_y1) (i32.const 1))))))
+  (f64.neg (call $kcc_cos_kernel (global.get $kcc_y0) (global.get $kcc_y1))))
+
+(func $kcc_cos (param $x f64) (result f64)
+  (local $ix i32)
+  (local $n i32)
+  (local.set $ix (i32.and (call $kcc_high_word (local.get $x)) (i32.const 0x7FFFFFFF)))
+  (if (i32.le_u (local.get $ix) (i32.const 0x3FE921FB))
+    (then
+      (if (i32.lt_u (local.get $ix) (i32.const 0x3E46A09E)) (then (return (f64.const 1))))
+      (return (call $kcc_cos_kernel (local.get $x) (f64.const 0)))))
+  (if (i32.ge_u (local.get $ix) (i32.const 0x7FF00000)) (then (return (f64.const nan))))
+  (local.set $n (i32.and (call $kcc_rem_pio2 (local.get $x)) (i32.const 3)))
+  (if (i32.eqz (local.get $n))
+    (then (return (call $kcc_cos_kernel (global.get $kcc_y0) (global.get $kcc_y1)))))
+  (if (i32.eq (local.get $n) (i32.const 1))
+    (then (return (f64.neg (call $kcc_sin_kernel (global.get $kcc_y0) (global.get $kcc_y1) (i32.const 1))))))
+  (if (i32.eq (local.get $n) (i32.const 2))
+    (then (return (f64.neg (call $kcc_cos_kernel (global.get $kcc_y0) (global.get $kcc_y1))))))
+  (call $kcc_sin_kernel (global.get $kcc_y0) (global.get $kcc_y1) (i32.const 1)))
+
+(func $kcc_tan (param $x f64) (result f64)
+  (local $ix i32)
+  (local.set $ix (i32.and (call $kcc_high_word (local.get $x)) (i32.const 0x7FFFFFFF)))
+  (if (i32.le_u (local.get $ix) (i32.const 0x3FE921FB))
+    (then
+      (if (i32.lt_u (local.get $ix) (i32.const 0x3E400000)) (then (return (local.get $x))))
+      (return (call $kcc_tan_kernel (local.get $x) (f64.const 0) (i32.const 0)))))
+  (if (i32.ge_u (local.get $ix) (i32.const 0x7FF00000)) (then (return (f64.const nan))))
+  (call $kcc_tan_kernel (global.get $kcc_y0) (global.get $kcc_y1)
+    (i32.and (call $kcc_rem_pio2 (local.get $x)) (i32.const 1))))
+
+(func $kcc_atan (param $x f64) (result f64)
+  (local $hx i32)
+  (local $ix i32)
+  (local $id i32)
+  (local $z f64)
+  (local $w f64)
+  (local $s1 f64)
+  (local $s2 f64)
+  (local $hi f64)
+  (local $lo f64)
+  (local.set $hx (call $kcc_high_word (local.get $x)))
+  (local.set $ix (i32.and (local.get $hx) (i32.const 0x7FFFFFFF)))
+  (if (i32.ge_u (local.get $ix) (i32.const 0x44100000))
+    (then
+      (if (f64.ne (local.get $x) (local.get $x)) (then (return (local.get $x))))
+      (return (f64.copysign (f64.const 1.57079632679489655800e+00) (local.get $x)))))
+  (local.set $id (i32.const -1))
+  (if (i32.lt_u (local.get $ix) (i32.const 0x3FDC0000))
+    (then
+      (if (i32.lt_u (local.get $ix) (i32.const 0x3E400000)) (then (return (local.get $x)))))
+    (else
+      (local.set $x (f64.abs (local.get $x)))
+      (if (i32.lt_u (local.get $ix) (i32.const 0x3FF30000))
+        (then
+          (if (i32.lt_u (local.get $ix) (i32.const 0x3FE60000))
+            (then
+              (local.set $id (i32.const 0))
+              (local.set $x
+                (f64.div (f64.sub (f64.mul (f64.const 2) (local.get $x)) (f64.const 1))
+                  (f64.add (f64.const 2) (local.get $x)))))
+            (else
+              (local.set $id (i32.const 1))
+              (local.set $x
+                (f64.div (f64.sub (local.get $x) (f64.const 1)) (f64.add (local.get $x) (f64.const 1)))))))
+        (else
+          (if (i32.lt_u (local.get $ix) (i32.const 0x40038000))
+            (then
+              (local.set $id (i32.const 2))
+              (local.set $x
+                (f64.div (f64.sub (local.get $x) (f64.const 1.5))
+                  (f64.add (f64.const 1) (f64.mul (f64.const 1.5) (local.get $x))))))
+            (else
+              (local.set $id (i32.const 3))
+              (local.set $x (f64.div (f64.const -1) (local.get $x)))))))))
+  (local.set $z (f64.mul (local.get $x) (local.get $x)))
+  (local.set $w (f64.mul (local.get $z) (local.get $z)))
+  (local.set $s1
+    (f64.mul (local.get $z)
+      (f64.add (f64.const 3.33333333333329318027e-01)
+        (f64.mul (local.get $w)
+          (f64.add (f64.const 1.42857142725034663711e-01)
+            (f64.mul (local.get $w)
+              (f64.add (f64.const 9.09088713343650656196e-02)
+                (f64.mul (local.get $w)
+                  (f64.add (f64.const 6.66107313738753120669e-02)
+                    (f64.mul (local.get $w)
+                      (f64.add (f64.const 4.97687799461593236017e-02)
+                        (f64.mul (local.get $w) (f64.const 1.62858201153657823623e-02)))))))))))))
+  (local.set $s2
+    (f64.mul (local.get $w)
+      (f64.add (f64.const -1.99999999998764832476e-01)
+        (f64.mul (local.get $w)
+          (f64.add (f64.const -1.11111104054623557880e-01)
+            (f64.mul (local.get $w)
+              (f64.add (f64.const -7.69187620504482999495e-02)
+                (f64.mul (local.get $w)
+                  (f64.add (f64.const -5.83357013379057348645e-02)
+                    (f64.mul (local.get $w) (f64.const -3.65315727442169155270e-02)))))))))))
+  (if (i32.lt_s (local.get $id) (i32.const 0))
+    (then
+      (return
+        (f64.sub (local.get $x) (f64.mul (local.get $x) (f64.add (local.get $s1) (local.get $s2)))))))
+  (if (i32.eqz (local.get $id))
+    (then
+      (local.set $hi (f64.const 4.63647609000806093515e-01))
+      (local.set $lo (f64.const 2.26987774529616870924e-17))))
+  (if (i32.eq (local.get $id) (i32.const 1))
+    (then
+      (local.set $hi (f64.const 7.85398163397448278999e-01))
+      (local.set $lo (f64.const 3.06161699786838301793e-17))))
+  (if (i32.eq (local.get $id) (i32.const 2))
+    (then
+      (local.set $hi (f64.const 9.82793723247329054082e-01))
+      (local.set $lo (f64.const 1.39033110312309984516e-17))))
+  (if (i32.eq (local.get $id) (i32.const 3))
+    (then
+      (local.set $hi (f64.const 1.57079632679489655800e+00))
+      (local.set $lo (f64.const 6.12323399573676603587e-17))))
+  (local.set $z
+    (f64.sub (local.get $hi)
+      (f64.sub
+        (f64.sub (f64.mul (local.get $x) (f64.add (local.get $s1) (local.get $s2))) (local.get $lo))
+        (local.get $x))))
+  (select (f64.neg (local.get $z)) (local.get $z) (i32.shr_u (local.get $hx) (i32.const 31))))
+
+;; The rational approximation shared by asin and acos.
+(func $kcc_asin_r (param $z f64) (result f64)
+  (f64.div
+    (f64.mul (local.get $z)
+      (f64.add (f64.const 1.66666666666666657415e-01)
+        (f64.mul (local.get $z)
+          (f64.add (f64.const -3.25565818622400915405e-01)
+            (f64.mul (local.get $z)
+              (f64.add (f64.const 2.01212532134862925881e-01)
+                (f64.mul (local.get $z)
+                  (f64.add (f64.const -4.00555345006794114027e-02)
+                    (f64.mul (local.get $z)
+                      (f64.add (f64.const 7.91534994289814532176e-04)
+                        (f64.mul (local.get $z) (f64.const 3.47933107596021167570e-05))))))))))))
+    (f64.add (f64.const 1)
+      (f64.mul (local.get $z)
+        (f64.add (f64.const -2.40339491173441421878e+00)
+          (f64.mul (local.get $z)
+            (f64.add (f64.const 2.02094576023350569471e+00)
+              (f64.mul (local.get $z)
+                (f64.add (f64.const -6.88283971605453293030e-01)
+                  (f64.mul (local.get $z) (f64.const 7.70381505559019352791e-02)))))))))))
+
+(func $kcc_asin (param $x f64) (result f64)
+  (local $hx i32)
+  (local $ix i32)
+  (local $z f64)
+  (local $s f64)
+  (local $r f64)
+  (local $f f64)
+  (local $c f64)
+  (local.set $hx (call $kcc_high_word (local.get $x)))
+  (local.set $ix (i32.and (local.get $hx) (i32.const 0x7FFFFFFF)))
+  (if (i32.ge_u (local.get $ix) (i32.const 0x3FF00000))
+    (then
+      (if (f64.eq (f64.abs (local.get $x)) (f64.const 1))
+        (then (return (f64.mul (local.get $x) (f64.const 1.57079632679489655800e+00)))))
+      (return (f64.const nan))))
+  (if (i32.lt_u (local.get $ix) (i32.const 0x3FE00000))
+    (then
+      (if (i32.and (i32.lt_u (local.get $ix) (i32.const 0x3E500000)) (i32.ge_u (local.get $ix) (i32.const 0x00100000)))
+        (then (return (local.get $x))))
+      (return
+        (f64.add (local.get $x)
+          (f64.mul (local.get $x) (call $kcc_asin_r (f64.mul (local.get $x) (local.get $x))))))))
+  (local.set $z (f64.mul (f64.sub (f64.const 1) (f64.abs (local.get $x))) (f64.const 0.5)))
+  (local.set $s (f64.sqrt (local.get $z)))
+  (local.set $r (call $kcc_asin_r (local.get $z)))
+  (if (i32.ge_u (local.get $ix) (i32.const 0x3FEF3333))
+    (then
+      (local.set $x
+        (f64.sub (f64.const 1.57079632679489655800e+00)
+          (f64.sub
+            (f64.mul (f64.const 2) (f64.add (local.get $s) (f64.mul (local.get $s) (local.get $r))))
+            (f64.const 6.12323399573676603587e-17)))))
+    (else
+      (local.set $f (call $kcc_clear_low (local.get $s)))
+      (local.set $c
+        (f64.div (f64.sub (local.get $z) (f64.mul (local.get $f) (local.get $f)))
+          (f64.add (local.get $s) (local.get $f))))
+      (local.set $x
+        (f64.sub (f64.mul (f64.const 0.5) (f64.const 1.57079632679489655800e+00))
+          (f64.sub
+            (f64.sub
+              (f64.mul (f64.mul (f64.const 2) (local.get $s)) (local.get $r))
+              (f64.sub (f64.const 6.12323399573676603587e-17) (f64.mul (f64.const 2) (local.get $c))))
+            (f64.sub (f64.mul (f64.const 0.5) (f64.const 1.57079632679489655800e+00))
+              (f64.mul (f64.const 2) (local.get $f))))))))
+  (select (f64.neg (local.get $x)) (local.get $x) (i32.shr_u (local.get $hx) (i32.const 31))))
+
+(func $kcc_acos (param $x f64) (result f64)
+  (local $hx i32)
+  (local $ix i32)
+  (local $z f64)
+  (local $s f64)
+  (local $w f64)
+  (local $df f64)
+  (local $c f64)
+  (local.set $hx (call $kcc_high_word (local.get $x)))
+  (local.set $ix (i32.and (local.get $hx) (i32.const 0x7FFFFFFF)))
+  (if (i32.ge_u (local.get $ix) (i32.const 0x3FF00000))
+    (then
+      (if (f64.eq (local.get $x) (f64.const 1)) (then (return (f64.const 0))))
+      (if (f64.eq (local.get $x) (f64.const -1))
+        (then (return (f64.mul (f64.const 2) (f64.const 1.57079632679489655800e+00)))))
+      (return (f64.const nan))))
+  (if (i32.lt_u (local.get $ix) (i32.const 0x3FE00000))
+    (then
+      (if (i32.le_u (local.get $ix) (i32.const 0x3C600000))
+        (then (return (f64.const 1.57079632679489655800e+00))))
+      (return
+        (f64.sub (f64.const 1.57079632679489655800e+00)
+          (f64.sub (local.get $x)
+            (f64.sub (f64.const 6.12323399573676603587e-17)
+              (f64.mul (local.get $x) (call $kcc_asin_r (f64.mul (local.get $x) (local.get $x))))))))))
+  (if (i32.shr_u (local.get $hx) (i32.const 31))
+    (then
+      (local.set $z (f64.mul (f64.add (f64.const 1) (local.get $x)) (f64.const 0.5)))
+      (local.set $s (f64.sqrt (local.get $z)))
+      (local.set $w
+        (f64.sub (f64.mul (call $kcc_asin_r (local.get $z)) (local.get $s))
+          (f64.const 6.12323399573676603587e-17)))
+      (return
+        (f64.mul (f64.const 2)
+          (f64.sub (f64.const 1.57079632679489655800e+00) (f64.add (local.get $s) (local.get $w)))))))
+  (local.set $z (f64.mul (f64.sub (f64.const 1) (local.get $x)) (f64.const 0.5)))
+  (local.set $s (f64.sqrt (local.get $z)))
+  (local.set $df (call $kcc_clear_low (local.get $s)))
+  (local.set $c
+    (f64.div (f64.sub (local.get $z) (f64.mul (local.get $df) (local.get $df)))
+      (f64.add (local.get $s) (local.get $df))))
+  (local.set $w (f64.add (f64.mul (call $kcc_asin_r (local.get $z)) (local.get $s)) (local.get $c)))
+  (f64.mul (f64.const 2) (f64.add (local.get $df) (local.get $w))))
+
+;; Reduces x > 0 to 1 + f with f in [sqrt(2)/2 - 1, sqrt(2) - 1], returning the
+;; power of two in $kcc_power, and computes the parts of log(1 + f) both
+;; logarithms need: f, hfsq = f^2 / 2, s = f / (2 + f) and R.
+(global $kcc_log_f (mut f64) (f64.const 0))
+(global $kcc_log_hfsq (mut f64) (f64.const 0))
+(global $kcc_log_s (mut f64) (f64.const 0))
+(global $kcc_log_r (mut f64) (f64.const 0))
+
+(func $kcc_log_reduce (param $x f64)
+  (local $bits i64)
+  (local $hx i32)
+  (local $k i32)
+  (local $f f64)
+  (local $s f64)
+  (local $z f64)
+  (local $w f64)
+  (local.set $bits (i64.reinterpret_f64 (local.get $x)))
+  (local.set $hx (i32.wrap_i64 (i64.shr_u (local.get $bits) (i64.const 32))))
+  (if (i32.lt_u (local.get $hx) (i32.const 0x00100000))
+    (then
+      (local.set $k (i32.const -54))
+      (local.set $bits (i64.reinterpret_f64 (f64.mul (local.get $x) (f64.const 0x1p54))))
+      (local.set $hx (i32.wrap_i64 (i64.shr_u (local.get $bits) (i64.const 32))))))
+  (local.set $hx (i32.add (local.get $hx) (i32.const 0x95F62)))
+  (local.set $k (i32.add (local.get $k) (i32.sub (i32.shr_u (local.get $hx) (i32.const 20)) (i32.const 0x3FF))))
+  (local.set $hx (i32.add (i32.and (local.get $hx) (i32.const 0x000FFFFF)) (i32.const 0x3FE6A09E)))
+  (local.set $f
+    (f64.sub
+      (f64.reinterpret_i64
+        (i64.or (i64.shl (i64.extend_i32_u (local.get $hx)) (i64.const 32))
+          (i64.and (local.get $bits) (i64.const 0xFFFFFFFF))))
+      (f64.const 1)))
+  (global.set $kcc_power (local.get $k))
+  (global.set $kcc_log_f (local.get $f))
+  (global.set $kcc_log_hfsq (f64.mul (f64.mul (f64.const 0.5) (local.get $f)) (local.get $f)))
+  (local.set $s (f64.div (local.get $f) (f64.add (f64.const 2) (local.get $f))))
+  (global.set $kcc_log_s (local.get $s))
+  (local.set $z (f64.mul (local.get $s) (local.get $s)))
+  (local.set $w (f64.mul (local.get $z) (local.get $z)))
+  (global.set $kcc_log_r
+    (f64.add
+      (f64.mul (local.get $z)
+        (f64.add (f64.const 6.666666666666735130e-01)
+          (f64.mul (local.get $w)
+            (f64.add (f64.const 2.857142874366239149e-01)
+              (f64.mul (local.get $w)
+                (f64.add (f64.const 1.818357216161805012e-01)
+                  (f64.mul (local.get $w) (f64.const 1.479819860511658591e-01))))))))
+      (f64.mul (local.get $w)
+        (f64.add (f64.const 3.999999999940941908e-01)
+          (f64.mul (local.get $w)
+            (f64.add (f64.const 2.222219843214978396e-01)
+              (f64.mul (local.get $w) (f64.const 1.531383769920937332e-01)))))))))
+
+;; The special cases of both logarithms, or 0 when there are none.
+(func $kcc_log_special (param $x f64) (result f64)
+  (if (f64.eq (local.get $x) (f64.const 0)) (then (return (f64.const -inf))))
+  (if (i32.or (f64.lt (local.get $x) (f64.const 0)) (f64.ne (local.get $x) (local.get $x)))
+    (then (return (f64.const nan))))
+  (if (f64.eq (local.get $x) (f64.const inf)) (then (return (f64.const inf))))
+  (f64.const 0))
+
+(func $kcc_log (param $x f64) (result f64)
+  (local $dk f64)
+  (if (i32.or (f64.eq (local.get $x) (f64.const 1))
+        (f64.ne (call $kcc_log_special (local.get $x)) (f64.const 0)))
+    (then (return (call $kcc_log_special (local.get $x)))))
+  (call $kcc_log_reduce (local.get $x))
+  (local.set $dk (f64.convert_i32_s (global.get $kcc_power)))
+  (f64.add
+    (f64.add
+      (f64.sub
+        (f64.add
+          (f64.mul (global.get $kcc_log_s) (f64.add (global.get $kcc_log_hfsq) (global.get $kcc_log_r)))
+          (f64.mul (local.get $dk) (f64.const 1.90821492927058770002e-10)))
+        (global.get $kcc_log_hfsq))
+      (global.get $kcc_log_f))
+    (f64.mul (local.get $dk) (f64.const 6.93147180369123816490e-01))))
+
+(func $kcc_log10 (param $x f64) (result f64)
+  (local $hi f64)
+  (local $lo f64)
+  (local $dk f64)
+  (local $y f64)
+  (local $val_hi f64)
+  (local $val_lo f64)
+  (local $w f64)
+  (if (i32.or (f64.eq (local.get $x) (f64.const 1))
+        (f64.ne (call $kcc_log_special (local.get $x)) (f64.const 0)))
+    (then (return (call $kcc_log_special (local.get $x)))))
+  (call $kcc_log_reduce (local.get $x))
+  (local.set $hi (call $kcc_clear_low (f64.sub (global.get $kcc_log_f) (global.get $kcc_log_hfsq))))
+  (local.set $lo
+    (f64.add
+      (f64.sub (f64.sub (global.get $kcc_log_f) (local.get $hi)) (global.get $kcc_log_hfsq))
+      (f64.mul (global.get $kcc_log_s) (f64.add (global.get $kcc_log_hfsq) (global.get $kcc_log_r)))))
+  (local.set $val_hi (f64.mul (local.get $hi) (f64.const 4.34294481878168880939e-01)))
+  (local.set $dk (f64.convert_i32_s (global.get $kcc_power)))
+  (local.set $y (f64.mul (local.get $dk) (f64.const 3.01029995663611771306e-01)))
+  (local.set $val_lo
+    (f64.add
+      (f64.add
+        (f64.mul (local.get $dk) (f64.const 3.69423907715893078616e-13))
+        (f64.mul (f64.add (local.get $lo) (local.get $hi)) (f64.const 2.50829467116452752298e-11)))
+      (f64.mul (local.get $lo) (f64.const 4.34294481878168880939e-01))))
+  (local.set $w (f64.add (local.get $y) (local.get $val_hi)))
+  (local.set $val_lo
+    (f64.add (local.get $val_lo) (f64.add (f64.sub (local.get $y) (local.get $w)) (local.get $val_hi))))
+  (f64.add (local.get $val_lo) (local.get $w)))
+
+;; e^x, as in glibc: x = k ln2/128 + r, e^x = 2^(k/128) e^r with 2^(k/128)
+;; from a table and a polynomial for e^r.
+(func $kcc_exp (param $x f64) (result f64)
+  (local $abstop i32)
+  (local $kd f64)
+  (local $ki i64)
+  (local $r f64)
+  (local $r2 f64)
+  (local $entry i32)
+  (local $sbits i64)
+  (local $tmp f64)
+  (local $scale f64)
+  (local $y f64)
+  (local $hi f64)
+  (local $lo f64)
+  (local.set $abstop
+    (i32.and (i32.wrap_i64 (i64.shr_u (i64.reinterpret_f64 (local.get $x)) (i64.const 52))) (i32.const 0x7FF)))
+  (if (i32.ge_u (i32.sub (local.get $abstop) (i32.const 0x3C9)) (i32.const 0x3F))
+    (then
+      ;; Tiny x.
+      (if (i32.lt_s (i32.sub (local.get $abstop) (i32.const 0x3C9)) (i32.const 0))
+        (then (return (f64.add (f64.const 1) (local.get $x)))))
+      (if (i32.ge_u (local.get $abstop) (i32.const 0x409))
+        (then
+          (if (f64.eq (local.get $x) (f64.const -inf)) (then (return (f64.const 0))))
+          (if (i32.ge_u (local.get $abstop) (i32.const 0x7FF))
+            (then (return (f64.add (f64.const 1) (local.get $x)))))
+          (if (f64.lt (local.get $x) (f64.const 0)) (then (return (f64.const 0))))
+          (return (f64.const inf))))
+      ;; Large x, whose result may overflow or be subnormal.
+      (local.set $abstop (i32.const 0))))
+  (local.set $kd (f64.add (f64.mul (f64.const 0x1.71547652b82fep7) (local.get $x)) (f64.const 0x1.8p52)))
+  (local.set $ki (i64.reinterpret_f64 (local.get $kd)))
+  (local.set $kd (f64.sub (local.get $kd) (f64.const 0x1.8p52)))
+  (local.set $r
+    (f64.add
+      (f64.add (local.get $x) (f64.mul (local.get $kd) (f64.const -0x1.62e42fefa0000p-8)))
+      (f64.mul (local.get $kd) (f64.const -0x1.cf79abc9e3b3ap-47))))
+  (local.set $entry
+    (i32.add (global.get $kcc_exp_table) (i32.shl (i32.and (i32.wrap_i64 (local.get $ki)) (i32.const 127)) (i32.const 4))))
+  (local.set $sbits (i64.add (i64.load offset=8 (local.get $entry)) (i64.shl (local.get $ki) (i64.const 45))))
+  (local.set $r2 (f64.mul (local.get $r) (local.get $r)))
+  (local.set $tmp
+    (f64.add
+      (f64.add
+        (f64.add (f64.load (local.get $entry)) (local.get $r))
+        (f64.mul (local.get $r2)
+          (f64.add (f64.const 0x1.ffffffffffdbdp-2) (f64.mul (local.get $r) (f64.const 0x1.555555555543cp-3)))))
+      (f64.mul (f64.mul (local.get $r2) (local.get $r2))
+        (f64.add (f64.const 0x1.55555cf172b91p-5) (f64.mul (local.get $r) (f64.const 0x1.1111167a4d017p-7))))))
+  (if (local.get $abstop)
+    (then
+      (local.set $scale (f64.reinterpret_i64 (local.get $sbits)))
+      (return (f64.add (local.get $scale) (f64.mul (local.get $scale) (local.get $tmp))))))
+  (if (i64.eqz (i64.and (local.get $ki) (i64.const 0x80000000)))
+    (then
+      (local.set $scale (f64.reinterpret_i64 (i64.sub (local.get $sbits) (i64.shl (i64.const 1009) (i64.const 52)))))
+      (return
+        (f64.mul (f64.const 0x1p1009) (f64.add (local.get $scale) (f64.mul (local.get $scale) (local.get $tmp)))))))
+  (local.set $scale (f64.reinterpret_i64 (i64.add (local.get $sbits) (i64.shl (i64.const 1022) (i64.const 52)))))
+  (local.set $y (f64.add (local.get $scale) (f64.mul (local.get $scale) (local.get $tmp))))
+  (if (f64.lt (local.get $y) (f64.const 1))
+    (then
+      (local.set $lo
+        (f64.add (f64.sub (local.get $scale) (local.get $y)) (f64.mul (local.get $scale) (local.get $tmp))))
+      (local.set $hi (f64.add (f64.const 1) (local.get $y)))
+      (local.set $lo (f64.add (f64.add (f64.sub (f64.const 1) (local.get $hi)) (local.get $y)) (local.get $lo)))
+      (local.set $y (f64.sub (f64.add (local.get $hi) (local.get $lo)) (f64.const 1)))))
+  (f64.mul (f64.const 0x1p-1022) (local.get $y)))
+
+;; 10^x. Integer powers are exact, others go through exp with the product
+;; x * ln(10) split in two to keep its rounding error.
+(func $kcc_pow10 (param $x f64) (result f64)
+  (local $i f64)
+  (local $f f64)
+  (local $p f64)
+  (local $error f64)
+  (local $ah f64)
+  (local $al f64)
+  (local $bh f64)
+  (local $bl f64)
+  (local $t f64)
+  (if (f64.ne (local.get $x) (local.get $x)) (then (return (local.get $x))))
+  (if (f64.gt (local.get $x) (f64.const 400)) (then (return (f64.const inf))))
+  (if (f64.lt (local.get $x) (f64.const -400)) (then (return (f64.const 0))))
+  (local.set $i (f64.floor (local.get $x)))
+  (if (f64.eq (local.get $i) (local.get $x))
+    (then
+      (i32.store8 (global.get $kcc_decimal_digits) (i32.const 1))
+      (return (call $kcc_decimal (i32.const 1) (i32.trunc_f64_s (local.get $x)) (i32.const 0)))))
+  (local.set $f (f64.sub (local.get $x) (local.get $i)))
+  (local.set $p (f64.mul (local.get $f) (f64.const 2.302585092994046)))
+  ;; Dekker's product error, with ln(10) = 2.302585092994046 + 2.1707562233822494e-16.
+  (local.set $t (f64.mul (local.get $f) (f64.const 134217729)))
+  (local.set $ah (f64.sub (local.get $t) (f64.sub (local.get $t) (local.get $f))))
+  (local.set $al (f64.sub (local.get $f) (local.get $ah)))
+  (local.set $t (f64.mul (f64.const 2.302585092994046) (f64.const 134217729)))
+  (local.set $bh (f64.sub (local.get $t) (f64.sub (local.get $t) (f64.const 2.302585092994046))))
+  (local.set $bl (f64.sub (f64.const 2.302585092994046) (local.get $bh)))
+  (local.set $error
+    (f64.add
+      (f64.add
+        (f64.add
+          (f64.sub (f64.mul (local.get $ah) (local.get $bh)) (local.get $p))
+          (f64.mul (local.get $ah) (local.get $bl)))
+        (f64.add (f64.mul (local.get $al) (local.get $bh)) (f64.mul (local.get $al) (local.get $bl))))
+      (f64.mul (local.get $f) (f64.const 2.1707562233822494e-16))))
+  (local.set $t (call $kcc_exp (local.get $p)))
+  (local.set $t (f64.add (local.get $t) (f64.mul (local.get $t) (local.get $error))))
+  (i32.store8 (global.get $kcc_decimal_digits) (i32.const 1))
+  (f64.mul (local.get $t) (call $kcc_decimal (i32.const 1) (i32.trunc_f64_s (local.get $i)) (i32.const 0))))
+
+;; ---- Casts ----
+
+;; A value as a number, NaN included.
+(func $kcc_js_number (param $v i64) (result f64)
+  (if (call $kcc_is_number (local.get $v))
+    (then (return (f64.reinterpret_i64 (local.get $v)))))
+  (if (call $kcc_is_boolean (local.get $v))
+    (then (return (f64.convert_i64_u (i64.and (local.get $v) (i64.const 1))))))
+  (call $kcc_str_to_number (i32.wrap_i64 (local.get $v))))
+
+(func $kcc_number (param $v i64) (result f64)
+  (local $n f64)
+  (local.set $n (call $kcc_js_number (local.get $v)))
+  (select (f64.const 0) (local.get $n) (f64.ne (local.get $n) (local.get $n))))
+
+(func $kcc_to_string (param $v i64) (result i32)
+  (if (call $kcc_is_string (local.get $v)) (then (return (i32.wrap_i64 (local.get $v)))))
+  (if (call $kcc_is_boolean (local.get $v))
+    (then
+      (return
+        (select (global.get $kcc_true_text) (global.get $kcc_false_text)
+          (i32.wrap_i64 (i64.and (local.get $v) (i64.const 1)))))))
+  (call $kcc_number_to_string (f64.reinterpret_i64 (local.get $v))))
+
+(func $kcc_boolean (param $v i64) (result i32)
+  (local $s i32)
+  (local $n f64)
+  (if (call $kcc_is_number (local.get $v))
+    (then
+      (local.set $n (f64.reinterpret_i64 (local.get $v)))
+      (return (i32.and (f64.ne (local.get $n) (f64.const 0)) (f64.eq (local.get $n) (local.get $n))))))
+  (if (call $kcc_is_boolean (local.get $v))
+    (then (return (i32.wrap_i64 (i64.and (local.get $v) (i64.const 1))))))
+  (local.set $s (i32.wrap_i64 (local.get $v)))
+  (if (i32.eqz (call $kcc_length_of (local.get $s))) (then (return (i32.const 0))))
+  (if (i32.and (i32.eq (call $kcc_length_of (local.get $s)) (i32.const 1))
+        (i32.eq (call $kcc_unit (local.get $s) (i32.const 0)) (i32.const 48)))
+    (then (return (i32.const 0))))
+  (if (i32.eq (call $kcc_length_of (local.get $s)) (i32.const 5))
+    (then
+      (if (call $kcc_equals_ascii
+            (call $kcc_ascii_lowercase (local.get $s)) (global.get $kcc_false_keyword) (i32.const 5))
+        (then (return (i32.const 0))))))
+  (i32.const 1))
+
+;; Lowercases ASCII letters only.
+(func $kcc_ascii_lowercase (param $s i32) (result i32)
+  (local $t i32)
+  (local $i i32)
+  (local $u i32)
+  (local.set $t (call $kcc_new_string (call $kcc_length_of (local.get $s))))
+  (block $done
+    (loop $units
+      (br_if $done (i32.ge_u (local.get $i) (call $kcc_length_of (local.get $s))))
+      (local.set $u (call $kcc_unit (local.get $s) (local.get $i)))
+      (call $kcc_set_unit (local.get $t) (local.get $i)
+        (select (i32.or (local.get $u) (i32.const 0x20)) (local.get $u)
+          (i32.le_u (i32.sub (local.get $u) (i32.const 65)) (i32.const 25))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $units)))
+  (local.get $t))
+
+;; Booleans are stored in variables and lists as text.
+(func $kcc_primitive (param $v i64) (result i64)
+  (if (result i64) (call $kcc_is_boolean (local.get $v))
+    (then (call $kcc_str (call $kcc_to_string (local.get $v))))
+    (else (local.get $v))))
+
+;; Whether a value is text made of nothing but whitespace.
+(func $kcc_is_whitespace (param $v i64) (result i32)
+  (local $s i32)
+  (local $i i32)
+  (if (i32.eqz (call $kcc_is_string (local.get $v))) (then (return (i32.const 0))))
+  (local.set $s (i32.wrap_i64 (local.get $v)))
+  (block $done
+    (loop $units
+      (br_if $done (i32.ge_u (local.get $i) (call $kcc_length_of (local.get $s))))
+      (if (i32.eqz (call $kcc_is_space (call $kcc_unit (local.get $s) (local.get $i))))
+        (then (return (i32.const 0))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $units)))
+  (i32.const 1))
+
+;; Compares values as numbers when both are, and as text ignoring case otherwise.
+(func $kcc_compare (param $a i64) (param $b i64) (result i32)
+  (local $n1 f64)
+  (local $n2 f64)
+  (local.set $n1 (call $kcc_js_number (local.get $a)))
+  (local.set $n2 (call $kcc_js_number (local.get $b)))
+  (if (i32.and (f64.eq (local.get $n1) (f64.const 0)) (call $kcc_is_whitespace (local.get $a)))
+    (then (local.set $n1 (f64.const nan)))
+    (else
+      (if (i32.and (f64.eq (local.get $n2) (f64.const 0)) (call $kcc_is_whitespace (local.get $b)))
+        (then (local.set $n2 (f64.const nan))))))
+  (if (i32.or (f64.ne (local.get $n1) (local.get $n1)) (f64.ne (local.get $n2) (local.get $n2)))
+    (then
+      (return
+        (call $kcc_compare_text (call $kcc_to_string (local.get $a)) (call $kcc_to_string (local.get $b))))))
+  (i32.sub (f64.gt (local.get $n1) (local.get $n2)) (f64.lt (local.get $n1) (local.get $n2))))
+
+;; ---- Operators ----
+
+(func $kcc_add (param $a i64) (param $b i64) (result i64)
+  (call $kcc_num (f64.add (call $kcc_number (local.get $a)) (call $kcc_number (local.get $b)))))
+
+(func $kcc_subtract (param $a i64) (param $b i64) (result i64)
+  (call $kcc_num (f64.sub (call $kcc_number (local.get $a)) (call $kcc_number (local.get $b)))))
+
+(func $kcc_multiply (param $a i64) (param $b i64) (result i64)
+  (call $kcc_num (f64.mul (call $kcc_number (local.get $a)) (call $kcc_number (local.get $b)))))
+
+(func $kcc_divide (param $a i64) (param $b i64) (result i64)
+  (call $kcc_num (f64.div (call $kcc_number (local.get $a)) (call $kcc_number (local.get $b)))))
+
+(func $kcc_gt (param $a i64) (param $b i64) (result i64)
+  (call $kcc_bool (i32.gt_s (call $kcc_compare (local.get $a) (local.get $b)) (i32.const 0))))
+
+(func $kcc_lt (param $a i64) (param $b i64) (result i64)
+  (call $kcc_bool (i32.lt_s (call $kcc_compare (local.get $a) (local.get $b)) (i32.const 0))))
+
+(func $kcc_equals (param $a i64) (param $b i64) (result i64)
+  (call $kcc_bool (i32.eqz (call $kcc_compare (local.get $a) (local.get $b)))))
+
+(func $kcc_and (param $a i64) (param $b i64) (result i64)
+  (call $kcc_bool (i32.and (call $kcc_boolean (local.get $a)) (call $kcc_boolean (local.get $b)))))
+
+(func $kcc_or (param $a i64) (param $b i64) (result i64)
+  (call $kcc_bool (i32.or (call $kcc_boolean (local.get $a)) (call $kcc_boolean (local.get $b)))))
+
+(func $kcc_not (param $a i64) (result i64)
+  (call $kcc_bool (i32.eqz (call $kcc_boolean (local.get $a)))))
+
+(func $kcc_join (param $a i64) (param $b i64) (result i64)
+  (call $kcc_str (call $kcc_concat (call $kcc_to_string (local.get $a)) (call $kcc_to_string (local.get $b)))))
+
+(func $kcc_letter_of (param $letter i64) (param $text i64) (result i64)
+  (local $index f64)
+  (local $s i32)
+  (local $u i32)
+  (local $t i32)
+  (local.set $index (f64.sub (call $kcc_number (local.get $letter)) (f64.const 1)))
+  (local.set $s (call $kcc_to_string (local.get $text)))
+  (if (i32.or (f64.lt (local.get $index) (f64.const 0))
+        (f64.ge (local.get $index) (f64.convert_i32_u (call $kcc_length_of (local.get $s)))))
+    (then (return (call $kcc_str (global.get $kcc_empty)))))
+  (local.set $u (call $kcc_unit (local.get $s) (i32.trunc_f64_u (local.get $index))))
+  ;; Half of a surrogate pair is not a character on its own.
+  (if (i32.eq (i32.and (local.get $u) (i32.const 0xF800)) (i32.const 0xD800))
+    (then (local.set $u (i32.const 0xFFFD))))
+  (local.set $t (call $kcc_new_string (i32.const 1)))
+  (call $kcc_set_unit (local.get $t) (i32.const 0) (local.get $u))
+  (call $kcc_str (local.get $t)))
+
+(func $kcc_length (param $text i64) (result i64)
+  (call $kcc_num (f64.convert_i32_u (call $kcc_length_of (call $kcc_to_string (local.get $text))))))
+
+(func $kcc_contains (param $a i64) (param $b i64) (result i64)
+  (local $s i32)
+  (local $t i32)
+  (local $i i32)
+  (local $j i32)
+  (local $n i32)
+  (local $m i32)
+  (local.set $s (call $kcc_to_string (local.get $a)))
+  (local.set $t (call $kcc_to_string (local.get $b)))
+  (local.set $n (call $kcc_length_of (local.get $s)))
+  (local.set $m (call $kcc_length_of (local.get $t)))
+  (block $missing
+    (loop $starts
+      (br_if $missing (i32.gt_s (i32.add (local.get $i) (local.get $m)) (local.get $n)))
+      (local.set $j (i32.const 0))
+      (block $mismatch
+        (loop $units
+          (if (i32.ge_u (local.get $j) (local.get $m)) (then (return (call $kcc_bool (i32.const 1)))))
+          (br_if $mismatch
+            (i32.ne
+              (call $kcc_lower (call $kcc_unit (local.get $s) (i32.add (local.get $i) (local.get $j))))
+              (call $kcc_lower (call $kcc_unit (local.get $t) (local.get $j)))))
+          (local.set $j (i32.add (local.get $j) (i32.const 1)))
+          (br $units)))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $starts)))
+  (call $kcc_bool (i32.const 0)))
+
+;; Modulo taking the sign of the divisor.
+(func $kcc_modulo (param $a i64) (param $b i64) (result i64)
+  (local $n f64)
+  (local $m f64)
+  (local $r f64)
+  (local.set $n (call $kcc_number (local.get $a)))
+  (local.set $m (call $kcc_number (local.get $b)))
+  (local.set $r (call $kcc_fmod (local.get $n) (local.get $m)))
+  (if (i32.and (f64.ne (local.get $r) (f64.const 0))
+        (i32.ne (f64.lt (local.get $r) (f64.const 0)) (f64.lt (local.get $m) (f64.const 0))))
+    (then (local.set $r (f64.add (local.get $r) (local.get $m)))))
+  (call $kcc_num (local.get $r)))
+
+;; Rounds half up, like JavaScript's Math.round.
+(func $kcc_round (param $n f64) (result f64)
+  (local $f f64)
+  (local.set $f (f64.floor (local.get $n)))
+  (select (f64.add (local.get $f) (f64.const 1)) (local.get $f)
+    (f64.ge (f64.sub (local.get $n) (local.get $f)) (f64.const 0.5))))
+
+(func $kcc_round_value (param $v i64) (result i64)
+  (call $kcc_num (call $kcc_round (call $kcc_number (local.get $v)))))
+
+(func $kcc_round10 (param $n f64) (result f64)
+  (f64.div (call $kcc_round_away (f64.mul (local.get $n) (f64.const 1e10))) (f64.const 1e10)))
+
+(func $kcc_radians (param $n f64) (result f64)
+  (f64.div (f64.mul (f64.const 0x1.921fb54442d18p1) (local.get $n)) (f64.const 180)))
+
+;; The `of` block. Operators are numbered by the compiler, in the order
+;; abs, floor, ceiling, sqrt, sin, cos, tan, asin, acos, atan, ln, log, e ^, 10 ^.
+(func $kcc_mathop (param $op i32) (param $v i64) (result i64)
+  (local $n f64)
+  (local $angle f64)
+  (local.set $n (call $kcc_number (local.get $v)))
+  (call $kcc_num
+    (block $result (result f64)
+      (block $unknown
+        (block $pow10
+          (block $exp
+            (block $log
+              (block $ln
+                (block $atan
+                  (block $acos
+                    (block $asin
+                      (block $tan
+                        (block $cos
+                          (block $sin
+                            (block $sqrt
+                              (block $ceiling
+                                (block $floor
+                                  (block $abs
+                                    (br_table $abs $floor $ceiling $sqrt $sin $cos $tan $asin $acos $atan
+                                      $ln $log $exp $pow10 $unknown (local.get $op)))
+                                  (br $result (f64.abs (local.get $n))))
+                                (br $result (f64.floor (local.get $n))))
+                              (br $result (f64.ceil (local.get $n))))
+                            (br $result (f64.sqrt (local.get $n))))
+                          (br $result (call $kcc_round10 (call $kcc_sin (call $kcc_radians (local.get $n))))))
+                        (br $result (call $kcc_round10 (call $kcc_cos (call $kcc_radians (local.get $n))))))
+                      (local.set $angle (call $kcc_fmod (local.get $n) (f64.const 360)))
+                      (if (i32.or (f64.eq (local.get $angle) (f64.const 90)) (f64.eq (local.get $angle) (f64.const -270)))
+                        (then (br $result (f64.const inf))))
+                      (if (i32.or (f64.eq (local.get $angle) (f64.const -90)) (f64.eq (local.get $angle) (f64.const 270)))
+                        (then (br $result (f64.const -inf))))
+                      (br $result (call $kcc_round10 (call $kcc_tan (call $kcc_radians (local.get $angle))))))
+                    (br $result (f64.mul (call $kcc_asin (local.get $n)) (f64.const 57.29577951308232))))
+                  (br $result (f64.mul (call $kcc_acos (local.get $n)) (f64.const 57.29577951308232))))
+                (br $result (f64.mul (call $kcc_atan (local.get $n)) (f64.const 57.29577951308232))))
+              (br $result (call $kcc_log (local.get $n))))
+            (br $result (call $kcc_log10 (local.get $n))))
+          (br $result (call $kcc_exp (local.get $n))))
+        (br $result (call $kcc_pow10 (local.get $n))))
+      (f64.const 0))))
+
+;; A pseudo-random number in [0, 1), by xorshift64*.
+(func $kcc_random_unit (result f64)
+  (local $x i64)
+  (local.set $x (global.get $kcc_seed))
+  (local.set $x (i64.xor (local.get $x) (i64.shr_u (local.get $x) (i64.const 12))))
+  (local.set $x (i64.xor (local.get $x) (i64.shl (local.get $x) (i64.const 25))))
+  (local.set $x (i64.xor (local.get $x) (i64.shr_u (local.get $x) (i64.const 27))))
+  (global.set $kcc_seed (local.get $x))
+  (f64.div
+    (f64.convert_i64_u (i64.shr_u (i64.mul (local.get $x) (i64.const 0x2545F4914F6CDD1D)) (i64.const 11)))
+    (f64.const 0x1p53)))
+
+;; Whether `pick random` treats a bound as an integer.
+(func $kcc_is_int (param $v i64) (result i32)
+  (local $n f64)
+  (local $s i32)
+  (local $i i32)
+  (if (call $kcc_is_boolean (local.get $v)) (then (return (i32.const 1))))
+  (if (call $kcc_is_number (local.get $v))
+    (then
+      (local.set $n (f64.reinterpret_i64 (local.get $v)))
+      (return (i32.or (f64.ne (local.get $n) (local.get $n)) (f64.eq (local.get $n) (f64.floor (local.get $n)))))))
+  (local.set $s (i32.wrap_i64 (local.get $v)))
+  (block $done
+    (loop $units
+      (br_if $done (i32.ge_u (local.get $i) (call $kcc_length_of (local.get $s))))
+      (if (i32.eq (call $kcc_unit (local.get $s) (local.get $i)) (i32.const 46)) (then (return (i32.const 0))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $units)))
+  (i32.const 1))
+
+(func $kcc_random (param $from i64) (param $to i64) (result i64)
+  (local $n1 f64)
+  (local $n2 f64)
+  (local $low f64)
+  (local $high f64)
+  (local.set $n1 (call $kcc_number (local.get $from)))
+  (local.set $n2 (call $kcc_number (local.get $to)))
+  (local.set $low (f64.min (local.get $n1) (local.get $n2)))
+  (local.set $high (f64.max (local.get $n1) (local.get $n2)))
+  (if (f64.eq (local.get $low) (local.get $high)) (then (return (call $kcc_num (local.get $low)))))
+  (if (i32.and (call $kcc_is_int (local.get $from)) (call $kcc_is_int (local.get $to)))
+    (then
+      (return
+        (call $kcc_num
+          (f64.add (local.get $low)
+            (f64.floor
+              (f64.mul (call $kcc_random_unit)
+                (f64.add (f64.sub (local.get $high) (local.get $low)) (f64.const 1)))))))))
+  (call $kcc_num
+    (f64.add (local.get $low)
+      (f64.mul (call $kcc_random_unit) (f64.sub (local.get $high) (local.get $low))))))
+
+;; ---- Time ----
+
+(func $kcc_timer (result i64)
+  (call $kcc_num (f64.div (f64.sub (call $host_now) (global.get $kcc_timer_start)) (f64.const 1000))))
+
+(func $kcc_reset_timer
+  (global.set $kcc_timer_start (call $host_now)))
+
+(func $kcc_days_since_2000 (result i64)
+  (call $kcc_num
+    (f64.div (f64.floor (f64.sub (call $host_now) (f64.const 946684800000))) (f64.const 86400000))))
+
+;; ---- Variables ----
+
+(func $kcc_var (param $slot i32) (result i64)
+  (i64.load (call $kcc_slot (global.get $kcc_variables) (local.get $slot))))
+
+(func $kcc_set_var (param $slot i32) (param $v i64)
+  (i64.store (call $kcc_slot (global.get $kcc_variables) (local.get $slot)) (call $kcc_primitive (local.get $v))))
+
+(func $kcc_change_var (param $slot i32) (param $delta i64)
+  (local $d f64)
+  (local.set $d (call $kcc_number (local.get $delta)))
+  (call $kcc_set_var (local.get $slot)
+    (call $kcc_num (f64.add (call $kcc_number (call $kcc_var (local.get $slot))) (local.get $d)))))
+
+;; ---- Lists ----
+;; Every list takes two values of $kcc_lists: its length, and its array of items.
+
+(global $kcc_list_item_limit i32 (i32.const 200000))
+
+(func $kcc_list_length_of (param $list i32) (result i32)
+  (i32.wrap_i64 (i64.load (call $kcc_slot (global.get $kcc_lists) (i32.shl (local.get $list) (i32.const 1))))))
+
+(func $kcc_list_set_length (param $list i32) (param $length i32)
+  (i64.store (call $kcc_slot (global.get $kcc_lists) (i32.shl (local.get $list) (i32.const 1)))
+    (i64.extend_i32_u (local.get $length))))
+
+(func $kcc_list_items (param $list i32) (result i32)
+  (i32.wrap_i64
+    (i64.load
+      (call $kcc_slot (global.get $kcc_lists) (i32.add (i32.shl (local.get $list) (i32.const 1)) (i32.const 1))))))
+
+(func $kcc_list_item_slot (param $list i32) (param $i i32) (result i32)
+  (call $kcc_slot (call $kcc_list_items (local.get $list)) (local.get $i)))
+
+;; Makes room for `length` items.
+(func $kcc_list_reserve (param $list i32) (param $length i32)
+  (local $items i32)
+  (local $capacity i32)
+  (local $grown i32)
+  (local.set $items (call $kcc_list_items (local.get $list)))
+  (if (local.get $items)
+    (then (local.set $capacity (i32.load offset=4 (local.get $items)))))
+  (if (i32.le_u (local.get $length) (local.get $capacity)) (then (return)))
+  (local.set $capacity
+    (select (i32.shl (local.get $capacity) (i32.const 1)) (i32.const 8) (local.get $capacity)))
+  (if (i32.lt_u (local.get $capacity) (local.get $length))
+    (then (local.set $capacity (local.get $length))))
+  (local.set $grown (call $kcc_new_array (local.get $capacity)))
+  (if (local.get $items)
+    (then
+      (memory.copy (i32.add (local.get $grown) (i32.const 8)) (i32.add (local.get $items) (i32.const 8))
+        (i32.shl (call $kcc_list_length_of (local.get $list)) (i32.const 3)))))
+  (i64.store
+    (call $kcc_slot (global.get $kcc_lists) (i32.add (i32.shl (local.get $list) (i32.const 1)) (i32.const 1)))
+    (call $kcc_array_ref (local.get $grown))))
+
+;; Scratch's `Cast.toListIndex`: the 0-based position of a 1-based index, `last` or
+;; `random`/`any`, -2 for `all` if `accept_all` is set, or -1 if there is none.
+(func $kcc_to_list_index (param $index i64) (param $length i32) (param $accept_all i32) (result i32)
+  (local $s i32)
+  (local $n f64)
+  (if (call $kcc_is_string (local.get $index))
+    (then
+      (local.set $s (i32.wrap_i64 (local.get $index)))
+      (if (call $kcc_equals_ascii (local.get $s) (global.get $kcc_all) (i32.const 3))
+        (then (return (select (i32.const -2) (i32.const -1) (local.get $accept_all)))))
+      (if (call $kcc_equals_ascii (local.get $s) (global.get $kcc_last) (i32.const 4))
+        (then (return (i32.sub (local.get $length) (i32.const 1)))))
+      (if (i32.or
+            (call $kcc_equals_ascii (local.get $s) (global.get $kcc_random_keyword) (i32.const 6))
+            (call $kcc_equals_ascii (local.get $s) (global.get $kcc_any) (i32.const 3)))
+        (then
+          (if (i32.eqz (local.get $length)) (then (return (i32.const -1))))
+          (return
+            (i32.trunc_f64_u (f64.mul (call $kcc_random_unit) (f64.convert_i32_u (local.get $length)))))))))
+  (local.set $n (f64.floor (call $kcc_number (local.get $index))))
+  (if (i32.or (f64.lt (local.get $n) (f64.const 1)) (f64.gt (local.get $n) (f64.convert_i32_u (local.get $length))))
+    (then (return (i32.const -1))))
+  (i32.sub (i32.trunc_f64_u (local.get $n)) (i32.const 1)))
+
+;; Whether a string is one character, a surrogate pair counting as one.
+(func $kcc_is_character (param $s i32) (result i32)
+  (local $length i32)
+  (local.set $length (call $kcc_length_of (local.get $s)))
+  (if (i32.eq (local.get $length) (i32.const 1)) (then (return (i32.const 1))))
+  (i32.and (i32.eq (local.get $length) (i32.const 2))
+    (i32.and
+      (i32.eq (i32.and (call $kcc_unit (local.get $s) (i32.const 0)) (i32.const 0xFC00)) (i32.const 0xD800))
+      (i32.eq (i32.and (call $kcc_unit (local.get $s) (i32.const 1)) (i32.const 0xFC00)) (i32.const 0xDC00)))))
+
+;; The contents of a list as shown by its reporter: items are joined by spaces,
+;; unless every item is a single character.
+(func $kcc_list_contents (param $list i32) (result i64)
+  (local $n i32)
+  (local $i i32)
+  (local $item i32)
+  (local $total i32)
+  (local $single i32)
+  (local $s i32)
+  (local $at i32)
+  (local $length i32)
+  (local.set $n (call $kcc_list_length_of (local.get $list)))
+  (local.set $single (i32.const 1))
+  (block $done
+    (loop $items
+      (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+      (local.set $item (call $kcc_to_string (i64.load (call $kcc_list_item_slot (local.get $list) (local.get $i)))))
+      (local.set $total (i32.add (local.get $total) (call $kcc_length_of (local.get $item))))
+      (local.set $single (i32.and (local.get $single) (call $kcc_is_character (local.get $item))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $items)))
+  (if (i32.and (i32.eqz (local.get $single)) (i32.gt_u (local.get $n) (i32.const 0)))
+    (then (local.set $total (i32.add (local.get $total) (i32.sub (local.get $n) (i32.const 1))))))
+  (local.set $s (call $kcc_new_string (local.get $total)))
+  (local.set $i (i32.const 0))
+  (block $done
+    (loop $items
+      (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+      (if (i32.and (i32.eqz (local.get $single)) (i32.gt_u (local.get $i) (i32.const 0)))
+        (then
+          (call $kcc_set_unit (local.get $s) (local.get $at) (i32.const 32))
+          (local.set $at (i32.add (local.get $at) (i32.const 1)))))
+      (local.set $item (call $kcc_to_string (i64.load (call $kcc_list_item_slot (local.get $list) (local.get $i)))))
+      (local.set $length (call $kcc_length_of (local.get $item)))
+      (memory.copy
+        (i32.add (i32.add (local.get $s) (i32.const 8)) (i32.shl (local.get $at) (i32.const 1)))
+        (i32.add (local.get $item) (i32.const 8))
+        (i32.shl (local.get $length) (i32.const 1)))
+      (local.set $at (i32.add (local.get $at) (local.get $length)))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $items)))
+  (call $kcc_str (local.get $s)))
+
+(func $kcc_list_add (param $list i32) (param $item i64)
+  (local $length i32)
+  (local.set $length (call $kcc_list_length_of (local.get $list)))
+  (if (i32.ge_u (local.get $length) (global.get $kcc_list_item_limit)) (then (return)))
+  (call $kcc_list_reserve (local.get $list) (i32.add (local.get $length) (i32.const 1)))
+  (i64.store (call $kcc_list_item_slot (local.get $list) (local.get $length)) (call $kcc_primitive (local.get $item)))
+  (call $kcc_list_set_length (local.get $list) (i32.add (local.get $length) (i32.const 1))))
+
+(func $kcc_list_delete (param $list i32) (param $index i64)
+  (local $length i32)
+  (local $i i32)
+  (local.set $length (call $kcc_list_length_of (local.get $list)))
+  (local.set $i (call $kcc_to_list_index (local.get $index) (local.get $length) (i32.const 1)))
+  (if (i32.eq (local.get $i) (i32.const -2))
+    (then (call $kcc_list_set_length (local.get $list) (i32.const 0))))
+  (if (i32.ge_s (local.get $i) (i32.const 0))
+    (then
+      (memory.copy
+        (call $kcc_list_item_slot (local.get $list) (local.get $i))
+        (call $kcc_list_item_slot (local.get $list) (i32.add (local.get $i) (i32.const 1)))
+        (i32.shl (i32.sub (i32.sub (local.get $length) (local.get $i)) (i32.const 1)) (i32.const 3)))
+      (call $kcc_list_set_length (local.get $list) (i32.sub (local.get $length) (i32.const 1))))))
+
+(func $kcc_list_clear (param $list i32)
+  (call $kcc_list_set_length (local.get $list) (i32.const 0)))
+
+(func $kcc_list_insert (param $list i32) (param $item i64) (param $index i64)
+  (local $length i32)
+  (local $i i32)
+  (local.set $item (call $kcc_primitive (local.get $item)))
+  (local.set $length (call $kcc_list_length_of (local.get $list)))
+  ;; Inserting one past the end appends.
+  (local.set $i
+    (call $kcc_to_list_index (local.get $index) (i32.add (local.get $length) (i32.const 1)) (i32.const 0)))
+  (if (i32.or (i32.lt_s (local.get $i) (i32.const 0)) (i32.ge_s (local.get $i) (global.get $kcc_list_item_limit)))
+    (then (return)))
+  (call $kcc_list_reserve (local.get $list) (i32.add (local.get $length) (i32.const 1)))
+  (memory.copy
+    (call $kcc_list_item_slot (local.get $list) (i32.add (local.get $i) (i32.const 1)))
+    (call $kcc_list_item_slot (local.get $list) (local.get $i))
+    (i32.shl (i32.sub (local.get $length) (local.get $i)) (i32.const 3)))
+  (i64.store (call $kcc_list_item_slot (local.get $list) (local.get $i)) (local.get $item))
+  (local.set $length (i32.add (local.get $length) (i32.const 1)))
+  (if (i32.gt_u (local.get $length) (global.get $kcc_list_item_limit))
+    (then (local.set $length (global.get $kcc_list_item_limit))))
+  (call $kcc_list_set_length (local.get $list) (local.get $length)))
+
+(func $kcc_list_replace (param $list i32) (param $item i64) (param $index i64)
+  (local $i i32)
+  (local.set $item (call $kcc_primitive (local.get $item)))
+  (local.set $i
+    (call $kcc_to_list_index (local.get $index) (call $kcc_list_length_of (local.get $list)) (i32.const 0)))
+  (if (i32.ge_s (local.get $i) (i32.const 0))
+    (then (i64.store (call $kcc_list_item_slot (local.get $list) (local.get $i)) (local.get $item)))))
+
+(func $kcc_list_item (param $list i32) (param $index i64) (result i64)
+  (local $i i32)
+  (local.set $i
+    (call $kcc_to_list_index (local.get $index) (call $kcc_list_length_of (local.get $list)) (i32.const 0)))
+  (if (result i64) (i32.ge_s (local.get $i) (i32.const 0))
+    (then (i64.load (call $kcc_list_item_slot (local.get $list) (local.get $i))))
+    (else (call $kcc_str (global.get $kcc_empty)))))
+
+;; The 1-based position of the first item equal to `item`, or 0.
+(func $kcc_list_index_of (param $list i32) (param $item i64) (result i64)
+  (local $i i32)
+  (local $n i32)
+  (local.set $n (call $kcc_list_length_of (local.get $list)))
+  (block $done
+    (loop $items
+      (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+      (if (i32.eqz
+            (call $kcc_compare (i64.load (call $kcc_list_item_slot (local.get $list) (local.get $i))) (local.get $item)))
+        (then (return (call $kcc_num (f64.convert_i32_u (i32.add (local.get $i) (i32.const 1)))))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $items)))
+  (call $kcc_num (f64.const 0)))
+
+(func $kcc_list_length (param $list i32) (result i64)
+  (call $kcc_num (f64.convert_i32_u (call $kcc_list_length_of (local.get $list)))))
+
+(func $kcc_list_contains (param $list i32) (param $item i64) (result i64)
+  (call $kcc_bool
+    (f64.ne (f64.reinterpret_i64 (call $kcc_list_index_of (local.get $list) (local.get $item))) (f64.const 0))))
+
+;; ---- Looks and sensing ----
+
+(func $kcc_say (param $message i64)
+  (local $text i32)
+  (local.set $text (call $kcc_utf8 (call $kcc_to_string (local.get $message))))
+  (call $host_say (local.get $text) (global.get $kcc_utf8_length)))
+
+(func $kcc_think (param $message i64)
+  (local $text i32)
+  (local.set $text (call $kcc_utf8 (call $kcc_to_string (local.get $message))))
+  (call $host_think (local.get $text) (global.get $kcc_utf8_length)))
+
+;; Answers longer than this are cut short.
+(global $kcc_answer_capacity i32 (i32.const 65536))
+
+(func $kcc_ask (param $question i64)
+  (local $text i32)
+  (local $buffer i32)
+  (local $length i32)
+  (local.set $text (call $kcc_utf8 (call $kcc_to_string (local.get $question))))
+  (local.set $buffer (call $kcc_alloc (global.get $kcc_answer_capacity)))
+  (local.set $length
+    (call $host_ask (local.get $text) (global.get $kcc_utf8_length) (local.get $buffer)
+      (global.get $kcc_answer_capacity)))
+  (if (i32.gt_u (local.get $length) (global.get $kcc_answer_capacity))
+    (then (local.set $length (global.get $kcc_answer_capacity))))
+  (global.set $kcc_answer (call $kcc_str (call $kcc_from_utf8 (local.get $buffer) (local.get $length)))))
+
+(func $kcc_get_answer (result i64)
+  (global.get $kcc_answer))
+
+;; ---- Threads ----
+;; Every script has one thread, of four values of $kcc_threads: whether it is running,
+;; the array of its frames, the index of its top frame, and when it wants to run again.
+;; Frames hold the script they run, the state to resume it in, the index of the frame
+;; that called it, then the locals of the script, arguments first.
+
+(func $kcc_thread_slot (param $thread i32) (param $field i32) (result i32)
+  (call $kcc_slot (global.get $kcc_threads) (i32.add (i32.shl (local.get $thread) (i32.const 2)) (local.get $field))))
+
+(func $kcc_running (param $thread i32) (result i32)
+  (i32.wrap_i64 (i64.load (call $kcc_thread_slot (local.get $thread) (i32.const 0)))))
+
+(func $kcc_wake_time (param $thread i32) (result f64)
+  (f64.load (call $kcc_thread_slot (local.get $thread) (i32.const 3))))
+
+(func $kcc_frame_size (param $script i32) (result i32)
+  (i32.add (i32.load (i32.add (global.get $kcc_frame_sizes) (i32.shl (local.get $script) (i32.const 2))))
+    (i32.const 3)))
+
+(func $kcc_frame_value (param $fp i32) (param $i i32) (result i32)
+  (call $kcc_slot (global.get $kcc_stack) (i32.add (local.get $fp) (local.get $i))))
+
+;; Local `i` of the frame at `fp`.
+(func $kcc_get (param $fp i32) (param $i i32) (result i64)
+  (i64.load (call $kcc_frame_value (local.get $fp) (i32.add (local.get $i) (i32.const 3)))))
+
+(func $kcc_set (param $fp i32) (param $i i32) (param $v i64)
+  (i64.store (call $kcc_frame_value (local.get $fp) (i32.add (local.get $i) (i32.const 3))) (local.get $v)))
+
+(func $kcc_get_number (param $fp i32) (param $i i32) (result f64)
+  (f64.reinterpret_i64 (call $kcc_get (local.get $fp) (local.get $i))))
+
+(func $kcc_set_number (param $fp i32) (param $i i32) (param $n f64)
+  (call $kcc_set (local.get $fp) (local.get $i) (call $kcc_num (local.get $n))))
+
+;; The state to resume the frame at `fp` in.
+(func $kcc_state (param $fp i32) (result i32)
+  (i32.wrap_i64 (i64.load (call $kcc_frame_value (local.get $fp) (i32.const 1)))))
+
+;; Saves the state to resume in and returns `status` for the scheduler:
+;; 1 when the script yields, 2 when it has called a custom block.
+(func $kcc_suspend (param $fp i32) (param $state i32) (param $status i32) (result i32)
+  (i64.store (call $kcc_frame_value (local.get $fp) (i32.const 1)) (i64.extend_i32_u (local.get $state)))
+  (local.get $status))
+
+(func $kcc_start_thread (param $thread i32)
+  (local $frames i32)
+  (local $size i32)
+  ;; A thread restarting itself restarts once its turn is over.
+  (if (i32.eq (local.get $thread) (global.get $kcc_thread))
+    (then
+      (global.set $kcc_restart (i32.const 1))
+      (return)))
+  (local.set $size (call $kcc_frame_size (local.get $thread)))
+  (local.set $frames (i32.wrap_i64 (i64.load (call $kcc_thread_slot (local.get $thread) (i32.const 1)))))
+  (if (i32.or (i32.eqz (local.get $frames)) (i32.lt_u (i32.load offset=4 (local.get $frames)) (local.get $size)))
+    (then
+      (local.set $frames
+        (call $kcc_new_array
+          (select (local.get $size) (i32.const 16) (i32.gt_u (local.get $size) (i32.const 16)))))
+      (i64.store (call $kcc_thread_slot (local.get $thread) (i32.const 1)) (call $kcc_array_ref (local.get $frames)))))
+  (i64.store (call $kcc_slot (local.get $frames) (i32.const 0)) (i64.extend_i32_u (local.get $thread)))
+  (i64.store (call $kcc_slot (local.get $frames) (i32.const 1)) (i64.const 0))
+  (i64.store (call $kcc_slot (local.get $frames) (i32.const 2)) (i64.const 0))
+  (i64.store (call $kcc_thread_slot (local.get $thread) (i32.const 0)) (i64.const 1))
+  (i64.store (call $kcc_thread_slot (local.get $thread) (i32.const 2)) (i64.const 0))
+  (i64.store (call $kcc_thread_slot (local.get $thread) (i32.const 3)) (i64.const 0)))
+
+;; Pushes a frame running `script` on the running thread, returning where it starts.
+(func $kcc_push (param $script i32) (result i32)
+  (local $top i32)
+  (local $fp i32)
+  (local $need i32)
+  (local $frames i32)
+  (local $capacity i32)
+  (local.set $top
+    (i32.wrap_i64 (i64.load (call $kcc_thread_slot (global.get $kcc_thread) (i32.const 2)))))
+  (local.set $fp
+    (i32.add (local.get $top)
+      (call $kcc_frame_size (i32.wrap_i64 (i64.load (call $kcc_frame_value (local.get $top) (i32.const 0)))))))
+  (local.set $need (i32.add (local.get $fp) (call $kcc_frame_size (local.get $script))))
+  (local.set $capacity (i32.load offset=4 (global.get $kcc_stack)))
+  (if (i32.gt_u (local.get $need) (local.get $capacity))
+    (then
+      (local.set $capacity (i32.shl (local.get $capacity) (i32.const 1)))
+      (local.set $frames
+        (call $kcc_new_array
+          (select (local.get $need) (local.get $capacity) (i32.gt_u (local.get $need) (local.get $capacity)))))
+      (memory.copy (i32.add (local.get $frames) (i32.const 8)) (i32.add (global.get $kcc_stack) (i32.const 8))
+        (i32.shl (local.get $fp) (i32.const 3)))
+      (global.set $kcc_stack (local.get $frames))
+      (i64.store (call $kcc_thread_slot (global.get $kcc_thread) (i32.const 1))
+        (call $kcc_array_ref (local.get $frames)))))
+  (i64.store (call $kcc_frame_value (local.get $fp) (i32.const 0)) (i64.extend_i32_u (local.get $script)))
+  (i64.store (call $kcc_frame_value (local.get $fp) (i32.const 1)) (i64.const 0))
+  (i64.store (call $kcc_frame_value (local.get $fp) (i32.const 2)) (i64.extend_i32_u (local.get $top)))
+  (i64.store (call $kcc_thread_slot (global.get $kcc_thread) (i32.const 2)) (i64.extend_i32_u (local.get $fp)))
+  (local.get $fp))
+
+;; Runs a thread until it yields or finishes.
+(func $kcc_step (param $thread i32)
+  (local $top i32)
+  (local $status i32)
+  (global.set $kcc_thread (local.get $thread))
+  (global.set $kcc_wake (f64.const 0))
+  (block $done
+    (loop $frames
+      (global.set $kcc_stack
+        (i32.wrap_i64 (i64.load (call $kcc_thread_slot (local.get $thread) (i32.const 1)))))
+      (local.set $top
+        (i32.wrap_i64 (i64.load (call $kcc_thread_slot (local.get $thread) (i32.const 2)))))
+      (local.set $status
+        (call_indirect $kcc_scripts (type $kcc_script)
+          (local.get $top)
+          (i32.wrap_i64 (i64.load (call $kcc_frame_value (local.get $top) (i32.const 0))))))
+      (br_if $done (i32.or (global.get $kcc_stopped) (global.get $kcc_restart)))
+      ;; Runs the custom block called.
+      (br_if $frames (i32.eq (local.get $status) (i32.const 2)))
+      (if (i32.eqz (local.get $status))
+        (then
+          (if (i32.eqz (local.get $top))
+            (then
+              (i64.store (call $kcc_thread_slot (local.get $thread) (i32.const 0)) (i64.const 0))
+              (br $done)))
+          ;; Returns to the caller.
+          (i64.store (call $kcc_thread_slot (local.get $thread) (i32.const 2))
+            (i64.load (call $kcc_frame_value (local.get $top) (i32.const 2))))
+          (br $frames)))
+      (f64.store (call $kcc_thread_slot (local.get $thread) (i32.const 3)) (global.get $kcc_wake))))
+  (global.set $kcc_thread (i32.const -1))
+  (if (global.get $kcc_restart)
+    (then
+      (global.set $kcc_restart (i32.const 0))
+      (call $kcc_start_thread (local.get $thread)))))
+
+;; When a wait of `secs` seconds ends, in milliseconds like $host_now.
+(func $kcc_deadline (param $secs i64) (result i64)
+  (call $kcc_num
+    (f64.add (call $host_now) (f64.mul (call $kcc_number (local.get $secs)) (f64.const 1000)))))
+
+;; Like $kcc_deadline, but drops what is shorter than a millisecond.
+(func $kcc_deadline_millis (param $secs i64) (result i64)
+  (call $kcc_num
+    (f64.add (call $host_now) (f64.trunc (f64.mul (call $kcc_number (local.get $secs)) (f64.const 1000))))))
+
+;; Whether `deadline` is still ahead, in which case the script should yield until then.
+(func $kcc_waiting (param $deadline i64) (result i32)
+  (if (result i32) (f64.lt (call $host_now) (call $kcc_number (local.get $deadline)))
+    (then
+      (global.set $kcc_wake (call $kcc_number (local.get $deadline)))
+      (i32.const 1))
+    (else (i32.const 0))))
+
+;; Calls `f` with every script receiving broadcast `name`, and returns whether
+;; one of them is running.
+(func $kcc_receivers (param $name i64) (param $start i32) (result i32)
+  (local $lowercase i32)
+  (local $i i32)
+  (local $entry i32)
+  (local $running i32)
+  (local.set $lowercase (call $kcc_lowercase (call $kcc_to_string (local.get $name))))
+  (block $done
+    (loop $receivers
+      (br_if $done (i32.ge_u (local.get $i) (global.get $kcc_receiver_count)))
+      (local.set $entry (i32.add (global.get $kcc_receivers) (i32.shl (local.get $i) (i32.const 3))))
+      (if (call $kcc_text_equals (local.get $lowercase) (i32.load (local.get $entry)))
+        (then
+          (if (local.get $start)
+            (then (call $kcc_start_thread (i32.load offset=4 (local.get $entry)))))
+          (local.set $running
+            (i32.or (local.get $running) (call $kcc_running (i32.load offset=4 (local.get $entry)))))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $receivers)))
+  (local.get $running))
+
+;; Starts the scripts receiving a broadcast, restarting those already running.
+(func $kcc_broadcast (param $name i64)
+  (drop (call $kcc_receivers (local.get $name) (i32.const 1))))
+
+;; Whether scripts receiving a broadcast are still running.
+(func $kcc_receiving (param $name i64) (result i32)
+  (call $kcc_receivers (local.get $name) (i32.const 0)))
+
+(func $kcc_stop_all
+  (global.set $kcc_stopped (i32.const 1)))
+
+;; ---- Entry points ----
+
+;; Sets variables and lists up and clicks the green flag.
+(func (export "start")
+  (local $i i32)
+  (global.set $kcc_top (global.get $kcc_heap_start))
+  (global.set $kcc_collect_at (i32.add (global.get $kcc_heap_start) (i32.const 0x400000)))
+  (global.set $kcc_stopped (i32.const 0))
+  (global.set $kcc_variables (call $kcc_new_array (global.get $kcc_variable_count)))
+  (global.set $kcc_lists (call $kcc_new_array (i32.shl (global.get $kcc_list_count) (i32.const 1))))
+  (global.set $kcc_threads (call $kcc_new_array (i32.shl (global.get $kcc_script_count) (i32.const 2))))
+  (global.set $kcc_answer (call $kcc_str (global.get $kcc_empty)))
+  (global.set $kcc_timer_start (call $host_now))
+  (global.set $kcc_seed (i64.or (i64.reinterpret_f64 (global.get $kcc_timer_start)) (i64.const 1)))
+  (call $kcc_setup)
+  (block $done
+    (loop $scripts
+      (br_if $done (i32.ge_u (local.get $i) (global.get $kcc_green_flag_count)))
+      (call $kcc_start_thread (i32.load (i32.add (global.get $kcc_green_flag) (i32.shl (local.get $i) (i32.const 2)))))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br $scripts))))
+
+;; Runs every script that is not waiting, in turns, for up to 10 ms. Returns how
+;; many milliseconds to wait before calling it again, or -1 once every script has finished.
+(func (export "tick") (result f64)
+  (local $began f64)
+  (local $now f64)
+  (local $next f64)
+  (local $thread i32)
+  (local $running i32)
+  (local $busy i32)
+  (local.set $began (call $host_now))
+  (loop $turn
+    (if (global.get $kcc_stopped) (then (return (f64.const -1))))
+    (if (i32.gt_u (global.get $kcc_top) (global.get $kcc_collect_at)) (then (call $kcc_collect)))
+    (local.set $now (call $host_now))
+    (local.set $thread (i32.const 0))
+    (block $done
+      (loop $threads
+        (br_if $done (i32.ge_u (local.get $thread) (global.get $kcc_script_count)))
+        (if (i32.and (call $kcc_running (local.get $thread))
+              (f64.le (call $kcc_wake_time (local.get $thread)) (local.get $now)))
+          (then
+            (call $kcc_step (local.get $thread))
+            (if (global.get $kcc_stopped) (then (return (f64.const -1))))))
+        (local.set $thread (i32.add (local.get $thread) (i32.const 1)))
+        (br $threads)))
+    (local.set $now (call $host_now))
+    (local.set $next (f64.const inf))
+    (local.set $running (i32.const 0))
+    (local.set $busy (i32.const 0))
+    (local.set $thread (i32.const 0))
+    (block $done
+      (loop $threads
+        (br_if $done (i32.ge_u (local.get $thread) (global.get $kcc_script_count)))
+        (if (call $kcc_running (local.get $thread))
+          (then
+            (local.set $running (i32.const 1))
+            (if (f64.le (call $kcc_wake_time (local.get $thread)) (local.get $now))
+              (then (local.set $busy (i32.const 1)))
+              (else (local.set $next (f64.min (local.get $next) (call $kcc_wake_time (local.get $thread))))))))
+        (local.set $thread (i32.add (local.get $thread) (i32.const 1)))
+        (br $threads)))
+    (if (i32.eqz (local.get $running)) (then (return (f64.const -1))))
+    (if (local.get $busy)
+      (then
+        (br_if $turn (f64.lt (f64.sub (local.get $now) (local.get $began)) (f64.const 10)))
+        (return (f64.const 0)))))
+  (f64.sub (local.get $next) (local.get $now)))
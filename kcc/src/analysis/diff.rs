@@ -0,0 +1,409 @@
+//! Compares two versions of a project by what their scripts do rather than by
+//! block IDs and positions, which the editor changes all the time.
+
+use std::{collections::HashMap, fmt, fmt::Write};
+
+use scratch_ast::{
+    cast,
+    model::{self, Block, BlockType, Mutation, RichValue, ShadowValue, ValuePointer},
+};
+use serde::Serialize;
+
+use super::scripts::{self, Target};
+use crate::linediff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Item {
+    Sprite,
+    Script,
+    Variable,
+    List,
+    Costume,
+    Sound,
+}
+
+/// Something one version has and the other does not, or has differently.
+#[derive(Clone, Debug, Serialize)]
+pub struct Change {
+    pub kind: Kind,
+    pub item: Item,
+    /// The sprite, or the stage.
+    pub target: String,
+    /// Scripts are named after their hat.
+    pub name: String,
+    /// The blocks of a script, prefixed with `-` and `+` where they changed,
+    /// or what changed about anything else.
+    pub details: Vec<String>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.kind {
+            Kind::Added => '+',
+            Kind::Removed => '-',
+            Kind::Changed => '~',
+        };
+        let item = serde_json::to_value(self.item).map_err(|_| fmt::Error)?;
+        let item = item.as_str().unwrap_or_default();
+        match self.item {
+            Item::Sprite => write!(f, "{sign} sprite {}", self.name)?,
+            _ => write!(f, "{sign} {}: {item} {}", self.target, self.name)?,
+        }
+        for line in self.details.iter() {
+            write!(f, "\n    {line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A script as lines of text, one per block, with reporters inside the line of
+/// their block and the inside of C blocks indented.
+struct Script {
+    name: String,
+    lines: Vec<String>,
+}
+
+impl Script {
+    fn text(&self) -> String {
+        self.lines.iter().map(|l| format!("{l}\n")).collect()
+    }
+
+    /// How alike two scripts are, from 0 to 1.
+    fn similarity(&self, other: &Script) -> f64 {
+        let diff = linediff::diff(&self.text(), &other.text());
+        let shared = diff.lines().filter(|l| l.starts_with(' ')).count();
+        2.0 * shared as f64 / (self.lines.len() + other.lines.len()).max(1) as f64
+    }
+}
+
+fn literal(value: &RichValue) -> String {
+    match value {
+        RichValue::Boolean(b) => b.to_string(),
+        RichValue::Number(n) | RichValue::PositiveNumber(n) | RichValue::Angle(n) => {
+            cast::number_to_string(*n)
+        }
+        RichValue::Integer(i) => i.to_string(),
+        RichValue::PositiveInteger(i) => i.to_string(),
+        RichValue::Color(c) => c.clone(),
+        RichValue::Broadcast(s) | RichValue::String(s) => format!("{s:?}"),
+    }
+}
+
+/// A block without its ID, with its fields and the values of its inputs.
+fn line(target: &Target, block: &Block) -> String {
+    let mut text = scripts::opcode(block.block_type);
+    if let Some(proccode) = scripts::proccode(block) {
+        write!(text, " {proccode:?}").unwrap();
+    }
+    let mut fields = block.fields.iter().collect::<Vec<_>>();
+    fields.sort_by_key(|(name, _)| *name);
+    for (name, field) in fields {
+        write!(text, " {name}={:?}", field.value).unwrap();
+    }
+    // Arguments are named by IDs as random as block IDs, so number them instead.
+    let arguments = match &block.mutation {
+        Some(Mutation::ProcedureCall(call)) => call.arguments_ids.clone(),
+        _ => Vec::new(),
+    };
+    let mut inputs = block
+        .inputs
+        .iter()
+        .filter(|(name, _)| !matches!(name.as_str(), "SUBSTACK" | "SUBSTACK2" | "custom_block"))
+        .map(
+            |(name, input)| match arguments.iter().position(|a| a == name) {
+                Some(index) => (format!("#{}", index + 1), name, input),
+                None => (name.clone(), name, input),
+            },
+        )
+        .collect::<Vec<_>>();
+    inputs.sort_by(|a, b| a.0.cmp(&b.0));
+    for (shown, name, input) in inputs {
+        let value = match &input.value {
+            Some(ShadowValue::Bare(value)) => literal(value),
+            Some(ShadowValue::Pointer(ValuePointer::Variable { name, .. })) => {
+                format!("(variable {name:?})")
+            }
+            Some(ShadowValue::Pointer(ValuePointer::List { name, .. })) => {
+                format!("(list {name:?})")
+            }
+            Some(ShadowValue::Block(_)) => match target.input(block, name) {
+                // Menus read as the option they show.
+                Some(menu) if menu.shadow && menu.inputs.is_empty() && menu.fields.len() == 1 => {
+                    let field = menu.fields.values().next().unwrap();
+                    format!("{:?}", field.value)
+                }
+                Some(reporter) => format!("({})", line(target, reporter)),
+                None => continue,
+            },
+            None => continue,
+        };
+        write!(text, " {shown}={value}").unwrap();
+    }
+    text
+}
+
+fn stack(target: &Target, first: Option<&Block>, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    for block in target.stack(first) {
+        lines.push(format!("{indent}{}", line(target, block)));
+        stack(target, target.input(block, "SUBSTACK"), depth + 1, lines);
+        if block.block_type == BlockType::ControlIfElse {
+            lines.push(format!("{indent}else"));
+            stack(target, target.input(block, "SUBSTACK2"), depth + 1, lines);
+        }
+    }
+}
+
+/// The scripts of a target, and the blocks lying around without a hat.
+fn target_scripts(target: &Target) -> Vec<Script> {
+    target
+        .sorted()
+        .into_iter()
+        .filter(|b| b.top_level && !b.shadow)
+        .map(|top| {
+            let mut lines = Vec::new();
+            if scripts::is_hat(top.block_type) {
+                lines.push(target.hat_label(top));
+                let next = top.next_id.as_ref().and_then(|id| target.blocks.get(id));
+                stack(target, next, 1, &mut lines);
+            } else {
+                stack(target, Some(top), 0, &mut lines);
+            }
+            Script {
+                name: lines[0].clone(),
+                lines,
+            }
+        })
+        .collect()
+}
+
+/// Pairs the scripts of two versions of a target: the same ones first, then
+/// those with the same hat whose blocks are most alike. Returns the pairs that
+/// differ, and the scripts left over on either side.
+fn pair(old: Vec<Script>, new: Vec<Script>) -> (Vec<(Script, Script)>, Vec<Script>, Vec<Script>) {
+    let mut old = old.into_iter().map(Some).collect::<Vec<_>>();
+    let mut new = new.into_iter().map(Some).collect::<Vec<_>>();
+    for a in old.iter_mut() {
+        let same = new
+            .iter()
+            .position(|b| matches!((&*a, b), (Some(a), Some(b)) if a.lines == b.lines));
+        if let Some(index) = same {
+            *a = None;
+            new[index] = None;
+        }
+    }
+    let count = |scripts: &[Option<Script>], name: &str| {
+        scripts.iter().flatten().filter(|s| s.name == name).count()
+    };
+    let mut candidates = Vec::new();
+    for (i, a) in old.iter().enumerate() {
+        for (j, b) in new.iter().enumerate() {
+            if let (Some(a), Some(b)) = (a, b) {
+                if a.name == b.name {
+                    let alone = count(&old, &a.name) == 1 && count(&new, &b.name) == 1;
+                    candidates.push((a.similarity(b), alone, i, j));
+                }
+            }
+        }
+    }
+    candidates.sort_by(|x, y| y.0.total_cmp(&x.0).then((x.2, x.3).cmp(&(y.2, y.3))));
+    let mut changed = Vec::new();
+    for (similarity, alone, i, j) in candidates {
+        // A script with a hat of its own is the same script however it changed.
+        if (similarity >= 0.5 || alone) && old[i].is_some() && new[j].is_some() {
+            changed.push((i, old[i].take().unwrap(), new[j].take().unwrap()));
+        }
+    }
+    changed.sort_by_key(|(i, _, _)| *i);
+    let changed = changed.into_iter().map(|(_, a, b)| (a, b)).collect();
+    let flatten = |scripts: Vec<Option<Script>>| scripts.into_iter().flatten().collect();
+    (changed, flatten(old), flatten(new))
+}
+
+/// Things named the same in both versions, with what differs about them.
+fn by_name<T>(
+    changes: &mut Vec<Change>,
+    target: &str,
+    item: Item,
+    old: Vec<(&str, T)>,
+    new: Vec<(&str, T)>,
+    compare: impl Fn(&T, &T) -> Vec<String>,
+) {
+    let change = |kind, name: &str, details| Change {
+        kind,
+        item,
+        target: target.to_string(),
+        name: name.to_string(),
+        details,
+    };
+    for (name, a) in old.iter() {
+        match new.iter().find(|(n, _)| n == name) {
+            Some((_, b)) => {
+                let details = compare(a, b);
+                if !details.is_empty() {
+                    changes.push(change(Kind::Changed, name, details));
+                }
+            }
+            None => changes.push(change(Kind::Removed, name, Vec::new())),
+        }
+    }
+    for (name, _) in new.iter().filter(|(n, _)| !old.iter().any(|(o, _)| o == n)) {
+        changes.push(change(Kind::Added, name, Vec::new()));
+    }
+}
+
+/// Variables or lists by name, as their IDs differ between versions.
+fn named<'a, T>(
+    items: &'a HashMap<String, T>,
+    name: impl Fn(&'a T) -> &'a str,
+) -> Vec<(&'a str, &'a T)> {
+    let mut items = items.values().map(|v| (name(v), v)).collect::<Vec<_>>();
+    items.sort_by_key(|(name, _)| *name);
+    items
+}
+
+fn assets(target: &model::Target) -> (&[model::Costume], &[model::Sound]) {
+    match target {
+        model::Target::Sprite(s) => (&s.costumes, &s.sounds),
+        model::Target::Stage(s) => (&s.costumes, &s.sounds),
+    }
+}
+
+fn file(a: &str, b: &str) -> Vec<String> {
+    match a == b {
+        true => Vec::new(),
+        false => vec![format!("file {a} -> {b}")],
+    }
+}
+
+fn compare_targets(
+    changes: &mut Vec<Change>,
+    old: (&Target, &model::Target),
+    new: (&Target, &model::Target),
+) {
+    let name = new.0.name;
+    let (changed, removed, added) = pair(target_scripts(old.0), target_scripts(new.0));
+    let script = |kind, script: &Script, details| Change {
+        kind,
+        item: Item::Script,
+        target: name.to_string(),
+        name: script.name.clone(),
+        details,
+    };
+    for (a, b) in changed.iter() {
+        let diff = linediff::diff(&a.text(), &b.text());
+        changes.push(script(
+            Kind::Changed,
+            b,
+            diff.lines().map(String::from).collect(),
+        ));
+    }
+    for a in removed.iter() {
+        changes.push(script(
+            Kind::Removed,
+            a,
+            a.lines.iter().map(|l| format!("-{l}")).collect(),
+        ));
+    }
+    for b in added.iter() {
+        changes.push(script(
+            Kind::Added,
+            b,
+            b.lines.iter().map(|l| format!("+{l}")).collect(),
+        ));
+    }
+
+    by_name(
+        changes,
+        name,
+        Item::Variable,
+        named(old.0.variables, |v| &v.name),
+        named(new.0.variables, |v| &v.name),
+        |a, b| {
+            let mut details = Vec::new();
+            let (x, y) = (String::from(a.value.clone()), String::from(b.value.clone()));
+            if x != y {
+                details.push(format!("value {x:?} -> {y:?}"));
+            }
+            if a.is_cloud != b.is_cloud {
+                details.push(format!("cloud {} -> {}", a.is_cloud, b.is_cloud));
+            }
+            details
+        },
+    );
+    by_name(
+        changes,
+        name,
+        Item::List,
+        named(old.0.lists, |l| &l.name),
+        named(new.0.lists, |l| &l.name),
+        |a, b| match (a.value.len(), b.value.len()) {
+            _ if a.value == b.value => Vec::new(),
+            (x, y) if x == y => vec![format!("{x} items, some different")],
+            (x, y) => vec![format!("{x} items -> {y} items")],
+        },
+    );
+    let ((old_costumes, old_sounds), (new_costumes, new_sounds)) = (assets(old.1), assets(new.1));
+    by_name(
+        changes,
+        name,
+        Item::Costume,
+        old_costumes.iter().map(|c| (c.name.as_str(), c)).collect(),
+        new_costumes.iter().map(|c| (c.name.as_str(), c)).collect(),
+        |a, b| file(&a.md5ext, &b.md5ext),
+    );
+    by_name(
+        changes,
+        name,
+        Item::Sound,
+        old_sounds.iter().map(|s| (s.name.as_str(), s)).collect(),
+        new_sounds.iter().map(|s| (s.name.as_str(), s)).collect(),
+        |a, b| file(&a.md5ext, &b.md5ext),
+    );
+}
+
+/// What changed from `old` to `new`, sprite by sprite, with the sprites that
+/// were added or removed last.
+pub fn diff(old: &model::Project, new: &model::Project) -> Vec<Change> {
+    let (old_blocks, new_blocks) = (scripts::blocks(old), scripts::blocks(new));
+    let old_targets = scripts::targets(old, &old_blocks);
+    let new_targets = scripts::targets(new, &new_blocks);
+    let mut changes = Vec::new();
+    let sprite = |kind, name: &str| Change {
+        kind,
+        item: Item::Sprite,
+        target: name.to_string(),
+        name: name.to_string(),
+        details: Vec::new(),
+    };
+    let mut sprites = Vec::new();
+    for (target, model) in new_targets.iter().zip(new.targets.iter()) {
+        let same = old_targets
+            .iter()
+            .zip(old.targets.iter())
+            .find(|(t, _)| t.name == target.name && t.is_stage == target.is_stage);
+        match same {
+            Some(old) => compare_targets(&mut changes, old, (target, model)),
+            None => sprites.push(sprite(Kind::Added, target.name)),
+        }
+    }
+    for target in old_targets.iter() {
+        if !new_targets
+            .iter()
+            .any(|t| t.name == target.name && t.is_stage == target.is_stage)
+        {
+            sprites.push(sprite(Kind::Removed, target.name));
+        }
+    }
+    changes.extend(sprites);
+    changes
+}
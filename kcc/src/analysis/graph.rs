@@ -0,0 +1,391 @@
+//! A graph of how the scripts of a project talk to each other: the broadcasts
+//! they send and receive, the custom blocks they call, and the variables and
+//! lists they read and write. Edges point the way control and data flow.
+
+use std::fmt::Write;
+
+use hashbrown::{HashMap as Map, HashSet};
+use scratch_ast::model::{self, Block};
+use serde::Serialize;
+
+use super::scripts::{self, Access, Broadcast, Target};
+
+/// How `kcc graph` prints the graph.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Graphviz, to draw with `dot -Tsvg`.
+    #[default]
+    Dot,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeKind {
+    Script,
+    Broadcast,
+    Variable,
+    List,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Node {
+    /// `target/block` for scripts, by the ID of their hat, `broadcast/name`,
+    /// and `variable/id` or `list/id`.
+    pub id: String,
+    pub kind: NodeKind,
+    /// The sprite, or the stage, the node belongs to. Broadcasts belong to the stage.
+    pub target: String,
+    pub label: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EdgeKind {
+    /// From a script to a broadcast.
+    Sends,
+    SendsAndWaits,
+    /// From a broadcast to a script receiving it.
+    Starts,
+    /// From a script to the definition of a custom block.
+    Calls,
+    /// From a variable or list to a script.
+    Reads,
+    /// From a script to a variable or list.
+    Writes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    /// Scripts that end up waiting for themselves, through `broadcast and wait`
+    /// and custom block calls, by node ID.
+    pub cycles: Vec<Vec<String>>,
+}
+
+fn script_id(target: &Target, hat: &Block) -> String {
+    format!("{}/{}", target.name, hat.obj_id)
+}
+
+impl Graph {
+    fn node(&mut self, id: String, kind: NodeKind, target: &str, label: String) {
+        self.nodes.push(Node {
+            id,
+            kind,
+            target: target.to_string(),
+            label,
+        });
+    }
+
+    /// Builds the graph of a project.
+    pub fn new(project: &model::Project) -> Self {
+        let blocks = scripts::blocks(project);
+        let targets = scripts::targets(project, &blocks);
+        let stage = targets
+            .iter()
+            .find(|t| t.is_stage)
+            .map_or("Stage", |t| t.name);
+        let mut graph = Graph::default();
+        let mut edges = HashSet::new();
+        let mut broadcasts = Vec::new();
+        let mut receivers = Map::<String, Vec<String>>::new();
+        let lists = targets
+            .iter()
+            .flat_map(|t| t.lists.keys().map(String::as_str))
+            .collect::<HashSet<_>>();
+        for target in targets.iter() {
+            let mut data = target
+                .variables
+                .iter()
+                .map(|(id, v)| (NodeKind::Variable, format!("variable/{id}"), &v.name))
+                .chain(
+                    target
+                        .lists
+                        .iter()
+                        .map(|(id, l)| (NodeKind::List, format!("list/{id}"), &l.name)),
+                )
+                .collect::<Vec<_>>();
+            data.sort_by(|a, b| (a.2, &a.1).cmp(&(b.2, &b.1)));
+            for (kind, id, name) in data {
+                graph.node(id, kind, target.name, name.clone());
+            }
+
+            for (hat, script) in target.scripts() {
+                let id = script_id(target, hat);
+                graph.node(
+                    id.clone(),
+                    NodeKind::Script,
+                    target.name,
+                    target.hat_label(hat),
+                );
+                let mut edge = |from: String, to: String, kind| {
+                    let edge = Edge { from, to, kind };
+                    if edges.insert(edge.clone()) {
+                        graph.edges.push(edge);
+                    }
+                };
+                for block in script {
+                    for (access, data) in scripts::data(block) {
+                        let data = match lists.contains(data) {
+                            true => format!("list/{data}"),
+                            false => format!("variable/{data}"),
+                        };
+                        match access {
+                            Access::Read => edge(data, id.clone(), EdgeKind::Reads),
+                            Access::Write => edge(id.clone(), data, EdgeKind::Writes),
+                        }
+                    }
+                    match scripts::broadcast(block) {
+                        // Broadcasts named by a reporter are left out.
+                        Some(Broadcast::Send {
+                            name: Some(name),
+                            wait,
+                        }) => {
+                            let kind = match wait {
+                                true => EdgeKind::SendsAndWaits,
+                                false => EdgeKind::Sends,
+                            };
+                            edge(id.clone(), format!("broadcast/{name}"), kind);
+                            broadcasts.push(name);
+                        }
+                        Some(Broadcast::Receive(name)) => {
+                            receivers.entry(name.clone()).or_default().push(id.clone());
+                            broadcasts.push(name);
+                        }
+                        _ => {}
+                    }
+                    if let Some(definition) = target.called(block) {
+                        edge(id.clone(), script_id(target, definition), EdgeKind::Calls);
+                    }
+                }
+            }
+        }
+
+        broadcasts.sort();
+        broadcasts.dedup();
+        for name in broadcasts {
+            let id = format!("broadcast/{name}");
+            for receiver in receivers.remove(&name).unwrap_or_default() {
+                graph.edges.push(Edge {
+                    from: id.clone(),
+                    to: receiver,
+                    kind: EdgeKind::Starts,
+                });
+            }
+            graph.node(id, NodeKind::Broadcast, stage, name);
+        }
+        // Variables no target has, which the editor would not save, are left out.
+        let known = graph
+            .nodes
+            .iter()
+            .map(|n| n.id.clone())
+            .collect::<HashSet<_>>();
+        graph
+            .edges
+            .retain(|e| known.contains(&e.from) && known.contains(&e.to));
+        graph.cycles = graph.cycles();
+        graph
+    }
+
+    /// The groups of scripts that can wait for each other forever, found as the
+    /// strongly connected components of the scripts with a `broadcast and wait`
+    /// inside.
+    fn cycles(&self) -> Vec<Vec<String>> {
+        // Waiting for a broadcast waits for the scripts it starts.
+        let mut next = Map::<&str, Vec<(&str, bool)>>::new();
+        for edge in self.edges.iter() {
+            match edge.kind {
+                EdgeKind::Calls => next.entry(&edge.from).or_default().push((&edge.to, false)),
+                EdgeKind::SendsAndWaits => {
+                    for started in self.edges.iter().filter(|e| e.from == edge.to) {
+                        next.entry(&edge.from)
+                            .or_default()
+                            .push((&started.to, true));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let scripts = self
+            .nodes
+            .iter()
+            .filter(|n| n.kind == NodeKind::Script)
+            .map(|n| n.id.as_str())
+            .collect::<Vec<_>>();
+        let mut tarjan = Tarjan {
+            next: &next,
+            index: Map::new(),
+            low: Map::new(),
+            stack: Vec::new(),
+            on_stack: HashSet::new(),
+            components: Vec::new(),
+        };
+        for script in scripts {
+            if !tarjan.index.contains_key(script) {
+                tarjan.visit(script);
+            }
+        }
+        let mut cycles = Vec::new();
+        for component in tarjan.components {
+            let inside = component.iter().copied().collect::<HashSet<_>>();
+            let waits = component.iter().any(|from| {
+                next.get(from)
+                    .is_some_and(|to| to.iter().any(|(to, wait)| *wait && inside.contains(to)))
+            });
+            if waits {
+                let mut cycle = component.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+                cycle.sort();
+                cycles.push(cycle);
+            }
+        }
+        cycles.sort();
+        cycles
+    }
+
+    /// The edges going around [`Graph::cycles`], by index.
+    fn cyclic_edges(&self) -> HashSet<usize> {
+        let mut found = HashSet::new();
+        for cycle in self.cycles.iter() {
+            let inside = |id: &String| cycle.contains(id);
+            for (index, edge) in self.edges.iter().enumerate() {
+                let around = match edge.kind {
+                    EdgeKind::Calls => inside(&edge.from) && inside(&edge.to),
+                    EdgeKind::SendsAndWaits => {
+                        inside(&edge.from)
+                            && self.edges.iter().any(|e| {
+                                e.kind == EdgeKind::Starts && e.from == edge.to && inside(&e.to)
+                            })
+                    }
+                    EdgeKind::Starts => {
+                        inside(&edge.to)
+                            && self.edges.iter().any(|e| {
+                                e.kind == EdgeKind::SendsAndWaits
+                                    && e.to == edge.from
+                                    && inside(&e.from)
+                            })
+                    }
+                    _ => false,
+                };
+                if around {
+                    found.insert(index);
+                }
+            }
+        }
+        found
+    }
+
+    /// The graph in Graphviz's language, with a box for every target and the
+    /// edges of cycles in red.
+    pub fn dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let cyclic = self.cyclic_edges();
+        let mut out = String::from(
+            "digraph project {\n    rankdir=LR;\n    node [fontname=\"Helvetica\"];\n",
+        );
+        let mut targets = Vec::new();
+        for node in self.nodes.iter() {
+            if node.kind != NodeKind::Broadcast && !targets.contains(&node.target.as_str()) {
+                targets.push(node.target.as_str());
+            }
+        }
+        let node_line = |node: &Node| {
+            let shape = match node.kind {
+                NodeKind::Script => "shape=box, style=rounded",
+                NodeKind::Broadcast => "shape=ellipse, style=filled, fillcolor=\"#ffe9a8\"",
+                NodeKind::Variable => "shape=note",
+                NodeKind::List => "shape=folder",
+            };
+            format!(
+                "{} [label={}, {shape}];",
+                quote(&node.id),
+                quote(&node.label)
+            )
+        };
+        for (index, target) in targets.iter().enumerate() {
+            writeln!(out, "    subgraph cluster_{index} {{").unwrap();
+            writeln!(out, "        label={};", quote(target)).unwrap();
+            for node in self.nodes.iter() {
+                if node.kind != NodeKind::Broadcast && node.target == *target {
+                    writeln!(out, "        {}", node_line(node)).unwrap();
+                }
+            }
+            out.push_str("    }\n");
+        }
+        for node in self.nodes.iter().filter(|n| n.kind == NodeKind::Broadcast) {
+            writeln!(out, "    {}", node_line(node)).unwrap();
+        }
+        for (index, edge) in self.edges.iter().enumerate() {
+            let mut style = match edge.kind {
+                EdgeKind::Sends | EdgeKind::Starts => vec![],
+                EdgeKind::SendsAndWaits => vec!["style=bold", "label=\"and wait\""],
+                EdgeKind::Calls => vec!["style=dashed"],
+                EdgeKind::Reads | EdgeKind::Writes => vec!["color=gray"],
+            };
+            if cyclic.contains(&index) {
+                style.push("color=red");
+            }
+            let style = match style.is_empty() {
+                true => String::new(),
+                false => format!(" [{}]", style.join(", ")),
+            };
+            writeln!(
+                out,
+                "    {} -> {}{style};",
+                quote(&edge.from),
+                quote(&edge.to)
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+struct Tarjan<'a> {
+    next: &'a Map<&'a str, Vec<(&'a str, bool)>>,
+    index: Map<&'a str, usize>,
+    low: Map<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: HashSet<&'a str>,
+    components: Vec<Vec<&'a str>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, node: &'a str) {
+        let index = self.index.len();
+        self.index.insert(node, index);
+        self.low.insert(node, index);
+        self.stack.push(node);
+        self.on_stack.insert(node);
+        let next = self.next;
+        for (next, _) in next.get(node).into_iter().flatten() {
+            if !self.index.contains_key(next) {
+                self.visit(next);
+                let low = self.low[node].min(self.low[next]);
+                self.low.insert(node, low);
+            } else if self.on_stack.contains(next) {
+                let low = self.low[node].min(self.index[next]);
+                self.low.insert(node, low);
+            }
+        }
+        if self.low[node] == index {
+            let mut component = Vec::new();
+            while let Some(top) = self.stack.pop() {
+                self.on_stack.remove(top);
+                component.push(top);
+                if top == node {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
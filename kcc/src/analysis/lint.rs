@@ -0,0 +1,443 @@
+//! Finds mistakes that are easy to make in the editor, such as variables
+//! nobody reads or broadcasts nobody receives. Unlike the other analyses, this
+//! one looks at the parsed project, so that stacks that never run are checked too.
+
+use std::fmt;
+
+use hashbrown::{HashMap as Map, HashSet};
+use scratch_ast::model::{self, Block, BlockType};
+use serde::Serialize;
+
+use super::scripts::{self, is_hat, proccode, Access, Broadcast, Target};
+
+/// What a [`Lint`] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    NeverRead,
+    NeverWritten,
+    NoReceivers,
+    NoSenders,
+    NeverCalled,
+    UndefinedCustomBlock,
+    Orphaned,
+    EmptyIf,
+    BusyLoop,
+    GreenFlagRace,
+}
+
+impl Rule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::NeverRead => "never-read",
+            Rule::NeverWritten => "never-written",
+            Rule::NoReceivers => "no-receivers",
+            Rule::NoSenders => "no-senders",
+            Rule::NeverCalled => "never-called",
+            Rule::UndefinedCustomBlock => "undefined-custom-block",
+            Rule::Orphaned => "orphaned",
+            Rule::EmptyIf => "empty-if",
+            Rule::BusyLoop => "busy-loop",
+            Rule::GreenFlagRace => "green-flag-race",
+        }
+    }
+}
+
+/// A block, by the ID the editor gave it.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Place {
+    pub target: String,
+    pub block: String,
+}
+
+/// A likely mistake.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Lint {
+    pub rule: Rule,
+    /// The sprite, or the stage, that owns what the lint is about. Global
+    /// variables and broadcasts belong to the stage.
+    pub target: String,
+    pub message: String,
+    /// The blocks to look at, which may be in other targets. Variables nobody
+    /// uses have none.
+    pub blocks: Vec<Place>,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} [{}]",
+            self.target,
+            self.message,
+            self.rule.name()
+        )?;
+        if !self.blocks.is_empty() {
+            let blocks = self
+                .blocks
+                .iter()
+                .map(|p| match p.target == self.target {
+                    true => p.block.clone(),
+                    false => format!("{}:{}", p.target, p.block),
+                })
+                .collect::<Vec<_>>();
+            write!(f, " (blocks {})", blocks.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Blocks that let other scripts, and the screen, catch up.
+const WAITS: &[BlockType] = &[
+    BlockType::ControlWait,
+    BlockType::ControlWaitUntil,
+    BlockType::EventBroadcastandWait,
+    BlockType::SensingAskAndWait,
+    BlockType::LooksSayForSecs,
+    BlockType::LooksThinkForSecs,
+    BlockType::LooksSwitchBackdropToAndWait,
+    BlockType::MotionGlideTo,
+    BlockType::MotionGlideSecsToXY,
+];
+
+impl<'a> Target<'a> {
+    fn place(&self, block: &Block) -> Place {
+        Place {
+            target: self.name.to_string(),
+            block: block.obj_id.clone(),
+        }
+    }
+
+    /// Whether running a stack lets other scripts run at some point.
+    fn waits(&self, stack: &[&'a Block], seen: &mut HashSet<&'a str>) -> bool {
+        stack.iter().any(|block| {
+            if WAITS.contains(&block.block_type) {
+                return true;
+            }
+            if let Some(definition) = self.called(block) {
+                let warp = self.prototype(definition).is_some_and(|p| p.warp);
+                if !warp
+                    && seen.insert(&definition.obj_id)
+                    && self.waits(&self.stack(Some(definition)), seen)
+                {
+                    return true;
+                }
+            }
+            self.waits(&self.substacks(block), seen)
+        })
+    }
+
+    /// The blocks of a script, and of the custom blocks it calls.
+    fn reached(&self, script: Vec<&'a Block>, seen: &mut HashSet<&'a str>) -> Vec<&'a Block> {
+        let mut found = Vec::new();
+        for block in script {
+            if let Some(definition) = self.called(block) {
+                if seen.insert(&definition.obj_id) {
+                    found.extend(self.reached(self.script(definition), seen));
+                }
+            }
+            found.push(block);
+        }
+        found
+    }
+}
+
+/// Every lint of a project, target by target.
+pub fn lint(project: &model::Project) -> Vec<Lint> {
+    let blocks = scripts::blocks(project);
+    let targets = scripts::targets(project, &blocks);
+    let mut lints = Vec::new();
+    data(&targets, &mut lints);
+    broadcasts(&targets, &mut lints);
+    for target in targets.iter() {
+        custom_blocks(target, &mut lints);
+        orphans(target, &mut lints);
+        empty_ifs(target, &mut lints);
+        busy_loops(target, &mut lints);
+    }
+    races(&targets, &mut lints);
+    lints
+}
+
+#[derive(Default)]
+struct Uses {
+    reads: Vec<Place>,
+    writes: Vec<Place>,
+}
+
+/// Variables and lists that are never read, or never written.
+fn data(targets: &[Target], lints: &mut Vec<Lint>) {
+    let mut uses = Map::<&str, Uses>::new();
+    for target in targets.iter() {
+        for block in target.sorted() {
+            for (access, id) in scripts::data(block) {
+                let uses = uses.entry(id).or_default();
+                match access {
+                    Access::Read => uses.reads.push(target.place(block)),
+                    Access::Write => uses.writes.push(target.place(block)),
+                }
+            }
+        }
+    }
+
+    let none = Uses::default();
+    for target in targets.iter() {
+        let mut variables = target.variables.iter().collect::<Vec<_>>();
+        variables.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+        let mut lists = target.lists.iter().collect::<Vec<_>>();
+        lists.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+        let data = variables
+            .into_iter()
+            .map(|(id, v)| (id, "variable", &v.name, Some(v)))
+            .chain(lists.into_iter().map(|(id, l)| (id, "list", &l.name, None)));
+        for (id, kind, name, variable) in data {
+            let uses = uses.get(id.as_str()).unwrap_or(&none);
+            let lint = |rule, message: String, blocks: &[Place]| Lint {
+                rule,
+                target: target.name.to_string(),
+                message,
+                blocks: blocks.to_vec(),
+            };
+            if uses.reads.is_empty() {
+                let message = match uses.writes.is_empty() {
+                    true => format!("{kind} \"{name}\" is never used"),
+                    false => format!("{kind} \"{name}\" is set but never read"),
+                };
+                lints.push(lint(Rule::NeverRead, message, &uses.writes));
+            } else if uses.writes.is_empty() {
+                // Cloud variables are set by other players, and a list that
+                // starts with items is a fine table of constants.
+                let message = match variable {
+                    Some(v) if v.is_cloud => continue,
+                    Some(v) => format!(
+                        "variable \"{name}\" is never set, so it is always {:?}",
+                        String::from(v.value.clone())
+                    ),
+                    None if !target.lists[id].value.is_empty() => continue,
+                    None => format!("list \"{name}\" is never changed, so it is always empty"),
+                };
+                lints.push(lint(Rule::NeverWritten, message, &uses.reads));
+            }
+        }
+    }
+}
+
+/// Broadcasts sent to nobody, and receivers waiting for a broadcast nobody sends.
+fn broadcasts(targets: &[Target], lints: &mut Vec<Lint>) {
+    let mut sent = Map::<String, Vec<Place>>::new();
+    let mut received = Map::<String, Vec<Place>>::new();
+    // A broadcast named by a reporter could be any of them.
+    let mut computed = false;
+    for target in targets.iter() {
+        for block in target.sorted() {
+            match scripts::broadcast(block) {
+                Some(Broadcast::Send {
+                    name: Some(name), ..
+                }) => sent.entry(name).or_default().push(target.place(block)),
+                Some(Broadcast::Send { name: None, .. }) => computed = true,
+                Some(Broadcast::Receive(name)) => {
+                    received.entry(name).or_default().push(target.place(block))
+                }
+                None => {}
+            }
+        }
+    }
+    let stage = targets
+        .iter()
+        .find(|t| t.is_stage)
+        .map_or("Stage", |t| t.name);
+    let mut names = sent.keys().chain(received.keys()).collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let (message, rule, blocks) = match (sent.get(name), received.get(name)) {
+            (Some(senders), None) => (
+                format!("nothing happens when \"{name}\" is broadcast"),
+                Rule::NoReceivers,
+                senders,
+            ),
+            (None, Some(receivers)) if !computed => (
+                format!("\"{name}\" is never broadcast"),
+                Rule::NoSenders,
+                receivers,
+            ),
+            _ => continue,
+        };
+        lints.push(Lint {
+            rule,
+            target: stage.to_string(),
+            message,
+            blocks: blocks.clone(),
+        });
+    }
+}
+
+/// Custom blocks nobody calls, and calls to custom blocks that do not exist.
+fn custom_blocks(target: &Target, lints: &mut Vec<Lint>) {
+    let mut called = HashSet::new();
+    for (hat, script) in target.scripts() {
+        let own = target.prototype(hat).map(|p| p.proccode.as_str());
+        for block in script {
+            let Some(proccode) = proccode(block) else {
+                continue;
+            };
+            // A custom block calling itself is not a reason to keep it.
+            if Some(proccode) != own {
+                called.insert(proccode);
+            }
+        }
+    }
+    // Calls in stacks that never run are still calls worth fixing.
+    for block in target.sorted() {
+        let Some(proccode) = proccode(block) else {
+            continue;
+        };
+        if !target.definitions.contains_key(proccode) {
+            lints.push(Lint {
+                rule: Rule::UndefinedCustomBlock,
+                target: target.name.to_string(),
+                message: format!("custom block \"{proccode}\" is not defined"),
+                blocks: vec![target.place(block)],
+            });
+        }
+    }
+    let mut definitions = target.definitions.iter().collect::<Vec<_>>();
+    definitions.sort_by_key(|(proccode, _)| **proccode);
+    for (proccode, definition) in definitions {
+        if !called.contains(proccode) {
+            lints.push(Lint {
+                rule: Rule::NeverCalled,
+                target: target.name.to_string(),
+                message: format!("custom block \"{proccode}\" is never used"),
+                blocks: vec![target.place(definition)],
+            });
+        }
+    }
+}
+
+/// Stacks without a hat, which never run.
+fn orphans(target: &Target, lints: &mut Vec<Lint>) {
+    for block in target.sorted() {
+        if !block.top_level || block.shadow || is_hat(block.block_type) {
+            continue;
+        }
+        let message = match block.next_id.is_some() {
+            true => "these blocks have no hat block, so they never run",
+            false => "this block has no hat block, so it never runs",
+        };
+        lints.push(Lint {
+            rule: Rule::Orphaned,
+            target: target.name.to_string(),
+            message: message.to_string(),
+            blocks: vec![target.place(block)],
+        });
+    }
+}
+
+/// `if` blocks with nothing inside.
+fn empty_ifs(target: &Target, lints: &mut Vec<Lint>) {
+    for block in target.sorted() {
+        let then = target.input(block, "SUBSTACK").is_none();
+        let otherwise = target.input(block, "SUBSTACK2").is_none();
+        let message = match block.block_type {
+            BlockType::ControlIf if then => "this if has nothing inside",
+            BlockType::ControlIfElse if then && otherwise => "this if else has nothing inside",
+            BlockType::ControlIfElse if then => {
+                "the first part of this if else is empty, an if with the opposite condition would do"
+            }
+            BlockType::ControlIfElse if otherwise => {
+                "the else part of this if else is empty, an if would do"
+            }
+            _ => continue,
+        };
+        lints.push(Lint {
+            rule: Rule::EmptyIf,
+            target: target.name.to_string(),
+            message: message.to_string(),
+            blocks: vec![target.place(block)],
+        });
+    }
+}
+
+/// Loops that never end and never wait. The editor runs them once a frame,
+/// but kcc runs them as fast as it can, keeping a core busy.
+fn busy_loops(target: &Target, lints: &mut Vec<Lint>) {
+    for (hat, script) in target.scripts() {
+        // Without screen refresh, a loop that never ends freezes the editor
+        // anyway, and so does not need to be told apart.
+        if target.prototype(hat).is_some_and(|p| p.warp) {
+            continue;
+        }
+        for block in script {
+            let endless = match block.block_type {
+                BlockType::ControlForever => true,
+                BlockType::ControlRepeatUntil => target.input(block, "CONDITION").is_none(),
+                _ => false,
+            };
+            if endless && !target.waits(&target.substack(block, "SUBSTACK"), &mut HashSet::new()) {
+                lints.push(Lint {
+                    rule: Rule::BusyLoop,
+                    target: target.name.to_string(),
+                    message: "this loop never ends and never waits".to_string(),
+                    blocks: vec![target.place(block)],
+                });
+            }
+        }
+    }
+}
+
+/// Global variables set by several targets when the green flag is clicked,
+/// whose value then depends on which script happens to run first.
+fn races(targets: &[Target], lints: &mut Vec<Lint>) {
+    let Some(stage) = targets.iter().find(|t| t.is_stage) else {
+        return;
+    };
+    let mut writers = Map::<&str, Vec<(usize, Place)>>::new();
+    for (index, target) in targets.iter().enumerate() {
+        for (hat, script) in target.scripts() {
+            if hat.block_type != BlockType::EventWhenFlagClicked {
+                continue;
+            }
+            let mut seen = HashSet::new();
+            for block in target.reached(script, &mut seen) {
+                let set = matches!(
+                    block.block_type,
+                    BlockType::DataSetVariableTo | BlockType::DataChangeVariableBy
+                );
+                let id = block
+                    .fields
+                    .get("VARIABLE")
+                    .and_then(|f| f.value_id.as_deref());
+                if let (true, Some(id)) = (set, id) {
+                    if stage.variables.contains_key(id) {
+                        writers
+                            .entry(id)
+                            .or_default()
+                            .push((index, target.place(block)));
+                    }
+                }
+            }
+        }
+    }
+    let mut variables = writers.into_iter().collect::<Vec<_>>();
+    variables.sort_by_key(|(id, _)| stage.variables[*id].name.as_str());
+    for (id, writes) in variables {
+        let mut indices = writes.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        indices.dedup();
+        if indices.len() < 2 {
+            continue;
+        }
+        let mut names = indices.iter().map(|i| targets[*i].name).collect::<Vec<_>>();
+        let last = names.pop().unwrap_or_default();
+        lints.push(Lint {
+            rule: Rule::GreenFlagRace,
+            target: stage.name.to_string(),
+            message: format!(
+                "{} and {last} set \"{}\" when the green flag is clicked, in no particular order",
+                names.join(", "),
+                stage.variables[id].name
+            ),
+            blocks: writes.into_iter().map(|(_, p)| p).collect(),
+        });
+    }
+}
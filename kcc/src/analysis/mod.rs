@@ -0,0 +1,61 @@
+//! Static analyses over the scripts of a project, used by `kcc check`, `kcc lint`,
+//! `kcc graph`, `kcc stats` and `kcc diff`.
+
+use std::fmt;
+
+use scratch_ast::model::BlockType;
+
+use crate::vm::internals::{Expression, StackExpression, VMEvaluable, VMThread};
+
+pub mod diff;
+pub mod graph;
+pub mod lint;
+mod scripts;
+pub mod stats;
+pub mod support;
+pub mod types;
+
+/// Something suspicious about a block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The sprite, or the stage, the block belongs to.
+    pub target: String,
+    pub block: String,
+    pub opcode: BlockType,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(target: &str, exp: &StackExpression, message: impl ToString) -> Self {
+        Self {
+            target: target.to_string(),
+            block: exp.original_block.obj_id.clone(),
+            opcode: exp.opcode,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} (block {}, {:?})",
+            self.target, self.message, self.block, self.opcode
+        )
+    }
+}
+
+/// The name of the custom block a script defines, such as `jump %s high`.
+pub fn proccode(thread: &VMThread) -> Option<&str> {
+    let Some(Expression::Stack(hat)) = thread.code.first() else {
+        return None;
+    };
+    let Some(VMEvaluable::Block(prototype)) = hat.dependencies.get("custom_block") else {
+        return None;
+    };
+    match &prototype.original_block.mutation {
+        Some(scratch_ast::model::Mutation::ProcedurePrototype(p)) => Some(&p.proccode),
+        _ => None,
+    }
+}
@@ -0,0 +1,288 @@
+//! Walks the stacks of the parsed project, for the analyses that look at every
+//! stack rather than at the scripts kcc runs.
+
+use std::collections::HashMap;
+
+use hashbrown::HashMap as Map;
+use scratch_ast::model::{
+    self, Block, BlockType, List, Mutation, RichValue, ShadowValue, ValuePointer, Variable,
+};
+
+/// The blocks of every target, which only know their ID by the key they are
+/// stored under, with the ID filled in.
+pub(crate) fn blocks(project: &model::Project) -> Vec<HashMap<String, Block>> {
+    project
+        .targets
+        .iter()
+        .map(|target| {
+            let mut blocks = target.blocks();
+            for (id, block) in blocks.iter_mut() {
+                block.obj_id = id.clone();
+            }
+            blocks
+        })
+        .collect()
+}
+
+/// The targets of a project, in order, with the blocks from [`blocks`].
+pub(crate) fn targets<'a>(
+    project: &'a model::Project,
+    blocks: &'a [HashMap<String, Block>],
+) -> Vec<Target<'a>> {
+    project
+        .targets
+        .iter()
+        .zip(blocks.iter())
+        .map(|(target, blocks)| Target::new(target, blocks))
+        .collect()
+}
+
+/// A sprite, or the stage, with the custom blocks it defines.
+pub(crate) struct Target<'a> {
+    pub name: &'a str,
+    pub is_stage: bool,
+    pub blocks: &'a HashMap<String, Block>,
+    pub variables: &'a HashMap<String, Variable>,
+    pub lists: &'a HashMap<String, List>,
+    /// The custom blocks defined here, by proccode, with their definition.
+    pub definitions: Map<&'a str, &'a Block>,
+}
+
+impl<'a> Target<'a> {
+    pub fn new(target: &'a model::Target, blocks: &'a HashMap<String, Block>) -> Self {
+        let (name, variables, lists) = match target {
+            model::Target::Sprite(s) => (&s.name, &s.variables, &s.lists),
+            model::Target::Stage(s) => (&s.name, &s.variables, &s.lists),
+        };
+        let mut target = Target {
+            name,
+            is_stage: matches!(target, model::Target::Stage(_)),
+            blocks,
+            variables,
+            lists,
+            definitions: Map::new(),
+        };
+        for block in target.sorted() {
+            if let Some(prototype) = target.prototype(block) {
+                target.definitions.insert(&prototype.proccode, block);
+            }
+        }
+        target
+    }
+
+    /// The blocks, sorted by ID so that analyses find things in the same order every time.
+    pub fn sorted(&self) -> Vec<&'a Block> {
+        let mut blocks = self.blocks.values().collect::<Vec<_>>();
+        blocks.sort_by(|a, b| a.obj_id.cmp(&b.obj_id));
+        blocks
+    }
+
+    /// The block in an input, if there is one.
+    pub fn input(&self, block: &Block, name: &str) -> Option<&'a Block> {
+        match block.inputs.get(name)?.value.as_ref()? {
+            ShadowValue::Block(r) => self.blocks.get(&r.id),
+            _ => None,
+        }
+    }
+
+    /// A block and the ones under it.
+    pub fn stack(&self, first: Option<&'a Block>) -> Vec<&'a Block> {
+        let mut stack = Vec::new();
+        let mut next = first;
+        while let Some(block) = next {
+            stack.push(block);
+            next = block.next_id.as_ref().and_then(|id| self.blocks.get(id));
+        }
+        stack
+    }
+
+    /// The stack of blocks in an input, such as the inside of a loop.
+    pub fn substack(&self, block: &Block, name: &str) -> Vec<&'a Block> {
+        self.stack(self.input(block, name))
+    }
+
+    /// The prototype of a custom block definition.
+    pub fn prototype(&self, definition: &Block) -> Option<&'a model::ProcedurePrototype> {
+        if definition.block_type != BlockType::ProceduresDefinition {
+            return None;
+        }
+        match &self.input(definition, "custom_block")?.mutation {
+            Some(Mutation::ProcedurePrototype(p)) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// The stacks inside a block, without its reporters.
+    pub fn substacks(&self, block: &Block) -> Vec<&'a Block> {
+        let mut blocks = self.substack(block, "SUBSTACK");
+        blocks.extend(self.substack(block, "SUBSTACK2"));
+        blocks
+    }
+
+    /// A stack and everything in it, reporters included.
+    pub fn script(&self, top: &'a Block) -> Vec<&'a Block> {
+        let mut found = Vec::new();
+        let mut todo = vec![top];
+        while let Some(block) = todo.pop() {
+            found.push(block);
+            if let Some(next) = block.next_id.as_ref().and_then(|id| self.blocks.get(id)) {
+                todo.push(next);
+            }
+            let mut names = block.inputs.keys().collect::<Vec<_>>();
+            names.sort();
+            todo.extend(names.into_iter().rev().filter_map(|n| self.input(block, n)));
+        }
+        found
+    }
+
+    /// The scripts that start with a hat, by their hat.
+    pub fn scripts(&self) -> Vec<(&'a Block, Vec<&'a Block>)> {
+        self.sorted()
+            .into_iter()
+            .filter(|b| b.top_level && !b.shadow && is_hat(b.block_type))
+            .map(|hat| (hat, self.script(hat)))
+            .collect()
+    }
+
+    /// What a script is, as its hat says it in the editor.
+    pub fn hat_label(&self, hat: &Block) -> String {
+        let field = |name| hat.fields.get(name).map_or("", |f| f.value.as_str());
+        match hat.block_type {
+            BlockType::EventWhenFlagClicked => "when flag clicked".to_string(),
+            BlockType::EventWhenKeyPressed => format!("when {} key pressed", field("KEY_OPTION")),
+            BlockType::EventWhenStageClicked => "when stage clicked".to_string(),
+            BlockType::EventWhenThisSpriteClicked => "when this sprite clicked".to_string(),
+            BlockType::EventWhenBackdropSwitchesTo => {
+                format!("when backdrop switches to {}", field("BACKDROP"))
+            }
+            BlockType::EventWhenGreaterThan => {
+                format!("when {} >", field("WHENGREATERTHANMENU").to_lowercase())
+            }
+            BlockType::EventWhenBroadcastReceived => {
+                format!("when I receive {}", field("BROADCAST_OPTION"))
+            }
+            BlockType::ControlStartAsClone => "when I start as a clone".to_string(),
+            BlockType::ProceduresDefinition => match self.prototype(hat) {
+                Some(prototype) => format!("define {}", prototype.proccode),
+                None => "define".to_string(),
+            },
+            other => format!("{other:?}"),
+        }
+    }
+
+    /// The definition of the custom block a block calls.
+    pub fn called(&self, block: &Block) -> Option<&'a Block> {
+        self.definitions.get(proccode(block)?).copied()
+    }
+}
+
+/// The opcode of a block, as in Scratch, such as `motion_movesteps`.
+pub(crate) fn opcode(opcode: BlockType) -> String {
+    match serde_json::to_value(opcode) {
+        Ok(serde_json::Value::String(opcode)) => opcode,
+        _ => format!("{opcode:?}").to_lowercase(),
+    }
+}
+
+pub(crate) fn is_hat(opcode: BlockType) -> bool {
+    matches!(
+        opcode,
+        BlockType::EventWhenFlagClicked
+            | BlockType::EventWhenKeyPressed
+            | BlockType::EventWhenStageClicked
+            | BlockType::EventWhenThisSpriteClicked
+            | BlockType::EventWhenBackdropSwitchesTo
+            | BlockType::EventWhenGreaterThan
+            | BlockType::EventWhenBroadcastReceived
+            | BlockType::ControlStartAsClone
+            | BlockType::ProceduresDefinition
+    )
+}
+
+/// The custom block a `procedures_call` block calls.
+pub(crate) fn proccode(block: &Block) -> Option<&str> {
+    match &block.mutation {
+        Some(Mutation::ProcedureCall(c)) if block.block_type == BlockType::ProceduresCall => {
+            Some(&c.proccode)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    Read,
+    Write,
+}
+
+/// The variables and lists a block reads or writes, by ID.
+pub(crate) fn data(block: &Block) -> Vec<(Access, &str)> {
+    let mut found = Vec::new();
+    let field = |name| block.fields.get(name).and_then(|f| f.value_id.as_deref());
+    let access = match block.block_type {
+        BlockType::DataSetVariableTo | BlockType::DataChangeVariableBy => {
+            Some((Access::Write, "VARIABLE"))
+        }
+        // Showing a variable shows it to whoever plays.
+        BlockType::DataVariable | BlockType::DataShowVariable => Some((Access::Read, "VARIABLE")),
+        BlockType::DataAddToList
+        | BlockType::DataListDeleteElement
+        | BlockType::DataListClear
+        | BlockType::DataListInsertAt
+        | BlockType::DataListReplaceItem => Some((Access::Write, "LIST")),
+        BlockType::DataListItemAt
+        | BlockType::DataListIndexOf
+        | BlockType::DataListLengthOf
+        | BlockType::DataListContainsItem
+        | BlockType::DataListShow
+        | BlockType::DataListContents => Some((Access::Read, "LIST")),
+        _ => None,
+    };
+    if let Some((access, name)) = access {
+        found.extend(field(name).map(|id| (access, id)));
+    }
+    // Variables and lists dropped straight into an input.
+    let mut names = block.inputs.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        if let Some(ShadowValue::Pointer(
+            ValuePointer::Variable { id, .. } | ValuePointer::List { id, .. },
+        )) = &block.inputs[name].value
+        {
+            found.push((Access::Read, id));
+        }
+    }
+    found
+}
+
+/// What a block does with broadcasts. Names are lowercase, as broadcasts are
+/// found regardless of case.
+pub(crate) enum Broadcast {
+    /// `name` is `None` when a reporter names the broadcast, which could be any of them.
+    Send {
+        name: Option<String>,
+        wait: bool,
+    },
+    Receive(String),
+}
+
+pub(crate) fn broadcast(block: &Block) -> Option<Broadcast> {
+    match block.block_type {
+        BlockType::EventBroadcast | BlockType::EventBroadcastandWait => {
+            let input = block.inputs.get("BROADCAST_INPUT");
+            let name = match input.and_then(|i| i.value.as_ref()) {
+                Some(ShadowValue::Bare(RichValue::Broadcast(name))) => Some(name.to_lowercase()),
+                _ => None,
+            };
+            Some(Broadcast::Send {
+                name,
+                wait: block.block_type == BlockType::EventBroadcastandWait,
+            })
+        }
+        BlockType::EventWhenBroadcastReceived => {
+            let field = block.fields.get("BROADCAST_OPTION")?;
+            Some(Broadcast::Receive(field.value.to_lowercase()))
+        }
+        _ => None,
+    }
+}
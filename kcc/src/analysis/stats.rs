@@ -0,0 +1,520 @@
+//! Counts what projects are made of, for research over many of them: blocks by
+//! category, scripts, nesting, custom blocks, data, assets, and Dr. Scratch's
+//! computational thinking scores.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+};
+
+use hashbrown::{HashMap, HashSet};
+use rayon::prelude::*;
+use scratch_ast::{
+    errors::ScratchError,
+    model::{self, Block, BlockType},
+};
+use serde::Serialize;
+
+use super::scripts::{self, Broadcast, Target};
+
+/// How `kcc stats` prints its numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Json,
+    /// A row per sprite, then one for the whole project.
+    Csv,
+}
+
+/// The categories of the editor, which are the first word of opcodes.
+const CATEGORIES: &[&str] = &[
+    "motion",
+    "looks",
+    "sound",
+    "event",
+    "control",
+    "sensing",
+    "operator",
+    "data",
+    "procedures",
+    "argument",
+];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    /// Stacks starting with a hat block.
+    pub scripts: usize,
+    /// Blocks that are not shadows, such as menus and the inside of inputs.
+    pub blocks: usize,
+    pub categories: BTreeMap<String, usize>,
+    /// The most C blocks inside each other, as in a `repeat` in a `forever`.
+    pub max_depth: usize,
+    pub custom_blocks: usize,
+    pub custom_block_calls: usize,
+    pub variables: usize,
+    pub lists: usize,
+    /// Broadcasts sent or received.
+    pub broadcasts: usize,
+    pub costumes: usize,
+    pub sounds: usize,
+    /// The size of the costumes and sounds in the `.sb3` file. Sprites sharing
+    /// an asset share its bytes, so the total may be less than their sum.
+    pub asset_bytes: u64,
+}
+
+/// Dr. Scratch's scores, from 0 to 3, of the concepts a project shows.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Thinking {
+    pub abstraction: u8,
+    pub parallelism: u8,
+    pub logic: u8,
+    pub synchronization: u8,
+    pub flow_control: u8,
+    pub user_interactivity: u8,
+    pub data_representation: u8,
+    /// Out of 21.
+    pub total: u8,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TargetStats {
+    pub name: String,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ProjectStats {
+    pub path: PathBuf,
+    /// Why the project could not be read. It has no other stats then.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub total: Counts,
+    pub targets: Vec<TargetStats>,
+    pub thinking: Thinking,
+}
+
+/// The `.sb3` files in `path`, and in the directories in it, or `path` itself.
+fn discover(path: &Path, found: &mut Vec<PathBuf>) -> Result<(), ScratchError> {
+    if !path.is_dir() {
+        found.push(path.to_path_buf());
+        return Ok(());
+    }
+    let location = format!("looking for projects in {}", path.display());
+    let mut entries = fs::read_dir(path)
+        .map_err(|e| ScratchError::not_found(e, &location))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect::<Vec<_>>();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            discover(&entry, found)?;
+        } else if entry.extension().is_some_and(|e| e == "sb3") {
+            found.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// The stats of every project in `paths`, read with `read` in parallel.
+pub fn collect<F>(paths: &[PathBuf], read: F) -> Result<Vec<ProjectStats>, ScratchError>
+where
+    F: Fn(&Path) -> Result<model::Project, ScratchError> + Sync,
+{
+    let mut projects = Vec::new();
+    for path in paths {
+        discover(path, &mut projects)?;
+    }
+    Ok(projects
+        .par_iter()
+        .map(|path| {
+            // The parser panics on some malformed projects.
+            let read =
+                std::panic::catch_unwind(AssertUnwindSafe(|| read(path))).unwrap_or_else(|_| {
+                    Err(ScratchError::syntax_error(
+                        "the project is malformed",
+                        format!("reading {}", path.display()),
+                    ))
+                });
+            match read.and_then(|project| Ok((asset_sizes(path)?, project))) {
+                Ok((sizes, project)) => stats(path, &project, &sizes),
+                Err(e) => ProjectStats {
+                    path: path.clone(),
+                    error: Some(crate::cli::describe(e)),
+                    total: Counts::default(),
+                    targets: Vec::new(),
+                    thinking: Thinking::default(),
+                },
+            }
+        })
+        .collect())
+}
+
+/// The size of every file in a `.sb3`, by name.
+fn asset_sizes(path: &Path) -> Result<HashMap<String, u64>, ScratchError> {
+    let location = format!("reading {}", path.display());
+    let file = File::open(path).map_err(|e| ScratchError::not_found(e, &location))?;
+    let mut archive =
+        zip::ZipArchive::new(file).map_err(|e| ScratchError::syntax_error(e, &location))?;
+    let mut sizes = HashMap::new();
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index(index)
+            .map_err(|e| ScratchError::syntax_error(e, &location))?;
+        sizes.insert(entry.name().to_string(), entry.size());
+    }
+    Ok(sizes)
+}
+
+fn category(opcode: BlockType) -> String {
+    let opcode = scripts::opcode(opcode);
+    opcode.split('_').next().unwrap_or_default().to_string()
+}
+
+/// The most C blocks inside each other in a stack.
+fn depth(target: &Target, stack: &[&Block]) -> usize {
+    stack
+        .iter()
+        .map(|block| {
+            ["SUBSTACK", "SUBSTACK2"]
+                .iter()
+                .filter(|name| block.inputs.contains_key(**name))
+                .map(|name| 1 + depth(target, &target.substack(block, name)))
+                .max()
+                .unwrap_or(0)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn assets(target: &model::Target) -> (usize, usize, Vec<&str>) {
+    let (costumes, sounds) = match target {
+        model::Target::Sprite(s) => (&s.costumes, &s.sounds),
+        model::Target::Stage(s) => (&s.costumes, &s.sounds),
+    };
+    let files = costumes
+        .iter()
+        .map(|c| c.md5ext.as_str())
+        .chain(sounds.iter().map(|s| s.md5ext.as_str()))
+        .collect();
+    (costumes.len(), sounds.len(), files)
+}
+
+fn stats(path: &Path, project: &model::Project, sizes: &HashMap<String, u64>) -> ProjectStats {
+    let blocks = scripts::blocks(project);
+    let targets = scripts::targets(project, &blocks);
+    let mut total = Counts::default();
+    let mut broadcasts = HashSet::new();
+    let mut files = HashSet::new();
+    let mut per_target = Vec::new();
+    for (target, model) in targets.iter().zip(project.targets.iter()) {
+        let mut counts = Counts::default();
+        let mut own = HashSet::new();
+        for block in target.sorted().into_iter().filter(|b| !b.shadow) {
+            counts.blocks += 1;
+            *counts
+                .categories
+                .entry(category(block.block_type))
+                .or_default() += 1;
+            if scripts::proccode(block).is_some() {
+                counts.custom_block_calls += 1;
+            }
+            match scripts::broadcast(block) {
+                Some(Broadcast::Send {
+                    name: Some(name), ..
+                })
+                | Some(Broadcast::Receive(name)) => {
+                    own.insert(name);
+                }
+                _ => {}
+            }
+        }
+        let scripts = target.scripts();
+        counts.scripts = scripts.len();
+        counts.max_depth = scripts
+            .iter()
+            .map(|(hat, _)| depth(target, &target.stack(Some(hat))))
+            .max()
+            .unwrap_or(0);
+        counts.custom_blocks = target.definitions.len();
+        counts.variables = target.variables.len();
+        counts.lists = target.lists.len();
+        counts.broadcasts = own.len();
+        let (costumes, sounds, assets) = assets(model);
+        counts.costumes = costumes;
+        counts.sounds = sounds;
+        let own_files = assets.into_iter().collect::<HashSet<_>>();
+        counts.asset_bytes = own_files.iter().filter_map(|f| sizes.get(*f)).sum();
+
+        total.scripts += counts.scripts;
+        total.blocks += counts.blocks;
+        for (category, count) in counts.categories.iter() {
+            *total.categories.entry(category.clone()).or_default() += count;
+        }
+        total.max_depth = total.max_depth.max(counts.max_depth);
+        total.custom_blocks += counts.custom_blocks;
+        total.custom_block_calls += counts.custom_block_calls;
+        total.variables += counts.variables;
+        total.lists += counts.lists;
+        total.costumes += counts.costumes;
+        total.sounds += counts.sounds;
+        broadcasts.extend(own);
+        files.extend(own_files);
+        per_target.push(TargetStats {
+            name: target.name.to_string(),
+            counts,
+        });
+    }
+    total.broadcasts = broadcasts.len();
+    total.asset_bytes = files.iter().filter_map(|f| sizes.get(*f)).sum();
+    ProjectStats {
+        path: path.to_path_buf(),
+        error: None,
+        total,
+        targets: per_target,
+        thinking: thinking(&targets),
+    }
+}
+
+/// Scores a project as Dr. Scratch does: each concept gets the highest level
+/// whose blocks the project uses.
+fn thinking(targets: &[Target]) -> Thinking {
+    let mut used = HashSet::new();
+    // Scripts starting the same way, which then run in parallel.
+    let mut hats = HashMap::<(BlockType, String), usize>::new();
+    let mut sequences = false;
+    for target in targets.iter() {
+        for block in target.blocks.values().filter(|b| !b.shadow) {
+            used.insert(block.block_type);
+        }
+        for (hat, script) in target.scripts() {
+            let field = |name| hat.fields.get(name).map(|f| f.value.to_lowercase());
+            let key = match hat.block_type {
+                BlockType::EventWhenKeyPressed => field("KEY_OPTION"),
+                BlockType::EventWhenBroadcastReceived => field("BROADCAST_OPTION"),
+                BlockType::EventWhenBackdropSwitchesTo => field("BACKDROP"),
+                BlockType::EventWhenGreaterThan => field("WHENGREATERTHANMENU"),
+                BlockType::EventWhenThisSpriteClicked => Some(target.name.to_string()),
+                _ => None,
+            };
+            *hats
+                .entry((hat.block_type, key.unwrap_or_default()))
+                .or_default() += 1;
+            sequences |= target.stack(Some(hat)).len() > 2 || script.len() > 2;
+        }
+    }
+    let uses = |opcodes: &[BlockType]| opcodes.iter().any(|o| used.contains(o));
+    let parallel = |opcodes: &[BlockType]| {
+        hats.iter()
+            .any(|((opcode, _), count)| *count > 1 && opcodes.contains(opcode))
+    };
+    let level = |levels: [bool; 3]| match levels {
+        [_, _, true] => 3,
+        [_, true, _] => 2,
+        [true, _, _] => 1,
+        _ => 0,
+    };
+    let sprites = targets.iter().filter(|t| !t.is_stage).count();
+    let script_count = targets.iter().map(|t| t.scripts().len()).sum::<usize>();
+    let mut thinking = Thinking {
+        abstraction: level([
+            sprites > 1 && script_count > 1,
+            uses(&[BlockType::ProceduresDefinition]),
+            uses(&[
+                BlockType::ControlCreateCloneOf,
+                BlockType::ControlStartAsClone,
+            ]),
+        ]),
+        parallelism: level([
+            parallel(&[BlockType::EventWhenFlagClicked]),
+            parallel(&[
+                BlockType::EventWhenKeyPressed,
+                BlockType::EventWhenThisSpriteClicked,
+            ]),
+            parallel(&[
+                BlockType::EventWhenBroadcastReceived,
+                BlockType::EventWhenBackdropSwitchesTo,
+                BlockType::EventWhenGreaterThan,
+                BlockType::ControlStartAsClone,
+            ]),
+        ]),
+        logic: level([
+            uses(&[BlockType::ControlIf]),
+            uses(&[BlockType::ControlIfElse]),
+            uses(&[
+                BlockType::OperatorAnd,
+                BlockType::OperatorOr,
+                BlockType::OperatorNot,
+            ]),
+        ]),
+        synchronization: level([
+            uses(&[BlockType::ControlWait]),
+            uses(&[
+                BlockType::EventBroadcast,
+                BlockType::EventWhenBroadcastReceived,
+                BlockType::ControlStop,
+            ]),
+            uses(&[
+                BlockType::ControlWaitUntil,
+                BlockType::EventWhenBackdropSwitchesTo,
+                BlockType::EventBroadcastandWait,
+            ]),
+        ]),
+        flow_control: level([
+            sequences,
+            uses(&[BlockType::ControlRepeat, BlockType::ControlForever]),
+            uses(&[BlockType::ControlRepeatUntil, BlockType::ControlWhile]),
+        ]),
+        user_interactivity: level([
+            uses(&[BlockType::EventWhenFlagClicked]),
+            uses(&[
+                BlockType::EventWhenKeyPressed,
+                BlockType::EventWhenThisSpriteClicked,
+                BlockType::EventWhenStageClicked,
+                BlockType::SensingAskAndWait,
+                BlockType::SensingKeyPressed,
+                BlockType::SensingMouseDown,
+                BlockType::SensingMouseX,
+                BlockType::SensingMouseY,
+            ]),
+            uses(&[BlockType::EventWhenGreaterThan]),
+        ]),
+        data_representation: level([
+            // Sprite properties, such as position, size, costume and effects.
+            used.iter().any(|o| {
+                let category = category(*o);
+                let speaks = matches!(
+                    o,
+                    BlockType::LooksSay
+                        | BlockType::LooksSayForSecs
+                        | BlockType::LooksThink
+                        | BlockType::LooksThinkForSecs
+                );
+                category == "motion" || (category == "looks" && !speaks)
+            }),
+            uses(&[
+                BlockType::DataSetVariableTo,
+                BlockType::DataChangeVariableBy,
+            ]),
+            used.iter().any(|o| {
+                matches!(
+                    o,
+                    BlockType::DataAddToList
+                        | BlockType::DataListDeleteElement
+                        | BlockType::DataListClear
+                        | BlockType::DataListInsertAt
+                        | BlockType::DataListReplaceItem
+                )
+            }),
+        ]),
+        total: 0,
+    };
+    thinking.total = thinking.abstraction
+        + thinking.parallelism
+        + thinking.logic
+        + thinking.synchronization
+        + thinking.flow_control
+        + thinking.user_interactivity
+        + thinking.data_representation;
+    thinking
+}
+
+/// The stats as CSV: a row per target, then a row for the project, with an
+/// empty `target` and the thinking scores.
+pub fn csv(projects: &[ProjectStats]) -> Result<String, ScratchError> {
+    let location = "writing CSV";
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut header = vec!["project", "target", "error", "scripts", "blocks"];
+    header.extend(CATEGORIES);
+    header.extend([
+        "other",
+        "max_depth",
+        "custom_blocks",
+        "custom_block_calls",
+        "variables",
+        "lists",
+        "broadcasts",
+        "costumes",
+        "sounds",
+        "asset_bytes",
+        "abstraction",
+        "parallelism",
+        "logic",
+        "synchronization",
+        "flow_control",
+        "user_interactivity",
+        "data_representation",
+        "thinking",
+    ]);
+    writer
+        .write_record(&header)
+        .map_err(|e| ScratchError::internal(e, location))?;
+    for project in projects.iter() {
+        let path = project.path.display().to_string();
+        let row = |target: &str, counts: &Counts, thinking: Option<&Thinking>| {
+            let mut row = vec![
+                path.clone(),
+                target.to_string(),
+                project.error.clone().unwrap_or_default(),
+                counts.scripts.to_string(),
+                counts.blocks.to_string(),
+            ];
+            let known = CATEGORIES
+                .iter()
+                .map(|c| counts.categories.get(*c).copied().unwrap_or(0))
+                .collect::<Vec<_>>();
+            let other = counts.blocks - known.iter().sum::<usize>();
+            row.extend(known.iter().map(usize::to_string));
+            row.push(other.to_string());
+            row.extend(
+                [
+                    counts.max_depth,
+                    counts.custom_blocks,
+                    counts.custom_block_calls,
+                    counts.variables,
+                    counts.lists,
+                    counts.broadcasts,
+                    counts.costumes,
+                    counts.sounds,
+                ]
+                .iter()
+                .map(usize::to_string),
+            );
+            row.push(counts.asset_bytes.to_string());
+            row.extend(match thinking {
+                Some(t) => [
+                    t.abstraction,
+                    t.parallelism,
+                    t.logic,
+                    t.synchronization,
+                    t.flow_control,
+                    t.user_interactivity,
+                    t.data_representation,
+                    t.total,
+                ]
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<_>>(),
+                None => vec![String::new(); 8],
+            });
+            row
+        };
+        let mut rows = project
+            .targets
+            .iter()
+            .map(|t| row(&t.name, &t.counts, None))
+            .collect::<Vec<_>>();
+        rows.push(row("", &project.total, Some(&project.thinking)));
+        for row in rows {
+            writer
+                .write_record(&row)
+                .map_err(|e| ScratchError::internal(e, location))?;
+        }
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| ScratchError::internal(e, location))?;
+    String::from_utf8(bytes).map_err(|e| ScratchError::internal(e, location))
+}
@@ -0,0 +1,81 @@
+//! Finds the blocks the interpreter cannot run yet, such as motion blocks,
+//! without running anything.
+
+use scratch_ast::model::BlockType;
+
+use crate::{
+    analysis::Diagnostic,
+    compiler::Layout,
+    vm::{
+        intepreter,
+        internals::{Expression, StackExpression, VMEvaluable},
+    },
+};
+
+/// Every block of a script that can run, or of the reporters in its inputs,
+/// that the interpreter does not support, in script order.
+pub fn unsupported(layout: &Layout) -> Vec<Diagnostic> {
+    let mut found = Vec::new();
+    for script in layout.scripts.iter() {
+        let target = &layout.startup.targets[script.target].0.name;
+        statements(&script.thread.code, &mut |exp| {
+            found.push(Diagnostic::new(
+                target,
+                exp,
+                "kcc cannot run this block yet",
+            ));
+        });
+    }
+    found
+}
+
+fn statements(code: &[Expression], f: &mut impl FnMut(&StackExpression)) {
+    for expression in code {
+        match expression {
+            Expression::Stack(header)
+            | Expression::InvokeBroadcast(header)
+            | Expression::Stop { header, .. } => reporters(header, f),
+            Expression::Conditional {
+                header,
+                then,
+                otherwise,
+            } => {
+                reporters(header, f);
+                statements(then, f);
+                statements(otherwise, f);
+            }
+            Expression::LoopTimes { header, body }
+            | Expression::LoopCondition { header, body }
+            | Expression::LoopForever { header, body } => {
+                reporters(header, f);
+                statements(body, f);
+            }
+            Expression::InvokeCustomBlock { arguments, .. } => {
+                let mut ids = arguments.keys().collect::<Vec<_>>();
+                ids.sort();
+                for id in ids {
+                    if let VMEvaluable::Block(b) = &arguments[id] {
+                        reporters(b, f);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn reporters(exp: &StackExpression, f: &mut impl FnMut(&StackExpression)) {
+    if !intepreter::supports(exp.opcode) {
+        f(exp);
+    }
+    // The inputs of a custom block definition only describe its arguments.
+    if exp.opcode == BlockType::ProceduresDefinition {
+        return;
+    }
+    let mut names = exp.dependencies.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        if let VMEvaluable::Block(b) = &exp.dependencies[name] {
+            reporters(b, f);
+        }
+    }
+}
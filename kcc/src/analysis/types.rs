@@ -0,0 +1,501 @@
+//! Infers whether every variable, list, custom block argument and reporter
+//! always holds a number, a boolean or a string, or a mix of them.
+//!
+//! Values only ever widen, so the scripts are walked again until nothing
+//! changes. Whatever a block cannot know, such as the answer to `ask`, is assumed
+//! to be anything it may report.
+
+use std::fmt;
+
+use hashbrown::HashMap;
+use scratch_ast::{
+    cast,
+    model::{BlockType, PrimitiveValue, RichValue},
+};
+
+use crate::{
+    analysis::{proccode, Diagnostic},
+    compiler::Layout,
+    vm::internals::{Expression, StackExpression, VMEvaluable, VMValuePointer},
+};
+
+/// A set of kinds of values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Type(u8);
+
+impl Type {
+    /// No value at all, e.g. the argument of a custom block nobody calls.
+    pub const NOTHING: Type = Type(0);
+    pub const NUMBER: Type = Type(1);
+    pub const BOOLEAN: Type = Type(2);
+    /// Text, unless it reads exactly like a number, as `12` does but `012` does not.
+    pub const STRING: Type = Type(4);
+    pub const ANY: Type = Type(7);
+
+    pub fn union(self, other: Type) -> Type {
+        Type(self.0 | other.0)
+    }
+
+    /// Whether some values of this type are of `other`.
+    pub fn may_be(self, other: Type) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Whether values of this type are of more than one kind.
+    pub fn is_mixed(self) -> bool {
+        self.0.count_ones() > 1
+    }
+
+    pub fn of_value(value: &RichValue) -> Type {
+        match value {
+            RichValue::Boolean(_) => Type::BOOLEAN,
+            RichValue::String(s) | RichValue::Color(s) | RichValue::Broadcast(s) => {
+                Type::of_text(s)
+            }
+            _ => Type::NUMBER,
+        }
+    }
+
+    pub fn of_primitive(value: &PrimitiveValue) -> Type {
+        match value {
+            PrimitiveValue::String(s) => Type::of_text(s),
+            _ => Type::NUMBER,
+        }
+    }
+
+    /// Text reading exactly like a number behaves like that number everywhere.
+    fn of_text(s: &str) -> Type {
+        let n = cast::str_to_number(s);
+        if !n.is_nan() && cast::number_to_string(n) == s {
+            Type::NUMBER
+        } else {
+            Type::STRING
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Type::NUMBER, "number"),
+            (Type::BOOLEAN, "boolean"),
+            (Type::STRING, "string"),
+        ]
+        .into_iter()
+        .filter(|(t, _)| self.may_be(*t))
+        .map(|(_, name)| name)
+        .collect::<Vec<_>>();
+        match names.is_empty() {
+            true => f.write_str("nothing"),
+            false => f.write_str(&names.join(" or ")),
+        }
+    }
+}
+
+/// What [`infer`] found out about a project.
+#[derive(Debug, Default)]
+pub struct Types {
+    /// By variable slot of the layout.
+    pub variables: Vec<Type>,
+    /// What the items of each list are, by list slot of the layout.
+    pub lists: Vec<Type>,
+    /// By script of the layout defining the custom block, and numeric ID of the argument.
+    pub arguments: HashMap<(usize, usize), Type>,
+    /// What each reporter block reports, by target and block ID.
+    pub reporters: HashMap<(usize, String), Type>,
+    /// Suspicious mixes, such as a boolean used as a number.
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Types {
+    /// Lists every variable and list by name, then every argument in script order,
+    /// with its type.
+    pub fn report(&self, layout: &Layout) -> String {
+        let mut out = String::new();
+        for (kind, names, types) in [
+            ("variable", &layout.variable_names, &self.variables),
+            ("list", &layout.list_names, &self.lists),
+        ] {
+            let mut named = names.iter().zip(types).collect::<Vec<_>>();
+            named.sort_by_key(|(name, _)| name.as_str());
+            for (name, t) in named {
+                out += &format!("{kind} {name}: {t}\n");
+            }
+        }
+        for (index, script) in layout.scripts.iter().enumerate() {
+            let mut names = script.thread.argument_names.iter().collect::<Vec<_>>();
+            names.sort_by_key(|(_, id)| **id);
+            for (name, id) in names {
+                let t = self
+                    .arguments
+                    .get(&(index, *id))
+                    .copied()
+                    .unwrap_or_default();
+                out += &format!(
+                    "argument {}/{} of {}: {t}\n",
+                    layout.startup.targets[script.target].0.name,
+                    name,
+                    proccode(script.thread).unwrap_or("?"),
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Infers the types of a project.
+pub fn infer(layout: &Layout) -> Types {
+    let mut types = Types {
+        variables: layout.variables.iter().map(Type::of_primitive).collect(),
+        lists: layout
+            .lists
+            .iter()
+            .map(|items| {
+                items
+                    .iter()
+                    .map(Type::of_primitive)
+                    .fold(Type::NOTHING, Type::union)
+            })
+            .collect(),
+        ..Default::default()
+    };
+    loop {
+        // The last walk, which changes nothing, keeps its reporters and warnings.
+        types.reporters.clear();
+        types.warnings.clear();
+        let mut changed = false;
+        for (index, script) in layout.scripts.iter().enumerate() {
+            let mut inference = Inference {
+                layout,
+                types: &mut types,
+                script: index,
+                target: script.target,
+                parameters: Layout::parameters(script.thread),
+                changed: false,
+            };
+            inference.statements(&script.thread.code);
+            changed |= inference.changed;
+        }
+        if !changed {
+            return types;
+        }
+    }
+}
+
+/// Walks a script, widening the types of what it stores.
+struct Inference<'a, 'b> {
+    layout: &'a Layout<'a>,
+    types: &'b mut Types,
+    script: usize,
+    target: usize,
+    parameters: Vec<usize>,
+    changed: bool,
+}
+
+/// How values of `t` may not be numbers, if they may be both numbers and something else.
+fn mix(t: Type) -> Option<&'static str> {
+    if t.may_be(Type::BOOLEAN) {
+        Some("may be a boolean")
+    } else if t.may_be(Type::NUMBER) && t.may_be(Type::STRING) {
+        Some("may be a number or text")
+    } else {
+        None
+    }
+}
+
+/// Widens `t` to include `with`, noting if it changed.
+fn widen(t: &mut Type, with: Type, changed: &mut bool) {
+    let widened = t.union(with);
+    if widened != *t {
+        *t = widened;
+        *changed = true;
+    }
+}
+
+impl Inference<'_, '_> {
+    fn warn(&mut self, exp: &StackExpression, message: impl ToString) {
+        let target = &self.layout.startup.targets[self.target].0.name;
+        self.types
+            .warnings
+            .push(Diagnostic::new(target, exp, message));
+    }
+
+    fn statements(&mut self, code: &[Expression]) {
+        for expression in code {
+            self.statement(expression);
+        }
+    }
+
+    fn statement(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Stack(exp) => self.command(exp),
+            Expression::Conditional {
+                header,
+                then,
+                otherwise,
+            } => {
+                self.inputs(header);
+                self.statements(then);
+                self.statements(otherwise);
+            }
+            Expression::LoopTimes { header, body } => {
+                self.numeric(header, "TIMES");
+                self.statements(body);
+            }
+            Expression::LoopCondition { header, body }
+            | Expression::LoopForever { header, body } => {
+                self.inputs(header);
+                self.statements(body);
+            }
+            Expression::InvokeBroadcast(header) | Expression::Stop { header, .. } => {
+                self.inputs(header)
+            }
+            Expression::InvokeCustomBlock {
+                target, arguments, ..
+            } => {
+                let definition = self.layout.procedure(self.target, *target);
+                let mut ids = arguments.keys().copied().collect::<Vec<_>>();
+                ids.sort();
+                for id in ids {
+                    let t = self.value(&arguments[&id], true);
+                    if let Some(index) = definition {
+                        let argument = self.types.arguments.entry((index, id)).or_default();
+                        widen(argument, t, &mut self.changed);
+                    }
+                }
+                // Arguments left out get their default value.
+                if let Some(index) = definition {
+                    let thread = self.layout.scripts[index].thread;
+                    for id in Layout::parameters(thread) {
+                        if arguments.contains_key(&id) {
+                            continue;
+                        }
+                        let t = thread
+                            .custom_block_arguments
+                            .get(&id)
+                            .map_or(Type::STRING, Type::of_primitive);
+                        let argument = self.types.arguments.entry((index, id)).or_default();
+                        widen(argument, t, &mut self.changed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Visits every input of a block, in a stable order.
+    fn inputs(&mut self, exp: &StackExpression) {
+        let mut names = exp.dependencies.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            self.value(&exp.dependencies[name], false);
+        }
+    }
+
+    fn variable(&self, exp: &StackExpression) -> Option<usize> {
+        let pointer = exp.argptr("VARIABLE").ok()?;
+        self.layout.variable(self.target, &pointer).ok()
+    }
+
+    fn list(&self, exp: &StackExpression) -> Option<usize> {
+        let pointer = exp.argptr("LIST").ok()?;
+        self.layout.list(self.target, &pointer).ok()
+    }
+
+    fn command(&mut self, exp: &StackExpression) {
+        match exp.opcode {
+            BlockType::DataSetVariableTo => {
+                let t = self.raw(exp, "VALUE");
+                if let Some(slot) = self.variable(exp) {
+                    widen(&mut self.types.variables[slot], t, &mut self.changed);
+                }
+            }
+            BlockType::DataChangeVariableBy => {
+                self.numeric(exp, "VALUE");
+                if let Some(slot) = self.variable(exp) {
+                    if let Some(mix) = mix(self.types.variables[slot]) {
+                        let name = &self.layout.variable_names[slot];
+                        self.warn(
+                            exp,
+                            format!("variable {name} {mix}, but is changed by a number"),
+                        );
+                    }
+                    widen(
+                        &mut self.types.variables[slot],
+                        Type::NUMBER,
+                        &mut self.changed,
+                    );
+                }
+            }
+            BlockType::DataAddToList
+            | BlockType::DataListInsertAt
+            | BlockType::DataListReplaceItem => {
+                if exp.argraw("INDEX").is_some() {
+                    self.input(exp, "INDEX");
+                }
+                let t = self.raw(exp, "ITEM");
+                if let Some(slot) = self.list(exp) {
+                    widen(&mut self.types.lists[slot], t, &mut self.changed);
+                }
+            }
+            BlockType::ControlWait => {
+                self.numeric(exp, "DURATION");
+            }
+            // The prototype only holds the argument reporters shown in the hat.
+            BlockType::ProceduresDefinition => (),
+            _ => self.inputs(exp),
+        }
+    }
+
+    /// The type of an evaluable. Fields are the value they point to if `resolve`
+    /// is set, and their displayed text otherwise.
+    fn value(&mut self, value: &VMEvaluable, resolve: bool) -> Type {
+        let pointer = match value {
+            VMEvaluable::Bare(value) => return Type::of_value(value),
+            VMEvaluable::Field(f) => match &f.pointer {
+                Some(pointer) if resolve => pointer,
+                _ => return Type::of_text(&f.display_value),
+            },
+            VMEvaluable::Pointer(pointer) => pointer,
+            VMEvaluable::Block(exp) => return self.reporter(exp),
+            VMEvaluable::Default => return Type::STRING,
+        };
+        match pointer {
+            VMValuePointer::Variable { .. } => self
+                .layout
+                .variable(self.target, pointer)
+                .map_or(Type::ANY, |slot| self.types.variables[slot]),
+            // A list in a reporter slot reports its contents, joined as text.
+            VMValuePointer::List { .. } | VMValuePointer::Broadcast { .. } => Type::STRING,
+        }
+    }
+
+    /// The type of an input of a block. Empty inputs are empty strings.
+    fn input(&mut self, exp: &StackExpression, name: &str) -> Type {
+        match exp.argraw(name) {
+            Some(value) => self.value(value, false),
+            None => Type::STRING,
+        }
+    }
+
+    /// Like [`Inference::input`], but fields are the value they point to.
+    fn raw(&mut self, exp: &StackExpression, name: &str) -> Type {
+        match exp.argraw(name) {
+            Some(value) => self.value(value, true),
+            None => Type::STRING,
+        }
+    }
+
+    /// An input used as a number, which warns about values that are not.
+    fn numeric(&mut self, exp: &StackExpression, name: &str) -> Type {
+        let t = self.input(exp, name);
+        let checked = match exp.argraw(name) {
+            // Items out of range count as 0, which is what reading them as numbers expects.
+            Some(VMEvaluable::Block(item)) if item.opcode == BlockType::DataListItemAt => {
+                self.list(item).map_or(t, |slot| self.types.lists[slot])
+            }
+            _ => t,
+        };
+        let literal = match exp.argraw(name) {
+            Some(VMEvaluable::Bare(RichValue::String(s))) => Some(s.clone()),
+            Some(VMEvaluable::Field(f)) if f.pointer.is_none() => Some(f.display_value.clone()),
+            _ => None,
+        };
+        if let Some(mix) = mix(checked) {
+            self.warn(exp, format!("{name} {mix}, but is used as a number"));
+        } else if let Some(s) = literal.filter(|s| cast::str_to_number(s).is_nan()) {
+            self.warn(exp, format!("{name} is the text {s:?}, which counts as 0"));
+        }
+        t
+    }
+
+    fn numerics(&mut self, exp: &StackExpression, names: &[&str]) {
+        for name in names {
+            self.numeric(exp, name);
+        }
+    }
+
+    /// The type of what a reporter reports, which is remembered.
+    fn reporter(&mut self, exp: &StackExpression) -> Type {
+        let t = match exp.opcode {
+            BlockType::OperatorAdd
+            | BlockType::OperatorSubtract
+            | BlockType::OperatorMultiply
+            | BlockType::OperatorDivide
+            | BlockType::OperatorMod => {
+                self.numerics(exp, &["NUM1", "NUM2"]);
+                Type::NUMBER
+            }
+            BlockType::OperatorRound | BlockType::OperatorMathop => {
+                self.numeric(exp, "NUM");
+                Type::NUMBER
+            }
+            BlockType::OperatorRandom => {
+                self.numerics(exp, &["FROM", "TO"]);
+                Type::NUMBER
+            }
+            BlockType::OperatorGt | BlockType::OperatorLt | BlockType::OperatorEquals => {
+                let left = self.input(exp, "OPERAND1");
+                let right = self.input(exp, "OPERAND2");
+                let (boolean, number) = (Type::BOOLEAN, Type::NUMBER);
+                if (left == boolean && right == number) || (left == number && right == boolean) {
+                    self.warn(exp, "compares a boolean with a number");
+                }
+                Type::BOOLEAN
+            }
+            BlockType::OperatorAnd
+            | BlockType::OperatorOr
+            | BlockType::OperatorNot
+            | BlockType::OperatorContains
+            | BlockType::DataListContainsItem
+            | BlockType::SensingKeyPressed
+            | BlockType::SensingMouseDown => {
+                self.inputs(exp);
+                Type::BOOLEAN
+            }
+            BlockType::OperatorJoin | BlockType::SensingAnswer | BlockType::SensingUsername => {
+                self.inputs(exp);
+                Type::STRING
+            }
+            BlockType::OperatorLetterOf => {
+                self.numeric(exp, "LETTER");
+                self.input(exp, "STRING");
+                Type::STRING
+            }
+            BlockType::OperatorLength
+            | BlockType::SensingTimer
+            | BlockType::SensingDaysSince2000
+            | BlockType::DataListIndexOf
+            | BlockType::DataListLengthOf => {
+                self.inputs(exp);
+                Type::NUMBER
+            }
+            BlockType::DataListItemAt => {
+                self.inputs(exp);
+                // Items out of range are empty strings.
+                self.list(exp)
+                    .map_or(Type::ANY, |slot| self.types.lists[slot])
+                    .union(Type::STRING)
+            }
+            BlockType::ArgumentReporterStringNumber | BlockType::ArgumentReporterBoolean => {
+                let script = &self.layout.scripts[self.script];
+                match self.layout.argument(script, &self.parameters, exp) {
+                    Some(i) => self
+                        .types
+                        .arguments
+                        .get(&(self.script, self.parameters[i]))
+                        .copied()
+                        .unwrap_or_default(),
+                    None => Type::STRING,
+                }
+            }
+            _ => {
+                self.inputs(exp);
+                Type::ANY
+            }
+        };
+        self.types
+            .reporters
+            .insert((self.target, exp.original_block.obj_id.clone()), t);
+        t
+    }
+}
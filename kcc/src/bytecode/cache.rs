@@ -0,0 +1,76 @@
+//! An on-disk cache of compiled projects, keyed by the SHA-256 of their `project.json`
+//! and the version of kcc and of [lowering](lower::VERSION) that compiled them.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use scratch_ast::errors::ScratchError;
+use sha2::{Digest, Sha256};
+
+use crate::bytecode::{lower, Program};
+
+/// Where compiled projects are kept: `dir` if given, otherwise `$KCC_CACHE_DIR`,
+/// `$XDG_CACHE_HOME/kcc` or `~/.cache/kcc`.
+pub fn directory(dir: Option<PathBuf>) -> Option<PathBuf> {
+    let var = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty());
+    dir.or_else(|| var("KCC_CACHE_DIR").map(PathBuf::from))
+        .or_else(|| var("XDG_CACHE_HOME").map(|d| PathBuf::from(d).join("kcc")))
+        .or_else(|| var("HOME").map(|d| PathBuf::from(d).join(".cache").join("kcc")))
+}
+
+/// The hex SHA-256 of the `project.json` of a `.sb3` file.
+pub fn project_hash(project: &Path) -> Result<String, ScratchError> {
+    let location = format!("hashing {}", project.display());
+    let file = File::open(project).map_err(|e| ScratchError::internal(e, &location))?;
+    let mut archive =
+        zip::ZipArchive::new(file).map_err(|e| ScratchError::type_error(e, &location))?;
+    let mut json = Vec::new();
+    archive
+        .by_name("project.json")
+        .map_err(|e| ScratchError::not_found(e, &location))?
+        .read_to_end(&mut json)
+        .map_err(|e| ScratchError::internal(e, &location))?;
+    Ok(Sha256::digest(&json)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Loads the compiled `project` from the cache in `dir`. On a miss, compiles it
+/// with `lower` and keeps the result for next time. Other versions of kcc, or
+/// of lowering, look for other files and never use this one. `variant` tells apart programs compiled from
+/// the same project with other options.
+pub fn load_or_lower(
+    project: &Path,
+    dir: &Path,
+    variant: &str,
+    lower: impl FnOnce() -> Result<Program, ScratchError>,
+) -> Result<Program, ScratchError> {
+    let path = dir.join(format!(
+        "{}{variant}-{}-{}.kbc",
+        project_hash(project)?,
+        env!("CARGO_PKG_VERSION"),
+        lower::VERSION
+    ));
+    match Program::load(&path) {
+        Ok(program) => {
+            debug!("loaded {} from {}", project.display(), path.display());
+            return Ok(program);
+        }
+        Err(e) if path.exists() => debug!("recompiling {}: {e}", path.display()),
+        Err(_) => debug!("{} is not cached yet", project.display()),
+    }
+    let program = lower()?;
+    // A cache that cannot be written only makes the next run slower.
+    if let Err(e) = fs::create_dir_all(dir)
+        .map_err(|e| ScratchError::internal(e, format!("creating {}", dir.display())))
+        .and_then(|_| program.save(&path))
+    {
+        warn!("not caching compiled project: {e}");
+    }
+    Ok(program)
+}
@@ -0,0 +1,339 @@
+//! Lowers the IR of a project into bytecode.
+//!
+//! Every value gets a local, the arguments first. Values used once, right where
+//! the stack has them on top, stay on the stack instead, and constants are pushed
+//! wherever they are used.
+
+use hashbrown::{HashMap, HashSet};
+use scratch_ast::errors::ScratchError;
+
+use crate::{
+    bytecode::{Constant, Op, Program, Script},
+    compiler::Layout,
+    ir::{self, BlockId, Target, Terminator, Value},
+};
+
+/// Bumped whenever a project lowers to other bytecode than before, in the IR,
+/// the optimizer or here, so that programs cached by older builds are not used.
+pub const VERSION: u32 = 3;
+
+pub fn lower(layout: &Layout) -> Result<Program, ScratchError> {
+    let program = ir::lower::lower(layout)?;
+    let mut lowerer = Lowerer {
+        constants: Vec::new(),
+        constant_ids: HashMap::new(),
+    };
+    let scripts = program
+        .functions
+        .iter()
+        .map(|function| lowerer.function(function))
+        .collect();
+    Ok(Program {
+        variables: program.variables.iter().map(|v| v.value.clone()).collect(),
+        lists: program.lists.iter().map(|l| l.items.clone()).collect(),
+        constants: lowerer.constants,
+        scripts,
+    })
+}
+
+struct Lowerer {
+    constants: Vec<Constant>,
+    /// Constants are deduplicated by their debug representation, as floats are not `Hash`.
+    constant_ids: HashMap<String, u32>,
+}
+
+/// The function being lowered.
+struct Scope<'a> {
+    function: &'a ir::Function,
+    /// The constant each value defined by a constant instruction is.
+    constants: HashMap<Value, &'a Constant>,
+    /// The values left on the stack, rather than kept in a local.
+    stacked: HashSet<Value>,
+    code: Vec<Op>,
+    /// Where each block starts.
+    starts: Vec<u32>,
+    /// Jumps to blocks, to point at their start once every block is lowered.
+    jumps: Vec<(usize, BlockId)>,
+}
+
+impl Scope<'_> {
+    fn emit(&mut self, op: Op) {
+        self.code.push(op);
+    }
+
+    fn jump(&mut self, op: Op, to: BlockId) {
+        self.jumps.push((self.code.len(), to));
+        self.emit(op);
+    }
+}
+
+fn local(value: Value) -> u32 {
+    value.0
+}
+
+/// How many times each value is read, and whether its only read, if any, may
+/// take it off the stack: an operand of an instruction or the condition of a
+/// branch in the block defining it.
+fn uses(function: &ir::Function) -> (Vec<u32>, HashSet<Value>) {
+    let mut uses = vec![0; function.types.len()];
+    let mut stackable = HashSet::new();
+    for block in function.blocks.iter() {
+        let defined = block
+            .instructions
+            .iter()
+            .filter_map(|i| i.result)
+            .collect::<HashSet<_>>();
+        let mut read = |value: Value, may_pop: bool| {
+            uses[value.0 as usize] += 1;
+            if may_pop && defined.contains(&value) {
+                stackable.insert(value);
+            }
+        };
+        for instruction in block.instructions.iter() {
+            instruction.operands.iter().for_each(|v| read(*v, true));
+        }
+        for operand in block.terminator.operands() {
+            read(
+                operand,
+                matches!(block.terminator, Terminator::Branch { .. }),
+            );
+        }
+        for target in block.terminator.targets() {
+            target.arguments.iter().for_each(|v| read(*v, false));
+        }
+    }
+    stackable.retain(|v| uses[v.0 as usize] == 1);
+    (uses, stackable)
+}
+
+impl Lowerer {
+    fn constant(&mut self, constant: &Constant) -> Op {
+        let key = format!("{constant:?}");
+        let id = *self.constant_ids.entry(key).or_insert_with(|| {
+            self.constants.push(constant.clone());
+            self.constants.len() as u32 - 1
+        });
+        Op::Constant(id)
+    }
+
+    fn function(&mut self, function: &ir::Function) -> Script {
+        let mut constants = HashMap::new();
+        for instruction in function.blocks.iter().flat_map(|b| &b.instructions) {
+            if let (Some(result), ir::Op::Constant(c)) = (instruction.result, &instruction.op) {
+                constants.insert(result, c);
+            }
+        }
+        let (uses, mut stacked) = uses(function);
+        stacked.retain(|v| !constants.contains_key(v));
+        let mut scope = Scope {
+            function,
+            constants,
+            stacked,
+            code: Vec::new(),
+            starts: Vec::new(),
+            jumps: Vec::new(),
+        };
+        for block in function.blocks.iter() {
+            while !schedule(block, &mut scope.stacked) {}
+        }
+        for (index, block) in function.blocks.iter().enumerate() {
+            scope.starts.push(scope.code.len() as u32);
+            for instruction in block.instructions.iter() {
+                self.instruction(instruction, &uses, &mut scope);
+            }
+            let next = BlockId(index as u32 + 1);
+            self.terminator(&block.terminator, next, &mut scope);
+        }
+        if scope.code.last() != Some(&Op::Return) {
+            scope.emit(Op::Return);
+        }
+        for (at, to) in std::mem::take(&mut scope.jumps) {
+            let start = scope.starts[to.0 as usize];
+            match &mut scope.code[at] {
+                Op::Jump(to) | Op::JumpIfFalse(to) => *to = start,
+                op => unreachable!("patching {op:?}, which does not jump"),
+            }
+        }
+        Script {
+            trigger: function.trigger.clone(),
+            parameters: function.parameters().len() as u32,
+            locals: function.types.len() as u32,
+            code: scope.code,
+        }
+    }
+
+    /// Pushes values that are not on the stack already.
+    fn push(&mut self, values: &[Value], scope: &mut Scope) {
+        for value in values {
+            if scope.stacked.contains(value) {
+                continue;
+            }
+            let op = match scope.constants.get(value) {
+                Some(constant) => self.constant(constant),
+                None => Op::Local(local(*value)),
+            };
+            scope.emit(op);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &ir::Instruction, uses: &[u32], scope: &mut Scope) {
+        if matches!(instruction.op, ir::Op::Constant(_)) {
+            return;
+        }
+        self.push(&instruction.operands, scope);
+        let result = instruction.result.map(local);
+        scope.emit(match &instruction.op {
+            ir::Op::Constant(_) => unreachable!("constants are pushed where they are used"),
+            ir::Op::Variable(v) => Op::Variable(*v),
+            ir::Op::SetVariable(v) => Op::SetVariable(*v),
+            ir::Op::ChangeVariable(v) => Op::ChangeVariable(*v),
+            ir::Op::ListContents(l) => Op::ListContents(*l),
+            ir::Op::AddToList(l) => Op::AddToList(*l),
+            ir::Op::DeleteOfList(l) => Op::DeleteOfList(*l),
+            ir::Op::DeleteAllOfList(l) => Op::DeleteAllOfList(*l),
+            ir::Op::InsertAtList(l) => Op::InsertAtList(*l),
+            ir::Op::ReplaceItemOfList(l) => Op::ReplaceItemOfList(*l),
+            ir::Op::ItemOfList(l) => Op::ItemOfList(*l),
+            ir::Op::ItemNumOfList(l) => Op::ItemNumOfList(*l),
+            ir::Op::LengthOfList(l) => Op::LengthOfList(*l),
+            ir::Op::ListContainsItem(l) => Op::ListContainsItem(*l),
+            ir::Op::Add => Op::Add,
+            ir::Op::Subtract => Op::Subtract,
+            ir::Op::Multiply => Op::Multiply,
+            ir::Op::Divide => Op::Divide,
+            ir::Op::Random => Op::Random,
+            ir::Op::Gt => Op::Gt,
+            ir::Op::Lt => Op::Lt,
+            ir::Op::Equals => Op::Equals,
+            ir::Op::And => Op::And,
+            ir::Op::Or => Op::Or,
+            ir::Op::Not => Op::Not,
+            ir::Op::Join => Op::Join,
+            ir::Op::LetterOf => Op::LetterOf,
+            ir::Op::Length => Op::Length,
+            ir::Op::Contains => Op::Contains,
+            ir::Op::Mod => Op::Mod,
+            ir::Op::Round => Op::Round,
+            ir::Op::MathOp(op) => Op::MathOp(*op),
+            ir::Op::Say => Op::Say,
+            ir::Op::Think => Op::Think,
+            ir::Op::Ask => Op::Ask,
+            ir::Op::Answer => Op::Answer,
+            ir::Op::Timer => Op::Timer,
+            ir::Op::ResetTimer => Op::ResetTimer,
+            ir::Op::DaysSince2000 => Op::DaysSince2000,
+            ir::Op::Deadline => return scope.emit(Op::Deadline(result.unwrap())),
+            ir::Op::DeadlineMillis => return scope.emit(Op::DeadlineMillis(result.unwrap())),
+            ir::Op::Broadcast => Op::Broadcast,
+            ir::Op::Call(f) => Op::Call(*f),
+        });
+        if let Some(value) = instruction.result {
+            if uses[value.0 as usize] == 0 {
+                scope.emit(Op::Pop);
+            } else if !scope.stacked.contains(&value) {
+                scope.emit(Op::SetLocal(local(value)));
+            }
+        }
+    }
+
+    /// Copies the arguments of `target` into the parameters of its block, all
+    /// at once as they may be the same locals.
+    fn arguments(&mut self, target: &Target, scope: &mut Scope) {
+        let parameters = &scope.function.blocks[target.block.0 as usize].parameters;
+        self.push(&target.arguments, scope);
+        for parameter in parameters.iter().rev() {
+            scope.emit(Op::SetLocal(local(*parameter)));
+        }
+    }
+
+    /// Goes to `target`, unless it is the block that comes `next`.
+    fn go(&mut self, target: &Target, next: BlockId, scope: &mut Scope) {
+        self.arguments(target, scope);
+        if target.block != next {
+            scope.jump(Op::Jump(0), target.block);
+        }
+    }
+
+    fn terminator(&mut self, terminator: &Terminator, next: BlockId, scope: &mut Scope) {
+        match terminator {
+            Terminator::Jump(target) => self.go(target, next, scope),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.push(&[*condition], scope);
+                if then.arguments.is_empty() && otherwise.arguments.is_empty() {
+                    scope.jump(Op::JumpIfFalse(0), otherwise.block);
+                    self.go(then, next, scope);
+                } else {
+                    let to_otherwise = scope.code.len();
+                    scope.emit(Op::JumpIfFalse(0));
+                    self.go(then, BlockId(u32::MAX), scope);
+                    let here = scope.code.len() as u32;
+                    scope.code[to_otherwise] = Op::JumpIfFalse(here);
+                    self.go(otherwise, next, scope);
+                }
+            }
+            Terminator::Yield(target) => {
+                self.arguments(target, scope);
+                scope.emit(Op::Yield);
+                if target.block != next {
+                    scope.jump(Op::Jump(0), target.block);
+                }
+            }
+            Terminator::Sleep { deadline, resume } => {
+                scope.emit(Op::Sleep(local(*deadline)));
+                self.go(resume, next, scope);
+            }
+            Terminator::AwaitBroadcast { name, resume } => {
+                // Constants are not kept in locals unless they have to.
+                if scope.constants.contains_key(name) {
+                    self.push(&[*name], scope);
+                    scope.emit(Op::SetLocal(local(*name)));
+                }
+                scope.emit(Op::AwaitBroadcast(local(*name)));
+                self.go(resume, next, scope);
+            }
+            Terminator::Return => scope.emit(Op::Return),
+            Terminator::StopAll => scope.emit(Op::StopAll),
+        }
+    }
+}
+
+/// Checks that the values of `block` marked `stacked` are on top of the stack,
+/// in order, when they are read. Unmarks those that are not and returns false,
+/// so that scheduling is tried again.
+fn schedule(block: &ir::Block, stacked: &mut HashSet<Value>) -> bool {
+    let mut stack = Vec::new();
+    for instruction in block.instructions.iter() {
+        if !take(&instruction.operands, &mut stack, stacked) {
+            return false;
+        }
+        if let Some(result) = instruction.result.filter(|r| stacked.contains(r)) {
+            stack.push(result);
+        }
+    }
+    if !take(&block.terminator.operands(), &mut stack, stacked) {
+        return false;
+    }
+    for value in stack.iter() {
+        stacked.remove(value);
+    }
+    stack.is_empty()
+}
+
+/// Pops the `operands` marked `stacked`, which must come first and be on top of `stack`.
+fn take(operands: &[Value], stack: &mut Vec<Value>, stacked: &mut HashSet<Value>) -> bool {
+    let on_stack = operands.iter().take_while(|v| stacked.contains(*v)).count();
+    let valid = operands[on_stack..].iter().all(|v| !stacked.contains(v))
+        && stack.ends_with(&operands[..on_stack]);
+    if !valid {
+        for operand in operands {
+            stacked.remove(operand);
+        }
+        return false;
+    }
+    stack.truncate(stack.len() - on_stack);
+    true
+}
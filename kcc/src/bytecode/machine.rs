@@ -0,0 +1,512 @@
+//! Runs bytecode on a single OS thread. Scripts take turns: each one runs
+//! until it yields at the end of a loop iteration or waits, like in Scratch.
+
+use std::cmp::Ordering;
+
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+use scratch_ast::{
+    cast,
+    errors::ScratchError,
+    model::{PrimitiveValue, RichValue},
+};
+
+use crate::{
+    bytecode::{MathOp, Op, Program, Trigger},
+    vm::{
+        argaccess::list_contents,
+        host,
+        list::{self, VMList},
+        Ending,
+    },
+};
+
+/// A custom block or script being run by a thread.
+struct Frame {
+    script: u32,
+    pc: usize,
+    /// Arguments first, then the locals the script needs.
+    locals: Vec<RichValue>,
+}
+
+struct Thread {
+    /// The script the thread started with.
+    script: u32,
+    frames: Vec<Frame>,
+    stack: Vec<RichValue>,
+    /// When a waiting thread may run again, in seconds since the start of the project.
+    wake: Option<f64>,
+}
+
+/// How the turn of a thread ended.
+enum Turn {
+    Yielded,
+    Waiting(f64),
+    Done,
+    StopAll,
+    StepLimit,
+}
+
+struct Machine<'a> {
+    program: &'a Program,
+    variables: Vec<PrimitiveValue>,
+    lists: Vec<VMList>,
+    /// The scripts receiving each broadcast, by lowercase name.
+    receivers: HashMap<&'a str, Vec<u32>>,
+    threads: Vec<Thread>,
+    /// The script of the thread taking its turn, which restarts once its turn ends
+    /// if `restart` is set.
+    current: Option<u32>,
+    restart: bool,
+    /// When the timer was reset, in seconds of the host clock.
+    timer: f64,
+    answer: String,
+}
+
+/// Runs a program from the green flag until every script has finished.
+pub fn run(program: &Program) -> Result<Ending, ScratchError> {
+    let mut receivers = HashMap::<&str, Vec<u32>>::new();
+    for (i, script) in program.scripts.iter().enumerate() {
+        if let Trigger::Broadcast(name) = &script.trigger {
+            receivers.entry(name.as_str()).or_default().push(i as u32);
+        }
+    }
+    let mut machine = Machine {
+        program,
+        variables: program.variables.iter().map(PrimitiveValue::from).collect(),
+        lists: program
+            .lists
+            .iter()
+            .map(|list| {
+                list.iter()
+                    .map(|item| RwLock::new(PrimitiveValue::from(item)))
+                    .collect()
+            })
+            .collect(),
+        receivers,
+        threads: Vec::new(),
+        current: None,
+        restart: false,
+        timer: host::get().now(),
+        answer: String::new(),
+    };
+    for (i, script) in program.scripts.iter().enumerate() {
+        if script.trigger == Trigger::GreenFlag {
+            machine.start(i as u32);
+        }
+    }
+    machine.run()
+}
+
+fn location(script: u32, pc: usize) -> String {
+    format!("running bytecode script {script} at {pc}")
+}
+
+fn pop(stack: &mut Vec<RichValue>) -> RichValue {
+    stack
+        .pop()
+        .expect("malformed bytecode popped an empty stack")
+}
+
+fn number(stack: &mut Vec<RichValue>) -> f64 {
+    cast::to_number(&pop(stack))
+}
+
+fn string(stack: &mut Vec<RichValue>) -> String {
+    cast::to_string(&pop(stack))
+}
+
+fn boolean(stack: &mut Vec<RichValue>) -> bool {
+    cast::to_boolean(&pop(stack))
+}
+
+/// Pops the right operand, then the left one.
+fn operands(stack: &mut Vec<RichValue>) -> (RichValue, RichValue) {
+    let right = pop(stack);
+    (pop(stack), right)
+}
+
+fn random(from: &RichValue, to: &RichValue) -> f64 {
+    let n1 = cast::to_number(from);
+    let n2 = cast::to_number(to);
+    let (low, high) = if n1 <= n2 { (n1, n2) } else { (n2, n1) };
+    if low == high {
+        return low;
+    }
+    let random = host::get().random();
+    if cast::is_int(from) && cast::is_int(to) {
+        return low + (random * (high - low + 1.0)).floor();
+    }
+    low + random * (high - low)
+}
+
+fn mathop(op: MathOp, n: f64) -> f64 {
+    // Scratch rounds trigonometric results to 10 decimal places,
+    // so that e.g. sin(180) is exactly 0.
+    let round10 = |x: f64| (x * 1e10).round() / 1e10;
+    let radians = |x: f64| std::f64::consts::PI * x / 180.0;
+    match op {
+        MathOp::Abs => n.abs(),
+        MathOp::Floor => n.floor(),
+        MathOp::Ceiling => n.ceil(),
+        MathOp::Sqrt => n.sqrt(),
+        MathOp::Sin => round10(radians(n).sin()),
+        MathOp::Cos => round10(radians(n).cos()),
+        MathOp::Tan => match n % 360.0 {
+            -270.0 | 90.0 => f64::INFINITY,
+            -90.0 | 270.0 => f64::NEG_INFINITY,
+            _ => round10(radians(n % 360.0).tan()),
+        },
+        MathOp::Asin => n.asin().to_degrees(),
+        MathOp::Acos => n.acos().to_degrees(),
+        MathOp::Atan => n.atan().to_degrees(),
+        MathOp::Ln => n.ln(),
+        MathOp::Log => n.log10(),
+        MathOp::Exp => n.exp(),
+        MathOp::Pow10 => 10f64.powf(n),
+    }
+}
+
+impl Machine<'_> {
+    fn thread(&self, script: u32) -> Thread {
+        let definition = &self.program.scripts[script as usize];
+        Thread {
+            script,
+            frames: vec![Frame {
+                script,
+                pc: 0,
+                locals: vec![RichValue::Number(0.0); definition.locals as usize],
+            }],
+            stack: Vec::new(),
+            wake: None,
+        }
+    }
+
+    /// Starts a script, restarting it if it is already running.
+    fn start(&mut self, script: u32) {
+        if self.current == Some(script) {
+            self.restart = true;
+        } else if let Some(i) = self.threads.iter().position(|t| t.script == script) {
+            self.threads[i] = self.thread(script);
+        } else {
+            let thread = self.thread(script);
+            self.threads.push(thread);
+        }
+    }
+
+    /// Seconds since the project started.
+    fn now(&self) -> f64 {
+        host::get().now()
+    }
+
+    fn run(&mut self) -> Result<Ending, ScratchError> {
+        while !self.threads.is_empty() {
+            let mut ready = false;
+            let mut earliest = f64::INFINITY;
+            let mut i = 0;
+            while i < self.threads.len() {
+                if let Some(wake) = self.threads[i].wake {
+                    if self.now() < wake {
+                        earliest = earliest.min(wake);
+                        i += 1;
+                        continue;
+                    }
+                }
+                // The thread is taken out while it runs, so that it cannot be found,
+                // e.g. by `broadcast and wait`.
+                let mut thread = std::mem::replace(
+                    &mut self.threads[i],
+                    Thread {
+                        script: u32::MAX,
+                        frames: Vec::new(),
+                        stack: Vec::new(),
+                        wake: None,
+                    },
+                );
+                thread.wake = None;
+                self.current = Some(thread.script);
+                let turn = self.turn(&mut thread);
+                self.current = None;
+                let turn = turn?;
+                if std::mem::take(&mut self.restart) {
+                    self.threads[i] = self.thread(thread.script);
+                    ready = true;
+                    i += 1;
+                    continue;
+                }
+                match turn {
+                    Turn::Yielded => ready = true,
+                    Turn::Waiting(wake) => {
+                        thread.wake = Some(wake);
+                        earliest = earliest.min(wake);
+                    }
+                    Turn::Done => {
+                        self.threads.remove(i);
+                        continue;
+                    }
+                    Turn::StopAll => return Ok(Ending::StopAll),
+                    Turn::StepLimit => return Ok(Ending::StepLimit),
+                }
+                self.threads[i] = thread;
+                i += 1;
+            }
+            if ready {
+                host::get().tick();
+            } else if earliest.is_finite() {
+                host::get().sleep_until(earliest);
+            }
+        }
+        Ok(Ending::Finished)
+    }
+
+    /// Whether a script receiving `name` is still running.
+    fn receiving(&self, name: &str) -> bool {
+        let Some(scripts) = self.receivers.get(name) else {
+            return false;
+        };
+        self.threads.iter().any(|t| scripts.contains(&t.script))
+    }
+
+    /// Runs a thread until it yields, waits or finishes.
+    fn turn(&mut self, thread: &mut Thread) -> Result<Turn, ScratchError> {
+        let program = self.program;
+        let stack = &mut thread.stack;
+        let host = host::get();
+        loop {
+            let Some(frame) = thread.frames.last_mut() else {
+                return Ok(Turn::Done);
+            };
+            if !host.step() {
+                return Ok(Turn::StepLimit);
+            }
+            let op = &program.scripts[frame.script as usize].code[frame.pc];
+            frame.pc += 1;
+            match op {
+                Op::Constant(i) => stack.push(RichValue::from(&program.constants[*i as usize])),
+                Op::Local(i) => stack.push(frame.locals[*i as usize].clone()),
+                Op::SetLocal(i) => frame.locals[*i as usize] = pop(stack),
+                Op::Pop => {
+                    pop(stack);
+                }
+
+                Op::Variable(i) => stack.push(RichValue::from(&self.variables[*i as usize])),
+                Op::SetVariable(i) => self.variables[*i as usize] = pop(stack).into(),
+                Op::ChangeVariable(i) => {
+                    let delta = number(stack);
+                    let variable = &mut self.variables[*i as usize];
+                    *variable = (variable.to_number() + delta).into();
+                }
+                Op::ListContents(i) => {
+                    stack.push(RichValue::String(list_contents(&self.lists[*i as usize])))
+                }
+                Op::AddToList(i) => list::add(&mut self.lists[*i as usize], pop(stack).into()),
+                Op::DeleteOfList(i) => list::delete(&mut self.lists[*i as usize], &pop(stack)),
+                Op::DeleteAllOfList(i) => self.lists[*i as usize].clear(),
+                Op::InsertAtList(i) => {
+                    let (index, item) = operands(stack);
+                    list::insert(&mut self.lists[*i as usize], &index, item.into());
+                }
+                Op::ReplaceItemOfList(i) => {
+                    let (index, item) = operands(stack);
+                    list::replace(&mut self.lists[*i as usize], &index, item.into());
+                }
+                Op::ItemOfList(i) => {
+                    let index = pop(stack);
+                    stack.push(list::item(&self.lists[*i as usize], &index));
+                }
+                Op::ItemNumOfList(i) => {
+                    let item = pop(stack);
+                    let index = list::index_of(&self.lists[*i as usize], &item);
+                    stack.push(RichValue::Number(index as f64));
+                }
+                Op::LengthOfList(i) => {
+                    stack.push(RichValue::Number(self.lists[*i as usize].len() as f64))
+                }
+                Op::ListContainsItem(i) => {
+                    let item = pop(stack);
+                    let contains = list::contains(&self.lists[*i as usize], &item);
+                    stack.push(RichValue::Boolean(contains));
+                }
+
+                Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Mod => {
+                    let right = number(stack);
+                    let left = number(stack);
+                    stack.push(RichValue::Number(match op {
+                        Op::Add => left + right,
+                        Op::Subtract => left - right,
+                        Op::Multiply => left * right,
+                        Op::Divide => left / right,
+                        _ => {
+                            let mut result = left % right;
+                            // Scratch's mod takes the sign of the divisor.
+                            if result / right < 0.0 {
+                                result += right;
+                            }
+                            result
+                        }
+                    }));
+                }
+                Op::Random => {
+                    let (from, to) = operands(stack);
+                    stack.push(RichValue::Number(random(&from, &to)));
+                }
+                Op::Gt | Op::Lt | Op::Equals => {
+                    let (left, right) = operands(stack);
+                    let expected = match op {
+                        Op::Gt => Ordering::Greater,
+                        Op::Lt => Ordering::Less,
+                        _ => Ordering::Equal,
+                    };
+                    stack.push(RichValue::Boolean(cast::compare(&left, &right) == expected));
+                }
+                Op::And => {
+                    let right = boolean(stack);
+                    let left = boolean(stack);
+                    stack.push(RichValue::Boolean(left && right));
+                }
+                Op::Or => {
+                    let right = boolean(stack);
+                    let left = boolean(stack);
+                    stack.push(RichValue::Boolean(left || right));
+                }
+                Op::Not => {
+                    let value = boolean(stack);
+                    stack.push(RichValue::Boolean(!value));
+                }
+                Op::Join => {
+                    let right = string(stack);
+                    let left = string(stack);
+                    stack.push(RichValue::String(left + &right));
+                }
+                Op::LetterOf => {
+                    let s = string(stack);
+                    let index = number(stack) - 1.0;
+                    stack.push(RichValue::String(
+                        if index < 0.0 || index >= cast::js_length(&s) as f64 {
+                            String::new()
+                        } else {
+                            cast::js_char_at(&s, index as usize)
+                        },
+                    ));
+                }
+                Op::Length => {
+                    let s = string(stack);
+                    stack.push(RichValue::Number(cast::js_length(&s) as f64));
+                }
+                Op::Contains => {
+                    let right = string(stack).to_lowercase();
+                    let left = string(stack).to_lowercase();
+                    stack.push(RichValue::Boolean(left.contains(&right)));
+                }
+                Op::Round => {
+                    let n = number(stack);
+                    stack.push(RichValue::Number(cast::round(n)));
+                }
+                Op::MathOp(operator) => {
+                    let n = number(stack);
+                    let Some(operator) = operator else {
+                        return Err(ScratchError::syntax_error(
+                            "unknown math operator",
+                            location(frame.script, frame.pc - 1),
+                        ));
+                    };
+                    stack.push(RichValue::Number(mathop(*operator, n)));
+                }
+
+                Op::Say => host.say(&string(stack)),
+                Op::Think => host.think(&string(stack)),
+                Op::Ask => {
+                    let question = string(stack);
+                    self.answer = host.ask(&question).map_err(|e| {
+                        ScratchError::internal(e, location(frame.script, frame.pc - 1))
+                    })?;
+                }
+                Op::Answer => stack.push(RichValue::String(self.answer.clone())),
+                Op::Timer => stack.push(RichValue::Number(self.now() - self.timer)),
+                Op::ResetTimer => self.timer = self.now(),
+                Op::DaysSince2000 => {
+                    let days = host.days_since_2000().map_err(|e| {
+                        ScratchError::internal(
+                            format!("time travelled into the past: {e}"),
+                            location(frame.script, frame.pc - 1),
+                        )
+                    })?;
+                    stack.push(RichValue::Number(days));
+                }
+
+                Op::Jump(to) => frame.pc = *to as usize,
+                Op::JumpIfFalse(to) => {
+                    if !boolean(stack) {
+                        frame.pc = *to as usize;
+                    }
+                }
+                Op::JumpIfTrue(to) => {
+                    if boolean(stack) {
+                        frame.pc = *to as usize;
+                    }
+                }
+                Op::Repeat { counter, exit } => {
+                    let counter = &mut frame.locals[*counter as usize];
+                    let left = cast::to_number(counter);
+                    if left > 0.0 {
+                        *counter = RichValue::Number(left - 1.0);
+                    } else {
+                        frame.pc = *exit as usize;
+                    }
+                }
+                Op::Yield => return Ok(Turn::Yielded),
+                Op::Deadline(i) => {
+                    let secs = number(stack).max(0.0);
+                    frame.locals[*i as usize] = RichValue::Number(self.now() + secs);
+                }
+                Op::DeadlineMillis(i) => {
+                    let millis = (number(stack) * 1000.0) as u64;
+                    frame.locals[*i as usize] =
+                        RichValue::Number(self.now() + millis as f64 / 1000.0);
+                }
+                Op::Sleep(i) => {
+                    let deadline = cast::to_number(&frame.locals[*i as usize]);
+                    if self.now() < deadline {
+                        frame.pc -= 1;
+                        return Ok(Turn::Waiting(deadline));
+                    }
+                }
+                Op::Broadcast => {
+                    let name = string(stack).to_lowercase();
+                    let scripts = self.receivers.get(name.as_str()).cloned();
+                    for script in scripts.into_iter().flatten() {
+                        self.start(script);
+                    }
+                }
+                Op::AwaitBroadcast(i) => {
+                    let name = cast::to_string(&frame.locals[*i as usize]).to_lowercase();
+                    if self.receiving(&name) {
+                        frame.pc -= 1;
+                        return Ok(Turn::Yielded);
+                    }
+                }
+                Op::Call(script) => {
+                    let definition = &program.scripts[*script as usize];
+                    let mut locals = vec![RichValue::Number(0.0); definition.locals as usize];
+                    let arguments = stack
+                        .len()
+                        .checked_sub(definition.parameters as usize)
+                        .expect("malformed bytecode popped an empty stack");
+                    for (local, argument) in locals.iter_mut().zip(stack.drain(arguments..)) {
+                        // Arguments are kept like variables.
+                        *local = PrimitiveValue::from(argument).into();
+                    }
+                    thread.frames.push(Frame {
+                        script: *script,
+                        pc: 0,
+                        locals,
+                    });
+                }
+                Op::Return => {
+                    thread.frames.pop();
+                }
+                Op::StopAll => return Ok(Turn::StopAll),
+            }
+        }
+    }
+}
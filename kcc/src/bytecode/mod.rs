@@ -0,0 +1,231 @@
+//! A compact bytecode for projects, lowered from the [IR](crate::ir) with every
+//! variable, list, script and argument resolved to an index ahead of time, and
+//! its `.kbc` file format.
+//!
+//! A `.kbc` file is the magic bytes `KBC\0`, the format version as a little-endian
+//! `u32`, then the [`Program`] encoded with bincode.
+
+use std::{fs, path::Path};
+
+use scratch_ast::errors::ScratchError;
+use serde::{Deserialize, Serialize};
+
+use crate::{compiler::Layout, vm::ScratchResult};
+
+pub use crate::ir::{Constant, MathOp, Trigger};
+
+pub mod cache;
+pub mod lower;
+pub mod machine;
+
+pub const MAGIC: &[u8; 4] = b"KBC\0";
+/// Bumped whenever the encoding of [`Program`] changes.
+pub const VERSION: u32 = 1;
+
+/// An instruction. Instructions pop their operands off the stack of the running
+/// script and push their result, if they have one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Op {
+    /// Pushes a constant of the program.
+    Constant(u32),
+    /// Pushes an argument or local of the running custom block or script.
+    Local(u32),
+    SetLocal(u32),
+    Pop,
+
+    Variable(u32),
+    SetVariable(u32),
+    ChangeVariable(u32),
+    /// Pushes the contents of a list, as shown by its reporter.
+    ListContents(u32),
+    AddToList(u32),
+    DeleteOfList(u32),
+    DeleteAllOfList(u32),
+    /// Pops the item, then the index.
+    InsertAtList(u32),
+    /// Pops the item, then the index.
+    ReplaceItemOfList(u32),
+    ItemOfList(u32),
+    ItemNumOfList(u32),
+    LengthOfList(u32),
+    ListContainsItem(u32),
+
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Random,
+    Gt,
+    Lt,
+    Equals,
+    And,
+    Or,
+    Not,
+    Join,
+    LetterOf,
+    Length,
+    Contains,
+    Mod,
+    Round,
+    /// `math op`, `None` for an operator Scratch does not know.
+    MathOp(Option<MathOp>),
+
+    Say,
+    Think,
+    Ask,
+    Answer,
+    Timer,
+    ResetTimer,
+    DaysSince2000,
+
+    Jump(u32),
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    /// Decrements the counter of a `repeat` kept in a local, or jumps out of
+    /// the loop once it reaches 0.
+    Repeat {
+        counter: u32,
+        exit: u32,
+    },
+    /// Ends the turn of the running script.
+    Yield,
+    /// Pops a duration in seconds and keeps when it ends in a local.
+    Deadline(u32),
+    /// Like [`Op::Deadline`], but drops what is shorter than a millisecond.
+    DeadlineMillis(u32),
+    /// Waits until the deadline kept in a local.
+    Sleep(u32),
+    /// Pops a broadcast name and starts the scripts receiving it.
+    Broadcast,
+    /// Waits while scripts receive the broadcast whose name is kept in a local.
+    AwaitBroadcast(u32),
+    /// Pops the arguments of a custom block, then runs the script defining it.
+    Call(u32),
+    /// Leaves the running custom block, or ends the running script.
+    Return,
+    StopAll,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Script {
+    pub trigger: Trigger,
+    /// How many arguments the custom block takes. They are its first locals.
+    pub parameters: u32,
+    /// How many locals the script needs, arguments included.
+    pub locals: u32,
+    pub code: Vec<Op>,
+}
+
+/// A project lowered to bytecode.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+    /// The initial value of every variable, by slot.
+    pub variables: Vec<Constant>,
+    /// The initial contents of every list, by slot.
+    pub lists: Vec<Vec<Constant>>,
+    pub constants: Vec<Constant>,
+    pub scripts: Vec<Script>,
+}
+
+impl Program {
+    pub fn encode(&self) -> Result<Vec<u8>, ScratchError> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self)
+            .map_err(|e| ScratchError::internal(e, "encoding bytecode"))?;
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ScratchError> {
+        let location = "decoding bytecode";
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err(ScratchError::type_error(
+                "not a kcc bytecode file",
+                location,
+            ));
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(ScratchError::type_error(
+                format!("bytecode format {version} is not supported, expected {VERSION}"),
+                location,
+            ));
+        }
+        let program: Program =
+            bincode::deserialize(&bytes[8..]).map_err(|e| ScratchError::type_error(e, location))?;
+        program.check()?;
+        Ok(program)
+    }
+
+    /// Checks that every instruction refers to something that exists, so that
+    /// a damaged file cannot make the machine read out of bounds.
+    fn check(&self) -> ScratchResult {
+        let in_range = |index: u32, len: usize| (index as usize) < len;
+        for (i, script) in self.scripts.iter().enumerate() {
+            if script.parameters > script.locals || script.code.last() != Some(&Op::Return) {
+                return Err(ScratchError::type_error(
+                    "malformed script",
+                    format!("checking bytecode script {i}"),
+                ));
+            }
+            for (pc, op) in script.code.iter().enumerate() {
+                let valid = match op {
+                    Op::Constant(c) => in_range(*c, self.constants.len()),
+                    Op::Local(l)
+                    | Op::SetLocal(l)
+                    | Op::Deadline(l)
+                    | Op::DeadlineMillis(l)
+                    | Op::Sleep(l)
+                    | Op::AwaitBroadcast(l) => *l < script.locals,
+                    Op::Variable(v) | Op::SetVariable(v) | Op::ChangeVariable(v) => {
+                        in_range(*v, self.variables.len())
+                    }
+                    Op::ListContents(l)
+                    | Op::AddToList(l)
+                    | Op::DeleteOfList(l)
+                    | Op::DeleteAllOfList(l)
+                    | Op::InsertAtList(l)
+                    | Op::ReplaceItemOfList(l)
+                    | Op::ItemOfList(l)
+                    | Op::ItemNumOfList(l)
+                    | Op::LengthOfList(l)
+                    | Op::ListContainsItem(l) => in_range(*l, self.lists.len()),
+                    Op::Jump(to) | Op::JumpIfFalse(to) | Op::JumpIfTrue(to) => {
+                        in_range(*to, script.code.len())
+                    }
+                    Op::Repeat { counter, exit } => {
+                        *counter < script.locals && in_range(*exit, script.code.len())
+                    }
+                    Op::Call(s) => in_range(*s, self.scripts.len()),
+                    _ => true,
+                };
+                if !valid {
+                    return Err(ScratchError::type_error(
+                        format!("{op:?} is out of range"),
+                        format!("checking bytecode script {i} at {pc}"),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ScratchError> {
+        let bytes = fs::read(path)
+            .map_err(|e| ScratchError::internal(e, format!("reading {}", path.display())))?;
+        Self::decode(&bytes)
+    }
+
+    pub fn save(&self, path: &Path) -> ScratchResult {
+        fs::write(path, self.encode()?)
+            .map_err(|e| ScratchError::internal(e, format!("writing {}", path.display())))
+    }
+}
+
+/// Writes `<name>.kbc` into `out_dir`.
+pub fn compile(layout: &Layout, name: &str, out_dir: &Path) -> ScratchResult {
+    let program = lower::lower(layout)?;
+    fs::create_dir_all(out_dir)
+        .map_err(|e| ScratchError::internal(e, format!("creating {}", out_dir.display())))?;
+    program.save(&out_dir.join(format!("{name}.kbc")))
+}
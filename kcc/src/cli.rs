@@ -0,0 +1,353 @@
+//! The command line of kcc. `kcc <project>` is short for `kcc run <project>`.
+
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use scratch_ast::errors::ScratchError;
+
+use crate::{
+    analysis::{graph, stats},
+    compiler::Target,
+    optimizer::Pass,
+    vm::{debugger::Breakpoint, host::Output, listfile::ListFile},
+};
+
+/// What kcc exits with, besides 0 when everything went fine.
+pub mod exit {
+    /// The project failed while running, or kcc failed otherwise, e.g. to write a file.
+    pub const RUNTIME_ERROR: i32 = 1;
+    // 2 is for a wrong command line, which clap exits with.
+    /// The project could not be read, parsed or compiled.
+    pub const PARSE_ERROR: i32 = 3;
+    /// A script ran `stop all`.
+    pub const STOP_ALL: i32 = 4;
+    /// The project ran more steps than `--max-steps` allows.
+    pub const STEP_LIMIT: i32 = 5;
+    /// `kcc check` found blocks kcc cannot run, or suspicious types, or `kcc lint` found lints.
+    pub const CHECK_FAILED: i32 = 6;
+    /// A project tested by `kcc test` said something else, or failed to run.
+    pub const TEST_FAILED: i32 = 7;
+}
+
+const EXIT_CODES: &str = "Exit codes:
+  0  the project finished, or the command succeeded
+  1  the project failed while running
+  2  the command line is wrong
+  3  the project could not be read, parsed or compiled
+  4  a script ran `stop all`
+  5  the project ran more steps than --max-steps allows
+  6  kcc check or kcc lint found problems
+  7  kcc test had failing tests";
+
+#[derive(Parser)]
+#[command(
+    name = "kcc",
+    version,
+    about = "Runs and compiles Scratch projects",
+    after_help = EXIT_CODES
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Runs a project, or a `.kbc` file. This is the default command.
+    Run(Box<RunArgs>),
+    /// Runs a project with the interpreter, pausing at breakpoints to look around.
+    Debug(DebugArgs),
+    /// Parses a project and reports the blocks kcc cannot run, without running it.
+    Check(CheckArgs),
+    /// Prints likely mistakes, such as variables nobody reads or broadcasts nobody receives.
+    Lint(LintArgs),
+    /// Prints which scripts send and receive which broadcasts, call which custom blocks,
+    /// and read and write which variables.
+    Graph(GraphArgs),
+    /// Prints the parsed project, or its IR.
+    Dump(DumpArgs),
+    /// Writes the project in another language.
+    Compile(CompileArgs),
+    /// Prints what a project is made of.
+    Info(InfoArgs),
+    /// Prints the scripts, variables, lists, costumes and sounds one version of a project
+    /// added, removed or changed, however its blocks were moved around.
+    Diff(DiffArgs),
+    /// Counts blocks, scripts, assets and more across many projects, with their
+    /// computational thinking scores.
+    Stats(StatsArgs),
+    /// Writes an executable that runs the project.
+    ///
+    /// The executable is a copy of kcc with the project, or its bytecode, appended
+    /// to it. It is never compiled ahead of time to machine code, as that takes a
+    /// C or Rust compiler: `kcc compile --target c` or `--target rust` writes a
+    /// program to build with one instead.
+    Package(PackageArgs),
+    /// Runs projects and compares what they say to their expected output.
+    Test(TestArgs),
+}
+
+impl Cli {
+    /// Parses the command line, running `args[1]` if it is not a command. Errors,
+    /// like `--help`, are left to the caller to print.
+    pub fn try_parse_with_default(mut args: Vec<String>) -> Result<Self, clap::Error> {
+        let commands = [
+            "run", "debug", "check", "lint", "graph", "dump", "compile", "info", "diff", "stats",
+            "package", "test", "help",
+        ];
+        let is_command = |arg: &str| {
+            commands.contains(&arg) || matches!(arg, "-h" | "--help" | "-V" | "--version")
+        };
+        if args.get(1).is_some_and(|arg| !is_command(arg)) {
+            args.insert(1, "run".to_string());
+        }
+        Cli::try_parse_from(args)
+    }
+}
+
+#[derive(Args)]
+pub struct Optimizations {
+    /// Turns every optimization off.
+    #[arg(long)]
+    pub no_optimize: bool,
+    /// Turns a pass off: inline, fold, unreachable, hoist or dead-scripts.
+    #[arg(long = "disable-pass", value_name = "PASS", value_parser = parse_pass)]
+    pub disabled: Vec<Pass>,
+}
+
+/// What went wrong, without the traceback, for clap to show.
+pub(crate) fn describe(e: ScratchError) -> String {
+    let descriptions = e.trace.into_iter().map(|t| t.description);
+    descriptions.collect::<Vec<_>>().join(": ")
+}
+
+fn parse_pass(name: &str) -> Result<Pass, String> {
+    Pass::parse(name).map_err(describe)
+}
+
+impl Optimizations {
+    pub fn passes(&self) -> Vec<Pass> {
+        match self.no_optimize {
+            true => Vec::new(),
+            false => Pass::ALL
+                .iter()
+                .copied()
+                .filter(|p| !self.disabled.contains(p))
+                .collect(),
+        }
+    }
+
+    /// The options turning off the same optimizations.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.no_optimize {
+            args.push("--no-optimize".to_string());
+        }
+        for pass in self.disabled.iter() {
+            args.extend(["--disable-pass".to_string(), pass.name().to_string()]);
+        }
+        args
+    }
+}
+
+/// Parses the `<list>=<file>` value of `--import-list` and `--export-list`.
+fn parse_list(value: &str) -> Result<(String, ListFile), String> {
+    let Some((list, file)) = value.split_once('=') else {
+        return Err(format!("expected <list>=<file>, got {value}"));
+    };
+    let file = ListFile::parse(file).map_err(describe)?;
+    Ok((list.to_string(), file))
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// A `.sb3` project, or a `.kbc` file written by `kcc compile --target bytecode`.
+    pub project: PathBuf,
+    #[command(flatten)]
+    pub optimizations: Optimizations,
+    /// Keeps cloud variables in a JSON file between runs.
+    #[arg(long, value_name = "PATH", conflicts_with = "cloud_ws")]
+    pub cloud_file: Option<String>,
+    /// Shares cloud variables between every kcc using this address.
+    #[arg(long, value_name = "HOST:PORT")]
+    pub cloud_ws: Option<String>,
+    /// Fills a list from a file before the project starts.
+    #[arg(long = "import-list", value_name = "LIST=FILE[:COLUMN]", value_parser = parse_list)]
+    pub imports: Vec<(String, ListFile)>,
+    /// Writes a list to a file once the project ends.
+    #[arg(long = "export-list", value_name = "LIST=FILE", value_parser = parse_list)]
+    pub exports: Vec<(String, ListFile)>,
+    /// Runs the project as bytecode, with every script on a single thread.
+    #[arg(long)]
+    pub bytecode: bool,
+    /// Where compiled bytecode is cached.
+    #[arg(long, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Seeds `pick random`, so that it picks the same numbers every run.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Stops the project after this many steps.
+    #[arg(long, value_name = "STEPS")]
+    pub max_steps: Option<u64>,
+    /// Makes waits take no time: the clock moves a frame once every script had
+    /// a turn, and jumps to when waits end instead of sleeping.
+    #[arg(long)]
+    pub virtual_clock: bool,
+    /// Answers the next `ask and wait` with TEXT instead of reading a line.
+    #[arg(long = "answer", value_name = "TEXT")]
+    pub answers: Vec<String>,
+    /// Answers `ask and wait` with the lines of FILE, after those of --answer.
+    #[arg(long, value_name = "FILE")]
+    pub answers_file: Option<PathBuf>,
+    /// How to print what the project says.
+    #[arg(long, value_enum, default_value_t)]
+    pub output: Output,
+    /// Prints how often every script, custom block and block ran, and for how long, to stderr.
+    #[arg(long)]
+    pub profile: bool,
+    /// Writes the time spent in every stack of blocks, as collapsed stacks for flamegraph tools.
+    #[arg(long, value_name = "FILE")]
+    pub flamegraph: Option<PathBuf>,
+    /// Writes every block the project runs, with its inputs and what it changed, as JSON lines.
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+    /// Records which blocks and branches ran into FILE, adding to the runs already in it.
+    #[arg(long, value_name = "FILE")]
+    pub coverage: Option<PathBuf>,
+    /// Draws the scripts into FILE as an SVG, with the blocks that never ran in red.
+    #[arg(long, value_name = "FILE", requires = "coverage")]
+    pub coverage_svg: Option<PathBuf>,
+}
+
+fn parse_breakpoint(spec: &str) -> Result<Breakpoint, String> {
+    Breakpoint::parse(spec).map_err(describe)
+}
+
+#[derive(Args)]
+pub struct DebugArgs {
+    pub project: PathBuf,
+    /// Runs until a breakpoint instead of pausing before the first block. SPEC is
+    /// `block ID`, `opcode OPCODE`, `proc NAME` or `broadcast NAME`.
+    #[arg(long = "break", value_name = "SPEC", value_parser = parse_breakpoint)]
+    pub breakpoints: Vec<Breakpoint>,
+    /// Seeds `pick random`, so that it picks the same numbers every run.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Makes waits take no time: the clock moves a frame once every script had
+    /// a turn, and jumps to when waits end instead of sleeping.
+    #[arg(long)]
+    pub virtual_clock: bool,
+    /// Answers the next `ask and wait` with TEXT, as stdin is for commands.
+    #[arg(long = "answer", value_name = "TEXT")]
+    pub answers: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct CheckArgs {
+    pub project: PathBuf,
+    /// Also prints the type of every variable, list and custom block argument.
+    #[arg(long)]
+    pub types: bool,
+}
+
+#[derive(Args)]
+pub struct LintArgs {
+    pub project: PathBuf,
+    /// Prints the lints as JSON, with the IDs of the blocks to look at.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args)]
+pub struct GraphArgs {
+    pub project: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: graph::Format,
+}
+
+#[derive(Args)]
+pub struct DumpArgs {
+    pub project: PathBuf,
+    /// Prints the project as parsed, in JSON, instead of the IR.
+    #[arg(long)]
+    pub ast: bool,
+    #[command(flatten)]
+    pub optimizations: Optimizations,
+}
+
+#[derive(Args)]
+pub struct CompileArgs {
+    pub project: PathBuf,
+    #[arg(long, default_value = "rust", value_parser = parse_target)]
+    pub target: Target,
+    /// The directory to write to, named after the project by default.
+    #[arg(short, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+    #[command(flatten)]
+    pub optimizations: Optimizations,
+}
+
+fn parse_target(name: &str) -> Result<Target, String> {
+    Target::parse(name).map_err(describe)
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    pub project: PathBuf,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    /// Prints the changes as JSON.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Projects, or folders searched for `.sb3` files, including their subfolders.
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: stats::Format,
+}
+
+#[derive(Args)]
+pub struct PackageArgs {
+    pub project: PathBuf,
+    /// Compiles the project to bytecode now, instead of parsing it each time it starts.
+    #[arg(long)]
+    pub bytecode: bool,
+    /// The executable to write, named after the project by default.
+    #[arg(short, value_name = "FILE")]
+    pub out: Option<PathBuf>,
+    #[command(flatten)]
+    pub optimizations: Optimizations,
+}
+
+#[derive(Args)]
+pub struct TestArgs {
+    /// A folder of projects, or a single project. Each `.sb3` is tested against
+    /// the `.out` file next to it, answering questions with the lines of the
+    /// `.in` file, and against the comments of the project starting with `expect:`.
+    pub path: PathBuf,
+    /// Seeds `pick random` in every project, unless a comment says otherwise.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Fails a project that runs more steps than this, e.g. because it never stops.
+    #[arg(long, value_name = "STEPS", default_value_t = 10_000_000)]
+    pub max_steps: u64,
+    /// Runs the projects as bytecode instead of with the interpreter.
+    #[arg(long)]
+    pub bytecode: bool,
+    /// Writes the results as JUnit XML.
+    #[arg(long, value_name = "FILE")]
+    pub junit: Option<PathBuf>,
+    /// Writes the results, with what every project said, as JSON.
+    #[arg(long, value_name = "FILE")]
+    pub json: Option<PathBuf>,
+    #[command(flatten)]
+    pub optimizations: Optimizations,
+}
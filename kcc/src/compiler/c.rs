@@ -0,0 +1,558 @@
+//! Compiles projects to portable C99.
+//!
+//! Every IR function becomes a frame: a struct keeping its values, and a
+//! `resume` function running its blocks as the cases of a `switch` in a loop.
+//! The header-only runtime in `runtime/c` schedules frames on a single thread
+//! and gives them the semantics of the interpreter.
+
+use std::{fs, path::Path};
+
+use hashbrown::HashMap;
+use scratch_ast::errors::ScratchError;
+
+use crate::{
+    compiler::Layout,
+    ir::{self, Constant, Function, Instruction, Op, Target, Terminator, Trigger, Value},
+    vm::ScratchResult,
+};
+
+pub const RUNTIME: &str = include_str!("../../runtime/c/kcc_runtime.h");
+
+/// Writes `<name>.c` and the runtime it includes into `out_dir`.
+pub fn compile(layout: &Layout, name: &str, out_dir: &Path) -> ScratchResult {
+    let program = ir::lower::lower(layout)?;
+    let source = generate(&program, name);
+    fs::create_dir_all(out_dir)
+        .map_err(|e| ScratchError::internal(e, format!("creating {}", out_dir.display())))?;
+    for (path, contents) in [
+        (out_dir.join(format!("{name}.c")), source.as_str()),
+        (out_dir.join("kcc_runtime.h"), RUNTIME),
+    ] {
+        fs::write(&path, contents)
+            .map_err(|e| ScratchError::internal(e, format!("writing {}", path.display())))?;
+    }
+    Ok(())
+}
+
+/// A C string literal. Bytes outside printable ASCII are escaped in octal,
+/// which unlike hexadecimal escapes cannot swallow the characters after them.
+fn string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            // Avoids trigraphs.
+            b'?' => literal.push_str("\\?"),
+            0x20..=0x7E => literal.push(b as char),
+            _ => literal.push_str(&format!("\\{b:03o}")),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn float(n: f64) -> String {
+    if n.is_nan() {
+        "NAN".to_string()
+    } else if n == f64::INFINITY {
+        "INFINITY".to_string()
+    } else if n == f64::NEG_INFINITY {
+        "-INFINITY".to_string()
+    } else {
+        format!("{n:?}")
+    }
+}
+
+fn literal(constant: &Constant) -> String {
+    match constant {
+        Constant::Boolean(b) => format!("kcc_bool({})", *b as u8),
+        Constant::Number(n) => format!("kcc_num({})", float(*n)),
+        Constant::Integer(n) => format!("kcc_num({})", float(*n as f64)),
+        Constant::String(s) | Constant::Color(s) | Constant::Broadcast(s) => {
+            format!("kcc_str({})", string(s))
+        }
+    }
+}
+
+/// Text put in a C comment.
+fn comment(s: &str) -> String {
+    s.replace("*/", "* /").replace('\n', " ")
+}
+
+struct Generator {
+    out: String,
+    indent: usize,
+}
+
+fn generate(program: &ir::Program, name: &str) -> String {
+    let mut generator = Generator {
+        out: String::new(),
+        indent: 0,
+    };
+    generator.line(&format!("/* {}, compiled by kcc. */", comment(name)));
+    generator.line("#include \"kcc_runtime.h\"");
+    generator.line("");
+    for (index, function) in program.functions.iter().enumerate() {
+        let parameters = function
+            .parameters()
+            .iter()
+            .map(|p| format!("kcc_value v{}", p.0))
+            .collect::<Vec<_>>();
+        generator.line(&format!(
+            "static kcc_frame *new_{index}({});",
+            match parameters.is_empty() {
+                true => "void".to_string(),
+                false => parameters.join(", "),
+            }
+        ));
+    }
+    for (index, function) in program.functions.iter().enumerate() {
+        generator.line("");
+        generator.function(index, function);
+    }
+
+    generator.line("");
+    generator.line("static kcc_frame *start(size_t script) {");
+    generator.indent += 1;
+    generator.line("switch (script) {");
+    let mut green_flag = Vec::new();
+    let mut receivers = Vec::new();
+    for (index, function) in program.functions.iter().enumerate() {
+        match &function.trigger {
+            Trigger::GreenFlag => green_flag.push(index.to_string()),
+            Trigger::Broadcast(name) => receivers.push(format!("{{{}, {index}}}", string(name))),
+            Trigger::Procedure => continue,
+        }
+        generator.line(&format!("case {index}:"));
+        generator.line(&format!("    return new_{index}();"));
+    }
+    generator.line("default:");
+    generator.line("    return NULL;");
+    generator.line("}");
+    generator.indent -= 1;
+    generator.line("}");
+    if !green_flag.is_empty() {
+        generator.line("");
+        generator.line(&format!(
+            "static const size_t green_flag[] = {{{}}};",
+            green_flag.join(", ")
+        ));
+    }
+    if !receivers.is_empty() {
+        generator.line("");
+        generator.line("static const kcc_receiver receivers[] = {");
+        generator.indent += 1;
+        for receiver in receivers.iter() {
+            generator.line(&format!("{receiver},"));
+        }
+        generator.indent -= 1;
+        generator.line("};");
+    }
+
+    generator.line("");
+    generator.line("int main(void) {");
+    generator.indent += 1;
+    generator.line(&format!(
+        "kcc_init({}, {});",
+        program.variables.len(),
+        program.lists.len()
+    ));
+    for (slot, variable) in program.variables.iter().enumerate() {
+        let line = format!("kcc_set_var({slot}, {});", literal(&variable.value));
+        generator.line(&line);
+    }
+    for (slot, list) in program.lists.iter().enumerate() {
+        for item in list.items.iter() {
+            generator.line(&format!("kcc_list_add({slot}, {});", literal(item)));
+        }
+    }
+    generator.line(&format!(
+        "return kcc_run({}, {}, {}, {}, start);",
+        if green_flag.is_empty() {
+            "NULL"
+        } else {
+            "green_flag"
+        },
+        green_flag.len(),
+        if receivers.is_empty() {
+            "NULL"
+        } else {
+            "receivers"
+        },
+        receivers.len()
+    ));
+    generator.indent -= 1;
+    generator.line("}");
+    generator.out
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        if !line.is_empty() {
+            for _ in 0..self.indent {
+                self.out.push_str("    ");
+            }
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn function(&mut self, index: usize, function: &Function) {
+        let mut scope = Scope::new(function);
+        let header = match (&function.trigger, &function.proccode) {
+            (Trigger::GreenFlag, _) => "when green flag clicked".to_string(),
+            (Trigger::Broadcast(name), _) => format!("when I receive {name:?}"),
+            (Trigger::Procedure, proccode) => {
+                format!("define {:?}", proccode.as_deref().unwrap_or(""))
+            }
+        };
+        self.line(&format!(
+            "/* {}: {} */",
+            comment(&function.target),
+            comment(&header)
+        ));
+        let fields = function
+            .blocks
+            .iter()
+            .flat_map(|block| {
+                let results = block.instructions.iter().filter_map(|i| i.result);
+                block.parameters.iter().copied().chain(results)
+            })
+            .filter(|v| !scope.constants.contains_key(v))
+            .collect::<Vec<_>>();
+        self.line("typedef struct {");
+        self.line("    kcc_frame frame;");
+        for field in fields.iter() {
+            self.line(&format!("    kcc_value v{};", field.0));
+        }
+        self.line(&format!("}} frame_{index};"));
+
+        self.line("");
+        self.line(&format!("static void drop_{index}(kcc_frame *frame) {{"));
+        self.indent += 1;
+        if !fields.is_empty() {
+            self.line(&format!("frame_{index} *self = (frame_{index} *)frame;"));
+            for field in fields.iter() {
+                self.line(&format!("kcc_free(self->v{});", field.0));
+            }
+        }
+        self.line("free(frame);");
+        self.indent -= 1;
+        self.line("}");
+
+        self.line("");
+        self.line(&format!(
+            "static kcc_turn resume_{index}(kcc_frame *frame) {{"
+        ));
+        self.indent += 1;
+        // The body is generated first, to know whether it needs `self`.
+        let mut body = Generator {
+            out: String::new(),
+            indent: self.indent,
+        };
+        body.line("for (;;) {");
+        body.indent += 1;
+        body.line("switch (frame->state) {");
+        for (b, block) in function.blocks.iter().enumerate() {
+            body.case(b);
+            body.block(block, b + 1, &mut scope);
+            body.indent -= 1;
+            body.line("}");
+        }
+        body.line("}");
+        body.indent -= 1;
+        body.line("}");
+        if body.out.contains("self->") {
+            self.line(&format!("frame_{index} *self = (frame_{index} *)frame;"));
+        }
+        self.out.push_str(&body.out);
+        self.indent -= 1;
+        self.line("}");
+
+        self.line("");
+        let parameters = function
+            .parameters()
+            .iter()
+            .map(|p| format!("kcc_value v{}", p.0))
+            .collect::<Vec<_>>();
+        self.line(&format!(
+            "static kcc_frame *new_{index}({}) {{",
+            match parameters.is_empty() {
+                true => "void".to_string(),
+                false => parameters.join(", "),
+            }
+        ));
+        self.indent += 1;
+        self.line(&format!(
+            "frame_{index} *self = (frame_{index} *)kcc_alloc(sizeof(frame_{index}));"
+        ));
+        self.line(&format!(
+            "kcc_frame_init(&self->frame, resume_{index}, drop_{index});"
+        ));
+        for field in fields.iter() {
+            let value = match function.parameters().contains(field) {
+                true => format!("v{}", field.0),
+                false => "kcc_num(0.0)".to_string(),
+            };
+            self.line(&format!("self->v{} = {value};", field.0));
+        }
+        self.line("return &self->frame;");
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// A block, followed by the block numbered `next`. Calls and waits resume
+    /// in states of their own, which are numbered after the blocks and follow
+    /// right away.
+    fn block(&mut self, block: &ir::Block, next: usize, scope: &mut Scope) {
+        for instruction in block.instructions.iter() {
+            match &instruction.op {
+                Op::Constant(_) => (),
+                Op::Call(f) => {
+                    // Arguments are kept like variables.
+                    let arguments = instruction
+                        .operands
+                        .iter()
+                        .map(|a| format!("kcc_primitive({})", scope.value(*a)))
+                        .collect::<Vec<_>>();
+                    let state = scope.state();
+                    self.line(&format!("frame->state = {state};"));
+                    self.line(&format!(
+                        "frame->callee = new_{f}({});",
+                        arguments.join(", ")
+                    ));
+                    self.line("return KCC_CALL;");
+                    self.state(state);
+                }
+                _ => {
+                    let line = scope.instruction(instruction);
+                    self.line(&line);
+                }
+            }
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => self.go(target, next, scope),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = format!("kcc_boolean({})", scope.value(*condition));
+                let (condition, away, here) = if then.block.0 as usize == next {
+                    (format!("!{condition}"), otherwise, then)
+                } else {
+                    (condition, then, otherwise)
+                };
+                self.line(&format!("if ({condition}) {{"));
+                self.indent += 1;
+                self.go(away, usize::MAX, scope);
+                self.indent -= 1;
+                self.line("}");
+                self.go(here, next, scope);
+            }
+            Terminator::Yield(target) => {
+                self.arguments(target, scope);
+                self.line(&format!("frame->state = {};", target.block.0));
+                self.line("return KCC_YIELD;");
+            }
+            Terminator::Sleep { deadline, resume } => {
+                let state = scope.state();
+                self.line(&format!("frame->state = {state};"));
+                self.state(state);
+                let deadline = scope.value(*deadline);
+                self.line(&format!("frame->wake = kcc_number({deadline});"));
+                self.line("if (kcc_now() < frame->wake) {");
+                self.line("    return KCC_SLEEP;");
+                self.line("}");
+                self.go(resume, next, scope);
+            }
+            Terminator::AwaitBroadcast { name, resume } => {
+                let state = scope.state();
+                self.line(&format!("frame->state = {state};"));
+                self.state(state);
+                self.line(&format!("if (kcc_receiving({})) {{", scope.value(*name)));
+                self.line("    return KCC_YIELD;");
+                self.line("}");
+                self.go(resume, next, scope);
+            }
+            Terminator::Return => self.line("return KCC_RETURN;"),
+            Terminator::StopAll => self.line("return KCC_STOP_ALL;"),
+        }
+    }
+
+    /// Closes the case being generated, which falls through into the new
+    /// case for `state`.
+    fn state(&mut self, state: usize) {
+        self.indent -= 1;
+        self.line("}");
+        self.case(state);
+    }
+
+    /// Opens the case for `state`, saying so to the C compiler if the code
+    /// before it falls through.
+    fn case(&mut self, state: usize) {
+        let mut lines = self.out.lines().rev().map(str::trim);
+        let falls = match (lines.next(), lines.next()) {
+            (Some("}"), Some(last)) => !(last == "continue;" || last.starts_with("return ")),
+            _ => false,
+        };
+        if falls {
+            self.line("/* fall through */");
+        }
+        self.line(&format!("case {state}: {{"));
+        self.indent += 1;
+    }
+
+    /// Copies the arguments of `target` into the parameters of its block, all
+    /// at once as they may be the same values.
+    fn arguments(&mut self, target: &Target, scope: &Scope) {
+        let parameters = &scope.function.blocks[target.block.0 as usize].parameters;
+        match parameters.len() {
+            0 => (),
+            1 => self.line(&format!(
+                "kcc_put(&self->v{}, {});",
+                parameters[0].0,
+                scope.value(target.arguments[0])
+            )),
+            _ => {
+                self.line("{");
+                self.indent += 1;
+                let arguments = target
+                    .arguments
+                    .iter()
+                    .enumerate()
+                    .map(|(i, a)| format!("a{i} = {}", scope.value(*a)))
+                    .collect::<Vec<_>>();
+                self.line(&format!("kcc_value {};", arguments.join(", ")));
+                for (i, parameter) in parameters.iter().enumerate() {
+                    self.line(&format!("kcc_put(&self->v{}, a{i});", parameter.0));
+                }
+                self.indent -= 1;
+                self.line("}");
+            }
+        }
+    }
+
+    fn go(&mut self, target: &Target, next: usize, scope: &Scope) {
+        self.arguments(target, scope);
+        // Falls through if the target is the block numbered `next`.
+        if target.block.0 as usize != next {
+            self.line(&format!("frame->state = {};", target.block.0));
+            self.line("continue;");
+        }
+    }
+}
+
+/// The function being compiled.
+struct Scope<'a> {
+    function: &'a Function,
+    /// The literal each value defined by a constant instruction is, to be used in its place.
+    constants: HashMap<Value, String>,
+    /// The states numbered so far, blocks first.
+    states: usize,
+}
+
+impl<'a> Scope<'a> {
+    fn new(function: &'a Function) -> Self {
+        let constants = function
+            .blocks
+            .iter()
+            .flat_map(|b| &b.instructions)
+            .filter_map(|instruction| match (instruction.result, &instruction.op) {
+                (Some(result), Op::Constant(c)) => Some((result, literal(c))),
+                _ => None,
+            })
+            .collect();
+        Scope {
+            function,
+            constants,
+            states: function.blocks.len(),
+        }
+    }
+
+    /// A new state to resume in.
+    fn state(&mut self) -> usize {
+        self.states += 1;
+        self.states - 1
+    }
+
+    /// A value, as a `kcc_value` the caller owns.
+    fn value(&self, value: Value) -> String {
+        match self.constants.get(&value) {
+            Some(literal) => literal.clone(),
+            None => format!("kcc_copy(self->v{})", value.0),
+        }
+    }
+
+    /// An instruction other than a constant or a call, as a statement.
+    fn instruction(&self, instruction: &Instruction) -> String {
+        let operands = instruction
+            .operands
+            .iter()
+            .map(|v| self.value(*v))
+            .collect::<Vec<_>>();
+        let args = operands.join(", ");
+        let call = |f: &str| format!("{f}({args})");
+        let list = |l: &u32, f: &str| match args.is_empty() {
+            true => format!("{f}({l})"),
+            false => format!("{f}({l}, {args})"),
+        };
+        let expression = match &instruction.op {
+            Op::Constant(_) | Op::Call(_) => unreachable!("generated by the block"),
+            Op::Variable(v) => format!("kcc_var({v})"),
+            Op::SetVariable(v) => format!("kcc_set_var({v}, {args})"),
+            Op::ChangeVariable(v) => format!("kcc_change_var({v}, {args})"),
+            Op::ListContents(l) => list(l, "kcc_list_contents"),
+            Op::AddToList(l) => list(l, "kcc_list_add"),
+            Op::DeleteOfList(l) => list(l, "kcc_list_delete"),
+            Op::DeleteAllOfList(l) => list(l, "kcc_list_clear"),
+            // The runtime takes the item first.
+            Op::InsertAtList(l) => {
+                format!("kcc_list_insert({l}, {}, {})", operands[1], operands[0])
+            }
+            Op::ReplaceItemOfList(l) => {
+                format!("kcc_list_replace({l}, {}, {})", operands[1], operands[0])
+            }
+            Op::ItemOfList(l) => list(l, "kcc_list_item"),
+            Op::ItemNumOfList(l) => list(l, "kcc_list_index_of"),
+            Op::LengthOfList(l) => list(l, "kcc_list_length"),
+            Op::ListContainsItem(l) => list(l, "kcc_list_contains"),
+            Op::Add => call("kcc_add"),
+            Op::Subtract => call("kcc_subtract"),
+            Op::Multiply => call("kcc_multiply"),
+            Op::Divide => call("kcc_divide"),
+            Op::Random => call("kcc_random"),
+            Op::Gt => call("kcc_gt"),
+            Op::Lt => call("kcc_lt"),
+            Op::Equals => call("kcc_equals"),
+            Op::And => call("kcc_and"),
+            Op::Or => call("kcc_or"),
+            Op::Not => call("kcc_not"),
+            Op::Join => call("kcc_join"),
+            Op::LetterOf => call("kcc_letter_of"),
+            Op::Length => call("kcc_length"),
+            Op::Contains => call("kcc_contains"),
+            Op::Mod => call("kcc_modulo"),
+            Op::Round => call("kcc_round_value"),
+            Op::MathOp(op) => format!(
+                "kcc_mathop(kcc_str({}), {args})",
+                string(op.map_or("", |op| op.name()))
+            ),
+            Op::Say | Op::Think => call("kcc_say"),
+            Op::Ask => call("kcc_ask"),
+            Op::Answer => "kcc_answer()".to_string(),
+            Op::Timer => "kcc_timer()".to_string(),
+            Op::ResetTimer => "kcc_reset_timer()".to_string(),
+            Op::DaysSince2000 => "kcc_days_since_2000()".to_string(),
+            Op::Deadline => call("kcc_deadline"),
+            Op::DeadlineMillis => call("kcc_deadline_millis"),
+            Op::Broadcast => call("kcc_broadcast"),
+        };
+        match instruction.result {
+            Some(result) => format!("kcc_put(&self->v{}, {expression});", result.0),
+            None if instruction.op.has_result() => format!("kcc_free({expression});"),
+            None => format!("{expression};"),
+        }
+    }
+}
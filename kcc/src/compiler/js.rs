@@ -0,0 +1,403 @@
+//! Compiles projects to a standalone JavaScript ES module.
+//!
+//! Every IR function becomes a generator, whose blocks are the cases of a
+//! `switch` in a loop, and the runtime in `runtime/js` is copied into the module
+//! to schedule them and give them the semantics of the interpreter.
+
+use std::{fs, path::Path};
+
+use hashbrown::HashMap;
+use scratch_ast::errors::ScratchError;
+
+use crate::{
+    compiler::Layout,
+    ir::{self, BlockId, Constant, Function, Instruction, Op, Target, Terminator, Trigger, Value},
+    vm::ScratchResult,
+};
+
+pub const RUNTIME: &str = include_str!("../../runtime/js/kcc_runtime.js");
+
+/// Writes `<name>.mjs` into `out_dir`.
+pub fn compile(layout: &Layout, name: &str, out_dir: &Path) -> ScratchResult {
+    let program = ir::lower::lower(layout)?;
+    let source = generate(&program, name);
+    fs::create_dir_all(out_dir)
+        .map_err(|e| ScratchError::internal(e, format!("creating {}", out_dir.display())))?;
+    let path = out_dir.join(format!("{name}.mjs"));
+    fs::write(&path, source)
+        .map_err(|e| ScratchError::internal(e, format!("writing {}", path.display())))
+}
+
+/// A JavaScript string literal.
+fn string(s: &str) -> String {
+    serde_json::to_string(s).expect("strings serialize")
+}
+
+fn literal(constant: &Constant) -> String {
+    let number = |n: f64| {
+        if n.is_nan() {
+            "NaN".to_string()
+        } else if n.is_infinite() {
+            if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+        } else {
+            format!("{n:?}")
+        }
+    };
+    match constant {
+        Constant::Boolean(b) => b.to_string(),
+        Constant::Number(n) => number(*n),
+        Constant::Integer(n) => n.to_string(),
+        Constant::String(s) | Constant::Color(s) | Constant::Broadcast(s) => string(s),
+    }
+}
+
+struct Generator {
+    out: String,
+    indent: usize,
+}
+
+fn generate(program: &ir::Program, name: &str) -> String {
+    let mut generator = Generator {
+        out: String::new(),
+        indent: 0,
+    };
+    generator.line(&format!("// {}, compiled by kcc.", name.replace('\n', " ")));
+    generator.line("");
+    generator.out.push_str(RUNTIME);
+    for (index, function) in program.functions.iter().enumerate() {
+        generator.line("");
+        generator.function(index, function);
+    }
+
+    generator.line("");
+    generator.line("const program = {");
+    generator.indent += 1;
+    generator.line("variables: [");
+    generator.indent += 1;
+    for variable in program.variables.iter() {
+        let line = format!("{}, // {}", literal(&variable.value), variable.name);
+        generator.line(&line);
+    }
+    generator.indent -= 1;
+    generator.line("],");
+    generator.line("lists: [");
+    generator.indent += 1;
+    for list in program.lists.iter() {
+        let items = list.items.iter().map(literal).collect::<Vec<_>>();
+        generator.line(&format!("[{}], // {}", items.join(", "), list.name));
+    }
+    generator.indent -= 1;
+    generator.line("],");
+    let functions = (0..program.functions.len())
+        .map(|i| format!("f{i}"))
+        .collect::<Vec<_>>();
+    generator.line(&format!("scripts: [{}],", functions.join(", ")));
+    let mut green_flag = Vec::new();
+    let mut receivers = Vec::<(&str, Vec<String>)>::new();
+    for (index, function) in program.functions.iter().enumerate() {
+        match &function.trigger {
+            Trigger::GreenFlag => green_flag.push(index.to_string()),
+            Trigger::Broadcast(name) => match receivers.iter_mut().find(|(n, _)| n == name) {
+                Some((_, scripts)) => scripts.push(index.to_string()),
+                None => receivers.push((name, vec![index.to_string()])),
+            },
+            Trigger::Procedure => (),
+        }
+    }
+    generator.line(&format!("greenFlag: [{}],", green_flag.join(", ")));
+    generator.line("receivers: {");
+    generator.indent += 1;
+    for (name, scripts) in receivers {
+        generator.line(&format!("{}: [{}],", string(name), scripts.join(", ")));
+    }
+    generator.indent -= 1;
+    generator.line("},");
+    generator.indent -= 1;
+    generator.line("};");
+
+    generator.out.push_str(
+        r#"
+/**
+ * Runs the project until every script has finished. `host` may replace how it
+ * says and thinks a line, asks a question and returns the answer, and the clock
+ * in milliseconds: `{ say(line), think(line), async ask(question), now() }`.
+ */
+export function run(host = {}) {
+    return new Runtime(program, host).run();
+}
+
+export default run;
+
+// Started on its own, e.g. with `node project.mjs`, the module runs the project.
+if (
+    import.meta.main ??
+    (globalThis.process?.argv?.[1] !== undefined &&
+        import.meta.url === (await import("node:url")).pathToFileURL(process.argv[1]).href)
+) {
+    await run();
+}
+"#,
+    );
+    generator.out
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        if !line.is_empty() {
+            for _ in 0..self.indent {
+                self.out.push_str("    ");
+            }
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn function(&mut self, index: usize, function: &Function) {
+        let scope = Scope::new(function);
+        let header = match (&function.trigger, &function.proccode) {
+            (Trigger::GreenFlag, _) => "when green flag clicked".to_string(),
+            (Trigger::Broadcast(name), _) => format!("when I receive {name:?}"),
+            (Trigger::Procedure, proccode) => {
+                format!("define {:?}", proccode.as_deref().unwrap_or(""))
+            }
+        };
+        self.line(&format!("// {}: {header}", function.target));
+        let mut parameters = vec!["rt".to_string()];
+        parameters.extend(function.parameters().iter().map(|p| scope.value(*p)));
+        self.line(&format!("function* f{index}({}) {{", parameters.join(", ")));
+        self.indent += 1;
+        let locals = function
+            .blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(b, block)| {
+                let parameters = block.parameters.iter().copied().filter(move |_| b > 0);
+                let results = block.instructions.iter().filter_map(|i| i.result);
+                parameters.chain(results)
+            })
+            .filter(|v| !scope.constants.contains_key(v))
+            .map(|v| scope.value(v))
+            .collect::<Vec<_>>();
+        if !locals.is_empty() {
+            self.line(&format!("let {};", locals.join(", ")));
+        }
+        // Straight code needs no loop to go from block to block.
+        let straight =
+            function.blocks.len() == 1 && function.blocks[0].terminator.targets().is_empty();
+        if straight {
+            self.block(&function.blocks[0], 1, &scope);
+        } else {
+            self.line("let block = 0;");
+            self.line("for (;;) {");
+            self.indent += 1;
+            self.line("switch (block) {");
+            for (b, block) in function.blocks.iter().enumerate() {
+                self.line(&format!("case {b}:"));
+                self.indent += 1;
+                self.block(block, b + 1, &scope);
+                self.indent -= 1;
+            }
+            self.line("}");
+            self.indent -= 1;
+            self.line("}");
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// A block, followed by the block numbered `next`.
+    fn block(&mut self, block: &ir::Block, next: usize, scope: &Scope) {
+        for instruction in block.instructions.iter() {
+            if let Some(line) = scope.instruction(instruction) {
+                self.line(&line);
+            }
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => self.go(target, next, scope),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = format!("toBoolean({})", scope.value(*condition));
+                let (condition, away, here) = if then.block.0 as usize == next {
+                    (format!("!{condition}"), otherwise, then)
+                } else {
+                    (condition, then, otherwise)
+                };
+                self.line(&format!("if ({condition}) {{"));
+                self.indent += 1;
+                self.go(away, usize::MAX, scope);
+                self.indent -= 1;
+                self.line("}");
+                self.go(here, next, scope);
+            }
+            Terminator::Yield(target) => {
+                self.arguments(target, scope);
+                self.line("yield;");
+                self.jump(target.block, next);
+            }
+            Terminator::Sleep { deadline, resume } => {
+                let deadline = scope.value(*deadline);
+                self.line(&format!("while (rt.now() < {deadline}) yield {deadline};"));
+                self.go(resume, next, scope);
+            }
+            Terminator::AwaitBroadcast { name, resume } => {
+                self.line(&format!(
+                    "while (rt.receiving({})) yield;",
+                    scope.value(*name)
+                ));
+                self.go(resume, next, scope);
+            }
+            Terminator::Return => self.line("return;"),
+            Terminator::StopAll => {
+                self.line("yield STOP;");
+                self.line("return;");
+            }
+        }
+    }
+
+    /// Copies the arguments of `target` into the parameters of its block, all
+    /// at once as they may be the same values.
+    fn arguments(&mut self, target: &Target, scope: &Scope) {
+        let parameters = &scope.function.blocks[target.block.0 as usize].parameters;
+        let values = |values: &[Value]| {
+            values
+                .iter()
+                .map(|v| scope.value(*v))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match parameters.len() {
+            0 => (),
+            1 => self.line(&format!(
+                "{} = {};",
+                values(parameters),
+                values(&target.arguments)
+            )),
+            _ => self.line(&format!(
+                "[{}] = [{}];",
+                values(parameters),
+                values(&target.arguments)
+            )),
+        }
+    }
+
+    fn go(&mut self, target: &Target, next: usize, scope: &Scope) {
+        self.arguments(target, scope);
+        self.jump(target.block, next);
+    }
+
+    /// Goes to `block`, falling through if it is the block numbered `next`.
+    fn jump(&mut self, block: BlockId, next: usize) {
+        if block.0 as usize != next {
+            self.line(&format!("block = {};", block.0));
+            self.line("continue;");
+        }
+    }
+}
+
+/// The function being compiled.
+struct Scope<'a> {
+    function: &'a Function,
+    /// The literal each value defined by a constant instruction is, to be used in its place.
+    constants: HashMap<Value, String>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(function: &'a Function) -> Self {
+        let constants = function
+            .blocks
+            .iter()
+            .flat_map(|b| &b.instructions)
+            .filter_map(|instruction| match (instruction.result, &instruction.op) {
+                (Some(result), Op::Constant(c)) => Some((result, literal(c))),
+                _ => None,
+            })
+            .collect();
+        Scope {
+            function,
+            constants,
+        }
+    }
+
+    fn value(&self, value: Value) -> String {
+        match self.constants.get(&value) {
+            Some(literal) => literal.clone(),
+            None => format!("v{}", value.0),
+        }
+    }
+
+    /// An instruction as a statement, or nothing for constants.
+    fn instruction(&self, instruction: &Instruction) -> Option<String> {
+        let operands = instruction
+            .operands
+            .iter()
+            .map(|v| self.value(*v))
+            .collect::<Vec<_>>();
+        let args = operands.join(", ");
+        let call = |f: &str| format!("{f}({args})");
+        let list = |l: &u32, f: &str| match args.is_empty() {
+            true => format!("{f}(rt.lists[{l}])"),
+            false => format!("{f}(rt.lists[{l}], {args})"),
+        };
+        let expression = match &instruction.op {
+            Op::Constant(_) => return None,
+            Op::Variable(v) => format!("rt.variables[{v}]"),
+            Op::SetVariable(v) => format!("rt.variables[{v}] = primitive({args})"),
+            Op::ChangeVariable(v) => format!("rt.variables[{v}] = add(rt.variables[{v}], {args})"),
+            Op::ListContents(l) => list(l, "listContents"),
+            Op::AddToList(l) => list(l, "addToList"),
+            Op::DeleteOfList(l) => list(l, "deleteOfList"),
+            Op::DeleteAllOfList(l) => format!("rt.lists[{l}].length = 0"),
+            Op::InsertAtList(l) => list(l, "insertAtList"),
+            Op::ReplaceItemOfList(l) => list(l, "replaceItemOfList"),
+            Op::ItemOfList(l) => list(l, "itemOfList"),
+            Op::ItemNumOfList(l) => list(l, "itemNumOfList"),
+            Op::LengthOfList(l) => format!("rt.lists[{l}].length"),
+            Op::ListContainsItem(l) => format!("{} !== 0", list(l, "itemNumOfList")),
+            Op::Add => call("add"),
+            Op::Subtract => call("subtract"),
+            Op::Multiply => call("multiply"),
+            Op::Divide => call("divide"),
+            Op::Random => call("random"),
+            Op::Gt => call("gt"),
+            Op::Lt => call("lt"),
+            Op::Equals => call("equals"),
+            Op::And => call("and"),
+            Op::Or => call("or"),
+            Op::Not => call("not"),
+            Op::Join => call("join"),
+            Op::LetterOf => call("letterOf"),
+            Op::Length => call("length"),
+            Op::Contains => call("contains"),
+            Op::Mod => call("mod"),
+            Op::Round => call("round"),
+            Op::MathOp(op) => format!(
+                "mathop({}, {args})",
+                op.map_or("null".to_string(), |op| string(op.name()))
+            ),
+            Op::Say => format!("rt.say(toString({args}))"),
+            Op::Think => format!("rt.think(toString({args}))"),
+            // Nothing else runs until the question is answered.
+            Op::Ask => format!("yield rt.ask({args})"),
+            Op::Answer => "rt.answerText".to_string(),
+            Op::Timer => "rt.timer()".to_string(),
+            Op::ResetTimer => "rt.resetTimer()".to_string(),
+            Op::DaysSince2000 => "rt.daysSince2000()".to_string(),
+            Op::Deadline => call("rt.deadline"),
+            Op::DeadlineMillis => call("rt.deadlineMillis"),
+            Op::Broadcast => call("rt.broadcast"),
+            Op::Call(f) => {
+                // Arguments are kept like variables.
+                let mut args = vec!["rt".to_string()];
+                args.extend(operands.iter().map(|a| format!("primitive({a})")));
+                format!("yield* f{f}({})", args.join(", "))
+            }
+        };
+        Some(match instruction.result {
+            Some(result) => format!("{} = {expression};", self.value(result)),
+            None => format!("{expression};"),
+        })
+    }
+}
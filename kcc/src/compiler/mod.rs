@@ -0,0 +1,274 @@
+//! Ahead-of-time compilation of projects to other languages.
+
+use std::path::Path;
+
+use hashbrown::HashMap;
+use scratch_ast::{errors::ScratchError, model::PrimitiveValue};
+
+use crate::vm::{
+    internals::{
+        Expression, StackExpression, ThreadTrigger, VMEvaluable, VMThread, VMValuePointer,
+    },
+    transform::VMStartup,
+    ScratchResult,
+};
+
+pub mod c;
+pub mod js;
+pub mod rust;
+pub mod wasm;
+
+/// A language projects can be compiled to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Rust,
+    C,
+    Wasm,
+    /// A standalone ES module.
+    Js,
+    /// kcc's own bytecode, see [`crate::bytecode`].
+    Bytecode,
+}
+
+impl Target {
+    pub fn parse(name: &str) -> Result<Self, ScratchError> {
+        match name {
+            "rust" => Ok(Target::Rust),
+            "c" => Ok(Target::C),
+            "wasm" => Ok(Target::Wasm),
+            "js" => Ok(Target::Js),
+            "bytecode" => Ok(Target::Bytecode),
+            _ => Err(ScratchError::not_found(
+                format!("unknown target {name}, expected rust, c, wasm, js or bytecode"),
+                "parsing compilation target",
+            )),
+        }
+    }
+}
+
+/// Compiles a project named `name` into `out_dir`.
+pub fn compile(startup: &VMStartup, target: Target, name: &str, out_dir: &Path) -> ScratchResult {
+    let layout = Layout::new(startup);
+    match target {
+        Target::Rust => rust::compile(&layout, name, out_dir),
+        Target::C => c::compile(&layout, name, out_dir),
+        Target::Wasm => wasm::compile(&layout, name, out_dir),
+        Target::Js => js::compile(&layout, name, out_dir),
+        Target::Bytecode => crate::bytecode::compile(&layout, name, out_dir),
+    }
+}
+
+pub fn location(exp: &StackExpression) -> String {
+    format!(
+        "compiling block {:?} (id={})",
+        exp.opcode, exp.original_block.obj_id
+    )
+}
+
+pub fn unsupported(exp: &StackExpression, backend: &str) -> ScratchError {
+    ScratchError::syntax_error(
+        format!("{:?} is not supported by the {backend} backend", exp.opcode),
+        location(exp),
+    )
+}
+
+/// A script, and the index of the target it belongs to.
+pub struct Script<'a> {
+    pub target: usize,
+    pub trigger: &'a ThreadTrigger,
+    pub thread: &'a VMThread,
+}
+
+/// Where the variables, lists and scripts of a project end up in compiled code.
+/// Variables and lists are numbered densely, stage first, then every sprite in order.
+pub struct Layout<'a> {
+    pub startup: &'a VMStartup,
+    /// The initial value of every variable, by slot.
+    pub variables: Vec<PrimitiveValue>,
+    /// The initial contents of every list, by slot.
+    pub lists: Vec<Vec<PrimitiveValue>>,
+    /// The name of every variable, by slot, prefixed with `Sprite/` unless it belongs to the stage.
+    pub variable_names: Vec<String>,
+    /// The name of every list, like [`Layout::variable_names`].
+    pub list_names: Vec<String>,
+    /// Every script that can run, in a stable order.
+    pub scripts: Vec<Script<'a>>,
+    /// Slots keyed by target, `None` for the stage, and numeric ID.
+    variable_slots: HashMap<(Option<usize>, usize), usize>,
+    list_slots: HashMap<(Option<usize>, usize), usize>,
+}
+
+fn sorted_ids<T>(map: &HashMap<usize, T>) -> Vec<usize> {
+    let mut ids = map.keys().copied().collect::<Vec<_>>();
+    ids.sort();
+    ids
+}
+
+/// The ID of the hat block of a script, to order scripts sharing a trigger.
+fn hat_id(thread: &VMThread) -> &str {
+    match thread.code.first() {
+        Some(Expression::Stack(hat)) => &hat.original_block.obj_id,
+        _ => "",
+    }
+}
+
+impl<'a> Layout<'a> {
+    pub fn new(startup: &'a VMStartup) -> Self {
+        let mut layout = Layout {
+            startup,
+            variables: Vec::new(),
+            lists: Vec::new(),
+            variable_names: Vec::new(),
+            list_names: Vec::new(),
+            scripts: Vec::new(),
+            variable_slots: HashMap::new(),
+            list_slots: HashMap::new(),
+        };
+        let gstate = &startup.gstate;
+        let states = std::iter::once((
+            None,
+            &gstate.variables,
+            &gstate.variable_names,
+            &gstate.lists,
+            &gstate.listname_to_numid,
+        ))
+        .chain(startup.targets.iter().enumerate().map(|(i, (state, _))| {
+            (
+                Some(i),
+                &state.variables,
+                &state.variable_names,
+                &state.lists,
+                &state.listname_to_numid,
+            )
+        }));
+        for (target, variables, variable_names, lists, list_ids) in states {
+            let qualified = |name: &str| match target {
+                Some(i) => format!("{}/{name}", startup.targets[i].0.name),
+                None => name.to_string(),
+            };
+            for id in sorted_ids(variables) {
+                layout
+                    .variable_slots
+                    .insert((target, id), layout.variables.len());
+                layout.variables.push(variables[&id].read().clone());
+                layout.variable_names.push(qualified(
+                    variable_names.get(&id).map_or("", String::as_str),
+                ));
+            }
+            for id in sorted_ids(lists) {
+                layout.list_slots.insert((target, id), layout.lists.len());
+                layout
+                    .lists
+                    .push(lists[&id].read().iter().map(|i| i.read().clone()).collect());
+                let name = list_ids.iter().find(|(_, i)| **i == id).map(|(n, _)| n);
+                layout
+                    .list_names
+                    .push(qualified(name.map_or("", String::as_str)));
+            }
+        }
+        for (target, (_, source_code)) in startup.targets.iter().enumerate() {
+            let mut triggers = source_code
+                .iter()
+                .filter(|(trigger, _)| !matches!(trigger, ThreadTrigger::Hat(_)))
+                .collect::<Vec<_>>();
+            triggers.sort_by_key(|(trigger, _)| match trigger {
+                ThreadTrigger::GreenFlag => (0, "", 0),
+                ThreadTrigger::Broadcast(name) => (1, name.as_str(), 0),
+                ThreadTrigger::Mutation(id) => (2, "", *id),
+                ThreadTrigger::Hat(_) => (3, "", 0),
+            });
+            for (trigger, threads) in triggers {
+                let mut threads = threads.iter().collect::<Vec<_>>();
+                threads.sort_by_key(|t| hat_id(t));
+                layout
+                    .scripts
+                    .extend(threads.into_iter().map(|thread| Script {
+                        target,
+                        trigger,
+                        thread,
+                    }));
+            }
+        }
+        layout
+    }
+
+    fn slot(
+        slots: &HashMap<(Option<usize>, usize), usize>,
+        target: usize,
+        id: usize,
+        name: &str,
+    ) -> Result<usize, ScratchError> {
+        slots
+            .get(&(Some(target), id))
+            .or_else(|| slots.get(&(None, id)))
+            .copied()
+            .ok_or(ScratchError::not_found(
+                format!("{name} not found"),
+                format!("laying out {name} (id={id})"),
+            ))
+    }
+
+    /// The slot of the variable `pointer` points to, as seen from `target`.
+    pub fn variable(&self, target: usize, pointer: &VMValuePointer) -> Result<usize, ScratchError> {
+        match pointer {
+            VMValuePointer::Variable { name, id } => Self::slot(
+                &self.variable_slots,
+                target,
+                *id,
+                &format!("variable {name}"),
+            ),
+            _ => Err(ScratchError::type_error(
+                format!("expected a variable, got {pointer:?}"),
+                "laying out variables",
+            )),
+        }
+    }
+
+    /// The slot of the list `pointer` points to, as seen from `target`.
+    pub fn list(&self, target: usize, pointer: &VMValuePointer) -> Result<usize, ScratchError> {
+        match pointer {
+            VMValuePointer::List { name, id } => {
+                Self::slot(&self.list_slots, target, *id, &format!("list {name}"))
+            }
+            _ => Err(ScratchError::type_error(
+                format!("expected a list, got {pointer:?}"),
+                "laying out lists",
+            )),
+        }
+    }
+
+    /// The script defining custom block `id` of `target`.
+    pub fn procedure(&self, target: usize, id: usize) -> Option<usize> {
+        self.scripts.iter().position(|s| {
+            s.target == target && matches!(s.trigger, ThreadTrigger::Mutation(m) if *m == id)
+        })
+    }
+
+    /// Which of the `parameters` of `script` the argument reporter `exp` reads,
+    /// or `None` if it is used outside its custom block.
+    pub fn argument(
+        &self,
+        script: &Script,
+        parameters: &[usize],
+        exp: &StackExpression,
+    ) -> Option<usize> {
+        let name = match exp.argraw("VALUE") {
+            Some(VMEvaluable::Field(f)) => f.display_value.clone(),
+            Some(VMEvaluable::Bare(v)) => scratch_ast::cast::to_string(v),
+            _ => String::new(),
+        };
+        let id = script
+            .thread
+            .argument_names
+            .get(&name)
+            .or_else(|| self.startup.gstate.mutationname_to_numid.get(&name))?;
+        parameters.iter().position(|p| p == id)
+    }
+
+    /// The numeric IDs of the arguments of a custom block, in a stable order.
+    pub fn parameters(thread: &VMThread) -> Vec<usize> {
+        let mut params = thread.argument_names.values().copied().collect::<Vec<_>>();
+        params.sort();
+        params
+    }
+}
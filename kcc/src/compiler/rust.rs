@@ -0,0 +1,787 @@
+//! Compiles projects to a standalone Rust crate.
+//!
+//! Every IR function becomes a frame of the runtime in `runtime/rust`: a state
+//! machine whose states are its blocks, which the runtime schedules on one
+//! thread with the semantics of the interpreter. Values, variables and lists
+//! are kept as the Rust type their inferred [`Type`] allows.
+
+use std::{fs, path::Path};
+
+use hashbrown::HashMap;
+use scratch_ast::{cast, errors::ScratchError};
+
+use crate::{
+    compiler::Layout,
+    ir::{self, Constant, Function, Instruction, Op, Target, Terminator, Trigger, Type, Value},
+    vm::ScratchResult,
+};
+
+const RUNTIME: &str = include_str!("../../runtime/rust/runtime.rs");
+const MODEL: &str = include_str!("../../runtime/rust/model.rs");
+const CAST: &str = include_str!("../../../ast/src/cast.rs");
+
+/// Writes a crate building the project named `name` into `out_dir`.
+pub fn compile(layout: &Layout, name: &str, out_dir: &Path) -> ScratchResult {
+    let program = ir::lower::lower(layout)?;
+    let main = generate(&program, name);
+    let package = package_name(name);
+    let manifest = format!(
+        "[package]\nname = \"{package}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [profile.release]\nopt-level = 3\n\n\
+         # Not part of any enclosing workspace.\n[workspace]\n"
+    );
+    let src = out_dir.join("src");
+    fs::create_dir_all(&src)
+        .map_err(|e| ScratchError::internal(e, format!("creating {}", src.display())))?;
+    for (path, contents) in [
+        (out_dir.join("Cargo.toml"), manifest.as_str()),
+        (src.join("main.rs"), main.as_str()),
+        (src.join("runtime.rs"), RUNTIME),
+        (src.join("model.rs"), MODEL),
+        (src.join("cast.rs"), CAST),
+    ] {
+        fs::write(&path, contents)
+            .map_err(|e| ScratchError::internal(e, format!("writing {}", path.display())))?;
+    }
+    Ok(())
+}
+
+/// A valid Cargo package name for a project.
+fn package_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => name,
+        _ => format!("project_{name}"),
+    }
+}
+
+/// The Rust type a value is kept as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Repr {
+    Number,
+    Boolean,
+    /// An owned `String`, or a `&str` for constants.
+    Text,
+    Primitive,
+    Value,
+}
+
+impl Repr {
+    /// How values of `ty` are kept in variables, lists and arguments, which
+    /// hold booleans as text.
+    fn slot(ty: Type) -> Repr {
+        if ty == Type::NUMBER {
+            Repr::Number
+        } else if !ty.may_be(Type::NUMBER) {
+            Repr::Text
+        } else {
+            Repr::Primitive
+        }
+    }
+
+    /// How values of `ty` reported by an instruction are kept.
+    fn local(ty: Type) -> Repr {
+        match ty {
+            Type::NUMBER => Repr::Number,
+            Type::BOOLEAN => Repr::Boolean,
+            _ if !ty.may_be(Type::NUMBER) && !ty.may_be(Type::BOOLEAN) => Repr::Text,
+            _ => Repr::Value,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Repr::Number => "f64",
+            Repr::Boolean => "bool",
+            Repr::Text => "String",
+            Repr::Primitive => "PrimitiveValue",
+            Repr::Value => "Value",
+        }
+    }
+
+    fn default(self) -> &'static str {
+        match self {
+            Repr::Number => "0.0",
+            Repr::Boolean => "false",
+            Repr::Text => "String::new()",
+            Repr::Primitive => "PrimitiveValue::Number(0.0)",
+            Repr::Value => "Value::Number(0.0)",
+        }
+    }
+}
+
+/// An expression, and the type it is of.
+struct Expr {
+    code: String,
+    repr: Repr,
+    /// Whether the expression is a place that must be cloned to be moved.
+    place: bool,
+}
+
+impl Expr {
+    fn new(code: impl Into<String>, repr: Repr) -> Self {
+        Self {
+            code: code.into(),
+            repr,
+            place: false,
+        }
+    }
+
+    /// The expression as a receiver of a method call.
+    fn receiver(&self) -> String {
+        match self.code.starts_with('-') {
+            true => format!("({})", self.code),
+            false => self.code.clone(),
+        }
+    }
+
+    /// The expression kept as `repr`.
+    fn to(&self, repr: Repr) -> String {
+        match (self.repr == repr, repr) {
+            (true, Repr::Number | Repr::Boolean) => self.code.clone(),
+            (true, Repr::Text) if !self.place => format!("String::from({})", self.code),
+            (true, _) if self.place => format!("{}.clone()", self.code),
+            (true, _) => self.code.clone(),
+            (false, Repr::Number) => format!("{}.number()", self.receiver()),
+            (false, Repr::Boolean) => format!("{}.boolean()", self.receiver()),
+            (false, Repr::Text) => format!("{}.text()", self.receiver()),
+            (false, Repr::Primitive) => format!("{}.primitive()", self.receiver()),
+            (false, Repr::Value) => format!("{}.value()", self.receiver()),
+        }
+    }
+
+    /// The expression used as a number, where NaN is 0.
+    fn number(&self) -> String {
+        match self.repr {
+            Repr::Number if !self.place && self.code != "f64::NAN" => self.code.clone(),
+            _ => format!("{}.number()", self.receiver()),
+        }
+    }
+
+    /// The expression borrowed, for the runtime functions taking any value.
+    fn borrow(&self) -> String {
+        match self.place {
+            true => format!("&{}", self.code),
+            // Text constants are already `&str`.
+            false if self.repr == Repr::Text => self.code.clone(),
+            false => format!("&{}", self.code),
+        }
+    }
+}
+
+fn float(n: f64) -> String {
+    if n.is_nan() {
+        "f64::NAN".to_string()
+    } else if n == f64::INFINITY {
+        "f64::INFINITY".to_string()
+    } else if n == f64::NEG_INFINITY {
+        "f64::NEG_INFINITY".to_string()
+    } else {
+        format!("{n:?}_f64")
+    }
+}
+
+/// A constant as an expression of the type it is inferred to be.
+fn literal(constant: &Constant) -> Expr {
+    let value = scratch_ast::model::RichValue::from(constant);
+    match (constant, Type::of_value(&value)) {
+        (Constant::Boolean(b), _) => Expr::new(b.to_string(), Repr::Boolean),
+        (Constant::Number(n), _) => Expr::new(float(*n), Repr::Number),
+        (Constant::Integer(n), _) => Expr::new(float(*n as f64), Repr::Number),
+        // Text reading exactly like a number.
+        (_, Type::NUMBER) => Expr::new(float(cast::to_number(&value)), Repr::Number),
+        _ => Expr::new(format!("{:?}", cast::to_string(&value)), Repr::Text),
+    }
+}
+
+/// A variable or list of the project, by kind and slot.
+fn slot(kind: char, index: u32) -> String {
+    format!("rt.slots.{kind}{index}")
+}
+
+struct Generator {
+    out: String,
+    indent: usize,
+}
+
+fn generate(program: &ir::Program, name: &str) -> String {
+    let mut generator = Generator {
+        out: String::new(),
+        indent: 0,
+    };
+    generator.line(&format!(
+        "//! {}, compiled by kcc.",
+        name.replace('\n', " ")
+    ));
+    generator.line("");
+    generator.line("#![allow(unused_assignments, unused_variables)]");
+    generator.line("");
+    generator.line("#[allow(dead_code)]");
+    generator.line("mod cast;");
+    generator.line("#[allow(dead_code)]");
+    generator.line("mod model;");
+    generator.line("#[allow(dead_code)]");
+    generator.line("mod runtime;");
+    generator.line("");
+    generator.line("use runtime::*;");
+    generator.line("");
+    generator.line("type Rt = Runtime<Slots>;");
+    generator.line("");
+    generator.line("/// The variables and lists of the project.");
+    generator.line("struct Slots {");
+    generator.indent += 1;
+    for (i, variable) in program.variables.iter().enumerate() {
+        let line = format!(
+            "v{i}: {}, // {}",
+            Repr::slot(variable.ty).name(),
+            variable.name
+        );
+        generator.line(&line);
+    }
+    for (i, list) in program.lists.iter().enumerate() {
+        let line = format!(
+            "l{i}: Vec<{}>, // {}",
+            Repr::slot(list.ty).name(),
+            list.name
+        );
+        generator.line(&line);
+    }
+    generator.indent -= 1;
+    generator.line("}");
+    for (index, function) in program.functions.iter().enumerate() {
+        generator.line("");
+        generator.function(index, function, program);
+    }
+
+    let mut green_flag = Vec::new();
+    let mut receivers = Vec::<(&str, Vec<String>)>::new();
+    let mut scripts = Vec::new();
+    for (index, function) in program.functions.iter().enumerate() {
+        match &function.trigger {
+            Trigger::GreenFlag => green_flag.push(index.to_string()),
+            Trigger::Broadcast(name) => match receivers.iter_mut().find(|(n, _)| n == name) {
+                Some((_, scripts)) => scripts.push(index.to_string()),
+                None => receivers.push((name, vec![index.to_string()])),
+            },
+            Trigger::Procedure => continue,
+        }
+        scripts.push(index);
+    }
+
+    generator.line("");
+    generator.line("fn receivers(name: &str) -> &'static [usize] {");
+    generator.indent += 1;
+    generator.line("match name {");
+    generator.indent += 1;
+    for (name, scripts) in receivers {
+        generator.line(&format!("{name:?} => &[{}],", scripts.join(", ")));
+    }
+    generator.line("_ => &[],");
+    generator.indent -= 1;
+    generator.line("}");
+    generator.indent -= 1;
+    generator.line("}");
+
+    generator.line("");
+    generator.line("fn start(script: usize) -> Box<dyn Frame<Slots>> {");
+    generator.indent += 1;
+    generator.line("match script {");
+    generator.indent += 1;
+    for index in scripts {
+        generator.line(&format!("{index} => Box::new(F{index}::new()),"));
+    }
+    generator.line("_ => unreachable!(\"script {script} is not started by an event\"),");
+    generator.indent -= 1;
+    generator.line("}");
+    generator.indent -= 1;
+    generator.line("}");
+
+    generator.line("");
+    generator.line("fn main() {");
+    generator.indent += 1;
+    generator.line("run(Project {");
+    generator.indent += 1;
+    generator.line("slots: Slots {");
+    generator.indent += 1;
+    for (i, variable) in program.variables.iter().enumerate() {
+        let value = literal(&variable.value).to(Repr::slot(variable.ty));
+        generator.line(&format!("v{i}: {value},"));
+    }
+    for (i, list) in program.lists.iter().enumerate() {
+        let repr = Repr::slot(list.ty);
+        let items = list
+            .items
+            .iter()
+            .map(|item| literal(item).to(repr))
+            .collect::<Vec<_>>();
+        generator.line(&format!("l{i}: vec![{}],", items.join(", ")));
+    }
+    generator.indent -= 1;
+    generator.line("},");
+    generator.line(&format!("green_flag: &[{}],", green_flag.join(", ")));
+    generator.line("receivers,");
+    generator.line("start,");
+    generator.indent -= 1;
+    generator.line("});");
+    generator.indent -= 1;
+    generator.line("}");
+    generator.out
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        if !line.is_empty() {
+            for _ in 0..self.indent {
+                self.out.push_str("    ");
+            }
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn function(&mut self, index: usize, function: &Function, program: &ir::Program) {
+        let mut scope = Scope::new(function, program);
+        let header = match (&function.trigger, &function.proccode) {
+            (Trigger::GreenFlag, _) => "when green flag clicked".to_string(),
+            (Trigger::Broadcast(name), _) => format!("when I receive {name:?}"),
+            (Trigger::Procedure, proccode) => {
+                format!("define {:?}", proccode.as_deref().unwrap_or(""))
+            }
+        };
+        self.line(&format!("/// {}: {header}", function.target));
+        self.line(&format!("struct F{index} {{"));
+        self.indent += 1;
+        self.line("state: u32,");
+        for (value, repr) in scope.fields() {
+            self.line(&format!("v{value}: {},", repr.name()));
+        }
+        self.indent -= 1;
+        self.line("}");
+
+        self.line("");
+        self.line(&format!("impl F{index} {{"));
+        self.indent += 1;
+        let parameters = function
+            .parameters()
+            .iter()
+            .map(|p| format!("v{}: {}", p.0, scope.repr[p].name()))
+            .collect::<Vec<_>>();
+        self.line(&format!("fn new({}) -> Self {{", parameters.join(", ")));
+        self.indent += 1;
+        self.line(&format!("F{index} {{"));
+        self.indent += 1;
+        self.line("state: 0,");
+        for (value, repr) in scope.fields() {
+            match function.parameters().contains(&Value(value)) {
+                true => self.line(&format!("v{value},")),
+                false => self.line(&format!("v{value}: {},", repr.default())),
+            }
+        }
+        self.indent -= 1;
+        self.line("}");
+        self.indent -= 1;
+        self.line("}");
+        self.indent -= 1;
+        self.line("}");
+
+        self.line("");
+        self.line(&format!("impl Frame<Slots> for F{index} {{"));
+        self.indent += 1;
+        self.line("fn resume(&mut self, rt: &mut Rt) -> Turn<Slots> {");
+        self.indent += 1;
+        self.line("loop {");
+        self.indent += 1;
+        self.line("match self.state {");
+        self.indent += 1;
+        for (b, block) in function.blocks.iter().enumerate() {
+            self.line(&format!("{b} => {{"));
+            self.indent += 1;
+            self.block(block, &mut scope);
+            self.indent -= 1;
+            self.line("}");
+        }
+        // The states of frames waiting within a block.
+        let mut pending = std::mem::take(&mut scope.pending);
+        while let Some((state, code)) = pending.pop() {
+            self.line(&format!("{state} => {{"));
+            self.indent += 1;
+            for line in code {
+                self.line(&line);
+            }
+            self.indent -= 1;
+            self.line("}");
+        }
+        self.line("_ => unreachable!(),");
+        self.indent -= 1;
+        self.line("}");
+        self.indent -= 1;
+        self.line("}");
+        self.indent -= 1;
+        self.line("}");
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// A block, whose code is split into new states after the custom blocks it calls.
+    fn block(&mut self, block: &ir::Block, scope: &mut Scope) {
+        let mut lines = Vec::new();
+        let mut states = Vec::new();
+        for instruction in block.instructions.iter() {
+            match &instruction.op {
+                Op::Call(callee) => {
+                    let state = scope.state();
+                    let arguments = scope.arguments(*callee, &instruction.operands);
+                    lines.push(format!("self.state = {state};"));
+                    lines.push(format!(
+                        "return Turn::Call(Box::new(F{callee}::new({})));",
+                        arguments.join(", ")
+                    ));
+                    states.push((state, std::mem::take(&mut lines)));
+                }
+                _ => lines.extend(scope.instruction(instruction)),
+            }
+        }
+        lines.extend(scope.terminator(&block.terminator));
+        let mut code = lines;
+        // Each state goes on with what comes after it in the block.
+        for (state, before) in states.into_iter().rev() {
+            scope.pending.push((state, code));
+            code = before;
+        }
+        for line in code {
+            self.line(&line);
+        }
+    }
+}
+
+/// The function being compiled.
+struct Scope<'a> {
+    function: &'a Function,
+    program: &'a ir::Program,
+    /// The constant each value defined by a constant instruction is, to be used in its place.
+    constants: HashMap<Value, Expr>,
+    repr: HashMap<Value, Repr>,
+    /// States past those of the blocks, with their code.
+    pending: Vec<(u32, Vec<String>)>,
+    states: u32,
+}
+
+impl<'a> Scope<'a> {
+    fn new(function: &'a Function, program: &'a ir::Program) -> Self {
+        let mut constants = HashMap::new();
+        let mut repr = HashMap::new();
+        for parameter in function.parameters() {
+            repr.insert(*parameter, Repr::slot(function.types[parameter.0 as usize]));
+        }
+        for block in function.blocks.iter().skip(1) {
+            for parameter in block.parameters.iter() {
+                repr.insert(
+                    *parameter,
+                    Repr::local(function.types[parameter.0 as usize]),
+                );
+            }
+        }
+        for instruction in function.blocks.iter().flat_map(|b| &b.instructions) {
+            let Some(result) = instruction.result else {
+                continue;
+            };
+            let ty = function.types[result.0 as usize];
+            match &instruction.op {
+                Op::Constant(c) => {
+                    constants.insert(result, literal(c));
+                }
+                Op::Variable(v) => {
+                    let ty = program.variables[*v as usize].ty;
+                    repr.insert(result, Repr::slot(ty));
+                }
+                _ => {
+                    repr.insert(result, Repr::local(ty));
+                }
+            }
+        }
+        Scope {
+            function,
+            program,
+            constants,
+            repr,
+            pending: Vec::new(),
+            states: function.blocks.len() as u32,
+        }
+    }
+
+    /// The values kept in the frame, in order.
+    fn fields(&self) -> Vec<(u32, Repr)> {
+        let mut fields = self.repr.iter().map(|(v, r)| (v.0, *r)).collect::<Vec<_>>();
+        fields.sort_by_key(|(v, _)| *v);
+        fields
+    }
+
+    /// A new state, past those of the blocks.
+    fn state(&mut self) -> u32 {
+        self.states += 1;
+        self.states - 1
+    }
+
+    fn value(&self, value: Value) -> Expr {
+        match self.constants.get(&value) {
+            Some(constant) => Expr::new(constant.code.clone(), constant.repr),
+            None => Expr {
+                code: format!("self.v{}", value.0),
+                repr: self.repr[&value],
+                place: true,
+            },
+        }
+    }
+
+    /// The operands of an instruction.
+    fn operands(&self, instruction: &Instruction) -> Vec<Expr> {
+        instruction
+            .operands
+            .iter()
+            .map(|v| self.value(*v))
+            .collect()
+    }
+
+    /// Arguments to a function, kept as its parameters are.
+    fn arguments(&self, callee: u32, operands: &[Value]) -> Vec<String> {
+        let function = &self.program.functions[callee as usize];
+        operands
+            .iter()
+            .zip(function.parameters())
+            .map(|(v, p)| {
+                let ty = function.types[p.0 as usize];
+                self.value(*v).to(Repr::slot(ty))
+            })
+            .collect()
+    }
+
+    /// An instruction as statements, or nothing for constants.
+    fn instruction(&self, instruction: &Instruction) -> Vec<String> {
+        let operands = self.operands(instruction);
+        let number = |i: usize| operands[i].number();
+        let borrow = |i: usize| operands[i].borrow();
+        let list = |l: &u32| slot('l', *l);
+        let item =
+            |l: &u32, i: usize| operands[i].to(Repr::slot(self.program.lists[*l as usize].ty));
+        let compare = || match (operands[0].repr, operands[1].repr) {
+            (Repr::Number, Repr::Number) => {
+                format!(
+                    "compare_numbers({}, {})",
+                    operands[0].code, operands[1].code
+                )
+            }
+            _ => format!("compare({}, {})", borrow(0), borrow(1)),
+        };
+        let (code, repr) = match &instruction.op {
+            Op::Constant(_) | Op::Call(_) => return Vec::new(),
+            Op::Variable(v) => {
+                let ty = self.program.variables[*v as usize].ty;
+                let variable = Expr {
+                    code: slot('v', *v),
+                    repr: Repr::slot(ty),
+                    place: true,
+                };
+                (variable.to(variable.repr), variable.repr)
+            }
+            Op::SetVariable(v) => {
+                let ty = self.program.variables[*v as usize].ty;
+                let value = operands[0].to(Repr::slot(ty));
+                return vec![format!("{} = {value};", slot('v', *v))];
+            }
+            Op::ChangeVariable(v) => {
+                let variable = slot('v', *v);
+                let sum = format!("{variable}.number() + {}", number(0));
+                return match Repr::slot(self.program.variables[*v as usize].ty) {
+                    Repr::Number => vec![format!("{variable} = {sum};")],
+                    _ => vec![format!("{variable} = PrimitiveValue::Number({sum});")],
+                };
+            }
+            Op::ListContents(l) => (format!("list_contents(&{})", list(l)), Repr::Text),
+            Op::AddToList(l) => {
+                return vec![format!("list_add(&mut {}, {});", list(l), item(l, 0))];
+            }
+            Op::DeleteOfList(l) => {
+                return vec![format!("list_delete(&mut {}, {});", list(l), borrow(0))];
+            }
+            Op::DeleteAllOfList(l) => return vec![format!("{}.clear();", list(l))],
+            Op::InsertAtList(l) => {
+                return vec![format!(
+                    "list_insert(&mut {}, {}, {});",
+                    list(l),
+                    borrow(0),
+                    item(l, 1)
+                )];
+            }
+            Op::ReplaceItemOfList(l) => {
+                return vec![format!(
+                    "list_replace(&mut {}, {}, {});",
+                    list(l),
+                    borrow(0),
+                    item(l, 1)
+                )];
+            }
+            Op::ItemOfList(l) => (
+                format!("list_item(&{}, {})", list(l), borrow(0)),
+                Repr::Value,
+            ),
+            Op::ItemNumOfList(l) => (
+                format!("list_index_of(&{}, {})", list(l), borrow(0)),
+                Repr::Number,
+            ),
+            Op::LengthOfList(l) => (format!("{}.len() as f64", list(l)), Repr::Number),
+            Op::ListContainsItem(l) => (
+                format!("list_index_of(&{}, {}) != 0.0", list(l), borrow(0)),
+                Repr::Boolean,
+            ),
+            Op::Add => (format!("{} + {}", number(0), number(1)), Repr::Number),
+            Op::Subtract => (format!("{} - {}", number(0), number(1)), Repr::Number),
+            Op::Multiply => (format!("{} * {}", number(0), number(1)), Repr::Number),
+            Op::Divide => (format!("{} / {}", number(0), number(1)), Repr::Number),
+            Op::Random => (
+                format!("random({}, {})", borrow(0), borrow(1)),
+                Repr::Number,
+            ),
+            Op::Gt => (format!("{}.is_gt()", compare()), Repr::Boolean),
+            Op::Lt => (format!("{}.is_lt()", compare()), Repr::Boolean),
+            Op::Equals => (format!("{}.is_eq()", compare()), Repr::Boolean),
+            Op::And => (
+                format!(
+                    "{} && {}",
+                    operands[0].to(Repr::Boolean),
+                    operands[1].to(Repr::Boolean)
+                ),
+                Repr::Boolean,
+            ),
+            Op::Or => (
+                format!(
+                    "{} || {}",
+                    operands[0].to(Repr::Boolean),
+                    operands[1].to(Repr::Boolean)
+                ),
+                Repr::Boolean,
+            ),
+            Op::Not => (format!("!{}", operands[0].to(Repr::Boolean)), Repr::Boolean),
+            Op::Join => (format!("join({}, {})", borrow(0), borrow(1)), Repr::Text),
+            Op::LetterOf => (
+                format!("letter_of({}, {})", number(0), borrow(1)),
+                Repr::Text,
+            ),
+            Op::Length => (format!("length({})", borrow(0)), Repr::Number),
+            Op::Contains => (
+                format!("contains({}, {})", borrow(0), borrow(1)),
+                Repr::Boolean,
+            ),
+            Op::Mod => (
+                format!("modulo({}, {})", number(0), number(1)),
+                Repr::Number,
+            ),
+            Op::Round => (format!("cast::round({})", number(0)), Repr::Number),
+            Op::MathOp(Some(op)) => (
+                format!("mathop({:?}, {})", op.name(), number(0)),
+                Repr::Number,
+            ),
+            Op::MathOp(None) => ("fail(\"unknown math operator\")".to_string(), Repr::Number),
+            Op::Say | Op::Think => return vec![format!("say({});", borrow(0))],
+            // Nothing else runs until the question is answered.
+            Op::Ask => return vec![format!("rt.ask({});", borrow(0))],
+            Op::Answer => ("rt.answer.clone()".to_string(), Repr::Text),
+            Op::Timer => ("rt.timer()".to_string(), Repr::Number),
+            Op::ResetTimer => return vec!["rt.reset_timer();".to_string()],
+            Op::DaysSince2000 => ("days_since_2000()".to_string(), Repr::Number),
+            Op::Deadline => (format!("rt.deadline({})", number(0)), Repr::Number),
+            Op::DeadlineMillis => (format!("rt.deadline_millis({})", number(0)), Repr::Number),
+            Op::Broadcast => return vec![format!("rt.broadcast({});", borrow(0))],
+        };
+        match instruction.result {
+            Some(result) => {
+                let value = Expr::new(format!("({code})"), repr);
+                let value = match value.repr == self.repr[&result] {
+                    true => code,
+                    false => value.to(self.repr[&result]),
+                };
+                vec![format!("self.v{} = {value};", result.0)]
+            }
+            None => vec![format!("{code};")],
+        }
+    }
+
+    /// Copies the arguments of `target` into the parameters of its block, all
+    /// at once as they may be the same values, and goes there.
+    fn go(&self, target: &Target) -> Vec<String> {
+        let parameters = &self.function.blocks[target.block.0 as usize].parameters;
+        let mut lines = Vec::new();
+        if !parameters.is_empty() {
+            let (names, values): (Vec<_>, Vec<_>) = parameters
+                .iter()
+                .zip(&target.arguments)
+                .map(|(p, a)| (format!("self.v{}", p.0), self.value(*a).to(self.repr[p])))
+                .unzip();
+            match names.len() {
+                1 => lines.push(format!("{} = {};", names[0], values[0])),
+                _ => lines.push(format!("({}) = ({});", names.join(", "), values.join(", "))),
+            }
+        }
+        lines.push(format!("self.state = {};", target.block.0));
+        lines
+    }
+
+    fn terminator(&mut self, terminator: &Terminator) -> Vec<String> {
+        let mut lines = Vec::new();
+        let indent = |lines: Vec<String>| lines.into_iter().map(|l| format!("    {l}"));
+        match terminator {
+            Terminator::Jump(target) => lines.extend(self.go(target)),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = self.value(*condition).to(Repr::Boolean);
+                lines.push(format!("if {condition} {{"));
+                lines.extend(indent(self.go(then)));
+                lines.push("} else {".to_string());
+                lines.extend(indent(self.go(otherwise)));
+                lines.push("}".to_string());
+            }
+            Terminator::Yield(target) => {
+                lines.extend(self.go(target));
+                lines.push("return Turn::Yield;".to_string());
+            }
+            Terminator::Sleep { deadline, resume } => {
+                let deadline = self.value(*deadline).code;
+                let state = self.state();
+                lines.push(format!("self.state = {state};"));
+                let mut wait = vec![
+                    format!("if rt.now() < {deadline} {{"),
+                    format!("    return Turn::Sleep({deadline});"),
+                    "}".to_string(),
+                ];
+                wait.extend(self.go(resume));
+                self.pending.push((state, wait));
+            }
+            Terminator::AwaitBroadcast { name, resume } => {
+                let name = self.value(*name).borrow();
+                let state = self.state();
+                lines.push(format!("self.state = {state};"));
+                let mut wait = vec![
+                    format!("if rt.receiving({name}) {{"),
+                    "    return Turn::Yield;".to_string(),
+                    "}".to_string(),
+                ];
+                wait.extend(self.go(resume));
+                self.pending.push((state, wait));
+            }
+            Terminator::Return => lines.push("return Turn::Return;".to_string()),
+            Terminator::StopAll => lines.push("return Turn::StopAll;".to_string()),
+        }
+        lines
+    }
+}
@@ -0,0 +1,523 @@
+//! Compiles projects to self-contained WebAssembly modules.
+//!
+//! Every IR function becomes a state machine, whose blocks are its states, run
+//! by the scheduler of the runtime in `runtime/wasm` so that scripts take turns
+//! like in Scratch. The module imports
+//! `say`, `think`, `ask` and `now` from `kcc` and exports `memory`, `start` and `tick`.
+
+use std::{fmt::Write, fs, path::Path};
+
+use hashbrown::HashMap;
+use scratch_ast::errors::ScratchError;
+
+use crate::{
+    compiler::Layout,
+    ir::{self, Constant, Function, Instruction, MathOp, Op, Target, Terminator, Trigger, Value},
+    vm::ScratchResult,
+};
+
+pub const RUNTIME: &str = include_str!("../../runtime/wasm/runtime.wat");
+
+/// Where the text and tables of the project start in linear memory.
+const DATA_START: usize = 16384;
+
+const TAG_BOOLEAN: u64 = 0xFFF9 << 48;
+const TAG_STRING: u64 = 0xFFFA << 48;
+
+/// Writes `<name>.wasm` into `out_dir`.
+pub fn compile(layout: &Layout, name: &str, out_dir: &Path) -> ScratchResult {
+    let program = ir::lower::lower(layout)?;
+    let source = generate(&program, name);
+    let module = wat::parse_str(&source)
+        .map_err(|e| ScratchError::internal(e, "assembling WebAssembly module"))?;
+    fs::create_dir_all(out_dir)
+        .map_err(|e| ScratchError::internal(e, format!("creating {}", out_dir.display())))?;
+    let path = out_dir.join(format!("{name}.wasm"));
+    fs::write(&path, module)
+        .map_err(|e| ScratchError::internal(e, format!("writing {}", path.display())))?;
+    Ok(())
+}
+
+/// Static data of the module: text of the project and the tables of the runtime.
+#[derive(Default)]
+struct Data {
+    bytes: Vec<u8>,
+    strings: HashMap<String, usize>,
+}
+
+impl Data {
+    fn align(&mut self, to: usize) {
+        while !self.bytes.len().is_multiple_of(to) {
+            self.bytes.push(0);
+        }
+    }
+
+    /// The address of a string, laid out like strings of the runtime.
+    fn string(&mut self, s: &str) -> usize {
+        if let Some(address) = self.strings.get(s) {
+            return *address;
+        }
+        self.align(8);
+        let address = DATA_START + self.bytes.len();
+        let units = s.encode_utf16().collect::<Vec<_>>();
+        self.bytes.extend(0u32.to_le_bytes());
+        self.bytes.extend((units.len() as u32).to_le_bytes());
+        for unit in units {
+            self.bytes.extend(unit.to_le_bytes());
+        }
+        self.strings.insert(s.to_string(), address);
+        address
+    }
+
+    /// The address of a table of 32-bit words.
+    fn words(&mut self, words: &[u32]) -> usize {
+        self.align(8);
+        let address = DATA_START + self.bytes.len();
+        for word in words {
+            self.bytes.extend(word.to_le_bytes());
+        }
+        address
+    }
+}
+
+fn number(n: f64) -> String {
+    let bits = if n.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        n.to_bits()
+    };
+    format!("(i64.const 0x{bits:X})")
+}
+
+/// The code of a `math op` operator, as dispatched by `$kcc_mathop`.
+fn mathop(operator: Option<MathOp>) -> u32 {
+    match operator {
+        Some(MathOp::Abs) => 0,
+        Some(MathOp::Floor) => 1,
+        Some(MathOp::Ceiling) => 2,
+        Some(MathOp::Sqrt) => 3,
+        Some(MathOp::Sin) => 4,
+        Some(MathOp::Cos) => 5,
+        Some(MathOp::Tan) => 6,
+        Some(MathOp::Asin) => 7,
+        Some(MathOp::Acos) => 8,
+        Some(MathOp::Atan) => 9,
+        Some(MathOp::Ln) => 10,
+        Some(MathOp::Log) => 11,
+        Some(MathOp::Exp) => 12,
+        Some(MathOp::Pow10) => 13,
+        None => 14,
+    }
+}
+
+/// The states of the function being compiled, its blocks first. Every state
+/// ends by jumping to another or returning.
+struct Machine {
+    states: Vec<Vec<String>>,
+    current: usize,
+}
+
+impl Machine {
+    fn state(&mut self) -> usize {
+        self.states.push(Vec::new());
+        self.states.len() - 1
+    }
+
+    fn emit(&mut self, instruction: String) {
+        self.states[self.current].push(instruction);
+    }
+
+    fn jump(&mut self, state: usize) {
+        self.emit(format!(
+            "(local.set $pc (i32.const {state})) (br $dispatch)"
+        ));
+    }
+
+    /// Saves where to resume and hands control back to the scheduler: 1 yields, 2 calls.
+    fn suspend(&mut self, state: usize, status: u8) {
+        self.emit(format!(
+            "(return (call $kcc_suspend (local.get $fp) (i32.const {state}) (i32.const {status})))"
+        ));
+    }
+}
+
+/// The function being compiled.
+struct Scope<'a> {
+    function: &'a Function,
+    /// The literal each value defined by a constant instruction is, to be used in its place.
+    constants: HashMap<Value, String>,
+    /// The local of the frame keeping each other value, arguments first.
+    locals: HashMap<Value, usize>,
+}
+
+impl Scope<'_> {
+    fn value(&self, value: Value) -> String {
+        match self.constants.get(&value) {
+            Some(literal) => literal.clone(),
+            None => format!(
+                "(call $kcc_get (local.get $fp) (i32.const {}))",
+                self.locals[&value]
+            ),
+        }
+    }
+
+    fn set(&self, value: Value, expression: &str) -> String {
+        format!(
+            "(call $kcc_set (local.get $fp) (i32.const {}) {expression})",
+            self.locals[&value]
+        )
+    }
+}
+
+struct Generator {
+    data: Data,
+    machine: Machine,
+}
+
+fn generate(program: &ir::Program, name: &str) -> String {
+    let mut generator = Generator {
+        data: Data::default(),
+        machine: Machine {
+            states: Vec::new(),
+            current: 0,
+        },
+    };
+    let mut functions = String::new();
+    let mut frame_sizes = Vec::new();
+    for (i, function) in program.functions.iter().enumerate() {
+        frame_sizes.push(generator.function(i, function, &mut functions) as u32);
+    }
+
+    let mut setup = String::from("(func $kcc_setup\n");
+    for (slot, variable) in program.variables.iter().enumerate() {
+        let value = generator.literal(&variable.value);
+        writeln!(setup, "  (call $kcc_set_var (i32.const {slot}) {value})").unwrap();
+    }
+    for (slot, list) in program.lists.iter().enumerate() {
+        for item in list.items.iter() {
+            let item = generator.literal(item);
+            writeln!(setup, "  (call $kcc_list_add (i32.const {slot}) {item})").unwrap();
+        }
+    }
+    setup.push_str(")\n");
+
+    let data = &mut generator.data;
+    let frame_sizes = data.words(&frame_sizes);
+    let mut green_flag = Vec::new();
+    let mut receivers = Vec::new();
+    for (i, function) in program.functions.iter().enumerate() {
+        match &function.trigger {
+            Trigger::GreenFlag => green_flag.push(i as u32),
+            Trigger::Broadcast(name) => receivers.extend([data.string(name) as u32, i as u32]),
+            Trigger::Procedure => (),
+        }
+    }
+    let green_flag_table = data.words(&green_flag);
+    let receiver_table = data.words(&receivers);
+    data.align(8);
+    let heap_start = DATA_START + data.bytes.len();
+
+    let mut out = format!(";; {name}, compiled by kcc.\n(module\n{RUNTIME}\n");
+    writeln!(
+        out,
+        "(memory (export \"memory\") {})",
+        heap_start.div_ceil(65536) + 1
+    )
+    .unwrap();
+    for (global, value) in [
+        ("heap_start", heap_start),
+        ("variable_count", program.variables.len()),
+        ("list_count", program.lists.len()),
+        ("script_count", program.functions.len()),
+        ("frame_sizes", frame_sizes),
+        ("green_flag", green_flag_table),
+        ("green_flag_count", green_flag.len()),
+        ("receivers", receiver_table),
+        ("receiver_count", receivers.len() / 2),
+    ] {
+        writeln!(out, "(global $kcc_{global} i32 (i32.const {value}))").unwrap();
+    }
+    if !data.bytes.is_empty() {
+        writeln!(out, "(data (i32.const {DATA_START})").unwrap();
+        for chunk in data.bytes.chunks(32) {
+            out.push_str("  \"");
+            for b in chunk {
+                write!(out, "\\{b:02x}").unwrap();
+            }
+            out.push_str("\"\n");
+        }
+        out.push_str(")\n");
+    }
+    let table = (0..program.functions.len())
+        .map(|i| format!(" $script_{i}"))
+        .collect::<String>();
+    writeln!(out, "(table $kcc_scripts funcref (elem{table}))").unwrap();
+    out.push_str(&setup);
+    out.push_str(&functions);
+    out.push_str(")\n");
+    out
+}
+
+impl Generator {
+    fn literal(&mut self, constant: &Constant) -> String {
+        match constant {
+            Constant::Boolean(b) => format!("(i64.const 0x{:X})", TAG_BOOLEAN | *b as u64),
+            Constant::Number(n) => number(*n),
+            Constant::Integer(n) => number(*n as f64),
+            Constant::String(s) | Constant::Color(s) | Constant::Broadcast(s) => self.string(s),
+        }
+    }
+
+    fn string(&mut self, s: &str) -> String {
+        format!(
+            "(i64.const 0x{:X})",
+            TAG_STRING | self.data.string(s) as u64
+        )
+    }
+
+    /// Compiles a function into one resuming it in the state its frame saved.
+    /// Returns how many locals its frame holds.
+    fn function(&mut self, index: usize, function: &Function, out: &mut String) -> usize {
+        let mut constants = HashMap::new();
+        let mut locals = HashMap::new();
+        for value in function.parameters() {
+            locals.insert(*value, locals.len());
+        }
+        for block in function.blocks.iter() {
+            for value in block.parameters.iter() {
+                if !locals.contains_key(value) {
+                    locals.insert(*value, locals.len());
+                }
+            }
+            for instruction in block.instructions.iter() {
+                match (instruction.result, &instruction.op) {
+                    (Some(result), Op::Constant(c)) => {
+                        constants.insert(result, self.literal(c));
+                    }
+                    (Some(result), _) => {
+                        locals.insert(result, locals.len());
+                    }
+                    (None, _) => (),
+                }
+            }
+        }
+        let scope = Scope {
+            function,
+            constants,
+            locals,
+        };
+        self.machine = Machine {
+            states: vec![Vec::new(); function.blocks.len()],
+            current: 0,
+        };
+        for (b, block) in function.blocks.iter().enumerate() {
+            self.machine.current = b;
+            self.block(block, &scope);
+        }
+
+        let states = std::mem::take(&mut self.machine.states);
+        let arguments = function
+            .blocks
+            .iter()
+            .map(|b| b.parameters.len())
+            .max()
+            .unwrap_or(0);
+        writeln!(
+            out,
+            "\n(func $script_{index} (type $kcc_script) (param $fp i32) (result i32)"
+        )
+        .unwrap();
+        out.push_str("  (local $pc i32) (local $frame i32)");
+        for i in 0..arguments {
+            write!(out, " (local $a{i} i64)").unwrap();
+        }
+        out.push('\n');
+        out.push_str("  (local.set $pc (call $kcc_state (local.get $fp)))\n");
+        out.push_str("  loop $dispatch\n");
+        for i in (0..states.len()).rev() {
+            writeln!(out, "  block $s{i}").unwrap();
+        }
+        let labels = (0..states.len())
+            .map(|i| format!(" $s{i}"))
+            .collect::<String>();
+        writeln!(
+            out,
+            "  (br_table{labels} $s{} (local.get $pc))",
+            states.len() - 1
+        )
+        .unwrap();
+        for state in states {
+            out.push_str("  end\n");
+            for instruction in state {
+                writeln!(out, "  {instruction}").unwrap();
+            }
+        }
+        out.push_str("  end\n  (i32.const 0))\n");
+        scope.locals.len()
+    }
+
+    fn block(&mut self, block: &ir::Block, scope: &Scope) {
+        for instruction in block.instructions.iter() {
+            match &instruction.op {
+                Op::Constant(_) => (),
+                Op::Call(f) => {
+                    self.machine.emit(format!(
+                        "(local.set $frame (call $kcc_push (i32.const {f})))"
+                    ));
+                    // Arguments are evaluated in the frame of the caller once the
+                    // callee's is pushed, and kept like variables.
+                    for (i, argument) in instruction.operands.iter().enumerate() {
+                        self.machine.emit(format!(
+                            "(call $kcc_set (local.get $frame) (i32.const {i}) (call $kcc_primitive {}))",
+                            scope.value(*argument)
+                        ));
+                    }
+                    let resume = self.machine.state();
+                    self.machine.suspend(resume, 2);
+                    self.machine.current = resume;
+                }
+                _ => {
+                    let instruction = self.instruction(instruction, scope);
+                    self.machine.emit(instruction);
+                }
+            }
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => self.go(target, scope),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.machine.emit(format!(
+                    "(if (call $kcc_boolean {}) (then",
+                    scope.value(*condition)
+                ));
+                self.go(then, scope);
+                self.machine.emit("))".to_string());
+                self.go(otherwise, scope);
+            }
+            Terminator::Yield(target) => {
+                self.arguments(target, scope);
+                self.machine.suspend(target.block.0 as usize, 1);
+            }
+            Terminator::Sleep { deadline, resume } => {
+                let waiting = format!("(call $kcc_waiting {})", scope.value(*deadline));
+                self.wait(waiting);
+                self.go(resume, scope);
+            }
+            Terminator::AwaitBroadcast { name, resume } => {
+                let waiting = format!("(call $kcc_receiving {})", scope.value(*name));
+                self.wait(waiting);
+                self.go(resume, scope);
+            }
+            Terminator::Return => self.machine.emit("(return (i32.const 0))".to_string()),
+            Terminator::StopAll => {
+                self.machine.emit("(call $kcc_stop_all)".to_string());
+                self.machine.emit("(return (i32.const 0))".to_string());
+            }
+        }
+    }
+
+    /// Yields in a new state for as long as `waiting` is true, then continues.
+    fn wait(&mut self, waiting: String) {
+        let state = self.machine.state();
+        self.machine.jump(state);
+        self.machine.current = state;
+        self.machine.emit(format!(
+            "(if {waiting} (then (return (call $kcc_suspend (local.get $fp) (i32.const {state}) (i32.const 1)))))"
+        ));
+    }
+
+    /// Copies the arguments of `target` into the parameters of its block, all
+    /// at once as they may be the same values.
+    fn arguments(&mut self, target: &Target, scope: &Scope) {
+        let parameters = &scope.function.blocks[target.block.0 as usize].parameters;
+        if parameters.len() == 1 {
+            let argument = scope.value(target.arguments[0]);
+            self.machine.emit(scope.set(parameters[0], &argument));
+            return;
+        }
+        for (i, argument) in target.arguments.iter().enumerate() {
+            self.machine
+                .emit(format!("(local.set $a{i} {})", scope.value(*argument)));
+        }
+        for (i, parameter) in parameters.iter().enumerate() {
+            self.machine
+                .emit(scope.set(*parameter, &format!("(local.get $a{i})")));
+        }
+    }
+
+    fn go(&mut self, target: &Target, scope: &Scope) {
+        self.arguments(target, scope);
+        self.machine.jump(target.block.0 as usize);
+    }
+
+    /// An instruction other than a constant or a call.
+    fn instruction(&mut self, instruction: &Instruction, scope: &Scope) -> String {
+        let operands = instruction
+            .operands
+            .iter()
+            .map(|v| scope.value(*v))
+            .collect::<Vec<_>>();
+        let args = operands.iter().map(|a| format!(" {a}")).collect::<String>();
+        let call = |f: &str| format!("(call {f}{args})");
+        let list = |l: &u32, f: &str| format!("(call {f} (i32.const {l}){args})");
+        let expression = match &instruction.op {
+            Op::Constant(_) | Op::Call(_) => unreachable!("compiled by the block"),
+            Op::Variable(v) => format!("(call $kcc_var (i32.const {v}))"),
+            Op::SetVariable(v) => format!("(call $kcc_set_var (i32.const {v}){args})"),
+            Op::ChangeVariable(v) => format!("(call $kcc_change_var (i32.const {v}){args})"),
+            Op::ListContents(l) => list(l, "$kcc_list_contents"),
+            Op::AddToList(l) => list(l, "$kcc_list_add"),
+            Op::DeleteOfList(l) => list(l, "$kcc_list_delete"),
+            Op::DeleteAllOfList(l) => list(l, "$kcc_list_clear"),
+            // The runtime takes the item first.
+            Op::InsertAtList(l) => format!(
+                "(call $kcc_list_insert (i32.const {l}) {} {})",
+                operands[1], operands[0]
+            ),
+            Op::ReplaceItemOfList(l) => format!(
+                "(call $kcc_list_replace (i32.const {l}) {} {})",
+                operands[1], operands[0]
+            ),
+            Op::ItemOfList(l) => list(l, "$kcc_list_item"),
+            Op::ItemNumOfList(l) => list(l, "$kcc_list_index_of"),
+            Op::LengthOfList(l) => list(l, "$kcc_list_length"),
+            Op::ListContainsItem(l) => list(l, "$kcc_list_contains"),
+            Op::Add => call("$kcc_add"),
+            Op::Subtract => call("$kcc_subtract"),
+            Op::Multiply => call("$kcc_multiply"),
+            Op::Divide => call("$kcc_divide"),
+            Op::Random => call("$kcc_random"),
+            Op::Gt => call("$kcc_gt"),
+            Op::Lt => call("$kcc_lt"),
+            Op::Equals => call("$kcc_equals"),
+            Op::And => call("$kcc_and"),
+            Op::Or => call("$kcc_or"),
+            Op::Not => call("$kcc_not"),
+            Op::Join => call("$kcc_join"),
+            Op::LetterOf => call("$kcc_letter_of"),
+            Op::Length => call("$kcc_length"),
+            Op::Contains => call("$kcc_contains"),
+            Op::Mod => call("$kcc_modulo"),
+            Op::Round => call("$kcc_round_value"),
+            Op::MathOp(op) => format!("(call $kcc_mathop (i32.const {}){args})", mathop(*op)),
+            Op::Say => call("$kcc_say"),
+            Op::Think => call("$kcc_think"),
+            Op::Ask => call("$kcc_ask"),
+            Op::Answer => "(call $kcc_get_answer)".to_string(),
+            Op::Timer => "(call $kcc_timer)".to_string(),
+            Op::ResetTimer => "(call $kcc_reset_timer)".to_string(),
+            Op::DaysSince2000 => "(call $kcc_days_since_2000)".to_string(),
+            Op::Deadline => call("$kcc_deadline"),
+            Op::DeadlineMillis => call("$kcc_deadline_millis"),
+            Op::Broadcast => call("$kcc_broadcast"),
+        };
+        match instruction.result {
+            Some(result) => scope.set(result, &expression),
+            None if instruction.op.has_result() => format!("(drop {expression})"),
+            None => expression,
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! `kcc test`: runs projects and compares what they say to what they should say,
+//! e.g. to grade student projects.
+//!
+//! A project `name.sb3` is tested against `name.out` when there is one, answering
+//! its questions with the lines of `name.in`. Comments of the project can hold
+//! more cases. A comment is a case when one of its lines is `expect:`: the lines
+//! after it are the expected output, and the lines before it settings:
+//!
+//! ```text
+//! answer: Alice
+//! seed: 7
+//! expect:
+//! Hello, Alice!
+//! ```
+//!
+//! Every case runs in its own `kcc run`, as the host is set up once per process,
+//! with a fixed seed and a virtual clock, under which scripts take turns in the
+//! same order every run on either engine, so that it says the same every time.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+use colored::Colorize;
+use scratch_ast::{errors::ScratchError, model};
+use serde::Serialize;
+
+use crate::{cli::exit, linediff};
+
+/// A run of a project, and what it should say.
+#[derive(Clone, Debug)]
+pub struct Case {
+    /// The file name of the project, with `#N` for the Nth case in its comments.
+    pub name: String,
+    pub project: PathBuf,
+    pub expected: String,
+    pub answers: Vec<String>,
+    /// Overrides the seed of the whole run.
+    pub seed: Option<u64>,
+    /// Why the case cannot run, e.g. a setting kcc does not know.
+    pub problem: Option<String>,
+}
+
+/// How every case runs.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub seed: u64,
+    pub max_steps: u64,
+    /// More options for `kcc run`, e.g. to turn optimizations off.
+    pub args: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Passed,
+    /// The project said something else.
+    Failed,
+    /// The project could not run to the end.
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Outcome {
+    pub name: String,
+    pub project: PathBuf,
+    pub status: Status,
+    /// Why the case failed or could not run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Seconds the run took.
+    pub time: f64,
+    pub exit_code: Option<i32>,
+    pub expected: String,
+    pub actual: String,
+    pub stderr: String,
+}
+
+/// The projects of `path`, sorted by name, or `path` itself if it is a project.
+/// Folders inside it are not searched.
+pub fn discover(path: &Path) -> Result<Vec<PathBuf>, ScratchError> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let location = format!("looking for projects in {}", path.display());
+    let mut projects = fs::read_dir(path)
+        .map_err(|e| ScratchError::not_found(e, &location))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "sb3"))
+        .collect::<Vec<_>>();
+    projects.sort();
+    Ok(projects)
+}
+
+/// Reads the lines of a file, which may not exist.
+fn read_lines(path: &Path) -> Option<Vec<String>> {
+    let text = fs::read_to_string(path).ok()?;
+    Some(text.lines().map(str::to_string).collect())
+}
+
+/// The case written in a comment, if it is one.
+fn comment_case(project: &Path, name: String, text: &str) -> Option<Case> {
+    let lines = text
+        .lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .collect::<Vec<_>>();
+    let expect = lines.iter().position(|l| l.trim() == "expect:")?;
+    let mut case = Case {
+        name,
+        project: project.to_path_buf(),
+        expected: lines[expect + 1..]
+            .iter()
+            .map(|l| format!("{l}\n"))
+            .collect(),
+        answers: Vec::new(),
+        seed: None,
+        problem: None,
+    };
+    for line in lines[..expect].iter().filter(|l| !l.trim().is_empty()) {
+        let (key, value) = line.split_once(':').unwrap_or((line, ""));
+        // Answers keep their spaces, but for the one after the colon.
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match key.trim() {
+            "answer" => case.answers.push(value.to_string()),
+            "seed" => match value.trim().parse() {
+                Ok(seed) => case.seed = Some(seed),
+                Err(_) => case.problem = Some(format!("the seed {value} is not a number")),
+            },
+            _ => case.problem = Some(format!("unknown setting {line}")),
+        }
+    }
+    Some(case)
+}
+
+/// The cases of a project: its `.out` file, and its comments if it could be read.
+/// A project that could not be read still gets a case, which will fail to run.
+pub fn cases(project: &Path, parsed: Option<&model::Project>) -> Vec<Case> {
+    let file_name = project
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut cases = Vec::new();
+    if let Some(expected) = read_lines(&project.with_extension("out")) {
+        cases.push(Case {
+            name: file_name.clone(),
+            project: project.to_path_buf(),
+            expected: expected.iter().map(|l| format!("{l}\n")).collect(),
+            answers: read_lines(&project.with_extension("in")).unwrap_or_default(),
+            seed: None,
+            problem: None,
+        });
+    }
+    let Some(parsed) = parsed else {
+        if cases.is_empty() {
+            cases.push(Case {
+                name: file_name,
+                project: project.to_path_buf(),
+                expected: String::new(),
+                answers: Vec::new(),
+                seed: None,
+                problem: None,
+            });
+        }
+        return cases;
+    };
+    let comments = parsed.targets.iter().flat_map(|target| match target {
+        model::Target::Sprite(s) => s.comments.iter(),
+        model::Target::Stage(s) => s.comments.iter(),
+    });
+    for comment in comments {
+        let name = format!("{file_name}#{}", cases.len() + 1);
+        cases.extend(comment_case(project, name, &comment.text));
+    }
+    cases
+}
+
+/// Outputs are compared line by line, whatever their line endings.
+fn normalize(output: &str) -> String {
+    output.lines().map(|l| format!("{l}\n")).collect()
+}
+
+/// Runs a case with `kcc`, the executable running this.
+pub fn run(case: &Case, settings: &Settings) -> Outcome {
+    let mut outcome = Outcome {
+        name: case.name.clone(),
+        project: case.project.clone(),
+        status: Status::Error,
+        message: case.problem.clone(),
+        time: 0.0,
+        exit_code: None,
+        expected: case.expected.clone(),
+        actual: String::new(),
+        stderr: String::new(),
+    };
+    if case.problem.is_some() {
+        return outcome;
+    }
+    let kcc = match std::env::current_exe() {
+        Ok(kcc) => kcc,
+        Err(e) => {
+            outcome.message = Some(format!("cannot find kcc: {e}"));
+            return outcome;
+        }
+    };
+    let mut command = Command::new(kcc);
+    command
+        .args(["run", "--virtual-clock", "--seed"])
+        .arg(case.seed.unwrap_or(settings.seed).to_string())
+        .arg("--max-steps")
+        .arg(settings.max_steps.to_string())
+        .args(&settings.args);
+    for answer in case.answers.iter() {
+        command.arg(format!("--answer={answer}"));
+    }
+    let start = Instant::now();
+    let output = command.arg(&case.project).stdin(Stdio::null()).output();
+    outcome.time = start.elapsed().as_secs_f64();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            outcome.message = Some(format!("cannot run kcc: {e}"));
+            return outcome;
+        }
+    };
+    outcome.exit_code = output.status.code();
+    outcome.actual = String::from_utf8_lossy(&output.stdout).to_string();
+    outcome.stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    (outcome.status, outcome.message) = match outcome.exit_code {
+        Some(0 | exit::STOP_ALL) if normalize(&outcome.actual) == normalize(&case.expected) => {
+            (Status::Passed, None)
+        }
+        Some(0 | exit::STOP_ALL) => (Status::Failed, Some("the output differs".to_string())),
+        Some(exit::PARSE_ERROR) => (Status::Error, Some("the project cannot be read".into())),
+        Some(exit::STEP_LIMIT) => (
+            Status::Error,
+            Some(format!(
+                "the project ran more than {} steps",
+                settings.max_steps
+            )),
+        ),
+        _ => (
+            Status::Error,
+            Some("the project failed while running".into()),
+        ),
+    };
+    outcome
+}
+
+/// How many cases passed, failed and could not run.
+pub fn counts(outcomes: &[Outcome]) -> (usize, usize, usize) {
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    (
+        count(Status::Passed),
+        count(Status::Failed),
+        count(Status::Error),
+    )
+}
+
+/// What `kcc test` prints once every case ran: the diffs of the failures, and
+/// how many passed.
+pub fn report(outcomes: &[Outcome]) -> String {
+    let mut output = String::new();
+    let failures = outcomes.iter().filter(|o| o.status != Status::Passed);
+    for outcome in failures.clone() {
+        writeln!(output, "\n---- {} ----", outcome.name).unwrap();
+        if let Some(message) = &outcome.message {
+            writeln!(output, "{message}").unwrap();
+        }
+        match outcome.status {
+            Status::Failed => {
+                writeln!(output, "--- expected\n+++ actual").unwrap();
+                for line in linediff::diff(&outcome.expected, &outcome.actual).lines() {
+                    let line = match line.chars().next() {
+                        Some('-') => line.red(),
+                        Some('+') => line.green(),
+                        _ => line.normal(),
+                    };
+                    writeln!(output, "{line}").unwrap();
+                }
+            }
+            _ => output.push_str(&outcome.stderr),
+        }
+    }
+    if failures.clone().next().is_some() {
+        writeln!(output, "\nfailures:").unwrap();
+        for outcome in failures {
+            writeln!(output, "    {}", outcome.name).unwrap();
+        }
+    }
+    let (passed, failed, errors) = counts(outcomes);
+    let result = match failed + errors {
+        0 => "ok".green(),
+        _ => "FAILED".red(),
+    };
+    writeln!(
+        output,
+        "\ntest result: {result}. {passed} passed; {failed} failed; {errors} errors"
+    )
+    .unwrap();
+    output
+}
+
+/// Escapes text for XML attributes and elements.
+fn xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            // XML 1.0 cannot hold most control characters, even escaped.
+            c if c.is_control() && !matches!(c, '\n' | '\t' | '\r') => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A JUnit XML report, which CI servers show like any other test results.
+pub fn junit(suite: &str, outcomes: &[Outcome]) -> String {
+    let (_, failed, errors) = counts(outcomes);
+    let time = outcomes.iter().map(|o| o.time).sum::<f64>();
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let counts = format!(
+        "tests=\"{}\" failures=\"{failed}\" errors=\"{errors}\" time=\"{time:.3}\"",
+        outcomes.len()
+    );
+    writeln!(output, "<testsuites {counts}>").unwrap();
+    writeln!(output, "  <testsuite name=\"{}\" {counts}>", xml(suite)).unwrap();
+    for outcome in outcomes {
+        let classname = outcome
+            .project
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        write!(
+            output,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            xml(&outcome.name),
+            xml(&classname),
+            outcome.time
+        )
+        .unwrap();
+        let message = xml(outcome.message.as_deref().unwrap_or_default());
+        match outcome.status {
+            Status::Passed => {
+                output.push_str("/>\n");
+                continue;
+            }
+            Status::Failed => writeln!(
+                output,
+                ">\n      <failure message=\"{message}\">{}</failure>",
+                xml(&linediff::diff(&outcome.expected, &outcome.actual))
+            ),
+            Status::Error => writeln!(
+                output,
+                ">\n      <error message=\"{message}\">{}</error>",
+                xml(&outcome.stderr)
+            ),
+        }
+        .unwrap();
+        writeln!(
+            output,
+            "      <system-out>{}</system-out>\n    </testcase>",
+            xml(&outcome.actual)
+        )
+        .unwrap();
+    }
+    output.push_str("  </testsuite>\n</testsuites>\n");
+    output
+}
+
+/// The counts and every outcome, as JSON.
+pub fn json(outcomes: &[Outcome]) -> String {
+    let (passed, failed, errors) = counts(outcomes);
+    let summary = serde_json::json!({
+        "passed": passed,
+        "failed": failed,
+        "errors": errors,
+        "cases": outcomes,
+    });
+    serde_json::to_string_pretty(&summary).unwrap()
+}
@@ -0,0 +1,211 @@
+//! The text format of the IR, printed by `kcc dump`:
+//!
+//! ```text
+//! variable v0 "count": number = 0
+//!
+//! f0 Sprite1 when green flag clicked {
+//! b0:
+//!     %0: number = constant 10
+//!     %1: number = round %0
+//!     jump b1(%1)
+//! b1(%2: number):
+//!     ...
+//! }
+//! ```
+
+use std::fmt;
+
+use scratch_ast::cast;
+
+use crate::ir::{Constant, Function, Instruction, Op, Program, Target, Terminator, Trigger, Value};
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Boolean(b) => write!(f, "{b}"),
+            Constant::Number(n) => f.write_str(&cast::number_to_string(*n)),
+            Constant::Integer(n) => write!(f, "{n}"),
+            Constant::String(s) => write!(f, "{s:?}"),
+            Constant::Color(s) => write!(f, "color {s:?}"),
+            Constant::Broadcast(s) => write!(f, "broadcast {s:?}"),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", self.block.0)?;
+        if !self.arguments.is_empty() {
+            write!(f, "({})", list(&self.arguments))?;
+        }
+        Ok(())
+    }
+}
+
+fn list(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Op {
+    /// The name of the operation, and the constant, operator, slot or function it refers to.
+    fn describe(&self) -> (&'static str, Option<String>) {
+        match self {
+            Op::Constant(c) => ("constant", Some(c.to_string())),
+            Op::Variable(v) => ("variable", Some(format!("v{v}"))),
+            Op::SetVariable(v) => ("set_variable", Some(format!("v{v}"))),
+            Op::ChangeVariable(v) => ("change_variable", Some(format!("v{v}"))),
+            Op::ListContents(l) => ("list_contents", Some(format!("l{l}"))),
+            Op::AddToList(l) => ("add_to_list", Some(format!("l{l}"))),
+            Op::DeleteOfList(l) => ("delete_of_list", Some(format!("l{l}"))),
+            Op::DeleteAllOfList(l) => ("delete_all_of_list", Some(format!("l{l}"))),
+            Op::InsertAtList(l) => ("insert_at_list", Some(format!("l{l}"))),
+            Op::ReplaceItemOfList(l) => ("replace_item_of_list", Some(format!("l{l}"))),
+            Op::ItemOfList(l) => ("item_of_list", Some(format!("l{l}"))),
+            Op::ItemNumOfList(l) => ("item_num_of_list", Some(format!("l{l}"))),
+            Op::LengthOfList(l) => ("length_of_list", Some(format!("l{l}"))),
+            Op::ListContainsItem(l) => ("list_contains_item", Some(format!("l{l}"))),
+            Op::Add => ("add", None),
+            Op::Subtract => ("subtract", None),
+            Op::Multiply => ("multiply", None),
+            Op::Divide => ("divide", None),
+            Op::Random => ("random", None),
+            Op::Gt => ("gt", None),
+            Op::Lt => ("lt", None),
+            Op::Equals => ("equals", None),
+            Op::And => ("and", None),
+            Op::Or => ("or", None),
+            Op::Not => ("not", None),
+            Op::Join => ("join", None),
+            Op::LetterOf => ("letter_of", None),
+            Op::Length => ("length", None),
+            Op::Contains => ("contains", None),
+            Op::Mod => ("mod", None),
+            Op::Round => ("round", None),
+            Op::MathOp(op) => (
+                "mathop",
+                Some(op.map_or("unknown".to_string(), |op| format!("{:?}", op.name()))),
+            ),
+            Op::Say => ("say", None),
+            Op::Think => ("think", None),
+            Op::Ask => ("ask", None),
+            Op::Answer => ("answer", None),
+            Op::Timer => ("timer", None),
+            Op::ResetTimer => ("reset_timer", None),
+            Op::DaysSince2000 => ("days_since_2000", None),
+            Op::Deadline => ("deadline", None),
+            Op::DeadlineMillis => ("deadline_millis", None),
+            Op::Broadcast => ("broadcast", None),
+            Op::Call(function) => ("call", Some(format!("f{function}"))),
+        }
+    }
+}
+
+impl Function {
+    fn instruction(&self, f: &mut fmt::Formatter<'_>, instruction: &Instruction) -> fmt::Result {
+        f.write_str("    ")?;
+        if let Some(result) = instruction.result {
+            write!(f, "{result}: {} = ", self.types[result.0 as usize])?;
+        }
+        let (name, detail) = instruction.op.describe();
+        let operands = list(&instruction.operands);
+        match (detail, operands.is_empty()) {
+            (Some(detail), _) if matches!(instruction.op, Op::Call(_)) => {
+                writeln!(f, "{name} {detail}({operands})")
+            }
+            (Some(detail), true) => writeln!(f, "{name} {detail}"),
+            (Some(detail), false) => writeln!(f, "{name} {detail}, {operands}"),
+            (None, true) => writeln!(f, "{name}"),
+            (None, false) => writeln!(f, "{name} {operands}"),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.trigger, &self.proccode) {
+            (Trigger::GreenFlag, _) => writeln!(f, "{} when green flag clicked {{", self.target)?,
+            (Trigger::Broadcast(name), _) => {
+                writeln!(f, "{} when I receive {name:?} {{", self.target)?
+            }
+            (Trigger::Procedure, proccode) => writeln!(
+                f,
+                "{} define {:?} {{",
+                self.target,
+                proccode.as_deref().unwrap_or("")
+            )?,
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            write!(f, "b{index}")?;
+            if !block.parameters.is_empty() {
+                let parameters = block
+                    .parameters
+                    .iter()
+                    .map(|p| format!("{p}: {}", self.types[p.0 as usize]))
+                    .collect::<Vec<_>>();
+                write!(f, "({})", parameters.join(", "))?;
+            }
+            writeln!(f, ":")?;
+            for instruction in block.instructions.iter() {
+                self.instruction(f, instruction)?;
+            }
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump {target}")?,
+                Terminator::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                } => writeln!(f, "    branch {condition}, {then}, {otherwise}")?,
+                Terminator::Yield(target) => writeln!(f, "    yield {target}")?,
+                Terminator::Sleep { deadline, resume } => {
+                    writeln!(f, "    sleep {deadline}, {resume}")?
+                }
+                Terminator::AwaitBroadcast { name, resume } => {
+                    writeln!(f, "    await_broadcast {name}, {resume}")?
+                }
+                Terminator::Return => writeln!(f, "    return")?,
+                Terminator::StopAll => writeln!(f, "    stop_all")?,
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (slot, variable) in self.variables.iter().enumerate() {
+            writeln!(
+                f,
+                "variable v{slot} {:?}: {} = {}",
+                variable.name, variable.ty, variable.value
+            )?;
+        }
+        for (slot, list) in self.lists.iter().enumerate() {
+            let items = list
+                .items
+                .iter()
+                .map(Constant::to_string)
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                "list l{slot} {:?}: {} = [{}]",
+                list.name,
+                list.ty,
+                items.join(", ")
+            )?;
+        }
+        for (index, function) in self.functions.iter().enumerate() {
+            write!(f, "\nf{index} {function}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,603 @@
+//! Lowers the scripts of a project into the IR.
+
+use log::warn;
+use scratch_ast::{
+    errors::ScratchError,
+    model::{BlockType, PrimitiveValue, RichValue},
+};
+
+use crate::{
+    analysis::{
+        proccode,
+        types::{self, Types},
+    },
+    compiler::{self, location, unsupported, Layout},
+    ir::{
+        Block, BlockId, Constant, Function, Instruction, List, MathOp, Op, Program, Target,
+        Terminator, Trigger, Type, Value, Variable,
+    },
+    vm::{
+        internals::{
+            Expression, StackExpression, StopOption, ThreadTrigger, VMEvaluable, VMValuePointer,
+        },
+        ScratchResult,
+    },
+};
+
+pub fn lower(layout: &Layout) -> Result<Program, ScratchError> {
+    let types = types::infer(layout);
+    let mut functions = Vec::new();
+    for (index, script) in layout.scripts.iter().enumerate() {
+        functions.push(Lowerer::function(layout, &types, index, script)?);
+    }
+    let program = Program {
+        variables: layout
+            .variables
+            .iter()
+            .zip(&layout.variable_names)
+            .zip(&types.variables)
+            .map(|((value, name), ty)| Variable {
+                name: name.clone(),
+                value: Constant::from(value),
+                ty: *ty,
+            })
+            .collect(),
+        lists: layout
+            .lists
+            .iter()
+            .zip(&layout.list_names)
+            .zip(&types.lists)
+            .map(|((items, name), ty)| List {
+                name: name.clone(),
+                items: items.iter().map(Constant::from).collect(),
+                ty: *ty,
+            })
+            .collect(),
+        functions,
+    };
+    program.verify()?;
+    Ok(program)
+}
+
+/// A block being built, which has no terminator until it is finished.
+struct Partial {
+    parameters: Vec<Value>,
+    instructions: Vec<Instruction>,
+    terminator: Option<Terminator>,
+}
+
+/// Builds the function of a script.
+struct Lowerer<'a, 'b> {
+    layout: &'a Layout<'a>,
+    types: &'b Types,
+    script: &'b compiler::Script<'a>,
+    parameters: Vec<usize>,
+    blocks: Vec<Partial>,
+    /// The block instructions are added to.
+    current: BlockId,
+    values: Vec<Type>,
+}
+
+impl<'a, 'b> Lowerer<'a, 'b> {
+    fn function(
+        layout: &'a Layout<'a>,
+        types: &'b Types,
+        index: usize,
+        script: &'b compiler::Script<'a>,
+    ) -> Result<Function, ScratchError> {
+        let parameters = Layout::parameters(script.thread);
+        let mut lowerer = Lowerer {
+            layout,
+            types,
+            script,
+            parameters,
+            blocks: Vec::new(),
+            current: BlockId(0),
+            values: Vec::new(),
+        };
+        let arguments = lowerer
+            .parameters
+            .iter()
+            .map(|id| {
+                types
+                    .arguments
+                    .get(&(index, *id))
+                    .copied()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        lowerer.block(&arguments);
+        lowerer.statements(&script.thread.code)?;
+        lowerer.terminate(Terminator::Return);
+        let mut function = Function {
+            target: layout.startup.targets[script.target].0.name.clone(),
+            trigger: match script.trigger {
+                ThreadTrigger::GreenFlag => Trigger::GreenFlag,
+                ThreadTrigger::Broadcast(name) => Trigger::Broadcast(name.to_lowercase()),
+                _ => Trigger::Procedure,
+            },
+            proccode: proccode(script.thread).map(str::to_string),
+            blocks: lowerer
+                .blocks
+                .into_iter()
+                .map(|b| Block {
+                    parameters: b.parameters,
+                    instructions: b.instructions,
+                    terminator: b.terminator.expect("every block is terminated"),
+                })
+                .collect(),
+            types: lowerer.values,
+        };
+        function.reorder();
+        Ok(function)
+    }
+
+    fn value(&mut self, ty: Type) -> Value {
+        self.values.push(ty);
+        Value(self.values.len() as u32 - 1)
+    }
+
+    /// Adds a block whose parameters are of `types`, and switches to it.
+    fn block(&mut self, types: &[Type]) -> Vec<Value> {
+        let parameters = types.iter().map(|t| self.value(*t)).collect::<Vec<_>>();
+        self.blocks.push(Partial {
+            parameters: parameters.clone(),
+            instructions: Vec::new(),
+            terminator: None,
+        });
+        self.current = BlockId(self.blocks.len() as u32 - 1);
+        parameters
+    }
+
+    /// Adds a block without parameters, and stays in the current one.
+    fn label(&mut self) -> BlockId {
+        let current = self.current;
+        self.block(&[]);
+        std::mem::replace(&mut self.current, current)
+    }
+
+    fn switch(&mut self, block: BlockId) {
+        self.current = block;
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        let block = &mut self.blocks[self.current.0 as usize];
+        debug_assert!(block.terminator.is_none(), "terminating a block twice");
+        block.terminator = Some(terminator);
+    }
+
+    /// Ends the current block for good. Whatever comes next goes into a new
+    /// block nothing goes to, which is dropped.
+    fn end(&mut self, terminator: Terminator) {
+        self.terminate(terminator);
+        let next = self.label();
+        self.switch(next);
+    }
+
+    fn emit(&mut self, op: Op, operands: Vec<Value>) {
+        self.blocks[self.current.0 as usize]
+            .instructions
+            .push(Instruction {
+                result: None,
+                op,
+                operands,
+            });
+    }
+
+    /// Adds an instruction reporting a value of type `ty`.
+    fn report(&mut self, op: Op, operands: Vec<Value>, ty: Type) -> Value {
+        let result = self.value(ty);
+        self.blocks[self.current.0 as usize]
+            .instructions
+            .push(Instruction {
+                result: Some(result),
+                op,
+                operands,
+            });
+        result
+    }
+
+    fn constant(&mut self, constant: Constant) -> Value {
+        let ty = Type::of_value(&RichValue::from(&constant));
+        self.report(Op::Constant(constant), Vec::new(), ty)
+    }
+
+    fn string(&mut self, s: &str) -> Value {
+        self.constant(Constant::String(s.to_string()))
+    }
+
+    fn statements(&mut self, code: &[Expression]) -> ScratchResult {
+        for expression in code {
+            self.statement(expression)?;
+        }
+        Ok(())
+    }
+
+    /// Lowers the body of a loop starting at `head`, which goes back to it with
+    /// `arguments` after yielding.
+    fn body(&mut self, body: &[Expression], head: BlockId, arguments: Vec<Value>) -> ScratchResult {
+        self.statements(body)?;
+        self.terminate(Terminator::Yield(Target {
+            block: head,
+            arguments,
+        }));
+        Ok(())
+    }
+
+    fn statement(&mut self, expression: &Expression) -> ScratchResult {
+        match expression {
+            Expression::Stack(exp) => self.command(exp)?,
+            Expression::Conditional {
+                header,
+                then,
+                otherwise,
+            } => {
+                let condition = self.condition(header)?;
+                let then_block = self.label();
+                let otherwise_block = self.label();
+                let end = if otherwise.is_empty() {
+                    otherwise_block
+                } else {
+                    self.label()
+                };
+                self.terminate(Terminator::Branch {
+                    condition,
+                    then: Target::new(then_block),
+                    otherwise: Target::new(otherwise_block),
+                });
+                self.switch(then_block);
+                self.statements(then)?;
+                self.terminate(Terminator::Jump(Target::new(end)));
+                if !otherwise.is_empty() {
+                    self.switch(otherwise_block);
+                    self.statements(otherwise)?;
+                    self.terminate(Terminator::Jump(Target::new(end)));
+                }
+                self.switch(end);
+            }
+            Expression::LoopTimes { header, body } => {
+                let times = self.input(header, "TIMES")?;
+                let times = self.report(Op::Round, vec![times], Type::NUMBER);
+                let current = self.current;
+                let left = self.block(&[Type::NUMBER])[0];
+                let head = self.current;
+                self.switch(current);
+                self.terminate(Terminator::Jump(Target {
+                    block: head,
+                    arguments: vec![times],
+                }));
+                self.switch(head);
+                let body_block = self.label();
+                let exit = self.label();
+                let zero = self.constant(Constant::Number(0.0));
+                let more = self.report(Op::Gt, vec![left, zero], Type::BOOLEAN);
+                self.terminate(Terminator::Branch {
+                    condition: more,
+                    then: Target::new(body_block),
+                    otherwise: Target::new(exit),
+                });
+                self.switch(body_block);
+                let one = self.constant(Constant::Number(1.0));
+                let next = self.report(Op::Subtract, vec![left, one], Type::NUMBER);
+                self.body(body, head, vec![next])?;
+                self.switch(exit);
+            }
+            Expression::LoopCondition { header, body } => {
+                let head = self.label();
+                self.terminate(Terminator::Jump(Target::new(head)));
+                self.switch(head);
+                let body_block = self.label();
+                let exit = self.label();
+                let condition = self.condition(header)?;
+                let (then, otherwise) = match header.opcode {
+                    BlockType::ControlRepeatUntil => (exit, body_block),
+                    _ => (body_block, exit),
+                };
+                self.terminate(Terminator::Branch {
+                    condition,
+                    then: Target::new(then),
+                    otherwise: Target::new(otherwise),
+                });
+                self.switch(body_block);
+                self.body(body, head, Vec::new())?;
+                self.switch(exit);
+            }
+            Expression::LoopForever { body, .. } => {
+                let head = self.label();
+                self.terminate(Terminator::Jump(Target::new(head)));
+                self.switch(head);
+                self.statements(body)?;
+                // A `forever` loop only ends with a `stop`, which ends the script.
+                self.end(Terminator::Yield(Target::new(head)));
+            }
+            Expression::InvokeBroadcast(header) => {
+                let name = self.input(header, "BROADCAST_INPUT")?;
+                self.emit(Op::Broadcast, vec![name]);
+                if header.opcode == BlockType::EventBroadcastandWait {
+                    let resume = self.label();
+                    self.terminate(Terminator::AwaitBroadcast {
+                        name,
+                        resume: Target::new(resume),
+                    });
+                    self.switch(resume);
+                }
+            }
+            Expression::InvokeCustomBlock {
+                header,
+                target,
+                arguments,
+            } => {
+                let Some(index) = self.layout.procedure(self.script.target, *target) else {
+                    return Err(ScratchError::not_found(
+                        format!("custom block {target} not found"),
+                        location(header),
+                    ));
+                };
+                let definition = self.layout.scripts[index].thread;
+                let mut operands = Vec::new();
+                for id in Layout::parameters(definition) {
+                    operands.push(match arguments.get(&id) {
+                        Some(value) => self.evaluate(value, true)?,
+                        None => {
+                            let default = definition
+                                .custom_block_arguments
+                                .get(&id)
+                                .cloned()
+                                .unwrap_or(PrimitiveValue::String(String::new()));
+                            self.constant(Constant::from(&default))
+                        }
+                    });
+                }
+                self.emit(Op::Call(index as u32), operands);
+            }
+            Expression::Stop { option, header } => match option {
+                StopOption::All => self.end(Terminator::StopAll),
+                // `stop this script` only leaves the custom block.
+                StopOption::ThisScript => self.end(Terminator::Return),
+                StopOption::OtherScriptsInSprite => {
+                    warn!(
+                        "stopping other scripts is not supported yet, ignoring block {}",
+                        header.original_block.obj_id
+                    );
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// The condition of a block. An empty condition slot is false.
+    fn condition(&mut self, exp: &StackExpression) -> Result<Value, ScratchError> {
+        match exp.argraw("CONDITION") {
+            Some(_) => self.input(exp, "CONDITION"),
+            None => Ok(self.constant(Constant::Boolean(false))),
+        }
+    }
+
+    /// Evaluates an evaluable. Fields evaluate to the value they point to if
+    /// `resolve` is set, and to their displayed text otherwise.
+    fn evaluate(&mut self, value: &VMEvaluable, resolve: bool) -> Result<Value, ScratchError> {
+        let pointer = match value {
+            VMEvaluable::Bare(value) => return Ok(self.constant(Constant::from(value))),
+            VMEvaluable::Field(f) => match &f.pointer {
+                Some(pointer) if resolve => pointer,
+                _ => return Ok(self.string(&f.display_value)),
+            },
+            VMEvaluable::Pointer(pointer) => pointer,
+            VMEvaluable::Block(b) => return self.reporter(b),
+            VMEvaluable::Default => return Ok(self.string("")),
+        };
+        Ok(match pointer {
+            p @ VMValuePointer::Variable { .. } => {
+                let slot = self.layout.variable(self.script.target, p)?;
+                let ty = self.types.variables[slot];
+                self.report(Op::Variable(slot as u32), Vec::new(), ty)
+            }
+            p @ VMValuePointer::List { .. } => {
+                let slot = self.layout.list(self.script.target, p)?;
+                self.report(Op::ListContents(slot as u32), Vec::new(), Type::STRING)
+            }
+            VMValuePointer::Broadcast { name, .. } => {
+                self.constant(Constant::from(&RichValue::Broadcast(name.clone())))
+            }
+        })
+    }
+
+    /// An input of a block. Empty inputs are empty strings.
+    fn input(&mut self, exp: &StackExpression, name: &str) -> Result<Value, ScratchError> {
+        match exp.argraw(name) {
+            Some(value) => self.evaluate(value, false),
+            None => Ok(self.string("")),
+        }
+    }
+
+    /// Like [`Lowerer::input`], but fields evaluate to the value they point to.
+    fn raw(&mut self, exp: &StackExpression, name: &str) -> Result<Value, ScratchError> {
+        match exp.argraw(name) {
+            Some(value) => self.evaluate(value, true),
+            None => Ok(self.string("")),
+        }
+    }
+
+    fn inputs(
+        &mut self,
+        exp: &StackExpression,
+        names: &[&str],
+    ) -> Result<Vec<Value>, ScratchError> {
+        names.iter().map(|name| self.input(exp, name)).collect()
+    }
+
+    fn variable(&self, exp: &StackExpression) -> Result<u32, ScratchError> {
+        Ok(self
+            .layout
+            .variable(self.script.target, &exp.sargptr("VARIABLE", exp)?)? as u32)
+    }
+
+    fn list(&self, exp: &StackExpression) -> Result<u32, ScratchError> {
+        Ok(self
+            .layout
+            .list(self.script.target, &exp.sargptr("LIST", exp)?)? as u32)
+    }
+
+    /// Lowers a block run for its effect.
+    fn command(&mut self, exp: &StackExpression) -> ScratchResult {
+        let (op, operands) = match exp.opcode {
+            BlockType::EventWhenFlagClicked
+            | BlockType::EventWhenBroadcastReceived
+            | BlockType::ProceduresDefinition
+            | BlockType::ProceduresPrototype => return Ok(()),
+            BlockType::LooksSay => (Op::Say, self.inputs(exp, &["MESSAGE"])?),
+            BlockType::LooksThink => (Op::Think, self.inputs(exp, &["MESSAGE"])?),
+            BlockType::LooksSayForSecs | BlockType::LooksThinkForSecs => {
+                let message = self.input(exp, "MESSAGE")?;
+                let secs = self.input(exp, "SECS")?;
+                let deadline = self.report(Op::DeadlineMillis, vec![secs], Type::NUMBER);
+                self.emit(
+                    match exp.opcode {
+                        BlockType::LooksSayForSecs => Op::Say,
+                        _ => Op::Think,
+                    },
+                    vec![message],
+                );
+                self.sleep(deadline);
+                return Ok(());
+            }
+            BlockType::ControlWait => {
+                let duration = self.input(exp, "DURATION")?;
+                let deadline = self.report(Op::Deadline, vec![duration], Type::NUMBER);
+                self.sleep(deadline);
+                return Ok(());
+            }
+            BlockType::ControlWaitUntil => {
+                let head = self.label();
+                self.terminate(Terminator::Jump(Target::new(head)));
+                self.switch(head);
+                let wait = self.label();
+                let resume = self.label();
+                let condition = self.condition(exp)?;
+                self.terminate(Terminator::Branch {
+                    condition,
+                    then: Target::new(resume),
+                    otherwise: Target::new(wait),
+                });
+                self.switch(wait);
+                self.terminate(Terminator::Yield(Target::new(head)));
+                self.switch(resume);
+                return Ok(());
+            }
+            BlockType::SensingResetTimer => (Op::ResetTimer, Vec::new()),
+            BlockType::SensingAskAndWait => (Op::Ask, self.inputs(exp, &["QUESTION"])?),
+            BlockType::DataSetVariableTo => {
+                let value = self.raw(exp, "VALUE")?;
+                (Op::SetVariable(self.variable(exp)?), vec![value])
+            }
+            BlockType::DataChangeVariableBy => {
+                let value = self.raw(exp, "VALUE")?;
+                (Op::ChangeVariable(self.variable(exp)?), vec![value])
+            }
+            BlockType::DataAddToList => {
+                let item = self.raw(exp, "ITEM")?;
+                (Op::AddToList(self.list(exp)?), vec![item])
+            }
+            BlockType::DataListDeleteElement => {
+                let index = self.input(exp, "INDEX")?;
+                (Op::DeleteOfList(self.list(exp)?), vec![index])
+            }
+            BlockType::DataListClear => (Op::DeleteAllOfList(self.list(exp)?), Vec::new()),
+            BlockType::DataListInsertAt | BlockType::DataListReplaceItem => {
+                let index = self.input(exp, "INDEX")?;
+                let item = self.raw(exp, "ITEM")?;
+                let list = self.list(exp)?;
+                let op = match exp.opcode {
+                    BlockType::DataListInsertAt => Op::InsertAtList(list),
+                    _ => Op::ReplaceItemOfList(list),
+                };
+                (op, vec![index, item])
+            }
+            // A reporter used as a command, whose value is dropped.
+            _ => {
+                self.reporter(exp)?;
+                return Ok(());
+            }
+        };
+        self.emit(op, operands);
+        Ok(())
+    }
+
+    /// Waits until `deadline`, going on in a new block.
+    fn sleep(&mut self, deadline: Value) {
+        let resume = self.label();
+        self.terminate(Terminator::Sleep {
+            deadline,
+            resume: Target::new(resume),
+        });
+        self.switch(resume);
+    }
+
+    /// Lowers a block reporting a value.
+    fn reporter(&mut self, exp: &StackExpression) -> Result<Value, ScratchError> {
+        let (names, op, ty): (&[&str], Op, Type) = match exp.opcode {
+            BlockType::OperatorAdd => (&["NUM1", "NUM2"], Op::Add, Type::NUMBER),
+            BlockType::OperatorSubtract => (&["NUM1", "NUM2"], Op::Subtract, Type::NUMBER),
+            BlockType::OperatorMultiply => (&["NUM1", "NUM2"], Op::Multiply, Type::NUMBER),
+            BlockType::OperatorDivide => (&["NUM1", "NUM2"], Op::Divide, Type::NUMBER),
+            BlockType::OperatorRandom => (&["FROM", "TO"], Op::Random, Type::NUMBER),
+            BlockType::OperatorGt => (&["OPERAND1", "OPERAND2"], Op::Gt, Type::BOOLEAN),
+            BlockType::OperatorLt => (&["OPERAND1", "OPERAND2"], Op::Lt, Type::BOOLEAN),
+            BlockType::OperatorEquals => (&["OPERAND1", "OPERAND2"], Op::Equals, Type::BOOLEAN),
+            BlockType::OperatorAnd => (&["OPERAND1", "OPERAND2"], Op::And, Type::BOOLEAN),
+            BlockType::OperatorOr => (&["OPERAND1", "OPERAND2"], Op::Or, Type::BOOLEAN),
+            BlockType::OperatorNot => (&["OPERAND"], Op::Not, Type::BOOLEAN),
+            BlockType::OperatorJoin => (&["STRING1", "STRING2"], Op::Join, Type::STRING),
+            BlockType::OperatorLetterOf => (&["LETTER", "STRING"], Op::LetterOf, Type::STRING),
+            BlockType::OperatorLength => (&["STRING"], Op::Length, Type::NUMBER),
+            BlockType::OperatorContains => (&["STRING1", "STRING2"], Op::Contains, Type::BOOLEAN),
+            BlockType::OperatorMod => (&["NUM1", "NUM2"], Op::Mod, Type::NUMBER),
+            BlockType::OperatorRound => (&["NUM"], Op::Round, Type::NUMBER),
+            BlockType::OperatorMathop => {
+                // The operator is a menu, so it is known ahead of time.
+                let operator = match exp.argraw("OPERATOR") {
+                    Some(VMEvaluable::Field(f)) => f.display_value.clone(),
+                    Some(VMEvaluable::Bare(v)) => scratch_ast::cast::to_string(v),
+                    _ => return Err(unsupported(exp, "IR")),
+                };
+                (&["NUM"], Op::MathOp(MathOp::parse(&operator)), Type::NUMBER)
+            }
+            BlockType::SensingTimer => (&[], Op::Timer, Type::NUMBER),
+            BlockType::SensingAnswer => (&[], Op::Answer, Type::STRING),
+            BlockType::SensingDaysSince2000 => (&[], Op::DaysSince2000, Type::NUMBER),
+            BlockType::DataListItemAt => {
+                let index = self.input(exp, "INDEX")?;
+                let list = self.list(exp)?;
+                // Items out of range are empty strings.
+                let ty = self.types.lists[list as usize].union(Type::STRING);
+                return Ok(self.report(Op::ItemOfList(list), vec![index], ty));
+            }
+            BlockType::DataListIndexOf => {
+                let item = self.raw(exp, "ITEM")?;
+                let list = self.list(exp)?;
+                return Ok(self.report(Op::ItemNumOfList(list), vec![item], Type::NUMBER));
+            }
+            BlockType::DataListLengthOf => {
+                let list = self.list(exp)?;
+                return Ok(self.report(Op::LengthOfList(list), Vec::new(), Type::NUMBER));
+            }
+            BlockType::DataListContainsItem => {
+                let item = self.raw(exp, "ITEM")?;
+                let list = self.list(exp)?;
+                return Ok(self.report(Op::ListContainsItem(list), vec![item], Type::BOOLEAN));
+            }
+            BlockType::ArgumentReporterStringNumber | BlockType::ArgumentReporterBoolean => {
+                return Ok(
+                    match self.layout.argument(self.script, &self.parameters, exp) {
+                        Some(i) => self.blocks[0].parameters[i],
+                        // Like Scratch, arguments used outside their custom block are 0.
+                        None => self.constant(Constant::Number(0.0)),
+                    },
+                );
+            }
+            _ => return Err(unsupported(exp, "IR")),
+        };
+        let operands = self.inputs(exp, names)?;
+        Ok(self.report(op, operands, ty))
+    }
+}
@@ -0,0 +1,554 @@
+//! A mid-level representation of projects, shared by the backends. Every script
+//! becomes a [`Function`] of basic blocks over SSA values, with each variable
+//! and list resolved to a slot, and every point where a script may give its turn
+//! away spelled out as a [`Terminator`].
+//!
+//! Scratch variables and lists stay in their slots, so the only SSA values that
+//! flow between blocks are the counters of `repeat` loops, passed as block parameters.
+
+use scratch_ast::model::{PrimitiveValue, RichValue};
+use serde::{Deserialize, Serialize};
+
+use crate::vm::ScratchResult;
+
+pub use crate::analysis::types::Type;
+
+mod dump;
+pub mod lower;
+mod verify;
+
+/// A literal value. Mirrors the kinds of [`RichValue`] that behave differently.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Constant {
+    Boolean(bool),
+    Number(f64),
+    Integer(i64),
+    String(String),
+    Color(String),
+    Broadcast(String),
+}
+
+impl From<&RichValue> for Constant {
+    fn from(value: &RichValue) -> Self {
+        match value {
+            RichValue::Boolean(b) => Constant::Boolean(*b),
+            RichValue::Number(n) | RichValue::PositiveNumber(n) | RichValue::Angle(n) => {
+                Constant::Number(*n)
+            }
+            RichValue::Integer(n) => Constant::Integer(*n),
+            RichValue::PositiveInteger(n) => Constant::Integer(*n as i64),
+            RichValue::String(s) => Constant::String(s.clone()),
+            RichValue::Color(s) => Constant::Color(s.clone()),
+            RichValue::Broadcast(s) => Constant::Broadcast(s.clone()),
+        }
+    }
+}
+
+impl From<&Constant> for RichValue {
+    fn from(value: &Constant) -> Self {
+        match value {
+            Constant::Boolean(b) => RichValue::Boolean(*b),
+            Constant::Number(n) => RichValue::Number(*n),
+            Constant::Integer(n) => RichValue::Integer(*n),
+            Constant::String(s) => RichValue::String(s.clone()),
+            Constant::Color(s) => RichValue::Color(s.clone()),
+            Constant::Broadcast(s) => RichValue::Broadcast(s.clone()),
+        }
+    }
+}
+
+impl From<&PrimitiveValue> for Constant {
+    fn from(value: &PrimitiveValue) -> Self {
+        match value {
+            PrimitiveValue::Number(n) => Constant::Number(*n),
+            PrimitiveValue::Integer(n) => Constant::Integer(*n),
+            PrimitiveValue::String(s) => Constant::String(s.clone()),
+        }
+    }
+}
+
+impl From<&Constant> for PrimitiveValue {
+    fn from(value: &Constant) -> Self {
+        RichValue::from(value).into()
+    }
+}
+
+/// The operators of the `math op` block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MathOp {
+    Abs,
+    Floor,
+    Ceiling,
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Ln,
+    Log,
+    Exp,
+    Pow10,
+}
+
+impl MathOp {
+    pub fn parse(operator: &str) -> Option<Self> {
+        Some(match operator.to_lowercase().as_str() {
+            "abs" => MathOp::Abs,
+            "floor" => MathOp::Floor,
+            "ceiling" => MathOp::Ceiling,
+            "sqrt" => MathOp::Sqrt,
+            "sin" => MathOp::Sin,
+            "cos" => MathOp::Cos,
+            "tan" => MathOp::Tan,
+            "asin" => MathOp::Asin,
+            "acos" => MathOp::Acos,
+            "atan" => MathOp::Atan,
+            "ln" => MathOp::Ln,
+            "log" => MathOp::Log,
+            "e ^" => MathOp::Exp,
+            "10 ^" => MathOp::Pow10,
+            _ => return None,
+        })
+    }
+
+    /// The operator as Scratch names it.
+    pub fn name(self) -> &'static str {
+        match self {
+            MathOp::Abs => "abs",
+            MathOp::Floor => "floor",
+            MathOp::Ceiling => "ceiling",
+            MathOp::Sqrt => "sqrt",
+            MathOp::Sin => "sin",
+            MathOp::Cos => "cos",
+            MathOp::Tan => "tan",
+            MathOp::Asin => "asin",
+            MathOp::Acos => "acos",
+            MathOp::Atan => "atan",
+            MathOp::Ln => "ln",
+            MathOp::Log => "log",
+            MathOp::Exp => "e ^",
+            MathOp::Pow10 => "10 ^",
+        }
+    }
+}
+
+/// What starts a script.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Trigger {
+    GreenFlag,
+    /// Receiving a broadcast, by lowercase name.
+    Broadcast(String),
+    /// A custom block, run when called.
+    Procedure,
+}
+
+/// A value, defined once, by an instruction or as a parameter of a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub u32);
+
+/// A basic block, by index in its function. The entry block is the first one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+/// A block to go to, and the values of its parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Target {
+    pub block: BlockId,
+    pub arguments: Vec<Value>,
+}
+
+impl Target {
+    pub fn new(block: BlockId) -> Self {
+        Self {
+            block,
+            arguments: Vec::new(),
+        }
+    }
+}
+
+/// What an instruction does. Slots and functions are operands known ahead of
+/// time, values are in [`Instruction::operands`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    Constant(Constant),
+
+    Variable(u32),
+    SetVariable(u32),
+    ChangeVariable(u32),
+    /// The contents of a list, as shown by its reporter.
+    ListContents(u32),
+    AddToList(u32),
+    DeleteOfList(u32),
+    DeleteAllOfList(u32),
+    /// Takes the index, then the item.
+    InsertAtList(u32),
+    /// Takes the index, then the item.
+    ReplaceItemOfList(u32),
+    ItemOfList(u32),
+    ItemNumOfList(u32),
+    LengthOfList(u32),
+    ListContainsItem(u32),
+
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Random,
+    Gt,
+    Lt,
+    Equals,
+    And,
+    Or,
+    Not,
+    Join,
+    LetterOf,
+    Length,
+    Contains,
+    Mod,
+    Round,
+    /// `math op`, `None` for an operator Scratch does not know.
+    MathOp(Option<MathOp>),
+
+    Say,
+    Think,
+    Ask,
+    Answer,
+    Timer,
+    ResetTimer,
+    DaysSince2000,
+
+    /// When a wait of the given seconds ends, for [`Terminator::Sleep`].
+    Deadline,
+    /// Like [`Op::Deadline`], but drops what is shorter than a millisecond.
+    DeadlineMillis,
+    /// Starts the scripts receiving the broadcast named by the operand.
+    Broadcast,
+    /// Runs a function with the operands as its arguments, until it returns.
+    Call(u32),
+}
+
+impl Op {
+    /// How many operands the instruction takes, or `None` if it depends on the callee.
+    pub fn arity(&self) -> Option<usize> {
+        Some(match self {
+            Op::Constant(_)
+            | Op::Variable(_)
+            | Op::ListContents(_)
+            | Op::DeleteAllOfList(_)
+            | Op::LengthOfList(_)
+            | Op::Answer
+            | Op::Timer
+            | Op::ResetTimer
+            | Op::DaysSince2000 => 0,
+            Op::SetVariable(_)
+            | Op::ChangeVariable(_)
+            | Op::AddToList(_)
+            | Op::DeleteOfList(_)
+            | Op::ItemOfList(_)
+            | Op::ItemNumOfList(_)
+            | Op::ListContainsItem(_)
+            | Op::Not
+            | Op::Length
+            | Op::Round
+            | Op::MathOp(_)
+            | Op::Say
+            | Op::Think
+            | Op::Ask
+            | Op::Deadline
+            | Op::DeadlineMillis
+            | Op::Broadcast => 1,
+            Op::InsertAtList(_)
+            | Op::ReplaceItemOfList(_)
+            | Op::Add
+            | Op::Subtract
+            | Op::Multiply
+            | Op::Divide
+            | Op::Random
+            | Op::Gt
+            | Op::Lt
+            | Op::Equals
+            | Op::And
+            | Op::Or
+            | Op::Join
+            | Op::LetterOf
+            | Op::Contains
+            | Op::Mod => 2,
+            Op::Call(_) => return None,
+        })
+    }
+
+    /// Whether the instruction reports a value.
+    pub fn has_result(&self) -> bool {
+        !matches!(
+            self,
+            Op::SetVariable(_)
+                | Op::ChangeVariable(_)
+                | Op::AddToList(_)
+                | Op::DeleteOfList(_)
+                | Op::DeleteAllOfList(_)
+                | Op::InsertAtList(_)
+                | Op::ReplaceItemOfList(_)
+                | Op::Say
+                | Op::Think
+                | Op::Ask
+                | Op::ResetTimer
+                | Op::Broadcast
+                | Op::Call(_)
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    /// The value defined, for instructions that report one.
+    pub result: Option<Value>,
+    pub op: Op,
+    pub operands: Vec<Value>,
+}
+
+/// How a block ends. Only [`Terminator::Yield`], [`Terminator::Sleep`] and
+/// [`Terminator::AwaitBroadcast`] let other scripts run.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Jump(Target),
+    Branch {
+        condition: Value,
+        then: Target,
+        otherwise: Target,
+    },
+    /// Ends the turn of the script, which goes on with `resume` in its next one.
+    Yield(Target),
+    /// Waits until the time reported by a [`Op::Deadline`].
+    Sleep {
+        deadline: Value,
+        resume: Target,
+    },
+    /// Waits while scripts receive the broadcast named by `name`.
+    AwaitBroadcast {
+        name: Value,
+        resume: Target,
+    },
+    /// Leaves the running custom block, or ends the running script.
+    Return,
+    StopAll,
+}
+
+impl Terminator {
+    pub fn targets(&self) -> Vec<&Target> {
+        match self {
+            Terminator::Jump(target) | Terminator::Yield(target) => vec![target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Terminator::Sleep { resume, .. } | Terminator::AwaitBroadcast { resume, .. } => {
+                vec![resume]
+            }
+            Terminator::Return | Terminator::StopAll => Vec::new(),
+        }
+    }
+
+    fn targets_mut(&mut self) -> Vec<&mut Target> {
+        match self {
+            Terminator::Jump(target) | Terminator::Yield(target) => vec![target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Terminator::Sleep { resume, .. } | Terminator::AwaitBroadcast { resume, .. } => {
+                vec![resume]
+            }
+            Terminator::Return | Terminator::StopAll => Vec::new(),
+        }
+    }
+
+    /// Every value the terminator reads, including the arguments of its targets.
+    fn values_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Jump(target) | Terminator::Yield(target) => {
+                target.arguments.iter_mut().collect()
+            }
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => std::iter::once(condition)
+                .chain(then.arguments.iter_mut())
+                .chain(otherwise.arguments.iter_mut())
+                .collect(),
+            Terminator::Sleep {
+                deadline: value,
+                resume,
+            }
+            | Terminator::AwaitBroadcast {
+                name: value,
+                resume,
+            } => std::iter::once(value)
+                .chain(resume.arguments.iter_mut())
+                .collect(),
+            Terminator::Return | Terminator::StopAll => Vec::new(),
+        }
+    }
+
+    /// The values the terminator reads, not counting the arguments of its targets.
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Terminator::Branch { condition, .. } => vec![*condition],
+            Terminator::Sleep { deadline, .. } => vec![*deadline],
+            Terminator::AwaitBroadcast { name, .. } => vec![*name],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub parameters: Vec<Value>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+/// A script. The parameters of its entry block are the arguments of the custom
+/// block it defines, and its first values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    /// The sprite, or the stage, the script belongs to.
+    pub target: String,
+    pub trigger: Trigger,
+    /// The name of the custom block the script defines, such as `jump %s high`.
+    pub proccode: Option<String>,
+    pub blocks: Vec<Block>,
+    /// The type of every value.
+    pub types: Vec<Type>,
+}
+
+impl Function {
+    pub fn parameters(&self) -> &[Value] {
+        &self.blocks[0].parameters
+    }
+
+    /// Sends whatever goes to a block that does nothing but jump on to where it jumps.
+    fn skip_empty(&mut self) {
+        let forward = |block: &Block| match &block.terminator {
+            Terminator::Jump(target)
+                if block.parameters.is_empty() && block.instructions.is_empty() =>
+            {
+                Some(target.clone())
+            }
+            _ => None,
+        };
+        let forwards = self.blocks.iter().map(forward).collect::<Vec<_>>();
+        for block in self.blocks.iter_mut() {
+            for target in block.terminator.targets_mut() {
+                // Such blocks cannot form a loop, as every loop yields.
+                while target.arguments.is_empty() {
+                    match &forwards[target.block.0 as usize] {
+                        Some(next) => *target = next.clone(),
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Puts the blocks in reverse postorder, so that blocks come before those they
+    /// go to except at loops, drops the blocks nothing goes to, such as those after
+    /// a `stop`, then numbers values in the order they are defined.
+    fn reorder(&mut self) {
+        self.skip_empty();
+        let mut postorder = Vec::new();
+        let mut visited = vec![false; self.blocks.len()];
+        visited[0] = true;
+        // Successors are popped off the end, so the last is visited first and
+        // ends up last, which puts `then` before `otherwise`.
+        let successors = |block: usize| -> Vec<usize> {
+            let targets = self.blocks[block].terminator.targets();
+            targets.iter().map(|t| t.block.0 as usize).collect()
+        };
+        let mut pending = vec![(0, successors(0))];
+        while let Some((block, next)) = pending.last_mut() {
+            match next.pop() {
+                Some(successor) if !visited[successor] => {
+                    visited[successor] = true;
+                    pending.push((successor, successors(successor)));
+                }
+                Some(_) => (),
+                None => {
+                    postorder.push(*block);
+                    pending.pop();
+                }
+            }
+        }
+        let mut blocks = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let mut renumbered = vec![BlockId(u32::MAX); blocks.len()];
+        for (index, block) in postorder.iter().rev().enumerate() {
+            renumbered[*block] = BlockId(index as u32);
+        }
+        for block in postorder.into_iter().rev() {
+            let mut block = blocks[block].take().expect("blocks are visited once");
+            for target in block.terminator.targets_mut() {
+                target.block = renumbered[target.block.0 as usize];
+            }
+            self.blocks.push(block);
+        }
+
+        let mut values = vec![None; self.types.len()];
+        let mut types = Vec::new();
+        for block in self.blocks.iter() {
+            let defined = block.instructions.iter().filter_map(|i| i.result);
+            for value in block.parameters.iter().copied().chain(defined) {
+                values[value.0 as usize] = Some(Value(types.len() as u32));
+                types.push(self.types[value.0 as usize]);
+            }
+        }
+        self.types = types;
+        let renumber = |value: &mut Value| {
+            *value = values[value.0 as usize].expect("values are defined before they are used");
+        };
+        for block in self.blocks.iter_mut() {
+            block.parameters.iter_mut().for_each(renumber);
+            for instruction in block.instructions.iter_mut() {
+                instruction.result.iter_mut().for_each(renumber);
+                instruction.operands.iter_mut().for_each(renumber);
+            }
+            block.terminator.values_mut().into_iter().for_each(renumber);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variable {
+    /// Prefixed with `Sprite/` unless the variable belongs to the stage.
+    pub name: String,
+    pub value: Constant,
+    pub ty: Type,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct List {
+    /// Prefixed with `Sprite/` unless the list belongs to the stage.
+    pub name: String,
+    pub items: Vec<Constant>,
+    /// What the items are.
+    pub ty: Type,
+}
+
+/// A project, with its variables and lists by slot and its scripts by index,
+/// in the order of [`crate::compiler::Layout`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub variables: Vec<Variable>,
+    pub lists: Vec<List>,
+    pub functions: Vec<Function>,
+}
+
+impl Program {
+    /// Checks that every block, slot and function the program refers to exists,
+    /// and that every value is defined once, before anything that may use it.
+    pub fn verify(&self) -> ScratchResult {
+        verify::program(self)
+    }
+}
@@ -0,0 +1,211 @@
+//! Checks that a program is well formed: every block, slot and function an
+//! instruction refers to exists, instructions get as many operands as they take,
+//! and every value is defined once, before anything that may use it.
+
+use scratch_ast::errors::ScratchError;
+
+use crate::{
+    ir::{Function, Op, Program, Value},
+    vm::ScratchResult,
+};
+
+pub fn program(program: &Program) -> ScratchResult {
+    for (index, function) in program.functions.iter().enumerate() {
+        Verifier {
+            program,
+            function,
+            location: format!("verifying IR function f{index}"),
+        }
+        .function()?;
+    }
+    Ok(())
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    function: &'a Function,
+    location: String,
+}
+
+impl Verifier<'_> {
+    fn error(&self, description: String) -> ScratchError {
+        ScratchError::internal(description, &self.location)
+    }
+
+    fn function(&self) -> ScratchResult {
+        let blocks = &self.function.blocks;
+        if blocks.is_empty() {
+            return Err(self.error("function has no blocks".to_string()));
+        }
+        // Where each value is defined: its block, and 0 for a parameter or
+        // 1 + the index of the instruction.
+        let mut definitions = vec![None; self.function.types.len()];
+        let mut define = |value: Value, at: (usize, usize)| {
+            match definitions.get_mut(value.0 as usize) {
+                Some(definition @ None) => *definition = Some(at),
+                Some(Some(_)) => return Err(self.error(format!("%{} is defined twice", value.0))),
+                None => return Err(self.error(format!("%{} has no type", value.0))),
+            }
+            Ok(())
+        };
+        for (b, block) in blocks.iter().enumerate() {
+            for parameter in block.parameters.iter() {
+                define(*parameter, (b, 0))?;
+            }
+            for (i, instruction) in block.instructions.iter().enumerate() {
+                if let Some(result) = instruction.result {
+                    define(result, (b, i + 1))?;
+                }
+            }
+        }
+
+        let dominators = self.dominators()?;
+        let dominates = |a: usize, mut b: usize| loop {
+            if a == b {
+                return true;
+            }
+            if b == 0 {
+                return false;
+            }
+            b = dominators[b];
+        };
+        let check_use = |value: Value, at: (usize, usize)| {
+            let Some(Some((block, position))) = definitions.get(value.0 as usize).copied() else {
+                return Err(self.error(format!("%{} is used but never defined", value.0)));
+            };
+            let before = match block == at.0 {
+                true => position < at.1,
+                false => dominates(block, at.0),
+            };
+            match before {
+                true => Ok(()),
+                false => Err(self.error(format!(
+                    "%{} is used in b{} before it is defined",
+                    value.0, at.0
+                ))),
+            }
+        };
+
+        for (b, block) in blocks.iter().enumerate() {
+            for (i, instruction) in block.instructions.iter().enumerate() {
+                self.instruction(&instruction.op, instruction.operands.len())?;
+                if instruction.result.is_some() != instruction.op.has_result() {
+                    return Err(self.error(format!(
+                        "{:?} in b{b} has the wrong kind of result",
+                        instruction.op
+                    )));
+                }
+                for operand in instruction.operands.iter() {
+                    check_use(*operand, (b, i + 1))?;
+                }
+            }
+            let end = (b, block.instructions.len() + 1);
+            for operand in block.terminator.operands() {
+                check_use(operand, end)?;
+            }
+            for target in block.terminator.targets() {
+                let Some(to) = blocks.get(target.block.0 as usize) else {
+                    return Err(self.error(format!(
+                        "b{b} goes to b{}, which does not exist",
+                        target.block.0
+                    )));
+                };
+                if target.arguments.len() != to.parameters.len() {
+                    return Err(self.error(format!(
+                        "b{b} passes {} arguments to b{}, which takes {}",
+                        target.arguments.len(),
+                        target.block.0,
+                        to.parameters.len()
+                    )));
+                }
+                for argument in target.arguments.iter() {
+                    check_use(*argument, end)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks what an instruction refers to, and how many operands it gets.
+    fn instruction(&self, op: &Op, operands: usize) -> ScratchResult {
+        let variables = self.program.variables.len();
+        let lists = self.program.lists.len();
+        let (slot, slots) = match op {
+            Op::Variable(v) | Op::SetVariable(v) | Op::ChangeVariable(v) => (*v, variables),
+            Op::ListContents(l)
+            | Op::AddToList(l)
+            | Op::DeleteOfList(l)
+            | Op::DeleteAllOfList(l)
+            | Op::InsertAtList(l)
+            | Op::ReplaceItemOfList(l)
+            | Op::ItemOfList(l)
+            | Op::ItemNumOfList(l)
+            | Op::LengthOfList(l)
+            | Op::ListContainsItem(l) => (*l, lists),
+            Op::Call(f) => (*f, self.program.functions.len()),
+            _ => (0, 1),
+        };
+        if slot as usize >= slots {
+            return Err(self.error(format!("{op:?} is out of range")));
+        }
+        let arity = match op {
+            Op::Call(f) => self.program.functions[*f as usize].parameters().len(),
+            op => op.arity().expect("only calls depend on the callee"),
+        };
+        if operands != arity {
+            return Err(self.error(format!(
+                "{op:?} takes {arity} operands, but gets {operands}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// The immediate dominator of every block, by the algorithm of Cooper, Harvey
+    /// and Kennedy. Blocks must be in reverse postorder.
+    fn dominators(&self) -> Result<Vec<usize>, ScratchError> {
+        let blocks = &self.function.blocks;
+        let mut predecessors = vec![Vec::new(); blocks.len()];
+        for (b, block) in blocks.iter().enumerate() {
+            for target in block.terminator.targets() {
+                if let Some(p) = predecessors.get_mut(target.block.0 as usize) {
+                    p.push(b);
+                }
+            }
+        }
+        const UNKNOWN: usize = usize::MAX;
+        let mut dominators = vec![UNKNOWN; blocks.len()];
+        dominators[0] = 0;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 1..blocks.len() {
+                let mut known = predecessors[b]
+                    .iter()
+                    .copied()
+                    .filter(|p| dominators[*p] != UNKNOWN);
+                let Some(first) = known.next() else {
+                    continue;
+                };
+                let dominator = known.fold(first, |mut x, mut y| {
+                    while x != y {
+                        while x > y {
+                            x = dominators[x];
+                        }
+                        while y > x {
+                            y = dominators[y];
+                        }
+                    }
+                    x
+                });
+                if dominators[b] != dominator {
+                    dominators[b] = dominator;
+                    changed = true;
+                }
+            }
+        }
+        match dominators.iter().position(|d| *d == UNKNOWN) {
+            Some(b) => Err(self.error(format!("nothing goes to b{b}"))),
+            None => Ok(dominators),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Line-by-line differences between two texts, shown like `diff -u` without
+//! the headers. Used by `kcc test` for failed cases and by `kcc diff` to match
+//! scripts between versions.
+
+use std::fmt::Write as _;
+
+/// The lines to remove from `expected` and add to it to get `actual`, prefixed
+/// with `-` and `+`, between the lines they share.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let a = expected.lines().collect::<Vec<_>>();
+    let b = actual.lines().collect::<Vec<_>>();
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let mut output = String::new();
+    for line in &a[..prefix] {
+        writeln!(output, " {line}").unwrap();
+    }
+    // Longest common subsequence, unless the outputs are too long to compare.
+    if a_mid.len() * b_mid.len() > 4_000_000 {
+        for line in a_mid {
+            writeln!(output, "-{line}").unwrap();
+        }
+        for line in b_mid {
+            writeln!(output, "+{line}").unwrap();
+        }
+    } else {
+        let (n, m) = (a_mid.len(), b_mid.len());
+        let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i][j] = match a_mid[i] == b_mid[j] {
+                    true => lengths[i + 1][j + 1] + 1,
+                    false => lengths[i + 1][j].max(lengths[i][j + 1]),
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && a_mid[i] == b_mid[j] {
+                writeln!(output, " {}", a_mid[i]).unwrap();
+                (i, j) = (i + 1, j + 1);
+            } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+                writeln!(output, "-{}", a_mid[i]).unwrap();
+                i += 1;
+            } else {
+                writeln!(output, "+{}", b_mid[j]).unwrap();
+                j += 1;
+            }
+        }
+    }
+    for line in &a[a.len() - suffix..] {
+        writeln!(output, " {line}").unwrap();
+    }
+    output
+}
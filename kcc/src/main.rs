@@ -22,35 +22,656 @@
  * You should have also received a copy of the GNU General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
+pub mod analysis;
+pub mod bytecode;
+pub mod cli;
+pub mod compiler;
+pub mod golden;
+pub mod ir;
+pub mod linediff;
+pub mod optimizer;
+pub mod package;
 pub mod vm;
 use mimalloc::MiMalloc;
-use std::fs::File;
+use std::{
+    fmt::Display,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use log::{debug, error};
+use colored::Colorize;
+use log::{debug, error, info};
 pub use scratch_ast::parser::load_from_directory;
+use scratch_ast::{
+    errors::{ErrorType, ScratchError},
+    model,
+};
+
+use crate::{
+    analysis::{
+        graph::{self, Graph},
+        stats,
+    },
+    bytecode::{cache, machine, Program},
+    cli::{
+        exit, CheckArgs, Cli, Command, CompileArgs, DebugArgs, DiffArgs, DumpArgs, GraphArgs,
+        InfoArgs, LintArgs, PackageArgs, RunArgs, StatsArgs, TestArgs,
+    },
+    compiler::Layout,
+    optimizer::Pass,
+    package::{Format, Package},
+    vm::{
+        cloud::{file::FileProvider, websocket::WebSocketProvider, CloudProvider},
+        coverage::{self, Coverage},
+        debugger::Debugger,
+        host::{self, Options},
+        listfile,
+        profiler::Profiler,
+        trace::Tracer,
+        transform::VMStartup,
+        Ending,
+    },
+};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-pub fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
-    pretty_env_logger::init();
-    if args.len() < 2 {
-        error!("no file specified");
-        std::process::exit(1);
+/// Reports an error, and exits with `code`.
+fn fail(code: i32, e: impl Display) -> ! {
+    std::process::exit(failed(code, e));
+}
+
+/// Reports an error, and returns `code` to exit with once kcc cleaned up, e.g.
+/// the directory a packaged project was extracted to.
+fn failed(code: i32, e: impl Display) -> i32 {
+    error!("{e}");
+    code
+}
+
+/// Failing to compile a project is like failing to parse it, unless kcc
+/// itself failed, e.g. to write a file.
+fn compile_error(e: ScratchError) -> ! {
+    let internal = e.trace.iter().any(|t| t.error_type == ErrorType::Internal);
+    fail(
+        if internal {
+            exit::RUNTIME_ERROR
+        } else {
+            exit::PARSE_ERROR
+        },
+        e,
+    )
+}
+
+/// The name of a project, from its file name.
+fn project_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "project".to_string())
+}
+
+/// Parses the `project.json` of a `.sb3` file.
+fn read_project(project_path: &Path) -> Result<model::Project, ScratchError> {
+    let location = format!("reading {}", project_path.display());
+    let temp_dir = tempfile::tempdir().map_err(|e| ScratchError::internal(e, &location))?;
+    let project_file =
+        File::open(project_path).map_err(|e| ScratchError::not_found(e, &location))?;
+    zip::ZipArchive::new(project_file)
+        .and_then(|mut archive| archive.extract(temp_dir.path()))
+        .map_err(|e| ScratchError::syntax_error(e, &location))?;
+    load_from_directory(temp_dir.path()).map_err(|e| ScratchError::syntax_error(e, &location))
+}
+
+/// Turns a parsed project into the scripts kcc runs.
+fn transform(project: model::Project) -> Result<VMStartup, ScratchError> {
+    // The transformer panics on projects it cannot make sense of.
+    std::panic::catch_unwind(|| VMStartup::from(project)).map_err(|_| {
+        ScratchError::syntax_error("the project is malformed", "transforming the project")
+    })
+}
+
+/// Reads and transforms a project, or exits with [`exit::PARSE_ERROR`].
+fn load_project(project_path: &Path) -> VMStartup {
+    read_project(project_path)
+        .and_then(transform)
+        .unwrap_or_else(|e| fail(exit::PARSE_ERROR, e))
+}
+
+/// `kcc compile`: writes the project in another language.
+fn compile_main(args: CompileArgs) -> i32 {
+    let name = project_name(&args.project);
+    let out_dir = args.out_dir.unwrap_or_else(|| PathBuf::from(&name));
+    let mut startup = load_project(&args.project);
+    optimizer::optimize(&mut startup, &args.optimizations.passes());
+    if let Err(e) = compiler::compile(&startup, args.target, &name, &out_dir) {
+        compile_error(e);
     }
-    let temp_dir = tempfile::tempdir()
-        .expect("failed to create a temporary directory to extract project contents");
-    if !std::fs::exists(&args[1]).unwrap() {
-        error!("file {} does not exist", &args[1]);
-        std::process::exit(1);
+    info!("wrote {}", out_dir.display());
+    0
+}
+
+/// `kcc check`: prints the blocks kcc cannot run and suspicious mixes of types.
+fn check_main(args: CheckArgs) -> i32 {
+    let startup = load_project(&args.project);
+    let layout = Layout::new(&startup);
+    let unsupported = analysis::support::unsupported(&layout);
+    let types = analysis::types::infer(&layout);
+    if args.types {
+        print!("{}", types.report(&layout));
+    }
+    for block in unsupported.iter() {
+        println!("error: {block}");
+    }
+    for warning in types.warnings.iter() {
+        println!("warning: {warning}");
+    }
+    match unsupported.is_empty() && types.warnings.is_empty() {
+        true => 0,
+        false => exit::CHECK_FAILED,
+    }
+}
+
+/// `kcc lint`: prints likely mistakes, found without running the project.
+fn lint_main(args: LintArgs) -> i32 {
+    let project = read_project(&args.project).unwrap_or_else(|e| fail(exit::PARSE_ERROR, e));
+    let lints = analysis::lint::lint(&project);
+    if args.json {
+        match serde_json::to_string_pretty(&lints) {
+            Ok(json) => println!("{json}"),
+            Err(e) => fail(exit::RUNTIME_ERROR, e),
+        }
+    } else {
+        for lint in lints.iter() {
+            println!("warning: {lint}");
+        }
+    }
+    match lints.is_empty() {
+        true => 0,
+        false => exit::CHECK_FAILED,
+    }
+}
+
+/// `kcc graph`: prints how the scripts of a project talk to each other.
+fn graph_main(args: GraphArgs) -> i32 {
+    let project = read_project(&args.project).unwrap_or_else(|e| fail(exit::PARSE_ERROR, e));
+    let graph = Graph::new(&project);
+    for cycle in graph.cycles.iter() {
+        eprintln!(
+            "warning: these scripts may wait for each other forever: {}",
+            cycle.join(", ")
+        );
+    }
+    match args.format {
+        graph::Format::Dot => print!("{}", graph.dot()),
+        graph::Format::Json => match serde_json::to_string_pretty(&graph) {
+            Ok(json) => println!("{json}"),
+            Err(e) => fail(exit::RUNTIME_ERROR, e),
+        },
+    }
+    0
+}
+
+/// `kcc diff`: prints what changed between two versions of a project.
+fn diff_main(args: DiffArgs) -> i32 {
+    let old = read_project(&args.old).unwrap_or_else(|e| fail(exit::PARSE_ERROR, e));
+    let new = read_project(&args.new).unwrap_or_else(|e| fail(exit::PARSE_ERROR, e));
+    let changes = analysis::diff::diff(&old, &new);
+    if args.json {
+        match serde_json::to_string_pretty(&changes) {
+            Ok(json) => println!("{json}"),
+            Err(e) => fail(exit::RUNTIME_ERROR, e),
+        }
+    } else {
+        for change in changes.iter() {
+            println!("{change}");
+        }
+    }
+    0
+}
+
+/// `kcc stats`: prints the stats of many projects, read in parallel. Projects
+/// that cannot be read get a row with their error.
+fn stats_main(args: StatsArgs) -> i32 {
+    let projects =
+        stats::collect(&args.paths, read_project).unwrap_or_else(|e| fail(exit::RUNTIME_ERROR, e));
+    match args.format {
+        stats::Format::Json => match serde_json::to_string_pretty(&projects) {
+            Ok(json) => println!("{json}"),
+            Err(e) => fail(exit::RUNTIME_ERROR, e),
+        },
+        stats::Format::Csv => match stats::csv(&projects) {
+            Ok(csv) => print!("{csv}"),
+            Err(e) => fail(exit::RUNTIME_ERROR, e),
+        },
+    }
+    for project in projects.iter() {
+        if let Some(e) = &project.error {
+            error!("{}: {e}", project.path.display());
+        }
+    }
+    match projects.iter().any(|p| p.error.is_some()) {
+        true => exit::PARSE_ERROR,
+        false => 0,
+    }
+}
+
+/// `kcc dump`: prints the parsed project, or its IR.
+fn dump_main(args: DumpArgs) -> i32 {
+    if args.ast {
+        let project = read_project(&args.project).unwrap_or_else(|e| fail(exit::PARSE_ERROR, e));
+        match serde_json::to_string_pretty(&project) {
+            Ok(json) => println!("{json}"),
+            Err(e) => fail(exit::RUNTIME_ERROR, e),
+        }
+        return 0;
+    }
+    let mut startup = load_project(&args.project);
+    optimizer::optimize(&mut startup, &args.optimizations.passes());
+    match ir::lower::lower(&Layout::new(&startup)) {
+        Ok(program) => print!("{program}"),
+        Err(e) => fail(exit::PARSE_ERROR, e),
+    }
+    0
+}
+
+/// `kcc info`: prints how many scripts, blocks, variables and so on each target has.
+fn info_main(args: InfoArgs) -> i32 {
+    let project = read_project(&args.project).unwrap_or_else(|e| fail(exit::PARSE_ERROR, e));
+    println!(
+        "{:<20} {:>7} {:>6} {:>9} {:>5} {:>8} {:>6}",
+        "target", "scripts", "blocks", "variables", "lists", "costumes", "sounds"
+    );
+    for target in project.targets.iter() {
+        let (name, blocks, variables, lists, costumes, sounds) = match target {
+            model::Target::Sprite(s) => (
+                &s.name,
+                &s.blocks,
+                s.variables.len(),
+                s.lists.len(),
+                s.costumes.len(),
+                s.sounds.len(),
+            ),
+            model::Target::Stage(s) => (
+                &s.name,
+                &s.blocks,
+                s.variables.len(),
+                s.lists.len(),
+                s.costumes.len(),
+                s.sounds.len(),
+            ),
+        };
+        // Menus and other inputs are shadow blocks.
+        let blocks = blocks.values().filter(|b| !b.shadow);
+        let scripts = blocks.clone().filter(|b| b.top_level).count();
+        println!(
+            "{name:<20} {scripts:>7} {:>6} {variables:>9} {lists:>5} {costumes:>8} {sounds:>6}",
+            blocks.count()
+        );
+    }
+    let startup = transform(project).unwrap_or_else(|e| fail(exit::PARSE_ERROR, e));
+    let layout = Layout::new(&startup);
+    println!(
+        "broadcasts: {}",
+        startup.gstate.broadcastname_to_numid.len()
+    );
+    println!("scripts that can run: {}", layout.scripts.len());
+    println!(
+        "blocks kcc cannot run: {}",
+        analysis::support::unsupported(&layout).len()
+    );
+    0
+}
+
+/// `kcc package`: writes an executable that runs the project like `kcc <project>` does.
+fn package_main(args: PackageArgs) -> i32 {
+    let name = project_name(&args.project);
+    let out = args
+        .out
+        .unwrap_or_else(|| PathBuf::from(format!("{name}{}", std::env::consts::EXE_SUFFIX)));
+    // Both formats load the project now, so that broken ones are not packaged.
+    let mut startup = load_project(&args.project);
+    let package = if args.bytecode {
+        optimizer::optimize(&mut startup, &args.optimizations.passes());
+        bytecode::lower::lower(&Layout::new(&startup))
+            .and_then(|program| program.encode())
+            .map(|data| Package {
+                name,
+                format: Format::Bytecode,
+                args: Vec::new(),
+                data,
+            })
+    } else {
+        // Projects packaged as they are are optimized when they start.
+        package::project(&args.project).map(|data| Package {
+            name,
+            format: Format::Project,
+            args: args.optimizations.args(),
+            data,
+        })
+    };
+    if let Err(e) = package.and_then(|package| package.write(&out)) {
+        compile_error(e);
+    }
+    info!("wrote {}", out.display());
+    0
+}
+
+/// Loads a `.kbc` file, or lowers a project through the bytecode cache.
+fn load_bytecode(
+    project_path: &Path,
+    cache_dir: Option<PathBuf>,
+    passes: &[Pass],
+) -> Result<Program, ScratchError> {
+    if project_path.extension().is_some_and(|e| e == "kbc") {
+        Program::load(project_path)
+    } else {
+        let lower = || {
+            let mut startup = read_project(project_path).and_then(transform)?;
+            optimizer::optimize(&mut startup, passes);
+            bytecode::lower::lower(&Layout::new(&startup))
+        };
+        // Projects compiled without some optimizations are cached separately.
+        let variant = Pass::ALL
+            .iter()
+            .filter(|p| !passes.contains(p))
+            .map(|p| format!("-no-{}", p.name()))
+            .collect::<String>();
+        match cache::directory(cache_dir) {
+            Some(dir) => cache::load_or_lower(project_path, &dir, &variant, lower),
+            None => lower(),
+        }
+    }
+}
+
+/// The code to exit with once the project ended.
+fn finish(result: Result<Ending, ScratchError>) -> i32 {
+    let host = host::get();
+    match result {
+        Ok(ending) => {
+            host.end(ending.name());
+            match ending {
+                Ending::Finished => 0,
+                Ending::StopAll => exit::STOP_ALL,
+                Ending::StepLimit => {
+                    let max = host.options().max_steps.unwrap_or_default();
+                    error!("stopped after {max} steps");
+                    exit::STEP_LIMIT
+                }
+            }
+        }
+        Err(e) => {
+            host.end("error");
+            error!("{e}");
+            exit::RUNTIME_ERROR
+        }
+    }
+}
+
+/// `kcc run`: runs a project with the interpreter, or as bytecode. It is how
+/// packaged projects run, so it returns exit codes instead of exiting.
+fn run_main(args: RunArgs) -> i32 {
+    run(args).unwrap_or_else(|code| code)
+}
+
+/// [`run_main`], with the code to exit with as the error when kcc fails.
+fn run(args: RunArgs) -> Result<i32, i32> {
+    let passes = args.optimizations.passes();
+    let mut answers = args.answers;
+    if let Some(path) = &args.answers_file {
+        match std::fs::read_to_string(path) {
+            Ok(text) => answers.extend(text.lines().map(str::to_string)),
+            Err(e) => {
+                return Err(failed(
+                    exit::RUNTIME_ERROR,
+                    format!("cannot read answers from {}: {e}", path.display()),
+                ))
+            }
+        }
+    }
+    let options = Options {
+        seed: args.seed,
+        max_steps: args.max_steps,
+        virtual_clock: args.virtual_clock,
+        answers,
+        output: args.output,
+    };
+    let project_path = &args.project;
+    if !project_path.exists() {
+        return Err(failed(
+            exit::PARSE_ERROR,
+            format!("file {} does not exist", project_path.display()),
+        ));
+    }
+    if args.bytecode || project_path.extension().is_some_and(|e| e == "kbc") {
+        if args.cloud_file.is_some()
+            || args.cloud_ws.is_some()
+            || !args.imports.is_empty()
+            || !args.exports.is_empty()
+            || args.profile
+            || args.flamegraph.is_some()
+            || args.trace.is_some()
+            || args.coverage.is_some()
+        {
+            return Err(failed(
+                exit::RUNTIME_ERROR,
+                "cloud variables, list files, profiling, tracing and coverage are only supported by the interpreter",
+            ));
+        }
+        let program = load_bytecode(project_path, args.cache_dir, &passes)
+            .map_err(|e| failed(exit::PARSE_ERROR, e))?;
+        host::init(options);
+        return Ok(finish(machine::run(&program)));
+    }
+    let project = read_project(project_path).map_err(|e| failed(exit::PARSE_ERROR, e))?;
+    // Coverage is about the blocks of the editor, which the optimizer would merge or drop.
+    let covered = args.coverage.is_some().then(|| project.clone());
+    let mut startup = transform(project).map_err(|e| failed(exit::PARSE_ERROR, e))?;
+    if covered.is_none() {
+        optimizer::optimize(&mut startup, &passes);
     }
-    let project_file = File::open(&args[1]).unwrap();
-    zip::ZipArchive::new(project_file)
-        .unwrap()
-        .extract(temp_dir.path())
-        .unwrap();
-    let prj = load_from_directory(temp_dir.path()).expect("unable to load project");
     debug!("Parsing completed, starting execution");
-    vm::run(prj.into());
+    startup.gstate.cloud = if let Some(path) = args.cloud_file {
+        Some(Arc::new(FileProvider::new(path)) as Arc<dyn CloudProvider>)
+    } else if let Some(address) = args.cloud_ws {
+        let provider = WebSocketProvider::host_or_join(address, project_name(project_path))
+            .map_err(|e| failed(exit::RUNTIME_ERROR, e))?;
+        Some(Arc::new(provider))
+    } else {
+        None
+    };
+    for (list, file) in args.imports.iter() {
+        listfile::import(&startup, list, file).map_err(|e| failed(exit::RUNTIME_ERROR, e))?;
+    }
+    let list_exports = args
+        .exports
+        .into_iter()
+        .map(|(list, file)| Ok((listfile::find_list(&startup, &list)?, file)))
+        .collect::<Result<Vec<_>, ScratchError>>()
+        .map_err(|e| failed(exit::RUNTIME_ERROR, e))?;
+    host::init(options);
+    let profiler = (args.profile || args.flamegraph.is_some()).then(|| Arc::new(Profiler::new()));
+    let tracer = args
+        .trace
+        .as_deref()
+        .map(Tracer::create)
+        .transpose()
+        .map_err(|e| failed(exit::RUNTIME_ERROR, e))?
+        .map(Arc::new);
+    let coverage = covered.as_ref().map(|_| Arc::new(Coverage::new()));
+    let result = vm::run_with(startup, |mut runtime| {
+        if let Some(profiler) = &profiler {
+            runtime = runtime.with_profiler(Arc::clone(profiler));
+        }
+        if let Some(tracer) = &tracer {
+            runtime = runtime.with_tracer(Arc::clone(tracer));
+        }
+        if let Some(coverage) = &coverage {
+            runtime = runtime.with_coverage(Arc::clone(coverage));
+        }
+        runtime
+    });
+    // Lists are dumped even if the project crashed, to help finding out why.
+    for (list, file) in list_exports.iter() {
+        if let Err(e) = listfile::export(list, file) {
+            error!("{e}");
+        }
+    }
+    if let Some(profiler) = profiler {
+        if args.profile {
+            eprint!("{}", profiler.report());
+        }
+        if let Some(path) = &args.flamegraph {
+            if let Err(e) = std::fs::write(path, profiler.stacks()) {
+                error!("cannot write {}: {e}", path.display());
+            }
+        }
+    }
+    if let Some(tracer) = tracer {
+        if let Err(e) = tracer.finish() {
+            error!("cannot write the trace: {e}");
+        }
+    }
+    if let (Some(coverage), Some(project), Some(path)) = (coverage, covered, &args.coverage) {
+        write_coverage(&coverage, &project, path, args.coverage_svg.as_deref())?;
+    }
+    Ok(finish(result))
+}
+
+/// Adds the blocks that ran to the report in `path`, and prints how much of the project that covers.
+fn write_coverage(
+    coverage: &Coverage,
+    project: &model::Project,
+    path: &Path,
+    svg: Option<&Path>,
+) -> Result<(), i32> {
+    let mut report = coverage.report();
+    if path.exists() {
+        let earlier = coverage::Report::read(path).map_err(|e| failed(exit::RUNTIME_ERROR, e))?;
+        report.merge(&earlier);
+    }
+    report.complete(project);
+    if let Err(e) = report.write(path) {
+        error!("{e}");
+    }
+    eprint!("{}", report.summary());
+    if let Some(svg) = svg {
+        if let Err(e) = std::fs::write(svg, coverage::svg(project, &report)) {
+            error!("cannot write {}: {e}", svg.display());
+        }
+    }
+    Ok(())
+}
+
+/// `kcc debug`: runs a project with the interpreter, reading debugger commands from stdin.
+fn debug_main(args: DebugArgs) -> i32 {
+    // The project is not optimized, so that its blocks are the ones in the editor.
+    let startup = load_project(&args.project);
+    host::init(Options {
+        seed: args.seed,
+        virtual_clock: args.virtual_clock,
+        answers: args.answers,
+        ..Options::default()
+    });
+    finish(vm::debug(startup, Debugger::new(args.breakpoints)))
+}
+
+/// `kcc test`: runs every case of the projects in a folder, and reports which
+/// said something else than expected.
+fn test_main(args: TestArgs) -> i32 {
+    let projects = golden::discover(&args.path).unwrap_or_else(|e| fail(exit::RUNTIME_ERROR, e));
+    let cases = projects
+        .iter()
+        .flat_map(|project| golden::cases(project, read_project(project).ok().as_ref()))
+        .collect::<Vec<_>>();
+    if cases.is_empty() {
+        fail(
+            exit::RUNTIME_ERROR,
+            format!("no tests found in {}", args.path.display()),
+        );
+    }
+    let mut run_args = args.optimizations.args();
+    if args.bytecode {
+        run_args.push("--bytecode".to_string());
+    }
+    let settings = golden::Settings {
+        seed: args.seed,
+        max_steps: args.max_steps,
+        args: run_args,
+    };
+    println!("running {} tests", cases.len());
+    let mut outcomes = Vec::new();
+    for case in cases.iter() {
+        let outcome = golden::run(case, &settings);
+        let status = match outcome.status {
+            golden::Status::Passed => "ok".green(),
+            golden::Status::Failed => "FAILED".red(),
+            golden::Status::Error => "ERROR".red(),
+        };
+        println!("test {} ... {status}", outcome.name);
+        outcomes.push(outcome);
+    }
+    print!("{}", golden::report(&outcomes));
+    let write = |path: &Path, report: String| {
+        if let Err(e) = std::fs::write(path, report) {
+            fail(
+                exit::RUNTIME_ERROR,
+                format!("cannot write {}: {e}", path.display()),
+            );
+        }
+    };
+    if let Some(path) = &args.junit {
+        write(path, golden::junit(&project_name(&args.path), &outcomes));
+    }
+    if let Some(path) = &args.json {
+        write(path, golden::json(&outcomes));
+    }
+    match outcomes.iter().all(|o| o.status == golden::Status::Passed) {
+        true => 0,
+        false => exit::TEST_FAILED,
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args().collect::<Vec<String>>();
+    pretty_env_logger::init();
+    // A packaged project runs as if it was given to `kcc run` after its own options.
+    let package_dir = package::embedded().map(|package| {
+        let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+        let path = match package.extract(dir.path()) {
+            Ok(path) => path,
+            Err(e) => {
+                drop(dir);
+                fail(exit::RUNTIME_ERROR, e);
+            }
+        };
+        let mut run = vec!["run".to_string()];
+        run.extend(package.args);
+        args.splice(1..1, run);
+        args.push(path.to_string_lossy().to_string());
+        dir
+    });
+    let cli = match Cli::try_parse_with_default(args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            // Usage errors, but also `--help` and `--version`.
+            drop(package_dir);
+            e.exit();
+        }
+    };
+    let code = match cli.command {
+        Command::Run(args) => run_main(*args),
+        Command::Debug(args) => debug_main(args),
+        Command::Check(args) => check_main(args),
+        Command::Lint(args) => lint_main(args),
+        Command::Graph(args) => graph_main(args),
+        Command::Dump(args) => dump_main(args),
+        Command::Compile(args) => compile_main(args),
+        Command::Info(args) => info_main(args),
+        Command::Diff(args) => diff_main(args),
+        Command::Stats(args) => stats_main(args),
+        Command::Package(args) => package_main(args),
+        Command::Test(args) => test_main(args),
+    };
+    drop(package_dir);
+    std::process::exit(code);
 }
@@ -0,0 +1,105 @@
+//! Removal of code that can never run: scripts nothing starts, and blocks after
+//! a block that never lets its script go on.
+
+use hashbrown::HashSet;
+use scratch_ast::cast;
+
+use crate::vm::{
+    internals::{Expression, StopOption, ThreadTrigger},
+    transform::VMStartup,
+};
+
+use super::{bodies_mut, constant, visit};
+
+pub fn unreachable(startup: &mut VMStartup) -> usize {
+    let mut removed = 0;
+    for (_, source_code) in startup.targets.iter_mut() {
+        for thread in source_code.values_mut().flatten() {
+            removed += trim(&mut thread.code);
+        }
+    }
+    removed
+}
+
+/// Removes what follows the first block of `code` that ends its script.
+fn trim(code: &mut Vec<Expression>) -> usize {
+    let mut removed = 0;
+    for expression in code.iter_mut() {
+        for body in bodies_mut(expression) {
+            removed += trim(body);
+        }
+    }
+    if let Some(end) = code.iter().position(ends) {
+        removed += code.len() - end - 1;
+        code.truncate(end + 1);
+    }
+    removed
+}
+
+/// Whether a script never goes on after `expression`. A `forever` loop only ends
+/// with a `stop`, which also ends the script.
+fn ends(expression: &Expression) -> bool {
+    match expression {
+        Expression::Stop { option, .. } => *option != StopOption::OtherScriptsInSprite,
+        Expression::LoopForever { .. } => true,
+        Expression::Conditional {
+            then, otherwise, ..
+        } => then.iter().any(ends) && otherwise.iter().any(ends),
+        _ => false,
+    }
+}
+
+/// Removes the scripts that cannot start from the green flag: hats kcc cannot fire,
+/// broadcasts nobody sends and custom blocks nobody calls.
+pub fn scripts(startup: &mut VMStartup) -> usize {
+    let targets = startup.targets.len();
+    let mut live = HashSet::new();
+    let mut pending = (0..targets)
+        .map(|t| (t, ThreadTrigger::GreenFlag))
+        .collect::<Vec<_>>();
+    while let Some((target, trigger)) = pending.pop() {
+        if !live.insert((target, trigger.clone())) {
+            continue;
+        }
+        let source_code = &startup.targets[target].1;
+        for thread in source_code.get(&trigger).into_iter().flatten() {
+            visit(&thread.code, &mut |expression| match expression {
+                Expression::InvokeBroadcast(header) => {
+                    match header.argraw("BROADCAST_INPUT").and_then(constant) {
+                        Some(name) => {
+                            let name = cast::to_string(&name).to_lowercase();
+                            pending.extend(
+                                (0..targets).map(|t| (t, ThreadTrigger::Broadcast(name.clone()))),
+                            );
+                        }
+                        // A broadcast whose name is only known when it runs may start any receiver.
+                        None => {
+                            for (t, (_, code)) in startup.targets.iter().enumerate() {
+                                pending.extend(
+                                    code.keys()
+                                        .filter(|k| matches!(k, ThreadTrigger::Broadcast(_)))
+                                        .map(|k| (t, k.clone())),
+                                );
+                            }
+                        }
+                    }
+                }
+                Expression::InvokeCustomBlock { target: id, .. } => {
+                    pending.push((target, ThreadTrigger::Mutation(*id)));
+                }
+                _ => (),
+            });
+        }
+    }
+    let mut removed = 0;
+    for (target, (_, source_code)) in startup.targets.iter_mut().enumerate() {
+        source_code.retain(|trigger, threads| {
+            let keep = live.contains(&(target, trigger.clone()));
+            if !keep {
+                removed += threads.len();
+            }
+            keep
+        });
+    }
+    removed
+}
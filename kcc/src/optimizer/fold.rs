@@ -0,0 +1,141 @@
+//! Constant folding: operators whose inputs are constants become the value they report.
+
+use std::cmp::Ordering;
+
+use scratch_ast::{
+    cast,
+    model::{BlockType, RichValue},
+};
+
+use crate::vm::{
+    intepreter::{mathop, modulo},
+    internals::{Expression, StackExpression, VMEvaluable},
+    transform::VMStartup,
+};
+
+use super::{bodies_mut, constant, inputs_mut};
+
+pub fn run(startup: &mut VMStartup) -> usize {
+    let mut folded = 0;
+    for (_, source_code) in startup.targets.iter_mut() {
+        for thread in source_code.values_mut().flatten() {
+            folded += statements(&mut thread.code);
+        }
+    }
+    folded
+}
+
+fn statements(code: &mut Vec<Expression>) -> usize {
+    let mut folded = 0;
+    for mut expression in std::mem::take(code) {
+        for value in inputs_mut(&mut expression) {
+            folded += fold(value);
+        }
+        for body in bodies_mut(&mut expression) {
+            folded += statements(body);
+        }
+        match expression {
+            Expression::Conditional {
+                header,
+                then,
+                otherwise,
+            } => match condition(&header) {
+                Some(true) => {
+                    folded += 1;
+                    code.extend(then);
+                }
+                Some(false) => {
+                    folded += 1;
+                    code.extend(otherwise);
+                }
+                None => code.push(Expression::Conditional {
+                    header,
+                    then,
+                    otherwise,
+                }),
+            },
+            // A loop that stops before its first iteration.
+            Expression::LoopCondition { header, .. }
+                if condition(&header) == Some(header.opcode == BlockType::ControlRepeatUntil) =>
+            {
+                folded += 1;
+            }
+            expression => code.push(expression),
+        }
+    }
+    folded
+}
+
+/// The condition of a block, if it is a constant. An empty condition slot is false.
+fn condition(exp: &StackExpression) -> Option<bool> {
+    match exp.argraw("CONDITION") {
+        Some(value) => constant(value).map(|v| cast::to_boolean(&v)),
+        None => Some(false),
+    }
+}
+
+/// Folds the reporters of an input, innermost first.
+fn fold(value: &mut VMEvaluable) -> usize {
+    let VMEvaluable::Block(exp) = value else {
+        return 0;
+    };
+    let mut folded = 0;
+    for dependency in exp.dependencies.values_mut() {
+        folded += fold(dependency);
+    }
+    if let Some(result) = evaluate(exp) {
+        *value = VMEvaluable::Bare(result);
+        folded += 1;
+    }
+    folded
+}
+
+/// What an operator reports when its inputs are constants, like [`eval_exp`] would.
+///
+/// [`eval_exp`]: crate::vm::intepreter::eval_exp
+fn evaluate(exp: &StackExpression) -> Option<RichValue> {
+    let value = |name: &str| exp.argraw(name).and_then(constant);
+    let number = |name: &str| value(name).map(|v| cast::to_number(&v));
+    let text = |name: &str| value(name).map(|v| cast::to_string(&v));
+    let boolean = |name: &str| value(name).map(|v| cast::to_boolean(&v));
+    let compare = |ordering: Ordering| {
+        Some(RichValue::Boolean(
+            cast::compare(&value("OPERAND1")?, &value("OPERAND2")?) == ordering,
+        ))
+    };
+    Some(match exp.opcode {
+        BlockType::OperatorAdd => RichValue::Number(number("NUM1")? + number("NUM2")?),
+        BlockType::OperatorSubtract => RichValue::Number(number("NUM1")? - number("NUM2")?),
+        BlockType::OperatorMultiply => RichValue::Number(number("NUM1")? * number("NUM2")?),
+        BlockType::OperatorDivide => RichValue::Number(number("NUM1")? / number("NUM2")?),
+        BlockType::OperatorMod => RichValue::Number(modulo(number("NUM1")?, number("NUM2")?)),
+        BlockType::OperatorRound => RichValue::Number(cast::round(number("NUM")?)),
+        BlockType::OperatorMathop => {
+            let op = text("OPERATOR")?.to_lowercase();
+            RichValue::Number(mathop(&op, number("NUM")?)?)
+        }
+        BlockType::OperatorGt => return compare(Ordering::Greater),
+        BlockType::OperatorLt => return compare(Ordering::Less),
+        BlockType::OperatorEquals => return compare(Ordering::Equal),
+        BlockType::OperatorAnd => RichValue::Boolean(boolean("OPERAND1")? && boolean("OPERAND2")?),
+        BlockType::OperatorOr => RichValue::Boolean(boolean("OPERAND1")? || boolean("OPERAND2")?),
+        BlockType::OperatorNot => RichValue::Boolean(!boolean("OPERAND")?),
+        BlockType::OperatorJoin => RichValue::String(text("STRING1")? + &text("STRING2")?),
+        BlockType::OperatorLetterOf => {
+            let index = number("LETTER")? - 1.0;
+            let s = text("STRING")?;
+            if index < 0.0 || index >= cast::js_length(&s) as f64 {
+                RichValue::String(String::new())
+            } else {
+                RichValue::String(cast::js_char_at(&s, index as usize))
+            }
+        }
+        BlockType::OperatorLength => RichValue::Number(cast::js_length(&text("STRING")?) as f64),
+        BlockType::OperatorContains => RichValue::Boolean(
+            text("STRING1")?
+                .to_lowercase()
+                .contains(&text("STRING2")?.to_lowercase()),
+        ),
+        _ => return None,
+    })
+}
@@ -0,0 +1,314 @@
+//! Loop-invariant code motion: reporters that report the same thing on every
+//! iteration of a loop are evaluated once, before it.
+//!
+//! The IR has no local variables, so the loop moves into a new custom block, and
+//! the hoisted reporters become its arguments. Like any argument, they are
+//! evaluated when the custom block is called, and private to the calling script.
+
+use hashbrown::{HashMap, HashSet};
+use scratch_ast::model::{BlockType, PrimitiveValue};
+
+use crate::vm::{
+    intepreter::mathop,
+    internals::{
+        Expression, StackExpression, StopOption, ThreadTrigger, VMEvaluable, VMField, VMThread,
+        VMValuePointer,
+    },
+    transform::{fresh_id, fresh_proccode_id, VMStartup},
+};
+
+use super::{
+    argument_name, bodies_mut, constant, inputs_mut, private, visit, visit_inputs, writers,
+    written, ScriptId,
+};
+
+pub fn run(startup: &mut VMStartup) -> usize {
+    let writers = writers(startup.targets.iter().map(|(_, source_code)| source_code));
+    let cloud = startup.gstate.cloud_names.keys().copied().collect();
+    let global_names = startup
+        .gstate
+        .mutationname_to_numid
+        .keys()
+        .cloned()
+        .collect::<HashSet<_>>();
+    let mut hoisted = 0;
+    for (target, (_, source_code)) in startup.targets.iter_mut().enumerate() {
+        let mut procedures = Vec::new();
+        for (trigger, threads) in source_code.iter_mut() {
+            for (index, thread) in threads.iter_mut().enumerate() {
+                let mut script = Script {
+                    id: (target, trigger.clone(), index),
+                    writers: &writers,
+                    cloud: &cloud,
+                    global_names: &global_names,
+                    custom_block_arguments: thread.custom_block_arguments.clone(),
+                    argument_names: thread.argument_names.clone(),
+                    procedures: &mut procedures,
+                    hoisted: 0,
+                };
+                script.statements(&mut thread.code);
+                hoisted += script.hoisted;
+            }
+        }
+        for (id, thread) in procedures {
+            source_code.insert(ThreadTrigger::Mutation(id), vec![thread]);
+        }
+    }
+    hoisted
+}
+
+/// Whether leaving `code` early would stop the script, which the new custom block cannot do.
+fn stops_script(code: &[Expression]) -> bool {
+    let mut stops = false;
+    visit(code, &mut |expression| {
+        stops |= matches!(
+            expression,
+            Expression::Stop {
+                option: StopOption::ThisScript,
+                ..
+            }
+        );
+    });
+    stops
+}
+
+/// Operators that report the same thing whenever their inputs do.
+fn pure(exp: &StackExpression) -> bool {
+    match exp.opcode {
+        BlockType::OperatorAdd
+        | BlockType::OperatorSubtract
+        | BlockType::OperatorMultiply
+        | BlockType::OperatorDivide
+        | BlockType::OperatorMod
+        | BlockType::OperatorRound
+        | BlockType::OperatorGt
+        | BlockType::OperatorLt
+        | BlockType::OperatorEquals
+        | BlockType::OperatorAnd
+        | BlockType::OperatorOr
+        | BlockType::OperatorNot
+        | BlockType::OperatorJoin
+        | BlockType::OperatorLetterOf
+        | BlockType::OperatorLength
+        | BlockType::OperatorContains => true,
+        // An unknown operator is an error, which must not happen before the loop.
+        BlockType::OperatorMathop => exp.argraw("OPERATOR").and_then(constant).is_some_and(|op| {
+            mathop(&scratch_ast::cast::to_string(&op).to_lowercase(), 0.0).is_some()
+        }),
+        _ => false,
+    }
+}
+
+/// Whether a hoisted reporter reads back the same as it reported. Arguments are
+/// kept like variables, which turns booleans into text, so only numbers are hoisted.
+fn numeric(exp: &StackExpression) -> bool {
+    matches!(
+        exp.opcode,
+        BlockType::OperatorAdd
+            | BlockType::OperatorSubtract
+            | BlockType::OperatorMultiply
+            | BlockType::OperatorDivide
+            | BlockType::OperatorMod
+            | BlockType::OperatorRound
+            | BlockType::OperatorMathop
+            | BlockType::OperatorLength
+    )
+}
+
+/// A block made up by the optimizer, looking like `like` in error messages.
+fn made_up(opcode: BlockType, like: &StackExpression, suffix: &str) -> StackExpression {
+    let mut block = like.original_block.clone();
+    block.block_type = opcode;
+    block.obj_id = format!("{}/{suffix}", block.obj_id);
+    StackExpression {
+        opcode,
+        dependencies: HashMap::new(),
+        original_block: block,
+    }
+}
+
+fn argument_reporter(name: &str, like: &StackExpression, suffix: &str) -> StackExpression {
+    let mut exp = made_up(BlockType::ArgumentReporterStringNumber, like, suffix);
+    exp.dependencies.insert(
+        "VALUE".to_string(),
+        VMEvaluable::Field(VMField {
+            display_value: name.to_string(),
+            pointer: None,
+        }),
+    );
+    exp
+}
+
+struct Script<'a> {
+    id: ScriptId,
+    writers: &'a HashMap<usize, HashSet<ScriptId>>,
+    cloud: &'a HashSet<usize>,
+    global_names: &'a HashSet<String>,
+    custom_block_arguments: HashMap<usize, PrimitiveValue>,
+    argument_names: HashMap<String, usize>,
+    /// The custom blocks loops were moved to.
+    procedures: &'a mut Vec<(usize, VMThread)>,
+    hoisted: usize,
+}
+
+impl Script<'_> {
+    fn statements(&mut self, code: &mut [Expression]) {
+        for expression in code.iter_mut() {
+            let is_loop = matches!(
+                expression,
+                Expression::LoopTimes { .. }
+                    | Expression::LoopCondition { .. }
+                    | Expression::LoopForever { .. }
+            );
+            if is_loop && self.hoist(expression) {
+                continue;
+            }
+            for body in bodies_mut(expression) {
+                self.statements(body);
+            }
+        }
+    }
+
+    /// Whether the variable `id` keeps its value while `body` runs: nothing
+    /// in it changes the variable, and no other script does either.
+    fn stable(&self, id: usize, body: &HashSet<usize>) -> bool {
+        !body.contains(&id) && !self.cloud.contains(&id) && private(self.writers, id, &self.id)
+    }
+
+    /// Whether `value` reports the same thing on every iteration of a loop writing `body`.
+    fn invariant(&self, value: &VMEvaluable, body: &HashSet<usize>) -> bool {
+        match value {
+            VMEvaluable::Pointer(VMValuePointer::Variable { id, .. }) => self.stable(*id, body),
+            VMEvaluable::Block(exp) => match argument_name(exp) {
+                Some(name) => self.argument_names.contains_key(&name),
+                None => {
+                    pure(exp)
+                        && exp
+                            .dependencies
+                            .values()
+                            .all(|dependency| self.invariant(dependency, body))
+                }
+            },
+            value => constant(value).is_some(),
+        }
+    }
+
+    /// Replaces the largest invariant reporters among the inputs of `value` by
+    /// arguments, keeping the reporters in `hoisted`.
+    fn replace(
+        &self,
+        value: &mut VMEvaluable,
+        body: &HashSet<usize>,
+        hoisted: &mut Vec<(String, usize, VMEvaluable)>,
+    ) {
+        let hoistable =
+            matches!(value, VMEvaluable::Block(exp) if numeric(exp)) && self.invariant(value, body);
+        let VMEvaluable::Block(exp) = value else {
+            return;
+        };
+        if !hoistable {
+            for dependency in exp.dependencies.values_mut() {
+                self.replace(dependency, body, hoisted);
+            }
+            return;
+        }
+        let name = (hoisted.len()..)
+            .map(|number| format!("hoisted {number}"))
+            .find(|name| {
+                !self.argument_names.contains_key(name)
+                    && !self.global_names.contains(name)
+                    && hoisted.iter().all(|(other, _, _)| other != name)
+            })
+            .expect("there are always unused names");
+        let reporter = argument_reporter(&name, exp, "hoisted");
+        let original = std::mem::replace(value, VMEvaluable::Block(reporter));
+        hoisted.push((name, fresh_id(), original));
+    }
+
+    fn replace_all(
+        &self,
+        code: &mut [Expression],
+        body: &HashSet<usize>,
+        hoisted: &mut Vec<(String, usize, VMEvaluable)>,
+    ) {
+        for expression in code.iter_mut() {
+            for value in inputs_mut(expression) {
+                self.replace(value, body, hoisted);
+            }
+            for inner in bodies_mut(expression) {
+                self.replace_all(inner, body, hoisted);
+            }
+        }
+    }
+
+    /// Moves the invariant reporters of a loop out of it, if it has any,
+    /// by turning the loop into a call to a new custom block.
+    fn hoist(&mut self, expression: &mut Expression) -> bool {
+        let whole = std::slice::from_ref(&*expression);
+        if stops_script(whole) {
+            return false;
+        }
+        // Arguments only keep their values if the loop reads none the script does not have.
+        let mut foreign = false;
+        visit(whole, &mut |e| {
+            visit_inputs(e, &mut |exp| {
+                if let Some(name) = argument_name(exp) {
+                    foreign |= !self.argument_names.contains_key(&name);
+                }
+            })
+        });
+        if foreign {
+            return false;
+        }
+        let hoist_condition = matches!(expression, Expression::LoopCondition { .. });
+        let (Expression::LoopTimes { header, body }
+        | Expression::LoopCondition { header, body }
+        | Expression::LoopForever { header, body }) = expression
+        else {
+            return false;
+        };
+        let changed = written(body);
+        let mut hoisted = Vec::new();
+        if hoist_condition {
+            if let Some(condition) = header.dependencies.get_mut("CONDITION") {
+                self.replace(condition, &changed, &mut hoisted);
+            }
+        }
+        self.replace_all(body, &changed, &mut hoisted);
+        if hoisted.is_empty() {
+            return false;
+        }
+        self.hoisted += hoisted.len();
+
+        let header = header.clone();
+        let mut thread = VMThread {
+            custom_block_arguments: self.custom_block_arguments.clone(),
+            argument_names: self.argument_names.clone(),
+            code: Vec::new(),
+        };
+        // The script passes its own arguments on.
+        let mut arguments = HashMap::new();
+        for (name, id) in self.argument_names.iter() {
+            let reporter = argument_reporter(name, &header, "argument");
+            arguments.insert(*id, VMEvaluable::Block(reporter));
+        }
+        for (name, id, value) in hoisted {
+            thread.argument_names.insert(name, id);
+            thread
+                .custom_block_arguments
+                .insert(id, PrimitiveValue::String(String::new()));
+            arguments.insert(id, value);
+        }
+        let procedure = fresh_proccode_id();
+        let call = Expression::InvokeCustomBlock {
+            header: made_up(BlockType::ProceduresCall, &header, "call"),
+            target: procedure,
+            arguments,
+        };
+        let hat = made_up(BlockType::ProceduresDefinition, &header, "definition");
+        let moved = std::mem::replace(expression, call);
+        thread.code = vec![Expression::Stack(hat), moved];
+        self.procedures.push((procedure, thread));
+        true
+    }
+}
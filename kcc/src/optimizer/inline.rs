@@ -0,0 +1,178 @@
+//! Inlining: calls of small custom blocks become a copy of their contents, with
+//! the arguments they are called with in place of the argument reporters.
+
+use hashbrown::HashMap;
+use scratch_ast::model::{PrimitiveValue, RichValue};
+
+use crate::vm::{
+    internals::{
+        Expression, StackExpression, StopOption, ThreadTrigger, VMEvaluable, VMSourceCode, VMThread,
+    },
+    transform::VMStartup,
+};
+
+use super::{argument_name, bodies_mut, constant, header_mut, inputs_mut, visit, visit_inputs};
+
+/// How many blocks a custom block may hold to be inlined, not counting its definition.
+const MAX_BLOCKS: usize = 12;
+
+pub fn run(startup: &mut VMStartup) -> usize {
+    let mut inlined = 0;
+    // Custom blocks calling others are only inlined once those calls are,
+    // so this ends even if some call themselves.
+    loop {
+        let mut changed = 0;
+        for (_, source_code) in startup.targets.iter_mut() {
+            let procedures = inlinable(source_code);
+            for thread in source_code.values_mut().flatten() {
+                let names = thread.argument_names.clone();
+                changed += calls(&mut thread.code, &names, &procedures);
+            }
+        }
+        if changed == 0 {
+            return inlined;
+        }
+        inlined += changed;
+    }
+}
+
+/// The custom blocks of a target that can be inlined: small ones that call no
+/// other custom block, do not `stop this script`, which would only leave the
+/// custom block, and only read their own arguments.
+fn inlinable(source_code: &VMSourceCode) -> HashMap<usize, VMThread> {
+    let mut procedures = HashMap::new();
+    for (trigger, threads) in source_code.iter() {
+        let (ThreadTrigger::Mutation(id), [thread]) = (trigger, threads.as_slice()) else {
+            continue;
+        };
+        let body = thread.code.get(1..).unwrap_or_default();
+        let mut blocks = 0;
+        let mut simple = true;
+        visit(body, &mut |expression| {
+            blocks += 1;
+            simple &= !matches!(
+                expression,
+                Expression::InvokeCustomBlock { .. }
+                    | Expression::Stop {
+                        option: StopOption::ThisScript,
+                        ..
+                    }
+            );
+            visit_inputs(expression, &mut |exp| {
+                if let Some(name) = argument_name(exp) {
+                    simple &= thread.argument_names.contains_key(&name);
+                }
+            });
+        });
+        if simple && blocks <= MAX_BLOCKS {
+            procedures.insert(*id, thread.clone());
+        }
+    }
+    procedures
+}
+
+/// Inlines the calls of `procedures` in `code`, a script whose arguments are `names`.
+fn calls(
+    code: &mut Vec<Expression>,
+    names: &HashMap<String, usize>,
+    procedures: &HashMap<usize, VMThread>,
+) -> usize {
+    let mut inlined = 0;
+    for mut expression in std::mem::take(code) {
+        for body in bodies_mut(&mut expression) {
+            inlined += calls(body, names, procedures);
+        }
+        if let Expression::InvokeCustomBlock {
+            header,
+            target,
+            arguments,
+        } = &expression
+        {
+            let body = procedures
+                .get(target)
+                .and_then(|definition| instantiate(definition, header, arguments, names));
+            if let Some(body) = body {
+                code.extend(body);
+                inlined += 1;
+                continue;
+            }
+        }
+        code.push(expression);
+    }
+    inlined
+}
+
+/// What an argument reporter of the inlined custom block reports instead, if
+/// it is the same whenever the argument is read: a constant, or an argument of the caller.
+fn passed(value: &VMEvaluable, names: &HashMap<String, usize>) -> Option<VMEvaluable> {
+    if let Some(value) = constant(value) {
+        // Arguments are kept like variables, so booleans become text.
+        let kept: PrimitiveValue = value.into();
+        return Some(VMEvaluable::Bare(RichValue::from(kept)));
+    }
+    match value {
+        VMEvaluable::Block(exp) if argument_name(exp).is_some_and(|n| names.contains_key(&n)) => {
+            Some(value.clone())
+        }
+        _ => None,
+    }
+}
+
+/// A copy of the contents of `definition` as called by `call`, or `None` if an
+/// argument cannot be inlined.
+fn instantiate(
+    definition: &VMThread,
+    call: &StackExpression,
+    arguments: &HashMap<usize, VMEvaluable>,
+    names: &HashMap<String, usize>,
+) -> Option<Vec<Expression>> {
+    let mut values = HashMap::new();
+    for (name, id) in definition.argument_names.iter() {
+        let value = match arguments.get(id) {
+            Some(value) => passed(value, names)?,
+            None => VMEvaluable::Bare(definition.custom_block_arguments.get(id)?.into()),
+        };
+        values.insert(name.clone(), value);
+    }
+    let mut body = definition.code.get(1..).unwrap_or_default().to_vec();
+    // Every copy gets its own block IDs, which the JIT tells loops apart by.
+    let prefix = &call.original_block.obj_id;
+    substitute(&mut body, prefix, &values);
+    Some(body)
+}
+
+fn substitute(code: &mut [Expression], prefix: &str, values: &HashMap<String, VMEvaluable>) {
+    for expression in code.iter_mut() {
+        rename(header_mut(expression), prefix);
+        for value in inputs_mut(expression) {
+            replace_arguments(value, values);
+        }
+        for body in bodies_mut(expression) {
+            substitute(body, prefix, values);
+        }
+    }
+}
+
+fn rename(exp: &mut StackExpression, prefix: &str) {
+    exp.original_block.obj_id = format!("{prefix}/{}", exp.original_block.obj_id);
+    for value in exp.dependencies.values_mut() {
+        if let VMEvaluable::Block(b) = value {
+            rename(b, prefix);
+        }
+    }
+}
+
+fn replace_arguments(value: &mut VMEvaluable, values: &HashMap<String, VMEvaluable>) {
+    let VMEvaluable::Block(exp) = value else {
+        return;
+    };
+    if let Some(name) = argument_name(exp) {
+        if let Some(replacement) = values.get(&name) {
+            *value = replacement.clone();
+        }
+        return;
+    }
+    for dependency in exp.dependencies.values_mut() {
+        replace_arguments(dependency, values);
+    }
+}
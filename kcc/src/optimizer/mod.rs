@@ -0,0 +1,277 @@
+//! Passes rewriting the scripts of a project before it runs or is compiled.
+//! Projects do the same with or without them, only with less work.
+
+use hashbrown::{HashMap, HashSet};
+use log::debug;
+use scratch_ast::{
+    cast,
+    errors::ScratchError,
+    model::{BlockType, RichValue},
+};
+
+use crate::vm::{
+    internals::{
+        Expression, StackExpression, ThreadTrigger, VMEvaluable, VMSourceCode, VMValuePointer,
+    },
+    transform::VMStartup,
+};
+
+mod dead;
+mod fold;
+mod hoist;
+mod inline;
+
+/// An optimization. Each can be turned off on its own, to find out which one breaks a project.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// Replaces calls of small custom blocks by their contents.
+    Inline,
+    /// Evaluates operators whose inputs are constants, and `if`s whose condition is.
+    Fold,
+    /// Removes blocks that can never run because of a `stop` or a `forever` before them.
+    Unreachable,
+    /// Evaluates reporters whose value cannot change once before a loop, instead of every iteration.
+    Hoist,
+    /// Removes scripts that can never start, such as custom blocks nobody calls.
+    DeadScripts,
+}
+
+/// The order passes run in. Folding runs again once arguments are inlined.
+const PIPELINE: [Pass; 6] = [
+    Pass::Fold,
+    Pass::Inline,
+    Pass::Fold,
+    Pass::Unreachable,
+    Pass::Hoist,
+    Pass::DeadScripts,
+];
+
+impl Pass {
+    pub const ALL: [Pass; 5] = [
+        Pass::Inline,
+        Pass::Fold,
+        Pass::Unreachable,
+        Pass::Hoist,
+        Pass::DeadScripts,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Inline => "inline",
+            Pass::Fold => "fold",
+            Pass::Unreachable => "unreachable",
+            Pass::Hoist => "hoist",
+            Pass::DeadScripts => "dead-scripts",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, ScratchError> {
+        Self::ALL
+            .into_iter()
+            .find(|pass| pass.name() == name)
+            .ok_or_else(|| {
+                let names = Self::ALL.map(Pass::name).join(", ");
+                ScratchError::not_found(
+                    format!("unknown optimization pass {name}, expected one of {names}"),
+                    "parsing optimization passes",
+                )
+            })
+    }
+}
+
+/// Runs `passes` over every script of a project.
+pub fn optimize(startup: &mut VMStartup, passes: &[Pass]) {
+    for pass in PIPELINE.into_iter().filter(|p| passes.contains(p)) {
+        let changes = match pass {
+            Pass::Inline => inline::run(startup),
+            Pass::Fold => fold::run(startup),
+            Pass::Unreachable => dead::unreachable(startup),
+            Pass::Hoist => hoist::run(startup),
+            Pass::DeadScripts => dead::scripts(startup),
+        };
+        debug!("{}: {changes} changes", pass.name());
+    }
+}
+
+/// The value an input always has, if it does not depend on anything.
+fn constant(value: &VMEvaluable) -> Option<RichValue> {
+    match value {
+        VMEvaluable::Bare(value) => Some(value.clone()),
+        VMEvaluable::Field(f) if f.pointer.is_none() => {
+            Some(RichValue::String(f.display_value.clone()))
+        }
+        VMEvaluable::Default => Some(RichValue::String(String::new())),
+        _ => None,
+    }
+}
+
+/// The name of the custom block argument `exp` reports, if it is an argument reporter.
+fn argument_name(exp: &StackExpression) -> Option<String> {
+    match exp.opcode {
+        BlockType::ArgumentReporterStringNumber | BlockType::ArgumentReporterBoolean => exp
+            .argraw("VALUE")
+            .and_then(constant)
+            .map(|name| cast::to_string(&name)),
+        _ => None,
+    }
+}
+
+/// The block of an expression, whose dependencies are its inputs.
+pub(crate) fn header(expression: &Expression) -> &StackExpression {
+    match expression {
+        Expression::Stack(header)
+        | Expression::Conditional { header, .. }
+        | Expression::LoopTimes { header, .. }
+        | Expression::LoopCondition { header, .. }
+        | Expression::LoopForever { header, .. }
+        | Expression::InvokeBroadcast(header)
+        | Expression::InvokeCustomBlock { header, .. }
+        | Expression::Stop { header, .. } => header,
+    }
+}
+
+fn header_mut(expression: &mut Expression) -> &mut StackExpression {
+    match expression {
+        Expression::Stack(header)
+        | Expression::Conditional { header, .. }
+        | Expression::LoopTimes { header, .. }
+        | Expression::LoopCondition { header, .. }
+        | Expression::LoopForever { header, .. }
+        | Expression::InvokeBroadcast(header)
+        | Expression::InvokeCustomBlock { header, .. }
+        | Expression::Stop { header, .. } => header,
+    }
+}
+
+/// The inputs an expression evaluates, including the arguments of a custom block call.
+fn inputs_mut(expression: &mut Expression) -> Vec<&mut VMEvaluable> {
+    match expression {
+        Expression::InvokeCustomBlock {
+            header, arguments, ..
+        } => header
+            .dependencies
+            .values_mut()
+            .chain(arguments.values_mut())
+            .collect(),
+        Expression::Stack(header)
+        | Expression::Conditional { header, .. }
+        | Expression::LoopTimes { header, .. }
+        | Expression::LoopCondition { header, .. }
+        | Expression::LoopForever { header, .. }
+        | Expression::InvokeBroadcast(header)
+        | Expression::Stop { header, .. } => header.dependencies.values_mut().collect(),
+    }
+}
+
+/// The stacks inside an expression, such as the branches of an `if`.
+fn bodies(expression: &Expression) -> Vec<&Vec<Expression>> {
+    match expression {
+        Expression::Conditional {
+            then, otherwise, ..
+        } => vec![then, otherwise],
+        Expression::LoopTimes { body, .. }
+        | Expression::LoopCondition { body, .. }
+        | Expression::LoopForever { body, .. } => vec![body],
+        _ => Vec::new(),
+    }
+}
+
+fn bodies_mut(expression: &mut Expression) -> Vec<&mut Vec<Expression>> {
+    match expression {
+        Expression::Conditional {
+            then, otherwise, ..
+        } => vec![then, otherwise],
+        Expression::LoopTimes { body, .. }
+        | Expression::LoopCondition { body, .. }
+        | Expression::LoopForever { body, .. } => vec![body],
+        _ => Vec::new(),
+    }
+}
+
+/// Calls `f` on every expression of `code`, and of the stacks inside them.
+pub(crate) fn visit<'a>(code: &'a [Expression], f: &mut impl FnMut(&'a Expression)) {
+    for expression in code {
+        f(expression);
+        for body in bodies(expression) {
+            visit(body, f);
+        }
+    }
+}
+
+/// Calls `f` on the block of an expression and on every reporter in its inputs, however deep.
+fn visit_inputs<'a>(expression: &'a Expression, f: &mut impl FnMut(&'a StackExpression)) {
+    visit_reporters(header(expression), f);
+    if let Expression::InvokeCustomBlock { arguments, .. } = expression {
+        for value in arguments.values() {
+            if let VMEvaluable::Block(b) = value {
+                visit_reporters(b, f);
+            }
+        }
+    }
+}
+
+/// Calls `f` on `exp` and on every reporter in its inputs, however deep.
+fn visit_reporters<'a>(exp: &'a StackExpression, f: &mut impl FnMut(&'a StackExpression)) {
+    f(exp);
+    for value in exp.dependencies.values() {
+        if let VMEvaluable::Block(b) = value {
+            visit_reporters(b, f);
+        }
+    }
+}
+
+/// A script, by target, trigger and position among the scripts sharing the trigger.
+pub(crate) type ScriptId = (usize, ThreadTrigger, usize);
+
+/// The scripts setting or changing each variable, given the scripts of every target.
+pub(crate) fn writers<'a>(
+    targets: impl Iterator<Item = &'a VMSourceCode>,
+) -> HashMap<usize, HashSet<ScriptId>> {
+    let mut writers = HashMap::<usize, HashSet<ScriptId>>::new();
+    for (target, source_code) in targets.enumerate() {
+        for (trigger, threads) in source_code.iter() {
+            for (index, thread) in threads.iter().enumerate() {
+                for id in written(&thread.code) {
+                    writers
+                        .entry(id)
+                        .or_default()
+                        .insert((target, trigger.clone(), index));
+                }
+            }
+        }
+    }
+    writers
+}
+
+/// The variables `code` sets or changes.
+pub(crate) fn written(code: &[Expression]) -> HashSet<usize> {
+    let mut written = HashSet::new();
+    visit(code, &mut |expression| {
+        if let Expression::Stack(exp) = expression {
+            if matches!(
+                exp.opcode,
+                BlockType::DataSetVariableTo | BlockType::DataChangeVariableBy
+            ) {
+                if let Ok(VMValuePointer::Variable { id, .. }) = exp.argptr("VARIABLE") {
+                    written.insert(id);
+                }
+            }
+        }
+    });
+    written
+}
+
+/// Whether no script but `script` sets or changes the variable `id` while `script` runs.
+pub(crate) fn private(
+    writers: &HashMap<usize, HashSet<ScriptId>>,
+    id: usize,
+    script: &ScriptId,
+) -> bool {
+    match writers.get(&id) {
+        None => true,
+        // A green flag script cannot run twice at once.
+        Some(writers) => {
+            script.1 == ThreadTrigger::GreenFlag && writers.iter().all(|writer| writer == script)
+        }
+    }
+}
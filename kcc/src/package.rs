@@ -0,0 +1,160 @@
+//! Self-contained executables: a copy of kcc with a project appended to it.
+//!
+//! The end of a packaged executable looks like this, so that kcc can find the
+//! project without knowing how long it is itself:
+//!
+//! ```text
+//! [kcc] [package, bincode] [length of the package: u64 le] [MAGIC]
+//! ```
+
+use std::{
+    fs::{self, File},
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use hashbrown::HashSet;
+use log::debug;
+use scratch_ast::errors::ScratchError;
+use serde::{Deserialize, Serialize};
+
+use crate::vm::ScratchResult;
+
+pub const MAGIC: &[u8; 8] = b"KCCPKG\x01\0";
+const TRAILER: u64 = 8 + MAGIC.len() as u64;
+
+/// What a package holds. Both run on the copy of kcc they are appended to, as
+/// compiling to machine code would need a C or Rust compiler where kcc runs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Format {
+    /// A `.sb3` file, run by the interpreter.
+    Project,
+    /// A `.kbc` file, lowered when packaging.
+    Bytecode,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Package {
+    /// The name of the project, which the extracted file is named after.
+    pub name: String,
+    pub format: Format,
+    /// Options passed to kcc before those given to the executable.
+    pub args: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+/// Where the package of `file` starts and how long it is, if it has one.
+fn find(file: &mut File) -> std::io::Result<Option<(u64, u64)>> {
+    let len = file.metadata()?.len();
+    if len < TRAILER {
+        return Ok(None);
+    }
+    let mut trailer = [0; TRAILER as usize];
+    file.seek(SeekFrom::Start(len - TRAILER))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[8..] != MAGIC {
+        return Ok(None);
+    }
+    let size = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    match size.checked_add(TRAILER).filter(|total| *total <= len) {
+        Some(total) => Ok(Some((len - total, size))),
+        None => Ok(None),
+    }
+}
+
+/// The package appended to the running executable, if any.
+pub fn embedded() -> Option<Package> {
+    let read = || -> Result<Option<Package>, Box<dyn std::error::Error>> {
+        let mut file = File::open(std::env::current_exe()?)?;
+        let Some((start, size)) = find(&mut file)? else {
+            return Ok(None);
+        };
+        let mut bytes = vec![0; size as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut bytes)?;
+        Ok(Some(bincode::deserialize(&bytes)?))
+    };
+    read().unwrap_or_else(|e| {
+        debug!("not reading a package: {e}");
+        None
+    })
+}
+
+/// Copies the `project.json` of a `.sb3` file, and the costumes and sounds it
+/// uses, into a new `.sb3` file.
+pub fn project(path: &Path) -> Result<Vec<u8>, ScratchError> {
+    let location = format!("packaging {}", path.display());
+    let file = File::open(path).map_err(|e| ScratchError::internal(e, &location))?;
+    let mut archive =
+        zip::ZipArchive::new(file).map_err(|e| ScratchError::type_error(e, &location))?;
+    let mut json = Vec::new();
+    archive
+        .by_name("project.json")
+        .map_err(|e| ScratchError::not_found(e, &location))?
+        .read_to_end(&mut json)
+        .map_err(|e| ScratchError::internal(e, &location))?;
+    let project: serde_json::Value =
+        serde_json::from_slice(&json).map_err(|e| ScratchError::type_error(e, &location))?;
+    let assets = project["targets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|target| {
+            let costumes = target["costumes"].as_array().into_iter().flatten();
+            let sounds = target["sounds"].as_array().into_iter().flatten();
+            costumes.chain(sounds)
+        })
+        .filter_map(|asset| asset["md5ext"].as_str())
+        .collect::<HashSet<_>>();
+
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let mut names = vec!["project.json"];
+    names.extend(assets);
+    names.sort();
+    for name in names {
+        match archive.by_name(name) {
+            Ok(entry) => writer
+                .raw_copy_file(entry)
+                .map_err(|e| ScratchError::internal(e, &location))?,
+            Err(e) => debug!("not packaging {name}: {e}"),
+        }
+    }
+    let bytes = writer
+        .finish()
+        .map_err(|e| ScratchError::internal(e, &location))?
+        .into_inner();
+    Ok(bytes)
+}
+
+impl Package {
+    /// Writes a copy of the running executable with this package appended to `out`.
+    pub fn write(&self, out: &Path) -> ScratchResult {
+        let location = format!("writing {}", out.display());
+        let exe = std::env::current_exe().map_err(|e| ScratchError::internal(e, &location))?;
+        let mut bytes = fs::read(exe).map_err(|e| ScratchError::internal(e, &location))?;
+        let package = bincode::serialize(self).map_err(|e| ScratchError::internal(e, &location))?;
+        bytes.extend(&package);
+        bytes.extend((package.len() as u64).to_le_bytes());
+        bytes.extend(MAGIC);
+        fs::write(out, bytes).map_err(|e| ScratchError::internal(e, &location))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(out, fs::Permissions::from_mode(0o755))
+                .map_err(|e| ScratchError::internal(e, &location))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the project into `dir`, and returns the path kcc should run.
+    pub fn extract(&self, dir: &Path) -> Result<PathBuf, ScratchError> {
+        let extension = match self.format {
+            Format::Project => "sb3",
+            Format::Bytecode => "kbc",
+        };
+        let path = dir.join(format!("{}.{extension}", self.name));
+        fs::write(&path, &self.data)
+            .map_err(|e| ScratchError::internal(e, format!("writing {}", path.display())))?;
+        Ok(path)
+    }
+}
@@ -2,9 +2,13 @@ use std::sync::Arc;
 
 use hashbrown::HashMap;
 use parking_lot::RwLock;
-use scratch_ast::model::{Block, Field, PrimitiveValue, RichValue, ShadowValue, ValuePointer};
+use scratch_ast::{
+    cast,
+    model::{Block, Field, PrimitiveValue, RichValue, ShadowValue, ValuePointer},
+};
 
 use crate::vm::{
+    cloud,
     intepreter::{eval_exp, VMState},
     internals::{StackExpression, VMEvaluable, VMField, VMValuePointer},
     ScratchError,
@@ -12,21 +16,30 @@ use crate::vm::{
 
 #[inline]
 pub fn rich_value_to_string(rval: &RichValue) -> Result<String, ScratchError> {
-    Ok(Into::<PrimitiveValue>::into(rval.clone()).into())
+    Ok(cast::to_string(rval))
 }
 
 #[inline]
 pub fn rich_value_to_f64(rval: &RichValue) -> Result<f64, ScratchError> {
-    Into::<PrimitiveValue>::into(rval.clone())
-        .try_into()
-        .map_err(|e| ScratchError::type_error(e, format!("casting rich value {rval:?} to float")))
+    Ok(cast::to_number(rval))
 }
 
 #[inline]
 pub fn rich_value_to_bool(rval: &RichValue) -> Result<bool, ScratchError> {
-    Into::<PrimitiveValue>::into(rval.clone())
-        .try_into()
-        .map_err(|e| ScratchError::type_error(e, format!("casting rich value {rval:?} to bool")))
+    Ok(cast::to_boolean(rval))
+}
+
+/// The contents of a list as shown by its reporter: items are joined by spaces,
+/// unless every item is a single character.
+pub fn list_contents(list: &[RwLock<PrimitiveValue>]) -> String {
+    let items = list
+        .iter()
+        .map(|e| e.read().clone().into())
+        .collect::<Vec<String>>();
+    if items.iter().all(|i| i.chars().count() == 1) {
+        return items.join("");
+    }
+    items.join(" ")
 }
 
 impl StackExpression {
@@ -35,13 +48,15 @@ impl StackExpression {
         self.dependencies.get(argname)
     }
 
-    pub fn argstr(&self, argname: &str, state: &VMState) -> Result<String, ScratchError> {
-        match self.argraw(argname).ok_or(ScratchError::not_found(
+    /// Evaluates an argument without converting it.
+    /// Fields evaluate to their displayed text, and lists to their contents.
+    pub fn argvalue(&self, argname: &str, state: &VMState) -> Result<RichValue, ScratchError> {
+        let value = match self.argraw(argname).ok_or(ScratchError::not_found(
             format!("argument '{argname}' not found"),
             format!("lookup '{argname}'"),
         ))? {
-            VMEvaluable::Bare(rv) => rich_value_to_string(rv),
-            VMEvaluable::Field(f) => Ok(f.display_value.clone()),
+            VMEvaluable::Bare(rv) => Ok(rv.clone()),
+            VMEvaluable::Field(f) => Ok(RichValue::String(f.display_value.clone())),
             VMEvaluable::Pointer(v) => match &v {
                 VMValuePointer::Variable { id, name } => {
                     let pv: PrimitiveValue;
@@ -62,116 +77,39 @@ impl StackExpression {
                     }
                     Ok(pv.into())
                 }
-                VMValuePointer::List { id, .. } => {
-                    let pv: String;
-                    if let Some(var) = state.local_state.read().lists.get(id) {
-                        pv = var
-                            .read()
-                            .iter()
-                            .map(|e| e.read().clone().into())
-                            .collect::<Vec<String>>()
-                            .join(" ");
-                    } else {
-                        pv = state
-                            .global_state
-                            .read()
-                            .lists
-                            .get(id)
-                            .ok_or(ScratchError::not_found(
+                VMValuePointer::List { .. } => Ok(RichValue::String(list_contents(
+                    &v.resolve_list(state)
+                        .map_err(|e| {
+                            e.push_not_found(
                                 format!("list {argname} not found"),
-                                format!("fetching '{argname}' (id={id})"),
-                            ))?
-                            .read()
-                            .iter()
-                            .map(|e| e.read().clone().into())
-                            .collect::<Vec<String>>()
-                            .join(" ")
-                    }
-                    Ok(pv)
+                                format!("fetching '{argname}'"),
+                            )
+                        })?
+                        .read(),
+                ))),
+                VMValuePointer::Broadcast { name, .. } => {
+                    Ok(RichValue::Broadcast(name.to_string()))
                 }
-                VMValuePointer::Broadcast { name, .. } => Ok(name.to_string()),
             },
-            VMEvaluable::Block(b) => rich_value_to_string(&eval_exp(b, state)?),
+            VMEvaluable::Block(b) => eval_exp(b, state),
             VMEvaluable::Default => Ok("".into()),
+        }?;
+        if let Some(tracer) = &state.runtime.tracer {
+            tracer.argument(argname, &value);
         }
+        Ok(value)
+    }
+
+    pub fn argstr(&self, argname: &str, state: &VMState) -> Result<String, ScratchError> {
+        Ok(cast::to_string(&self.argvalue(argname, state)?))
     }
 
     pub fn argfloat(&self, argname: &str, state: &VMState) -> Result<f64, ScratchError> {
-        match self.argraw(argname).ok_or(ScratchError::not_found(format!("argument '{argname}' not found"), format!("lookup '{argname}'")))? {
-            VMEvaluable::Bare(rv) => rich_value_to_f64(rv),
-            VMEvaluable::Field(f) => f
-                .display_value
-                .parse::<f64>()
-                .map_err(|e| ScratchError::type_error(format!("cannot convert argument to float: {e}"), format!("fetching field '{argname}'"))),
-            VMEvaluable::Pointer(v) => match &v {
-                VMValuePointer::Variable { id, name } => {
-                    let pv: PrimitiveValue;
-                    if let Some(var) = state.local_state.read().variables.get(id) {
-                        pv = var.read().clone();
-                    } else {
-                        pv = state.global_state
-                            .read()
-                            .variables
-                            .get(id)
-                            .ok_or(ScratchError::not_found(format!("variable '{name}' not found"), format!("fetching '{argname}' (id={id}) as string")))?
-                            .read()
-                            .clone()
-                    }
-                    pv.try_into().map_err(|e| ScratchError::type_error(e, format!("fetching argument '{argname}' (id={id})")))
-                }
-                VMValuePointer::List { id, .. } => Err(ScratchError::type_error(
-                    format!("lists cannot be converted into float. Argument '{argname}' points to a list, you may have accidentally dragged a list reporter inside a block slot that only accept strings."),
-                    format!("fetching argument '{argname}' (id={id}) as string")
-                )),
-                VMValuePointer::Broadcast { .. } => Err(ScratchError::type_error(
-                    format!("broadcasts cannot be converted into float. Argument '{argname}' points to a broadcast, you may have accidentally dragged a broadcast inside a block slot that only accept strings."),
-                    format!("fetching argument '{argname}' as string")
-                )),
-            },
-            VMEvaluable::Block(b) => rich_value_to_f64(&eval_exp(b, state)?),
-            VMEvaluable::Default => Ok(0.0.into()),
-        }
+        Ok(cast::to_number(&self.argvalue(argname, state)?))
     }
 
     pub fn argbool(&self, argname: &str, state: &VMState) -> Result<bool, ScratchError> {
-        match self.argraw(argname).ok_or(ScratchError::not_found(format!("argument '{argname}' not found"), format!("lookup '{argname}'")))? {
-            VMEvaluable::Bare(rv) => rich_value_to_bool(rv),
-            VMEvaluable::Field(f) => if f
-                .display_value == "true" {
-                    Ok(true)
-                } else if f.display_value == "false" {
-                    Ok(false)
-                } else {
-                    Err(ScratchError::type_error(format!("cannot convert argument to float {argname}='{:#?}'", f.display_value), format!("fetching field '{argname}'")))
-                }
-            VMEvaluable::Pointer(v) => match &v {
-                VMValuePointer::Variable { id, name } => {
-                    let pv: PrimitiveValue;
-                    if let Some(var) = state.local_state.read().variables.get(id) {
-                        pv = var.read().clone();
-                    } else {
-                        pv = state.global_state
-                            .read()
-                            .variables
-                            .get(id)
-                            .ok_or(ScratchError::not_found(format!("variable '{name}' not found"), format!("fetching '{argname}' (id={id}) as bool")))?
-                            .read()
-                            .clone()
-                    }
-                    pv.try_into().map_err(|e| ScratchError::type_error(e, format!("fetching argument '{argname}' (id={id})")))
-                }
-                VMValuePointer::List { id, .. } => Err(ScratchError::type_error(
-                    format!("lists cannot be converted into float. Argument '{argname}' points to a list, you may have accidentally dragged a list reporter inside a block slot that only accept strings."),
-                    format!("fetching argument '{argname}' (id={id}) as string")
-                )),
-                VMValuePointer::Broadcast { .. } => Err(ScratchError::type_error(
-                    format!("broadcasts cannot be converted into float. Argument '{argname}' points to a broadcast, you may have accidentally dragged a broadcast inside a block slot that only accept strings."),
-                    format!("fetching argument '{argname}' as string")
-                )),
-            },
-            VMEvaluable::Block(b) => rich_value_to_bool(&eval_exp(b, state)?),
-            VMEvaluable::Default => Ok(false.into()),
-        }
+        Ok(cast::to_boolean(&self.argvalue(argname, state)?))
     }
 
     pub fn argptr(&self, argname: &str) -> Result<VMValuePointer, ScratchError> {
@@ -226,6 +164,22 @@ impl StackExpression {
         })
     }
 
+    /// argvalue with nice error.
+    /// utility function.
+    pub fn sargvalue(
+        &self,
+        argname: &str,
+        state: &VMState,
+        exp: &StackExpression,
+    ) -> Result<RichValue, ScratchError> {
+        self.argvalue(argname, state).map_err(|e| {
+            e.push_not_found(
+                format!("required argument {argname} not found"),
+                format!("block {:?} (id={})", exp.opcode, exp.original_block.obj_id),
+            )
+        })
+    }
+
     /// argraw with nice error.
     /// utility function.
     pub fn sargraw(
@@ -248,6 +202,21 @@ impl StackExpression {
 
     /// argstr with nice error.
     /// utility function.
+    /// Evaluates an argument like [`VMEvaluable::eval`], so that fields pointing to
+    /// a variable evaluate to its value.
+    pub fn sargeval(
+        &self,
+        argname: &str,
+        state: &VMState,
+        exp: &StackExpression,
+    ) -> Result<RichValue, ScratchError> {
+        let value = self.sargraw(argname, exp)?.eval(state)?;
+        if let Some(tracer) = &state.runtime.tracer {
+            tracer.argument(argname, &value);
+        }
+        Ok(value)
+    }
+
     pub fn sargstr(
         &self,
         argname: &str,
@@ -304,16 +273,16 @@ impl VMValuePointer {
                     .read()
                     .variables
                     .get(id)
-                    .ok_or(ScratchError::not_found(
-                        format!("value pointed to by variable pointer '{name}' not found"),
-                        format!("resolving variable pointer '{name}' (id={id})"),
-                    ))?
+                    .ok_or_else(|| {
+                        ScratchError::not_found(
+                            format!("value pointed to by variable pointer '{name}' not found"),
+                            format!("resolving variable pointer '{name}' (id={id})"),
+                        )
+                    })?
                     .read()
                     .clone()
             }
-            return pv.try_into().map_err(|e| {
-                ScratchError::type_error(e, format!("fetching argument '{name}' (id={id})"))
-            });
+            return Ok(pv);
         }
         Err(ScratchError::type_error(format!("tried to resolve pointer into var, but it does not point to a variable (it pointed to a {self:?})"), format!("resolving into var {self:#?}")))
     }
@@ -332,11 +301,17 @@ impl VMValuePointer {
                     .write()
                     .variables
                     .get_mut(id)
-                    .ok_or(ScratchError::not_found(
-                        format!("value pointed to by variable pointer '{name}' not found"),
-                        format!("resolving variable pointer '{name}' (id={id})"),
-                    ))?
+                    .ok_or_else(|| {
+                        ScratchError::not_found(
+                            format!("value pointed to by variable pointer '{name}' not found"),
+                            format!("resolving variable pointer '{name}' (id={id})"),
+                        )
+                    })?
                     .write() = value.clone();
+                cloud::publish(&state.global_state, *id, &value)?;
+            }
+            if let Some(tracer) = &state.runtime.tracer {
+                tracer.variable(name, &value);
             }
             return Ok(value);
         }
@@ -393,10 +368,12 @@ impl VMEvaluable {
             VMEvaluable::Bare(rv) => Ok(rv.clone()),
             VMEvaluable::Field(f) => Ok(match &f.pointer {
                 None => RichValue::String(f.display_value.clone()),
-                Some(p) => {
-                    p.resolve_var(state).unwrap_or(
-                        PrimitiveValue::String(p.resolve_broadcast(state)?)
-                    ).into()
+                Some(p @ VMValuePointer::Variable { .. }) => p.resolve_var(state)?.into(),
+                Some(p @ VMValuePointer::List { .. }) => {
+                    RichValue::String(list_contents(&p.resolve_list(state)?.read()))
+                }
+                Some(p @ VMValuePointer::Broadcast { .. }) => {
+                    RichValue::Broadcast(p.resolve_broadcast(state)?)
                 }
             }),
             VMEvaluable::Pointer(v) => match &v {
@@ -405,21 +382,26 @@ impl VMEvaluable {
                     if let Some(var) = state.local_state.read().variables.get(id) {
                         pv = var.read().clone();
                     } else {
-                        pv = state.global_state
+                        pv = state
+                            .global_state
                             .read()
                             .variables
                             .get(id)
-                            .ok_or(ScratchError::not_found(format!("variable {name} not found"), format!("fetching '{self:?}' (id={id})")))?
+                            .ok_or(ScratchError::not_found(
+                                format!("variable {name} not found"),
+                                format!("fetching '{self:?}' (id={id})"),
+                            ))?
                             .read()
                             .clone()
                     }
                     Ok(pv.into())
                 }
-                VMValuePointer::List { id, .. } => Err(ScratchError::type_error(
-                    format!("lists cannot be converted into strings. '{self:?}' points to a list, you may have accidentally dragged a list reporter inside a block slot that only accept strings."),
-                    format!("fetching '{self:?}' (id={id}) as string")
-                )),
-                VMValuePointer::Broadcast { name, ..} => Ok(RichValue::Broadcast(name.to_string())),
+                VMValuePointer::List { .. } => Ok(RichValue::String(list_contents(
+                    &v.resolve_list(state)?.read(),
+                ))),
+                VMValuePointer::Broadcast { name, .. } => {
+                    Ok(RichValue::Broadcast(name.to_string()))
+                }
             },
             VMEvaluable::Block(b) => eval_exp(b, state),
             VMEvaluable::Default => Ok("".into()),
@@ -476,15 +458,25 @@ pub fn fetch_dependencies(
                 pointer: match ik.as_str() {
                     "VARIABLE" => Some(VMValuePointer::Variable {
                         name: value.to_string(),
-                        id: *local_var_numid_map.get(&value_id.clone().expect("Malformed field array, field does not have a varid reference")).unwrap_or(global_var_numid_map.get(&value_id.clone().unwrap()).expect("varid referenced by field not found")),
+                        id: field_numid(value_id, local_var_numid_map, global_var_numid_map, "var"),
                     }),
                     "LIST" => Some(VMValuePointer::List {
                         name: value.to_string(),
-                        id: *local_list_numid_map.get(&value_id.clone().expect("Malformed field array, field does not have a listid reference")).unwrap_or(global_list_numid_map.get(&value_id.clone().unwrap()).expect("listid referenced by field not found")),
+                        id: field_numid(
+                            value_id,
+                            local_list_numid_map,
+                            global_list_numid_map,
+                            "list",
+                        ),
                     }),
                     "BROADCAST_OPTION" => Some(VMValuePointer::Broadcast {
                         name: value.to_string(),
-                        id: *local_broadcast_numid_map.get(&value_id.clone().expect("Malformed field array, field does not have a broadcastid reference")).unwrap_or(global_broadcast_numid_map.get(&value_id.clone().unwrap()).expect("broadcastid referenced by field not found")),
+                        id: field_numid(
+                            value_id,
+                            local_broadcast_numid_map,
+                            global_broadcast_numid_map,
+                            "broadcast",
+                        ),
                     }),
                     _ => None,
                 },
@@ -494,6 +486,23 @@ pub fn fetch_dependencies(
     output
 }
 
+/// Resolves the ID a field refers to, looking at the target's own variables,
+/// lists or broadcasts before the stage's.
+fn field_numid(
+    value_id: &Option<String>,
+    local_numid_map: &HashMap<String, usize>,
+    global_numid_map: &HashMap<String, usize>,
+    kind: &str,
+) -> usize {
+    let value_id = value_id.as_ref().unwrap_or_else(|| {
+        panic!("Malformed field array, field does not have a {kind}id reference")
+    });
+    *local_numid_map
+        .get(value_id)
+        .or_else(|| global_numid_map.get(value_id))
+        .unwrap_or_else(|| panic!("{kind}id referenced by field not found"))
+}
+
 impl VMEvaluable {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -512,21 +521,19 @@ impl VMEvaluable {
                 ShadowValue::Pointer(ValuePointer::List { name, id: str_id }) => {
                     Self::Pointer(VMValuePointer::List {
                         name,
-                        id: *local_list_numid_map.get(&str_id).unwrap_or(
-                            global_list_numid_map
-                                .get(&str_id)
-                                .expect("listid referenced by pointer not found"),
-                        ),
+                        id: *local_list_numid_map
+                            .get(&str_id)
+                            .or_else(|| global_list_numid_map.get(&str_id))
+                            .expect("listid referenced by pointer not found"),
                     })
                 }
                 ShadowValue::Pointer(ValuePointer::Variable { name, id: str_id }) => {
                     Self::Pointer(VMValuePointer::Variable {
                         name,
-                        id: *local_var_numid_map.get(&str_id).unwrap_or(
-                            global_var_numid_map
-                                .get(&str_id)
-                                .expect("varid referenced by pointer not found"),
-                        ),
+                        id: *local_var_numid_map
+                            .get(&str_id)
+                            .or_else(|| global_var_numid_map.get(&str_id))
+                            .expect("varid referenced by pointer not found"),
                     })
                 }
                 ShadowValue::Block(b) => {
@@ -0,0 +1,126 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use scratch_ast::errors::ScratchError;
+
+use crate::vm::{
+    cloud::{CloudListener, CloudProvider},
+    ScratchResult,
+};
+
+/// How often the file is written at most while the project runs. Changes in
+/// between are written together with the next one, or when the provider closes.
+const SAVE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Keeps cloud variables in a JSON file, so that they survive between runs.
+/// The file holds a single object mapping variable names to their values.
+#[derive(Debug)]
+pub struct FileProvider {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    values: HashMap<String, String>,
+    /// When the file was last written.
+    saved: Option<Instant>,
+    /// Whether the values changed since.
+    dirty: bool,
+}
+
+impl FileProvider {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn load(&self) -> Result<HashMap<String, String>, ScratchError> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+        let location = format!("loading cloud variables from {}", self.path.display());
+        let content = std::fs::read_to_string(&self.path)
+            .map_err(|e| ScratchError::internal(e, &location))?;
+        let object: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&content).map_err(|e| ScratchError::syntax_error(e, &location))?;
+        Ok(object
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    serde_json::Value::String(s) => s,
+                    v => v.to_string(),
+                };
+                (name, value)
+            })
+            .collect())
+    }
+
+    fn save(&self, values: &HashMap<String, String>) -> ScratchResult {
+        let location = format!("saving cloud variables to {}", self.path.display());
+        let object: serde_json::Map<String, serde_json::Value> = values
+            .iter()
+            .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+            .collect();
+        let content = serde_json::to_string_pretty(&object)
+            .map_err(|e| ScratchError::internal(e, &location))?;
+        // Write to a sibling file first so that a crash never leaves a truncated file behind.
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        std::fs::write(&temp, content).map_err(|e| ScratchError::internal(e, &location))?;
+        std::fs::rename(&temp, &self.path).map_err(|e| ScratchError::internal(e, &location))
+    }
+
+    /// Writes the values after a change, unless the file was written less than
+    /// [`SAVE_INTERVAL`] ago.
+    fn changed(&self, state: &mut State) -> ScratchResult {
+        state.dirty = true;
+        if state
+            .saved
+            .is_some_and(|saved| saved.elapsed() < SAVE_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.save(&state.values)?;
+        state.saved = Some(Instant::now());
+        state.dirty = false;
+        Ok(())
+    }
+}
+
+impl CloudProvider for FileProvider {
+    fn connect(&self, _listener: CloudListener) -> Result<HashMap<String, String>, ScratchError> {
+        let values = self.load()?;
+        self.state.lock().values = values.clone();
+        Ok(values)
+    }
+
+    fn set(&self, name: &str, value: &str) -> ScratchResult {
+        let mut state = self.state.lock();
+        state.values.insert(name.to_string(), value.to_string());
+        self.changed(&mut state)
+    }
+
+    fn create(&self, name: &str, value: &str) -> ScratchResult {
+        self.set(name, value)
+    }
+
+    fn delete(&self, name: &str) -> ScratchResult {
+        let mut state = self.state.lock();
+        state.values.remove(name);
+        self.changed(&mut state)
+    }
+
+    fn close(&self) -> ScratchResult {
+        let mut state = self.state.lock();
+        self.save(&state.values)?;
+        state.dirty = false;
+        Ok(())
+    }
+}
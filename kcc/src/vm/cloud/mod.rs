@@ -0,0 +1,137 @@
+use std::{fmt::Debug, sync::Arc};
+
+use hashbrown::HashMap;
+use log::{debug, warn};
+use parking_lot::RwLock;
+use scratch_ast::{errors::ScratchError, model::PrimitiveValue};
+
+use crate::vm::{internals::VMGlobalState, ScratchResult};
+
+pub mod file;
+pub mod server;
+pub mod websocket;
+
+/// The prefix of every cloud variable name.
+pub const CLOUD_MARKER: char = '☁';
+/// Longest value, in characters, accepted by the Scratch cloud server.
+pub const MAX_VALUE_LENGTH: usize = 256;
+
+/// Called by a provider whenever another party changes a cloud variable.
+/// Takes the name of the variable and its new value.
+pub type CloudListener = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// A place where cloud variables are stored.
+pub trait CloudProvider: Debug + Send + Sync {
+    /// Starts the provider, returning the values it currently knows about.
+    /// `listener` is invoked for every change made by someone else afterwards.
+    fn connect(&self, listener: CloudListener) -> Result<HashMap<String, String>, ScratchError>;
+    fn set(&self, name: &str, value: &str) -> ScratchResult;
+    fn create(&self, name: &str, value: &str) -> ScratchResult;
+    fn delete(&self, name: &str) -> ScratchResult;
+    /// Flushes pending changes and disconnects.
+    fn close(&self) -> ScratchResult;
+}
+
+pub fn is_cloud_name(name: &str) -> bool {
+    name.starts_with(CLOUD_MARKER)
+}
+
+/// Checks that `value` can be stored in a cloud variable:
+/// it must be a number and be at most [`MAX_VALUE_LENGTH`] characters long.
+pub fn validate(name: &str, value: &str) -> ScratchResult {
+    if value.chars().count() > MAX_VALUE_LENGTH {
+        return Err(ScratchError::type_error(
+            format!("cloud variables cannot hold more than {MAX_VALUE_LENGTH} characters"),
+            format!("setting cloud variable '{name}'"),
+        ));
+    }
+    if value.is_empty()
+        || !value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'))
+        || value.parse::<f64>().is_err()
+    {
+        return Err(ScratchError::type_error(
+            format!("cloud variables can only hold numbers, got {value:?}"),
+            format!("setting cloud variable '{name}'"),
+        ));
+    }
+    Ok(())
+}
+
+fn parse_value(value: &str) -> PrimitiveValue {
+    match value.parse::<f64>() {
+        Ok(n) => PrimitiveValue::Number(n),
+        Err(_) => PrimitiveValue::String(value.to_string()),
+    }
+}
+
+/// Connects the cloud provider of `global_state`, if any.
+/// Values stored by the provider replace the ones saved in the project,
+/// and cloud variables the provider does not know about are created.
+pub fn attach(global_state: &Arc<RwLock<VMGlobalState>>) -> ScratchResult {
+    let Some(provider) = global_state.read().cloud.clone() else {
+        return Ok(());
+    };
+    let weak = Arc::downgrade(global_state);
+    let listener: CloudListener = Arc::new(move |name, value| {
+        let Some(gs) = weak.upgrade() else {
+            return;
+        };
+        let gs = gs.read();
+        match gs.cloud_names.iter().find(|(_, n)| n.as_str() == name) {
+            Some((id, _)) => {
+                debug!("cloud variable {name} changed remotely to {value}");
+                if let Some(var) = gs.variables.get(id) {
+                    *var.write() = parse_value(value);
+                }
+            }
+            None => debug!("ignoring update of unknown cloud variable {name}"),
+        }
+    });
+
+    let remote = provider.connect(listener)?;
+    let gs = global_state.read();
+    for (id, name) in gs.cloud_names.iter() {
+        let Some(var) = gs.variables.get(id) else {
+            continue;
+        };
+        match remote.get(name) {
+            Some(value) => *var.write() = parse_value(value),
+            None => {
+                let value: String = var.read().clone().into();
+                provider.create(name, &value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends the new value of variable `id` to the cloud provider,
+/// provided that the variable is a cloud variable.
+/// Values that cannot be stored in the cloud only change locally,
+/// just like in the online editor.
+pub fn publish(
+    global_state: &RwLock<VMGlobalState>,
+    id: usize,
+    value: &PrimitiveValue,
+) -> ScratchResult {
+    let gs = global_state.read();
+    let (Some(provider), Some(name)) = (&gs.cloud, gs.cloud_names.get(&id)) else {
+        return Ok(());
+    };
+    let value: String = value.clone().into();
+    if let Err(e) = validate(name, &value) {
+        warn!("not sending {name} to the cloud: {e}");
+        return Ok(());
+    }
+    provider.set(name, &value)
+}
+
+/// Disconnects the cloud provider of `global_state`, if any.
+pub fn detach(global_state: &RwLock<VMGlobalState>) -> ScratchResult {
+    match &global_state.read().cloud {
+        Some(provider) => provider.close(),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use hashbrown::HashMap;
+use log::{debug, warn};
+use parking_lot::Mutex;
+use tungstenite::{Message, WebSocket};
+
+use crate::vm::cloud::{
+    is_cloud_name, validate,
+    websocket::{read_frame, CloudMessage, POLL_INTERVAL},
+};
+
+static CLIENT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The variables of one project, and everyone connected to it.
+#[derive(Debug, Default)]
+struct Room {
+    values: HashMap<String, String>,
+    clients: HashMap<usize, Sender<String>>,
+}
+
+impl Room {
+    fn broadcast(&mut self, from: usize, message: &CloudMessage) {
+        let encoded = message.encode();
+        // Clients that hung up are dropped here.
+        self.clients
+            .retain(|id, client| *id == from || client.send(encoded.clone()).is_ok());
+    }
+}
+
+type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+
+/// Serves the Scratch cloud protocol on `listener` from a background thread.
+pub fn spawn(listener: TcpListener) -> JoinHandle<()> {
+    let rooms: Rooms = Arc::new(Mutex::new(HashMap::new()));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let rooms = Arc::clone(&rooms);
+                    std::thread::spawn(move || serve_client(stream, rooms));
+                }
+                Err(e) => warn!("cloud server failed to accept a connection: {e}"),
+            }
+        }
+    })
+}
+
+fn serve_client(stream: TcpStream, rooms: Rooms) {
+    let id = CLIENT_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("cloud client {id} failed the websocket handshake: {e}");
+            return;
+        }
+    };
+    if let Err(e) = socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)) {
+        warn!("cannot configure the socket of cloud client {id}: {e}");
+        return;
+    }
+    let (sender, receiver) = mpsc::channel();
+    let mut project: Option<String> = None;
+
+    if let Err(e) = run_session(id, &mut socket, &rooms, &sender, &receiver, &mut project) {
+        debug!("cloud client {id} disconnected: {e}");
+    }
+    if let Some(project) = project {
+        if let Some(room) = rooms.lock().get_mut(&project) {
+            room.clients.remove(&id);
+        }
+    }
+}
+
+fn run_session(
+    id: usize,
+    socket: &mut WebSocket<TcpStream>,
+    rooms: &Rooms,
+    sender: &Sender<String>,
+    receiver: &Receiver<String>,
+    project: &mut Option<String>,
+) -> Result<(), Box<tungstenite::Error>> {
+    loop {
+        while let Ok(outgoing) = receiver.try_recv() {
+            socket.send(Message::text(outgoing))?;
+        }
+        let Some(messages) = read_frame(socket)? else {
+            continue;
+        };
+        for message in messages {
+            if let CloudMessage::Handshake { project_id, .. } = &message {
+                let mut rooms = rooms.lock();
+                let room = rooms.entry(project_id.clone()).or_default();
+                room.clients.insert(id, sender.clone());
+                let initial = room
+                    .values
+                    .iter()
+                    .map(|(name, value)| {
+                        CloudMessage::Set {
+                            name: name.clone(),
+                            value: value.clone(),
+                        }
+                        .encode()
+                    })
+                    .collect::<Vec<String>>();
+                if !initial.is_empty() {
+                    sender.send(initial.join("\n")).ok();
+                }
+                *project = Some(project_id.clone());
+                continue;
+            }
+            let Some(project) = project.as_ref() else {
+                warn!("cloud client {id} sent {message:?} before the handshake");
+                continue;
+            };
+            let mut rooms = rooms.lock();
+            let room = rooms.entry(project.clone()).or_default();
+            match &message {
+                CloudMessage::Set { name, value } | CloudMessage::Create { name, value } => {
+                    if !is_cloud_name(name) {
+                        warn!("cloud client {id} tried to set non-cloud variable {name}");
+                        continue;
+                    }
+                    if let Err(e) = validate(name, value) {
+                        warn!("cloud client {id} sent an invalid value: {e}");
+                        continue;
+                    }
+                    room.values.insert(name.clone(), value.clone());
+                    room.broadcast(
+                        id,
+                        &CloudMessage::Set {
+                            name: name.clone(),
+                            value: value.clone(),
+                        },
+                    );
+                }
+                CloudMessage::Delete { name } => {
+                    room.values.remove(name);
+                    room.broadcast(id, &message);
+                }
+                CloudMessage::Rename { name, new_name } => {
+                    if let Some(value) = room.values.remove(name) {
+                        room.values.insert(new_name.clone(), value);
+                    }
+                    room.broadcast(id, &message);
+                }
+                CloudMessage::Handshake { .. } => unreachable!(),
+            }
+        }
+    }
+}
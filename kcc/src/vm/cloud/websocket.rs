@@ -0,0 +1,294 @@
+use std::{
+    io::ErrorKind,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use hashbrown::HashMap;
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use scratch_ast::errors::ScratchError;
+use serde::{Deserialize, Deserializer, Serialize};
+use tungstenite::{handshake::HandshakeError, Message, WebSocket};
+
+use crate::vm::{
+    cloud::{server, CloudListener, CloudProvider},
+    ScratchResult,
+};
+
+/// How often blocked reads give up the socket so that writers can use it.
+pub(super) const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How long to wait for the server to finish sending the initial values.
+const SYNC_QUIET_PERIOD: Duration = Duration::from_millis(200);
+/// How long connecting to the server, and then its WebSocket handshake, may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A message of the Scratch cloud protocol.
+/// Several messages may be sent in one frame, separated by newlines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum CloudMessage {
+    Handshake {
+        user: String,
+        project_id: String,
+    },
+    Set {
+        name: String,
+        #[serde(deserialize_with = "value_as_string")]
+        value: String,
+    },
+    Create {
+        name: String,
+        #[serde(deserialize_with = "value_as_string")]
+        value: String,
+    },
+    Delete {
+        name: String,
+    },
+    Rename {
+        name: String,
+        new_name: String,
+    },
+}
+
+/// The official server sends numbers as either JSON numbers or strings.
+fn value_as_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => s,
+        v => v.to_string(),
+    })
+}
+
+impl CloudMessage {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("cloud messages are always serializable")
+    }
+
+    /// Decodes every message in a frame, skipping the malformed ones.
+    pub fn decode_frame(frame: &str) -> Vec<CloudMessage> {
+        frame
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .filter_map(|l| match serde_json::from_str(l) {
+                Ok(m) => Some(m),
+                Err(e) => {
+                    warn!("ignoring malformed cloud message {l:?}: {e}");
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Reads the next frame from `socket`.
+/// Returns `Ok(None)` if nothing arrived within the read timeout of the socket.
+pub(super) fn read_frame(
+    socket: &mut WebSocket<TcpStream>,
+) -> Result<Option<Vec<CloudMessage>>, Box<tungstenite::Error>> {
+    match socket.read() {
+        Ok(Message::Text(t)) => Ok(Some(CloudMessage::decode_frame(t.as_str()))),
+        Ok(_) => Ok(Some(Vec::new())),
+        Err(tungstenite::Error::Io(e))
+            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Connects to the first address of `address` (`host:port`) that answers
+/// within [`CONNECT_TIMEOUT`].
+fn connect_timeout(address: &str) -> std::io::Result<TcpStream> {
+    let mut error = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no such address")))
+}
+
+/// Shares cloud variables with other projects through a Scratch cloud server.
+#[derive(Debug)]
+pub struct WebSocketProvider {
+    address: String,
+    user: String,
+    project_id: String,
+    socket: Arc<Mutex<Option<WebSocket<TcpStream>>>>,
+    running: Arc<AtomicBool>,
+    reader: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WebSocketProvider {
+    /// Prepares a connection to the cloud server listening on `address` (`host:port`).
+    /// Projects only see the variables of other projects with the same `project_id`.
+    pub fn new<T: ToString, Q: ToString>(address: T, project_id: Q) -> Self {
+        Self {
+            address: address.to_string(),
+            user: std::env::var("USER").unwrap_or_else(|_| "kcc".to_string()),
+            project_id: project_id.to_string(),
+            socket: Arc::new(Mutex::new(None)),
+            running: Arc::new(AtomicBool::new(false)),
+            reader: Mutex::new(None),
+        }
+    }
+
+    /// Starts a cloud server on `address` in the background, unless another
+    /// `kcc` instance already did, and prepares a connection to it.
+    /// The server lives as long as the process that started it.
+    pub fn host_or_join<T: ToString, Q: ToString>(
+        address: T,
+        project_id: Q,
+    ) -> Result<Self, ScratchError> {
+        let address = address.to_string();
+        match TcpListener::bind(&address) {
+            Ok(listener) => {
+                info!("hosting cloud server on {address}");
+                server::spawn(listener);
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                info!("joining the cloud server already running on {address}");
+            }
+            Err(e) => {
+                return Err(ScratchError::internal(
+                    e,
+                    format!("starting cloud server on {address}"),
+                ))
+            }
+        }
+        Ok(Self::new(address, project_id))
+    }
+
+    fn send(&self, message: CloudMessage) -> ScratchResult {
+        let mut guard = self.socket.lock();
+        let socket = guard.as_mut().ok_or(ScratchError::internal(
+            "not connected to the cloud server",
+            format!("sending {message:?}"),
+        ))?;
+        socket
+            .send(Message::text(message.encode()))
+            .map_err(|e| ScratchError::internal(e, format!("sending {message:?}")))
+    }
+}
+
+impl CloudProvider for WebSocketProvider {
+    fn connect(&self, listener: CloudListener) -> Result<HashMap<String, String>, ScratchError> {
+        let location = format!("connecting to cloud server {}", self.address);
+        let stream =
+            connect_timeout(&self.address).map_err(|e| ScratchError::internal(e, &location))?;
+        stream
+            .set_read_timeout(Some(CONNECT_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(CONNECT_TIMEOUT)))
+            .map_err(|e| ScratchError::internal(e, &location))?;
+        let (mut socket, _) = tungstenite::client(format!("ws://{}/", self.address), stream)
+            .map_err(|e| match e {
+                // A blocking stream is only interrupted by its timeouts.
+                HandshakeError::Interrupted(_) => ScratchError::internal(
+                    format!("no handshake within {CONNECT_TIMEOUT:?}"),
+                    &location,
+                ),
+                HandshakeError::Failure(e) => ScratchError::internal(e, &location),
+            })?;
+        let stream = socket.get_ref();
+        stream
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .and_then(|()| stream.set_write_timeout(None))
+            .map_err(|e| ScratchError::internal(e, &location))?;
+        socket
+            .send(Message::text(
+                CloudMessage::Handshake {
+                    user: self.user.clone(),
+                    project_id: self.project_id.clone(),
+                }
+                .encode(),
+            ))
+            .map_err(|e| ScratchError::internal(e, &location))?;
+
+        // The server answers the handshake with every value it knows, but never
+        // says when it is done, so wait until it stays quiet for a while.
+        let mut values = HashMap::new();
+        let mut last_message = Instant::now();
+        while last_message.elapsed() < SYNC_QUIET_PERIOD {
+            match read_frame(&mut socket).map_err(|e| ScratchError::internal(e, &location))? {
+                Some(messages) => {
+                    last_message = Instant::now();
+                    for m in messages {
+                        if let CloudMessage::Set { name, value } = m {
+                            values.insert(name, value);
+                        }
+                    }
+                }
+                None => continue,
+            }
+        }
+        debug!("received {} cloud variables from the server", values.len());
+
+        *self.socket.lock() = Some(socket);
+        self.running.store(true, Ordering::Release);
+        let socket = Arc::clone(&self.socket);
+        let running = Arc::clone(&self.running);
+        *self.reader.lock() = Some(std::thread::spawn(move || {
+            while running.load(Ordering::Acquire) {
+                let frame = match socket.lock().as_mut() {
+                    Some(s) => read_frame(s),
+                    None => break,
+                };
+                match frame {
+                    Ok(Some(messages)) => {
+                        for m in messages {
+                            if let CloudMessage::Set { name, value } = m {
+                                listener(&name, &value);
+                            }
+                        }
+                    }
+                    // Let writers grab the socket before reading again.
+                    Ok(None) => std::thread::sleep(POLL_INTERVAL / 4),
+                    Err(e) => {
+                        warn!("lost connection to the cloud server: {e}");
+                        break;
+                    }
+                }
+            }
+        }));
+        Ok(values)
+    }
+
+    fn set(&self, name: &str, value: &str) -> ScratchResult {
+        self.send(CloudMessage::Set {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    fn create(&self, name: &str, value: &str) -> ScratchResult {
+        self.send(CloudMessage::Create {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    fn delete(&self, name: &str) -> ScratchResult {
+        self.send(CloudMessage::Delete {
+            name: name.to_string(),
+        })
+    }
+
+    fn close(&self) -> ScratchResult {
+        self.running.store(false, Ordering::Release);
+        if let Some(reader) = self.reader.lock().take() {
+            let _ = reader.join();
+        }
+        if let Some(mut socket) = self.socket.lock().take() {
+            let _ = socket.close(None);
+            let _ = socket.flush();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+//! `kcc run --coverage FILE`: records which blocks of a project ran, and which
+//! branches of its `if` and `if else` blocks were taken.
+//!
+//! The report in `FILE` adds up every run written to it, so that running a
+//! project once per test input shows what the inputs left out. It holds, by
+//! target, how often each block ran, how often each `if` ran its blocks or not,
+//! the blocks that never ran, and totals. Blocks count as in the editor: menus
+//! and other shadow blocks do not.
+
+use std::{cell::RefCell, collections::BTreeMap, fmt::Write as _, fs, path::Path};
+
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use scratch_ast::{
+    errors::ScratchError,
+    model::{self, Block, BlockType, Mutation, ShadowValue},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::vm::{intepreter::VMState, internals::StackExpression, profiler::block_name};
+
+thread_local! {
+    /// The target whose script runs on this thread.
+    static TARGET: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// How often an `if` ran its blocks, and how often it did not.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Branches {
+    pub then: u64,
+    #[serde(rename = "else")]
+    pub otherwise: u64,
+}
+
+impl Branches {
+    fn covered(&self) -> u64 {
+        (self.then > 0) as u64 + (self.otherwise > 0) as u64
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Total {
+    pub covered: u64,
+    pub total: u64,
+}
+
+impl Total {
+    fn percent(&self) -> String {
+        match self.total {
+            0 => "-".to_string(),
+            total => format!("{:.1}%", self.covered as f64 * 100.0 / total as f64),
+        }
+    }
+}
+
+/// What ran of a target.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TargetCoverage {
+    pub blocks: Total,
+    /// Each `if` has two branches, whether it has an `else` or not.
+    pub branches: Total,
+    /// How often each block ran, by block ID.
+    pub counts: BTreeMap<String, u64>,
+    /// By block ID of the `if`.
+    pub ifs: BTreeMap<String, Branches>,
+    /// The blocks that never ran.
+    pub uncovered: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Report {
+    pub runs: u64,
+    /// By target name.
+    pub targets: BTreeMap<String, TargetCoverage>,
+}
+
+/// Records what a running project runs.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    report: Mutex<Report>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Notes which target the script about to run on this thread belongs to.
+    pub fn thread(&self, state: &VMState) {
+        let name = state.local_state.read().name.clone();
+        TARGET.with_borrow_mut(|target| *target = name);
+    }
+
+    fn with_target(&self, f: impl FnOnce(&mut TargetCoverage)) {
+        TARGET.with_borrow(|target| {
+            let mut report = self.report.lock();
+            if !report.targets.contains_key(target) {
+                report
+                    .targets
+                    .insert(target.clone(), TargetCoverage::default());
+            }
+            f(report.targets.get_mut(target).unwrap())
+        })
+    }
+
+    pub fn block(&self, block: &StackExpression) {
+        let id = &block.original_block.obj_id;
+        self.with_target(|target| match target.counts.get_mut(id) {
+            Some(count) => *count += 1,
+            None => {
+                target.counts.insert(id.clone(), 1);
+            }
+        });
+    }
+
+    /// Notes whether an `if` ran its blocks (`then`), or its `else` if any.
+    pub fn branch(&self, block: &StackExpression, then: bool) {
+        let id = &block.original_block.obj_id;
+        self.with_target(|target| {
+            let branches = target.ifs.entry(id.clone()).or_default();
+            match then {
+                true => branches.then += 1,
+                false => branches.otherwise += 1,
+            }
+        });
+    }
+
+    /// What this run covered.
+    pub fn report(&self) -> Report {
+        let mut report = self.report.lock().clone();
+        report.runs = 1;
+        report
+    }
+}
+
+fn target_blocks(target: &model::Target) -> (&str, &std::collections::HashMap<String, Block>) {
+    match target {
+        model::Target::Sprite(s) => (&s.name, &s.blocks),
+        model::Target::Stage(s) => (&s.name, &s.blocks),
+    }
+}
+
+/// Whether a block counts: custom block prototypes and shadow blocks never run.
+fn counts(block: &Block) -> bool {
+    !block.shadow && block.block_type != BlockType::ProceduresPrototype
+}
+
+/// The blocks of a target that count, by ID.
+fn blocks(target: &model::Target) -> (&str, HashMap<&str, &Block>) {
+    let (name, blocks) = target_blocks(target);
+    let blocks = blocks
+        .iter()
+        .filter(|(_, b)| counts(b))
+        .map(|(id, b)| (id.as_str(), b))
+        .collect();
+    (name, blocks)
+}
+
+fn is_if(block: &Block) -> bool {
+    matches!(
+        block.block_type,
+        BlockType::ControlIf | BlockType::ControlIfElse
+    )
+}
+
+impl Report {
+    /// Reads a report written by an earlier run.
+    pub fn read(path: &Path) -> Result<Self, ScratchError> {
+        let location = format!("reading coverage from {}", path.display());
+        let text = fs::read_to_string(path).map_err(|e| ScratchError::not_found(e, &location))?;
+        serde_json::from_str(&text).map_err(|e| ScratchError::syntax_error(e, &location))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), ScratchError> {
+        let location = format!("writing coverage to {}", path.display());
+        let json =
+            serde_json::to_string_pretty(self).map_err(|e| ScratchError::internal(e, &location))?;
+        fs::write(path, json + "\n").map_err(|e| ScratchError::internal(e, &location))
+    }
+
+    /// Adds the runs of `other`.
+    pub fn merge(&mut self, other: &Report) {
+        self.runs += other.runs;
+        for (name, other) in other.targets.iter() {
+            let target = self.targets.entry(name.clone()).or_default();
+            for (id, count) in other.counts.iter() {
+                *target.counts.entry(id.clone()).or_default() += count;
+            }
+            for (id, branches) in other.ifs.iter() {
+                let target = target.ifs.entry(id.clone()).or_default();
+                target.then += branches.then;
+                target.otherwise += branches.otherwise;
+            }
+        }
+    }
+
+    /// Fills in the totals and the blocks that never ran, from the project the
+    /// report is about. Blocks the project does not have are forgotten.
+    pub fn complete(&mut self, project: &model::Project) {
+        let mut targets = BTreeMap::new();
+        for target in project.targets.iter() {
+            let (name, blocks) = blocks(target);
+            let mut coverage = self.targets.remove(name).unwrap_or_default();
+            coverage
+                .counts
+                .retain(|id, _| blocks.contains_key(id.as_str()));
+            coverage
+                .ifs
+                .retain(|id, _| blocks.get(id.as_str()).is_some_and(|b| is_if(b)));
+            for (id, _) in blocks.iter().filter(|(_, b)| is_if(b)) {
+                coverage.ifs.entry(id.to_string()).or_default();
+            }
+            let mut uncovered = blocks
+                .keys()
+                .filter(|id| !coverage.counts.contains_key(**id))
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+            uncovered.sort();
+            coverage.blocks = Total {
+                covered: coverage.counts.len() as u64,
+                total: blocks.len() as u64,
+            };
+            coverage.branches = Total {
+                covered: coverage.ifs.values().map(Branches::covered).sum(),
+                total: 2 * coverage.ifs.len() as u64,
+            };
+            coverage.uncovered = uncovered;
+            targets.insert(name.to_string(), coverage);
+        }
+        self.targets = targets;
+    }
+
+    /// A table of how much of each target ran.
+    pub fn summary(&self) -> String {
+        let mut output = String::new();
+        writeln!(
+            output,
+            "{:<20} {:>15} {:>15}",
+            "target", "blocks", "branches"
+        )
+        .unwrap();
+        let row = |t: &Total| format!("{}/{} {:>6}", t.covered, t.total, t.percent());
+        let mut all = (Total::default(), Total::default());
+        for (name, target) in self.targets.iter() {
+            writeln!(
+                output,
+                "{name:<20} {:>15} {:>15}",
+                row(&target.blocks),
+                row(&target.branches)
+            )
+            .unwrap();
+            all.0.covered += target.blocks.covered;
+            all.0.total += target.blocks.total;
+            all.1.covered += target.branches.covered;
+            all.1.total += target.branches.total;
+        }
+        writeln!(
+            output,
+            "{:<20} {:>15} {:>15}",
+            format!("total ({} runs)", self.runs),
+            row(&all.0),
+            row(&all.1)
+        )
+        .unwrap();
+        output
+    }
+}
+
+const ROW: f64 = 24.0;
+const INDENT: f64 = 20.0;
+/// Roughly how wide a character of the monospace labels is.
+const CHAR: f64 = 7.2;
+
+/// Escapes text for SVG.
+fn xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Draws the scripts of a project as they are nested, blocks that ran in green
+/// and blocks that never ran in red. Reporters are drawn under the block using
+/// them, and the branches of an `if` that were never taken are called out.
+struct Drawing<'a> {
+    blocks: &'a std::collections::HashMap<String, Block>,
+    coverage: Option<&'a TargetCoverage>,
+    /// `<rect>` and `<text>` elements.
+    elements: String,
+    y: f64,
+    width: f64,
+}
+
+impl Drawing<'_> {
+    fn text(&mut self, x: f64, label: &str, class: &str) {
+        writeln!(
+            self.elements,
+            r#"<text x="{:.0}" y="{:.0}" class="{class}">{}</text>"#,
+            x + 6.0,
+            self.y + 16.0,
+            xml(label)
+        )
+        .unwrap();
+        self.width = self
+            .width
+            .max(x + 12.0 + label.chars().count() as f64 * CHAR);
+    }
+
+    fn label(&self, id: &str, block: &Block) -> String {
+        let mut label = block_name(block.block_type, id);
+        let proccode = match &block.mutation {
+            Some(Mutation::ProcedureCall(call)) => Some(&call.proccode),
+            _ => self.prototype(block).map(|p| &p.proccode),
+        };
+        if let Some(proccode) = proccode {
+            write!(label, " ({proccode})").unwrap();
+        }
+        if let Some(branches) = self.coverage.and_then(|c| c.ifs.get(id)) {
+            write!(
+                label,
+                "  then {}x, else {}x",
+                branches.then, branches.otherwise
+            )
+            .unwrap();
+        }
+        label
+    }
+
+    /// The prototype of a custom block definition.
+    fn prototype(&self, block: &Block) -> Option<&model::ProcedurePrototype> {
+        let input = block.inputs.get("custom_block")?;
+        let Some(ShadowValue::Block(prototype)) = &input.value else {
+            return None;
+        };
+        match &self.blocks.get(&prototype.id)?.mutation {
+            Some(Mutation::ProcedurePrototype(prototype)) => Some(prototype),
+            _ => None,
+        }
+    }
+
+    fn block(&mut self, id: &str, depth: usize) {
+        let Some(block) = self.blocks.get(id).filter(|b| counts(b)) else {
+            return;
+        };
+        let x = 10.0 + depth as f64 * INDENT;
+        let label = self.label(id, block);
+        let ran = self.coverage.is_some_and(|c| c.counts.contains_key(id));
+        let class = match ran {
+            true => "ran",
+            false => "missed",
+        };
+        let width = 12.0 + label.chars().count() as f64 * CHAR;
+        writeln!(
+            self.elements,
+            r#"<rect x="{x:.0}" y="{:.0}" width="{width:.0}" height="{:.0}" rx="4" class="{class}"/>"#,
+            self.y + 2.0,
+            ROW - 4.0
+        )
+        .unwrap();
+        self.text(x, &label, "label");
+        self.y += ROW;
+        let mut inputs = block.inputs.iter().collect::<Vec<_>>();
+        inputs.sort_by_key(|(name, _)| name.as_str());
+        for (name, input) in inputs.iter() {
+            if let (false, Some(ShadowValue::Block(reporter))) =
+                (name.starts_with("SUBSTACK"), &input.value)
+            {
+                self.block(&reporter.id, depth + 1);
+            }
+        }
+        for name in ["SUBSTACK", "SUBSTACK2"] {
+            let Some(ShadowValue::Block(first)) =
+                block.inputs.get(name).and_then(|i| i.value.as_ref())
+            else {
+                continue;
+            };
+            if name == "SUBSTACK2" {
+                self.text(x, "else", "else");
+                self.y += ROW;
+            }
+            self.stack(&first.id, depth + 1);
+        }
+    }
+
+    fn stack(&mut self, first: &str, depth: usize) {
+        let mut next = Some(first.to_string());
+        while let Some(id) = next {
+            self.block(&id, depth);
+            next = self.blocks.get(&id).and_then(|b| b.next_id.clone());
+        }
+    }
+}
+
+/// An SVG image of every script, see [`Drawing`].
+pub fn svg(project: &model::Project, report: &Report) -> String {
+    let empty = std::collections::HashMap::new();
+    let mut drawing = Drawing {
+        blocks: &empty,
+        coverage: None,
+        elements: String::new(),
+        y: 10.0,
+        width: 400.0,
+    };
+    for target in project.targets.iter() {
+        let (name, blocks) = target_blocks(target);
+        let coverage = report.targets.get(name);
+        let heading = match coverage {
+            Some(c) => format!(
+                "{name}: {}/{} blocks, {}/{} branches",
+                c.blocks.covered, c.blocks.total, c.branches.covered, c.branches.total
+            ),
+            None => name.to_string(),
+        };
+        drawing.text(4.0, &heading, "target");
+        drawing.y += ROW + 4.0;
+        let mut scripts = blocks
+            .iter()
+            .filter(|(_, b)| b.top_level && counts(b))
+            .map(|(id, b)| (b.y.unwrap_or_default(), b.x.unwrap_or_default(), id))
+            .collect::<Vec<_>>();
+        scripts.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        drawing.blocks = blocks;
+        drawing.coverage = coverage;
+        for (_, _, id) in scripts {
+            drawing.stack(id, 0);
+            drawing.y += ROW / 2.0;
+        }
+        drawing.y += ROW;
+    }
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}" font-family="monospace" font-size="12">
+<style>
+.ran {{ fill: #d4f4d4; stroke: #3a9a3a; }}
+.missed {{ fill: #ffd6d6; stroke: #d03030; stroke-width: 2; }}
+.target {{ font-size: 15px; font-weight: bold; }}
+.else {{ font-style: italic; }}
+</style>
+{}</svg>
+"##,
+        drawing.width + 10.0,
+        drawing.y,
+        drawing.elements
+    )
+}
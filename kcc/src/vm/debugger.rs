@@ -0,0 +1,597 @@
+//! `kcc debug`: runs a project with the interpreter, pausing before blocks to
+//! look at and change what its scripts see.
+//!
+//! The interpreter calls [`Debugger::before`] before every block of a stack. A
+//! script that pauses there reads commands from stdin, while the others wait
+//! before their next block. Each script runs on its own thread, so the state of
+//! the paused one, such as its custom block calls, is kept in a thread local.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use parking_lot::{Mutex, RwLock};
+use scratch_ast::{
+    errors::ScratchError,
+    model::{BlockType, PrimitiveValue},
+};
+
+use crate::{
+    cli::describe,
+    optimizer::header,
+    vm::{
+        intepreter::VMState,
+        internals::{Expression, StackExpression, ThreadTrigger, VMEvaluable, VMValuePointer},
+    },
+};
+
+const HELP: &str = "\
+break [SPEC]        breaks on SPEC, or lists the breakpoints. SPEC is one of
+                      block ID, opcode OPCODE, proc NAME or broadcast NAME
+delete N            deletes breakpoint N
+continue            runs until a breakpoint
+step                runs this block, or goes into the custom block it calls
+next                runs this block, with the blocks inside it
+finish              runs until the custom block returns
+where               shows the block about to run
+threads             lists the running scripts and their blocks
+backtrace           lists the custom blocks being run, innermost first
+print [NAME]        shows a variable or list, or all of them
+set NAME = VALUE    sets a variable
+set LIST[I] = VALUE replaces item I of a list
+add VALUE to LIST   adds an item to a list
+remove LIST[I]      deletes item I of a list
+clear LIST          deletes every item of a list
+quit                stops the project, like `stop all`";
+
+/// Where a script pauses.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// Before the block with this ID, or the block whose inputs hold it.
+    Block(String),
+    /// Before every block with this opcode, or whose inputs hold one.
+    Opcode(BlockType),
+    /// Before every call to the custom block with this name, such as `jump %s high`.
+    Proc(String),
+    /// Before the first block of the scripts receiving this broadcast, by lowercase name.
+    Broadcast(String),
+}
+
+impl Breakpoint {
+    /// Parses `block ID`, `opcode OPCODE`, `proc NAME` or `broadcast NAME`.
+    /// Opcodes are named like in Scratch, e.g. `looks_say`.
+    pub fn parse(spec: &str) -> Result<Self, ScratchError> {
+        let location = format!("parsing breakpoint '{spec}'");
+        let (kind, value) = spec.trim().split_once(' ').unwrap_or((spec.trim(), ""));
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(ScratchError::syntax_error(
+                "expected what to break on, such as `opcode looks_say`",
+                location,
+            ));
+        }
+        match kind {
+            "block" => Ok(Self::Block(value.to_string())),
+            "opcode" => serde_json::from_value(value.into())
+                .map(Self::Opcode)
+                .map_err(|_| {
+                    ScratchError::not_found(format!("there is no opcode named {value}"), location)
+                }),
+            "proc" => Ok(Self::Proc(value.to_string())),
+            "broadcast" => Ok(Self::Broadcast(value.to_lowercase())),
+            _ => Err(ScratchError::syntax_error(
+                format!("cannot break on {kind}, only on a block, opcode, proc or broadcast"),
+                location,
+            )),
+        }
+    }
+
+    /// Whether a script pauses before `expression`. `received` is the broadcast
+    /// that started the script, when `expression` is its first block.
+    fn hits(&self, expression: &Expression, received: Option<&str>) -> bool {
+        match self {
+            Self::Block(id) => any_block(expression, &|b| b.original_block.obj_id == *id),
+            Self::Opcode(opcode) => any_block(expression, &|b| b.opcode == *opcode),
+            Self::Proc(name) => match expression {
+                Expression::InvokeCustomBlock { header, .. } => {
+                    header.proccode().is_some_and(|p| p == name)
+                }
+                _ => false,
+            },
+            Self::Broadcast(name) => received == Some(name.as_str()),
+        }
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Block(id) => write!(f, "block {id}"),
+            Self::Opcode(opcode) => match serde_json::to_value(opcode) {
+                Ok(serde_json::Value::String(name)) => write!(f, "opcode {name}"),
+                _ => write!(f, "opcode {opcode:?}"),
+            },
+            Self::Proc(name) => write!(f, "proc {name}"),
+            Self::Broadcast(name) => write!(f, "broadcast {name}"),
+        }
+    }
+}
+
+/// Whether a block or one of the reporters in its inputs, or in the arguments
+/// of the custom block it calls, is `f`.
+fn any_block(expression: &Expression, f: &impl Fn(&StackExpression) -> bool) -> bool {
+    fn reporters(exp: &StackExpression, f: &impl Fn(&StackExpression) -> bool) -> bool {
+        f(exp) || exp.dependencies.values().any(|d| input(d, f))
+    }
+    fn input(value: &VMEvaluable, f: &impl Fn(&StackExpression) -> bool) -> bool {
+        matches!(value, VMEvaluable::Block(b) if reporters(b, f))
+    }
+    let arguments = match expression {
+        Expression::InvokeCustomBlock { arguments, .. } => Some(arguments),
+        _ => None,
+    };
+    reporters(header(expression), f)
+        || arguments
+            .into_iter()
+            .flat_map(|a| a.values())
+            .any(|a| input(a, f))
+}
+
+/// When a script that was told to step pauses again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stepping {
+    /// Before its next block.
+    Into,
+    /// Before its next block no deeper than this in C blocks and custom blocks.
+    Over(usize),
+    /// Before its next block once fewer custom blocks than this are running.
+    Out(usize),
+}
+
+/// What the script that paused does next.
+enum Resume {
+    Continue,
+    Step(Stepping),
+}
+
+/// The part of a running script the debugger keeps track of.
+#[derive(Default)]
+struct Local {
+    /// The number of the script in `threads`.
+    id: usize,
+    /// How many stacks deep the next block is, counting C blocks and custom blocks.
+    depth: usize,
+    /// The custom blocks being run, innermost last.
+    calls: Vec<String>,
+    stepping: Option<Stepping>,
+    /// The broadcast that started the script, until its first block.
+    received: Option<String>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<Local> = RefCell::default();
+}
+
+/// A running script, as listed by `threads`.
+#[derive(Debug)]
+struct ThreadInfo {
+    target: String,
+    script: String,
+    /// The ID and opcode of the block it last reached.
+    block: Option<(String, BlockType)>,
+}
+
+/// Runs `F` when dropped, to leave what was entered.
+pub struct Leave<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> Drop for Leave<F> {
+    fn drop(&mut self) {
+        if let Some(leave) = self.0.take() {
+            leave();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    /// Deleted breakpoints are `None`, so that the others keep their number.
+    breakpoints: Mutex<Vec<Option<Breakpoint>>>,
+    threads: Mutex<BTreeMap<usize, ThreadInfo>>,
+    next_thread: AtomicUsize,
+    /// Held while a script is paused, so that the others wait before their next block.
+    prompt: Mutex<()>,
+    /// Whether the first block to run pauses.
+    pause_at_start: AtomicBool,
+    /// Set once stdin ends, after which nothing pauses anymore.
+    detached: AtomicBool,
+}
+
+impl Debugger {
+    /// A debugger pausing at the breakpoints, or before the first block when there are none.
+    pub fn new(breakpoints: Vec<Breakpoint>) -> Self {
+        Self {
+            pause_at_start: AtomicBool::new(breakpoints.is_empty()),
+            breakpoints: Mutex::new(breakpoints.into_iter().map(Some).collect()),
+            threads: Mutex::new(BTreeMap::new()),
+            next_thread: AtomicUsize::new(1),
+            prompt: Mutex::new(()),
+            detached: AtomicBool::new(false),
+        }
+    }
+
+    /// Registers the script about to run on this thread, until the result is dropped.
+    pub fn thread<'a>(
+        &'a self,
+        state: &VMState,
+        trigger: &ThreadTrigger,
+    ) -> Leave<impl FnOnce() + 'a> {
+        let id = self.next_thread.fetch_add(1, Ordering::Relaxed);
+        self.threads.lock().insert(
+            id,
+            ThreadInfo {
+                target: state.local_state.read().name.clone(),
+                script: trigger.to_string(),
+                block: None,
+            },
+        );
+        let received = match trigger {
+            ThreadTrigger::Broadcast(name) => Some(name.clone()),
+            _ => None,
+        };
+        LOCAL.with_borrow_mut(|local| {
+            *local = Local {
+                id,
+                received,
+                ..Local::default()
+            }
+        });
+        Leave(Some(move || {
+            self.threads.lock().remove(&id);
+        }))
+    }
+
+    /// Enters a stack of blocks, until the result is dropped.
+    pub fn nest(&self) -> Leave<impl FnOnce()> {
+        LOCAL.with_borrow_mut(|local| local.depth += 1);
+        Leave(Some(|| LOCAL.with_borrow_mut(|local| local.depth -= 1)))
+    }
+
+    /// Enters the custom block `call` runs, until the result is dropped.
+    pub fn call(&self, call: &StackExpression) -> Leave<impl FnOnce()> {
+        let name = call.proccode().unwrap_or("?").to_string();
+        LOCAL.with_borrow_mut(|local| local.calls.push(name));
+        Leave(Some(|| {
+            LOCAL.with_borrow_mut(|local| local.calls.pop());
+        }))
+    }
+
+    /// Called before a script runs `expression`: waits while another script is
+    /// paused, then pauses if a breakpoint or a step says so. Returns whether
+    /// the script goes on, which it does not once `quit` stopped the project.
+    pub fn before(&self, expression: &Expression, state: &VMState) -> bool {
+        if self.detached.load(Ordering::Relaxed) {
+            return !state.runtime.is_stopped();
+        }
+        drop(self.prompt.lock());
+        let block = header(expression);
+        let (id, reason) =
+            LOCAL.with_borrow_mut(|local| (local.id, self.reason(expression, local)));
+        if let Some(thread) = self.threads.lock().get_mut(&id) {
+            thread.block = Some((block.original_block.obj_id.clone(), block.opcode));
+        }
+        if let Some(reason) = reason {
+            self.pause(&reason, block, state);
+        }
+        !state.runtime.is_stopped()
+    }
+
+    /// Why the script pauses before `expression`, if it does.
+    fn reason(&self, expression: &Expression, local: &mut Local) -> Option<String> {
+        let received = local.received.take();
+        if self.pause_at_start.swap(false, Ordering::Relaxed) {
+            return Some(" at start".to_string());
+        }
+        let stepped = local.stepping.is_some_and(|stepping| match stepping {
+            Stepping::Into => true,
+            Stepping::Over(depth) => local.depth <= depth,
+            Stepping::Out(calls) => local.calls.len() < calls,
+        });
+        if stepped {
+            return Some(String::new());
+        }
+        let breakpoints = self.breakpoints.lock();
+        breakpoints.iter().enumerate().find_map(|(i, breakpoint)| {
+            let breakpoint = breakpoint.as_ref()?;
+            breakpoint
+                .hits(expression, received.as_deref())
+                .then(|| format!(" at breakpoint {}, {breakpoint}", i + 1))
+        })
+    }
+
+    /// Reads commands until one resumes the script.
+    fn pause(&self, reason: &str, block: &StackExpression, state: &VMState) {
+        let _prompt = self.prompt.lock();
+        let id = LOCAL.with_borrow_mut(|local| {
+            local.stepping = None;
+            local.id
+        });
+        println!(
+            "thread {id} ({}) paused{reason}",
+            state.local_state.read().name
+        );
+        println!("    {block}");
+        let mut stdout = std::io::stdout();
+        loop {
+            print!("(kcc) ");
+            stdout.flush().ok();
+            let mut line = String::new();
+            if !matches!(std::io::stdin().lock().read_line(&mut line), Ok(1..)) {
+                // Without commands, the project runs to the end.
+                println!();
+                self.detached.store(true, Ordering::Relaxed);
+                return;
+            }
+            match self.command(line.trim(), block, state) {
+                Ok(Some(Resume::Continue)) => return,
+                Ok(Some(Resume::Step(stepping))) => {
+                    LOCAL.with_borrow_mut(|local| local.stepping = Some(stepping));
+                    return;
+                }
+                Ok(None) => (),
+                Err(message) => println!("{message}"),
+            }
+        }
+    }
+
+    fn command(
+        &self,
+        line: &str,
+        block: &StackExpression,
+        state: &VMState,
+    ) -> Result<Option<Resume>, String> {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command {
+            "" => (),
+            "help" | "h" => println!("{HELP}"),
+            "break" | "b" if rest.is_empty() => {
+                for (i, breakpoint) in self.breakpoints.lock().iter().enumerate() {
+                    if let Some(breakpoint) = breakpoint {
+                        println!("{}: {breakpoint}", i + 1);
+                    }
+                }
+            }
+            "break" | "b" => {
+                let breakpoint = Breakpoint::parse(rest).map_err(describe)?;
+                let mut breakpoints = self.breakpoints.lock();
+                println!("breakpoint {}: {breakpoint}", breakpoints.len() + 1);
+                breakpoints.push(Some(breakpoint));
+            }
+            "delete" | "d" => {
+                let mut breakpoints = self.breakpoints.lock();
+                let deleted = rest
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| breakpoints.get_mut(n.checked_sub(1)?))
+                    .and_then(Option::take);
+                if deleted.is_none() {
+                    return Err(format!("there is no breakpoint {rest}"));
+                }
+            }
+            "continue" | "c" => return Ok(Some(Resume::Continue)),
+            "step" | "s" => return Ok(Some(Resume::Step(Stepping::Into))),
+            "next" | "n" => {
+                let depth = LOCAL.with_borrow(|local| local.depth);
+                return Ok(Some(Resume::Step(Stepping::Over(depth))));
+            }
+            "finish" | "f" => {
+                let calls = LOCAL.with_borrow(|local| local.calls.len());
+                if calls == 0 {
+                    return Err("not in a custom block".to_string());
+                }
+                return Ok(Some(Resume::Step(Stepping::Out(calls))));
+            }
+            "where" | "w" => println!("    {block}"),
+            "threads" | "t" => {
+                let current = LOCAL.with_borrow(|local| local.id);
+                for (id, thread) in self.threads.lock().iter() {
+                    let mark = if *id == current { '*' } else { ' ' };
+                    let at = match &thread.block {
+                        Some((block, opcode)) => format!("{block}.{opcode:?}"),
+                        None => "not started".to_string(),
+                    };
+                    println!("{mark} {id} {} ({}) at {at}", thread.target, thread.script);
+                }
+            }
+            "backtrace" | "bt" => LOCAL.with_borrow(|local| {
+                for (i, call) in local.calls.iter().rev().enumerate() {
+                    println!("{i}: {call}");
+                }
+                println!("{}: {}", local.calls.len(), self.script(local.id));
+            }),
+            "print" | "p" if rest.is_empty() => print_all(state),
+            "print" | "p" => match (variable(state, rest), list(state, rest)) {
+                (Some((_, value)), _) => println!("{rest} = {}", show(&value)),
+                (None, Some(items)) => println!("{rest} = {}", show_list(&items.read())),
+                (None, None) => return Err(format!("there is no variable or list named {rest}")),
+            },
+            "set" => {
+                let (target, value) = rest.split_once(" = ").ok_or("expected set NAME = VALUE")?;
+                let value = parse_value(value);
+                match item(target) {
+                    Some((name, index)) => {
+                        let items = list(state, name).ok_or(no_list(name))?;
+                        let items = items.read();
+                        let item = index.and_then(|i| items.get(i)).ok_or(no_item(target))?;
+                        *item.write() = value;
+                    }
+                    None => {
+                        let (pointer, _) = variable(state, target)
+                            .ok_or(format!("there is no variable named {target}"))?;
+                        pointer.set_var(state, value).map_err(describe)?;
+                    }
+                }
+            }
+            "add" => {
+                let (value, name) = rest
+                    .rsplit_once(" to ")
+                    .ok_or("expected add VALUE to LIST")?;
+                let items = list(state, name).ok_or(no_list(name))?;
+                items.write().push(RwLock::new(parse_value(value)));
+            }
+            "remove" => {
+                let (name, index) = item(rest).ok_or("expected remove LIST[I]")?;
+                let items = list(state, name).ok_or(no_list(name))?;
+                let mut items = items.write();
+                match index.filter(|i| *i < items.len()) {
+                    Some(i) => drop(items.remove(i)),
+                    None => return Err(no_item(rest)),
+                }
+            }
+            "clear" => list(state, rest).ok_or(no_list(rest))?.write().clear(),
+            "quit" | "q" => {
+                state.runtime.stop_all();
+                return Ok(Some(Resume::Continue));
+            }
+            _ => return Err(format!("unknown command {command}, try help")),
+        }
+        Ok(None)
+    }
+
+    /// The target and hat of a script, e.g. `Sprite1 (when green flag clicked)`.
+    fn script(&self, id: usize) -> String {
+        match self.threads.lock().get(&id) {
+            Some(thread) => format!("{} ({})", thread.target, thread.script),
+            None => "?".to_string(),
+        }
+    }
+}
+
+fn no_list(name: &str) -> String {
+    format!("there is no list named {name}")
+}
+
+fn no_item(item: &str) -> String {
+    format!("there is no item {item}")
+}
+
+/// Splits `LIST[I]` into the list and the index from 0, which is `None` if `I`
+/// is not a number from 1.
+fn item(spec: &str) -> Option<(&str, Option<usize>)> {
+    let (name, index) = spec.strip_suffix(']')?.rsplit_once('[')?;
+    let index = index
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| i.checked_sub(1));
+    Some((name.trim(), index))
+}
+
+/// A value typed at the prompt: a number, a string in double quotes, or else
+/// the text as it is.
+fn parse_value(text: &str) -> PrimitiveValue {
+    let text = text.trim();
+    if let Ok(n) = text.parse::<f64>() {
+        return PrimitiveValue::Number(n);
+    }
+    match serde_json::from_str::<String>(text) {
+        Ok(s) => PrimitiveValue::String(s),
+        Err(_) => PrimitiveValue::String(text.to_string()),
+    }
+}
+
+/// A value as `print` shows it, with strings in double quotes.
+fn show(value: &PrimitiveValue) -> String {
+    match value {
+        PrimitiveValue::String(s) => serde_json::to_string(s).unwrap_or_default(),
+        value => String::from(value.clone()),
+    }
+}
+
+fn show_list(items: &[RwLock<PrimitiveValue>]) -> String {
+    let items = items.iter().map(|i| show(&i.read())).collect::<Vec<_>>();
+    format!("[{}]", items.join(", "))
+}
+
+/// The variable named `name`, of the sprite or else of the stage, with its value.
+fn variable(state: &VMState, name: &str) -> Option<(VMValuePointer, PrimitiveValue)> {
+    let local = state.local_state.read();
+    let global = state.global_state.read();
+    let (id, value) = [
+        (&local.variable_names, &local.variables),
+        (&global.variable_names, &global.variables),
+    ]
+    .into_iter()
+    .find_map(|(names, variables)| {
+        let id = names.iter().find(|(_, n)| *n == name).map(|(id, _)| *id)?;
+        Some((id, variables.get(&id)?.read().clone()))
+    })?;
+    let pointer = VMValuePointer::Variable {
+        name: name.to_string(),
+        id,
+    };
+    Some((pointer, value))
+}
+
+type List = std::sync::Arc<RwLock<Vec<RwLock<PrimitiveValue>>>>;
+
+/// The list named `name`, of the sprite or else of the stage.
+fn list(state: &VMState, name: &str) -> Option<List> {
+    let local = state.local_state.read();
+    let global = state.global_state.read();
+    local
+        .listname_to_numid
+        .get(name)
+        .and_then(|id| local.lists.get(id))
+        .or_else(|| {
+            let id = global.listname_to_numid.get(name)?;
+            global.lists.get(id)
+        })
+        .cloned()
+}
+
+/// Prints every variable and list the paused script can see.
+fn print_all(state: &VMState) {
+    let local = state.local_state.read();
+    let global = state.global_state.read();
+    let scopes = [
+        (
+            "for this sprite only",
+            &local.variable_names,
+            &local.variables,
+            &local.listname_to_numid,
+            &local.lists,
+        ),
+        (
+            "for all sprites",
+            &global.variable_names,
+            &global.variables,
+            &global.listname_to_numid,
+            &global.lists,
+        ),
+    ];
+    for (scope, names, variables, list_names, lists) in scopes {
+        let mut shown = Vec::new();
+        for (id, name) in names.iter() {
+            if let Some(value) = variables.get(id) {
+                shown.push(format!("{name} = {}", show(&value.read())));
+            }
+        }
+        for (name, id) in list_names.iter() {
+            if let Some(items) = lists.get(id) {
+                shown.push(format!("{name} = {}", show_list(&items.read())));
+            }
+        }
+        if shown.is_empty() {
+            continue;
+        }
+        shown.sort();
+        println!("{scope}:");
+        for line in shown {
+            println!("    {line}");
+        }
+    }
+}
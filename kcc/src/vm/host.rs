@@ -0,0 +1,388 @@
+//! What a running project sees of the world outside: where what it says goes,
+//! the answers it gets, its clock, random numbers and how long it may run.
+//! Both the interpreter and the bytecode machine go through it, and `kcc run`
+//! sets it up from its options before the project starts.
+//!
+//! With a virtual clock, the host also decides which of the interpreter's
+//! script threads runs: they take turns in the order they started, one at a
+//! time, like the threads of the bytecode machine, so that runs repeat.
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    io::{BufRead, Write},
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, SystemTimeError, UNIX_EPOCH},
+};
+
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::json;
+
+const START_OF_2000_TIMESTAMP: u64 = 946684800;
+const SECS_IN_A_DAY: f64 = 60.0 * 60.0 * 24.0;
+/// How far a virtual clock moves when the scripts have all taken a turn, as
+/// Scratch draws 30 frames a second.
+const FRAME: f64 = 1.0 / 30.0;
+
+static HOST: OnceLock<Host> = OnceLock::new();
+
+thread_local! {
+    /// The script of this thread in the schedule, see [`Host::enter`].
+    static SCRIPT: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// How what a project says is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Output {
+    /// One line per `say`, `think` and question.
+    #[default]
+    Text,
+    /// One JSON object per line, for programs reading the output.
+    Json,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Seeds `pick random` and random list items, which are random otherwise.
+    pub seed: Option<u64>,
+    /// Stops the project after this many steps, see [`Host::step`].
+    pub max_steps: Option<u64>,
+    /// Makes waits take no time: the clock moves a frame once every script had
+    /// a turn, and jumps to when waits end instead of sleeping.
+    pub virtual_clock: bool,
+    /// Given to `ask and wait` in order, before reading lines from stdin.
+    pub answers: Vec<String>,
+    pub output: Output,
+}
+
+pub struct Host {
+    options: Options,
+    rng: Mutex<StdRng>,
+    steps: AtomicU64,
+    start: Instant,
+    schedule: Mutex<Schedule>,
+    /// Notified whenever the turn goes to another script.
+    turn: Condvar,
+    answers: Mutex<VecDeque<String>>,
+}
+
+/// The scripts taking turns, and the virtual clock they share.
+#[derive(Default)]
+struct Schedule {
+    /// Seconds since the start, when the clock is virtual.
+    clock: f64,
+    scripts: Vec<Script>,
+    /// The next script of the round to get a turn.
+    next: usize,
+    /// The script whose turn it is.
+    running: Option<u64>,
+    /// Whether a script ended its turn ready to go on, so that the clock moves
+    /// a frame at the end of the round.
+    yielded: bool,
+    /// How many scripts were ever admitted, which gives the next one its ID.
+    admitted: u64,
+}
+
+struct Script {
+    id: u64,
+    /// When a sleeping script wakes up.
+    wake: Option<f64>,
+    /// The scripts it waits for, e.g. with `broadcast and wait`.
+    waiting_for: Range<u64>,
+}
+
+impl Schedule {
+    fn ready(&self, script: &Script) -> bool {
+        script.wake.is_none_or(|wake| wake <= self.clock)
+            && !self
+                .scripts
+                .iter()
+                .any(|s| script.waiting_for.contains(&s.id))
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.scripts.iter().position(|s| s.id == id)
+    }
+
+    /// Gives the turn to the next script of the round that can go on. At the
+    /// end of a round, the clock moves a frame if a script is still busy, or
+    /// else jumps to when the first sleeping one wakes up.
+    fn dispatch(&mut self) {
+        self.running = None;
+        loop {
+            while self.next < self.scripts.len() {
+                let i = self.next;
+                self.next += 1;
+                if self.ready(&self.scripts[i]) {
+                    self.scripts[i].wake = None;
+                    self.running = Some(self.scripts[i].id);
+                    return;
+                }
+            }
+            self.next = 0;
+            if std::mem::take(&mut self.yielded) {
+                self.clock += FRAME;
+            } else if !self.scripts.iter().any(|s| self.ready(s)) {
+                let earliest = self.scripts.iter().filter_map(|s| s.wake);
+                match earliest.min_by(f64::total_cmp) {
+                    Some(wake) => self.clock = self.clock.max(wake),
+                    // Every script waits for another, or there are none left.
+                    None => return,
+                }
+            }
+        }
+    }
+}
+
+/// A script in the schedule, which leaves it when this is dropped.
+pub struct Scheduled {
+    id: Option<u64>,
+}
+
+impl Drop for Scheduled {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+        SCRIPT.set(None);
+        let host = get();
+        let mut schedule = host.schedule.lock();
+        if let Some(i) = schedule.position(id) {
+            schedule.scripts.remove(i);
+            if i < schedule.next {
+                schedule.next -= 1;
+            }
+        }
+        if schedule.running == Some(id) {
+            schedule.dispatch();
+        }
+        host.turn.notify_all();
+    }
+}
+
+/// Sets up the host, which must happen before anything uses it.
+pub fn init(options: Options) {
+    if HOST.set(Host::new(options)).is_err() {
+        log::warn!("the host was already set up, ignoring new options");
+    }
+}
+
+/// The host, with default options unless [`init`] set it up.
+pub fn get() -> &'static Host {
+    HOST.get_or_init(|| Host::new(Options::default()))
+}
+
+impl Host {
+    fn new(options: Options) -> Self {
+        let rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        Self {
+            rng: Mutex::new(rng),
+            steps: AtomicU64::new(0),
+            start: Instant::now(),
+            schedule: Mutex::new(Schedule::default()),
+            turn: Condvar::new(),
+            answers: Mutex::new(options.answers.iter().cloned().collect()),
+            options,
+        }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// A random number in `[0, 1)`.
+    pub fn random(&self) -> f64 {
+        self.rng.lock().random()
+    }
+
+    pub fn random_range(&self, range: Range<usize>) -> usize {
+        self.rng.lock().random_range(range)
+    }
+
+    /// Counts a step, and returns false once there have been more than allowed.
+    /// The interpreter counts blocks and loop iterations, the bytecode machine
+    /// counts instructions.
+    pub fn step(&self) -> bool {
+        let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+        self.options.max_steps.is_none_or(|max| steps <= max)
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps.load(Ordering::Relaxed)
+    }
+
+    /// Whether the project ran more steps than allowed.
+    pub fn out_of_steps(&self) -> bool {
+        self.options.max_steps.is_some_and(|max| self.steps() > max)
+    }
+
+    /// Seconds since the project started.
+    pub fn now(&self) -> f64 {
+        match self.options.virtual_clock {
+            true => self.schedule.lock().clock,
+            false => self.start.elapsed().as_secs_f64(),
+        }
+    }
+
+    /// Moves a virtual clock a frame, once every running script had a turn.
+    pub fn tick(&self) {
+        if self.options.virtual_clock {
+            self.schedule.lock().clock += FRAME;
+        }
+    }
+
+    /// Waits until [`Host::now`] is `deadline`. A scheduled script lets the
+    /// others take turns until then, and a virtual clock otherwise jumps there
+    /// at once, unless it is already later.
+    pub fn sleep_until(&self, deadline: f64) {
+        if self.options.virtual_clock {
+            let mut schedule = self.schedule.lock();
+            match SCRIPT.get() {
+                Some(id) => {
+                    if let Some(i) = schedule.position(id) {
+                        schedule.scripts[i].wake = Some(deadline);
+                    }
+                    self.hand_over(schedule, id);
+                }
+                None => schedule.clock = schedule.clock.max(deadline),
+            }
+            return;
+        }
+        let delay = deadline - self.now();
+        if delay > 0.0 {
+            thread::sleep(Duration::from_secs_f64(delay));
+        }
+    }
+
+    /// Adds `count` scripts to the end of the schedule, when the clock is
+    /// virtual, and returns their IDs. Each must [`Host::enter`] it on its own
+    /// thread before it runs.
+    pub fn admit(&self, count: usize) -> Range<u64> {
+        let mut schedule = self.schedule.lock();
+        let first = schedule.admitted;
+        schedule.admitted += count as u64;
+        let ids = first..schedule.admitted;
+        if self.options.virtual_clock {
+            schedule.scripts.extend(ids.clone().map(|id| Script {
+                id,
+                wake: None,
+                waiting_for: 0..0,
+            }));
+            if schedule.running.is_none() {
+                schedule.dispatch();
+                self.turn.notify_all();
+            }
+        }
+        ids
+    }
+
+    /// Waits for the first turn of the admitted script `id` on this thread.
+    pub fn enter(&self, id: u64) -> Scheduled {
+        if !self.options.virtual_clock {
+            return Scheduled { id: None };
+        }
+        SCRIPT.set(Some(id));
+        let mut schedule = self.schedule.lock();
+        while schedule.running != Some(id) {
+            self.turn.wait(&mut schedule);
+        }
+        Scheduled { id: Some(id) }
+    }
+
+    /// Ends the turn of the script of this thread, which goes on after the
+    /// others had theirs.
+    pub fn pass(&self) {
+        let Some(id) = SCRIPT.get() else {
+            return;
+        };
+        let mut schedule = self.schedule.lock();
+        schedule.yielded = true;
+        self.hand_over(schedule, id);
+    }
+
+    /// Lets the other scripts take turns until the scripts `ids` finish.
+    pub fn wait_for(&self, ids: Range<u64>) {
+        let Some(id) = SCRIPT.get() else {
+            return;
+        };
+        let mut schedule = self.schedule.lock();
+        if let Some(i) = schedule.position(id) {
+            schedule.scripts[i].waiting_for = ids;
+        }
+        self.hand_over(schedule, id);
+    }
+
+    /// Gives the turn of the script `id` to the next one, and waits until it
+    /// comes back.
+    fn hand_over(&self, mut schedule: MutexGuard<Schedule>, id: u64) {
+        schedule.dispatch();
+        self.turn.notify_all();
+        while schedule.running != Some(id) {
+            self.turn.wait(&mut schedule);
+        }
+        if let Some(i) = schedule.position(id) {
+            schedule.scripts[i].waiting_for = 0..0;
+        }
+    }
+
+    /// `days since 2000`. A virtual clock starts at midnight on January 1, 2000.
+    pub fn days_since_2000(&self) -> Result<f64, SystemTimeError> {
+        if self.options.virtual_clock {
+            return Ok(self.now() / SECS_IN_A_DAY);
+        }
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_secs(START_OF_2000_TIMESTAMP))?;
+        Ok(since.as_secs_f64() / SECS_IN_A_DAY)
+    }
+
+    fn event(&self, event: &str, text: &str) {
+        match self.options.output {
+            Output::Text => println!("{text}"),
+            Output::Json => println!("{}", json!({ "event": event, "text": text })),
+        }
+    }
+
+    pub fn say(&self, text: &str) {
+        self.event("say", text);
+    }
+
+    pub fn think(&self, text: &str) {
+        self.event("think", text);
+    }
+
+    /// Asks a question, and returns the next answer given in the options, or
+    /// else the next line of stdin.
+    pub fn ask(&self, question: &str) -> std::io::Result<String> {
+        // Only one script can ask at a time.
+        let mut answers = self.answers.lock();
+        if !question.is_empty() || self.options.output == Output::Json {
+            self.event("ask", question);
+        }
+        std::io::stdout().flush().ok();
+        if let Some(answer) = answers.pop_front() {
+            return Ok(answer);
+        }
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer)?;
+        Ok(answer.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Prints how the project ended, when the output is JSON.
+    pub fn end(&self, ending: &str) {
+        if self.options.output == Output::Json {
+            println!(
+                "{}",
+                json!({ "event": "end", "status": ending, "steps": self.steps() })
+            );
+        }
+    }
+}
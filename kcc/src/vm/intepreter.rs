@@ -1,436 +1,955 @@
-use std::{
-    sync::Arc,
-    thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
-
-use log::debug;
-use parking_lot::RwLock;
-use rand::Rng;
-use scratch_ast::{
-    errors::ScratchError,
-    model::{BlockType, RichValue},
-};
-
-use crate::vm::internals::{
-    Expression, StackExpression, ThreadTrigger, VMGlobalState, VMLocalState, VMSourceCode, VMThread,
-};
-
-use super::ScratchResult;
-
-const START_OF_2000_TIMESTAMP: u64 = 946684800;
-const MILISECS_IN_A_DAY: u64 = 1000 * 60 * 60 * 24;
-
-#[derive(Clone, Debug)]
-pub struct VMState {
-    pub source_code: Arc<VMSourceCode>,
-    pub global_state: Arc<RwLock<VMGlobalState>>,
-    pub local_state: Arc<RwLock<VMLocalState>>,
-    pub curent_thread: Arc<RwLock<VMThread>>,
-}
-
-pub fn exec_source(
-    threads: VMSourceCode,
-    global_state: Arc<RwLock<VMGlobalState>>,
-    local_state: Arc<RwLock<VMLocalState>>,
-) -> ScratchResult {
-    std::thread::scope(|scope| {
-        for (trigger, thread) in threads.iter() {
-            if trigger == &ThreadTrigger::GreenFlag {
-                let state = Arc::new(VMState {
-                    global_state: Arc::clone(&global_state),
-                    local_state: Arc::clone(&local_state),
-                    source_code: Arc::new(threads.clone()),
-                    curent_thread: Arc::new(RwLock::new(thread.clone())),
-                });
-                let cs = Arc::clone(&state);
-                scope.spawn(move || exec_thread(scope, &cs));
-            }
-        }
-    });
-
-    Ok(())
-}
-
-pub fn exec_thread(scope: &std::thread::Scope, state: &VMState) -> ScratchResult {
-    for t in state.curent_thread.read().code.iter() {
-        match t {
-            Expression::Stack(s) => {
-                eval_exp(s, &state)?;
-            }
-            Expression::InvokeCustomBlock { target, arguments } => {
-                let mut nthread = state
-                    .source_code
-                    .get(&ThreadTrigger::Mutation(*target))
-                    .ok_or(ScratchError::not_found(
-                        format!("custom block {target} not found"),
-                        format!("triggering custom block {target}"),
-                    ))?
-                    .clone();
-                for (id, val) in arguments {
-                    nthread.custom_block_arguments.insert(
-                        *id,
-                        val.eval(state).expect("unable to evaluate value").into(),
-                    );
-                }
-                exec_thread(
-                    scope,
-                    &VMState {
-                        global_state: Arc::clone(&state.global_state),
-                        local_state: Arc::clone(&state.local_state),
-                        source_code: Arc::clone(&state.source_code.clone()),
-                        curent_thread: Arc::new(RwLock::new(nthread)),
-                    },
-                )?;
-            }
-            _ => todo!(),
-        };
-    }
-
-    Ok(())
-}
-
-#[allow(unused)]
-pub fn eval_exp(exp: &StackExpression, state: &VMState) -> Result<RichValue, ScratchError> {
-    debug!("exec {}", exp);
-    match exp.opcode {
-        BlockType::MotionMoveSteps => todo!(),
-        BlockType::MotionTurnRight => todo!(),
-        BlockType::MotionTurnLeft => todo!(),
-        BlockType::MotionGoTo => todo!(),
-        BlockType::MotionGoToXY => todo!(),
-        BlockType::MotionGlideTo => todo!(),
-        BlockType::MotionGlideSecsToXY => todo!(),
-        BlockType::MotionPointInDirection => todo!(),
-        BlockType::MotionPointTowards => todo!(),
-        BlockType::MotionChangeXBy => todo!(),
-        BlockType::MotionSetX => todo!(),
-        BlockType::MotionChangeYBy => todo!(),
-        BlockType::MotionSetY => todo!(),
-        BlockType::MotionIfOnEdgeBounce => todo!(),
-        BlockType::MotionSetRotationStyle => todo!(),
-        BlockType::LooksSayForSecs => {
-            let msg = exp.sargstr("MESSAGE", state, exp)?;
-            let secs = exp.sargfloat("SECS", state, exp)?;
-            println!("{}", msg);
-            thread::sleep(Duration::from_millis((secs * 1000.0) as u64));
-            Ok(RichValue::success())
-        }
-        BlockType::LooksSay => {
-            let msg = exp.sargstr("MESSAGE", state, exp)?;
-            println!("{}", msg);
-            Ok(RichValue::success())
-        }
-        BlockType::LooksThinkForSecs => todo!(),
-        BlockType::LooksThink => todo!(),
-        BlockType::LooksSwitchBackdropTo => todo!(),
-        BlockType::LooksSwitchBackdropToAndWait => todo!(),
-        BlockType::LooksNextBackdrop => todo!(),
-        BlockType::LooksNextCostume => todo!(),
-        BlockType::LooksChangeSizeBy => todo!(),
-        BlockType::LooksSetSizeTo => todo!(),
-        BlockType::LooksChangeEffectBy => todo!(),
-        BlockType::LooksSetEffectTo => todo!(),
-        BlockType::LooksClearGraphicEffects => todo!(),
-        BlockType::LooksShow => todo!(),
-        BlockType::LooksHide => todo!(),
-        BlockType::LooksGoToFrontBack => todo!(),
-        BlockType::LooksGoForwardBackwardLayers => todo!(),
-        BlockType::SoundStopallSounds => todo!(),
-        BlockType::SoundChangeEffectBy => todo!(),
-        BlockType::SoundSetEffectTo => todo!(),
-        BlockType::SoundClearEffects => todo!(),
-        BlockType::SoundChangeVolumeBy => todo!(),
-        BlockType::SoundSetVolumeTo => todo!(),
-        BlockType::EventWhenFlagClicked => Ok(RichValue::success()),
-        BlockType::EventWhenKeyPressed => todo!(),
-        BlockType::EventWhenStageClicked => todo!(),
-        BlockType::EventWhenThisSpriteClicked => todo!(),
-        BlockType::EventWhenBackdropSwitchesTo => todo!(),
-        BlockType::EventWhenGreaterThan => todo!(),
-        BlockType::EventWhenBroadcastReceived => Ok(RichValue::success()),
-        BlockType::EventBroadcast => todo!(),
-        BlockType::EventBroadcastandWait => todo!(),
-        BlockType::ControlWait => todo!(),
-        BlockType::ControlRepeat => todo!(),
-        BlockType::ControlIf => todo!(),
-        BlockType::ControlIfElse => todo!(),
-        BlockType::ControlStop => todo!(),
-        BlockType::ControlCreateCloneOf => todo!(),
-        BlockType::ControlStartAsClone => todo!(),
-        BlockType::ControlDeleteThisClone => todo!(),
-        BlockType::SensingTouchingObject => todo!(),
-        BlockType::SensingTouchingColor => todo!(),
-        BlockType::SensingColorIsTouchingColor => todo!(),
-        BlockType::SensingDistanceTo => todo!(),
-        BlockType::SensingKeyPressed => todo!(),
-        BlockType::SensingMouseDown => todo!(),
-        BlockType::SensingMouseX => todo!(),
-        BlockType::SensingMouseY => todo!(),
-        BlockType::SensingSetDragMode => todo!(),
-        BlockType::SensingResetTimer => todo!(),
-        BlockType::SensingDaysSince2000 => Ok(RichValue::Number(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH + Duration::from_secs(START_OF_2000_TIMESTAMP))
-                .map_err(|e| {
-                    ScratchError::internal(
-                        format!("time travelled into the past: {e}"),
-                        format!(
-                            "<vm::intepreter::SensingDaysSince2000> executing block {:?} (id={})",
-                            exp.opcode, exp.original_block.obj_id
-                        ),
-                    )
-                })?
-                .as_millis() as f64
-                / MILISECS_IN_A_DAY as f64,
-        )),
-        BlockType::SensingUsername => todo!(),
-        BlockType::OperatorAdd => {
-            let n1 = exp.sargfloat("NUM1", state, exp)?;
-            let n2 = exp.sargfloat("NUM2", state, exp)?;
-            Ok(RichValue::Number(n1 + n2))
-        }
-        BlockType::OperatorSubtract => {
-            let n1 = exp.sargfloat("NUM1", state, exp)?;
-            let n2 = exp.sargfloat("NUM2", state, exp)?;
-            Ok(RichValue::Number(n1 - n2))
-        }
-        BlockType::OperatorMultiply => {
-            let n1 = exp.sargfloat("NUM1", state, exp)?;
-            let n2 = exp.sargfloat("NUM2", state, exp)?;
-            Ok(RichValue::Number(n1 * n2))
-        }
-        BlockType::OperatorDivide => {
-            let n1 = exp.sargfloat("NUM1", state, exp)?;
-            let n2 = exp.sargfloat("NUM2", state, exp)?;
-            Ok(RichValue::Number(n1 / n2))
-        }
-        BlockType::OperatorRandom => {
-            let mut rng = rand::rng();
-            let lower = exp.sargfloat("FROM", state, exp)?;
-            let upper = exp.sargfloat("TO", state, exp)?;
-            if (lower.fract() == 0.0 && upper.fract() == 0.0) {
-                return Ok(RichValue::Integer(
-                    rng.random_range(lower as i64..upper as i64),
-                ));
-            }
-            Ok(RichValue::Number(rng.random_range(lower..upper)))
-        }
-        BlockType::OperatorGt => {
-            let n1 = exp.sargfloat("OPERAND1", state, exp)?;
-            let n2 = exp.sargfloat("OPERAND2", state, exp)?;
-            Ok(RichValue::Boolean(n1 > n2))
-        }
-        BlockType::OperatorLt => {
-            let n1 = exp.sargfloat("OPERAND1", state, exp)?;
-            let n2 = exp.sargfloat("OPERAND2", state, exp)?;
-            Ok(RichValue::Boolean(n1 < n2))
-        }
-        BlockType::OperatorEquals => {
-            let n1 = exp.sargfloat("OPERAND1", state, exp)?;
-            let n2 = exp.sargfloat("OPERAND2", state, exp)?;
-            Ok(RichValue::Boolean(n1 == n2))
-        }
-        BlockType::OperatorAnd => {
-            let n1 = exp.sargbool("OPERAND1", state, exp)?;
-            let n2 = exp.sargbool("OPERAND2", state, exp)?;
-            Ok(RichValue::Boolean(n1 && n2))
-        }
-        BlockType::OperatorOr => {
-            let n1 = exp.sargbool("OPERAND1", state, exp)?;
-            let n2 = exp.sargbool("OPERAND2", state, exp)?;
-            Ok(RichValue::Boolean(n1 || n2))
-        }
-        BlockType::OperatorNot => {
-            let n1 = exp.sargbool("OPERAND", state, exp)?;
-            Ok(RichValue::Boolean(!n1))
-        }
-        BlockType::OperatorJoin => {
-            let n1 = exp.sargstr("STRING1", state, exp)?;
-            let n2 = exp.sargstr("STRING2", state, exp)?;
-            Ok(RichValue::String(n1 + &n2))
-        }
-        BlockType::OperatorLetterOf => {
-            let n1 = exp.sargfloat("LETTER", state, exp)? as usize;
-            let n2 = exp.sargstr("STRING", state, exp)?;
-            Ok(RichValue::String(match n2.chars().nth(n1) {
-                Some(c) => c.to_string(),
-                None => "".to_string(),
-            }))
-        }
-        BlockType::OperatorLength => {
-            let s: String = exp.sargstr("STRING", state, exp)?;
-            Ok(RichValue::Number(s.len() as f64))
-        }
-        BlockType::OperatorContains => {
-            let n1 = exp.sargstr("STRING1", state, exp)?;
-            let n2 = exp.sargstr("STRING2", state, exp)?;
-            Ok(RichValue::Boolean(n1.contains(&n2)))
-        }
-        BlockType::OperatorMod => {
-            let n1 = exp.sargfloat("NUM1", state, exp)?;
-            let n2 = exp.sargfloat("NUM2", state, exp)?;
-            Ok(RichValue::Number(n1 % n2))
-        }
-        BlockType::OperatorRound => {
-            let n1 = exp.sargfloat("NUM", state, exp)?;
-            Ok(RichValue::Integer(n1.round() as i64))
-        }
-        BlockType::OperatorMathop => {
-            let n = exp.sargfloat("NUM", state, exp)?;
-            let op = exp.sargstr("OPERATOR", state, exp)?;
-            match op.as_str() {
-                "abs" => Ok(RichValue::Number(n.abs())),
-                "floor" => Ok(RichValue::Number(n.floor())),
-                "ceiling" => Ok(RichValue::Number(n.ceil())),
-                "sqrt" => Ok(RichValue::Number(n.sqrt())),
-                "sin" => Ok(RichValue::Number(n.sin())),
-                "cos" => Ok(RichValue::Number(n.cos())),
-                "tan" => Ok(RichValue::Number(n.tan())),
-                "asin" => Ok(RichValue::Number(n.asin())),
-                "acos" => Ok(RichValue::Number(n.acos())),
-                "atan" => Ok(RichValue::Number(n.atan())),
-                "ln" => Ok(RichValue::Number(n.ln())),
-                "log" => Ok(RichValue::Number(n.log10())),
-                "e ^" => Ok(RichValue::Number(n.exp())),
-                "10 ^" => Ok(RichValue::Number(n.powi(10))),
-                _ => Err(ScratchError::syntax_error(
-                    format!("unknown math operator {op}"),
-                    format!("block {:?} (id={})", exp.opcode, exp.original_block.obj_id),
-                )),
-            }
-        }
-
-        BlockType::ControlWait => todo!(),
-        BlockType::ControlRepeat => todo!(),
-        BlockType::ControlIf => todo!(),
-        BlockType::ControlIfElse => todo!(),
-        BlockType::ControlStop => todo!(),
-        BlockType::ControlCreateCloneOf => todo!(),
-        BlockType::ControlStartAsClone => todo!(),
-        BlockType::ControlDeleteThisClone => todo!(),
-        BlockType::DataSetVariableTo => {
-            let value = exp.sargraw("VALUE", exp)?.eval(state)?;
-            let var = exp.sargptr("VARIABLE", exp)?;
-
-            state.set_var(var, value.into())?;
-
-            Ok(RichValue::success())
-        }
-        BlockType::DataChangeVariableBy => {
-            let delta: f64 = exp.sargraw("VALUE", exp)?.eval(state)?.try_into()?;
-            let var = exp.sargptr("VARIABLE", exp)?;
-            let src: f64 = var.resolve_var(state)?.try_into()?;
-            state.set_var(var, (src + delta).into())?;
-
-            Ok(RichValue::success())
-        }
-        BlockType::DataShowVariable => todo!(),
-        BlockType::DataHideVariable => todo!(),
-        BlockType::DataAddToList => {
-            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
-            let item = exp.sargraw("ITEM", exp)?.eval(state)?;
-            list.write().push(RwLock::new(item.into()));
-            Ok(RichValue::success())
-        }
-        BlockType::DataListDeleteElement => {
-            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
-            let index: usize = exp.sargfloat("INDEX", state, exp)? as usize;
-            list.write().remove(index);
-            Ok(RichValue::success())
-        }
-        BlockType::DataListClear => {
-            let list = exp.argptr("LIST")?.resolve_list(state)?;
-            list.write().clear();
-            Ok(RichValue::success())
-        }
-        BlockType::DataListInsertAt => {
-            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
-            let item = exp.sargraw("ITEM", exp)?.eval(state)?;
-            let index: usize = exp.sargfloat("INDEX", state, exp)? as usize;
-            list.write().insert(index, RwLock::new(item.into()));
-            Ok(RichValue::success())
-        }
-        BlockType::DataListReplaceItem => {
-            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
-            let item = exp.sargraw("ITEM", exp)?.eval(state)?;
-            let index: usize = exp.sargfloat("INDEX", state, exp)? as usize;
-            std::mem::replace(
-                list.write().get_mut(index).ok_or(ScratchError::internal(
-                    "failed to get mutable reference of vecitem",
-                    "interpreter, DataListReplaceItem",
-                ))?,
-                RwLock::new(item.into()),
-            );
-            Ok(RichValue::success())
-        }
-        BlockType::DataListItemAt => {
-            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
-            let index: usize = exp.sargfloat("INDEX", state, exp)? as usize;
-            let result = match list.read().get(index) {
-                Some(v) => v.read().clone().into(),
-                None => RichValue::String("".to_string()),
-            };
-            Ok(result)
-        }
-        BlockType::DataListIndexOf => {
-            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
-            let item: RichValue = exp.sargraw("ITEM", exp)?.eval(state)?;
-            for (i, e) in list.read().iter().enumerate() {
-                let inner: RichValue = e.read().clone().into();
-                if inner == item {
-                    return Ok(RichValue::Number(i as f64));
-                }
-            }
-            Ok(RichValue::Number(0.0))
-        }
-        BlockType::DataListLengthOf => {
-            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
-            let r = list.read().len() as f64;
-            Ok(RichValue::Number(r))
-        }
-        BlockType::DataListContainsItem => {
-            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
-            let item: RichValue = exp.sargraw("ITEM", exp)?.eval(state)?;
-
-            for i in list.read().iter() {
-                let inner: RichValue = i.read().clone().into();
-                if inner == item {
-                    return Ok(RichValue::Boolean(true));
-                }
-            }
-            Ok(RichValue::Boolean(false))
-        }
-        BlockType::DataListShow => todo!(),
-        BlockType::DataListHide => todo!(),
-
-        BlockType::ProceduresDefinition => Ok(RichValue::success()),
-        BlockType::ProceduresCall => panic!("this error should be unreachable. If you see this error, there is an error in the transformer."),
-        BlockType::ArgumentReporterStringNumber | BlockType::ArgumentReporterBoolean => {
-            let bref = exp.argstr("VALUE", state)?;
-            let val = state.curent_thread.read().custom_block_arguments.get(
-                state.global_state
-                    .read()
-                    .mutationname_to_numid
-                    .get(&bref)
-                    .ok_or(ScratchError::not_found("custom block argument not found", format!("accessing custom block argument {}", bref))
-                )?
-            ).ok_or(ScratchError::not_found("custom block argument not found", format!("accessing custom block argument {}", bref)))?.into();
-            debug!("accessed variable {bref}, got {val:?}");
-            Ok(val)
-        },
-        BlockType::ProceduresPrototype => Ok(RichValue::success()),
-
-        BlockType::ArgumentEditorBoolean => todo!(),
-        BlockType::ArgumentEditorStringNumber => todo!(),
-        BlockType::Note => todo!(),
-        BlockType::MathPositiveNumber => todo!(),
-        BlockType::MathWholeNumber => todo!(),
-        BlockType::MathInteger => todo!(),
-        BlockType::MathAngle => todo!(),
-        BlockType::ColourPicker => todo!(),
-        BlockType::Text => todo!(),
-        BlockType::DataVariable => todo!(),
-        BlockType::DataListContents => todo!(),
-    }
-}
+use std::{
+    cmp::Ordering,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use parking_lot::{Mutex, RwLock};
+use scratch_ast::{
+    cast,
+    errors::ScratchError,
+    model::{BlockType, RichValue},
+};
+
+use crate::optimizer::header;
+use crate::vm::{
+    coverage::Coverage,
+    debugger::Debugger,
+    host,
+    internals::{
+        Expression, StackExpression, StopOption, ThreadTrigger, VMGlobalState, VMLocalState,
+        VMSourceCode, VMThread,
+    },
+    list,
+    profiler::Profiler,
+    trace::Tracer,
+};
+
+use super::ScratchResult;
+
+/// How often `wait until` checks its condition.
+const WAIT_UNTIL_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A sprite or the stage, with its scripts.
+#[derive(Debug)]
+pub struct VMTarget {
+    pub local_state: Arc<RwLock<VMLocalState>>,
+    pub source_code: Arc<VMSourceCode>,
+}
+
+/// Everything shared by the running scripts of a project.
+#[derive(Debug)]
+pub struct VMRuntime {
+    pub global_state: Arc<RwLock<VMGlobalState>>,
+    pub targets: Vec<VMTarget>,
+    /// Scripts started by the green flag or a broadcast that nobody waits for.
+    threads: Mutex<Vec<JoinHandle<ScratchResult>>>,
+    stopped: AtomicBool,
+    /// When the timer was reset, in seconds of the host clock.
+    timer: Mutex<f64>,
+    answer: RwLock<String>,
+    #[cfg(feature = "jit")]
+    pub jit: super::jit::Jit,
+    pub debugger: Option<Debugger>,
+    pub profiler: Option<Arc<Profiler>>,
+    pub tracer: Option<Arc<Tracer>>,
+    pub coverage: Option<Arc<Coverage>>,
+}
+
+impl VMRuntime {
+    pub fn new(
+        global_state: Arc<RwLock<VMGlobalState>>,
+        targets: Vec<(VMLocalState, VMSourceCode)>,
+    ) -> Self {
+        Self {
+            global_state,
+            targets: targets
+                .into_iter()
+                .map(|(local_state, source_code)| VMTarget {
+                    local_state: Arc::new(RwLock::new(local_state)),
+                    source_code: Arc::new(source_code),
+                })
+                .collect(),
+            threads: Mutex::new(Vec::new()),
+            stopped: AtomicBool::new(false),
+            timer: Mutex::new(host::get().now()),
+            answer: RwLock::new(String::new()),
+            #[cfg(feature = "jit")]
+            jit: super::jit::Jit::default(),
+            debugger: None,
+            profiler: None,
+            tracer: None,
+            coverage: None,
+        }
+    }
+
+    /// Runs the project under a debugger, without compiling loops, so that
+    /// every block goes through [`Debugger::before`].
+    pub fn with_debugger(mut self, debugger: Debugger) -> Self {
+        self.debugger = Some(debugger);
+        #[cfg(feature = "jit")]
+        {
+            self.jit = super::jit::Jit::off();
+        }
+        self
+    }
+
+    /// Times every block the project runs. Loops are not compiled either, as
+    /// their compiled iterations would not be timed.
+    pub fn with_profiler(mut self, profiler: Arc<Profiler>) -> Self {
+        self.profiler = Some(profiler);
+        #[cfg(feature = "jit")]
+        {
+            self.jit = super::jit::Jit::off();
+        }
+        self
+    }
+
+    /// Writes every block the project runs to a trace. Compiled loops would
+    /// skip it, so they are not compiled.
+    pub fn with_tracer(mut self, tracer: Arc<Tracer>) -> Self {
+        self.tracer = Some(tracer);
+        #[cfg(feature = "jit")]
+        {
+            self.jit = super::jit::Jit::off();
+        }
+        self
+    }
+
+    /// Records which blocks run. Compiled loops would not say, so they are not compiled.
+    pub fn with_coverage(mut self, coverage: Arc<Coverage>) -> Self {
+        self.coverage = Some(coverage);
+        #[cfg(feature = "jit")]
+        {
+            self.jit = super::jit::Jit::off();
+        }
+        self
+    }
+
+    /// Starts every script of every target listening to `trigger`, each on its own
+    /// thread, and returns their IDs in the schedule of the host.
+    pub fn start(
+        self: &Arc<Self>,
+        trigger: &ThreadTrigger,
+    ) -> (Range<u64>, Vec<JoinHandle<ScratchResult>>) {
+        let scripts = self
+            .targets
+            .iter()
+            .flat_map(|target| {
+                let threads = target.source_code.get(trigger).into_iter().flatten();
+                threads.map(move |thread| (target, thread))
+            })
+            .collect::<Vec<_>>();
+        let ids = host::get().admit(scripts.len());
+        let mut handles = Vec::new();
+        for ((target, thread), id) in scripts.into_iter().zip(ids.clone()) {
+            let state = VMState {
+                runtime: Arc::clone(self),
+                global_state: Arc::clone(&self.global_state),
+                local_state: Arc::clone(&target.local_state),
+                source_code: Arc::clone(&target.source_code),
+                curent_thread: Arc::new(RwLock::new(thread.clone())),
+            };
+            let trigger = trigger.clone();
+            handles.push(thread::spawn(move || {
+                let _scheduled = host::get().enter(id);
+                let debugger = state.runtime.debugger.as_ref();
+                let _thread = debugger.map(|d| d.thread(&state, &trigger));
+                let profiler = state.runtime.profiler.as_deref();
+                let _timing = profiler.map(|p| p.script(&state, &trigger));
+                if let Some(tracer) = &state.runtime.tracer {
+                    tracer.thread(&state);
+                }
+                if let Some(coverage) = &state.runtime.coverage {
+                    coverage.thread(&state);
+                }
+                exec_thread(&state)
+            }));
+        }
+        (ids, handles)
+    }
+
+    /// Like [`VMRuntime::start`], but [`VMRuntime::join`] waits for the scripts instead.
+    pub fn start_detached(self: &Arc<Self>, trigger: &ThreadTrigger) {
+        let (_, handles) = self.start(trigger);
+        self.threads.lock().extend(handles);
+    }
+
+    /// Waits until every script started with [`VMRuntime::start_detached`] finishes,
+    /// including the ones started while waiting. Returns the first error.
+    pub fn join(&self) -> ScratchResult {
+        let mut result = Ok(());
+        loop {
+            let Some(handle) = self.threads.lock().pop() else {
+                return result;
+            };
+            if let Err(e) = join_thread(handle) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+
+    /// `stop all`: every script stops before running its next block.
+    pub fn stop_all(&self) {
+        self.stopped.store(true, AtomicOrdering::Release);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(AtomicOrdering::Acquire)
+    }
+}
+
+fn join_thread(handle: JoinHandle<ScratchResult>) -> ScratchResult {
+    handle.join().unwrap_or_else(|_| {
+        Err(ScratchError::internal(
+            "a script panicked",
+            "waiting for a script to finish",
+        ))
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct VMState {
+    pub runtime: Arc<VMRuntime>,
+    pub source_code: Arc<VMSourceCode>,
+    pub global_state: Arc<RwLock<VMGlobalState>>,
+    pub local_state: Arc<RwLock<VMLocalState>>,
+    pub curent_thread: Arc<RwLock<VMThread>>,
+}
+
+/// Whether a script goes on after running some of its blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+pub fn exec_thread(state: &VMState) -> ScratchResult {
+    let thread = Arc::clone(&state.curent_thread);
+    exec_code(&thread.read().code, state)?;
+    Ok(())
+}
+
+/// Counts a step, and stops every script once the host allows no more.
+/// Returns whether the script goes on.
+fn step(state: &VMState) -> bool {
+    if state.runtime.is_stopped() {
+        return false;
+    }
+    if !host::get().step() {
+        state.runtime.stop_all();
+        return false;
+    }
+    true
+}
+
+/// [`step`] before a loop iteration, which is where a script ends its turn for
+/// the others to take theirs.
+fn iterate(state: &VMState) -> bool {
+    host::get().pass();
+    step(state)
+}
+
+/// The condition of a block. An empty condition slot is false.
+fn condition(exp: &StackExpression, state: &VMState) -> Result<bool, ScratchError> {
+    match exp.argraw("CONDITION") {
+        Some(_) => exp.sargbool("CONDITION", state, exp),
+        None => Ok(false),
+    }
+}
+
+/// [`condition`], traced with its result.
+fn traced_condition(exp: &StackExpression, state: &VMState) -> Result<bool, ScratchError> {
+    traced(
+        exp,
+        state,
+        || condition(exp, state),
+        |c| Some(RichValue::Boolean(*c)),
+    )
+}
+
+pub fn exec_code(code: &[Expression], state: &VMState) -> Result<Flow, ScratchError> {
+    let debugger = state.runtime.debugger.as_ref();
+    let _nested = debugger.map(|d| d.nest());
+    let profiler = state.runtime.profiler.as_deref();
+    let coverage = state.runtime.coverage.as_deref();
+    for t in code.iter() {
+        if !step(state) || debugger.is_some_and(|d| !d.before(t, state)) {
+            return Ok(Flow::Stop);
+        }
+        let _timing = profiler.map(|p| p.block(t));
+        if let Some(coverage) = coverage {
+            coverage.block(header(t));
+        }
+        let flow = match t {
+            Expression::Stack(s) => {
+                // Stack blocks report nothing worth tracing.
+                traced(s, state, || eval_block(s, state), |_| None)?;
+                Flow::Continue
+            }
+            Expression::Conditional {
+                header,
+                then,
+                otherwise,
+            } => {
+                let then_taken = traced_condition(header, state)?;
+                if let Some(coverage) = coverage {
+                    coverage.branch(header, then_taken);
+                }
+                if then_taken {
+                    exec_code(then, state)?
+                } else {
+                    exec_code(otherwise, state)?
+                }
+            }
+            Expression::LoopTimes { header, body } => {
+                let times = traced(
+                    header,
+                    state,
+                    || header.sargfloat("TIMES", state, header),
+                    |_| None,
+                )?;
+                let times = cast::round(times);
+                #[cfg(feature = "jit")]
+                let mut jit = super::jit::LoopJit::new(header, body, None, state);
+                let mut flow = Flow::Continue;
+                let mut i = 0.0;
+                while i < times && flow == Flow::Continue && iterate(state) {
+                    #[cfg(feature = "jit")]
+                    if let super::jit::Step::Ran(n) = jit.step(state, (times - i) as u64)? {
+                        i += n as f64;
+                        continue;
+                    }
+                    flow = exec_code(body, state)?;
+                    i += 1.0;
+                }
+                flow
+            }
+            Expression::LoopCondition { header, body } => {
+                let until = header.opcode == BlockType::ControlRepeatUntil;
+                #[cfg(feature = "jit")]
+                let mut jit = super::jit::LoopJit::new(header, body, Some(until), state);
+                let mut flow = Flow::Continue;
+                while flow == Flow::Continue && iterate(state) {
+                    #[cfg(feature = "jit")]
+                    match jit.step(state, u64::MAX)? {
+                        super::jit::Step::Ran(_) => continue,
+                        super::jit::Step::Exited => break,
+                        super::jit::Step::Interpret => (),
+                    }
+                    if traced_condition(header, state)? == until {
+                        break;
+                    }
+                    flow = exec_code(body, state)?;
+                }
+                flow
+            }
+            Expression::LoopForever { header, body } => {
+                #[cfg(feature = "jit")]
+                let mut jit = super::jit::LoopJit::new(header, body, None, state);
+                traced(header, state, || Ok(()), |_| None)?;
+                let mut flow = Flow::Continue;
+                while flow == Flow::Continue && iterate(state) {
+                    #[cfg(feature = "jit")]
+                    if let super::jit::Step::Ran(_) = jit.step(state, u64::MAX)? {
+                        continue;
+                    }
+                    flow = exec_code(body, state)?;
+                }
+                flow
+            }
+            Expression::InvokeBroadcast(header) => {
+                let name = traced(
+                    header,
+                    state,
+                    || header.sargstr("BROADCAST_INPUT", state, header),
+                    |_| None,
+                )?;
+                let trigger = ThreadTrigger::Broadcast(name.to_lowercase());
+                if header.opcode == BlockType::EventBroadcastandWait {
+                    let (ids, handles) = state.runtime.start(&trigger);
+                    host::get().wait_for(ids);
+                    for handle in handles {
+                        join_thread(handle)?;
+                    }
+                } else {
+                    state.runtime.start_detached(&trigger);
+                }
+                Flow::Continue
+            }
+            Expression::InvokeCustomBlock {
+                header,
+                target,
+                arguments,
+            } => {
+                let mut nthread = state
+                    .source_code
+                    .get(&ThreadTrigger::Mutation(*target))
+                    .and_then(|t| t.first())
+                    .ok_or(ScratchError::not_found(
+                        format!("custom block {target} not found"),
+                        format!("triggering custom block {target}"),
+                    ))?
+                    .clone();
+                traced(
+                    header,
+                    state,
+                    || {
+                        for (id, val) in arguments {
+                            let value = val.eval(state)?;
+                            if let Some(tracer) = &state.runtime.tracer {
+                                // Arguments are traced by name, not by numeric ID.
+                                let mut names = nthread.argument_names.iter();
+                                if let Some((name, _)) = names.find(|(_, i)| *i == id) {
+                                    tracer.argument(name, &value);
+                                }
+                            }
+                            nthread.custom_block_arguments.insert(*id, value.into());
+                        }
+                        Ok(())
+                    },
+                    |_| None,
+                )?;
+                let _call = debugger.map(|d| d.call(header));
+                // `stop this script` only leaves the custom block.
+                exec_thread(&VMState {
+                    runtime: Arc::clone(&state.runtime),
+                    global_state: Arc::clone(&state.global_state),
+                    local_state: Arc::clone(&state.local_state),
+                    source_code: Arc::clone(&state.source_code),
+                    curent_thread: Arc::new(RwLock::new(nthread)),
+                })?;
+                Flow::Continue
+            }
+            Expression::Stop { header, option } => {
+                match traced(header, state, || Ok(option), |_| None)? {
+                    StopOption::All => {
+                        state.runtime.stop_all();
+                        Flow::Stop
+                    }
+                    StopOption::ThisScript => Flow::Stop,
+                    StopOption::OtherScriptsInSprite => {
+                        warn!("stopping other scripts is not supported yet, ignoring");
+                        Flow::Continue
+                    }
+                }
+            }
+        };
+        if flow == Flow::Stop {
+            return Ok(Flow::Stop);
+        }
+    }
+
+    Ok(Flow::Continue)
+}
+
+/// `mod`, which takes the sign of the divisor like in Scratch.
+pub fn modulo(n: f64, modulus: f64) -> f64 {
+    let mut result = n % modulus;
+    if result / modulus < 0.0 {
+        result += modulus;
+    }
+    result
+}
+
+/// `math op` with a lowercase operator, `None` if Scratch does not know it.
+pub fn mathop(op: &str, n: f64) -> Option<f64> {
+    // Scratch rounds trigonometric results to 10 decimal places,
+    // so that e.g. sin(180) is exactly 0.
+    let round10 = |x: f64| (x * 1e10).round() / 1e10;
+    let radians = |x: f64| std::f64::consts::PI * x / 180.0;
+    Some(match op {
+        "abs" => n.abs(),
+        "floor" => n.floor(),
+        "ceiling" => n.ceil(),
+        "sqrt" => n.sqrt(),
+        "sin" => round10(radians(n).sin()),
+        "cos" => round10(radians(n).cos()),
+        "tan" => match n % 360.0 {
+            -270.0 | 90.0 => f64::INFINITY,
+            -90.0 | 270.0 => f64::NEG_INFINITY,
+            _ => round10(radians(n % 360.0).tan()),
+        },
+        "asin" => n.asin().to_degrees(),
+        "acos" => n.acos().to_degrees(),
+        "atan" => n.atan().to_degrees(),
+        "ln" => n.ln(),
+        "log" => n.log10(),
+        "e ^" => n.exp(),
+        "10 ^" => 10f64.powf(n),
+        _ => return None,
+    })
+}
+
+/// Whether [`eval_exp`] can run a block yet.
+pub fn supports(opcode: BlockType) -> bool {
+    !matches!(
+        opcode,
+        BlockType::MotionMoveSteps
+            | BlockType::MotionTurnRight
+            | BlockType::MotionTurnLeft
+            | BlockType::MotionGoTo
+            | BlockType::MotionGoToXY
+            | BlockType::MotionGlideTo
+            | BlockType::MotionGlideSecsToXY
+            | BlockType::MotionPointInDirection
+            | BlockType::MotionPointTowards
+            | BlockType::MotionChangeXBy
+            | BlockType::MotionSetX
+            | BlockType::MotionChangeYBy
+            | BlockType::MotionSetY
+            | BlockType::MotionIfOnEdgeBounce
+            | BlockType::MotionSetRotationStyle
+            | BlockType::LooksThinkForSecs
+            | BlockType::LooksThink
+            | BlockType::LooksSwitchBackdropTo
+            | BlockType::LooksSwitchBackdropToAndWait
+            | BlockType::LooksNextBackdrop
+            | BlockType::LooksNextCostume
+            | BlockType::LooksChangeSizeBy
+            | BlockType::LooksSetSizeTo
+            | BlockType::LooksChangeEffectBy
+            | BlockType::LooksSetEffectTo
+            | BlockType::LooksClearGraphicEffects
+            | BlockType::LooksShow
+            | BlockType::LooksHide
+            | BlockType::LooksGoToFrontBack
+            | BlockType::LooksGoForwardBackwardLayers
+            | BlockType::SoundStopallSounds
+            | BlockType::SoundChangeEffectBy
+            | BlockType::SoundSetEffectTo
+            | BlockType::SoundClearEffects
+            | BlockType::SoundChangeVolumeBy
+            | BlockType::SoundSetVolumeTo
+            | BlockType::EventWhenKeyPressed
+            | BlockType::EventWhenStageClicked
+            | BlockType::EventWhenThisSpriteClicked
+            | BlockType::EventWhenBackdropSwitchesTo
+            | BlockType::EventWhenGreaterThan
+            | BlockType::ControlCreateCloneOf
+            | BlockType::ControlStartAsClone
+            | BlockType::ControlDeleteThisClone
+            | BlockType::SensingTouchingObject
+            | BlockType::SensingTouchingColor
+            | BlockType::SensingColorIsTouchingColor
+            | BlockType::SensingDistanceTo
+            | BlockType::SensingKeyPressed
+            | BlockType::SensingMouseDown
+            | BlockType::SensingMouseX
+            | BlockType::SensingMouseY
+            | BlockType::SensingSetDragMode
+            | BlockType::SensingUsername
+            | BlockType::DataShowVariable
+            | BlockType::DataHideVariable
+            | BlockType::DataListShow
+            | BlockType::DataListHide
+            | BlockType::ArgumentEditorBoolean
+            | BlockType::ArgumentEditorStringNumber
+            | BlockType::Note
+            | BlockType::MathPositiveNumber
+            | BlockType::MathWholeNumber
+            | BlockType::MathInteger
+            | BlockType::MathAngle
+            | BlockType::ColourPicker
+            | BlockType::Text
+            | BlockType::DataVariable
+            | BlockType::DataListContents
+    )
+}
+
+/// Notes a change to the `LIST` of a block in the trace, if there is one.
+fn trace_list(
+    exp: &StackExpression,
+    state: &VMState,
+    action: &str,
+    index: Option<&RichValue>,
+    item: Option<&RichValue>,
+) {
+    if let (Some(tracer), Ok(list)) = (&state.runtime.tracer, exp.argptr("LIST")) {
+        tracer.list(&list, action, index, item);
+    }
+}
+
+/// Runs `run` for `block`, and traces it if the project is traced.
+fn traced<T>(
+    block: &StackExpression,
+    state: &VMState,
+    run: impl FnOnce() -> Result<T, ScratchError>,
+    result: impl FnOnce(&T) -> Option<RichValue>,
+) -> Result<T, ScratchError> {
+    match &state.runtime.tracer {
+        Some(tracer) => tracer.block(block, run, result),
+        None => run(),
+    }
+}
+
+/// Evaluates a reporter, or runs a block.
+pub fn eval_exp(exp: &StackExpression, state: &VMState) -> Result<RichValue, ScratchError> {
+    if let Some(coverage) = &state.runtime.coverage {
+        coverage.block(exp);
+    }
+    traced(
+        exp,
+        state,
+        || eval_block(exp, state),
+        |value| Some(value.clone()),
+    )
+}
+
+#[allow(unused)]
+fn eval_block(exp: &StackExpression, state: &VMState) -> Result<RichValue, ScratchError> {
+    debug!("exec {}", exp);
+    match exp.opcode {
+        BlockType::MotionMoveSteps => todo!(),
+        BlockType::MotionTurnRight => todo!(),
+        BlockType::MotionTurnLeft => todo!(),
+        BlockType::MotionGoTo => todo!(),
+        BlockType::MotionGoToXY => todo!(),
+        BlockType::MotionGlideTo => todo!(),
+        BlockType::MotionGlideSecsToXY => todo!(),
+        BlockType::MotionPointInDirection => todo!(),
+        BlockType::MotionPointTowards => todo!(),
+        BlockType::MotionChangeXBy => todo!(),
+        BlockType::MotionSetX => todo!(),
+        BlockType::MotionChangeYBy => todo!(),
+        BlockType::MotionSetY => todo!(),
+        BlockType::MotionIfOnEdgeBounce => todo!(),
+        BlockType::MotionSetRotationStyle => todo!(),
+        BlockType::LooksSayForSecs => {
+            let msg = exp.sargstr("MESSAGE", state, exp)?;
+            let secs = exp.sargfloat("SECS", state, exp)?;
+            let host = host::get();
+            host.say(&msg);
+            host.sleep_until(host.now() + (secs * 1000.0) as u64 as f64 / 1000.0);
+            Ok(RichValue::success())
+        }
+        BlockType::LooksSay => {
+            let msg = exp.sargstr("MESSAGE", state, exp)?;
+            host::get().say(&msg);
+            Ok(RichValue::success())
+        }
+        BlockType::LooksThinkForSecs => todo!(),
+        BlockType::LooksThink => todo!(),
+        BlockType::LooksSwitchBackdropTo => todo!(),
+        BlockType::LooksSwitchBackdropToAndWait => todo!(),
+        BlockType::LooksNextBackdrop => todo!(),
+        BlockType::LooksNextCostume => todo!(),
+        BlockType::LooksChangeSizeBy => todo!(),
+        BlockType::LooksSetSizeTo => todo!(),
+        BlockType::LooksChangeEffectBy => todo!(),
+        BlockType::LooksSetEffectTo => todo!(),
+        BlockType::LooksClearGraphicEffects => todo!(),
+        BlockType::LooksShow => todo!(),
+        BlockType::LooksHide => todo!(),
+        BlockType::LooksGoToFrontBack => todo!(),
+        BlockType::LooksGoForwardBackwardLayers => todo!(),
+        BlockType::SoundStopallSounds => todo!(),
+        BlockType::SoundChangeEffectBy => todo!(),
+        BlockType::SoundSetEffectTo => todo!(),
+        BlockType::SoundClearEffects => todo!(),
+        BlockType::SoundChangeVolumeBy => todo!(),
+        BlockType::SoundSetVolumeTo => todo!(),
+        BlockType::EventWhenFlagClicked => Ok(RichValue::success()),
+        BlockType::EventWhenKeyPressed => todo!(),
+        BlockType::EventWhenStageClicked => todo!(),
+        BlockType::EventWhenThisSpriteClicked => todo!(),
+        BlockType::EventWhenBackdropSwitchesTo => todo!(),
+        BlockType::EventWhenGreaterThan => todo!(),
+        BlockType::EventWhenBroadcastReceived => Ok(RichValue::success()),
+        BlockType::ControlWait => {
+            let secs = exp.sargfloat("DURATION", state, exp)?;
+            let host = host::get();
+            host.sleep_until(host.now() + secs.max(0.0));
+            Ok(RichValue::success())
+        }
+        BlockType::ControlWaitUntil => {
+            while !state.runtime.is_stopped() && !condition(exp, state)? {
+                let host = host::get();
+                host.sleep_until(host.now() + WAIT_UNTIL_POLL_INTERVAL.as_secs_f64());
+            }
+            Ok(RichValue::success())
+        }
+        BlockType::ControlCreateCloneOf => todo!(),
+        BlockType::ControlStartAsClone => todo!(),
+        BlockType::ControlDeleteThisClone => todo!(),
+        BlockType::SensingTouchingObject => todo!(),
+        BlockType::SensingTouchingColor => todo!(),
+        BlockType::SensingColorIsTouchingColor => todo!(),
+        BlockType::SensingDistanceTo => todo!(),
+        BlockType::SensingKeyPressed => todo!(),
+        BlockType::SensingMouseDown => todo!(),
+        BlockType::SensingMouseX => todo!(),
+        BlockType::SensingMouseY => todo!(),
+        BlockType::SensingSetDragMode => todo!(),
+        BlockType::SensingResetTimer => {
+            *state.runtime.timer.lock() = host::get().now();
+            Ok(RichValue::success())
+        }
+        BlockType::SensingTimer => Ok(RichValue::Number(
+            host::get().now() - *state.runtime.timer.lock(),
+        )),
+        BlockType::SensingAskAndWait => {
+            let question = exp.sargstr("QUESTION", state, exp)?;
+            let answer = host::get().ask(&question).map_err(|e| {
+                ScratchError::internal(
+                    e,
+                    format!("block {:?} (id={})", exp.opcode, exp.original_block.obj_id),
+                )
+            })?;
+            *state.runtime.answer.write() = answer;
+            Ok(RichValue::success())
+        }
+        BlockType::SensingAnswer => Ok(RichValue::String(state.runtime.answer.read().clone())),
+        BlockType::SensingDaysSince2000 => Ok(RichValue::Number(
+            host::get()
+                .days_since_2000()
+                .map_err(|e| {
+                    ScratchError::internal(
+                        format!("time travelled into the past: {e}"),
+                        format!(
+                            "<vm::intepreter::SensingDaysSince2000> executing block {:?} (id={})",
+                            exp.opcode, exp.original_block.obj_id
+                        ),
+                    )
+                })?,
+        )),
+        BlockType::SensingUsername => todo!(),
+        BlockType::OperatorAdd => {
+            let n1 = exp.sargfloat("NUM1", state, exp)?;
+            let n2 = exp.sargfloat("NUM2", state, exp)?;
+            Ok(RichValue::Number(n1 + n2))
+        }
+        BlockType::OperatorSubtract => {
+            let n1 = exp.sargfloat("NUM1", state, exp)?;
+            let n2 = exp.sargfloat("NUM2", state, exp)?;
+            Ok(RichValue::Number(n1 - n2))
+        }
+        BlockType::OperatorMultiply => {
+            let n1 = exp.sargfloat("NUM1", state, exp)?;
+            let n2 = exp.sargfloat("NUM2", state, exp)?;
+            Ok(RichValue::Number(n1 * n2))
+        }
+        BlockType::OperatorDivide => {
+            let n1 = exp.sargfloat("NUM1", state, exp)?;
+            let n2 = exp.sargfloat("NUM2", state, exp)?;
+            Ok(RichValue::Number(n1 / n2))
+        }
+        BlockType::OperatorRandom => {
+            let from = exp.sargvalue("FROM", state, exp)?;
+            let to = exp.sargvalue("TO", state, exp)?;
+            let n1 = cast::to_number(&from);
+            let n2 = cast::to_number(&to);
+            let (low, high) = if n1 <= n2 { (n1, n2) } else { (n2, n1) };
+            if low == high {
+                return Ok(RichValue::Number(low));
+            }
+            let random = host::get().random();
+            if cast::is_int(&from) && cast::is_int(&to) {
+                return Ok(RichValue::Number(
+                    low + (random * (high - low + 1.0)).floor(),
+                ));
+            }
+            Ok(RichValue::Number(low + random * (high - low)))
+        }
+        BlockType::OperatorGt => {
+            let n1 = exp.sargvalue("OPERAND1", state, exp)?;
+            let n2 = exp.sargvalue("OPERAND2", state, exp)?;
+            Ok(RichValue::Boolean(cast::compare(&n1, &n2) == Ordering::Greater))
+        }
+        BlockType::OperatorLt => {
+            let n1 = exp.sargvalue("OPERAND1", state, exp)?;
+            let n2 = exp.sargvalue("OPERAND2", state, exp)?;
+            Ok(RichValue::Boolean(cast::compare(&n1, &n2) == Ordering::Less))
+        }
+        BlockType::OperatorEquals => {
+            let n1 = exp.sargvalue("OPERAND1", state, exp)?;
+            let n2 = exp.sargvalue("OPERAND2", state, exp)?;
+            Ok(RichValue::Boolean(cast::compare(&n1, &n2) == Ordering::Equal))
+        }
+        BlockType::OperatorAnd => {
+            let n1 = exp.sargbool("OPERAND1", state, exp)?;
+            let n2 = exp.sargbool("OPERAND2", state, exp)?;
+            Ok(RichValue::Boolean(n1 && n2))
+        }
+        BlockType::OperatorOr => {
+            let n1 = exp.sargbool("OPERAND1", state, exp)?;
+            let n2 = exp.sargbool("OPERAND2", state, exp)?;
+            Ok(RichValue::Boolean(n1 || n2))
+        }
+        BlockType::OperatorNot => {
+            let n1 = exp.sargbool("OPERAND", state, exp)?;
+            Ok(RichValue::Boolean(!n1))
+        }
+        BlockType::OperatorJoin => {
+            let n1 = exp.sargstr("STRING1", state, exp)?;
+            let n2 = exp.sargstr("STRING2", state, exp)?;
+            Ok(RichValue::String(n1 + &n2))
+        }
+        BlockType::OperatorLetterOf => {
+            let index = exp.sargfloat("LETTER", state, exp)? - 1.0;
+            let s = exp.sargstr("STRING", state, exp)?;
+            if index < 0.0 || index >= cast::js_length(&s) as f64 {
+                return Ok(RichValue::String("".to_string()));
+            }
+            Ok(RichValue::String(cast::js_char_at(&s, index as usize)))
+        }
+        BlockType::OperatorLength => {
+            let s: String = exp.sargstr("STRING", state, exp)?;
+            Ok(RichValue::Number(cast::js_length(&s) as f64))
+        }
+        BlockType::OperatorContains => {
+            let n1 = exp.sargstr("STRING1", state, exp)?.to_lowercase();
+            let n2 = exp.sargstr("STRING2", state, exp)?.to_lowercase();
+            Ok(RichValue::Boolean(n1.contains(&n2)))
+        }
+        BlockType::OperatorMod => {
+            let n = exp.sargfloat("NUM1", state, exp)?;
+            let modulus = exp.sargfloat("NUM2", state, exp)?;
+            Ok(RichValue::Number(modulo(n, modulus)))
+        }
+        BlockType::OperatorRound => {
+            let n1 = exp.sargfloat("NUM", state, exp)?;
+            Ok(RichValue::Number(cast::round(n1)))
+        }
+        BlockType::OperatorMathop => {
+            let n = exp.sargfloat("NUM", state, exp)?;
+            let op = exp.sargstr("OPERATOR", state, exp)?.to_lowercase();
+            match mathop(&op, n) {
+                Some(n) => Ok(RichValue::Number(n)),
+                None => Err(ScratchError::syntax_error(
+                    format!("unknown math operator {op}"),
+                    format!("block {:?} (id={})", exp.opcode, exp.original_block.obj_id),
+                )),
+            }
+        }
+
+        BlockType::DataSetVariableTo => {
+            let value = exp.sargeval("VALUE", state, exp)?;
+            let var = exp.sargptr("VARIABLE", exp)?;
+
+            state.set_var(var, value.into())?;
+
+            Ok(RichValue::success())
+        }
+        BlockType::DataChangeVariableBy => {
+            let delta = cast::to_number(&exp.sargeval("VALUE", state, exp)?);
+            let var = exp.sargptr("VARIABLE", exp)?;
+            let src = var.resolve_var(state)?.to_number();
+            state.set_var(var, (src + delta).into())?;
+
+            Ok(RichValue::success())
+        }
+        BlockType::DataShowVariable => todo!(),
+        BlockType::DataHideVariable => todo!(),
+        BlockType::DataAddToList => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            let item = exp.sargeval("ITEM", state, exp)?;
+            trace_list(exp, state, "add", None, Some(&item));
+            list::add(&mut list.write(), item.into());
+            Ok(RichValue::success())
+        }
+        BlockType::DataListDeleteElement => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            let index = exp.sargvalue("INDEX", state, exp)?;
+            trace_list(exp, state, "delete", Some(&index), None);
+            list::delete(&mut list.write(), &index);
+            Ok(RichValue::success())
+        }
+        BlockType::DataListClear => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            trace_list(exp, state, "clear", None, None);
+            list.write().clear();
+            Ok(RichValue::success())
+        }
+        BlockType::DataListInsertAt => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            let item = exp.sargeval("ITEM", state, exp)?;
+            let index = exp.sargvalue("INDEX", state, exp)?;
+            trace_list(exp, state, "insert", Some(&index), Some(&item));
+            list::insert(&mut list.write(), &index, item.into());
+            Ok(RichValue::success())
+        }
+        BlockType::DataListReplaceItem => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            let item = exp.sargeval("ITEM", state, exp)?;
+            let index = exp.sargvalue("INDEX", state, exp)?;
+            trace_list(exp, state, "replace", Some(&index), Some(&item));
+            list::replace(&mut list.write(), &index, item.into());
+            Ok(RichValue::success())
+        }
+        BlockType::DataListItemAt => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            let index = exp.sargvalue("INDEX", state, exp)?;
+            let result = list::item(&list.read(), &index);
+            Ok(result)
+        }
+        BlockType::DataListIndexOf => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            let item: RichValue = exp.sargeval("ITEM", state, exp)?;
+            let result = list::index_of(&list.read(), &item);
+            Ok(RichValue::Number(result as f64))
+        }
+        BlockType::DataListLengthOf => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            let r = list.read().len() as f64;
+            Ok(RichValue::Number(r))
+        }
+        BlockType::DataListContainsItem => {
+            let list = exp.sargptr("LIST", exp)?.resolve_list(state)?;
+            let item: RichValue = exp.sargeval("ITEM", state, exp)?;
+            let result = list::contains(&list.read(), &item);
+            Ok(RichValue::Boolean(result))
+        }
+        BlockType::DataListShow => todo!(),
+        BlockType::DataListHide => todo!(),
+
+        BlockType::ProceduresDefinition => Ok(RichValue::success()),
+        BlockType::ProceduresCall
+        | BlockType::ControlRepeat
+        | BlockType::ControlForever
+        | BlockType::ControlRepeatUntil
+        | BlockType::ControlWhile
+        | BlockType::ControlIf
+        | BlockType::ControlIfElse
+        | BlockType::ControlStop
+        | BlockType::EventBroadcast
+        | BlockType::EventBroadcastandWait => panic!("this error should be unreachable. If you see this error, there is an error in the transformer."),
+        BlockType::ArgumentReporterStringNumber | BlockType::ArgumentReporterBoolean => {
+            let bref = exp.argstr("VALUE", state)?;
+            let thread = state.curent_thread.read();
+            let aid = match thread.argument_names.get(&bref) {
+                Some(aid) => *aid,
+                None => *state.global_state
+                    .read()
+                    .mutationname_to_numid
+                    .get(&bref)
+                    .ok_or(ScratchError::not_found("custom block argument not found", format!("accessing custom block argument {}", bref))
+                )?,
+            };
+            let val = thread.custom_block_arguments.get(&aid).ok_or(ScratchError::not_found("custom block argument not found", format!("accessing custom block argument {}", bref)))?.into();
+            debug!("accessed variable {bref}, got {val:?}");
+            Ok(val)
+        },
+        BlockType::ProceduresPrototype => Ok(RichValue::success()),
+
+        BlockType::ArgumentEditorBoolean => todo!(),
+        BlockType::ArgumentEditorStringNumber => todo!(),
+        BlockType::Note => todo!(),
+        BlockType::MathPositiveNumber => todo!(),
+        BlockType::MathWholeNumber => todo!(),
+        BlockType::MathInteger => todo!(),
+        BlockType::MathAngle => todo!(),
+        BlockType::ColourPicker => todo!(),
+        BlockType::Text => todo!(),
+        BlockType::DataVariable => todo!(),
+        BlockType::DataListContents => todo!(),
+    }
+}
@@ -4,11 +4,16 @@ use hashbrown::HashMap;
 use parking_lot::RwLock;
 use scratch_ast::prelude::*;
 
+use crate::vm::cloud::CloudProvider;
+
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
 pub enum ThreadTrigger {
     GreenFlag,
+    /// Receiving a broadcast, by lowercase name.
     Broadcast(String),
     Mutation(usize),
+    /// A hat block kcc cannot fire, such as `when this sprite clicked`.
+    Hat(BlockType),
 }
 
 #[derive(Clone, Debug)]
@@ -40,43 +45,96 @@ pub struct StackExpression {
     pub original_block: Box<Block>,
 }
 
+impl StackExpression {
+    /// The name of the custom block this block calls, if it is a call.
+    pub fn proccode(&self) -> Option<&str> {
+        match &self.original_block.mutation {
+            Some(Mutation::ProcedureCall(call)) => Some(&call.proccode),
+            _ => None,
+        }
+    }
+}
+
+/// What `stop` stops.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopOption {
+    All,
+    ThisScript,
+    OtherScriptsInSprite,
+}
+
+/// A block of a script. Blocks that hold other blocks keep the block itself
+/// in `header`, whose dependencies are its inputs, such as the condition of an `if`.
 #[derive(Clone, Debug)]
 pub enum Expression {
     Stack(StackExpression),
-    Conditional(),
-    LoopTimes(),
-    LoopCondition(),
-    LoopForever(),
-    InvokeBroadcast(),
+    /// `if`, and `if else`. The `otherwise` branch of `if` is empty.
+    Conditional {
+        header: StackExpression,
+        then: Vec<Expression>,
+        otherwise: Vec<Expression>,
+    },
+    /// `repeat`.
+    LoopTimes {
+        header: StackExpression,
+        body: Vec<Expression>,
+    },
+    /// `repeat until`, and `while`.
+    LoopCondition {
+        header: StackExpression,
+        body: Vec<Expression>,
+    },
+    LoopForever {
+        header: StackExpression,
+        body: Vec<Expression>,
+    },
+    /// `broadcast`, and `broadcast and wait`.
+    InvokeBroadcast(StackExpression),
     InvokeCustomBlock {
+        header: StackExpression,
         target: usize,
         arguments: HashMap<usize, VMEvaluable>,
     },
+    Stop {
+        header: StackExpression,
+        option: StopOption,
+    },
 }
 
 #[derive(Clone, Debug)]
 pub struct VMThread {
     pub custom_block_arguments: HashMap<usize, PrimitiveValue>,
+    /// Numeric IDs of the arguments of a custom block, by name.
+    pub argument_names: HashMap<String, usize>,
     pub code: Vec<Expression>,
 }
 
-pub type VMSourceCode = HashMap<ThreadTrigger, VMThread>;
+/// The scripts of a target. Several scripts may share a trigger,
+/// e.g. a sprite may have many `when green flag clicked` scripts.
+pub type VMSourceCode = HashMap<ThreadTrigger, Vec<VMThread>>;
 
 #[derive(Debug)]
 pub struct VMGlobalState {
     pub variables: HashMap<usize, RwLock<PrimitiveValue>>,
+    /// Names of the variables, keyed by their numeric ID.
+    pub variable_names: HashMap<usize, String>,
     pub lists: HashMap<usize, Arc<RwLock<Vec<RwLock<PrimitiveValue>>>>>,
     pub broadcasts: HashMap<usize, String>,
     pub listname_to_numid: Arc<HashMap<String, usize>>,
     pub varname_to_numid: Arc<HashMap<String, usize>>,
     pub broadcastname_to_numid: Arc<HashMap<String, usize>>,
     pub mutationname_to_numid: Arc<HashMap<String, usize>>,
+    /// Names of the cloud variables, keyed by their numeric ID.
+    pub cloud_names: HashMap<usize, String>,
+    pub cloud: Option<Arc<dyn CloudProvider>>,
 }
 
 #[derive(Debug)]
 pub struct VMLocalState {
     pub name: String,
     pub variables: HashMap<usize, RwLock<PrimitiveValue>>,
+    /// Names of the variables, keyed by their numeric ID.
+    pub variable_names: HashMap<usize, String>,
     pub lists: HashMap<usize, Arc<RwLock<Vec<RwLock<PrimitiveValue>>>>>,
     pub broadcasts: HashMap<usize, String>,
     pub listname_to_numid: Arc<HashMap<String, usize>>,
@@ -0,0 +1,1017 @@
+//! Compiles hot loops to native code with Cranelift.
+//!
+//! Once a loop has run [`HOT_ITERATIONS`] times, its body is compiled if it only
+//! does arithmetic on variables and custom block arguments: `set`, `change`, `if`
+//! and nested `repeat`, with operators, comparisons and `math op`. The compiled code
+//! assumes what the interpreter saw then, that every value it reads is a number.
+//! It runs up to [`BATCH`] iterations at a time on a copy of the values, checking
+//! this still holds before each batch, and writes back the values it changed after
+//! the last iteration that completed. Whenever a value turns out to be anything
+//! else, the interpreter takes over for an iteration.
+//!
+//! Scripts run on threads of their own, so a batch only runs when no other script
+//! can set the variables the loop reads, like the optimizer decides what it can
+//! hoist. Otherwise the compiled code runs one iteration at a time, and checks
+//! that the variables still hold what it read before writing back; if another
+//! script changed one meanwhile, the interpreter runs the iteration again.
+//!
+//! Setting `KCC_JIT=off` disables it, and so do a step limit, as compiled
+//! iterations are not counted, the debugger, which pauses between blocks, and
+//! the profiler, which times them.
+
+use std::{
+    cmp::Ordering,
+    sync::{Arc, OnceLock},
+};
+
+use cranelift_codegen::{
+    entity::EntityRef,
+    ir::{
+        condcodes::{FloatCC, IntCC},
+        types, AbiParam, InstBuilder, MemFlags, UserFuncName, Value,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use hashbrown::{HashMap, HashSet};
+use log::debug;
+use parking_lot::Mutex;
+use scratch_ast::{
+    cast,
+    errors::ScratchError,
+    model::{BlockType, PrimitiveValue, RichValue},
+};
+
+use crate::{
+    optimizer::{self, ScriptId},
+    vm::{
+        intepreter::{self, VMState},
+        internals::{Expression, StackExpression, VMEvaluable, VMValuePointer},
+    },
+};
+
+/// How many iterations a loop runs in the interpreter before it is compiled.
+pub const HOT_ITERATIONS: u64 = 1000;
+/// The most iterations compiled code runs before values are written back.
+pub const BATCH: u64 = 10_000;
+/// After this many iterations handed back in a row, a loop stops trying its compiled code.
+const MAX_BAILS: u32 = 8;
+
+/// What the compiled code of a loop returns.
+const RAN: i32 = 0;
+const BAILED: i32 = 1;
+const EXITED: i32 = 2;
+
+/// The operators of `math op`, numbered for the compiled code.
+const MATHOPS: [&str; 14] = [
+    "abs", "floor", "ceiling", "sqrt", "sin", "cos", "tan", "asin", "acos", "atan", "ln", "log",
+    "e ^", "10 ^",
+];
+
+extern "C" fn jit_modulo(n: f64, modulus: f64) -> f64 {
+    intepreter::modulo(n, modulus)
+}
+
+extern "C" fn jit_round(n: f64) -> f64 {
+    cast::round(n)
+}
+
+extern "C" fn jit_mathop(op: u32, n: f64) -> f64 {
+    intepreter::mathop(MATHOPS[op as usize], n).unwrap_or(f64::NAN)
+}
+
+/// A number, as the block computing it reports it. Operators convert their
+/// operands with `Cast.toNumber`, which turns NaN into 0.
+enum Num {
+    Const(f64),
+    Slot(usize),
+    Add(Box<Num>, Box<Num>),
+    Subtract(Box<Num>, Box<Num>),
+    Multiply(Box<Num>, Box<Num>),
+    Divide(Box<Num>, Box<Num>),
+    Mod(Box<Num>, Box<Num>),
+    Round(Box<Num>),
+    Mathop(u32, Box<Num>),
+}
+
+enum Cond {
+    Const(bool),
+    /// Numbers compare like floats, unless one of them is NaN, which Scratch
+    /// compares as text. The compiled code bails out then.
+    Compare(Ordering, Num, Num),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+    Not(Box<Cond>),
+}
+
+enum Stmt {
+    Set(usize, Num),
+    Change(usize, Num),
+    If(Cond, Vec<Stmt>, Vec<Stmt>),
+    Repeat(Num, Vec<Stmt>),
+}
+
+/// Where the value of a slot comes from.
+#[derive(Clone, Debug)]
+enum Input {
+    Variable(VMValuePointer),
+    /// A custom block argument, by numeric ID.
+    Argument(usize),
+}
+
+impl Input {
+    fn key(&self) -> (bool, usize) {
+        match self {
+            Input::Variable(
+                VMValuePointer::Variable { id, .. }
+                | VMValuePointer::List { id, .. }
+                | VMValuePointer::Broadcast { id, .. },
+            ) => (true, *id),
+            Input::Argument(id) => (false, *id),
+        }
+    }
+}
+
+/// Why a loop cannot be compiled.
+struct Unsupported;
+
+/// Turns the blocks of a loop into the subset the compiler understands.
+#[derive(Default)]
+struct Shape {
+    inputs: Vec<Input>,
+    written: Vec<bool>,
+}
+
+impl Shape {
+    fn slot(&mut self, input: Input) -> usize {
+        match self.inputs.iter().position(|i| i.key() == input.key()) {
+            Some(slot) => slot,
+            None => {
+                self.inputs.push(input);
+                self.written.push(false);
+                self.inputs.len() - 1
+            }
+        }
+    }
+
+    fn variable(&mut self, pointer: &VMValuePointer) -> Result<usize, Unsupported> {
+        match pointer {
+            VMValuePointer::Variable { .. } => Ok(self.slot(Input::Variable(pointer.clone()))),
+            _ => Err(Unsupported),
+        }
+    }
+
+    fn statements(
+        &mut self,
+        code: &[Expression],
+        state: &VMState,
+    ) -> Result<Vec<Stmt>, Unsupported> {
+        code.iter().map(|e| self.statement(e, state)).collect()
+    }
+
+    fn statement(&mut self, expression: &Expression, state: &VMState) -> Result<Stmt, Unsupported> {
+        match expression {
+            Expression::Stack(exp) => {
+                let pointer = exp.argptr("VARIABLE").map_err(|_| Unsupported)?;
+                let slot = self.variable(&pointer)?;
+                let value = exp.argraw("VALUE").ok_or(Unsupported)?;
+                let stmt = match exp.opcode {
+                    BlockType::DataSetVariableTo => Stmt::Set(slot, self.stored(value, state)?),
+                    BlockType::DataChangeVariableBy => {
+                        Stmt::Change(slot, self.stored(value, state)?)
+                    }
+                    _ => return Err(Unsupported),
+                };
+                self.written[slot] = true;
+                Ok(stmt)
+            }
+            Expression::Conditional {
+                header,
+                then,
+                otherwise,
+            } => Ok(Stmt::If(
+                self.condition(header, state)?,
+                self.statements(then, state)?,
+                self.statements(otherwise, state)?,
+            )),
+            Expression::LoopTimes { header, body } => Ok(Stmt::Repeat(
+                self.input(header.argraw("TIMES"), state)?,
+                self.statements(body, state)?,
+            )),
+            _ => Err(Unsupported),
+        }
+    }
+
+    fn condition(&mut self, exp: &StackExpression, state: &VMState) -> Result<Cond, Unsupported> {
+        match exp.argraw("CONDITION") {
+            Some(value) => self.boolean(value, state),
+            None => Ok(Cond::Const(false)),
+        }
+    }
+
+    fn boolean(&mut self, value: &VMEvaluable, state: &VMState) -> Result<Cond, Unsupported> {
+        let exp = match value {
+            VMEvaluable::Bare(value) => return Ok(Cond::Const(cast::to_boolean(value))),
+            VMEvaluable::Default => return Ok(Cond::Const(false)),
+            VMEvaluable::Block(exp) => exp,
+            _ => return Err(Unsupported),
+        };
+        let mut operand = |name: &str| self.boolean(exp.argraw(name).ok_or(Unsupported)?, state);
+        Ok(match exp.opcode {
+            BlockType::OperatorAnd => Cond::And(
+                Box::new(operand("OPERAND1")?),
+                Box::new(operand("OPERAND2")?),
+            ),
+            BlockType::OperatorOr => Cond::Or(
+                Box::new(operand("OPERAND1")?),
+                Box::new(operand("OPERAND2")?),
+            ),
+            BlockType::OperatorNot => Cond::Not(Box::new(operand("OPERAND")?)),
+            BlockType::OperatorGt | BlockType::OperatorLt | BlockType::OperatorEquals => {
+                let ordering = match exp.opcode {
+                    BlockType::OperatorGt => Ordering::Greater,
+                    BlockType::OperatorLt => Ordering::Less,
+                    _ => Ordering::Equal,
+                };
+                Cond::Compare(
+                    ordering,
+                    self.compared(exp.argraw("OPERAND1"), state)?,
+                    self.compared(exp.argraw("OPERAND2"), state)?,
+                )
+            }
+            _ => return Err(Unsupported),
+        })
+    }
+
+    /// An operand of a comparison. Literals must compare as numbers.
+    fn compared(
+        &mut self,
+        value: Option<&VMEvaluable>,
+        state: &VMState,
+    ) -> Result<Num, Unsupported> {
+        let literal = match value.ok_or(Unsupported)? {
+            VMEvaluable::Bare(value) => value.clone(),
+            VMEvaluable::Field(f) => RichValue::String(f.display_value.clone()),
+            VMEvaluable::Default => return Err(Unsupported),
+            value => return self.reported(value, state),
+        };
+        numeric(&literal).map(Num::Const).ok_or(Unsupported)
+    }
+
+    /// The value `set` and `change` store. Literals must read back the same as numbers.
+    fn stored(&mut self, value: &VMEvaluable, state: &VMState) -> Result<Num, Unsupported> {
+        match value {
+            VMEvaluable::Bare(value) => canonical(&value.into()).map(Num::Const).ok_or(Unsupported),
+            VMEvaluable::Field(f) => match &f.pointer {
+                Some(pointer) => Ok(Num::Slot(self.variable(pointer)?)),
+                None => canonical(&PrimitiveValue::String(f.display_value.clone()))
+                    .map(Num::Const)
+                    .ok_or(Unsupported),
+            },
+            VMEvaluable::Default => Err(Unsupported),
+            value => self.reported(value, state),
+        }
+    }
+
+    /// An operand of an operator, before `Cast.toNumber`.
+    fn input(&mut self, value: Option<&VMEvaluable>, state: &VMState) -> Result<Num, Unsupported> {
+        match value {
+            None => Err(Unsupported),
+            Some(VMEvaluable::Bare(value)) => Ok(Num::Const(cast::to_number(value))),
+            Some(VMEvaluable::Field(f)) => Ok(Num::Const(cast::to_number(&RichValue::String(
+                f.display_value.clone(),
+            )))),
+            Some(VMEvaluable::Default) => Ok(Num::Const(0.0)),
+            Some(value) => self.reported(value, state),
+        }
+    }
+
+    /// A variable or a block reporting a number.
+    fn reported(&mut self, value: &VMEvaluable, state: &VMState) -> Result<Num, Unsupported> {
+        let exp = match value {
+            VMEvaluable::Pointer(pointer) => return Ok(Num::Slot(self.variable(pointer)?)),
+            VMEvaluable::Block(exp) => exp,
+            _ => return Err(Unsupported),
+        };
+        let mut operand = |name: &str| -> Result<Box<Num>, Unsupported> {
+            Ok(Box::new(self.input(exp.argraw(name), state)?))
+        };
+        Ok(match exp.opcode {
+            BlockType::OperatorAdd => Num::Add(operand("NUM1")?, operand("NUM2")?),
+            BlockType::OperatorSubtract => Num::Subtract(operand("NUM1")?, operand("NUM2")?),
+            BlockType::OperatorMultiply => Num::Multiply(operand("NUM1")?, operand("NUM2")?),
+            BlockType::OperatorDivide => Num::Divide(operand("NUM1")?, operand("NUM2")?),
+            BlockType::OperatorMod => Num::Mod(operand("NUM1")?, operand("NUM2")?),
+            BlockType::OperatorRound => Num::Round(operand("NUM")?),
+            BlockType::OperatorMathop => {
+                let operator = match exp.argraw("OPERATOR") {
+                    Some(VMEvaluable::Field(f)) => f.display_value.to_lowercase(),
+                    Some(VMEvaluable::Bare(v)) => cast::to_string(v).to_lowercase(),
+                    _ => return Err(Unsupported),
+                };
+                let op = MATHOPS
+                    .iter()
+                    .position(|o| *o == operator)
+                    .ok_or(Unsupported)?;
+                Num::Mathop(op as u32, operand("NUM")?)
+            }
+            BlockType::ArgumentReporterStringNumber => {
+                let name = exp.argstr("VALUE", state).map_err(|_| Unsupported)?;
+                let thread = state.curent_thread.read();
+                let id = match thread.argument_names.get(&name) {
+                    Some(id) => *id,
+                    None => *state
+                        .global_state
+                        .read()
+                        .mutationname_to_numid
+                        .get(&name)
+                        .ok_or(Unsupported)?,
+                };
+                Num::Slot(self.slot(Input::Argument(id)))
+            }
+            _ => return Err(Unsupported),
+        })
+    }
+}
+
+/// The number a literal compares as, `None` if Scratch would compare it as text.
+fn numeric(value: &RichValue) -> Option<f64> {
+    let n = match value {
+        RichValue::String(s) | RichValue::Color(s) | RichValue::Broadcast(s) => {
+            cast::str_to_number(s)
+        }
+        RichValue::Boolean(_) => return None,
+        value => cast::to_number(value),
+    };
+    (!n.is_nan() && !cast::is_whitespace(value)).then_some(n)
+}
+
+/// The number a value holds, if it behaves exactly like that number everywhere,
+/// i.e. it is not NaN and, if it is text, reads the same as the number.
+fn canonical(value: &PrimitiveValue) -> Option<f64> {
+    match value {
+        PrimitiveValue::Number(n) => (!n.is_nan()).then_some(*n),
+        PrimitiveValue::Integer(i) => (i.unsigned_abs() <= 1 << 53).then_some(*i as f64),
+        PrimitiveValue::String(s) => {
+            let n = cast::str_to_number(s);
+            (!n.is_nan() && cast::number_to_string(n) == *s).then_some(n)
+        }
+    }
+}
+
+type Function = extern "C" fn(*mut f64, u64, *mut u64) -> i32;
+
+/// The compiled code of a loop.
+pub struct Compiled {
+    /// Takes the slots, how many iterations to run at most, and where to write
+    /// how many completed.
+    function: Function,
+    inputs: Vec<Input>,
+    written: Vec<bool>,
+    /// Whether another script may set the variable of each slot while the loop runs.
+    shared: Vec<bool>,
+}
+
+/// What [`LoopJit::step`] did.
+pub enum Step {
+    /// The compiled code ran this many iterations, at least one.
+    Ran(u64),
+    /// The compiled code found the loop is over.
+    Exited,
+    /// The interpreter has to run this iteration.
+    Interpret,
+}
+
+#[derive(Default)]
+struct Entry {
+    iterations: u64,
+    compiled: Option<Arc<Compiled>>,
+    rejected: bool,
+}
+
+/// Loops seen by the interpreter, keyed by target and block ID.
+pub struct Jit {
+    enabled: bool,
+    loops: Mutex<HashMap<(usize, String), Entry>>,
+    /// The scripts setting or changing each variable, found once a loop is compiled.
+    writers: OnceLock<HashMap<usize, HashSet<ScriptId>>>,
+}
+
+impl std::fmt::Debug for Jit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Jit")
+            .field("enabled", &self.enabled)
+            .field("loops", &self.loops.lock().len())
+            .finish()
+    }
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self {
+            enabled: std::env::var("KCC_JIT").map_or(true, |v| v != "off" && v != "0")
+                && super::host::get().options().max_steps.is_none(),
+            loops: Mutex::new(HashMap::new()),
+            writers: OnceLock::new(),
+        }
+    }
+}
+
+impl Jit {
+    /// A JIT that compiles nothing.
+    pub fn off() -> Self {
+        Self {
+            enabled: false,
+            loops: Mutex::new(HashMap::new()),
+            writers: OnceLock::new(),
+        }
+    }
+}
+
+/// Drives the compiled code of a loop while the interpreter runs it.
+pub struct LoopJit<'a> {
+    jit: &'a Jit,
+    header: &'a StackExpression,
+    body: &'a [Expression],
+    /// Whether the compiled code also checks the condition of the loop.
+    until: Option<bool>,
+    key: (usize, String),
+    compiled: Option<Arc<Compiled>>,
+    /// Whether the loop is never compiled.
+    rejected: bool,
+    /// Iterations run by the interpreter since the loop started.
+    iterations: u64,
+    /// Iterations run by the interpreter before that.
+    seen: u64,
+    bails: u32,
+    slots: Vec<f64>,
+    /// The slots as loaded, before the compiled code ran.
+    loaded: Vec<f64>,
+}
+
+impl<'a> LoopJit<'a> {
+    /// `until` is whether a `repeat until` or `while` loop ends when its condition
+    /// is true, and `None` for other loops.
+    pub fn new(
+        header: &'a StackExpression,
+        body: &'a [Expression],
+        until: Option<bool>,
+        state: &'a VMState,
+    ) -> Self {
+        let jit = &state.runtime.jit;
+        let key = (
+            Arc::as_ptr(&state.local_state) as usize,
+            header.original_block.obj_id.clone(),
+        );
+        let (compiled, rejected, seen) = match jit.loops.lock().get(&key) {
+            _ if !jit.enabled => (None, true, 0),
+            Some(entry) => (entry.compiled.clone(), entry.rejected, entry.iterations),
+            None => (None, false, 0),
+        };
+        Self {
+            jit,
+            header,
+            body,
+            until,
+            key,
+            compiled,
+            rejected,
+            iterations: 0,
+            seen,
+            bails: 0,
+            slots: Vec::new(),
+            loaded: Vec::new(),
+        }
+    }
+
+    /// Runs up to `remaining` iterations with the compiled code if possible.
+    pub fn step(&mut self, state: &VMState, remaining: u64) -> Result<Step, ScratchError> {
+        if self.compiled.is_none() {
+            if self.rejected {
+                return Ok(Step::Interpret);
+            }
+            self.iterations += 1;
+            if self.seen.saturating_add(self.iterations) < HOT_ITERATIONS {
+                return Ok(Step::Interpret);
+            }
+            self.compile(state);
+        }
+        let Some(compiled) = self.compiled.clone() else {
+            return Ok(Step::Interpret);
+        };
+        if self.bails >= MAX_BAILS {
+            return Ok(Step::Interpret);
+        }
+        let limit = match compiled.shared.contains(&true) {
+            true => 1,
+            false => remaining.min(BATCH),
+        };
+        let step = self.run(&compiled, state, limit)?;
+        match step {
+            Step::Interpret => self.bails += 1,
+            _ => self.bails = 0,
+        }
+        Ok(step)
+    }
+
+    fn compile(&mut self, state: &VMState) {
+        let mut loops = self.jit.loops.lock();
+        let entry = loops.entry(self.key.clone()).or_default();
+        if let Some(compiled) = &entry.compiled {
+            self.compiled = Some(Arc::clone(compiled));
+            return;
+        }
+        if entry.rejected {
+            self.rejected = true;
+            return;
+        }
+        let mut shape = Shape::default();
+        let lowered = (|| {
+            let condition = match self.until {
+                Some(until) => Some((shape.condition(self.header, state)?, until)),
+                None => None,
+            };
+            let body = shape.statements(self.body, state)?;
+            Ok::<_, Unsupported>((condition, body))
+        })();
+        let Ok((condition, body)) = lowered else {
+            debug!("loop {} cannot be compiled", self.key.1);
+            entry.rejected = true;
+            self.rejected = true;
+            return;
+        };
+        // The compiled code assumes every value it reads is a number, as they are now.
+        if self.load(&shape.inputs, state).is_none() {
+            debug!(
+                "not compiling loop {} yet, it reads non-numbers",
+                self.key.1
+            );
+            entry.iterations = 0;
+            self.seen = 0;
+            self.iterations = 0;
+            return;
+        }
+        match generate(&shape, condition.as_ref(), &body) {
+            Ok(function) => {
+                debug!(
+                    "compiled loop {} ({} slots)",
+                    self.key.1,
+                    shape.inputs.len()
+                );
+                let shared = self.shared(&shape.inputs, state);
+                let compiled = Arc::new(Compiled {
+                    function,
+                    inputs: shape.inputs,
+                    written: shape.written,
+                    shared,
+                });
+                entry.compiled = Some(Arc::clone(&compiled));
+                self.compiled = Some(compiled);
+            }
+            Err(e) => {
+                debug!("compiling loop {} failed: {e}", self.key.1);
+                entry.rejected = true;
+                self.rejected = true;
+            }
+        }
+    }
+
+    /// Whether another script may set the variable of each input while the loop
+    /// runs: a cloud variable, or one set by other scripts or by the script of the
+    /// loop running twice at once.
+    fn shared(&self, inputs: &[Input], state: &VMState) -> Vec<bool> {
+        let runtime = &state.runtime;
+        let writers = self.jit.writers.get_or_init(|| {
+            optimizer::writers(runtime.targets.iter().map(|t| t.source_code.as_ref()))
+        });
+        let script = self.script(state);
+        let gs = state.global_state.read();
+        inputs
+            .iter()
+            .map(|input| match input {
+                Input::Variable(VMValuePointer::Variable { id, .. }) => {
+                    gs.cloud_names.contains_key(id)
+                        || script
+                            .as_ref()
+                            .is_none_or(|script| !optimizer::private(writers, *id, script))
+                }
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// The script the loop is in, if it is in only one. Custom blocks inlined into
+    /// several scripts are in all of them.
+    fn script(&self, state: &VMState) -> Option<ScriptId> {
+        let target = state
+            .runtime
+            .targets
+            .iter()
+            .position(|t| Arc::ptr_eq(&t.local_state, &state.local_state))?;
+        let mut scripts = Vec::new();
+        for (trigger, threads) in state.runtime.targets[target].source_code.iter() {
+            for (index, thread) in threads.iter().enumerate() {
+                let mut contains = false;
+                optimizer::visit(&thread.code, &mut |e| {
+                    contains |= optimizer::header(e).original_block.obj_id == self.key.1;
+                });
+                if contains {
+                    scripts.push((target, trigger.clone(), index));
+                }
+            }
+        }
+        match scripts.len() {
+            1 => scripts.pop(),
+            _ => None,
+        }
+    }
+
+    /// Reads every input into the slots, `None` if one is not a number.
+    fn load(&mut self, inputs: &[Input], state: &VMState) -> Option<()> {
+        self.slots.clear();
+        for input in inputs {
+            let value = match input {
+                Input::Variable(pointer) => pointer.resolve_var(state).ok()?,
+                Input::Argument(id) => state
+                    .curent_thread
+                    .read()
+                    .custom_block_arguments
+                    .get(id)?
+                    .clone(),
+            };
+            self.slots.push(canonical(&value)?);
+        }
+        Some(())
+    }
+
+    fn run(
+        &mut self,
+        compiled: &Compiled,
+        state: &VMState,
+        limit: u64,
+    ) -> Result<Step, ScratchError> {
+        if self.load(&compiled.inputs, state).is_none() {
+            return Ok(Step::Interpret);
+        }
+        self.loaded.clone_from(&self.slots);
+        let mut done = 0;
+        let status = (compiled.function)(self.slots.as_mut_ptr(), limit, &mut done);
+        // The iteration read values another script has changed since.
+        if !self.unchanged(compiled, state) {
+            return Ok(Step::Interpret);
+        }
+        if done > 0 {
+            for (slot, input) in compiled.inputs.iter().enumerate() {
+                let changed = self.slots[slot].to_bits() != self.loaded[slot].to_bits();
+                if let (true, Input::Variable(pointer)) = (compiled.written[slot] && changed, input)
+                {
+                    pointer.set_var(state, PrimitiveValue::Number(self.slots[slot]))?;
+                }
+            }
+        }
+        Ok(match status {
+            EXITED => Step::Exited,
+            _ if done > 0 => Step::Ran(done),
+            _ => Step::Interpret,
+        })
+    }
+
+    /// Whether the shared variables still hold what was loaded.
+    fn unchanged(&self, compiled: &Compiled, state: &VMState) -> bool {
+        compiled.inputs.iter().enumerate().all(|(slot, input)| {
+            let (true, Input::Variable(pointer)) = (compiled.shared[slot], input) else {
+                return true;
+            };
+            pointer
+                .resolve_var(state)
+                .ok()
+                .and_then(|value| canonical(&value))
+                .is_some_and(|n| n.to_bits() == self.loaded[slot].to_bits())
+        })
+    }
+}
+
+impl Drop for LoopJit<'_> {
+    /// Remembers how many iterations the interpreter ran, so that loops running
+    /// a few iterations many times get compiled too.
+    fn drop(&mut self) {
+        if self.iterations > 0 && self.compiled.is_none() && !self.rejected {
+            let mut loops = self.jit.loops.lock();
+            let entry = loops.entry(self.key.clone()).or_default();
+            entry.iterations = entry.iterations.saturating_add(self.iterations);
+        }
+    }
+}
+
+/// Compiles a loop into a function running its iterations on the slots.
+fn generate(
+    shape: &Shape,
+    condition: Option<&(Cond, bool)>,
+    body: &[Stmt],
+) -> Result<Function, String> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+    let isa = cranelift_native::builder()?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| e.to_string())?;
+    let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+    builder.symbol("kcc_jit_modulo", jit_modulo as *const u8);
+    builder.symbol("kcc_jit_round", jit_round as *const u8);
+    builder.symbol("kcc_jit_mathop", jit_mathop as *const u8);
+    // Dropping the module leaks its code on purpose, so the function stays valid.
+    let mut module = JITModule::new(builder);
+    let pointer = module.target_config().pointer_type();
+
+    let mut declare = |name: &str, params: &[types::Type]| {
+        let mut signature = module.make_signature();
+        signature
+            .params
+            .extend(params.iter().map(|t| AbiParam::new(*t)));
+        signature.returns.push(AbiParam::new(types::F64));
+        module
+            .declare_function(name, Linkage::Import, &signature)
+            .map_err(|e| e.to_string())
+    };
+    let helpers = Helpers {
+        modulo: declare("kcc_jit_modulo", &[types::F64, types::F64])?,
+        round: declare("kcc_jit_round", &[types::F64])?,
+        mathop: declare("kcc_jit_mathop", &[types::I32, types::F64])?,
+    };
+
+    let mut ctx = module.make_context();
+    ctx.func.signature.params.extend([
+        AbiParam::new(pointer),
+        AbiParam::new(types::I64),
+        AbiParam::new(pointer),
+    ]);
+    ctx.func.signature.returns.push(AbiParam::new(types::I32));
+    let id = module
+        .declare_function("iteration", Linkage::Local, &ctx.func.signature)
+        .map_err(|e| e.to_string())?;
+    ctx.func.name = UserFuncName::user(0, id.as_u32());
+
+    let mut function_ctx = FunctionBuilderContext::new();
+    let mut b = FunctionBuilder::new(&mut ctx.func, &mut function_ctx);
+    let entry = b.create_block();
+    b.append_block_params_for_function_params(entry);
+    b.switch_to_block(entry);
+    let (slots, limit, done) = match *b.block_params(entry) {
+        [slots, limit, done] => (slots, limit, done),
+        _ => unreachable!("the signature has three parameters"),
+    };
+    let bail = b.create_block();
+    let iteration = Variable::new(shape.inputs.len());
+
+    let mut codegen = Codegen {
+        helpers: Helpers {
+            modulo: module.declare_func_in_func(helpers.modulo, b.func),
+            round: module.declare_func_in_func(helpers.round, b.func),
+            mathop: module.declare_func_in_func(helpers.mathop, b.func),
+        },
+        bail,
+        variables: shape.inputs.len() + 1,
+    };
+    for slot in 0..shape.inputs.len() {
+        let value = b
+            .ins()
+            .load(types::F64, MemFlags::trusted(), slots, (slot * 8) as i32);
+        b.declare_var(Variable::new(slot), types::F64);
+        b.def_var(Variable::new(slot), value);
+    }
+    b.declare_var(iteration, types::I64);
+    let zero = b.ins().iconst(types::I64, 0);
+    b.def_var(iteration, zero);
+    let head = b.create_block();
+    let run = b.create_block();
+    b.ins().jump(head, &[]);
+
+    // Every way out writes how many iterations completed.
+    let finish = |b: &mut FunctionBuilder, block, status: i32| {
+        b.switch_to_block(block);
+        let i = b.use_var(iteration);
+        b.ins().store(MemFlags::trusted(), i, done, 0);
+        let status = b.ins().iconst(types::I32, status as i64);
+        b.ins().return_(&[status]);
+    };
+    let finished = b.create_block();
+    b.switch_to_block(head);
+    let i = b.use_var(iteration);
+    let more = b.ins().icmp(IntCC::UnsignedLessThan, i, limit);
+    match condition {
+        Some((condition, until)) => {
+            let check = b.create_block();
+            let exit = b.create_block();
+            b.ins().brif(more, check, &[], finished, &[]);
+            b.switch_to_block(check);
+            let value = codegen.condition(&mut b, condition);
+            if *until {
+                b.ins().brif(value, exit, &[], run, &[]);
+            } else {
+                b.ins().brif(value, run, &[], exit, &[]);
+            }
+            finish(&mut b, exit, EXITED);
+        }
+        None => {
+            b.ins().brif(more, run, &[], finished, &[]);
+        }
+    }
+    b.switch_to_block(run);
+    codegen.statements(&mut b, body);
+    // The slots keep the values after the last complete iteration, for bailing out.
+    for (slot, written) in shape.written.iter().enumerate() {
+        if *written {
+            let value = b.use_var(Variable::new(slot));
+            b.ins()
+                .store(MemFlags::trusted(), value, slots, (slot * 8) as i32);
+        }
+    }
+    let i = b.use_var(iteration);
+    let next = b.ins().iadd_imm(i, 1);
+    b.def_var(iteration, next);
+    b.ins().jump(head, &[]);
+    finish(&mut b, finished, RAN);
+    finish(&mut b, bail, BAILED);
+    b.seal_all_blocks();
+    b.finalize();
+
+    module
+        .define_function(id, &mut ctx)
+        .map_err(|e| format!("{e:?}"))?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().map_err(|e| e.to_string())?;
+    let code = module.get_finalized_function(id);
+    // SAFETY: the function was built with exactly this signature.
+    Ok(unsafe { std::mem::transmute::<*const u8, Function>(code) })
+}
+
+struct Helpers<T> {
+    modulo: T,
+    round: T,
+    mathop: T,
+}
+
+struct Codegen {
+    helpers: Helpers<cranelift_codegen::ir::FuncRef>,
+    bail: cranelift_codegen::ir::Block,
+    /// How many variables are declared, slots first.
+    variables: usize,
+}
+
+impl Codegen {
+    fn statements(&mut self, b: &mut FunctionBuilder, code: &[Stmt]) {
+        for stmt in code {
+            self.statement(b, stmt);
+        }
+    }
+
+    fn statement(&mut self, b: &mut FunctionBuilder, stmt: &Stmt) {
+        match stmt {
+            Stmt::Set(slot, value) => {
+                let value = self.number(b, value);
+                b.def_var(Variable::new(*slot), value);
+            }
+            Stmt::Change(slot, delta) => {
+                let current = b.use_var(Variable::new(*slot));
+                let current = to_number(b, current);
+                let delta = self.operand(b, delta);
+                let value = b.ins().fadd(current, delta);
+                b.def_var(Variable::new(*slot), value);
+            }
+            Stmt::If(condition, then, otherwise) => {
+                let value = self.condition(b, condition);
+                let then_block = b.create_block();
+                let otherwise_block = b.create_block();
+                let join = b.create_block();
+                b.ins().brif(value, then_block, &[], otherwise_block, &[]);
+                b.switch_to_block(then_block);
+                self.statements(b, then);
+                b.ins().jump(join, &[]);
+                b.switch_to_block(otherwise_block);
+                self.statements(b, otherwise);
+                b.ins().jump(join, &[]);
+                b.switch_to_block(join);
+            }
+            Stmt::Repeat(times, body) => {
+                let times = self.operand(b, times);
+                let times = b.ins().call(self.helpers.round, &[times]);
+                let times = b.inst_results(times)[0];
+                let counter = Variable::new(self.variables);
+                self.variables += 1;
+                b.declare_var(counter, types::F64);
+                let zero = b.ins().f64const(0.0);
+                b.def_var(counter, zero);
+                let head = b.create_block();
+                let run = b.create_block();
+                let exit = b.create_block();
+                b.ins().jump(head, &[]);
+                b.switch_to_block(head);
+                let i = b.use_var(counter);
+                let more = b.ins().fcmp(FloatCC::LessThan, i, times);
+                b.ins().brif(more, run, &[], exit, &[]);
+                b.switch_to_block(run);
+                self.statements(b, body);
+                let i = b.use_var(counter);
+                let one = b.ins().f64const(1.0);
+                let next = b.ins().fadd(i, one);
+                b.def_var(counter, next);
+                b.ins().jump(head, &[]);
+                b.switch_to_block(exit);
+            }
+        }
+    }
+
+    /// A condition, as an `i8` that is 0 or 1.
+    fn condition(&mut self, b: &mut FunctionBuilder, condition: &Cond) -> Value {
+        match condition {
+            Cond::Const(value) => b.ins().iconst(types::I8, *value as i64),
+            Cond::Compare(ordering, left, right) => {
+                let left = self.number(b, left);
+                let right = self.number(b, right);
+                let comparable = b.create_block();
+                let nan = b.ins().fcmp(FloatCC::Unordered, left, right);
+                b.ins().brif(nan, self.bail, &[], comparable, &[]);
+                b.switch_to_block(comparable);
+                let cc = match ordering {
+                    Ordering::Greater => FloatCC::GreaterThan,
+                    Ordering::Less => FloatCC::LessThan,
+                    Ordering::Equal => FloatCC::Equal,
+                };
+                b.ins().fcmp(cc, left, right)
+            }
+            Cond::And(left, right) => {
+                let left = self.condition(b, left);
+                let right = self.condition(b, right);
+                b.ins().band(left, right)
+            }
+            Cond::Or(left, right) => {
+                let left = self.condition(b, left);
+                let right = self.condition(b, right);
+                b.ins().bor(left, right)
+            }
+            Cond::Not(value) => {
+                let value = self.condition(b, value);
+                b.ins().bxor_imm(value, 1)
+            }
+        }
+    }
+
+    /// An operand of an operator, converted with `Cast.toNumber`.
+    fn operand(&mut self, b: &mut FunctionBuilder, value: &Num) -> Value {
+        let value = self.number(b, value);
+        to_number(b, value)
+    }
+
+    fn binary(&mut self, b: &mut FunctionBuilder, left: &Num, right: &Num) -> (Value, Value) {
+        let left = self.operand(b, left);
+        let right = self.operand(b, right);
+        (left, right)
+    }
+
+    fn number(&mut self, b: &mut FunctionBuilder, value: &Num) -> Value {
+        match value {
+            Num::Const(n) => b.ins().f64const(*n),
+            Num::Slot(slot) => b.use_var(Variable::new(*slot)),
+            Num::Add(l, r) => {
+                let (l, r) = self.binary(b, l, r);
+                b.ins().fadd(l, r)
+            }
+            Num::Subtract(l, r) => {
+                let (l, r) = self.binary(b, l, r);
+                b.ins().fsub(l, r)
+            }
+            Num::Multiply(l, r) => {
+                let (l, r) = self.binary(b, l, r);
+                b.ins().fmul(l, r)
+            }
+            Num::Divide(l, r) => {
+                let (l, r) = self.binary(b, l, r);
+                b.ins().fdiv(l, r)
+            }
+            Num::Mod(l, r) => {
+                let (l, r) = self.binary(b, l, r);
+                let call = b.ins().call(self.helpers.modulo, &[l, r]);
+                b.inst_results(call)[0]
+            }
+            Num::Round(n) => {
+                let n = self.operand(b, n);
+                let call = b.ins().call(self.helpers.round, &[n]);
+                b.inst_results(call)[0]
+            }
+            Num::Mathop(op, n) => {
+                let n = self.operand(b, n);
+                let op = b.ins().iconst(types::I32, *op as i64);
+                let call = b.ins().call(self.helpers.mathop, &[op, n]);
+                b.inst_results(call)[0]
+            }
+        }
+    }
+}
+
+/// `Cast.toNumber` of a number: NaN becomes 0.
+fn to_number(b: &mut FunctionBuilder, value: Value) -> Value {
+    let zero = b.ins().f64const(0.0);
+    let nan = b.ins().fcmp(FloatCC::Unordered, value, value);
+    b.ins().select(nan, zero, value)
+}
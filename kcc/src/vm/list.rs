@@ -0,0 +1,102 @@
+use parking_lot::RwLock;
+use scratch_ast::{
+    cast,
+    model::{PrimitiveValue, RichValue},
+};
+
+use crate::vm::host;
+
+/// The contents of a list at runtime.
+pub type VMList = Vec<RwLock<PrimitiveValue>>;
+
+/// Lists cannot grow past this many items.
+pub const LIST_ITEM_LIMIT: usize = 200_000;
+
+/// A list index as typed by the user, resolved against a list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListIndex {
+    /// Out of range, or otherwise meaningless. Blocks do nothing.
+    Invalid,
+    /// `all`, only accepted by `delete`.
+    All,
+    /// A 0-based position inside the list.
+    Position(usize),
+}
+
+/// Scratch's `Cast.toListIndex`. Resolves a 1-based index, `last`,
+/// `random`/`any` and, if `accept_all` is set, `all`, for a list of `length` items.
+pub fn to_list_index(index: &RichValue, length: usize, accept_all: bool) -> ListIndex {
+    if let RichValue::String(s) = index {
+        match s.as_str() {
+            "all" if accept_all => return ListIndex::All,
+            "all" => return ListIndex::Invalid,
+            "last" if length > 0 => return ListIndex::Position(length - 1),
+            "random" | "any" if length > 0 => {
+                return ListIndex::Position(host::get().random_range(0..length))
+            }
+            "last" | "random" | "any" => return ListIndex::Invalid,
+            _ => (),
+        }
+    }
+    let index = cast::to_number(index).floor();
+    if index < 1.0 || index > length as f64 {
+        return ListIndex::Invalid;
+    }
+    ListIndex::Position(index as usize - 1)
+}
+
+pub fn add(list: &mut VMList, item: PrimitiveValue) {
+    if list.len() < LIST_ITEM_LIMIT {
+        list.push(RwLock::new(item));
+    }
+}
+
+pub fn delete(list: &mut VMList, index: &RichValue) {
+    match to_list_index(index, list.len(), true) {
+        ListIndex::Invalid => (),
+        ListIndex::All => list.clear(),
+        ListIndex::Position(i) => {
+            list.remove(i);
+        }
+    }
+}
+
+pub fn insert(list: &mut VMList, index: &RichValue, item: PrimitiveValue) {
+    // Inserting one past the end appends.
+    let ListIndex::Position(i) = to_list_index(index, list.len() + 1, false) else {
+        return;
+    };
+    if i >= LIST_ITEM_LIMIT {
+        return;
+    }
+    list.insert(i, RwLock::new(item));
+    if list.len() > LIST_ITEM_LIMIT {
+        list.pop();
+    }
+}
+
+pub fn replace(list: &mut VMList, index: &RichValue, item: PrimitiveValue) {
+    if let ListIndex::Position(i) = to_list_index(index, list.len(), false) {
+        *list[i].write() = item;
+    }
+}
+
+/// The item at `index`, or an empty string if there is none.
+pub fn item(list: &VMList, index: &RichValue) -> RichValue {
+    match to_list_index(index, list.len(), false) {
+        ListIndex::Position(i) => list[i].read().clone().into(),
+        _ => RichValue::String("".to_string()),
+    }
+}
+
+/// The 1-based position of the first item equal to `item`, or 0.
+/// Items are compared like the `=` block, so `"ABC"` matches `"abc"` and `"1.0"` matches `1`.
+pub fn index_of(list: &VMList, item: &RichValue) -> usize {
+    list.iter()
+        .position(|e| cast::compare(&e.read().clone().into(), item).is_eq())
+        .map_or(0, |i| i + 1)
+}
+
+pub fn contains(list: &VMList, item: &RichValue) -> bool {
+    index_of(list, item) != 0
+}
@@ -0,0 +1,200 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use log::warn;
+use parking_lot::RwLock;
+use scratch_ast::{errors::ScratchError, model::PrimitiveValue};
+
+use crate::vm::{
+    list::{VMList, LIST_ITEM_LIMIT},
+    transform::VMStartup,
+    ScratchResult,
+};
+
+/// How the items of a list are laid out in a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListFormat {
+    /// One item per line, like the files exported by the Scratch editor.
+    Lines,
+    /// Items come from one column, counted from 1, of a CSV file.
+    /// Exported lists are written as a single column.
+    Csv { column: usize },
+}
+
+/// A file a list is imported from or exported to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListFile {
+    pub path: PathBuf,
+    pub format: ListFormat,
+}
+
+impl ListFile {
+    /// Parses `<path>[:<column>]`. Files ending in `.csv` are read as CSV,
+    /// taking items from `column` or the first column; anything else is read line by line.
+    pub fn parse(spec: &str) -> Result<Self, ScratchError> {
+        let (path, column) = match spec.rsplit_once(':') {
+            Some((path, column))
+                if !column.is_empty() && column.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                let column = column.parse::<usize>().ok().filter(|c| *c > 0).ok_or(
+                    ScratchError::syntax_error(
+                        "columns are counted from 1",
+                        format!("reading list file {spec}"),
+                    ),
+                )?;
+                (path, Some(column))
+            }
+            _ => (spec, None),
+        };
+        let path = PathBuf::from(path);
+        let is_csv = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+        let format = match (is_csv, column) {
+            (true, column) => ListFormat::Csv {
+                column: column.unwrap_or(1),
+            },
+            (false, None) => ListFormat::Lines,
+            (false, Some(_)) => {
+                return Err(ScratchError::syntax_error(
+                    "only CSV files have columns",
+                    format!("reading list file {spec}"),
+                ))
+            }
+        };
+        Ok(Self { path, format })
+    }
+
+    fn location(&self) -> String {
+        format!("list file {}", self.path.display())
+    }
+
+    /// Reads the items stored in the file. Like the Scratch editor,
+    /// every item is kept as text.
+    pub fn read(&self) -> Result<Vec<PrimitiveValue>, ScratchError> {
+        let file =
+            File::open(&self.path).map_err(|e| ScratchError::not_found(e, self.location()))?;
+        let items = match self.format {
+            ListFormat::Lines => BufReader::new(file)
+                .lines()
+                .map(|l| l.map(|l| PrimitiveValue::String(l.trim_end_matches('\r').to_string())))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ScratchError::internal(e, self.location()))?,
+            ListFormat::Csv { column } => csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(file)
+                .records()
+                .map(|r| {
+                    r.map(|r| PrimitiveValue::String(r.get(column - 1).unwrap_or("").to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ScratchError::syntax_error(e, self.location()))?,
+        };
+        Ok(items)
+    }
+
+    /// Replaces the contents of the file with `items`.
+    pub fn write(&self, items: &[PrimitiveValue]) -> ScratchResult {
+        let file =
+            File::create(&self.path).map_err(|e| ScratchError::internal(e, self.location()))?;
+        match self.format {
+            ListFormat::Lines => {
+                let mut writer = BufWriter::new(file);
+                items
+                    .iter()
+                    .try_for_each(|i| writeln!(writer, "{}", String::from(i.clone())))
+                    .and_then(|_| writer.flush())
+                    .map_err(|e| ScratchError::internal(e, self.location()))
+            }
+            ListFormat::Csv { .. } => {
+                let mut writer = csv::Writer::from_writer(file);
+                items
+                    .iter()
+                    .try_for_each(|i| writer.write_record([String::from(i.clone())]))
+                    .and_then(|_| writer.flush().map_err(csv::Error::from))
+                    .map_err(|e| ScratchError::internal(e, self.location()))
+            }
+        }
+    }
+}
+
+/// Finds a list by name. Lists of the stage are looked up first,
+/// then the lists of every sprite; `<sprite>/<list>` picks the list of a given sprite
+/// when several sprites have a list with the same name.
+pub fn find_list(startup: &VMStartup, name: &str) -> Result<Arc<RwLock<VMList>>, ScratchError> {
+    let location = format!("looking up list {name}");
+    if let Some(list) = startup
+        .gstate
+        .listname_to_numid
+        .get(name)
+        .and_then(|id| startup.gstate.lists.get(id))
+    {
+        return Ok(Arc::clone(list));
+    }
+    let (sprite, name) = match name.split_once('/') {
+        Some((sprite, name)) => (Some(sprite), name),
+        None => (None, name),
+    };
+    let mut found = startup
+        .targets
+        .iter()
+        .map(|(target, _)| target)
+        .filter(|target| sprite.is_none_or(|s| s == target.name))
+        .filter_map(|target| {
+            target
+                .listname_to_numid
+                .get(name)
+                .and_then(|id| target.lists.get(id))
+                .map(|list| (target.name.as_str(), list))
+        })
+        .collect::<Vec<_>>();
+    match found.len() {
+        0 => Err(ScratchError::not_found(
+            format!("there is no list named {name}"),
+            location,
+        )),
+        1 => Ok(Arc::clone(found.remove(0).1)),
+        _ => Err(ScratchError::not_found(
+            format!(
+                "several sprites have a list named {name}, pick one with {}",
+                found
+                    .iter()
+                    .map(|(sprite, _)| format!("{sprite}/{name}"))
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+            location,
+        )),
+    }
+}
+
+/// Replaces the contents of list `name` with the items of `file`.
+pub fn import(startup: &VMStartup, name: &str, file: &ListFile) -> ScratchResult {
+    let list = find_list(startup, name)?;
+    let mut items = file.read()?;
+    if items.len() > LIST_ITEM_LIMIT {
+        warn!(
+            "{} has {} items, only the first {LIST_ITEM_LIMIT} fit in list {name}",
+            file.path.display(),
+            items.len()
+        );
+        items.truncate(LIST_ITEM_LIMIT);
+    }
+    *list.write() = items.into_iter().map(RwLock::new).collect();
+    Ok(())
+}
+
+/// Writes the contents of `list` to `file`.
+pub fn export(list: &RwLock<VMList>, file: &ListFile) -> ScratchResult {
+    let items = list
+        .read()
+        .iter()
+        .map(|i| i.read().clone())
+        .collect::<Vec<_>>();
+    file.write(&items)
+}
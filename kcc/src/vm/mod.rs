@@ -3,23 +3,74 @@ use std::sync::Arc;
 use parking_lot::RwLock;
 use scratch_ast::errors::ScratchError;
 
-use crate::vm::transform::VMStartup;
+use crate::vm::{internals::ThreadTrigger, transform::VMStartup};
 
 pub mod argaccess;
+pub mod cloud;
+pub mod coverage;
+pub mod debugger;
+pub mod host;
 pub mod intepreter;
 pub mod internals;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod list;
+pub mod listfile;
+pub mod profiler;
 pub mod terminal;
+pub mod trace;
 pub mod transform;
 
 pub type ScratchResult = Result<(), ScratchError>;
 
-pub fn run(startup: VMStartup) {
-    let global_state = Arc::new(RwLock::new(startup.gstate));
-    std::thread::scope(|scope| {
-        for (target, threads) in startup.targets {
-            let local_state = Arc::new(RwLock::new(target));
-            let cgs = Arc::clone(&global_state);
-            scope.spawn(move || intepreter::exec_source(threads, cgs, local_state).unwrap());
+/// How a project stopped running, when it did not fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ending {
+    /// Every script finished.
+    Finished,
+    /// A script ran `stop all`.
+    StopAll,
+    /// It ran more steps than [`host::Options::max_steps`] allows.
+    StepLimit,
+}
+
+impl Ending {
+    pub fn name(self) -> &'static str {
+        match self {
+            Ending::Finished => "finished",
+            Ending::StopAll => "stop all",
+            Ending::StepLimit => "step limit",
         }
-    });
+    }
+}
+
+pub fn run(startup: VMStartup) -> Result<Ending, ScratchError> {
+    run_with(startup, |runtime| runtime)
+}
+
+/// Runs a project, pausing where the debugger says to.
+pub fn debug(startup: VMStartup, debugger: debugger::Debugger) -> Result<Ending, ScratchError> {
+    run_with(startup, |runtime| runtime.with_debugger(debugger))
+}
+
+/// Runs a project, letting `setup` add a profiler or a tracer to the runtime first.
+pub fn run_with(
+    startup: VMStartup,
+    setup: impl FnOnce(intepreter::VMRuntime) -> intepreter::VMRuntime,
+) -> Result<Ending, ScratchError> {
+    let global_state = Arc::new(RwLock::new(startup.gstate));
+    cloud::attach(&global_state)?;
+    let runtime = intepreter::VMRuntime::new(Arc::clone(&global_state), startup.targets);
+    let runtime = Arc::new(setup(runtime));
+    runtime.start_detached(&ThreadTrigger::GreenFlag);
+    let result = runtime.join();
+    cloud::detach(&global_state)?;
+    result?;
+    Ok(if host::get().out_of_steps() {
+        Ending::StepLimit
+    } else if runtime.is_stopped() {
+        Ending::StopAll
+    } else {
+        Ending::Finished
+    })
 }
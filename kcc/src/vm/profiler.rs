@@ -0,0 +1,345 @@
+//! `kcc run --profile`: counts how often the blocks, scripts and custom blocks of
+//! a project run, and how long they take, attributed to the sprites they belong to.
+//!
+//! Times are wall-clock and inclusive: a `repeat` takes as long as the blocks
+//! inside it, and a `wait` as long as it waits. Each script keeps its numbers in
+//! a thread local while it runs, and adds them to the [`Profiler`] once it ends.
+//! Besides a report, the profiler writes the collapsed stacks flamegraph tools
+//! read, such as `flamegraph.pl` or `inferno-flamegraph`.
+
+use std::{
+    cell::RefCell,
+    fmt::Write as _,
+    time::{Duration, Instant},
+};
+
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use scratch_ast::model::BlockType;
+
+use crate::{
+    optimizer::header,
+    vm::{
+        intepreter::VMState,
+        internals::{Expression, ThreadTrigger},
+    },
+};
+
+/// How often something ran, and for how long in total.
+#[derive(Clone, Copy, Debug, Default)]
+struct Stat {
+    count: u64,
+    time: Duration,
+}
+
+impl Stat {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+
+    fn merge(&mut self, other: Stat) {
+        self.count += other.count;
+        self.time += other.time;
+    }
+}
+
+/// A frame of the collapsed stacks, with the frames called from it.
+#[derive(Debug, Default)]
+struct Node {
+    name: String,
+    /// The block the frame times, unless it is the script.
+    block: Option<(String, BlockType)>,
+    /// Children, by block ID.
+    children: HashMap<String, usize>,
+    /// Time spent in the frame itself, not in its children.
+    own: Duration,
+}
+
+/// What a script measured, keyed by target name like in [`Profile`].
+#[derive(Debug, Default)]
+struct Profile {
+    /// By target and block ID, with the opcode of the block.
+    blocks: HashMap<(String, String), (BlockType, Stat)>,
+    /// By target and hat, such as `when green flag clicked`.
+    scripts: HashMap<(String, String), Stat>,
+    /// By target and name, such as `jump %s high`.
+    procedures: HashMap<(String, String), Stat>,
+    /// Own time by stack of frames, joined by `;`.
+    stacks: HashMap<String, Duration>,
+}
+
+/// A frame being timed.
+struct Frame {
+    node: usize,
+    start: Instant,
+    /// Time spent in the frames it called.
+    children: Duration,
+}
+
+/// The script running on this thread.
+#[derive(Default)]
+struct Local {
+    target: String,
+    script: String,
+    /// The stack tree of the script, under a root with the script as only child.
+    nodes: Vec<Node>,
+    frames: Vec<Frame>,
+    blocks: HashMap<String, (BlockType, Stat)>,
+    procedures: HashMap<String, Stat>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<Local> = RefCell::default();
+}
+
+/// Flamegraph tools split frames at `;`, so frames cannot contain one.
+fn frame_name(name: &str) -> String {
+    name.replace(';', ",")
+}
+
+/// A block as the report and the stacks name it: its opcode, as in Scratch, and its ID.
+pub(crate) fn block_name(opcode: BlockType, id: &str) -> String {
+    match serde_json::to_value(opcode) {
+        Ok(serde_json::Value::String(opcode)) => format!("{opcode} {id}"),
+        _ => format!("{opcode:?} {id}"),
+    }
+}
+
+impl Local {
+    fn push(&mut self, block: Option<(&str, BlockType)>, name: impl FnOnce() -> String) {
+        let key = block.map_or("", |(id, _)| id);
+        let parent = self.frames.last().map_or(0, |f| f.node);
+        let node = match self.nodes[parent].children.get(key) {
+            Some(node) => *node,
+            None => {
+                let node = self.nodes.len();
+                self.nodes.push(Node {
+                    name: frame_name(&name()),
+                    block: block.map(|(id, opcode)| (id.to_string(), opcode)),
+                    ..Node::default()
+                });
+                self.nodes[parent].children.insert(key.to_string(), node);
+                node
+            }
+        };
+        self.frames.push(Frame {
+            node,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    /// Ends the innermost frame, and returns how long it took. Blocks that
+    /// run inside themselves, through custom blocks, count that time twice.
+    fn pop(&mut self) -> Duration {
+        let frame = self.frames.pop().expect("a frame to end");
+        let time = frame.start.elapsed();
+        self.nodes[frame.node].own += time.saturating_sub(frame.children);
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += time;
+        }
+        if let Some((id, opcode)) = &self.nodes[frame.node].block {
+            let (_, stat) = self
+                .blocks
+                .entry_ref(id.as_str())
+                .or_insert((*opcode, Stat::default()));
+            stat.add(time);
+        }
+        time
+    }
+
+    /// Adds the stacks under `node` to `stacks`.
+    fn collapse(&self, node: usize, prefix: &str, stacks: &mut HashMap<String, Duration>) {
+        let node_ref = &self.nodes[node];
+        let stack = match prefix {
+            "" => node_ref.name.clone(),
+            prefix => format!("{prefix};{}", node_ref.name),
+        };
+        for child in node_ref.children.values() {
+            self.collapse(*child, &stack, stacks);
+        }
+        if !node_ref.own.is_zero() {
+            *stacks.entry(stack).or_default() += node_ref.own;
+        }
+    }
+}
+
+/// Ends the timing of a block, or of a script, when dropped.
+pub struct Timing<'a> {
+    profiler: &'a Profiler,
+    kind: Timed,
+}
+
+enum Timed {
+    Script,
+    Block,
+    Procedure(String),
+}
+
+impl Drop for Timing<'_> {
+    fn drop(&mut self) {
+        LOCAL.with_borrow_mut(|local| {
+            let time = local.pop();
+            match &self.kind {
+                Timed::Script => self.profiler.end_script(local, time),
+                Timed::Block => (),
+                Timed::Procedure(name) => local.procedures.entry_ref(name).or_default().add(time),
+            }
+        });
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    profile: Mutex<Profile>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Times the script about to run on this thread, until the result is dropped.
+    pub fn script(&self, state: &VMState, trigger: &ThreadTrigger) -> Timing<'_> {
+        let script = trigger.to_string();
+        let target = state.local_state.read().name.clone();
+        LOCAL.with_borrow_mut(|local| {
+            *local = Local {
+                nodes: vec![Node::default()],
+                ..Local::default()
+            };
+            local.push(None, || format!("{target}: {script}"));
+            local.target = target;
+            local.script = script;
+        });
+        Timing {
+            profiler: self,
+            kind: Timed::Script,
+        }
+    }
+
+    /// Times a block of a script, with the blocks inside it, until the result is dropped.
+    pub fn block(&self, expression: &Expression) -> Timing<'_> {
+        let block = header(expression);
+        let id = &block.original_block.obj_id;
+        let procedure = match expression {
+            Expression::InvokeCustomBlock { header, .. } => header.proccode().map(str::to_string),
+            _ => None,
+        };
+        LOCAL.with_borrow_mut(|local| {
+            // Calls are named after the custom block, whose blocks are under it.
+            local.push(Some((id, block.opcode)), || match &procedure {
+                Some(name) => name.clone(),
+                None => block_name(block.opcode, id),
+            });
+        });
+        Timing {
+            profiler: self,
+            kind: match procedure {
+                Some(name) => Timed::Procedure(name),
+                None => Timed::Block,
+            },
+        }
+    }
+
+    /// Adds what the script on this thread measured to the profile.
+    fn end_script(&self, local: &Local, time: Duration) {
+        let mut profile = self.profile.lock();
+        let target = &local.target;
+        for (id, (opcode, stat)) in local.blocks.iter() {
+            profile
+                .blocks
+                .entry((target.clone(), id.clone()))
+                .or_insert((*opcode, Stat::default()))
+                .1
+                .merge(*stat);
+        }
+        profile
+            .scripts
+            .entry((target.clone(), local.script.clone()))
+            .or_default()
+            .add(time);
+        for (name, stat) in local.procedures.iter() {
+            profile
+                .procedures
+                .entry((target.clone(), name.clone()))
+                .or_default()
+                .merge(*stat);
+        }
+        for script in local.nodes[0].children.values() {
+            local.collapse(*script, "", &mut profile.stacks);
+        }
+    }
+
+    /// The collapsed stacks, one per line, with the time spent in their last
+    /// frame in microseconds.
+    pub fn stacks(&self) -> String {
+        let profile = self.profile.lock();
+        let mut stacks = profile
+            .stacks
+            .iter()
+            .map(|(stack, time)| (stack, time.as_micros()))
+            .filter(|(_, micros)| *micros > 0)
+            .collect::<Vec<_>>();
+        stacks.sort();
+        let mut output = String::new();
+        for (stack, micros) in stacks {
+            writeln!(output, "{stack} {micros}").unwrap();
+        }
+        output
+    }
+
+    /// What ran, slowest first, in three tables: scripts, custom blocks and blocks.
+    pub fn report(&self) -> String {
+        let profile = self.profile.lock();
+        let mut output = String::new();
+        let mut table = |title: &str, count: &str, rows: Vec<(String, Stat)>| {
+            let mut rows = rows;
+            rows.sort_by(|(a, x), (b, y)| y.time.cmp(&x.time).then_with(|| a.cmp(b)));
+            writeln!(output, "{:>12} {:>10}  {title}", "time (ms)", count).unwrap();
+            for (name, stat) in rows {
+                writeln!(
+                    output,
+                    "{:>12.3} {:>10}  {name}",
+                    stat.time.as_secs_f64() * 1000.0,
+                    stat.count
+                )
+                .unwrap();
+            }
+            output.push('\n');
+        };
+        table(
+            "script",
+            "runs",
+            profile
+                .scripts
+                .iter()
+                .map(|((target, script), stat)| (format!("{target}: {script}"), *stat))
+                .collect(),
+        );
+        table(
+            "custom block",
+            "calls",
+            profile
+                .procedures
+                .iter()
+                .map(|((target, name), stat)| (format!("{target}: {name}"), *stat))
+                .collect(),
+        );
+        table(
+            "block",
+            "runs",
+            profile
+                .blocks
+                .iter()
+                .map(|((target, id), (opcode, stat))| {
+                    (format!("{target}: {}", block_name(*opcode, id)), *stat)
+                })
+                .collect(),
+        );
+        output.truncate(output.trim_end().len());
+        output.push('\n');
+        output
+    }
+}
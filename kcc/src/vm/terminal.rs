@@ -1,7 +1,9 @@
 use colored::Colorize;
 use log::debug;
 
-use crate::vm::internals::{Expression, StackExpression, VMEvaluable, VMValuePointer};
+use crate::vm::internals::{
+    Expression, StackExpression, ThreadTrigger, VMEvaluable, VMValuePointer,
+};
 
 impl std::fmt::Display for StackExpression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -14,8 +16,11 @@ impl std::fmt::Display for StackExpression {
             "(".black(),
             {
                 let mut output = Vec::new();
+                // Sorted, so that the same block always reads the same.
+                let mut dependencies = self.dependencies.iter().collect::<Vec<_>>();
+                dependencies.sort_by_key(|(name, _)| name.as_str());
 
-                for (name, val) in &self.dependencies {
+                for (name, val) in dependencies {
                     output.push(format!(
                         "{}{}{}",
                         name.to_lowercase().black(),
@@ -108,11 +113,61 @@ impl std::fmt::Display for StackExpression {
     }
 }
 
+/// The hat of the scripts a trigger starts, e.g. `when I receive game over`.
+impl std::fmt::Display for ThreadTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GreenFlag => write!(f, "when green flag clicked"),
+            Self::Broadcast(name) => write!(f, "when I receive {name}"),
+            Self::Mutation(id) => write!(f, "custom block {id}"),
+            Self::Hat(hat) => write!(f, "{hat:?}"),
+        }
+    }
+}
+
+/// Writes the expressions of a stack inside another, one level deeper.
+fn indented(f: &mut std::fmt::Formatter<'_>, code: &[Expression]) -> std::fmt::Result {
+    for expression in code {
+        for line in expression.to_string().lines() {
+            write!(f, "\n    {line}")?;
+        }
+    }
+    Ok(())
+}
+
 impl std::fmt::Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Stack(s) => write!(f, "{}", s),
-            _ => todo!(),
+            Self::Stack(s) | Self::InvokeBroadcast(s) => write!(f, "{}", s),
+            Self::Stop { header, .. } => write!(f, "{}", header),
+            Self::InvokeCustomBlock { header, target, .. } => {
+                write!(
+                    f,
+                    "{} {} {}",
+                    header,
+                    "->".black(),
+                    target.to_string().cyan()
+                )
+            }
+            Self::Conditional {
+                header,
+                then,
+                otherwise,
+            } => {
+                write!(f, "{}", header)?;
+                indented(f, then)?;
+                if !otherwise.is_empty() {
+                    write!(f, "\n{}", "else".black())?;
+                    indented(f, otherwise)?;
+                }
+                Ok(())
+            }
+            Self::LoopTimes { header, body }
+            | Self::LoopCondition { header, body }
+            | Self::LoopForever { header, body } => {
+                write!(f, "{}", header)?;
+                indented(f, body)
+            }
         }
     }
 }
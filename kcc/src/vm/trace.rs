@@ -0,0 +1,202 @@
+//! `kcc run --trace FILE`: writes every block the interpreter runs as a line of
+//! JSON, to look at later or to compare two runs of a project.
+//!
+//! A line looks like this, and is written once the block ran, so the reporters
+//! in the inputs of a block come before it. `if`, loops, broadcasts and custom
+//! block calls are written once their inputs are evaluated, before the blocks
+//! they run, and `repeat until` and `while` once per check of their condition.
+//!
+//! ```json
+//! {"args":{"VALUE":3},"block":"b7","frame":12,"opcode":"data_setvariableto","sprite":"Sprite1",
+//!  "thread":1,"writes":[{"value":3,"variable":"score"}]}
+//! ```
+//!
+//! kcc has no screen refreshes, so `frame` is the step the block started at, as
+//! counted for `--max-steps`. `args` are the inputs as the block evaluated them.
+//! Reporters and the conditions of `if`, `repeat until` and `while` have a
+//! `result`, blocks that failed an `error`, and blocks that changed variables or
+//! lists their `writes`. Threads are numbered in the order their scripts started.
+
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use parking_lot::Mutex;
+use scratch_ast::{
+    errors::ScratchError,
+    model::{PrimitiveValue, RichValue},
+};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    cli::describe,
+    vm::{
+        host,
+        intepreter::VMState,
+        internals::{StackExpression, VMValuePointer},
+    },
+};
+
+/// A block being run.
+struct Record {
+    frame: u64,
+    block: String,
+    opcode: Value,
+    args: Map<String, Value>,
+    writes: Vec<Value>,
+}
+
+/// The script running on this thread.
+#[derive(Default)]
+struct Local {
+    thread: usize,
+    sprite: String,
+    /// The blocks being run, the innermost last.
+    records: Vec<Record>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<Local> = RefCell::default();
+}
+
+pub struct Tracer {
+    out: Mutex<BufWriter<File>>,
+    next_thread: AtomicUsize,
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer").finish_non_exhaustive()
+    }
+}
+
+/// A value as JSON: numbers and booleans as such, anything else as a string.
+fn json(value: &RichValue) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+impl Tracer {
+    pub fn create(path: &Path) -> Result<Self, ScratchError> {
+        let file = File::create(path).map_err(|e| {
+            ScratchError::internal(e, format!("creating trace file {}", path.display()))
+        })?;
+        Ok(Self {
+            out: Mutex::new(BufWriter::new(file)),
+            next_thread: AtomicUsize::new(1),
+        })
+    }
+
+    /// Numbers the script about to run on this thread.
+    pub fn thread(&self, state: &VMState) {
+        let thread = self.next_thread.fetch_add(1, Ordering::Relaxed);
+        let sprite = state.local_state.read().name.clone();
+        LOCAL.with_borrow_mut(|local| {
+            *local = Local {
+                thread,
+                sprite,
+                records: Vec::new(),
+            }
+        });
+    }
+
+    /// Runs `run`, which runs `block` or evaluates its inputs, and writes a line
+    /// for it. `result` is what the line reports the block returned, if anything.
+    pub fn block<T>(
+        &self,
+        block: &StackExpression,
+        run: impl FnOnce() -> Result<T, ScratchError>,
+        result: impl FnOnce(&T) -> Option<RichValue>,
+    ) -> Result<T, ScratchError> {
+        LOCAL.with_borrow_mut(|local| {
+            local.records.push(Record {
+                frame: host::get().steps(),
+                block: block.original_block.obj_id.clone(),
+                opcode: serde_json::to_value(block.opcode).unwrap_or(Value::Null),
+                args: Map::new(),
+                writes: Vec::new(),
+            })
+        });
+        let outcome = run();
+        let line = LOCAL.with_borrow_mut(|local| {
+            let record = local.records.pop().expect("a block being traced");
+            let mut line = json!({
+                "frame": record.frame,
+                "thread": local.thread,
+                "sprite": local.sprite,
+                "block": record.block,
+                "opcode": record.opcode,
+                "args": record.args,
+            });
+            match &outcome {
+                Ok(value) => {
+                    if let Some(value) = result(value) {
+                        line["result"] = json(&value);
+                    }
+                }
+                Err(e) => line["error"] = describe(e.clone()).into(),
+            }
+            if !record.writes.is_empty() {
+                line["writes"] = record.writes.into();
+            }
+            line
+        });
+        if let Err(e) = writeln!(self.out.lock(), "{line}") {
+            log::warn!("cannot write the trace: {e}");
+        }
+        outcome
+    }
+
+    /// Notes that the block being run evaluated its input `name` to `value`.
+    pub fn argument(&self, name: &str, value: &RichValue) {
+        LOCAL.with_borrow_mut(|local| {
+            if let Some(record) = local.records.last_mut() {
+                record.args.insert(name.to_string(), json(value));
+            }
+        });
+    }
+
+    fn write(&self, write: Value) {
+        LOCAL.with_borrow_mut(|local| {
+            if let Some(record) = local.records.last_mut() {
+                record.writes.push(write);
+            }
+        });
+    }
+
+    /// Notes that the block being run set a variable.
+    pub fn variable(&self, name: &str, value: &PrimitiveValue) {
+        self.write(json!({ "variable": name, "value": json(&value.into()) }));
+    }
+
+    /// Notes that the block being run changed a list: `add`, `delete`, `clear`,
+    /// `insert` or `replace`, with the index and the item it was given.
+    pub fn list(
+        &self,
+        list: &VMValuePointer,
+        action: &str,
+        index: Option<&RichValue>,
+        item: Option<&RichValue>,
+    ) {
+        let name = match list {
+            VMValuePointer::List { name, .. } => name.as_str(),
+            _ => "",
+        };
+        let mut write = json!({ "list": name, "action": action });
+        if let Some(index) = index {
+            write["index"] = json(index);
+        }
+        if let Some(item) = item {
+            write["item"] = json(item);
+        }
+        self.write(write);
+    }
+
+    /// Writes what is left of the trace to its file.
+    pub fn finish(&self) -> std::io::Result<()> {
+        self.out.lock().flush()
+    }
+}
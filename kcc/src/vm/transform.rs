@@ -1,11 +1,12 @@
 use std::sync::{atomic::AtomicUsize, Arc};
 
-use crate::vm::{argaccess::fetch_dependencies, internals::*};
+use crate::vm::{argaccess::fetch_dependencies, cloud, internals::*};
 use hashbrown::HashMap;
 use parking_lot::RwLock;
 use rayon::iter::{IntoParallelRefIterator, ParallelBridge, ParallelIterator};
 use scratch_ast::model::{
-    self, Block, BlockType, Mutation, PrimitiveValue, ProcedureCall, ProcedurePrototype, Target,
+    self, Block, BlockType, Mutation, PrimitiveValue, ProcedureCall, ProcedurePrototype,
+    ShadowValue, Target,
 };
 
 static ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
@@ -22,6 +23,17 @@ const HAT_BLOCKS: [BlockType; 9] = [
     BlockType::ProceduresDefinition,
 ];
 
+/// A numeric ID that no variable, list, broadcast or custom block argument has.
+pub fn fresh_id() -> usize {
+    ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+/// A numeric ID that no custom block has.
+pub fn fresh_proccode_id() -> usize {
+    PROCCODE_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn extract_threads(
     block_list: std::collections::HashMap<String, Block>,
     local_varid_to_numid: Arc<HashMap<String, usize>>,
@@ -34,8 +46,19 @@ fn extract_threads(
     global_mutation_argname_to_numid: Arc<RwLock<HashMap<String, usize>>>,
     global_mutation_argid_to_numid: Arc<RwLock<HashMap<String, usize>>>,
 ) -> VMSourceCode {
-    let bl = Arc::new(block_list);
-    let hats: Vec<&String> = bl
+    let context = ScriptContext {
+        block_list: &block_list,
+        local_varid_to_numid: &local_varid_to_numid,
+        local_listid_to_numid: &local_listid_to_numid,
+        local_broadcastid_to_numid: &local_broadcastid_to_numid,
+        global_varid_to_numid: &global_varid_to_numid,
+        global_listid_to_numid: &global_listid_to_numid,
+        global_broadcastid_to_numid: &global_broadcastid_to_numid,
+        global_mutation_proccode_to_numid: &global_mutation_proccode_to_numid,
+        global_mutation_argname_to_numid: &global_mutation_argname_to_numid,
+        global_mutation_argid_to_numid: &global_mutation_argid_to_numid,
+    };
+    let hats: Vec<&String> = block_list
         .par_iter()
         .filter_map(|(i, b)| {
             if HAT_BLOCKS.contains(&b.block_type) {
@@ -45,160 +68,218 @@ fn extract_threads(
         })
         .collect();
 
-    hats.par_iter()
-        .map(|h| {
-            extract_thread(
-                h.to_string(),
-                Arc::clone(&bl),
-                Arc::clone(&local_varid_to_numid),
-                Arc::clone(&local_listid_to_numid),
-                Arc::clone(&local_broadcastid_to_numid),
-                Arc::clone(&global_varid_to_numid),
-                Arc::clone(&global_listid_to_numid),
-                Arc::clone(&global_broadcastid_to_numid),
-                Arc::clone(&global_mutation_proccode_to_numid),
-                Arc::clone(&global_mutation_argname_to_numid),
-                Arc::clone(&global_mutation_argid_to_numid),
-            )
-        })
-        .collect()
+    let mut source_code = VMSourceCode::new();
+    for (trigger, thread) in hats
+        .par_iter()
+        .map(|h| context.extract_thread(h))
+        .collect::<Vec<_>>()
+    {
+        source_code.entry(trigger).or_default().push(thread);
+    }
+    source_code
 }
 
-#[allow(clippy::too_many_arguments)]
-fn extract_thread(
-    hat_block_id: String,
-    block_list: Arc<std::collections::HashMap<String, Block>>,
-    local_varid_to_numid: Arc<HashMap<String, usize>>,
-    local_listid_to_numid: Arc<HashMap<String, usize>>,
-    local_broadcastid_to_numid: Arc<HashMap<String, usize>>,
-    global_varid_to_numid: Arc<HashMap<String, usize>>,
-    global_listid_to_numid: Arc<HashMap<String, usize>>,
-    global_broadcastid_to_numid: Arc<HashMap<String, usize>>,
-    global_mutation_proccode_to_numid: Arc<RwLock<HashMap<String, usize>>>,
-    global_mutation_argname_to_numid: Arc<RwLock<HashMap<String, usize>>>,
-    global_mutation_argid_to_numid: Arc<RwLock<HashMap<String, usize>>>,
-) -> (ThreadTrigger, VMThread) {
-    let mut code = Vec::new();
-    let mut next_id: &Option<String> = &Some(hat_block_id);
-    let mut current_block: &Block;
-    while let Some(id) = next_id {
-        current_block = block_list.get(id).unwrap_or_else(|| {
+/// Everything needed to turn the blocks of a target into scripts.
+struct ScriptContext<'a> {
+    block_list: &'a std::collections::HashMap<String, Block>,
+    local_varid_to_numid: &'a HashMap<String, usize>,
+    local_listid_to_numid: &'a HashMap<String, usize>,
+    local_broadcastid_to_numid: &'a HashMap<String, usize>,
+    global_varid_to_numid: &'a HashMap<String, usize>,
+    global_listid_to_numid: &'a HashMap<String, usize>,
+    global_broadcastid_to_numid: &'a HashMap<String, usize>,
+    global_mutation_proccode_to_numid: &'a RwLock<HashMap<String, usize>>,
+    global_mutation_argname_to_numid: &'a RwLock<HashMap<String, usize>>,
+    global_mutation_argid_to_numid: &'a RwLock<HashMap<String, usize>>,
+}
+
+impl ScriptContext<'_> {
+    fn block(&self, id: &str) -> &Block {
+        self.block_list.get(id).unwrap_or_else(|| {
             panic!(
                 "malformed project, references a block that does not exist: {}",
                 id
             )
-        });
-        code.push(Expression::Stack(StackExpression {
-            opcode: current_block.block_type,
+        })
+    }
+
+    fn stack_expression(&self, id: &str) -> StackExpression {
+        let block = self.block(id);
+        StackExpression {
+            opcode: block.block_type,
             dependencies: fetch_dependencies(
-                current_block,
-                &local_listid_to_numid,
-                &local_varid_to_numid,
-                &local_broadcastid_to_numid,
-                &global_listid_to_numid,
-                &global_varid_to_numid,
-                &global_broadcastid_to_numid,
-                &block_list,
+                block,
+                self.local_listid_to_numid,
+                self.local_varid_to_numid,
+                self.local_broadcastid_to_numid,
+                self.global_listid_to_numid,
+                self.global_varid_to_numid,
+                self.global_broadcastid_to_numid,
+                self.block_list,
             ),
             original_block: Box::new({
-                let mut o = current_block.clone();
+                let mut o = block.clone();
                 o.obj_id = id.to_string();
                 o
             }),
-        }));
-        next_id = &current_block.next_id;
+        }
     }
 
-    let mut custom_block_arguments = HashMap::new();
-    let mut trigger: ThreadTrigger = ThreadTrigger::GreenFlag;
-    if let Some(Expression::Stack(StackExpression {
-        opcode,
-        dependencies,
-        ..
-    })) = code.get(0)
-    {
-        if opcode == &BlockType::ProceduresDefinition {
-            if let VMEvaluable::Block(prototype) = dependencies.get("custom_block").expect("Malformed custom block definition, definition hat block did not point to its prototype") {
-                if let Mutation::ProcedurePrototype(ProcedurePrototype { proccode, arguments_ids, argument_names, argument_defaults, ..}) =  prototype.original_block.mutation.as_ref().unwrap() {
-                    trigger = ThreadTrigger::Mutation(
-                            *global_mutation_proccode_to_numid
-                                .write()
-                                .entry(proccode.to_string())
-                                .or_insert_with(|| {
-                                    PROCCODE_COUNTER
-                                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-                                }),
-                        );
-                    for ((name, strid), default_value) in std::iter::zip(std::iter::zip(argument_names, arguments_ids), argument_defaults) {
-                        let aid = if let Some(a) = global_mutation_argid_to_numid.read().get(strid) {
-                            *a
-                        } else {
-                            ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-                        };
-                        global_mutation_argid_to_numid.write().insert(strid.to_owned(), aid);
-                        global_mutation_argname_to_numid.write().insert(name.to_string(), aid);
-                        custom_block_arguments.insert(aid, default_value.to_owned());
+    fn proccode_numid(&self, proccode: &str) -> usize {
+        *self
+            .global_mutation_proccode_to_numid
+            .write()
+            .entry(proccode.to_string())
+            .or_insert_with(|| PROCCODE_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+    }
+
+    fn argid_numid(&self, argid: &str) -> usize {
+        *self
+            .global_mutation_argid_to_numid
+            .write()
+            .entry(argid.to_string())
+            .or_insert_with(|| ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+    }
+
+    /// The blocks of the stack starting at `first_id`.
+    fn extract_stack(&self, first_id: Option<&String>) -> Vec<Expression> {
+        let mut code = Vec::new();
+        let mut next_id = first_id;
+        while let Some(id) = next_id {
+            code.push(self.extract_expression(id));
+            next_id = self.block(id).next_id.as_ref();
+        }
+        code
+    }
+
+    /// The stack held by input `name` of `header`, e.g. the inside of a loop.
+    fn extract_substack(&self, header: &StackExpression, name: &str) -> Vec<Expression> {
+        match header
+            .original_block
+            .inputs
+            .get(name)
+            .and_then(|i| i.value.as_ref())
+        {
+            Some(ShadowValue::Block(b)) => self.extract_stack(Some(&b.id)),
+            _ => Vec::new(),
+        }
+    }
+
+    fn extract_expression(&self, id: &str) -> Expression {
+        let mut header = self.stack_expression(id);
+        header.dependencies.remove("SUBSTACK");
+        header.dependencies.remove("SUBSTACK2");
+        match header.opcode {
+            BlockType::ControlIf | BlockType::ControlIfElse => Expression::Conditional {
+                then: self.extract_substack(&header, "SUBSTACK"),
+                otherwise: self.extract_substack(&header, "SUBSTACK2"),
+                header,
+            },
+            BlockType::ControlRepeat => Expression::LoopTimes {
+                body: self.extract_substack(&header, "SUBSTACK"),
+                header,
+            },
+            BlockType::ControlRepeatUntil | BlockType::ControlWhile => Expression::LoopCondition {
+                body: self.extract_substack(&header, "SUBSTACK"),
+                header,
+            },
+            BlockType::ControlForever => Expression::LoopForever {
+                body: self.extract_substack(&header, "SUBSTACK"),
+                header,
+            },
+            BlockType::EventBroadcast | BlockType::EventBroadcastandWait => {
+                Expression::InvokeBroadcast(header)
+            }
+            BlockType::ControlStop => {
+                let option = match header
+                    .original_block
+                    .fields
+                    .get("STOP_OPTION")
+                    .map(|f| f.value.as_str())
+                {
+                    Some("all") => StopOption::All,
+                    Some("other scripts in sprite") | Some("other scripts in stage") => {
+                        StopOption::OtherScriptsInSprite
                     }
+                    _ => StopOption::ThisScript,
+                };
+                Expression::Stop { header, option }
+            }
+            BlockType::ProceduresCall => {
+                let Some(Mutation::ProcedureCall(ProcedureCall { proccode, .. })) =
+                    header.original_block.mutation.as_ref()
+                else {
+                    panic!("malformed custom block call {id}, it has no mutation")
+                };
+                Expression::InvokeCustomBlock {
+                    target: self.proccode_numid(proccode),
+                    arguments: header
+                        .dependencies
+                        .iter()
+                        .map(|(strid, val)| (self.argid_numid(strid), val.to_owned()))
+                        .collect(),
+                    header,
                 }
             }
+            _ => Expression::Stack(header),
         }
-        for exp in code.iter_mut() {
-            if let Expression::Stack(StackExpression {
-                opcode,
-                dependencies,
-                original_block,
-            }) = exp
-            {
-                if opcode == &BlockType::ProceduresCall {
-                    if let Mutation::ProcedureCall(ProcedureCall { proccode, .. }) =
-                        original_block.mutation.as_ref().unwrap()
-                    {
-                        global_mutation_proccode_to_numid
-                            .write()
-                            .entry(proccode.to_string())
-                            .or_insert_with(|| {
-                                PROCCODE_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-                            });
-
-                        *exp = Expression::InvokeCustomBlock {
-                            target: match global_mutation_proccode_to_numid.read().get(proccode) {
-                                Some(s) => *s,
-                                None => {
-                                    let numproc = PROCCODE_COUNTER
-                                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                                    global_mutation_proccode_to_numid
-                                        .write()
-                                        .insert(proccode.to_string(), numproc)
-                                        .expect("making new custom block id failed");
-                                    numproc
-                                }
-                            },
-                            arguments: dependencies
-                                .iter()
-                                .map(|(strid, val)| {
-                                    let aid = *global_mutation_argid_to_numid
-                                        .write()
-                                        .entry(strid.to_owned())
-                                        .or_insert_with(|| {
-                                            ID_COUNTER
-                                                .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-                                        });
-                                    (aid, val.to_owned())
-                                })
-                                .collect(),
-                        };
-                    }
+    }
+
+    fn extract_thread(&self, hat_block_id: &String) -> (ThreadTrigger, VMThread) {
+        let code = self.extract_stack(Some(hat_block_id));
+
+        let mut custom_block_arguments = HashMap::new();
+        let mut argument_names = HashMap::new();
+        let Some(Expression::Stack(hat)) = code.first() else {
+            unreachable!("scripts always start with their hat block")
+        };
+        let trigger = match hat.opcode {
+            BlockType::EventWhenFlagClicked => ThreadTrigger::GreenFlag,
+            BlockType::EventWhenBroadcastReceived => ThreadTrigger::Broadcast(
+                hat.original_block
+                    .fields
+                    .get("BROADCAST_OPTION")
+                    .map(|f| f.value.to_lowercase())
+                    .unwrap_or_default(),
+            ),
+            BlockType::ProceduresDefinition => {
+                let Some(VMEvaluable::Block(prototype)) = hat.dependencies.get("custom_block")
+                else {
+                    panic!("Malformed custom block definition, definition hat block did not point to its prototype")
+                };
+                let Some(Mutation::ProcedurePrototype(ProcedurePrototype {
+                    proccode,
+                    arguments_ids,
+                    argument_names: names,
+                    argument_defaults,
+                    ..
+                })) = prototype.original_block.mutation.as_ref()
+                else {
+                    panic!("Malformed custom block prototype, it has no mutation")
+                };
+                for ((name, strid), default_value) in
+                    std::iter::zip(std::iter::zip(names, arguments_ids), argument_defaults)
+                {
+                    let aid = self.argid_numid(strid);
+                    self.global_mutation_argname_to_numid
+                        .write()
+                        .insert(name.to_string(), aid);
+                    argument_names.insert(name.to_string(), aid);
+                    custom_block_arguments.insert(aid, default_value.to_owned());
                 }
+                ThreadTrigger::Mutation(self.proccode_numid(proccode))
             }
-        }
+            opcode => ThreadTrigger::Hat(opcode),
+        };
+        (
+            trigger,
+            VMThread {
+                code,
+                custom_block_arguments,
+                argument_names,
+            },
+        )
     }
-    (
-        trigger,
-        VMThread {
-            code,
-            custom_block_arguments,
-        },
-    )
 }
 
 pub struct VMStartup {
@@ -211,8 +292,11 @@ impl From<model::Project> for VMStartup {
         let mut global_listid_to_value = hashbrown::HashMap::new();
         let mut global_varid_to_value = hashbrown::HashMap::new();
         let mut global_broadcastid_to_value = hashbrown::HashMap::new();
+        let mut global_variable_names = hashbrown::HashMap::new();
+        let mut cloud_names = hashbrown::HashMap::new();
         let mut global_varid_to_numid: Arc<HashMap<String, usize>> = Arc::new(HashMap::new());
         let mut global_listid_to_numid: Arc<HashMap<String, usize>> = Arc::new(HashMap::new());
+        let mut global_listname_to_numid: Arc<HashMap<String, usize>> = Arc::new(HashMap::new());
         let mut global_broadcastid_to_numid: Arc<HashMap<String, usize>> = Arc::new(HashMap::new());
 
         let global_mutation_proccode_to_numid: Arc<RwLock<HashMap<String, usize>>> =
@@ -308,13 +392,21 @@ impl From<model::Project> for VMStartup {
                             (*numid, s.broadcasts.get(strid).unwrap().to_string())
                         })
                         .collect();
+                    let listname_to_numid = listid_to_numid
+                        .iter()
+                        .map(|(k, v)| (s.lists[k].name.clone(), *v))
+                        .collect();
                     target_tuple.push((
                         VMLocalState {
                             name: s.name.clone(),
                             variables: numid_to_varvalue,
+                            variable_names: varid_to_numid
+                                .iter()
+                                .map(|(k, v)| (*v, s.variables[k].name.clone()))
+                                .collect(),
                             lists: numid_to_listvalue,
                             broadcasts: broadcastid_to_value,
-                            listname_to_numid: Arc::clone(&listid_to_numid),
+                            listname_to_numid: Arc::new(listname_to_numid),
                             varname_to_numi: Arc::clone(&varid_to_numid),
                             broadcastname_to_numid: Arc::clone(&broadcastid_to_numid),
                         },
@@ -361,6 +453,22 @@ impl From<model::Project> for VMStartup {
                         })
                         .collect();
 
+                    global_variable_names = global_varid_to_numid
+                        .iter()
+                        .map(|(k, v)| (*v, s.variables[k].name.clone()))
+                        .collect();
+
+                    cloud_names = global_varid_to_numid
+                        .iter()
+                        .filter_map(|(k, v)| {
+                            let var = s.variables.get(k)?;
+                            if var.is_cloud || cloud::is_cloud_name(&var.name) {
+                                return Some((*v, var.name.clone()));
+                            }
+                            None
+                        })
+                        .collect();
+
                     global_listid_to_numid = Arc::new(
                         s.lists
                             .keys()
@@ -394,6 +502,13 @@ impl From<model::Project> for VMStartup {
                         output
                     };
 
+                    global_listname_to_numid = Arc::new(
+                        global_listid_to_numid
+                            .iter()
+                            .map(|(k, v)| (s.lists[k].name.clone(), *v))
+                            .collect(),
+                    );
+
                     global_broadcastid_to_numid = Arc::new(
                         s.broadcasts
                             .keys()
@@ -416,10 +531,11 @@ impl From<model::Project> for VMStartup {
                         VMLocalState {
                             name: s.name.clone(),
                             variables: HashMap::new(),
+                            variable_names: HashMap::new(),
                             lists: HashMap::new(),
                             broadcasts: HashMap::new(),
-                            listname_to_numid: Arc::clone(&global_varid_to_numid),
-                            varname_to_numi: Arc::clone(&global_listid_to_numid),
+                            listname_to_numid: Arc::clone(&global_listname_to_numid),
+                            varname_to_numi: Arc::clone(&global_varid_to_numid),
                             broadcastname_to_numid: Arc::clone(&global_broadcastid_to_numid),
                         },
                         extract_threads(
@@ -443,14 +559,17 @@ impl From<model::Project> for VMStartup {
             gstate: VMGlobalState {
                 lists: global_listid_to_value,
                 variables: global_varid_to_value,
+                variable_names: global_variable_names,
                 broadcasts: global_broadcastid_to_value,
-                listname_to_numid: Arc::clone(&global_listid_to_numid),
+                listname_to_numid: global_listname_to_numid,
                 varname_to_numid: Arc::clone(&global_varid_to_numid),
                 broadcastname_to_numid: Arc::clone(&global_broadcastid_to_numid),
                 mutationname_to_numid: Arc::new({
                     let x = global_mutation_argname_to_numid.read().clone();
                     x
                 }),
+                cloud_names,
+                cloud: None,
             },
             targets: target_tuple,
         }
@@ -0,0 +1,144 @@
+//! Compiles every project of a suite to bytecode, runs the `.kbc` file, and
+//! compares what it says with the `.out` file next to it. The `cli` suite runs
+//! with the options `tests/cli.rs` gives it, such as a virtual clock. Also runs projects
+//! through the bytecode cache, both when it misses and when it hits, and next to
+//! entries left by other builds of kcc.
+
+use std::{fs, path::Path, process::Command};
+
+mod common;
+
+fn kcc(args: &[&Path]) -> (String, String, bool) {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(args)
+        .output()
+        .expect("kcc runs");
+    (
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+        output.status.success(),
+    )
+}
+
+fn run_suite(suite: &str, options: &[&str]) {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    common::check_suite(suite, |project| {
+        let name = project.file_stem().unwrap().to_string_lossy().to_string();
+        let (_, stderr, compiled) = kcc(&[
+            Path::new("compile"),
+            Path::new("--target"),
+            Path::new("bytecode"),
+            Path::new("-o"),
+            work_dir.path(),
+            project,
+        ]);
+        assert!(
+            compiled,
+            "compiling {} failed:\n{stderr}",
+            project.display()
+        );
+        let compiled = work_dir.path().join(format!("{name}.kbc"));
+        let mut args = options.iter().map(Path::new).collect::<Vec<_>>();
+        args.push(&compiled);
+        let (actual, stderr, _) = kcc(&args);
+        (actual, stderr)
+    });
+}
+
+#[test]
+fn lists() {
+    run_suite("lists", &[]);
+}
+
+#[test]
+fn control() {
+    run_suite("control", &[]);
+}
+
+#[test]
+fn operators() {
+    run_suite("operators", &[]);
+}
+
+#[test]
+fn optimizer() {
+    run_suite("optimizer", &[]);
+}
+
+/// Includes `waits.sb3`, whose busy loop only ends if the virtual clock moves
+/// while a script is running.
+#[test]
+fn cli() {
+    let options = ["--seed", "7", "--virtual-clock", "--max-steps", "100000"];
+    run_suite("cli", &options);
+}
+
+#[test]
+fn cache() {
+    let cache_dir = tempfile::tempdir().expect("temporary directory");
+    let project = common::projects("control")
+        .into_iter()
+        .next()
+        .expect("control suite has projects");
+    let expected = common::expected(&project);
+    let run = || {
+        kcc(&[
+            Path::new("--bytecode"),
+            Path::new("--cache-dir"),
+            cache_dir.path(),
+            &project,
+        ])
+    };
+    let cached = |dir: &Path| {
+        fs::read_dir(dir)
+            .expect("cache directory exists")
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>()
+    };
+
+    let (actual, stderr, _) = run();
+    assert_eq!(actual, expected, "on a miss:\n{stderr}");
+    let entries = cached(cache_dir.path());
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].extension().unwrap(), "kbc");
+
+    let (actual, stderr, _) = run();
+    assert_eq!(actual, expected, "on a hit:\n{stderr}");
+
+    // Files from another version of the format are replaced.
+    let mut stale = fs::read(&entries[0]).unwrap();
+    stale[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&entries[0], &stale).unwrap();
+    let (actual, stderr, _) = run();
+    assert_eq!(actual, expected, "on a stale entry:\n{stderr}");
+    assert_ne!(fs::read(&entries[0]).unwrap(), stale);
+
+    // Files of other builds of kcc are named differently, and never used.
+    let name = entries[0]
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    assert!(name.contains(env!("CARGO_PKG_VERSION")), "{name}");
+    let (hash, _) = name.split_once('-').expect("versioned name");
+    let other = common::projects("control")
+        .into_iter()
+        .find(|p| common::expected(p) != expected)
+        .expect("control suite has projects saying other things");
+    let (_, stderr, compiled) = kcc(&[
+        Path::new("compile"),
+        Path::new("--target"),
+        Path::new("bytecode"),
+        Path::new("-o"),
+        cache_dir.path(),
+        &other,
+    ]);
+    assert!(compiled, "compiling {} failed:\n{stderr}", other.display());
+    let old = cache_dir
+        .path()
+        .join(other.with_extension("kbc").file_name().unwrap());
+    fs::rename(old, cache_dir.path().join(format!("{hash}.kbc"))).unwrap();
+    fs::remove_file(&entries[0]).unwrap();
+    let (actual, stderr, _) = run();
+    assert_eq!(actual, expected, "next to an older entry:\n{stderr}");
+}
@@ -0,0 +1,73 @@
+//! Compiles every project of a suite to C, builds it with `cc`, and compares
+//! what it says with the `.out` file next to it, which the interpreter says too.
+
+use std::process::Command;
+
+mod common;
+
+fn run_suite(suite: &str) {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    common::check_suite(suite, |project| {
+        let name = project.file_stem().unwrap().to_string_lossy().to_string();
+        let out_dir = work_dir.path().join(&name);
+        let compiled = Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .arg("compile")
+            .args(["--target", "c", "-o"])
+            .arg(&out_dir)
+            .arg(project)
+            .output()
+            .expect("kcc runs");
+        assert!(
+            compiled.status.success(),
+            "compiling {} failed:\n{}",
+            project.display(),
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+        let binary = out_dir.join(&name);
+        let built = Command::new(std::env::var("CC").unwrap_or("cc".to_string()))
+            .args(["-std=c99", "-O1", "-o"])
+            .arg(&binary)
+            .arg(out_dir.join(format!("{name}.c")))
+            .arg("-lm")
+            .output()
+            .expect("cc runs");
+        assert!(
+            built.status.success(),
+            "building {} failed:\n{}",
+            project.display(),
+            String::from_utf8_lossy(&built.stderr)
+        );
+        let output = common::answering(project, &mut Command::new(&binary));
+        common::said(&output, output.status.success())
+    });
+}
+
+#[test]
+fn lists() {
+    run_suite("lists");
+}
+
+#[test]
+fn control() {
+    run_suite("control");
+}
+
+#[test]
+fn operators() {
+    run_suite("operators");
+}
+
+#[test]
+fn optimizer() {
+    run_suite("optimizer");
+}
+
+#[test]
+fn jit() {
+    run_suite("jit");
+}
+
+#[test]
+fn js() {
+    run_suite("js");
+}
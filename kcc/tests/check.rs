@@ -0,0 +1,21 @@
+//! Runs `kcc check --types` on every project in `tests/check` and compares what
+//! it prints with the `.out` file next to it. Projects with warnings or blocks
+//! kcc cannot run must fail.
+
+use std::process::Command;
+
+mod common;
+
+#[test]
+fn check() {
+    common::check_suite("check", |project| {
+        let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .args(["check", "--types"])
+            .arg(project)
+            .output()
+            .expect("kcc runs");
+        let expected = common::expected(project);
+        let problems = expected.contains("warning: ") || expected.contains("error: ");
+        common::said(&output, output.status.success() != problems)
+    });
+}
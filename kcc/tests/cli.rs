@@ -0,0 +1,130 @@
+//! Tests the options of `kcc run` and the exit codes of kcc. The projects in
+//! `tests/cli` run with a seed, a virtual clock and a step limit, with both the
+//! interpreter and the bytecode machine, and must say what the `.out` file next
+//! to them says.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use serde_json::Value;
+
+mod common;
+
+fn kcc(args: &[&str], project: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(args)
+        .arg(project)
+        .stdin(Stdio::null())
+        .output()
+        .expect("kcc runs")
+}
+
+fn fixture(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../tests")
+        .join(path)
+}
+
+#[test]
+fn suite() {
+    for engine in [&[][..], &["--bytecode"]] {
+        let mut args = vec!["--seed", "7", "--virtual-clock", "--max-steps", "100000"];
+        args.extend(engine);
+        common::check_suite("cli", |project| common::said(&kcc(&args, project), true));
+    }
+}
+
+/// With a virtual clock, the interpreter runs its script threads one at a time
+/// in a fixed order, so a project with scripts waiting for each other and for
+/// the clock says the same every run.
+#[test]
+fn repeatable() {
+    let project = fixture("cli/waits.sb3");
+    let expected = common::expected(&project);
+    for _ in 0..50 {
+        let output = kcc(&["--virtual-clock", "--max-steps", "100000"], &project);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+}
+
+#[test]
+fn exit_codes() {
+    let not_a_project = fixture("cli/random.out");
+    let cases: [(&[&str], PathBuf, i32); 9] = [
+        (&[], fixture("control/repeat.sb3"), 0),
+        (&["run", "--bytecode"], fixture("control/repeat.sb3"), 0),
+        (&[], fixture("control/stop_all.sb3"), 4),
+        (&["--bytecode"], fixture("control/stop_all.sb3"), 4),
+        (&["--max-steps", "1000"], fixture("cli/forever.sb3"), 5),
+        (
+            &["--bytecode", "--max-steps", "1000"],
+            fixture("cli/forever.sb3"),
+            5,
+        ),
+        (&[], not_a_project.clone(), 3),
+        (&["--no-such-option"], fixture("control/repeat.sb3"), 2),
+        (&["check"], fixture("check/unsupported.sb3"), 6),
+    ];
+    for (args, project, code) in cases {
+        let output = kcc(args, &project);
+        assert_eq!(
+            output.status.code(),
+            Some(code),
+            "kcc {args:?} {}:\n{}",
+            project.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    for command in ["check", "dump", "info", "compile"] {
+        let output = kcc(&[command], &not_a_project);
+        assert_eq!(
+            output.status.code(),
+            Some(3),
+            "kcc {command} of a broken file"
+        );
+    }
+}
+
+/// `ask and wait` takes answers from the command line, then from a file, and
+/// never waits for stdin when there are enough of them.
+#[test]
+fn answers() {
+    let project = fixture("js/ask.sb3");
+    let expected = common::expected(&project);
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let answers = work_dir.path().join("answers.txt");
+    fs::write(&answers, "scratch\n").unwrap();
+    let answers = answers.to_str().unwrap();
+    for engine in [&[][..], &["--bytecode"]] {
+        let mut args = vec!["--answer", "Kat", "--answers-file", answers];
+        args.extend(engine);
+        let output = kcc(&args, &project);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+}
+
+#[test]
+fn json_output() {
+    let project = fixture("control/stop_all.sb3");
+    for engine in [&[][..], &["--bytecode"]] {
+        let mut args = vec!["--output", "json"];
+        args.extend(engine);
+        let output = kcc(&args, &project);
+        let events = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).expect("a JSON object per line"))
+            .collect::<Vec<_>>();
+        let said = events
+            .iter()
+            .filter(|e| e["event"] == "say")
+            .map(|e| format!("{}\n", e["text"].as_str().unwrap()))
+            .collect::<String>();
+        assert_eq!(said, common::expected(&project));
+        let end = events.last().expect("an end event");
+        assert_eq!(end["event"], "end");
+        assert_eq!(end["status"], "stop all");
+    }
+}
@@ -0,0 +1,283 @@
+//! Runs the projects in `tests/cloud` with cloud variables kept in a file, and
+//! with a cloud server that the test talks to as another client would.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+use tungstenite::{Message, WebSocket};
+
+mod common;
+
+fn project(name: &str) -> PathBuf {
+    common::projects("cloud")
+        .into_iter()
+        .find(|p| p.file_stem().is_some_and(|s| s == name))
+        .unwrap_or_else(|| panic!("no project {name} in tests/cloud"))
+}
+
+fn run_with_file(project: &Path, file: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(["run", "--cloud-file"])
+        .arg(file)
+        .arg(project)
+        .output()
+        .expect("kcc runs")
+}
+
+fn read_json(path: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(path).expect("cloud file written")).unwrap()
+}
+
+fn check(project: &Path, output: &Output, expected: &str) {
+    let actual = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success() && actual == expected,
+        "{}",
+        common::failure(
+            project,
+            expected,
+            &actual,
+            &String::from_utf8_lossy(&output.stderr)
+        )
+    );
+}
+
+#[test]
+fn file_keeps_values_between_runs() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let file = work_dir.path().join("cloud.json");
+    let counter = project("counter");
+    check(
+        &counter,
+        &run_with_file(&counter, &file),
+        &common::expected(&counter),
+    );
+    assert_eq!(read_json(&file), json!({"☁ count": "1"}));
+    check(&counter, &run_with_file(&counter, &file), "2\n");
+    assert_eq!(read_json(&file), json!({"☁ count": "2"}));
+}
+
+/// Changes made faster than the file is written are written together, and the
+/// last ones when the project ends.
+#[test]
+fn file_keeps_the_last_of_many_changes() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let file = work_dir.path().join("cloud.json");
+    let busy = project("busy");
+    check(
+        &busy,
+        &run_with_file(&busy, &file),
+        &common::expected(&busy),
+    );
+    assert_eq!(read_json(&file), json!({"☁ count": "1000"}));
+    check(
+        &busy,
+        &run_with_file(&busy, &file),
+        "2000
+",
+    );
+    assert_eq!(read_json(&file), json!({"☁ count": "2000"}));
+}
+
+/// Like on the Scratch website, words and values longer than 256 characters
+/// only change the variable locally.
+#[test]
+fn only_numbers_reach_the_cloud() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let file = work_dir.path().join("cloud.json");
+    let invalid = project("invalid");
+    check(
+        &invalid,
+        &run_with_file(&invalid, &file),
+        &common::expected(&invalid),
+    );
+    assert_eq!(
+        read_json(&file),
+        json!({
+            "☁ word": "0",
+            "☁ long": "1".repeat(256),
+            "☁ longest": "0",
+            "☁ ok": "12",
+        })
+    );
+}
+
+/// Kills kcc if the test fails before it exits.
+struct Running(Option<Child>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Some(child) = self.0.as_mut() {
+            let _ = child.kill();
+        }
+    }
+}
+
+fn connect(port: u16, project: &str) -> WebSocket<TcpStream> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => break stream,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("kcc never started its cloud server: {e}"),
+        }
+    };
+    stream
+        .set_read_timeout(Some(Duration::from_millis(50)))
+        .unwrap();
+    let (mut socket, _) =
+        tungstenite::client(format!("ws://127.0.0.1:{port}/"), stream).expect("handshake");
+    send(
+        &mut socket,
+        json!({"method": "handshake", "user": "test", "project_id": project}),
+    );
+    socket
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: Value) {
+    socket.send(Message::text(message.to_string())).unwrap();
+}
+
+/// The messages received until `done` says so, or until nothing came for a while.
+fn receive(socket: &mut WebSocket<TcpStream>, done: impl Fn(&Value) -> bool) -> Vec<Value> {
+    let mut messages = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut quiet_since = Instant::now();
+    while Instant::now() < deadline && quiet_since.elapsed() < Duration::from_secs(1) {
+        match socket.read() {
+            Ok(Message::Text(frame)) => {
+                quiet_since = Instant::now();
+                for line in frame.as_str().lines() {
+                    let message: Value = serde_json::from_str(line).unwrap();
+                    let stop = done(&message);
+                    messages.push(message);
+                    if stop {
+                        return messages;
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => panic!("lost the cloud server: {e}"),
+        }
+    }
+    messages
+}
+
+fn is(message: &Value, method: &str, name: &str) -> bool {
+    message["method"] == method && message["name"] == name
+}
+
+#[test]
+fn websocket_server() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .expect("free port")
+        .port();
+    let waiter = project("waiter");
+    let mut kcc = Running(Some(
+        Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .args(["run", "--cloud-ws", &format!("127.0.0.1:{port}")])
+            .arg(&waiter)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("kcc runs"),
+    ));
+
+    let mut alice = connect(port, "waiter");
+    receive(&mut alice, |m| is(m, "set", "☁ ready") && m["value"] == "1");
+    let mut bob = connect(port, "waiter");
+    // Bob is in once the server told him what it knows.
+    receive(&mut bob, |m| is(m, "set", "☁ ready"));
+    // Only numbers in cloud variables are shared with the others.
+    send(
+        &mut alice,
+        json!({"method": "set", "name": "score", "value": 5}),
+    );
+    send(
+        &mut alice,
+        json!({"method": "set", "name": "☁ answer", "value": "abc"}),
+    );
+    send(
+        &mut alice,
+        json!({"method": "set", "name": "☁ answer", "value": 42}),
+    );
+    send(&mut alice, json!({"method": "delete", "name": "☁ ready"}));
+    let seen = receive(&mut bob, |m| is(m, "delete", "☁ ready"));
+    assert!(seen.iter().any(|m| is(m, "delete", "☁ ready")), "{seen:?}");
+    assert!(
+        seen.iter()
+            .any(|m| is(m, "set", "☁ answer") && m["value"] == "42"),
+        "{seen:?}"
+    );
+    assert!(
+        !seen
+            .iter()
+            .any(|m| m["name"] == "score" || m["value"] == "abc"),
+        "{seen:?}"
+    );
+
+    // Newcomers are told every value the server knows.
+    let mut carol = connect(port, "waiter");
+    let known = receive(&mut carol, |_| false)
+        .into_iter()
+        .filter(|m| m["method"] == "set")
+        .map(|m| (m["name"].as_str().unwrap().to_string(), m["value"].clone()))
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(
+        json!(known),
+        json!({"☁ answer": "42", "☁ go": "0"}),
+        "the server knows other values"
+    );
+
+    send(
+        &mut alice,
+        json!({"method": "set", "name": "☁ go", "value": 1}),
+    );
+    let output = kcc.0.take().unwrap().wait_with_output().expect("kcc exits");
+    check(&waiter, &output, &common::expected(&waiter));
+}
+
+/// A server that accepts the connection but never answers the WebSocket
+/// handshake makes kcc give up instead of waiting forever.
+#[test]
+fn silent_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("free port");
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let _connections = listener.incoming().collect::<Vec<_>>();
+    });
+    let mut kcc = Running(Some(
+        Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .args(["run", "--cloud-ws", &format!("127.0.0.1:{port}")])
+            .arg(project("counter"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("kcc runs"),
+    ));
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let child = kcc.0.as_mut().unwrap();
+    while child.try_wait().unwrap().is_none() {
+        assert!(
+            Instant::now() < deadline,
+            "kcc still waits for the handshake"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+    let output = kcc.0.take().unwrap().wait_with_output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("handshake"), "{stderr}");
+}
@@ -0,0 +1,83 @@
+//! Helpers shared by the tests running the projects in `tests/<suite>`.
+// Every test includes this module, most use only some of it.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+fn suite_dir(suite: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join("tests")
+        .join(suite)
+}
+
+/// The projects of a suite, sorted by name.
+pub fn projects(suite: &str) -> Vec<PathBuf> {
+    let mut projects = fs::read_dir(suite_dir(suite))
+        .expect("suite directory exists")
+        .map(|e| e.expect("readable suite directory").path())
+        .filter(|p| p.extension().is_some_and(|e| e == "sb3"))
+        .collect::<Vec<_>>();
+    projects.sort();
+    assert!(!projects.is_empty(), "suite {suite} has no projects");
+    projects
+}
+
+/// What a project is expected to say.
+pub fn expected(project: &Path) -> String {
+    fs::read_to_string(project.with_extension("out"))
+        .unwrap_or_else(|e| panic!("missing expected output of {}: {e}", project.display()))
+}
+
+/// Describes a project whose output differs from the expected one.
+pub fn failure(project: &Path, expected: &str, actual: &str, stderr: &str) -> String {
+    format!(
+        "{}\n--- expected\n{expected}--- actual\n{actual}--- stderr\n{stderr}",
+        project.display()
+    )
+}
+
+/// Runs `command`, answering the questions of `project` with the lines of the
+/// `.in` file next to it, if there is one.
+pub fn answering(project: &Path, command: &mut Command) -> Output {
+    let answers = fs::read(project.with_extension("in")).unwrap_or_default();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("program runs");
+    child.stdin.take().unwrap().write_all(&answers).unwrap();
+    child.wait_with_output().expect("program finishes")
+}
+
+/// What a program said and printed to stderr. A program that did not end the way
+/// it should says so after its output, failing the comparison with the `.out` file.
+pub fn said(output: &Output, ended: bool) -> (String, String) {
+    let mut stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if !ended {
+        stdout.push_str(&format!("[{}]\n", output.status));
+    }
+    (stdout, String::from_utf8_lossy(&output.stderr).to_string())
+}
+
+/// Runs every project of a suite with `run`, which returns what it said and what
+/// it printed to stderr, and fails listing every project that did not say what
+/// its `.out` file says.
+#[track_caller]
+pub fn check_suite(suite: &str, mut run: impl FnMut(&Path) -> (String, String)) {
+    let mut failures = Vec::new();
+    for project in projects(suite) {
+        let expected = expected(&project);
+        let (actual, stderr) = run(&project);
+        if actual != expected {
+            failures.push(failure(&project, &expected, &actual, &stderr));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,42 @@
+//! Runs every project of a suite in `tests/<suite>`, answering its questions with
+//! the `.in` file next to it, and compares what it says with the `.out` file.
+
+use std::process::Command;
+
+mod common;
+
+fn run_suite(suite: &str) {
+    common::check_suite(suite, |project| {
+        let output = common::answering(
+            project,
+            Command::new(env!("CARGO_BIN_EXE_kcc")).arg(project),
+        );
+        // Projects running `stop all` exit with 4.
+        common::said(&output, matches!(output.status.code(), Some(0 | 4)))
+    });
+}
+
+#[test]
+fn lists() {
+    run_suite("lists");
+}
+
+#[test]
+fn control() {
+    run_suite("control");
+}
+
+#[test]
+fn operators() {
+    run_suite("operators");
+}
+
+#[test]
+fn optimizer() {
+    run_suite("optimizer");
+}
+
+#[test]
+fn js() {
+    run_suite("js");
+}
@@ -0,0 +1,103 @@
+//! Tests `kcc run --coverage`.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+use serde_json::Value;
+
+mod common;
+
+/// Runs a project with `--coverage`, and returns its output with the report.
+fn cover(project: &Path, report: &Path, args: &[&str]) -> (Output, Value) {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("run")
+        .args(args)
+        .arg("--coverage")
+        .arg(report)
+        .arg(project)
+        .stdin(Stdio::null())
+        .output()
+        .expect("kcc runs");
+    let json = fs::read_to_string(report).expect("a coverage report");
+    (output, serde_json::from_str(&json).expect("valid JSON"))
+}
+
+/// Coverage does not change what a project says, and every project runs some blocks.
+#[test]
+fn suite() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    common::check_suite("lists", |project| {
+        let report = work_dir.path().join("coverage.json");
+        let _ = fs::remove_file(&report);
+        let (output, report) = cover(project, &report, &[]);
+        let covered = report["targets"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|t| t["blocks"]["covered"].as_u64().unwrap())
+            .sum::<u64>();
+        assert!(covered > 0, "{}: {report}", project.display());
+        common::said(&output, true)
+    });
+}
+
+/// Two runs taking each a branch of an `if else` cover both, but not the script
+/// nobody starts.
+#[test]
+fn merged_runs() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let project = &common::projects("coverage")[0];
+    let report = work_dir.path().join("coverage.json");
+    let svg = work_dir.path().join("coverage.svg");
+
+    let (output, first) = cover(project, &report, &["--answer", "yes"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        common::expected(project)
+    );
+    let sprite = &first["targets"]["Sprite1"];
+    assert_eq!(sprite["branches"]["covered"], 1);
+    let (id, branches) = sprite["ifs"].as_object().unwrap().iter().next().unwrap();
+    assert_eq!(
+        (branches["then"].as_u64(), branches["else"].as_u64()),
+        (Some(1), Some(0))
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("total (1 runs)"), "{stderr}");
+
+    let svg_arg = svg.to_string_lossy().to_string();
+    let (_, second) = cover(
+        project,
+        &report,
+        &["--answer", "no", "--coverage-svg", &svg_arg],
+    );
+    assert_eq!(second["runs"], 2);
+    let sprite = &second["targets"]["Sprite1"];
+    assert_eq!(sprite["ifs"][id]["else"], 1);
+    assert_eq!(sprite["branches"]["covered"], 2);
+    assert_eq!(sprite["blocks"]["total"], 9);
+    assert_eq!(sprite["blocks"]["covered"], 7);
+    assert_eq!(sprite["uncovered"].as_array().unwrap().len(), 2);
+    assert_eq!(sprite["counts"][id], 2);
+
+    let svg = fs::read_to_string(svg).expect("an SVG");
+    assert!(svg.starts_with("<svg"), "{svg}");
+    assert_eq!(svg.matches("class=\"missed\"").count(), 2, "{svg}");
+}
+
+#[test]
+fn only_the_interpreter_covers() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let status = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(["run", "--bytecode", "--coverage"])
+        .arg(work_dir.path().join("coverage.json"))
+        .arg(&common::projects("coverage")[0])
+        .stderr(Stdio::null())
+        .status()
+        .expect("kcc runs");
+    assert_eq!(status.code(), Some(1));
+}
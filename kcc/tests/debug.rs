@@ -0,0 +1,57 @@
+//! Drives `kcc debug` through stdin. Each project in `tests/debug` has the
+//! commands to type in a `.commands` file, and what the debugger and the project
+//! print in its `.out` file.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+mod common;
+
+fn debug(args: &[&str], project: &Path, commands: &str) -> Output {
+    let mut kcc = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("debug")
+        .args(args)
+        .arg(project)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("kcc runs");
+    let mut stdin = kcc.stdin.take().unwrap();
+    stdin.write_all(commands.as_bytes()).unwrap();
+    drop(stdin);
+    kcc.wait_with_output().expect("kcc finishes")
+}
+
+#[test]
+fn sessions() {
+    common::check_suite("debug", |project| {
+        let commands = fs::read_to_string(project.with_extension("commands")).unwrap();
+        common::said(&debug(&["--virtual-clock"], project, &commands), true)
+    });
+}
+
+/// With `--break`, the project runs until a breakpoint, and `quit` stops it.
+#[test]
+fn breakpoint_option() {
+    let project = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../tests/debug/session.sb3");
+    let output = debug(&["--break", "block b7"], &project, "print score\nquit\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("thread 1 (Sprite1) paused at breakpoint 1, block b7\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("score = 3\n"), "{stdout}");
+    assert!(
+        !stdout.contains("\n3\n"),
+        "the project went on after quit: {stdout}"
+    );
+    assert_eq!(output.status.code(), Some(4));
+
+    let output = debug(&["--break", "opcode nothing"], &project, "");
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,81 @@
+//! Runs `kcc diff` between the versions of the project in `tests/diff`, whose
+//! blocks all got new IDs and positions, and compares what it prints with the
+//! `.out` file of the newer version.
+
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+use serde_json::Value;
+
+mod common;
+
+fn kcc_diff(args: &[&str], old: &Path, new: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("diff")
+        .args(args)
+        .arg(old)
+        .arg(new)
+        .output()
+        .expect("kcc runs")
+}
+
+#[test]
+fn text() {
+    let projects = common::projects("diff");
+    let expected = common::expected(&projects[1]);
+    let output = kcc_diff(&[], &projects[0], &projects[1]);
+    let actual = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success() && actual == expected,
+        "{}",
+        common::failure(
+            &projects[1],
+            &expected,
+            &actual,
+            &String::from_utf8_lossy(&output.stderr)
+        )
+    );
+}
+
+#[test]
+fn json() {
+    let projects = common::projects("diff");
+    let output = kcc_diff(&["--json"], &projects[0], &projects[1]);
+    assert!(output.status.success());
+    let changes: Value = serde_json::from_slice(&output.stdout).expect("valid JSON");
+    let changes = changes.as_array().unwrap();
+    let find = |item: &str, name: &str| {
+        changes
+            .iter()
+            .find(|c| c["item"] == item && c["name"] == name)
+            .unwrap_or_else(|| panic!("no change to {item} {name}"))
+    };
+    let script = find("script", "when flag clicked");
+    assert_eq!(script["kind"], "changed");
+    assert_eq!(script["target"], "Cat");
+    assert!(script["details"]
+        .as_array()
+        .unwrap()
+        .contains(&Value::from("+  control_wait DURATION=1")));
+    assert_eq!(find("sprite", "Dog")["kind"], "removed");
+    assert_eq!(find("sound", "meow")["kind"], "added");
+    // The custom block and the other green flag script only got new IDs.
+    assert!(!changes.iter().any(|c| c["name"] == "define jump %s"));
+    assert_eq!(
+        changes
+            .iter()
+            .filter(|c| c["name"] == "when flag clicked")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn same_project() {
+    let project = &common::projects("diff")[0];
+    let output = kcc_diff(&[], project, project);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,135 @@
+//! Tests `kcc test`, on the lists suite and on `tests/golden`, whose projects
+//! expect what they say in `.out` files and in comments.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use serde_json::Value;
+
+mod common;
+
+fn suite_dir(suite: &str) -> PathBuf {
+    common::projects(suite)[0].parent().unwrap().to_path_buf()
+}
+
+/// Runs `kcc test` on `path`, and returns its output with its JSON report.
+fn test(path: &Path, work_dir: &Path, args: &[&str]) -> (Output, Value) {
+    let json = work_dir.join("results.json");
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("test")
+        .args(args)
+        .arg("--json")
+        .arg(&json)
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .expect("kcc runs");
+    let report = fs::read_to_string(&json).expect("a JSON report");
+    (output, serde_json::from_str(&report).expect("valid JSON"))
+}
+
+fn case<'a>(report: &'a Value, name: &str) -> &'a Value {
+    let cases = report["cases"].as_array().unwrap();
+    cases
+        .iter()
+        .find(|c| c["name"] == name)
+        .unwrap_or_else(|| panic!("no case {name} in {report}"))
+}
+
+/// Every project of a suite passes, and says what it says with `kcc run`.
+#[test]
+fn suite() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let (output, report) = test(&suite_dir("lists"), work_dir.path(), &[]);
+    common::check_suite("lists", |project| {
+        let case = case(&report, &project.file_name().unwrap().to_string_lossy());
+        let mut actual = case["actual"].as_str().unwrap().to_string();
+        if case["status"] != "passed" {
+            actual.push_str(&format!("[{}]\n", case["status"]));
+        }
+        (actual, case["stderr"].as_str().unwrap().to_string())
+    });
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(report["failed"], 0);
+}
+
+#[test]
+fn comments_and_reports() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let junit = work_dir.path().join("results.xml");
+    let junit_arg = junit.to_string_lossy().to_string();
+    let (output, report) = test(
+        &suite_dir("golden"),
+        work_dir.path(),
+        &["--bytecode", "--junit", &junit_arg],
+    );
+    assert_eq!(output.status.code(), Some(7));
+    // The comment that is only a note is not a case.
+    assert_eq!(report["cases"].as_array().unwrap().len(), 5);
+    for (name, status) in [
+        ("greet.sb3#1", "passed"),
+        ("greet.sb3#2", "passed"),
+        ("greet.sb3#3", "error"),
+        ("sum.sb3", "passed"),
+        ("wrong.sb3", "failed"),
+    ] {
+        assert_eq!(case(&report, name)["status"], status, "{name}");
+    }
+    assert_eq!(
+        case(&report, "greet.sb3#3")["message"],
+        "unknown setting anwser: Carol"
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("test sum.sb3 ... ok"), "{stdout}");
+    assert!(
+        stdout.contains(" one\n-2\n+two\n three\n+four\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("3 passed; 1 failed; 1 errors"), "{stdout}");
+
+    let junit = fs::read_to_string(junit).unwrap();
+    assert!(
+        junit.contains("tests=\"5\" failures=\"1\" errors=\"1\""),
+        "{junit}"
+    );
+    assert!(junit.contains("<testcase name=\"wrong.sb3\" classname=\"wrong\""));
+    assert!(junit.contains("<failure message=\"the output differs\"> one\n-2\n+two\n"));
+}
+
+/// Scripts waiting for the clock and for each other pass every run, with the
+/// interpreter as with the bytecode machine.
+#[test]
+fn waits() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let projects = work_dir.path().join("projects");
+    fs::create_dir(&projects).unwrap();
+    let project = common::projects("cli")
+        .into_iter()
+        .find(|p| p.ends_with("waits.sb3"))
+        .expect("cli suite has waits.sb3");
+    fs::copy(&project, projects.join("waits.sb3")).unwrap();
+    fs::copy(project.with_extension("out"), projects.join("waits.out")).unwrap();
+    for args in [&[][..], &["--bytecode"]] {
+        for _ in 0..20 {
+            let (output, report) = test(&projects, work_dir.path(), args);
+            assert_eq!(output.status.code(), Some(0), "{report}");
+            assert_eq!(case(&report, "waits.sb3")["status"], "passed");
+        }
+    }
+}
+
+#[test]
+fn no_tests() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let status = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("test")
+        .arg(work_dir.path())
+        .stderr(Stdio::null())
+        .status()
+        .expect("kcc runs");
+    assert_eq!(status.code(), Some(1));
+}
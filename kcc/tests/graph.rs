@@ -0,0 +1,63 @@
+//! Runs `kcc graph` on every project in `tests/graph` and compares the DOT it
+//! prints with the `.out` file next to it, then checks the JSON graph.
+
+use std::process::Command;
+
+use serde_json::Value;
+
+mod common;
+
+#[test]
+fn dot() {
+    common::check_suite("graph", |project| {
+        let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .arg("graph")
+            .arg(project)
+            .output()
+            .expect("kcc runs");
+        common::said(&output, output.status.success())
+    });
+}
+
+/// Waiting for a broadcast through a custom block is a cycle, sending one
+/// without waiting is not.
+#[test]
+fn json() {
+    let project = &common::projects("graph")[0];
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(["graph", "--format", "json"])
+        .arg(project)
+        .output()
+        .expect("kcc runs");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Cat/b4, Cat/d7, Dog/b10"), "{stderr}");
+    let graph: Value = serde_json::from_slice(&output.stdout).expect("valid JSON");
+    assert_eq!(
+        graph["cycles"],
+        serde_json::json!([["Cat/b4", "Cat/d7", "Dog/b10"]])
+    );
+    let edges = graph["edges"].as_array().unwrap();
+    let kind = |from: &str, to: &str| {
+        edges
+            .iter()
+            .find(|e| e["from"] == from && e["to"] == to)
+            .map(|e| e["kind"].as_str().unwrap().to_string())
+    };
+    assert_eq!(kind("Cat/b4", "Cat/d7").as_deref(), Some("calls"));
+    assert_eq!(
+        kind("Cat/d7", "broadcast/answer").as_deref(),
+        Some("sends-and-waits")
+    );
+    assert_eq!(
+        kind("broadcast/answer", "Dog/b10").as_deref(),
+        Some("starts")
+    );
+    assert_eq!(kind("Dog/b14", "broadcast/ping").as_deref(), Some("sends"));
+    assert_eq!(kind("variable/v0", "Dog/b10").as_deref(), Some("reads"));
+    assert_eq!(kind("Cat/b1", "variable/v0").as_deref(), Some("writes"));
+    let nodes = graph["nodes"].as_array().unwrap();
+    let relay = nodes.iter().find(|n| n["id"] == "Cat/d7").unwrap();
+    assert_eq!(relay["label"], "define relay");
+    assert_eq!(relay["kind"], "script");
+}
@@ -0,0 +1,59 @@
+//! Runs `kcc dump --no-optimize` on every project in `tests/ir` and compares the
+//! IR it prints with the `.out` file next to it. Also lowers every project of the
+//! other suites, with and without optimizations, which checks that their IR is well formed,
+//! except those `kcc check` finds blocks kcc cannot run in.
+
+use std::process::Command;
+
+mod common;
+
+fn dump(args: &[&str], project: &std::path::Path) -> (String, String, bool) {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("dump")
+        .args(args)
+        .arg(project)
+        .output()
+        .expect("kcc runs");
+    (
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+        output.status.success(),
+    )
+}
+
+#[test]
+fn dumps() {
+    common::check_suite("ir", |project| {
+        let (actual, stderr, _) = dump(&["--no-optimize"], project);
+        (actual, stderr)
+    });
+}
+
+#[test]
+fn suites() {
+    let suites = [
+        "control",
+        "lists",
+        "operators",
+        "optimizer",
+        "jit",
+        "check",
+        "js",
+        "cli",
+    ];
+    for suite in suites {
+        for project in common::projects(suite) {
+            if common::expected(&project).contains("error: ") {
+                continue;
+            }
+            for args in [&[][..], &["--no-optimize"][..]] {
+                let (_, stderr, lowered) = dump(args, &project);
+                assert!(
+                    lowered,
+                    "lowering {} with {args:?} failed:\n{stderr}",
+                    project.display()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Differential tests of the JIT: every project runs once with `KCC_JIT=off`,
+//! where only the interpreter evaluates blocks, and once with the JIT, and both
+//! must say the same. Besides the projects in `tests/jit`, random loops doing
+//! arithmetic on variables are generated from fixed seeds.
+//!
+//! Without the `jit` feature, both runs use the interpreter.
+
+use std::{fs, io::Write, path::Path, process::Command};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::{json, Map, Value};
+
+mod common;
+
+fn kcc(project: &Path, jit: bool) -> (String, String) {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_kcc"));
+    if !jit {
+        command.env("KCC_JIT", "off");
+    }
+    let output = command.arg(project).output().expect("kcc runs");
+    (
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn suite() {
+    for jit in [false, true] {
+        common::check_suite("jit", |project| kcc(project, jit));
+    }
+}
+
+const MATHOPS: [&str; 14] = [
+    "abs", "floor", "ceiling", "sqrt", "sin", "cos", "tan", "asin", "acos", "atan", "ln", "log",
+    "e ^", "10 ^",
+];
+/// Literals that are not plain numbers, to make values change type.
+const TEXTS: [&str; 6] = ["1.50", "abc", "", "-0", "Infinity", " 7 "];
+
+/// Builds a project whose green flag script runs a hot loop over a few variables.
+struct Generator {
+    rng: StdRng,
+    blocks: Map<String, Value>,
+    variables: Vec<(String, String)>,
+}
+
+impl Generator {
+    fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let variables = (0..rng.random_range(1..=4))
+            .map(|i| (format!("v{i}"), format!("var{i}")))
+            .collect();
+        Self {
+            rng,
+            blocks: Map::new(),
+            variables,
+        }
+    }
+
+    fn block(
+        &mut self,
+        opcode: &str,
+        parent: Option<&str>,
+        inputs: Value,
+        fields: Value,
+    ) -> String {
+        let id = format!("b{}", self.blocks.len());
+        self.blocks.insert(
+            id.clone(),
+            json!({
+                "opcode": opcode, "next": null, "parent": parent, "inputs": inputs,
+                "fields": fields, "shadow": false, "topLevel": parent.is_none(), "x": 0, "y": 0,
+            }),
+        );
+        id
+    }
+
+    /// Chains blocks made by `make`, returning the first one.
+    fn stack(
+        &mut self,
+        parent: &str,
+        count: usize,
+        mut make: impl FnMut(&mut Self, &str) -> String,
+    ) -> Option<String> {
+        let mut first = None;
+        let mut previous = parent.to_string();
+        for _ in 0..count {
+            let id = make(self, &previous);
+            match &first {
+                None => first = Some(id.clone()),
+                Some(_) => self.blocks[&previous]["next"] = json!(id),
+            }
+            previous = id;
+        }
+        first
+    }
+
+    fn variable(&mut self) -> (String, String) {
+        let i = self.rng.random_range(0..self.variables.len());
+        self.variables[i].clone()
+    }
+
+    fn number(&mut self) -> String {
+        match self.rng.random_range(0..4) {
+            0 => self.rng.random_range(-10..=10).to_string(),
+            1 => format!("{:.2}", self.rng.random_range(-100.0..100.0)),
+            2 => "0".to_string(),
+            _ => format!("{:.1}", self.rng.random_range(0.0..2.0)),
+        }
+    }
+
+    fn input(&mut self, parent: &str, depth: u32) -> Value {
+        match self.rng.random_range(0..10) {
+            0..=2 => json!([1, [4, self.number()]]),
+            3 => json!([1, [10, TEXTS[self.rng.random_range(0..TEXTS.len())]]]),
+            4..=6 => {
+                let (id, name) = self.variable();
+                json!([3, [12, name, id], [10, ""]])
+            }
+            _ if depth == 0 => json!([1, [4, self.number()]]),
+            _ => json!([3, self.operator(parent, depth - 1), [10, ""]]),
+        }
+    }
+
+    fn operator(&mut self, parent: &str, depth: u32) -> String {
+        let (opcode, names): (&str, &[&str]) = match self.rng.random_range(0..7) {
+            0 => ("operator_add", &["NUM1", "NUM2"]),
+            1 => ("operator_subtract", &["NUM1", "NUM2"]),
+            2 => ("operator_multiply", &["NUM1", "NUM2"]),
+            3 => ("operator_divide", &["NUM1", "NUM2"]),
+            4 => ("operator_mod", &["NUM1", "NUM2"]),
+            5 => ("operator_round", &["NUM"]),
+            _ => ("operator_mathop", &["NUM"]),
+        };
+        let fields = match opcode {
+            "operator_mathop" => {
+                json!({"OPERATOR": [MATHOPS[self.rng.random_range(0..MATHOPS.len())], null]})
+            }
+            _ => json!({}),
+        };
+        let id = self.block(opcode, Some(parent), json!({}), fields);
+        for name in names {
+            let input = self.input(&id, depth);
+            self.blocks[&id]["inputs"][*name] = input;
+        }
+        id
+    }
+
+    fn condition(&mut self, parent: &str, depth: u32) -> String {
+        let opcode = match self.rng.random_range(0..6) {
+            0 if depth > 0 => "operator_and",
+            1 if depth > 0 => "operator_or",
+            2 if depth > 0 => "operator_not",
+            3 => "operator_gt",
+            4 => "operator_equals",
+            _ => "operator_lt",
+        };
+        let id = self.block(opcode, Some(parent), json!({}), json!({}));
+        let inputs = match opcode {
+            "operator_not" => json!({"OPERAND": [2, self.condition(&id, depth - 1)]}),
+            "operator_and" | "operator_or" => json!({
+                "OPERAND1": [2, self.condition(&id, depth - 1)],
+                "OPERAND2": [2, self.condition(&id, depth - 1)],
+            }),
+            _ => json!({"OPERAND1": self.input(&id, 2), "OPERAND2": self.input(&id, 2)}),
+        };
+        self.blocks[&id]["inputs"] = inputs;
+        id
+    }
+
+    fn statement(&mut self, parent: &str, depth: u32) -> String {
+        let kind = self.rng.random_range(0..if depth > 0 { 6 } else { 3 });
+        match kind {
+            0..=2 => {
+                let opcode = match kind {
+                    0 => "data_changevariableby",
+                    _ => "data_setvariableto",
+                };
+                let (id, name) = self.variable();
+                let block = self.block(
+                    opcode,
+                    Some(parent),
+                    json!({}),
+                    json!({"VARIABLE": [name, id]}),
+                );
+                self.blocks[&block]["inputs"] = json!({"VALUE": self.input(&block, 2)});
+                block
+            }
+            3 | 4 => {
+                let opcode = ["control_if", "control_if_else"][kind - 3];
+                let block = self.block(opcode, Some(parent), json!({}), json!({}));
+                let mut inputs = json!({"CONDITION": [2, self.condition(&block, 1)]});
+                for name in ["SUBSTACK", "SUBSTACK2"].iter().take(kind - 2) {
+                    let count = self.rng.random_range(1..=3);
+                    if let Some(first) = self.stack(&block, count, |g, p| g.statement(p, depth - 1))
+                    {
+                        inputs[*name] = json!([2, first]);
+                    }
+                }
+                self.blocks[&block]["inputs"] = inputs;
+                block
+            }
+            _ => {
+                let block = self.block("control_repeat", Some(parent), json!({}), json!({}));
+                let times = self.rng.random_range(0..5).to_string();
+                let count = self.rng.random_range(1..=3);
+                let first = self.stack(&block, count, |g, p| g.statement(p, depth - 1));
+                self.blocks[&block]["inputs"] =
+                    json!({"TIMES": [1, [6, times]], "SUBSTACK": [2, first]});
+                block
+            }
+        }
+    }
+
+    /// Sets every variable, runs the loop, then says every variable.
+    fn project(mut self) -> Value {
+        let flag = self.block("event_whenflagclicked", None, json!({}), json!({}));
+        let variables = self.variables.clone();
+        let mut blocks = Vec::new();
+        for (id, name) in &variables {
+            let value = self.number();
+            let block = self.block(
+                "data_setvariableto",
+                Some(&flag),
+                json!({"VALUE": [1, [10, value]]}),
+                json!({"VARIABLE": [name, id]}),
+            );
+            blocks.push(block);
+        }
+        let repeat = self.block("control_repeat", Some(&flag), json!({}), json!({}));
+        let iterations = self.rng.random_range(1500..3000).to_string();
+        let count = self.rng.random_range(1..=5);
+        let body = self.stack(&repeat, count, |g, p| g.statement(p, 2));
+        self.blocks[&repeat]["inputs"] =
+            json!({"TIMES": [1, [6, iterations]], "SUBSTACK": [2, body]});
+        blocks.push(repeat);
+        for (id, name) in &variables {
+            let say = self.block("looks_say", Some(&flag), json!({}), json!({}));
+            self.blocks[&say]["inputs"] = json!({"MESSAGE": [3, [12, name, id], [10, ""]]});
+            blocks.push(say);
+        }
+        let mut previous = flag;
+        for block in blocks {
+            self.blocks[&previous]["next"] = json!(block);
+            self.blocks[&block]["parent"] = json!(previous);
+            previous = block;
+        }
+
+        let stage_variables: Map<String, Value> = variables
+            .iter()
+            .map(|(id, name)| (id.clone(), json!([name, 0])))
+            .collect();
+        json!({
+            "targets": [
+                {
+                    "isStage": true, "name": "Stage", "variables": stage_variables, "lists": {},
+                    "broadcasts": {}, "blocks": {}, "comments": {}, "currentCostume": 0,
+                    "costumes": [], "sounds": [], "volume": 100, "layerOrder": 0, "tempo": 60,
+                    "videoTransparency": 50, "videoState": "on", "textToSpeechLanguage": null,
+                },
+                {
+                    "isStage": false, "name": "Sprite1", "variables": {}, "lists": {},
+                    "broadcasts": {}, "blocks": self.blocks, "comments": {}, "currentCostume": 0,
+                    "costumes": [], "sounds": [], "volume": 100, "layerOrder": 1,
+                    "visible": true, "x": 0, "y": 0, "size": 100, "direction": 90,
+                    "draggable": false, "rotationStyle": "all around",
+                },
+            ],
+            "monitors": [], "extensions": [],
+            "meta": {"semver": "3.0.0", "vm": "0.2.0", "agent": "kcc"},
+        })
+    }
+}
+
+fn save(project: &Value, path: &Path) {
+    let mut zip = zip::ZipWriter::new(fs::File::create(path).expect("project file"));
+    zip.start_file("project.json", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(project.to_string().as_bytes()).unwrap();
+    zip.finish().unwrap();
+}
+
+#[test]
+fn random_loops() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let mut failures = Vec::new();
+    for seed in 0..40 {
+        let path = work_dir.path().join(format!("{seed}.sb3"));
+        save(&Generator::new(seed).project(), &path);
+        let (expected, stderr) = kcc(&path, false);
+        assert!(!expected.is_empty(), "seed {seed} said nothing:\n{stderr}");
+        let (actual, stderr) = kcc(&path, true);
+        if actual != expected {
+            failures.push(format!(
+                "seed {seed}\n--- interpreter\n{expected}--- jit\n{actual}--- stderr\n{stderr}"
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,91 @@
+//! Compiles every project of a suite to an ES module, runs it with the first
+//! JavaScript engine found (`$KCC_JS`, node, deno or bun), and compares what it
+//! says with the `.out` file next to it, which the interpreter says too. The
+//! tests fail rather than pass unchecked when none of them is installed.
+
+use std::{path::Path, process::Command};
+
+mod common;
+
+/// The command running a module with a JavaScript engine.
+fn engine() -> Vec<String> {
+    let candidates = match std::env::var("KCC_JS") {
+        Ok(engine) => vec![engine],
+        Err(_) => vec!["node".to_string(), "deno".to_string(), "bun".to_string()],
+    };
+    let tried = candidates.join(", ");
+    candidates
+        .into_iter()
+        .find_map(|engine| {
+            let installed = Command::new(&engine)
+                .arg("--version")
+                .output()
+                .is_ok_and(|o| o.status.success());
+            let is_deno = Path::new(&engine).file_stem().is_some_and(|s| s == "deno");
+            installed.then(|| match is_deno {
+                true => vec![engine, "run".to_string(), "--allow-read".to_string()],
+                false => vec![engine],
+            })
+        })
+        .unwrap_or_else(|| {
+            panic!("no JavaScript engine found (tried {tried}), install one or point $KCC_JS to it")
+        })
+}
+
+fn run_suite(suite: &str) {
+    let engine = engine();
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    common::check_suite(suite, |project| {
+        let name = project.file_stem().unwrap().to_string_lossy().to_string();
+        let compiled = Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .arg("compile")
+            .args(["--target", "js", "-o"])
+            .arg(work_dir.path())
+            .arg(project)
+            .output()
+            .expect("kcc runs");
+        assert!(
+            compiled.status.success(),
+            "compiling {} failed:\n{}",
+            project.display(),
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+        let output = common::answering(
+            project,
+            Command::new(&engine[0])
+                .args(&engine[1..])
+                .arg(work_dir.path().join(format!("{name}.mjs"))),
+        );
+        common::said(&output, output.status.success())
+    });
+}
+
+#[test]
+fn lists() {
+    run_suite("lists");
+}
+
+#[test]
+fn control() {
+    run_suite("control");
+}
+
+#[test]
+fn operators() {
+    run_suite("operators");
+}
+
+#[test]
+fn optimizer() {
+    run_suite("optimizer");
+}
+
+#[test]
+fn jit() {
+    run_suite("jit");
+}
+
+#[test]
+fn js() {
+    run_suite("js");
+}
@@ -0,0 +1,57 @@
+//! Runs `kcc lint` on every project in `tests/lint` and compares what it prints
+//! with the `.out` file next to it. Projects with lints must fail.
+
+use std::process::Command;
+
+use serde_json::Value;
+
+mod common;
+
+#[test]
+fn lint() {
+    common::check_suite("lint", |project| {
+        let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .arg("lint")
+            .arg(project)
+            .output()
+            .expect("kcc runs");
+        let lints = common::expected(project).contains("warning: ");
+        common::said(&output, output.status.success() != lints)
+    });
+}
+
+/// The JSON lints say the same, with the blocks to highlight.
+#[test]
+fn json() {
+    let project = common::projects("lint")
+        .into_iter()
+        .find(|p| p.ends_with("mistakes.sb3"))
+        .expect("tests/lint/mistakes.sb3");
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(["lint", "--json"])
+        .arg(&project)
+        .output()
+        .expect("kcc runs");
+    assert_eq!(output.status.code(), Some(6));
+    let lints: Value = serde_json::from_slice(&output.stdout).expect("valid JSON");
+    let lints = lints.as_array().unwrap();
+    assert_eq!(lints.len(), common::expected(&project).lines().count());
+    let race = lints
+        .iter()
+        .find(|l| l["rule"] == "green-flag-race")
+        .expect("a race");
+    assert_eq!(race["target"], "Stage");
+    let targets = race["blocks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|b| b["target"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(targets, ["Cat", "Cat", "Dog"]);
+    let unused = lints
+        .iter()
+        .find(|l| l["message"] == "variable \"unused\" is never used")
+        .expect("an unused variable");
+    assert_eq!(unused["rule"], "never-read");
+    assert_eq!(unused["blocks"].as_array().unwrap().len(), 0);
+}
@@ -0,0 +1,191 @@
+//! Runs the projects in `tests/listfile` with lists imported from and exported
+//! to text and CSV files, with `--import-list` and `--export-list`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+mod common;
+
+fn project(name: &str) -> PathBuf {
+    common::projects("listfile")
+        .into_iter()
+        .find(|p| p.file_stem().is_some_and(|s| s == name))
+        .unwrap_or_else(|| panic!("no project {name} in tests/listfile"))
+}
+
+fn kcc(args: &[String], project: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("run")
+        .args(args)
+        .arg(project)
+        .output()
+        .expect("kcc runs")
+}
+
+/// `--<action>-list <list>=<file>`.
+fn list(action: &str, list: &str, file: impl AsRef<Path>) -> [String; 2] {
+    [
+        format!("--{action}-list"),
+        format!("{list}={}", file.as_ref().display()),
+    ]
+}
+
+fn check(project: &Path, output: &Output, expected: &str) {
+    let actual = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success() && actual == expected,
+        "{}",
+        common::failure(
+            project,
+            expected,
+            &actual,
+            &String::from_utf8_lossy(&output.stderr)
+        )
+    );
+}
+
+fn fails(output: &Output, message: &str) {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        !output.status.success() && stderr.contains(message),
+        "expected an error about {message}, got:\n{stderr}"
+    );
+}
+
+#[test]
+fn export() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let sorter = project("sorter");
+    let (data, names) = (
+        work_dir.path().join("data.txt"),
+        work_dir.path().join("names.csv"),
+    );
+    let args = [
+        list("export", "data", &data),
+        list("export", "Sorter/names", &names),
+    ]
+    .concat();
+    check(&sorter, &kcc(&args, &sorter), &common::expected(&sorter));
+    assert_eq!(
+        fs::read_to_string(data).unwrap(),
+        "old\nadded, with comma\n"
+    );
+    assert_eq!(fs::read_to_string(names).unwrap(), "z\n");
+}
+
+#[test]
+fn import() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let sorter = project("sorter");
+    let (lines, table) = (
+        work_dir.path().join("lines.txt"),
+        work_dir.path().join("table.csv"),
+    );
+    fs::write(&lines, "a\r\nb b\n\nc\n").unwrap();
+    fs::write(&table, "name,score\nAda,\"1,5\"\nBob\n").unwrap();
+    let exported = work_dir.path().join("exported.csv");
+    let args = [
+        list("import", "data", &lines),
+        list("import", "Sorter/names", format!("{}:2", table.display())),
+        list("export", "data", &exported),
+    ]
+    .concat();
+    check(&sorter, &kcc(&args, &sorter), "a b b  c\n4\nscore 1,5 \n");
+    // Lists are exported as a single CSV column, quoted where needed.
+    assert_eq!(
+        fs::read_to_string(&exported).unwrap(),
+        "a\nb b\n\"\"\nc\n\"added, with comma\"\n"
+    );
+
+    // Without a column, items come from the first one.
+    let args = list("import", "Sorter/names", &table);
+    check(&sorter, &kcc(&args, &sorter), "old\n1\nname Ada Bob\n");
+}
+
+#[test]
+fn export_after_crash() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let file = work_dir.path().join("data.txt");
+    let output = kcc(&list("export", "data", &file), &project("crash"));
+    fails(&output, "custom block argument not found");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(fs::read_to_string(file).unwrap(), "before the crash\n");
+}
+
+#[test]
+fn long_files_are_truncated() {
+    // `LIST_ITEM_LIMIT` items fit in a list.
+    const LIMIT: usize = 200_000;
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let sorter = project("sorter");
+    let (long, exported) = (
+        work_dir.path().join("long.txt"),
+        work_dir.path().join("exported.txt"),
+    );
+    fs::write(
+        &long,
+        (0..LIMIT + 5).map(|i| format!("{i}\n")).collect::<String>(),
+    )
+    .unwrap();
+    let args = [
+        list("import", "data", &long),
+        list("export", "data", &exported),
+    ]
+    .concat();
+    let output = kcc(&args, &sorter);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().nth(1), Some(LIMIT.to_string().as_str()));
+    let items = fs::read_to_string(exported).unwrap();
+    // The list was full, so adding to it did nothing.
+    assert_eq!(items.lines().count(), LIMIT);
+    assert_eq!(items.lines().last(), Some((LIMIT - 1).to_string().as_str()));
+}
+
+#[test]
+fn bad_lists_and_files() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let sorter = project("sorter");
+    let (lines, table) = (
+        work_dir.path().join("lines.txt"),
+        work_dir.path().join("table.csv"),
+    );
+    fs::write(&lines, "a\n").unwrap();
+    fs::write(&table, "a,b\n").unwrap();
+
+    let column = |spec: &str| list("import", "data", spec);
+    fails(
+        &kcc(&column(&format!("{}:0", table.display())), &sorter),
+        "columns are counted from 1",
+    );
+    fails(
+        &kcc(&column(&format!("{}:2", lines.display())), &sorter),
+        "only CSV files have columns",
+    );
+    fails(
+        &kcc(&["--import-list".to_string(), "data".to_string()], &sorter),
+        "expected <list>=<file>",
+    );
+    fails(
+        &kcc(
+            &list("import", "data", work_dir.path().join("missing.txt")),
+            &sorter,
+        ),
+        "missing.txt",
+    );
+
+    fails(
+        &kcc(&list("import", "nope", &lines), &sorter),
+        "there is no list named nope",
+    );
+    fails(
+        &kcc(&list("export", "Judge/data", &lines), &sorter),
+        "there is no list named data",
+    );
+    fails(
+        &kcc(&list("import", "names", &lines), &sorter),
+        "pick one with Sorter/names or Judge/names",
+    );
+}
@@ -0,0 +1,52 @@
+//! Runs projects with and without optimizations, which must not change what they
+//! say. The projects in `tests/optimizer` also run with each pass on its own.
+
+use std::{path::Path, process::Command};
+
+mod common;
+
+const PASSES: [&str; 5] = ["inline", "fold", "unreachable", "hoist", "dead-scripts"];
+
+fn kcc(project: &Path, args: &[&str]) -> (String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .env("RUST_LOG", "kcc::optimizer=debug")
+        .args(args)
+        .arg(project)
+        .output()
+        .expect("kcc runs");
+    (
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn suites() {
+    for suite in ["optimizer", "control", "operators", "lists"] {
+        for args in [&[][..], &["--no-optimize"]] {
+            common::check_suite(suite, |project| kcc(project, args));
+        }
+    }
+}
+
+/// Every pass changes some of the projects in `tests/optimizer`, without changing what they say.
+#[test]
+fn each_pass() {
+    for pass in PASSES {
+        let mut args = Vec::new();
+        for other in PASSES.iter().filter(|p| **p != pass) {
+            args.extend(["--disable-pass", other]);
+        }
+        let mut changes = 0;
+        common::check_suite("optimizer", |project| {
+            let (actual, stderr) = kcc(project, &args);
+            changes += stderr
+                .lines()
+                .filter_map(|line| line.split_once(&format!(" {pass}: "))?.1.split_once(' '))
+                .map(|(n, _)| n.parse::<usize>().expect("a number of changes"))
+                .sum::<usize>();
+            (actual, stderr)
+        });
+        assert!(changes > 0, "{pass} changed nothing");
+    }
+}
@@ -0,0 +1,86 @@
+//! Packages every project of a suite into an executable, both as it is and as
+//! bytecode, runs the executable, and compares what it says with the `.out`
+//! file next to it.
+
+use std::{path::Path, process::Command};
+
+mod common;
+
+fn run(program: &Path, args: &[&Path]) -> (String, String, bool) {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .expect("program runs");
+    (
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+        output.status.success(),
+    )
+}
+
+fn run_suite(suite: &str) {
+    let kcc = Path::new(env!("CARGO_BIN_EXE_kcc"));
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    for format in [&[][..], &[Path::new("--bytecode")][..]] {
+        common::check_suite(suite, |project| {
+            let out = work_dir.path().join("packaged");
+            let mut args = vec![Path::new("package"), Path::new("-o"), &out];
+            args.extend(format);
+            args.push(project);
+            let (_, stderr, packaged) = run(kcc, &args);
+            assert!(
+                packaged,
+                "packaging {} with {format:?} failed:\n{stderr}",
+                project.display()
+            );
+            let (actual, stderr, _) = run(&out, &[]);
+            (actual, stderr)
+        });
+    }
+}
+
+#[test]
+fn control() {
+    run_suite("control");
+}
+
+#[test]
+fn lists() {
+    run_suite("lists");
+}
+
+/// The project is extracted to a temporary directory, which is removed however
+/// the executable exits.
+#[test]
+fn cleans_up() {
+    let kcc = Path::new(env!("CARGO_BIN_EXE_kcc"));
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let temp = work_dir.path().join("tmp");
+    std::fs::create_dir(&temp).unwrap();
+    let out = work_dir.path().join("packaged");
+    let project = common::projects("control")
+        .into_iter()
+        .find(|p| p.ends_with("stop_all.sb3"))
+        .expect("control suite has stop_all.sb3");
+    let (_, stderr, packaged) = run(
+        kcc,
+        &[Path::new("package"), Path::new("-o"), &out, &project],
+    );
+    assert!(packaged, "packaging failed:\n{stderr}");
+    for args in [
+        &[][..],
+        &["--answers-file", "no such file"],
+        &["--no-such-option"],
+        &["--help"],
+    ] {
+        let status = Command::new(&out)
+            .args(args)
+            .env("TMPDIR", &temp)
+            .output()
+            .expect("program runs")
+            .status;
+        assert_ne!(status.code(), None, "{args:?}");
+        let left = std::fs::read_dir(&temp).unwrap().count();
+        assert_eq!(left, 0, "{args:?} left files in the temporary directory");
+    }
+}
@@ -0,0 +1,102 @@
+//! Tests `kcc run --profile` and `--flamegraph`. Times differ from run to run,
+//! so only what ran, and how often, is checked.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+mod common;
+
+fn project() -> PathBuf {
+    common::projects("control")
+        .into_iter()
+        .find(|p| p.ends_with("procedures.sb3"))
+        .expect("control suite has procedures.sb3")
+}
+
+/// Runs a project with `--profile`, writing its stacks to `folded`.
+fn profile(project: &Path, folded: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(["run", "--no-optimize", "--profile", "--flamegraph"])
+        .arg(folded)
+        .arg(project)
+        .stdin(Stdio::null())
+        .output()
+        .expect("kcc runs")
+}
+
+/// The count and name of every row of the report, without the times.
+fn rows(report: &str) -> Vec<String> {
+    report
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("time"))
+        .filter_map(|line| line.split_once('.'))
+        .map(|(_, rest)| {
+            rest.split_whitespace()
+                .skip(1)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+/// Profiling does not change what a project says, and writes one stack per
+/// line, starting with the script.
+#[test]
+fn suite() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    common::check_suite("control", |project| {
+        let folded = work_dir.path().join("project.folded");
+        let output = profile(project, &folded);
+        for line in fs::read_to_string(&folded).unwrap().lines() {
+            let (stack, micros) = line.rsplit_once(' ').expect("a stack and a time");
+            let script = stack.split(';').next().unwrap();
+            assert!(script.contains(": when "), "{line}");
+            micros.parse::<u64>().expect("a time in microseconds");
+        }
+        common::said(&output, true)
+    });
+}
+
+#[test]
+fn report_and_flamegraph() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let folded = work_dir.path().join("procedures.folded");
+    let output = profile(&project(), &folded);
+    assert!(output.status.success());
+
+    let report = String::from_utf8_lossy(&output.stderr);
+    let rows = rows(&report);
+    for row in [
+        "1 Sprite1: when green flag clicked",
+        "1 Sprite1: add %s %s",
+        "4 Sprite1: count %s",
+        "1 Sprite1: early",
+        "4 Sprite1: control_if b73",
+        "3 Sprite1: looks_say b76",
+    ] {
+        assert!(rows.contains(&row.to_string()), "no {row} in\n{report}");
+    }
+    // The block after `stop this script` never runs, so it is not listed.
+    assert!(!report.contains("b83"), "{report}");
+
+    let stacks = fs::read_to_string(&folded).unwrap();
+    assert!(
+        stacks.contains("count %s;control_if b73;count %s;"),
+        "{stacks}"
+    );
+}
+
+#[test]
+fn only_the_interpreter_profiles() {
+    let status = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(["run", "--bytecode", "--profile"])
+        .arg(project())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("kcc runs");
+    assert_eq!(status.code(), Some(1));
+}
@@ -0,0 +1,79 @@
+//! Compiles every project of a suite to Rust, builds it with cargo, and compares
+//! what it says with the `.out` file next to it, answering its questions with
+//! the `.in` file.
+
+use std::{path::PathBuf, process::Command};
+
+mod common;
+
+fn run_suite(suite: &str) {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    // Builds are kept with the other test artifacts, so that reruns are incremental.
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join("rust_backend")
+        .join(suite);
+    common::check_suite(suite, |project| {
+        let name = project.file_stem().unwrap().to_string_lossy().to_string();
+        let crate_dir = work_dir.path().join(&name);
+        let compiled = Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .arg("compile")
+            .args(["--target", "rust", "-o"])
+            .arg(&crate_dir)
+            .arg(project)
+            .output()
+            .expect("kcc runs");
+        assert!(
+            compiled.status.success(),
+            "compiling {} failed:\n{}",
+            project.display(),
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+        let built = Command::new(std::env::var("CARGO").unwrap_or("cargo".to_string()))
+            .args(["build", "--quiet", "--manifest-path"])
+            .arg(crate_dir.join("Cargo.toml"))
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .output()
+            .expect("cargo runs");
+        assert!(
+            built.status.success(),
+            "building {} failed:\n{}",
+            project.display(),
+            String::from_utf8_lossy(&built.stderr)
+        );
+        let output = common::answering(
+            project,
+            &mut Command::new(target_dir.join("debug").join(&name)),
+        );
+        common::said(&output, output.status.success())
+    });
+}
+
+#[test]
+fn lists() {
+    run_suite("lists");
+}
+
+#[test]
+fn control() {
+    run_suite("control");
+}
+
+#[test]
+fn operators() {
+    run_suite("operators");
+}
+
+#[test]
+fn optimizer() {
+    run_suite("optimizer");
+}
+
+#[test]
+fn js() {
+    run_suite("js");
+}
+
+#[test]
+fn jit() {
+    run_suite("jit");
+}
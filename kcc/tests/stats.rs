@@ -0,0 +1,73 @@
+//! Runs `kcc stats --format csv` on every project in `tests/stats` and compares
+//! the rows with the `.out` file next to it, then checks the JSON and that
+//! unreadable projects do not stop the others.
+
+use std::{fs, process::Command};
+
+use serde_json::Value;
+
+mod common;
+
+#[test]
+fn csv() {
+    common::check_suite("stats", |project| {
+        // Run next to the project, so that rows name it without its folder.
+        let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .args(["stats", "--format", "csv"])
+            .arg(project.file_name().unwrap())
+            .current_dir(project.parent().unwrap())
+            .output()
+            .expect("kcc runs");
+        common::said(&output, output.status.success())
+    });
+}
+
+/// The cat and the dog share a costume, whose bytes the project counts once.
+#[test]
+fn json() {
+    let project = &common::projects("stats")[0];
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("stats")
+        .arg(project)
+        .output()
+        .expect("kcc runs");
+    assert!(output.status.success());
+    let stats: Value = serde_json::from_slice(&output.stdout).expect("valid JSON");
+    let total = &stats[0]["total"];
+    assert_eq!(total["asset_bytes"], 330);
+    assert_eq!(total["max_depth"], 2);
+    assert_eq!(total["categories"]["operator"], 4);
+    let cat = &stats[0]["targets"][1];
+    assert_eq!(cat["name"], "Cat");
+    assert_eq!(cat["custom_blocks"], 1);
+    assert_eq!(cat["asset_bytes"], 270);
+    let thinking = &stats[0]["thinking"];
+    assert_eq!(thinking["logic"], 3);
+    assert_eq!(thinking["flow_control"], 3);
+    assert_eq!(thinking["total"], 15);
+}
+
+#[test]
+fn unreadable_projects() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let nested = work_dir.path().join("class");
+    fs::create_dir(&nested).unwrap();
+    fs::copy(&common::projects("stats")[0], nested.join("game.sb3")).unwrap();
+    fs::write(work_dir.path().join("broken.sb3"), "not a zip").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .arg("stats")
+        .arg(work_dir.path())
+        .output()
+        .expect("kcc runs");
+    assert_eq!(output.status.code(), Some(3));
+    let stats: Value = serde_json::from_slice(&output.stdout).expect("valid JSON");
+    let projects = stats.as_array().unwrap();
+    assert_eq!(projects.len(), 2);
+    assert!(projects[0]["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("broken.sb3"));
+    assert!(projects[0]["error"].is_string());
+    assert!(projects[1].get("error").is_none());
+    assert_eq!(projects[1]["total"]["blocks"], 22);
+}
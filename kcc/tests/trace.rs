@@ -0,0 +1,124 @@
+//! Tests `kcc run --trace`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use serde_json::{json, Value};
+
+mod common;
+
+fn project(suite: &str, name: &str) -> PathBuf {
+    common::projects(suite)
+        .into_iter()
+        .find(|p| p.ends_with(name))
+        .unwrap_or_else(|| panic!("{suite} suite has {name}"))
+}
+
+/// Runs a project with `--trace`, and returns its output with the lines of the trace.
+fn trace(project: &Path, work_dir: &Path) -> (Output, Vec<Value>) {
+    let file = work_dir.join("trace.jsonl");
+    let output = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(["run", "--no-optimize", "--trace"])
+        .arg(&file)
+        .arg(project)
+        .stdin(Stdio::null())
+        .output()
+        .expect("kcc runs");
+    let lines = fs::read_to_string(&file)
+        .expect("a trace")
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{e}: {line}")))
+        .collect();
+    (output, lines)
+}
+
+/// The lines of a block.
+fn block<'a>(lines: &'a [Value], id: &str) -> Vec<&'a Value> {
+    lines.iter().filter(|l| l["block"] == id).collect()
+}
+
+/// Tracing does not change what a project says, and every line has the same fields.
+#[test]
+fn suite() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let run = |project: &Path| {
+        let (output, lines) = trace(project, work_dir.path());
+        assert!(!lines.is_empty(), "{}", project.display());
+        for line in lines {
+            for field in ["frame", "thread", "sprite", "block", "opcode", "args"] {
+                assert!(line.get(field).is_some(), "no {field} in {line}");
+            }
+        }
+        common::said(&output, true)
+    };
+    common::check_suite("control", run);
+    common::check_suite("lists", run);
+}
+
+#[test]
+fn arguments_and_results() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let (_, lines) = trace(&project("control", "procedures.sb3"), work_dir.path());
+    assert_eq!(lines[0]["opcode"], "event_whenflagclicked");
+    // Custom blocks get their arguments by name.
+    assert_eq!(block(&lines, "b85")[0]["args"], json!({"a": 2.0, "b": 3.0}));
+    let add = block(&lines, "r66");
+    assert_eq!(add[0]["args"], json!({"NUM1": 2.0, "NUM2": 3.0}));
+    assert_eq!(add[0]["result"], 5.0);
+    // `if` is traced each time it checks its condition, before the blocks inside it.
+    let conditions = block(&lines, "b73")
+        .iter()
+        .map(|l| l["result"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(conditions, [true, true, true, false]);
+    let say = lines.iter().position(|l| l["block"] == "b76").unwrap();
+    assert!(lines.iter().position(|l| l["block"] == "b73").unwrap() < say);
+    assert!(lines.iter().all(|l| l["thread"] == 1));
+}
+
+#[test]
+fn writes() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let (_, lines) = trace(&project("lists", "insert_replace.sb3"), work_dir.path());
+    let writes = lines
+        .iter()
+        .filter_map(|l| l.get("writes"))
+        .flat_map(|w| w.as_array().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(writes.len(), 11);
+    assert_eq!(
+        writes[0],
+        &json!({"list": "L", "action": "insert", "index": 1, "item": "x"})
+    );
+    assert_eq!(writes[9], &json!({"list": "L", "action": "clear"}));
+
+    let (_, lines) = trace(
+        &project("control", "conditional_loops.sb3"),
+        work_dir.path(),
+    );
+    let values = lines
+        .iter()
+        .filter_map(|l| l.get("writes"))
+        .flat_map(|w| w.as_array().unwrap())
+        .filter(|w| w["variable"] == "i")
+        .map(|w| w["value"].as_f64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(values[..3], [2.0, 4.0, 6.0]);
+}
+
+#[test]
+fn only_the_interpreter_traces() {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    let status = Command::new(env!("CARGO_BIN_EXE_kcc"))
+        .args(["run", "--bytecode", "--trace"])
+        .arg(work_dir.path().join("trace.jsonl"))
+        .arg(project("control", "procedures.sb3"))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("kcc runs");
+    assert_eq!(status.code(), Some(1));
+}
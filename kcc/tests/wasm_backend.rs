@@ -0,0 +1,147 @@
+//! Compiles every project of a suite to WebAssembly, runs it with wasmi, and
+//! compares what it says with the `.out` file next to it, which the interpreter
+//! says too.
+
+use std::{
+    process::Command,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use wasmi::{Caller, Engine, Extern, Linker, Module, Store};
+
+mod common;
+
+/// What the module said, and the answers left to give it.
+struct Host {
+    said: String,
+    answers: Vec<String>,
+}
+
+fn memory(caller: &Caller<'_, Host>) -> wasmi::Memory {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .expect("module exports its memory")
+}
+
+/// The text at `ptr` in the memory of a module.
+fn text(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> String {
+    let bytes = &memory(caller).data(caller)[ptr as usize..(ptr + len) as usize];
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+fn say(mut caller: Caller<'_, Host>, ptr: i32, len: i32) {
+    let line = text(&caller, ptr, len);
+    caller.data_mut().said.push_str(&line);
+    caller.data_mut().said.push('\n');
+}
+
+/// Runs a module until every script has finished, answering questions with
+/// `answers` line by line, and returns what it said.
+fn run(module: &[u8], answers: &str) -> Result<String, wasmi::Error> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, module)?;
+    let host = Host {
+        said: String::new(),
+        answers: answers.lines().rev().map(str::to_string).collect(),
+    };
+    let mut store = Store::new(&engine, host);
+    let mut linker = Linker::<Host>::new(&engine);
+    linker.func_wrap("kcc", "say", say)?;
+    linker.func_wrap("kcc", "think", say)?;
+    linker.func_wrap(
+        "kcc",
+        "ask",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32, buffer: i32, capacity: i32| {
+            let question = text(&caller, ptr, len);
+            if !question.is_empty() {
+                caller.data_mut().said.push_str(&question);
+                caller.data_mut().said.push('\n');
+            }
+            let answer = caller.data_mut().answers.pop().unwrap_or_default();
+            let bytes = &answer.as_bytes()[..answer.len().min(capacity as usize)];
+            memory(&caller)
+                .write(&mut caller, buffer as usize, bytes)
+                .expect("buffer in memory");
+            bytes.len() as i32
+        },
+    )?;
+    linker.func_wrap("kcc", "now", || {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock after 1970")
+            .as_secs_f64()
+            * 1000.0
+    })?;
+    let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+    instance
+        .get_typed_func::<(), ()>(&store, "start")?
+        .call(&mut store, ())?;
+    let tick = instance.get_typed_func::<(), f64>(&store, "tick")?;
+    loop {
+        let delay = tick.call(&mut store, ())?;
+        if delay < 0.0 {
+            break;
+        }
+        thread::sleep(Duration::from_secs_f64(delay / 1000.0));
+    }
+    Ok(store.into_data().said)
+}
+
+fn run_suite(suite: &str) {
+    let work_dir = tempfile::tempdir().expect("temporary directory");
+    common::check_suite(suite, |project| {
+        let name = project.file_stem().unwrap().to_string_lossy().to_string();
+        let out_dir = work_dir.path().join(&name);
+        let compiled = Command::new(env!("CARGO_BIN_EXE_kcc"))
+            .arg("compile")
+            .args(["--target", "wasm", "-o"])
+            .arg(&out_dir)
+            .arg(project)
+            .output()
+            .expect("kcc runs");
+        assert!(
+            compiled.status.success(),
+            "compiling {} failed:\n{}",
+            project.display(),
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+        let module = std::fs::read(out_dir.join(format!("{name}.wasm"))).expect("module written");
+        let answers = std::fs::read_to_string(project.with_extension("in")).unwrap_or_default();
+        match run(&module, &answers) {
+            Ok(output) => (output, String::new()),
+            Err(e) => (String::from("[trapped]\n"), e.to_string()),
+        }
+    });
+}
+
+#[test]
+fn lists() {
+    run_suite("lists");
+}
+
+#[test]
+fn control() {
+    run_suite("control");
+}
+
+#[test]
+fn operators() {
+    run_suite("operators");
+}
+
+#[test]
+fn optimizer() {
+    run_suite("optimizer");
+}
+
+#[test]
+fn jit() {
+    run_suite("jit");
+}
+
+#[test]
+fn js() {
+    run_suite("js");
+}